@@ -0,0 +1,103 @@
+// A bump arena for short-lived byte strings, such as decoded bins. A scan
+// decoding millions of bins can allocate their bytes here instead of in
+// individual Vecs, then reset the arena between batches, keeping the
+// buffer's capacity for the next batch.
+//
+// Allocations are addressed by ArenaRef handles (offset + length) rather
+// than references, so the arena can keep growing while handles are held.
+// Each handle records the arena generation it was allocated in, and
+// resolving a handle from before the most recent reset fails. Sizes and
+// handles may come from decoded data, so an allocation past the arena's
+// 4GiB addressing limit, or a stale handle, is an error rather than a
+// panic.
+
+use crate::{Error, Result};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct ArenaRef {
+    generation: u32,
+    off: u32,
+    len: u32,
+}
+
+impl ArenaRef {
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Arena {
+    buf: Vec<u8>,
+    generation: u32,
+}
+
+impl Arena {
+    pub fn new() -> Self {
+        Arena::default()
+    }
+
+    pub fn with_capacity(cap: usize) -> Self {
+        Arena {
+            buf: Vec::with_capacity(cap),
+            generation: 0,
+        }
+    }
+
+    // Reserves `len` zeroed bytes and returns their handle along with a
+    // mutable view for the caller to fill in.
+    pub fn alloc_mut(&mut self, len: usize) -> Result<(ArenaRef, &mut [u8])> {
+        let off = self.buf.len();
+        let end = off
+            .checked_add(len)
+            .filter(|end| u32::try_from(*end).is_ok())
+            .ok_or_else(|| Error::overloaded("arena allocation exceeds 4GiB"))?;
+        let r = ArenaRef {
+            generation: self.generation,
+            off: off as u32,
+            len: len as u32,
+        };
+        self.buf.resize(end, 0);
+        Ok((r, &mut self.buf[off..end]))
+    }
+
+    pub fn alloc(&mut self, bytes: &[u8]) -> Result<ArenaRef> {
+        let (r, buf) = self.alloc_mut(bytes.len())?;
+        buf.copy_from_slice(bytes);
+        Ok(r)
+    }
+
+    pub fn try_get(&self, r: ArenaRef) -> Option<&[u8]> {
+        if r.generation != self.generation {
+            return None;
+        }
+        let off = r.off as usize;
+        self.buf.get(off..off + r.len as usize)
+    }
+
+    // Fails if `r` was allocated before the last reset (or by another arena
+    // that happened to be larger).
+    pub fn get(&self, r: ArenaRef) -> Result<&[u8]> {
+        self.try_get(r)
+            .ok_or_else(|| Error::internal("stale or foreign ArenaRef"))
+    }
+
+    // Drops every allocation, invalidating outstanding handles, but keeps
+    // the buffer's capacity.
+    pub fn reset(&mut self) {
+        self.buf.clear();
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    // Bytes currently allocated.
+    pub fn used(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.capacity()
+    }
+}
@@ -1,8 +1,9 @@
-use std::u64;
+use serde::{Deserialize, Serialize};
+use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Not};
 
 /// A simple 32-byte / 256-bit bitmap that counts bits in order from
 /// least-to-most significant bits and ascending words.
-#[derive(Clone, Default, PartialEq, Eq, Debug, Hash, PartialOrd, Ord)]
+#[derive(Clone, Default, PartialEq, Eq, Debug, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Bitmap256 {
     pub bits: [u64; 4],
 }
@@ -47,6 +48,22 @@ impl Bitmap256 {
         }
         bits as usize
     }
+    // Return the index of the n'th (0-based) set bit, if there are more
+    // than n bits set.
+    pub fn select(&self, mut n: u32) -> Option<u8> {
+        for (i, word) in self.bits.iter().enumerate() {
+            let ones = word.count_ones();
+            if n < ones {
+                let mut w = *word;
+                for _ in 0..n {
+                    w &= w - 1;
+                }
+                return Some((i * 64) as u8 + w.trailing_zeros() as u8);
+            }
+            n -= ones;
+        }
+        None
+    }
     pub fn is_empty(&self) -> bool {
         self.bits.iter().all(|x| *x == 0)
     }
@@ -71,8 +88,62 @@ impl Bitmap256 {
             self.bits[i] &= !other.bits[i];
         }
     }
+    // Non-mutating counterpart of subtract: bits in self but not other.
+    pub fn difference(&self, other: &Self) -> Self {
+        self & &!other
+    }
+    // Bits in exactly one of self and other.
+    pub fn symmetric_difference(&self, other: &Self) -> Self {
+        self ^ other
+    }
+    // Iterate the indices of set bits in ascending order.
+    pub fn iter_ones(&self) -> Bitmap256Iter {
+        Bitmap256Iter::new(self.bits)
+    }
+    // Iterate the indices of clear bits in ascending order.
+    pub fn iter_zeros(&self) -> Bitmap256Iter {
+        Bitmap256Iter::new(self.bits.map(|w| !w))
+    }
+}
+
+// Walks a copy of the bitmap's words, peeling off the lowest set bit of the
+// current word with trailing_zeros until the word is exhausted.
+#[derive(Clone, Debug)]
+pub struct Bitmap256Iter {
+    words: [u64; 4],
+    word: usize,
 }
 
+impl Bitmap256Iter {
+    fn new(words: [u64; 4]) -> Self {
+        Bitmap256Iter { words, word: 0 }
+    }
+}
+
+impl Iterator for Bitmap256Iter {
+    type Item = u8;
+    fn next(&mut self) -> Option<u8> {
+        while self.word < 4 {
+            let w = self.words[self.word];
+            if w != 0 {
+                self.words[self.word] = w & (w - 1);
+                return Some((self.word * 64) as u8 + w.trailing_zeros() as u8);
+            }
+            self.word += 1;
+        }
+        None
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.words[self.word.min(4)..]
+            .iter()
+            .map(|w| w.count_ones() as usize)
+            .sum();
+        (n, Some(n))
+    }
+}
+
+impl ExactSizeIterator for Bitmap256Iter {}
+
 // A convenience type for storing a set of 256 2-bit values
 // representing numbers in the range 0..3. This comes up fairly
 // often in the coldb codebase.
@@ -110,4 +181,112 @@ impl DoubleBitmap256 {
         let hi_val = (self.double_bits[hi / 64] & (1 << (hi % 64))) != 0;
         (lo_val as u8) | (hi_val as u8) << 1
     }
+    // Returns, for word w, a mask with the low bit of each 2-bit slot set
+    // iff that slot equals val.
+    fn eq_mask(w: u64, val: u8) -> u64 {
+        const LO: u64 = 0x5555_5555_5555_5555;
+        let pattern = LO * (val & 3) as u64;
+        let x = w ^ pattern;
+        !(x | (x >> 1)) & LO
+    }
+    // Count the slots equal to val.
+    pub fn count_eq(&self, val: u8) -> u32 {
+        self.double_bits
+            .iter()
+            .map(|w| Self::eq_mask(*w, val).count_ones())
+            .sum()
+    }
+    // Count the slots equal to val up to and including i, like
+    // Bitmap256::rank.
+    pub fn rank_eq(&self, val: u8, i: u8) -> usize {
+        let i = i as usize;
+        let (word, slot) = (i / 32, i % 32);
+        let mut n = 0;
+        for w in &self.double_bits[..word] {
+            n += Self::eq_mask(*w, val).count_ones();
+        }
+        let keep = u64::MAX >> (62 - 2 * slot);
+        n += (Self::eq_mask(self.double_bits[word], val) & keep).count_ones();
+        n as usize
+    }
+    pub fn first_index_of(&self, val: u8) -> Option<u8> {
+        for (i, w) in self.double_bits.iter().enumerate() {
+            let m = Self::eq_mask(*w, val);
+            if m != 0 {
+                return Some((i * 32) as u8 + (m.trailing_zeros() / 2) as u8);
+            }
+        }
+        None
+    }
+    // Iterate all 256 values in index order.
+    pub fn iter_values(&self) -> impl Iterator<Item = u8> + '_ {
+        self.double_bits
+            .iter()
+            .flat_map(|w| (0..32).map(move |s| ((w >> (2 * s)) & 3) as u8))
+    }
+    // Bitwise over the packed representation, i.e. each 2-bit value of the
+    // result is the bitwise combination of the corresponding input values.
+    pub fn difference(&self, other: &Self) -> Self {
+        self & &!other
+    }
+    pub fn symmetric_difference(&self, other: &Self) -> Self {
+        self ^ other
+    }
 }
+
+// Bitwise operators (by value and by reference) over the word arrays of the
+// two bitmap types.
+macro_rules! impl_bitwise_ops {
+    ($ty:ident, $field:ident) => {
+        impl_bitwise_ops!(@bin $ty, $field, BitAnd, bitand, BitAndAssign, bitand_assign, &=);
+        impl_bitwise_ops!(@bin $ty, $field, BitOr, bitor, BitOrAssign, bitor_assign, |=);
+        impl_bitwise_ops!(@bin $ty, $field, BitXor, bitxor, BitXorAssign, bitxor_assign, ^=);
+        impl Not for $ty {
+            type Output = $ty;
+            fn not(mut self) -> $ty {
+                for w in self.$field.iter_mut() {
+                    *w = !*w;
+                }
+                self
+            }
+        }
+        impl Not for &$ty {
+            type Output = $ty;
+            fn not(self) -> $ty {
+                !self.clone()
+            }
+        }
+    };
+    (@bin $ty:ident, $field:ident, $tr:ident, $m:ident, $atr:ident, $am:ident, $op:tt) => {
+        impl $atr<&$ty> for $ty {
+            fn $am(&mut self, rhs: &$ty) {
+                for (a, b) in self.$field.iter_mut().zip(rhs.$field.iter()) {
+                    *a $op *b;
+                }
+            }
+        }
+        impl $atr for $ty {
+            fn $am(&mut self, rhs: $ty) {
+                self.$am(&rhs);
+            }
+        }
+        impl $tr<&$ty> for &$ty {
+            type Output = $ty;
+            fn $m(self, rhs: &$ty) -> $ty {
+                let mut out = self.clone();
+                out.$am(rhs);
+                out
+            }
+        }
+        impl $tr for $ty {
+            type Output = $ty;
+            fn $m(mut self, rhs: $ty) -> $ty {
+                self.$am(&rhs);
+                self
+            }
+        }
+    };
+}
+
+impl_bitwise_ops!(Bitmap256, bits);
+impl_bitwise_ops!(DoubleBitmap256, double_bits);
@@ -0,0 +1,197 @@
+use crate::Bitmap256;
+use serde::{Deserialize, Serialize};
+
+/// A growable bitmap over u64 positions, for selections that span more than
+/// one 256-row chunk (eg. a whole block, layer or table). Positions are split
+/// into a segment number (pos / 256) and an offset within the segment, and
+/// the bitmap stores a sorted sequence of non-empty segments as Bitmap256s,
+/// roaring-style: sparse selections cost nothing for their empty stretches
+/// and dense ones cost 32 bytes per 256 positions.
+#[derive(Clone, Default, PartialEq, Eq, Debug, Hash, Serialize, Deserialize)]
+pub struct Bitvec {
+    // Sorted by segment number, with no empty segments.
+    segs: Vec<(u64, Bitmap256)>,
+}
+
+fn split(pos: u64) -> (u64, u8) {
+    (pos >> 8, pos as u8)
+}
+
+impl Bitvec {
+    pub fn new() -> Self {
+        Bitvec { segs: Vec::new() }
+    }
+
+    fn find(&self, seg: u64) -> Result<usize, usize> {
+        self.segs.binary_search_by_key(&seg, |(s, _)| *s)
+    }
+
+    pub fn set(&mut self, pos: u64, val: bool) {
+        let (seg, i) = split(pos);
+        match self.find(seg) {
+            Ok(n) => {
+                self.segs[n].1.set(i, val);
+                if !val && self.segs[n].1.is_empty() {
+                    self.segs.remove(n);
+                }
+            }
+            Err(n) => {
+                if val {
+                    let mut bm = Bitmap256::new();
+                    bm.set(i, true);
+                    self.segs.insert(n, (seg, bm));
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, pos: u64) -> bool {
+        let (seg, i) = split(pos);
+        match self.find(seg) {
+            Ok(n) => self.segs[n].1.get(i),
+            Err(_) => false,
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.segs.iter().map(|(_, bm)| bm.count() as u64).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segs.is_empty()
+    }
+
+    // Return the number of bits set up to and including pos, matching
+    // Bitmap256::rank.
+    pub fn rank(&self, pos: u64) -> u64 {
+        let (seg, i) = split(pos);
+        let mut bits = 0;
+        for (s, bm) in self.segs.iter() {
+            if *s < seg {
+                bits += bm.count() as u64;
+            } else {
+                if *s == seg {
+                    bits += bm.rank(i) as u64;
+                }
+                break;
+            }
+        }
+        bits
+    }
+
+    // Return the position of the n'th (0-based) set bit, if any.
+    pub fn select(&self, mut n: u64) -> Option<u64> {
+        for (s, bm) in self.segs.iter() {
+            let count = bm.count() as u64;
+            if n < count {
+                return bm.select(n as u32).map(|i| (s << 8) | i as u64);
+            }
+            n -= count;
+        }
+        None
+    }
+
+    // Merge two sorted segment sequences, combining segments present in
+    // both with `both` and keeping one-sided segments if `keep_lhs` /
+    // `keep_rhs` say so.
+    fn merge(
+        &mut self,
+        other: &Self,
+        keep_lhs: bool,
+        keep_rhs: bool,
+        both: impl Fn(&mut Bitmap256, &Bitmap256),
+    ) {
+        let mut out = Vec::with_capacity(self.segs.len().max(other.segs.len()));
+        let mut lhs = std::mem::take(&mut self.segs).into_iter().peekable();
+        let mut rhs = other.segs.iter().peekable();
+        loop {
+            match (lhs.peek(), rhs.peek()) {
+                (Some((a, _)), Some((b, _))) if a == b => {
+                    let (s, mut bm) = lhs.next().unwrap();
+                    both(&mut bm, &rhs.next().unwrap().1);
+                    if bm.any() {
+                        out.push((s, bm));
+                    }
+                }
+                (Some((a, _)), Some((b, _))) if a < b => {
+                    let seg = lhs.next().unwrap();
+                    if keep_lhs {
+                        out.push(seg);
+                    }
+                }
+                (_, Some(_)) => {
+                    let seg = rhs.next().unwrap();
+                    if keep_rhs {
+                        out.push(seg.clone());
+                    }
+                }
+                (Some(_), None) => {
+                    let seg = lhs.next().unwrap();
+                    if keep_lhs {
+                        out.push(seg);
+                    }
+                }
+                (None, None) => break,
+            }
+        }
+        self.segs = out;
+    }
+
+    pub fn union(&mut self, other: &Self) {
+        self.merge(other, true, true, |a, b| a.union(b))
+    }
+
+    pub fn intersect(&mut self, other: &Self) {
+        self.merge(other, false, false, |a, b| a.intersect(b))
+    }
+
+    pub fn subtract(&mut self, other: &Self) {
+        self.merge(other, true, false, |a, b| a.subtract(b))
+    }
+
+    pub fn iter_ones(&self) -> impl Iterator<Item = u64> + '_ {
+        self.segs
+            .iter()
+            .flat_map(|(s, bm)| bm.iter_ones().map(move |i| (s << 8) | i as u64))
+    }
+
+    // Iterate the non-empty segments as (segment number, bitmap) pairs. The
+    // segment covers positions seg*256 .. seg*256+255.
+    pub fn segments(&self) -> impl Iterator<Item = (u64, &Bitmap256)> + '_ {
+        self.segs.iter().map(|(s, bm)| (*s, bm))
+    }
+
+    pub fn segment(&self, seg: u64) -> Option<&Bitmap256> {
+        self.find(seg).ok().map(|n| &self.segs[n].1)
+    }
+
+    // Replace the contents of one segment wholesale.
+    pub fn set_segment(&mut self, seg: u64, bm: Bitmap256) {
+        match (self.find(seg), bm.any()) {
+            (Ok(n), true) => self.segs[n].1 = bm,
+            (Ok(n), false) => {
+                self.segs.remove(n);
+            }
+            (Err(n), true) => self.segs.insert(n, (seg, bm)),
+            (Err(_), false) => (),
+        }
+    }
+}
+
+impl From<Bitmap256> for Bitvec {
+    fn from(bm: Bitmap256) -> Self {
+        let mut bv = Bitvec::new();
+        bv.set_segment(0, bm);
+        bv
+    }
+}
+
+impl FromIterator<(u64, Bitmap256)> for Bitvec {
+    fn from_iter<I: IntoIterator<Item = (u64, Bitmap256)>>(iter: I) -> Self {
+        let mut bv = Bitvec::new();
+        for (seg, bm) in iter {
+            bv.set_segment(seg, bm);
+        }
+        bv
+    }
+}
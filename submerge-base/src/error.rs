@@ -3,6 +3,8 @@
 // 2. A way to centralize setting a breakpoint to trap any error in the system fairly soon
 //    after it's created (or at least when it's propagated from a library we use back to us)
 // 3. Same but for logging / emitting error messages into the tracing/logging system
+// 4. A coarse classification of errors so callers (mainly txn and net) can branch on
+//    the class of failure without inspecting message strings
 
 use backtrace_error::DynBacktraceError;
 use std::borrow::Cow;
@@ -11,9 +13,64 @@ use tracing::error;
 #[cfg(test)]
 use test_log::test;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ErrorKind {
+    // The OS or an underlying stream reported a failure.
+    Io,
+    // Stored or received bytes are damaged or inconsistent with themselves.
+    Corruption,
+    // Bytes are well-formed but in a format or version we don't support.
+    Format,
+    // A peer violated the message protocol.
+    Protocol,
+    // Something we were waiting for didn't happen in time.
+    Timeout,
+    // An operation lost a race with a concurrent one and can be retried.
+    Conflict,
+    // The node is too busy to take the work on now, and shed it.
+    Overloaded,
+    // The work was called off before it was done.
+    Aborted,
+    // Evaluating an expression failed on its data. Every node evaluating the
+    // same expression over the same data fails the same way.
+    Eval,
+    // A bug, or an error we have no better classification for.
+    Internal,
+}
+
+impl ErrorKind {
+    // Whether retrying the failed operation, unchanged, might succeed.
+    pub fn is_retryable(self) -> bool {
+        matches!(
+            self,
+            ErrorKind::Timeout | ErrorKind::Conflict | ErrorKind::Overloaded
+        )
+    }
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            ErrorKind::Io => "io",
+            ErrorKind::Corruption => "corruption",
+            ErrorKind::Format => "format",
+            ErrorKind::Protocol => "protocol",
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::Conflict => "conflict",
+            ErrorKind::Overloaded => "overloaded",
+            ErrorKind::Aborted => "aborted",
+            ErrorKind::Eval => "eval",
+            ErrorKind::Internal => "internal",
+        };
+        f.write_str(s)
+    }
+}
+
 #[derive(Debug)]
-#[allow(dead_code)]
-pub struct Error(DynBacktraceError);
+pub struct Error {
+    kind: ErrorKind,
+    inner: DynBacktraceError,
+}
 pub type Result<T> = std::result::Result<T, Error>;
 
 struct SimpleErr(Cow<'static, str>);
@@ -39,20 +96,186 @@ impl<E: std::error::Error + Send + Sync + 'static> From<E> for Error {
     }
 }
 
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} error: {}", self.kind, self.inner)
+    }
+}
+
+// Best-effort classification of errors arriving from libraries.
+fn classify<E: std::error::Error + 'static>(err: &E) -> ErrorKind {
+    let any: &dyn std::any::Any = err;
+    if let Some(io) = any.downcast_ref::<std::io::Error>() {
+        match io.kind() {
+            // WouldBlock is a non-blocking stream's backpressure, not a
+            // timeout, so it stays an io error for the caller to handle.
+            std::io::ErrorKind::TimedOut => ErrorKind::Timeout,
+            _ => ErrorKind::Io,
+        }
+    } else {
+        ErrorKind::Internal
+    }
+}
+
 impl Error {
     pub fn new<E: std::error::Error + Send + Sync + 'static>(err: E) -> Error {
-        error!(target: "submerge", "{:?}", err);
-        let dbe = DynBacktraceError::from(err);
-        Error(dbe)
+        let kind = classify(&err);
+        Error::new_with_kind(kind, err)
+    }
+
+    pub fn new_with_kind<E: std::error::Error + Send + Sync + 'static>(
+        kind: ErrorKind,
+        err: E,
+    ) -> Error {
+        error!(target: "submerge", "{} error: {:?}", kind, err);
+        let inner = DynBacktraceError::from(err);
+        Error { kind, inner }
+    }
+
+    pub fn with_kind(kind: ErrorKind, msg: impl Into<Cow<'static, str>>) -> Error {
+        Error::new_with_kind(kind, SimpleErr(msg.into()))
+    }
+
+    pub fn io(msg: impl Into<Cow<'static, str>>) -> Error {
+        Error::with_kind(ErrorKind::Io, msg)
+    }
+    pub fn corruption(msg: impl Into<Cow<'static, str>>) -> Error {
+        Error::with_kind(ErrorKind::Corruption, msg)
+    }
+    pub fn format(msg: impl Into<Cow<'static, str>>) -> Error {
+        Error::with_kind(ErrorKind::Format, msg)
+    }
+    pub fn protocol(msg: impl Into<Cow<'static, str>>) -> Error {
+        Error::with_kind(ErrorKind::Protocol, msg)
+    }
+    pub fn timeout(msg: impl Into<Cow<'static, str>>) -> Error {
+        Error::with_kind(ErrorKind::Timeout, msg)
+    }
+    pub fn conflict(msg: impl Into<Cow<'static, str>>) -> Error {
+        Error::with_kind(ErrorKind::Conflict, msg)
+    }
+    pub fn overloaded(msg: impl Into<Cow<'static, str>>) -> Error {
+        Error::with_kind(ErrorKind::Overloaded, msg)
+    }
+    pub fn aborted(msg: impl Into<Cow<'static, str>>) -> Error {
+        Error::with_kind(ErrorKind::Aborted, msg)
+    }
+    pub fn eval(msg: impl Into<Cow<'static, str>>) -> Error {
+        Error::with_kind(ErrorKind::Eval, msg)
+    }
+    pub fn internal(msg: impl Into<Cow<'static, str>>) -> Error {
+        Error::with_kind(ErrorKind::Internal, msg)
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+    // The error's message, without its kind or backtrace.
+    pub fn message(&self) -> String {
+        (*self.inner).to_string()
+    }
+    // The wrapped error, if it has type E.
+    pub fn downcast_ref<E: std::error::Error + 'static>(&self) -> Option<&E> {
+        self.inner.downcast_ref()
+    }
+    pub fn is_io(&self) -> bool {
+        self.kind == ErrorKind::Io
+    }
+    pub fn is_corruption(&self) -> bool {
+        self.kind == ErrorKind::Corruption
+    }
+    pub fn is_format(&self) -> bool {
+        self.kind == ErrorKind::Format
+    }
+    pub fn is_protocol(&self) -> bool {
+        self.kind == ErrorKind::Protocol
+    }
+    pub fn is_timeout(&self) -> bool {
+        self.kind == ErrorKind::Timeout
+    }
+    pub fn is_conflict(&self) -> bool {
+        self.kind == ErrorKind::Conflict
+    }
+    pub fn is_overloaded(&self) -> bool {
+        self.kind == ErrorKind::Overloaded
+    }
+    pub fn is_aborted(&self) -> bool {
+        self.kind == ErrorKind::Aborted
+    }
+    pub fn is_eval(&self) -> bool {
+        self.kind == ErrorKind::Eval
+    }
+    pub fn is_internal(&self) -> bool {
+        self.kind == ErrorKind::Internal
+    }
+    pub fn is_retryable(&self) -> bool {
+        self.kind.is_retryable()
     }
 }
 
+// Unclassified errors are Internal; prefer a specific constructor above
+// where the failure class is known.
 pub fn err(msg: impl Into<Cow<'static, str>>) -> Error {
-    let err = SimpleErr(msg.into());
-    Error::new(err)
+    Error::internal(msg)
 }
 
 #[test]
 fn test_error() {
     let _err = err("test error");
 }
+
+#[test]
+fn test_error_kinds() {
+    assert_eq!(err("x").kind(), ErrorKind::Internal);
+    assert!(Error::corruption("x").is_corruption());
+    assert!(Error::timeout("x").is_retryable());
+    assert!(Error::conflict("x").is_retryable());
+    assert!(!Error::protocol("x").is_retryable());
+
+    let io: Error = std::io::Error::from(std::io::ErrorKind::NotFound).into();
+    assert!(io.is_io());
+    assert!(!io.is_retryable());
+    let slow: Error = std::io::Error::from(std::io::ErrorKind::TimedOut).into();
+    assert!(slow.is_timeout());
+    let full: Error = std::io::Error::from(std::io::ErrorKind::WouldBlock).into();
+    assert!(full.is_io());
+    let other: Error = "x".parse::<i64>().unwrap_err().into();
+    assert_eq!(other.kind(), ErrorKind::Internal);
+    assert!(other.to_string().starts_with("internal error: "));
+    assert_eq!(err("plain message").message(), "plain message");
+    assert!(other.downcast_ref::<std::num::ParseIntError>().is_some());
+    assert!(io.downcast_ref::<std::num::ParseIntError>().is_none());
+    assert!(!Error::eval("x").is_retryable());
+
+    // Every kind has a constructor and a predicate that agree.
+    let preds: [fn(&Error) -> bool; 10] = [
+        Error::is_io,
+        Error::is_corruption,
+        Error::is_format,
+        Error::is_protocol,
+        Error::is_timeout,
+        Error::is_conflict,
+        Error::is_overloaded,
+        Error::is_aborted,
+        Error::is_eval,
+        Error::is_internal,
+    ];
+    let errs = [
+        (Error::io("x"), ErrorKind::Io),
+        (Error::corruption("x"), ErrorKind::Corruption),
+        (Error::format("x"), ErrorKind::Format),
+        (Error::protocol("x"), ErrorKind::Protocol),
+        (Error::timeout("x"), ErrorKind::Timeout),
+        (Error::conflict("x"), ErrorKind::Conflict),
+        (Error::overloaded("x"), ErrorKind::Overloaded),
+        (Error::aborted("x"), ErrorKind::Aborted),
+        (Error::eval("x"), ErrorKind::Eval),
+        (Error::internal("x"), ErrorKind::Internal),
+    ];
+    for (i, (e, kind)) in errs.iter().enumerate() {
+        assert_eq!(e.kind(), *kind);
+        for (j, is) in preds.iter().enumerate() {
+            assert_eq!(is(e), i == j);
+        }
+    }
+}
@@ -1,8 +1,14 @@
+mod arena;
 mod bitmap256;
+mod bitvec;
 mod error;
+pub mod telemetry;
+pub mod varint;
 
-pub use bitmap256::{Bitmap256, DoubleBitmap256};
-pub use error::{err, Error, Result};
+pub use arena::{Arena, ArenaRef};
+pub use bitmap256::{Bitmap256, Bitmap256Iter, DoubleBitmap256};
+pub use bitvec::Bitvec;
+pub use error::{err, Error, ErrorKind, Result};
 
 #[cfg(test)]
 mod test;
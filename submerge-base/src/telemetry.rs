@@ -0,0 +1,56 @@
+// Span and field conventions for tracing across the submerge crates.
+//
+// Everything logs under the "submerge" target. Spans are named for the
+// subsystem and level of structure they cover ("coldb.layer", "coldb.block",
+// "coldb.track", "txn", "net.msg"), carry an `op` field naming the operation,
+// and then whichever of these fields apply:
+//
+//   block     -- coldb block number within its layer
+//   track     -- coldb track number within its block
+//   txn_time  -- RealmTime of the transaction
+//   node      -- NodeID of the node doing the work
+//   src, dst  -- NodeIDs of a message's endpoints
+//   seq       -- message sequence number
+//
+// Create spans through the helpers here rather than ad hoc so the names and
+// fields stay uniform and a slow query can be followed from net through txn
+// into coldb with one filter. coldb spans are at trace level since they're
+// on hot paths; txn and net spans are at debug.
+
+use std::fmt::Debug;
+use tracing::{debug_span, trace_span, Span};
+
+pub const TARGET: &str = "submerge";
+
+pub fn layer_span(op: &'static str) -> Span {
+    trace_span!(target: TARGET, "coldb.layer", op)
+}
+
+pub fn block_span(op: &'static str, block: usize) -> Span {
+    trace_span!(target: TARGET, "coldb.block", op, block)
+}
+
+pub fn track_span(op: &'static str, block: usize, track: usize) -> Span {
+    trace_span!(target: TARGET, "coldb.track", op, block, track)
+}
+
+pub fn txn_span(op: &'static str, node: &impl Debug, txn_time: &impl Debug) -> Span {
+    debug_span!(target: TARGET, "txn", op, node = ?node, txn_time = ?txn_time)
+}
+
+pub fn msg_span(op: &'static str, src: &impl Debug, dst: &impl Debug, seq: i64) -> Span {
+    debug_span!(target: TARGET, "net.msg", op, src = ?src, dst = ?dst, seq)
+}
+
+#[cfg(test)]
+use test_log::test;
+
+#[test]
+fn test_spans() {
+    let _layer = layer_span("open").entered();
+    let _block = block_span("open", 1).entered();
+    let _track = track_span("open", 1, 2).entered();
+    let _txn = txn_span("put", &3, &(4, 5)).entered();
+    let _msg = msg_span("send", &3, &6, 7).entered();
+    tracing::trace!(target: TARGET, "inside all spans");
+}
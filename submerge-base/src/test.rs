@@ -1 +1,4 @@
+mod arena;
 mod bitmap256;
+mod bitvec;
+mod varint;
@@ -0,0 +1,37 @@
+use crate::{Arena, ErrorKind, Result};
+
+#[test]
+fn test_arena_alloc_and_reset() -> Result<()> {
+    let mut arena = Arena::with_capacity(64);
+    let a = arena.alloc(b"hello")?;
+    let b = arena.alloc(b"")?;
+    let (c, buf) = arena.alloc_mut(3)?;
+    buf.copy_from_slice(b"abc");
+    assert_eq!(arena.get(a)?, b"hello");
+    assert!(b.is_empty());
+    assert_eq!(arena.get(b)?, b"");
+    assert_eq!(arena.get(c)?, b"abc");
+    assert_eq!(arena.used(), 8);
+
+    // Growing past the initial capacity keeps earlier handles valid.
+    let big = arena.alloc(&[7_u8; 1000])?;
+    assert_eq!(arena.get(a)?, b"hello");
+    assert_eq!(arena.get(big)?.len(), 1000);
+
+    let cap = arena.capacity();
+    arena.reset();
+    assert_eq!(arena.used(), 0);
+    assert_eq!(arena.capacity(), cap);
+    assert_eq!(arena.try_get(a), None);
+    assert_eq!(arena.get(a).unwrap_err().kind(), ErrorKind::Internal);
+    let d = arena.alloc(b"world")?;
+    assert_eq!(arena.get(d)?, b"world");
+
+    // An allocation past the arena's addressing limit fails, and leaves
+    // the arena as it was.
+    let huge = arena.alloc_mut(usize::MAX).unwrap_err();
+    assert_eq!(huge.kind(), ErrorKind::Overloaded);
+    assert!(arena.alloc_mut(u32::MAX as usize).is_err());
+    assert_eq!(arena.used(), 5);
+    Ok(())
+}
@@ -33,3 +33,102 @@ fn test_double_bitmap() {
         assert_eq!(bm.get(i as u8), val as u8);
     }
 }
+
+#[test]
+fn test_iter_ones_and_zeros() {
+    let mut bm = Bitmap256::new();
+    assert_eq!(bm.iter_ones().count(), 0);
+    assert_eq!(bm.iter_zeros().len(), 256);
+    let idxs = [0_u8, 1, 63, 64, 65, 127, 128, 200, 254, 255];
+    for i in idxs {
+        bm.set(i, true);
+    }
+    assert_eq!(bm.iter_ones().len(), idxs.len());
+    assert_eq!(bm.iter_ones().collect::<Vec<_>>(), idxs);
+    let zeros = bm.iter_zeros().collect::<Vec<_>>();
+    assert_eq!(zeros.len(), 256 - idxs.len());
+    assert!(zeros.windows(2).all(|w| w[0] < w[1]));
+    assert!(zeros.iter().all(|i| !bm.get(*i)));
+    bm.set_all();
+    assert!(bm.iter_ones().map(|i| i as usize).eq(0..256));
+    assert_eq!(bm.iter_zeros().next(), None);
+}
+
+#[test]
+fn test_bitwise_ops() {
+    let mut a = Bitmap256::new();
+    let mut b = Bitmap256::new();
+    for i in 0..=255_u8 {
+        a.set(i, i % 2 == 0);
+        b.set(i, i % 3 == 0);
+    }
+    let and = &a & &b;
+    let or = &a | &b;
+    let xor = &a ^ &b;
+    let not = !&a;
+    let diff = a.difference(&b);
+    let sym = a.symmetric_difference(&b);
+    for i in 0..=255_u8 {
+        let (x, y) = (i % 2 == 0, i % 3 == 0);
+        assert_eq!(and.get(i), x && y);
+        assert_eq!(or.get(i), x || y);
+        assert_eq!(xor.get(i), x != y);
+        assert_eq!(not.get(i), !x);
+        assert_eq!(diff.get(i), x && !y);
+        assert_eq!(sym.get(i), x != y);
+    }
+    let mut c = a.clone();
+    c &= &b;
+    assert_eq!(c, and);
+    c |= a.clone();
+    assert_eq!(c, a);
+    c ^= &a;
+    assert!(c.is_empty());
+    assert!((a.clone() | !a).is_full());
+}
+
+#[test]
+fn test_double_bitmap_bitwise_ops() {
+    let mut a = DoubleBitmap256::new();
+    let mut b = DoubleBitmap256::new();
+    for i in 0..=255_u8 {
+        a.set(i, i % 4);
+        b.set(i, (i / 4) % 4);
+    }
+    let and = &a & &b;
+    let xor = a.clone() ^ b.clone();
+    let not = !&a;
+    let diff = a.difference(&b);
+    for i in 0..=255_u8 {
+        let (x, y) = (i % 4, (i / 4) % 4);
+        assert_eq!(and.get(i), x & y);
+        assert_eq!(xor.get(i), x ^ y);
+        assert_eq!(not.get(i), 3 - x);
+        assert_eq!(diff.get(i), x & !y & 3);
+    }
+}
+
+#[test]
+fn test_double_bitmap_scans() {
+    let mut bm = DoubleBitmap256::new();
+    assert_eq!(bm.count_eq(0), 256);
+    assert_eq!(bm.first_index_of(2), None);
+    for i in 0..=255_u8 {
+        bm.set(i, i % 7 % 4);
+    }
+    let vals = bm.iter_values().collect::<Vec<_>>();
+    assert_eq!(vals.len(), 256);
+    for val in 0..4_u8 {
+        let naive = (0..=255_u8).filter(|i| bm.get(*i) == val).count();
+        assert_eq!(bm.count_eq(val) as usize, naive);
+        assert_eq!(
+            bm.first_index_of(val),
+            (0..=255_u8).find(|i| bm.get(*i) == val)
+        );
+        for i in 0..=255_u8 {
+            assert_eq!(vals[i as usize], bm.get(i));
+            let naive_rank = (0..=i).filter(|j| bm.get(*j) == val).count();
+            assert_eq!(bm.rank_eq(val, i), naive_rank);
+        }
+    }
+}
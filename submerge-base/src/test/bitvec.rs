@@ -0,0 +1,82 @@
+use crate::{Bitmap256, Bitvec};
+
+fn from_positions(ps: &[u64]) -> Bitvec {
+    let mut bv = Bitvec::new();
+    for p in ps {
+        bv.set(*p, true);
+    }
+    bv
+}
+
+#[test]
+fn test_bitvec_set_get_rank_select() {
+    let ps = [0, 5, 255, 256, 1000, 1 << 24, u64::MAX];
+    let mut bv = from_positions(&ps);
+    assert_eq!(bv.count(), ps.len() as u64);
+    assert_eq!(bv.iter_ones().collect::<Vec<_>>(), ps);
+    for (n, p) in ps.iter().enumerate() {
+        assert!(bv.get(*p));
+        assert_eq!(bv.rank(*p), n as u64 + 1);
+        assert_eq!(bv.select(n as u64), Some(*p));
+    }
+    assert!(!bv.get(6));
+    assert_eq!(bv.rank(999), 4);
+    assert_eq!(bv.select(ps.len() as u64), None);
+
+    bv.set(1000, false);
+    assert!(!bv.get(1000));
+    assert_eq!(bv.segment(1000 >> 8), None);
+    assert_eq!(bv.count(), ps.len() as u64 - 1);
+}
+
+#[test]
+fn test_bitvec_set_ops() {
+    let a = from_positions(&[1, 300, 600, 900]);
+    let b = from_positions(&[300, 601, 900, 5000]);
+
+    let mut u = a.clone();
+    u.union(&b);
+    assert_eq!(
+        u.iter_ones().collect::<Vec<_>>(),
+        [1, 300, 600, 601, 900, 5000]
+    );
+
+    let mut i = a.clone();
+    i.intersect(&b);
+    assert_eq!(i.iter_ones().collect::<Vec<_>>(), [300, 900]);
+    // Segment 2 (512..767) is non-empty on both sides but its
+    // intersection is empty, so it must be dropped.
+    assert_eq!(i.segment(2), None);
+
+    let mut d = a.clone();
+    d.subtract(&b);
+    assert_eq!(d.iter_ones().collect::<Vec<_>>(), [1, 600]);
+}
+
+#[test]
+fn test_bitvec_segments() {
+    let mut bm = Bitmap256::new();
+    bm.set(3, true);
+    let bv: Bitvec = [(0, bm.clone()), (7, bm.clone()), (9, Bitmap256::new())]
+        .into_iter()
+        .collect();
+    let segs = bv
+        .segments()
+        .map(|(s, b)| (s, b.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(segs, vec![(0, bm.clone()), (7, bm.clone())]);
+    assert_eq!(bv.iter_ones().collect::<Vec<_>>(), [3, 7 * 256 + 3]);
+    assert_eq!(Bitvec::from(bm.clone()).segment(0), Some(&bm));
+}
+
+#[test]
+fn test_bitmap256_select() {
+    let mut bm = Bitmap256::new();
+    for i in (0..=255_u8).step_by(3) {
+        bm.set(i, true);
+    }
+    for (n, i) in bm.iter_ones().enumerate() {
+        assert_eq!(bm.select(n as u32), Some(i));
+    }
+    assert_eq!(bm.select(bm.count()), None);
+}
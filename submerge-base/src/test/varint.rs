@@ -0,0 +1,108 @@
+use crate::varint::{
+    decode_ivarint, decode_uvarint, encode_ivarint, encode_uvarint, zigzag_decode, zigzag_encode,
+    VarintReadExt, VarintWriteExt, MAX_VARINT_LEN,
+};
+
+const INTERESTING_U64S: [u64; 12] = [
+    0,
+    1,
+    0x7f,
+    0x80,
+    0x3fff,
+    0x4000,
+    0xffff_ffff,
+    1 << 35,
+    (1 << 56) - 1,
+    1 << 63,
+    u64::MAX - 1,
+    u64::MAX,
+];
+
+#[test]
+fn test_zigzag() {
+    assert_eq!(zigzag_encode(0), 0);
+    assert_eq!(zigzag_encode(-1), 1);
+    assert_eq!(zigzag_encode(1), 2);
+    assert_eq!(zigzag_encode(-2), 3);
+    assert_eq!(zigzag_encode(i64::MAX), u64::MAX - 1);
+    assert_eq!(zigzag_encode(i64::MIN), u64::MAX);
+    for v in [
+        0,
+        1,
+        -1,
+        63,
+        -64,
+        64,
+        i64::MAX,
+        i64::MIN,
+        12345678,
+        -87654321,
+    ] {
+        assert_eq!(zigzag_decode(zigzag_encode(v)), v);
+    }
+}
+
+#[test]
+fn test_uvarint_slice_round_trip() {
+    for v in INTERESTING_U64S {
+        let mut buf = [0_u8; MAX_VARINT_LEN];
+        let n = encode_uvarint(v, &mut buf);
+        assert_eq!(n, (64 - v.leading_zeros() as usize).max(1).div_ceil(7));
+        assert_eq!(decode_uvarint(&buf[..n]).unwrap(), (v, n));
+        assert!(decode_uvarint(&buf[..n - 1]).is_err());
+        // The same value with a zero group on the end is overlong.
+        if n < MAX_VARINT_LEN - 1 {
+            let mut padded = buf;
+            padded[n - 1] |= 0x80;
+            padded[n] = 0;
+            let err = decode_uvarint(&padded[..n + 1]).unwrap_err();
+            assert!(err.is_corruption());
+        }
+    }
+    assert_eq!(decode_uvarint(&[0x96, 0x01, 0xff]).unwrap(), (150, 2));
+}
+
+#[test]
+fn test_ivarint_slice_round_trip() {
+    for v in INTERESTING_U64S {
+        for v in [v as i64, (v as i64).wrapping_neg()] {
+            let mut buf = [0_u8; MAX_VARINT_LEN];
+            let n = encode_ivarint(v, &mut buf);
+            assert_eq!(decode_ivarint(&buf[..n]).unwrap(), (v, n));
+        }
+    }
+}
+
+#[test]
+fn test_varint_rejects_bad_encodings() {
+    // 10th byte may only contribute the top bit of a u64.
+    let mut overflow = [0xff_u8; MAX_VARINT_LEN];
+    overflow[9] = 0x02;
+    assert!(decode_uvarint(&overflow).unwrap_err().is_corruption());
+    let overlong = [0x80_u8; MAX_VARINT_LEN + 1];
+    assert!(decode_uvarint(&overlong).unwrap_err().is_corruption());
+    assert!(decode_uvarint(&[0x80, 0x00]).unwrap_err().is_corruption());
+    assert!(decode_uvarint(&[0xff, 0x80, 0x00])
+        .unwrap_err()
+        .is_corruption());
+    assert!(std::io::Cursor::new([0x80, 0x00])
+        .read_uvarint()
+        .unwrap_err()
+        .is_corruption());
+    assert!(decode_uvarint(&[]).is_err());
+}
+
+#[test]
+fn test_varint_read_write_adapters() {
+    let mut out: Vec<u8> = Vec::new();
+    for v in INTERESTING_U64S {
+        out.write_uvarint(v).unwrap();
+        out.write_ivarint((v as i64).wrapping_neg()).unwrap();
+    }
+    let mut rd = std::io::Cursor::new(out);
+    for v in INTERESTING_U64S {
+        assert_eq!(rd.read_uvarint().unwrap(), v);
+        assert_eq!(rd.read_ivarint().unwrap(), (v as i64).wrapping_neg());
+    }
+    assert!(rd.read_uvarint().unwrap_err().is_corruption());
+}
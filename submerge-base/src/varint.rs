@@ -0,0 +1,113 @@
+// LEB128 variable-length integers and zigzag signed-to-unsigned mapping.
+//
+// Unsigned values are written 7 bits at a time, least significant group
+// first, with the high bit of each byte set when more bytes follow. Signed
+// values are zigzag-mapped first (0, -1, 1, -2, 2 ... => 0, 1, 2, 3, 4 ...)
+// so small magnitudes of either sign stay short.
+
+use crate::{Error, Result};
+use std::io::{Read, Write};
+
+// A u64 needs at most ceil(64/7) = 10 bytes.
+pub const MAX_VARINT_LEN: usize = 10;
+
+pub fn zigzag_encode(v: i64) -> u64 {
+    ((v << 1) ^ (v >> 63)) as u64
+}
+
+pub fn zigzag_decode(v: u64) -> i64 {
+    ((v >> 1) as i64) ^ -((v & 1) as i64)
+}
+
+// Encodes `v` into the front of `buf`, returning the number of bytes used.
+pub fn encode_uvarint(mut v: u64, buf: &mut [u8; MAX_VARINT_LEN]) -> usize {
+    let mut i = 0;
+    while v >= 0x80 {
+        buf[i] = (v as u8) | 0x80;
+        v >>= 7;
+        i += 1;
+    }
+    buf[i] = v as u8;
+    i + 1
+}
+
+pub fn encode_ivarint(v: i64, buf: &mut [u8; MAX_VARINT_LEN]) -> usize {
+    encode_uvarint(zigzag_encode(v), buf)
+}
+
+// Accumulates one byte of a varint, returning Some(value) on the final byte.
+// Rejects encodings that are overlong, ending in a zero group that a
+// shorter encoding would leave off, or that overflow 64 bits, so every value
+// has exactly one encoding.
+fn accumulate(acc: &mut u64, i: usize, byte: u8) -> Result<Option<u64>> {
+    if i == MAX_VARINT_LEN - 1 && byte > 1 {
+        return Err(Error::corruption("varint overflows u64"));
+    }
+    if i >= MAX_VARINT_LEN {
+        return Err(Error::corruption("varint too long"));
+    }
+    if i > 0 && byte == 0 {
+        return Err(Error::corruption("overlong varint"));
+    }
+    *acc |= ((byte & 0x7f) as u64) << (7 * i);
+    if byte & 0x80 == 0 {
+        Ok(Some(*acc))
+    } else {
+        Ok(None)
+    }
+}
+
+// Decodes a varint from the front of `buf`, returning the value and the
+// number of bytes consumed.
+pub fn decode_uvarint(buf: &[u8]) -> Result<(u64, usize)> {
+    let mut acc = 0;
+    for (i, byte) in buf.iter().enumerate() {
+        if let Some(v) = accumulate(&mut acc, i, *byte)? {
+            return Ok((v, i + 1));
+        }
+    }
+    Err(Error::corruption("truncated varint"))
+}
+
+pub fn decode_ivarint(buf: &[u8]) -> Result<(i64, usize)> {
+    let (v, n) = decode_uvarint(buf)?;
+    Ok((zigzag_decode(v), n))
+}
+
+// Adapters for anything implementing std::io::Write, which includes the
+// coldb Writer trait and network framing buffers.
+pub trait VarintWriteExt: Write {
+    fn write_uvarint(&mut self, v: u64) -> Result<usize> {
+        let mut buf = [0_u8; MAX_VARINT_LEN];
+        let n = encode_uvarint(v, &mut buf);
+        self.write_all(&buf[..n])?;
+        Ok(n)
+    }
+    fn write_ivarint(&mut self, v: i64) -> Result<usize> {
+        self.write_uvarint(zigzag_encode(v))
+    }
+}
+impl<W: Write + ?Sized> VarintWriteExt for W {}
+
+pub trait VarintReadExt: Read {
+    fn read_uvarint(&mut self) -> Result<u64> {
+        let mut acc = 0;
+        for i in 0..MAX_VARINT_LEN {
+            let mut byte = [0_u8; 1];
+            match self.read_exact(&mut byte) {
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    return Err(Error::corruption("truncated varint"))
+                }
+                res => res?,
+            }
+            if let Some(v) = accumulate(&mut acc, i, byte[0])? {
+                return Ok(v);
+            }
+        }
+        Err(Error::corruption("varint too long"))
+    }
+    fn read_ivarint(&mut self) -> Result<i64> {
+        Ok(zigzag_decode(self.read_uvarint()?))
+    }
+}
+impl<R: Read + ?Sized> VarintReadExt for R {}
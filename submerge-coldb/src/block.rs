@@ -5,7 +5,7 @@ use crate::{
     layer::{LayerReader, LayerWriter},
     track::{TrackInfoForBlock, TrackReader, TrackWriter},
 };
-use submerge_base::{err, Bitmap256, Result};
+use submerge_base::{err, telemetry, Bitmap256, Error, Result};
 
 pub(crate) struct BlockWriter {
     layer_writer: LayerWriter,
@@ -33,6 +33,10 @@ impl BlockWriter {
         })
     }
 
+    pub(crate) fn block_num(&self) -> usize {
+        self.info.block_num
+    }
+
     pub(crate) fn begin_track(self, wr: &mut impl Writer) -> Result<TrackWriter> {
         let track_num = self.meta.track_end_offsets.len();
         TrackWriter::new(self, track_num, wr)
@@ -52,7 +56,9 @@ impl BlockWriter {
     }
 
     pub fn finish_block(mut self, wr: &mut impl Writer) -> Result<LayerWriter> {
+        let _span = telemetry::block_span("finish", self.info.block_num).entered();
         self.meta.write(wr)?;
+        self.info.end_pos = wr.pos()?;
         wr.pop_context();
         wr.pop_context();
         self.layer_writer.note_block_finished(wr, &self.info)?;
@@ -84,10 +90,10 @@ impl BlockMeta {
         if ntracks > 255 {
             return Err(err("track count > 255"));
         }
-        if ntracks != self.track_rows.len() as usize {
+        if ntracks != self.track_rows.len() {
             return Err(err("track_lo_vals and track_rows length mismatch"));
         }
-        if ntracks != self.track_end_offsets.len() as usize {
+        if ntracks != self.track_end_offsets.len() {
             return Err(err("track_lo_vals and track_end_offsets length mismatch"));
         }
         wr.push_context("meta");
@@ -108,10 +114,10 @@ impl BlockMeta {
         let mut meta = BlockMeta::default();
         let ntracks: i64 = rd.read_le_num()?;
         if ntracks < 0 {
-            return Err(err("negative track count"));
+            return Err(Error::corruption("negative track count"));
         }
         if ntracks > 255 {
-            return Err(err("track count > 255"));
+            return Err(Error::corruption("track count > 255"));
         }
         let ntracks = ntracks as usize;
         meta.track_lo_vals = rd.read_le_num_vec(ntracks)?;
@@ -119,6 +125,15 @@ impl BlockMeta {
         meta.track_implicit = Bitmap256::read(rd)?;
         meta.track_rows = rd.read_le_num_vec(ntracks)?;
         meta.track_end_offsets = rd.read_le_num_vec(ntracks)?;
+        if rd.is_hardened() {
+            let mut prev = 0;
+            for &off in meta.track_end_offsets.iter() {
+                if off < prev || off > end_pos {
+                    return Err(Error::corruption("track end offset outside block"));
+                }
+                prev = off;
+            }
+        }
         Ok(meta)
     }
 }
@@ -150,13 +165,25 @@ impl BlockReader {
         track_num: usize,
         rd: &mut impl Reader,
     ) -> Result<Arc<TrackReader>> {
+        let _span = telemetry::track_span("open", self.block_num, track_num).entered();
         if let Some(&end_pos) = self.meta.track_end_offsets.get(track_num) {
             if end_pos < 0 {
-                return Err(err("negative track end offset"));
+                return Err(Error::corruption("negative track end offset"));
             }
-            TrackReader::new(self, track_num, end_pos, rd)
+            // The first track's start isn't recorded, so its data is only
+            // bounded by the start of the file.
+            let start_pos = match track_num {
+                0 => 0,
+                n => self.meta.track_end_offsets[n - 1],
+            };
+            let rows = self.meta.track_rows.get(track_num).copied().unwrap_or(0);
+            TrackReader::new(self, track_num, start_pos, end_pos, rows, rd)
         } else {
             Err(err("track number out of range"))
         }
     }
+
+    pub(crate) fn track_count(&self) -> usize {
+        self.meta.track_end_offsets.len()
+    }
 }
@@ -12,6 +12,7 @@ use submerge_base::{err, Result};
 
 #[derive(Clone, Default, PartialEq, Eq, Debug, Hash, PartialOrd, Ord)]
 pub(crate) struct DictEntryChunkMeta {
+    pub(crate) entries: u16,
     pub(crate) any_bin_large: bool,
     pub(crate) val_ty: Option<WordTy>,
     pub(crate) bin_len_ty: Option<WordTy>,
@@ -38,6 +39,10 @@ impl DictEntryChunkWriter {
         wr: &mut impl Writer,
         heap: &mut Heap,
     ) -> Result<()> {
+        if vals.len() > 256 {
+            return Err(err("dict entry chunk longer than 256 entries"));
+        }
+        self.meta.entries = vals.len() as u16;
         let n_components = vals
             .iter()
             .map(|x| x.get_component_count())
@@ -55,7 +60,7 @@ impl DictEntryChunkWriter {
                 .map(|x| x.get_component_as_int(component, heap))
                 .collect::<Vec<i64>>();
             let (min, wordty) = WordTy::select_min_and_ty(&vals);
-            wr.write_annotated_le_wordty_slice(&vals, wordty.clone())?;
+            wr.write_annotated_le_wordty_slice(&vals, wordty)?;
             if component == COMPONENT_VALUE {
                 self.meta.val_ty = Some(wordty);
             } else if component == BIN_COMPONENT_LEN {
@@ -112,7 +117,7 @@ impl DictCodeChunkWriter {
         }
 
         // Then decide whether to row-end-encode this chunk.
-        let (run_vals, run_ends) = run_end_encode(&vals)?;
+        let (run_vals, run_ends) = run_end_encode(vals)?;
         let chunk_code_width = if self.meta.two_bytes { 2 } else { 1 };
         let run_end_encoded_len = run_ends.len() * (chunk_code_width + 2);
         let simple_encoded_len = vals.len() * chunk_code_width;
@@ -1,10 +1,12 @@
 use std::{
+    borrow::Cow,
     fs::File,
     io::{BufReader, BufWriter, Cursor, Read, Seek, Write},
+    ops::Range,
     path::PathBuf,
     sync::Arc,
 };
-use submerge_base::{err, Bitmap256, Result};
+use submerge_base::{err, Bitmap256, Error, Result};
 
 #[cfg(test)]
 use crate::test::annotations::Annotations;
@@ -30,22 +32,84 @@ impl RangeExt for std::ops::Range<i64> {
     }
 }
 
+// ReadMode
+
+// Layers we wrote ourselves are read in Trusted mode. Layers that arrive from
+// elsewhere (replication, imports) should be read in Hardened mode, in which
+// every length and offset taken from the file is checked against the length
+// of the stream (and against format maxima) before anything is allocated or
+// seeked-to, so a malformed file produces an error rather than a panic or a
+// huge allocation.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Hash, PartialOrd, Ord)]
+pub(crate) enum ReadMode {
+    #[default]
+    Trusted,
+    Hardened,
+}
+
 // Reader and Writer
 
 pub(crate) trait Reader: Read + Seek + Send + Sized {
     fn try_clone_independent(&self) -> Result<Self>;
+    fn read_mode(&self) -> ReadMode;
+    fn set_read_mode(&mut self, mode: ReadMode);
+    fn with_read_mode(mut self, mode: ReadMode) -> Self {
+        self.set_read_mode(mode);
+        self
+    }
+    fn is_hardened(&self) -> bool {
+        self.read_mode() == ReadMode::Hardened
+    }
+    // The total length of the underlying stream.
+    fn total_len(&mut self) -> Result<i64>;
     fn pos(&mut self) -> Result<i64> {
         Ok(self.stream_position()?.try_into()?)
     }
+    // In hardened mode, fail unless `off` lies within the stream.
+    fn check_offset(&mut self, off: i64) -> Result<()> {
+        if self.is_hardened() && (off < 0 || off > self.total_len()?) {
+            return Err(Error::corruption("offset outside stream"));
+        }
+        Ok(())
+    }
+    // In hardened mode, fail unless `count` items of `size` bytes each
+    // remain in the stream after the current position.
+    fn check_remaining(&mut self, count: usize, size: usize) -> Result<()> {
+        if self.is_hardened() {
+            let need = count
+                .checked_mul(size)
+                .and_then(|n| i64::try_from(n).ok())
+                .ok_or_else(|| Error::corruption("read length overflow"))?;
+            let end = self
+                .pos()?
+                .checked_add(need)
+                .ok_or_else(|| Error::corruption("read length overflow"))?;
+            if end > self.total_len()? {
+                return Err(Error::corruption("read past end of stream"));
+            }
+        }
+        Ok(())
+    }
+    // Fill `buf` from the stream. Running out of stream partway means the
+    // file is shorter than its own lengths say, which is corruption.
+    fn read_full(&mut self, buf: &mut [u8]) -> Result<()> {
+        match self.read_exact(buf) {
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                Err(Error::corruption("read past end of stream"))
+            }
+            res => Ok(res?),
+        }
+    }
     fn read_le_num<const N: usize, T: funty::Numeric<Bytes = [u8; N]>>(&mut self) -> Result<T> {
         let mut buf: [u8; N] = [0; N];
-        self.read_exact(&mut buf)?;
+        self.read_full(&mut buf)?;
         Ok(T::from_le_bytes(buf))
     }
     fn read_le_num_vec<const N: usize, T: funty::Numeric<Bytes = [u8; N]>>(
         &mut self,
         len: usize,
     ) -> Result<Vec<T>> {
+        self.check_remaining(len, N)?;
         let mut buf = vec![T::default(); len];
         self.read_le_num_slice(&mut buf)?;
         Ok(buf)
@@ -61,22 +125,41 @@ pub(crate) trait Reader: Read + Seek + Send + Sized {
     }
     fn read_footer_len_ending_at_pos_and_rewind_to_start(&mut self, pos: i64) -> Result<()> {
         if pos < 8 {
-            return Err(err("footer seek underflow"));
+            return Err(Error::corruption("footer seek underflow"));
         }
+        self.check_offset(pos)?;
         let pos_minus_len_len = pos - 8;
         self.seek(std::io::SeekFrom::Start(pos_minus_len_len as u64))?;
         let len: i64 = self.read_le_num::<8, i64>()?;
         if len < 0 {
-            return Err(err("negative footer len"));
+            return Err(Error::corruption("negative footer len"));
         }
-        let seek = -len;
-        if let Some(seek) = seek.checked_sub(8) {
-            self.seek(std::io::SeekFrom::Current(seek))?;
-        } else {
-            return Err(err("footer seek underflow"));
+        if len > pos_minus_len_len {
+            return Err(Error::corruption("footer seek underflow"));
         }
+        self.seek(std::io::SeekFrom::Start((pos_minus_len_len - len) as u64))?;
         Ok(())
     }
+    // Returns the bytes in `range`. Readers backed by memory (or, eventually,
+    // an mmap) override this to borrow directly from their storage so chunk
+    // bytes can be handed to scan kernels without copying; the default
+    // implementation seeks and copies through `Read`. The reader's position
+    // afterwards is unspecified.
+    fn read_range(&mut self, range: Range<i64>) -> Result<Cow<'_, [u8]>> {
+        let len = checked_range_len(&range)?;
+        self.seek(std::io::SeekFrom::Start(range.start as u64))?;
+        self.check_remaining(len, 1)?;
+        let mut buf = vec![0_u8; len];
+        self.read_full(&mut buf)?;
+        Ok(Cow::Owned(buf))
+    }
+}
+
+fn checked_range_len(range: &Range<i64>) -> Result<usize> {
+    if range.start < 0 || range.end < range.start {
+        return Err(err("bad read range"));
+    }
+    Ok(usize::try_from(range.end - range.start)?)
 }
 
 pub(crate) trait Writer: Write + Seek + Send + Sized {
@@ -93,7 +176,7 @@ pub(crate) trait Writer: Write + Seek + Send + Sized {
     #[cfg(test)]
     fn annotate_to_pos_from<T: ToString>(&mut self, name: T, start: i64) -> Result<()> {
         let pos = self.annotate_pos()?;
-        self.get_annotations().annotate((start..pos).into(), name);
+        self.get_annotations().annotate(start..pos, name);
         Ok(())
     }
     #[cfg(test)]
@@ -192,7 +275,7 @@ pub(crate) trait Writer: Write + Seek + Send + Sized {
             for &v in val {
                 let tmp = v.to_be_bytes();
                 let byte = tmp[lane as usize];
-                w.write(&[byte])?;
+                w.write_all(&[byte])?;
             }
             Ok(())
         })
@@ -206,7 +289,7 @@ pub(crate) trait Bitmap256IoExt: Sized {
 
 // writes words to bytes, trimming trailing zeros. returns the slice of bytes
 // that contains the non-zero byte prefix.
-fn words_to_min_bytes<'w, 'b>(words: &'w [u64], bytes: &'b mut [u8]) -> &'b [u8] {
+fn words_to_min_bytes<'b>(words: &[u64], bytes: &'b mut [u8]) -> &'b [u8] {
     let mut i = 0;
     let mut last_nonzero_byte_idx = 0;
     for mut w in words.iter().cloned() {
@@ -223,35 +306,85 @@ fn words_to_min_bytes<'w, 'b>(words: &'w [u64], bytes: &'b mut [u8]) -> &'b [u8]
     &bytes[..=last_nonzero_byte_idx]
 }
 
+// A serialized Bitmap256 starts with a tag byte. Tags 0..=32 give the length
+// of a little-endian byte string with trailing zero bytes trimmed (the
+// original encoding). Metadata bitmaps are often nearly empty or nearly full,
+// so there are also compact forms: all-clear, all-set, and a count byte
+// followed by the positions of the set (or, for nearly full bitmaps, the
+// clear) bits. The writer picks whichever is shortest.
+const BITMAP_TAG_MAX_RAW: u8 = 32;
+const BITMAP_TAG_EMPTY: u8 = 0x40;
+const BITMAP_TAG_FULL: u8 = 0x41;
+const BITMAP_TAG_ONES: u8 = 0x42;
+const BITMAP_TAG_ZEROS: u8 = 0x43;
+
 impl Bitmap256IoExt for Bitmap256 {
     fn write_annotated(&self, name: &str, wr: &mut impl Writer) -> Result<()> {
         wr.push_context(name);
+        wr.push_context("bitmap");
         let mut buf: [u8; 32] = [0; 32];
-        let out = words_to_min_bytes(&self.bits, &mut buf);
-        if out.len() > 32 {
-            return Err(err("bitmap too long"));
+        let raw = words_to_min_bytes(&self.bits, &mut buf);
+        let ones = self.count() as usize;
+        let zeros = 256 - ones;
+        if ones == 0 {
+            wr.write_annotated_byte_slice("tag", &[BITMAP_TAG_EMPTY])?;
+        } else if zeros == 0 {
+            wr.write_annotated_byte_slice("tag", &[BITMAP_TAG_FULL])?;
+        } else if ones + 1 < raw.len() && ones <= zeros {
+            wr.write_annotated_byte_slice("tag", &[BITMAP_TAG_ONES, ones as u8])?;
+            let pos = self.iter_ones().collect::<Vec<u8>>();
+            wr.write_annotated_byte_slice("positions", &pos)?;
+        } else if zeros + 1 < raw.len() {
+            wr.write_annotated_byte_slice("tag", &[BITMAP_TAG_ZEROS, zeros as u8])?;
+            let pos = self.iter_zeros().collect::<Vec<u8>>();
+            wr.write_annotated_byte_slice("positions", &pos)?;
+        } else {
+            if raw.len() > BITMAP_TAG_MAX_RAW as usize {
+                return Err(err("bitmap too long"));
+            }
+            wr.write_annotated_byte_slice("len", &[raw.len() as u8])?;
+            wr.write_annotated_byte_slice("bytes", raw)?;
         }
-        let n = out.len() as u8;
-        wr.push_context("bitmap");
-        wr.write_annotated_byte_slice("len", &[n])?;
-        wr.write_annotated_byte_slice("bytes", out)?;
         wr.pop_context();
         wr.pop_context();
         Ok(())
     }
     fn read(rd: &mut impl Reader) -> Result<Self> {
-        let mut bits = [0_u64; 4];
-        let n = rd.read_le_num::<1, u8>()?;
-        if n > 32 {
-            return Err(err("bitmap too long"));
-        }
-        let mut buf = [0_u8; 32];
-        rd.read_exact(&mut buf[..n as usize])?;
-        for (i, chunk) in buf.chunks_exact(8).enumerate() {
-            let word = u64::from_le_bytes(chunk.try_into().unwrap());
-            bits[i] = word;
+        let tag = rd.read_le_num::<1, u8>()?;
+        let read_positions = |rd: &mut _, val: bool| -> Result<Bitmap256> {
+            let n = Reader::read_le_num::<1, u8>(rd)?;
+            let mut bm = Bitmap256::new();
+            if !val {
+                bm.set_all();
+            }
+            let mut buf = [0_u8; 255];
+            Reader::read_full(rd, &mut buf[..n as usize])?;
+            for pos in &buf[..n as usize] {
+                bm.set(*pos, val);
+            }
+            Ok(bm)
+        };
+        match tag {
+            BITMAP_TAG_EMPTY => Ok(Bitmap256::new()),
+            BITMAP_TAG_FULL => {
+                let mut bm = Bitmap256::new();
+                bm.set_all();
+                Ok(bm)
+            }
+            BITMAP_TAG_ONES => read_positions(rd, true),
+            BITMAP_TAG_ZEROS => read_positions(rd, false),
+            n if n <= BITMAP_TAG_MAX_RAW => {
+                let mut bits = [0_u64; 4];
+                let mut buf = [0_u8; 32];
+                rd.read_full(&mut buf[..n as usize])?;
+                for (i, chunk) in buf.chunks_exact(8).enumerate() {
+                    let word = u64::from_le_bytes(chunk.try_into().unwrap());
+                    bits[i] = word;
+                }
+                Ok(Bitmap256 { bits })
+            }
+            _ => Err(Error::corruption("bad bitmap tag")),
         }
-        Ok(Bitmap256 { bits })
     }
 }
 
@@ -280,10 +413,10 @@ impl DoubleBitmap256IoExt for submerge_base::DoubleBitmap256 {
         let mut double_bits = [0_u64; 8];
         let n = rd.read_le_num::<1, u8>()?;
         if n > 64 {
-            return Err(err("bitmap too long"));
+            return Err(Error::corruption("bitmap too long"));
         }
         let mut buf = [0_u8; 64];
-        rd.read_exact(&mut buf[..n as usize])?;
+        rd.read_full(&mut buf[..n as usize])?;
         for (i, chunk) in buf.chunks_exact(8).enumerate() {
             let word = u64::from_le_bytes(chunk.try_into().unwrap());
             double_bits[i] = word;
@@ -296,14 +429,32 @@ impl DoubleBitmap256IoExt for submerge_base::DoubleBitmap256 {
 
 pub struct MemReader {
     mem: Cursor<Arc<[u8]>>,
+    mode: ReadMode,
 }
 
 impl MemReader {
-    fn new(mem: Arc<[u8]>) -> Self {
+    pub(crate) fn new(mem: Arc<[u8]>) -> Self {
         Self {
             mem: Cursor::new(mem),
+            mode: ReadMode::default(),
         }
     }
+
+    // Borrows `range` of the underlying buffer without moving the cursor.
+    pub(crate) fn slice(&self, range: Range<i64>) -> Result<&[u8]> {
+        checked_range_len(&range)?;
+        let mem = self.mem.get_ref();
+        let lo = usize::try_from(range.start)?;
+        let hi = usize::try_from(range.end)?;
+        mem.get(lo..hi)
+            .ok_or_else(|| err("read range out of bounds"))
+    }
+
+    // Returns the shared buffer itself, for consumers that need to hold on
+    // to chunk bytes beyond the lifetime of a borrow of the reader.
+    pub(crate) fn shared_bytes(&self) -> Arc<[u8]> {
+        self.mem.get_ref().clone()
+    }
 }
 
 impl From<Vec<u8>> for MemReader {
@@ -328,7 +479,19 @@ impl Seek for MemReader {
 impl Reader for MemReader {
     fn try_clone_independent(&self) -> Result<Self> {
         let rc = self.mem.get_ref().clone();
-        Ok(Self::new(rc))
+        Ok(Self::new(rc).with_read_mode(self.mode))
+    }
+    fn read_mode(&self) -> ReadMode {
+        self.mode
+    }
+    fn set_read_mode(&mut self, mode: ReadMode) {
+        self.mode = mode;
+    }
+    fn total_len(&mut self) -> Result<i64> {
+        Ok(self.mem.get_ref().len().try_into()?)
+    }
+    fn read_range(&mut self, range: Range<i64>) -> Result<Cow<'_, [u8]>> {
+        Ok(Cow::Borrowed(self.slice(range)?))
     }
 }
 
@@ -373,9 +536,7 @@ impl Writer for MemWriter {
     fn try_into_reader(self) -> Result<Self::PairedReader> {
         let mem = self.mem.into_inner();
         let rc: Arc<[u8]> = Arc::from(mem);
-        Ok(MemReader {
-            mem: Cursor::new(rc),
-        })
+        Ok(MemReader::new(rc))
     }
     fn get_annotations(&mut self) -> &mut Annotations {
         &mut self.annotations
@@ -387,13 +548,15 @@ impl Writer for MemWriter {
 pub struct FileReader {
     file: BufReader<File>,
     path: PathBuf,
+    mode: ReadMode,
 }
 
 impl FileReader {
     fn try_open_existing(path: PathBuf) -> Result<Self> {
         let file = File::open(&path)?;
         let file = BufReader::new(file);
-        Ok(Self { file, path })
+        let mode = ReadMode::default();
+        Ok(Self { file, path, mode })
     }
 }
 impl Read for FileReader {
@@ -410,7 +573,16 @@ impl Seek for FileReader {
 
 impl Reader for FileReader {
     fn try_clone_independent(&self) -> Result<Self> {
-        FileReader::try_open_existing(self.path.clone())
+        Ok(FileReader::try_open_existing(self.path.clone())?.with_read_mode(self.mode))
+    }
+    fn read_mode(&self) -> ReadMode {
+        self.mode
+    }
+    fn set_read_mode(&mut self, mode: ReadMode) {
+        self.mode = mode;
+    }
+    fn total_len(&mut self) -> Result<i64> {
+        Ok(self.file.get_ref().metadata()?.len().try_into()?)
     }
 }
 
@@ -463,7 +635,7 @@ impl Writer for FileWriter {
         let file = file.into_inner()?;
         file.sync_all()?;
         drop(file);
-        Ok(FileReader::try_open_existing(path)?)
+        FileReader::try_open_existing(path)
     }
     fn get_annotations(&mut self) -> &mut Annotations {
         &mut self.annotations
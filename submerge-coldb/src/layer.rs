@@ -1,10 +1,10 @@
 use std::sync::Arc;
 
 use crate::{
-    block::{self, BlockInfoForLayer, BlockReader, BlockWriter},
+    block::{BlockInfoForLayer, BlockReader, BlockWriter},
     ioutil::{Reader, Writer},
 };
-use submerge_base::{err, Result};
+use submerge_base::{err, telemetry, Error, Result};
 
 #[derive(Clone, Default, PartialEq, Eq, Debug, Hash, PartialOrd, Ord)]
 pub(crate) struct LayerMeta {
@@ -17,6 +17,8 @@ pub(crate) struct LayerMeta {
 impl LayerMeta {
     pub const MAGIC: &[u8; 8] = b"submerge";
     pub const VERS: i64 = 0;
+    // A layer spans at most 2^24 rows in blocks of 64k rows.
+    pub const MAX_BLOCKS: i64 = 256;
 
     pub(crate) fn write_magic_header(&self, wr: &mut impl Writer) -> Result<()> {
         wr.rewind()?;
@@ -26,9 +28,9 @@ impl LayerMeta {
     pub(crate) fn read_and_check_magic_header(rd: &mut impl Reader) -> Result<()> {
         rd.rewind()?;
         let mut buf: [u8; 8] = [0; 8];
-        rd.read_exact(&mut buf)?;
+        rd.read_full(&mut buf)?;
         if buf != *Self::MAGIC {
-            return Err(err("bad magic number"));
+            return Err(Error::format("bad magic number"));
         }
         Ok(())
     }
@@ -54,17 +56,26 @@ impl LayerMeta {
     pub(crate) fn read(rd: &mut impl Reader) -> Result<Self> {
         let vers: i64 = rd.read_le_num()?;
         if vers > Self::VERS {
-            return Err(err("unsupported future version number"));
+            return Err(Error::format("unsupported future version number"));
         }
         let rows: i64 = rd.read_le_num()?;
         let cols: i64 = rd.read_le_num()?;
         let blocks: i64 = rd.read_le_num()?;
-        let ublocks = blocks as usize;
-        if ublocks as i64 != blocks {
-            return Err(err("bad block count"));
+        let ublocks = usize::try_from(blocks).map_err(|_| Error::corruption("bad block count"))?;
+        if rd.is_hardened() && blocks > Self::MAX_BLOCKS {
+            return Err(Error::corruption("block count > 256"));
+        }
+        let block_end_offsets: Vec<i64> = rd.read_le_num_vec(ublocks)?;
+        if rd.is_hardened() {
+            let mut prev = 0;
+            for &off in block_end_offsets.iter() {
+                rd.check_offset(off)?;
+                if off < prev {
+                    return Err(Error::corruption("block end offsets out of order"));
+                }
+                prev = off;
+            }
         }
-        let mut block_end_offsets = vec![0_i64; ublocks];
-        rd.read_le_num_slice(&mut block_end_offsets)?;
         Ok(Self {
             vers,
             rows,
@@ -101,6 +112,7 @@ impl LayerWriter {
     }
 
     pub fn finish_layer(self, wr: &mut impl Writer) -> Result<()> {
+        let _span = telemetry::layer_span("finish").entered();
         self.meta.write(wr)?;
         wr.pop_context();
         Ok(())
@@ -113,6 +125,7 @@ pub(crate) struct LayerReader {
 
 impl LayerReader {
     pub fn new(rd: &mut impl Reader) -> Result<Arc<Self>> {
+        let _span = telemetry::layer_span("open").entered();
         LayerMeta::read_and_check_magic_header(rd)?;
         rd.seek(std::io::SeekFrom::End(0))?;
         let end_pos = rd.pos()?;
@@ -121,14 +134,19 @@ impl LayerReader {
         Ok(Arc::new(LayerReader { meta }))
     }
 
+    pub(crate) fn block_count(&self) -> usize {
+        self.meta.block_end_offsets.len()
+    }
+
     pub fn new_block_reader(
         self: &Arc<Self>,
         block_num: usize,
         rd: &mut impl Reader,
     ) -> Result<Arc<BlockReader>> {
+        let _span = telemetry::block_span("open", block_num).entered();
         if let Some(&end_pos) = self.meta.block_end_offsets.get(block_num) {
             if end_pos < 0 {
-                return Err(err("negative block end offset"));
+                return Err(Error::corruption("negative block end offset"));
             }
             BlockReader::new(self, block_num, end_pos, rd)
         } else {
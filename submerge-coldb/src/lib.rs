@@ -71,9 +71,18 @@ mod dict;
 mod heap;
 mod ioutil;
 mod layer;
+mod scan;
+mod stats;
 mod track;
 mod wordty;
 
+pub use scan::{Cmp, Key, Pred, Scan};
+pub use stats::{BlockStats, ColStats, LayerStats, DEFAULT_EQ, DEFAULT_RANGE};
+
+// The most rows a block can hold, so that every row of a track has a
+// two-byte dict code.
+pub const BLOCK_ROWS: usize = 0xffff;
+
 #[cfg(test)]
 mod test;
 
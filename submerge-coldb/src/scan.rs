@@ -0,0 +1,205 @@
+// Scan requests, and the part of them that runs on dict codes.
+//
+// A scan reads some columns of a table, keeping the rows that satisfy a
+// predicate. The predicate is pushed down to here because only here can it
+// run on the sliced dict codes, before any values are reassembled: a track's
+// dictionary is sorted and unique, so a comparison of its values against a
+// key is a comparison of their codes against the key's position in the
+// dictionary, and a chunk whose min and max codes fall outside that range is
+// skipped without reading its lanes. Nothing sliced leaves this module; a
+// scan yields the kept rows as a bitmap per chunk.
+//
+// A null never satisfies a comparison, so a scan drops null rows as a filter
+// does.
+
+use crate::chunk::DictCodeChunkMeta;
+use ordered_float::OrderedFloat;
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Display, Formatter},
+};
+use submerge_base::Bitmap256;
+
+// A constant to compare a column with: an int, flo or bin value, compared
+// the way the column's dictionary is sorted.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Key {
+    Int(i64),
+    Flo(OrderedFloat<f64>),
+    Bin(Vec<u8>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Cmp {
+    // The comparison that holds with its operands swapped: k < c is c > k.
+    pub fn flip(self) -> Cmp {
+        match self {
+            Cmp::Eq => Cmp::Eq,
+            Cmp::Ne => Cmp::Ne,
+            Cmp::Lt => Cmp::Gt,
+            Cmp::Le => Cmp::Ge,
+            Cmp::Gt => Cmp::Lt,
+            Cmp::Ge => Cmp::Le,
+        }
+    }
+}
+
+// A predicate over a table's columns, named by their labels. An empty And is
+// true, and an empty Or is false.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Pred {
+    Cmp(String, Cmp, Key),
+    And(Vec<Pred>),
+    Or(Vec<Pred>),
+}
+
+impl Pred {
+    pub const TRUE: Pred = Pred::And(Vec::new());
+
+    pub fn is_true(&self) -> bool {
+        matches!(self, Pred::And(ps) if ps.is_empty())
+    }
+
+    // The columns the predicate reads.
+    pub fn cols(&self) -> BTreeSet<&str> {
+        match self {
+            Pred::Cmp(col, _, _) => BTreeSet::from([col.as_str()]),
+            Pred::And(ps) | Pred::Or(ps) => ps.iter().flat_map(|p| p.cols()).collect(),
+        }
+    }
+}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::Int(i) => write!(f, "{}", i),
+            Key::Flo(x) => write!(f, "{:?}", x.0),
+            Key::Bin(b) => write!(f, "{:?}", String::from_utf8_lossy(b)),
+        }
+    }
+}
+
+impl Display for Cmp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Cmp::Eq => "==",
+            Cmp::Ne => "!=",
+            Cmp::Lt => "<",
+            Cmp::Le => "<=",
+            Cmp::Gt => ">",
+            Cmp::Ge => ">=",
+        })
+    }
+}
+
+// As the predicate would be written in lang, with an empty And as true and
+// an empty Or as false.
+impl Display for Pred {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let (ps, sep, empty) = match self {
+            Pred::Cmp(col, cmp, k) => return write!(f, "{} {} {}", col, cmp, k),
+            Pred::And(ps) => (ps, " & ", "true"),
+            Pred::Or(ps) => (ps, " | ", "false"),
+        };
+        match ps.as_slice() {
+            [] => f.write_str(empty),
+            [p] => write!(f, "{}", p),
+            _ => {
+                for (i, p) in ps.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(sep)?;
+                    }
+                    match p {
+                        Pred::Cmp(..) => write!(f, "{}", p)?,
+                        _ => write!(f, "({})", p)?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// A request to read a table's rows that satisfy a predicate. The columns are
+// the ones to yield, or all of them if None; the predicate's own columns are
+// read either way.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Scan {
+    pub table: String,
+    pub cols: Option<Vec<String>>,
+    pub pred: Pred,
+}
+
+// The dict codes a comparison keeps: those in lo..hi, or those outside it.
+// Bounds are u32 since a track's dictionary can have 64k entries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Codes {
+    pub(crate) lo: u32,
+    pub(crate) hi: u32,
+    pub(crate) inside: bool,
+}
+
+impl Codes {
+    // The codes of the values in a sorted, unique dictionary that compare
+    // with a key as cmp says.
+    pub(crate) fn new<T: Ord + ?Sized>(dict: &[&T], cmp: Cmp, key: &T) -> Codes {
+        let lower = dict.partition_point(|v| *v < key) as u32;
+        let upper = dict.partition_point(|v| *v <= key) as u32;
+        let n = dict.len() as u32;
+        let (lo, hi, inside) = match cmp {
+            Cmp::Eq => (lower, upper, true),
+            Cmp::Ne => (lower, upper, false),
+            Cmp::Lt => (0, lower, true),
+            Cmp::Le => (0, upper, true),
+            Cmp::Gt => (upper, n, true),
+            Cmp::Ge => (lower, n, true),
+        };
+        Codes { lo, hi, inside }
+    }
+
+    pub(crate) fn keeps(&self, code: u16) -> bool {
+        let code = code as u32;
+        (self.lo <= code && code < self.hi) == self.inside
+    }
+
+    // Whether a chunk with these min and max codes can have any row kept.
+    pub(crate) fn may_match(&self, meta: &DictCodeChunkMeta) -> bool {
+        let (min, max) = (meta.min_dict_code as u32, meta.max_dict_code as u32);
+        if self.inside {
+            min < self.hi && self.lo <= max
+        } else {
+            min < self.lo || self.hi <= max
+        }
+    }
+
+    // The rows of a chunk's code lanes that are kept. One-byte chunks have
+    // no hi lane, so every code is below 256 and the bounds are clamped to
+    // bytes to compare the lo lane directly.
+    pub(crate) fn filter_lanes(&self, hi: Option<&[u8]>, lo: &[u8]) -> Bitmap256 {
+        let mut kept = Bitmap256::new();
+        match hi {
+            None => {
+                let (blo, bhi) = (self.lo.min(256) as u16, self.hi.min(256) as u16);
+                for (i, b) in lo.iter().enumerate() {
+                    let b = *b as u16;
+                    kept.set(i as u8, (blo <= b && b < bhi) == self.inside);
+                }
+            }
+            Some(hi) => {
+                for (i, (h, l)) in hi.iter().zip(lo).enumerate() {
+                    kept.set(i as u8, self.keeps(u16::from_be_bytes([*h, *l])));
+                }
+            }
+        }
+        kept
+    }
+}
@@ -0,0 +1,145 @@
+// Statistics of a layer's blocks, for planning: each block's row count, and
+// each column's min, max and distinct count in it. A track's dictionary is
+// sorted and unique, so its min and max are its first and last entries, and
+// its length is the distinct count; elsewhere the distinct count can be an
+// estimate, as an HLL sketch gives it.
+//
+// A scan's predicate can rule a block out by its min and max alone, without
+// reading any chunk, and estimate the fraction of the rest it keeps. A
+// comparison is taken to keep 1/distinct of the rows for each equal value,
+// and rows evenly spread between min and max for a range; ints and flos are
+// placed there by value and bins by their first 8 bytes. A column without
+// stats keeps DEFAULT_EQ of the rows on an equality and DEFAULT_RANGE on
+// anything else, and a conjunction keeps the product of its parts, as if
+// they were independent.
+
+use crate::{Cmp, Key, Pred};
+use std::collections::BTreeMap;
+
+pub const DEFAULT_EQ: f64 = 0.1;
+pub const DEFAULT_RANGE: f64 = 1.0 / 3.0;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ColStats {
+    pub min: Key,
+    pub max: Key,
+    pub distinct: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockStats {
+    pub rows: u64,
+    pub cols: BTreeMap<String, ColStats>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LayerStats {
+    pub blocks: Vec<BlockStats>,
+}
+
+impl LayerStats {
+    pub fn rows(&self) -> u64 {
+        self.blocks.iter().map(|b| b.rows).sum()
+    }
+}
+
+// Where a key falls on a line with the values of its kind.
+fn place(k: &Key) -> f64 {
+    match k {
+        Key::Int(i) => *i as f64,
+        Key::Flo(f) => f.0,
+        Key::Bin(b) => {
+            let mut word = [0; 8];
+            let n = b.len().min(8);
+            word[..n].copy_from_slice(&b[..n]);
+            u64::from_be_bytes(word) as f64
+        }
+    }
+}
+
+fn same_kind(a: &Key, b: &Key) -> bool {
+    std::mem::discriminant(a) == std::mem::discriminant(b)
+}
+
+impl ColStats {
+    // Whether any value between min and max can compare with a key so.
+    pub fn may_match(&self, cmp: Cmp, k: &Key) -> bool {
+        if !same_kind(k, &self.min) || !same_kind(k, &self.max) {
+            return true;
+        }
+        let (min, max) = (&self.min, &self.max);
+        match cmp {
+            Cmp::Eq => min <= k && k <= max,
+            Cmp::Ne => !(min == k && k == max),
+            Cmp::Lt => min < k,
+            Cmp::Le => min <= k,
+            Cmp::Gt => k < max,
+            Cmp::Ge => k <= max,
+        }
+    }
+
+    // The fraction of rows estimated to compare with a key so.
+    pub fn selectivity(&self, cmp: Cmp, k: &Key) -> f64 {
+        if !self.may_match(cmp, k) {
+            return 0.0;
+        }
+        let one = 1.0 / self.distinct.max(1) as f64;
+        if !same_kind(k, &self.min) || !same_kind(k, &self.max) {
+            return default_selectivity(cmp);
+        }
+        let (lo, hi, at) = (place(&self.min), place(&self.max), place(k));
+        let below = match hi - lo {
+            span if span > 0.0 => ((at - lo) / span).clamp(0.0, 1.0),
+            _ => 0.0,
+        };
+        let frac = match cmp {
+            Cmp::Eq => one,
+            Cmp::Ne => 1.0 - one,
+            Cmp::Lt => below,
+            Cmp::Le => below + one,
+            Cmp::Gt => 1.0 - below - one,
+            Cmp::Ge => 1.0 - below,
+        };
+        frac.clamp(one, 1.0)
+    }
+}
+
+fn default_selectivity(cmp: Cmp) -> f64 {
+    match cmp {
+        Cmp::Eq => DEFAULT_EQ,
+        Cmp::Ne => 1.0 - DEFAULT_EQ,
+        _ => DEFAULT_RANGE,
+    }
+}
+
+impl Pred {
+    // Whether a block's stats leave room for any row to satisfy the
+    // predicate, so that the block has to be scanned.
+    pub fn may_match(&self, block: &BlockStats) -> bool {
+        match self {
+            Pred::Cmp(col, cmp, k) => match block.cols.get(col) {
+                Some(s) => s.may_match(*cmp, k),
+                None => true,
+            },
+            Pred::And(ps) => ps.iter().all(|p| p.may_match(block)),
+            Pred::Or(ps) => ps.iter().any(|p| p.may_match(block)),
+        }
+    }
+
+    // The fraction of a block's rows estimated to satisfy the predicate.
+    pub fn selectivity(&self, block: &BlockStats) -> f64 {
+        match self {
+            Pred::Cmp(col, cmp, k) => match block.cols.get(col) {
+                Some(s) => s.selectivity(*cmp, k),
+                None => default_selectivity(*cmp),
+            },
+            Pred::And(ps) => ps.iter().map(|p| p.selectivity(block)).product(),
+            Pred::Or(ps) => {
+                1.0 - ps
+                    .iter()
+                    .map(|p| 1.0 - p.selectivity(block))
+                    .product::<f64>()
+            }
+        }
+    }
+}
@@ -1,8 +1,18 @@
 use crate::{
-    ioutil::MemWriter, layer::LayerWriter, neg_virt_base_and_factor, pos_virt_base_and_factor,
-    wordty::WordTy,
+    chunk::DictCodeChunkMeta,
+    ioutil::{Bitmap256IoExt, MemReader, MemWriter, ReadMode, Reader, Writer},
+    layer::{LayerReader, LayerWriter},
+    neg_virt_base_and_factor, pos_virt_base_and_factor,
+    scan::Codes,
+    track::dict_encode,
+    wordty::{WordTy, WordTy256},
+    BlockStats, Cmp, ColStats, Key, Pred,
 };
-use submerge_base::Result;
+use std::{
+    borrow::Cow,
+    io::{Read, Seek},
+};
+use submerge_base::{Bitmap256, ErrorKind, Result};
 use test_log::test;
 
 pub(crate) mod annotations;
@@ -33,6 +43,20 @@ fn test_wordty() {
     );
 }
 
+#[test]
+fn test_wordty256_len_sum() {
+    let mut tys = WordTy256::default();
+    let all = [WordTy::Word1, WordTy::Word2, WordTy::Word4, WordTy::Word8];
+    for i in 0..=255_u8 {
+        tys.set_word_ty(i, all[(i as usize * 5) % 4]);
+    }
+    let mut naive = 0;
+    for i in 0..=255_u8 {
+        naive += tys.get_word_ty(i).len();
+        assert_eq!(tys.len_sum_through(i), naive);
+    }
+}
+
 #[test]
 fn test_annotations() -> Result<()> {
     let mut w = MemWriter::new();
@@ -64,3 +88,328 @@ fn test_annotations() -> Result<()> {
     eprintln!("dump:\n{}", w.render_annotations()?);
     Ok(())
 }
+
+#[test]
+fn test_mem_reader_read_range() -> Result<()> {
+    let mut w = MemWriter::new();
+    LayerWriter::new(&mut w)?
+        .begin_block(&mut w)?
+        .begin_track(&mut w)?
+        .write_dict_encoded(&[1_i64, 2, 3, 2, 1], &mut w)?
+        .finish_track(&mut w)?
+        .finish_block(&mut w)?
+        .finish_layer(&mut w)?;
+    let mut rd = w.try_into_reader()?;
+
+    let mut copied = Vec::new();
+    rd.rewind()?;
+    rd.read_to_end(&mut copied)?;
+    let len = copied.len() as i64;
+
+    assert_eq!(rd.slice(0..8)?, b"submerge");
+    assert_eq!(rd.slice(0..len)?, copied.as_slice());
+    assert_eq!(rd.shared_bytes().as_ref(), copied.as_slice());
+    match rd.read_range(4..len)? {
+        Cow::Borrowed(bytes) => assert_eq!(bytes, &copied[4..]),
+        Cow::Owned(_) => panic!("MemReader copied a range it could borrow"),
+    }
+
+    assert!(rd.slice(0..len + 1).is_err());
+    assert!(rd.slice(-1..4).is_err());
+    #[allow(clippy::reversed_empty_ranges)]
+    let backwards = 8..4;
+    assert!(rd.read_range(backwards).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_bitmap256_compact_encoding() -> Result<()> {
+    let mut full = Bitmap256::new();
+    full.set_all();
+    let sparse: Bitmap256 = [3_u8, 200, 255].iter().fold(Bitmap256::new(), |mut bm, i| {
+        bm.set(*i, true);
+        bm
+    });
+    let nearly_full = !&sparse;
+    let mut dense = Bitmap256::new();
+    for i in (0..=255_u8).step_by(2) {
+        dense.set(i, true);
+    }
+    let mut low = Bitmap256::new();
+    for i in 0..12 {
+        low.set(i, true);
+    }
+    let cases = [
+        (Bitmap256::new(), 1),
+        (full, 1),
+        (sparse, 5),
+        (nearly_full, 5),
+        (dense, 33),
+        (low, 3),
+    ];
+    for (bm, size) in cases {
+        let mut w = MemWriter::new();
+        bm.write_annotated("bm", &mut w)?;
+        let mut rd = w.try_into_reader()?;
+        assert_eq!(rd.total_len()?, size);
+        rd.rewind()?;
+        assert_eq!(Bitmap256::read(&mut rd)?, bm);
+    }
+
+    // The original trimmed-bytes form still reads, and unknown tags don't.
+    let mut rd = MemReader::new(vec![2, 0xff, 0x01].into());
+    let bm = Bitmap256::read(&mut rd)?;
+    assert_eq!(bm.count(), 9);
+    let mut rd = MemReader::new(vec![0x50].into());
+    assert_eq!(
+        Bitmap256::read(&mut rd).unwrap_err().kind(),
+        ErrorKind::Corruption
+    );
+    Ok(())
+}
+
+fn write_small_layer() -> Result<Vec<u8>> {
+    let mut w = MemWriter::new();
+    LayerWriter::new(&mut w)?
+        .begin_block(&mut w)?
+        .begin_track(&mut w)?
+        .write_dict_encoded(&[7_i64, 7, 7, 9, 9, 1000, 3], &mut w)?
+        .finish_track(&mut w)?
+        .begin_track(&mut w)?
+        .write_dict_encoded(&["short".as_bytes(), "much longer bin".as_bytes()], &mut w)?
+        .finish_track(&mut w)?
+        .finish_block(&mut w)?
+        .begin_block(&mut w)?
+        .begin_track(&mut w)?
+        .write_dict_encoded(&[1_i64, 2, 3], &mut w)?
+        .finish_track(&mut w)?
+        .finish_block(&mut w)?
+        .finish_layer(&mut w)?;
+    let mut rd = w.try_into_reader()?;
+    let mut buf = Vec::new();
+    rd.rewind()?;
+    rd.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+// Opens every block and track reader in the layer, returning the number of
+// tracks seen.
+fn read_whole_layer(rd: &mut MemReader) -> Result<usize> {
+    let layer = LayerReader::new(rd)?;
+    let mut tracks = 0;
+    for b in 0..layer.block_count() {
+        let block = layer.new_block_reader(b, rd)?;
+        for t in 0..block.track_count() {
+            block.new_track_reader(t, rd)?;
+            tracks += 1;
+        }
+    }
+    Ok(tracks)
+}
+
+#[test]
+fn test_hardened_read_of_valid_layer() -> Result<()> {
+    let buf = write_small_layer()?;
+    let mut rd = MemReader::from(buf).with_read_mode(ReadMode::Hardened);
+    assert_eq!(read_whole_layer(&mut rd)?, 3);
+    Ok(())
+}
+
+#[test]
+fn test_track_map_bounds() -> Result<()> {
+    // A dict with more entries than its track has rows, or with chunks that
+    // run past the track's data, is corruption.
+    let mut w = MemWriter::new();
+    LayerWriter::new(&mut w)?
+        .begin_block(&mut w)?
+        .begin_track(&mut w)?
+        .write_dict_encoded(&[1_i64, 2, 3], &mut w)?
+        .finish_track(&mut w)?
+        .finish_block(&mut w)?
+        .finish_layer(&mut w)?;
+    let field = |w: &mut MemWriter, name: &str| {
+        let found = w
+            .get_annotations()
+            .annotations
+            .iter()
+            .find(|(_, ctx)| ctx.last().map(String::as_str) == Some(name));
+        let (range, _) = found.expect("annotated field");
+        range.start as usize..range.end as usize
+    };
+    let count = field(&mut w, "dict_entry_count");
+    let rows = field(&mut w, "track_rows");
+    let mut rd = w.try_into_reader()?;
+    let mut valid = Vec::new();
+    rd.rewind()?;
+    rd.read_to_end(&mut valid)?;
+    let read = |buf: Vec<u8>| {
+        let mut rd = MemReader::from(buf).with_read_mode(ReadMode::Hardened);
+        read_whole_layer(&mut rd)
+    };
+    assert_eq!(read(valid.clone())?, 1);
+
+    let mut buf = valid.clone();
+    buf[count.clone()].copy_from_slice(&4_u16.to_le_bytes());
+    assert!(read(buf).unwrap_err().is_corruption());
+
+    let mut buf = valid;
+    buf[count].copy_from_slice(&200_u16.to_le_bytes());
+    buf[rows].copy_from_slice(&200_u16.to_le_bytes());
+    assert!(read(buf).unwrap_err().is_corruption());
+    Ok(())
+}
+
+#[test]
+fn test_hardened_read_fuzz() -> Result<()> {
+    // Feed mutated and random buffers to a hardened reader. Any of them may
+    // fail to read, but none may panic or attempt a giant allocation.
+    let valid = write_small_layer()?;
+    let mut state = 1234_u32;
+    fn lcg_rand_step(state: &mut u32) -> u32 {
+        *state = (*state as u64 * 279470273u64 % 0xfffffffb) as u32;
+        *state
+    }
+    for i in 0..4096 {
+        let mut buf = valid.clone();
+        match i % 4 {
+            0 => {
+                // Flip a few random bytes.
+                for _ in 0..=(lcg_rand_step(&mut state) % 4) {
+                    let pos = lcg_rand_step(&mut state) as usize % buf.len();
+                    buf[pos] = lcg_rand_step(&mut state) as u8;
+                }
+            }
+            1 => {
+                // Overwrite a random 8-byte window with an extreme number.
+                let pos = lcg_rand_step(&mut state) as usize % (buf.len() - 8);
+                let extreme = [i64::MAX, i64::MIN, -1, 0x7fff_ffff][(i / 4) % 4];
+                buf[pos..pos + 8].copy_from_slice(&extreme.to_le_bytes());
+            }
+            2 => {
+                // Truncate, keeping the magic header.
+                let len = 8 + lcg_rand_step(&mut state) as usize % (buf.len() - 8);
+                buf.truncate(len);
+            }
+            _ => {
+                // Pure noise behind a valid magic header.
+                let len = lcg_rand_step(&mut state) as usize % 512;
+                buf.truncate(8);
+                buf.extend((0..len).map(|_| lcg_rand_step(&mut state) as u8));
+            }
+        }
+        let mut rd = MemReader::from(buf).with_read_mode(ReadMode::Hardened);
+        if let Err(e) = read_whole_layer(&mut rd) {
+            // Damage shows up as corruption, truncation included, or a bad
+            // header.
+            assert!(e.is_corruption() || e.is_format(), "{:?}", e);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_scan_codes() -> Result<()> {
+    let vals = [30_i64, 10, 40, 20, 10, 30];
+    let (dict, codes) = dict_encode(&vals)?;
+    let kept = |cmp, key: i64| {
+        let c = Codes::new(&dict, cmp, &key);
+        codes.iter().map(|x| c.keeps(*x)).collect::<Vec<_>>()
+    };
+    let direct = |cmp, key: i64| {
+        let f = |v: &i64| match cmp {
+            Cmp::Eq => *v == key,
+            Cmp::Ne => *v != key,
+            Cmp::Lt => *v < key,
+            Cmp::Le => *v <= key,
+            Cmp::Gt => *v > key,
+            Cmp::Ge => *v >= key,
+        };
+        vals.iter().map(f).collect::<Vec<_>>()
+    };
+    for cmp in [Cmp::Eq, Cmp::Ne, Cmp::Lt, Cmp::Le, Cmp::Gt, Cmp::Ge] {
+        for key in [0, 10, 25, 30, 50] {
+            assert_eq!(kept(cmp, key), direct(cmp, key), "{:?} {}", cmp, key);
+        }
+    }
+
+    // Chunks whose codes are all outside the range are pruned.
+    let meta = |min, max| DictCodeChunkMeta {
+        min_dict_code: min,
+        max_dict_code: max,
+        ..Default::default()
+    };
+    let ge30 = Codes::new(&dict, Cmp::Ge, &30);
+    assert!(!ge30.may_match(&meta(0, 1)));
+    assert!(ge30.may_match(&meta(1, 2)));
+    let ne10 = Codes::new(&dict, Cmp::Ne, &10);
+    assert!(!ne10.may_match(&meta(0, 0)));
+    assert!(ne10.may_match(&meta(0, 1)));
+
+    // One- and two-byte lanes give the same rows.
+    let wide = [0x0102_u16, 0x0005, 0x0101, 0x00ff];
+    let hi = wide.map(|c| (c >> 8) as u8);
+    let lo = wide.map(|c| c as u8);
+    let c = Codes {
+        lo: 0x00ff,
+        hi: 0x0102,
+        inside: true,
+    };
+    let rows = c
+        .filter_lanes(Some(&hi), &lo)
+        .iter_ones()
+        .collect::<Vec<_>>();
+    assert_eq!(rows, vec![2, 3]);
+    let c = Codes {
+        lo: 5,
+        hi: 0x0102,
+        inside: false,
+    };
+    let rows = c.filter_lanes(None, &[4, 5, 200, 255]);
+    assert_eq!(rows.iter_ones().collect::<Vec<_>>(), vec![0]);
+
+    let p = Pred::And(vec![
+        Pred::Cmp("x".into(), Cmp::Lt, Key::Int(3)),
+        Pred::Or(vec![Pred::Cmp("y".into(), Cmp::Eq, Key::Int(1))]),
+    ]);
+    assert_eq!(p.cols().into_iter().collect::<Vec<_>>(), vec!["x", "y"]);
+    assert!(Pred::TRUE.is_true() && !p.is_true());
+    Ok(())
+}
+
+#[test]
+fn test_block_stats() {
+    let col = |min, max, distinct| ColStats {
+        min: Key::Int(min),
+        max: Key::Int(max),
+        distinct,
+    };
+    let block = BlockStats {
+        rows: 100,
+        cols: [("x".to_string(), col(0, 100, 50))].into(),
+    };
+    let x = |cmp, k| Pred::Cmp("x".into(), cmp, Key::Int(k));
+
+    // Min and max rule blocks out, but only for keys of their own kind.
+    assert!(x(Cmp::Lt, 1).may_match(&block));
+    assert!(!x(Cmp::Lt, 0).may_match(&block));
+    assert!(!x(Cmp::Gt, 100).may_match(&block));
+    assert!(!x(Cmp::Eq, 101).may_match(&block));
+    let flo = Pred::Cmp("x".into(), Cmp::Eq, Key::Flo(1000.0.into()));
+    assert!(flo.may_match(&block));
+    assert!(Pred::Or(vec![x(Cmp::Lt, 0), x(Cmp::Ge, 100)]).may_match(&block));
+    assert!(!Pred::And(vec![x(Cmp::Lt, 50), x(Cmp::Gt, 100)]).may_match(&block));
+
+    let sel = |p: Pred| p.selectivity(&block);
+    assert_eq!(sel(x(Cmp::Eq, 10)), 0.02);
+    assert_eq!(sel(x(Cmp::Lt, 25)), 0.25);
+    assert_eq!(sel(x(Cmp::Ge, 25)), 0.75);
+    assert_eq!(sel(x(Cmp::Gt, 100)), 0.0);
+    // Never less than one value's worth, if any row can be kept.
+    assert_eq!(sel(x(Cmp::Ge, 100)), 0.02);
+    assert_eq!(sel(Pred::And(vec![x(Cmp::Lt, 50), x(Cmp::Ge, 50)])), 0.25);
+    assert_eq!(sel(Pred::Or(vec![x(Cmp::Lt, 50), x(Cmp::Ge, 50)])), 0.75);
+    let y = Pred::Cmp("y".into(), Cmp::Eq, Key::Int(1));
+    assert_eq!(sel(y), crate::DEFAULT_EQ);
+    assert_eq!(sel(Pred::TRUE), 1.0);
+    assert_eq!(sel(Pred::Or(vec![])), 0.0);
+}
@@ -101,7 +101,7 @@ impl Annotations {
                         s.push('.');
                     }
                 }
-                writeln!(s, "")?;
+                writeln!(s)?;
             }
             if repeated > DISPLAYED_REPEAT_LIMIT_BEFORE_SUPPRESS {
                 writeln!(
@@ -7,8 +7,9 @@ use crate::{
     heap::Heap,
     ioutil::{Bitmap256IoExt, Reader, Writer},
     wordty::WordTy256,
+    BLOCK_ROWS,
 };
-use submerge_base::{err, Bitmap256, Result};
+use submerge_base::{err, telemetry, Bitmap256, Error, Result};
 
 // TrackMeta is nonempty only when track encoding is not Virt
 #[derive(Clone, Default, PartialEq, Eq, Debug, Hash, PartialOrd, Ord)]
@@ -55,34 +56,45 @@ struct TrackMap {
 }
 
 impl TrackMap {
-    fn new(meta: &TrackMeta, is_bin: bool) -> Self {
-        let mut off = 0;
-        let mut dict_chunk_offsets = Vec::new();
-        let mut dict_entry_count = meta.dict_entry_count;
-        for i in 0..=255 {
-            let n_chunk_entries = dict_entry_count.min(256) as i64;
-            dict_chunk_offsets.push(off);
-            let mut chunk_len = 0;
-            chunk_len += n_chunk_entries * (meta.dict_val_chunk_tys.get_word_ty(i).len() as i64);
+    // The offsets are checked against what the file says the track has: a
+    // dict holds at most one entry per row, and its chunks and the code chunks
+    // end within the track's `len` bytes of data.
+    fn new(meta: &TrackMeta, is_bin: bool, rows: u16, len: i64) -> Result<Self> {
+        if meta.dict_entry_count > rows {
+            return Err(Error::corruption(
+                "dict has more entries than the track has rows",
+            ));
+        }
+        // Every dict chunk but the last holds 256 entries, so a chunk's offset
+        // is 256 times the bytes per entry of the chunks before it, which the
+        // word-type maps sum a word at a time.
+        let entry_lens_through = |i: u8| -> i64 {
+            let mut n = meta.dict_val_chunk_tys.len_sum_through(i);
             if is_bin {
-                chunk_len +=
-                    n_chunk_entries * (meta.dict_bin_len_chunk_tys.get_word_ty(i).len() as i64);
-                if meta.dict_bin_large.get(i) {
-                    chunk_len +=
-                        n_chunk_entries * (meta.dict_bin_off_tys.get_word_ty(i).len() as i64);
-                }
-            }
-            off += chunk_len;
-            if n_chunk_entries < 256 {
-                break;
+                n += meta.dict_bin_len_chunk_tys.len_sum_through(i);
+                let large = meta.dict_bin_large.iter_ones().take_while(|&j| j <= i);
+                n += large
+                    .map(|j| meta.dict_bin_off_tys.get_word_ty(j).len())
+                    .sum::<usize>();
             }
-            dict_entry_count -= 256;
-        }
+            n as i64
+        };
+        let entry_lens_before = |i: u8| match i {
+            0 => 0,
+            i => entry_lens_through(i - 1),
+        };
+        let full_chunks = (meta.dict_entry_count / 256) as u8;
+        let rest = i64::from(meta.dict_entry_count % 256);
+        let dict_chunk_offsets = (0..=full_chunks)
+            .map(|i| 256 * entry_lens_before(i))
+            .collect::<Vec<_>>();
+        let last_lens = entry_lens_through(full_chunks) - entry_lens_before(full_chunks);
+        let mut off = 256 * entry_lens_before(full_chunks) + rest * last_lens;
 
         let mut code_chunk_offsets = Vec::new();
         let mut total_codes = meta.code_chunk_populated.count() as i64;
         for i in 0..=255 {
-            let n_chunk_codes = total_codes.min(256) as i64;
+            let n_chunk_codes = total_codes.min(256);
             if !meta.code_chunk_populated.get(i) {
                 code_chunk_offsets.push(None);
                 continue;
@@ -102,11 +114,14 @@ impl TrackMap {
             total_codes -= 256;
         }
 
-        TrackMap {
+        if off > len {
+            return Err(Error::corruption("track chunks run past the track"));
+        }
+        Ok(TrackMap {
             dict_chunk_offsets,
             code_chunk_offsets,
             heap_offset: off,
-        }
+        })
     }
 }
 
@@ -146,11 +161,17 @@ impl TrackMeta {
         Ok(())
     }
 
-    pub(crate) fn read_from_footer_end(rd: &mut impl Reader, end_pos: i64) -> Result<Self> {
+    // The meta, and the position its footer starts at, which is where the
+    // track's data ends.
+    pub(crate) fn read_from_footer_end(rd: &mut impl Reader, end_pos: i64) -> Result<(Self, i64)> {
         rd.read_footer_len_ending_at_pos_and_rewind_to_start(end_pos)?;
-        let mut meta = TrackMeta::default();
-        meta.code_chunk_populated = Bitmap256::read(rd)?;
+        let footer_pos = rd.pos()?;
+        let mut meta = TrackMeta {
+            code_chunk_populated: Bitmap256::read(rd)?,
+            ..Default::default()
+        };
 
+        meta.dict_entry_count = rd.read_le_num()?;
         meta.dict_val_chunk_tys = WordTy256::read(rd)?;
         meta.dict_bin_len_chunk_tys = WordTy256::read(rd)?;
         meta.dict_bin_large = Bitmap256::read(rd)?;
@@ -164,7 +185,7 @@ impl TrackMeta {
         let n_code_chunks = meta.code_chunk_populated.count() as usize;
         meta.code_chunk_mins = rd.read_le_num_vec(n_code_chunks)?;
         meta.code_chunk_maxs = rd.read_le_num_vec(n_code_chunks)?;
-        Ok(meta)
+        Ok((meta, footer_pos))
     }
 }
 
@@ -175,7 +196,7 @@ pub(crate) struct TrackWriter {
 }
 
 pub(crate) fn dict_encode<T: Ord + Eq>(vals: &[T]) -> Result<(Vec<&T>, Vec<u16>)> {
-    if vals.len() > 0xffff {
+    if vals.len() > BLOCK_ROWS {
         return Err(err("track longer than 64k rows"));
     }
     let mut dict = vals
@@ -232,7 +253,7 @@ impl TrackWriter {
             return Err(err("code chunk num > 255"));
         }
         let chunk_num = chunk_num as u8;
-        self.meta.dict_entry_count += 256;
+        self.meta.dict_entry_count += meta.entries;
         if let Some(ty) = &meta.val_ty {
             self.meta.dict_val_chunk_tys.set_word_ty(chunk_num, *ty);
         }
@@ -274,12 +295,13 @@ impl TrackWriter {
         vals: &[T],
         wr: &mut impl Writer,
     ) -> Result<Self> {
-        if vals.len() > 0xffff {
+        let _span = self.span("write_dict_encoded").entered();
+        if vals.len() > BLOCK_ROWS {
             return Err(err("track longer than 64k rows"));
         }
         self.info.rows = vals.len() as u16;
         self.info.implicit = false;
-        if vals.len() == 0 {
+        if vals.is_empty() {
             return Ok(self);
         }
 
@@ -313,7 +335,7 @@ impl TrackWriter {
         }
         wr.pop_context(); // dict_code_chunks
 
-        if heap.data.len() > 0 {
+        if !heap.data.is_empty() {
             wr.push_context("heap");
             wr.write_annotated_le_num("len", heap.data.len())?;
             wr.write_annotated_byte_slice("data", &heap.data)?;
@@ -323,7 +345,13 @@ impl TrackWriter {
         Ok(self)
     }
 
+    fn span(&self, op: &'static str) -> tracing::Span {
+        let track = self.info.track_num as usize;
+        telemetry::track_span(op, self.block_writer.block_num(), track)
+    }
+
     pub(crate) fn finish_track(mut self, wr: &mut impl Writer) -> Result<BlockWriter> {
+        let _span = self.span("finish").entered();
         self.meta.write(wr)?;
         self.info.end_pos = wr.pos()?;
         wr.pop_context();
@@ -344,17 +372,22 @@ impl TrackReader {
     pub(crate) fn new(
         block_reader: &Arc<BlockReader>,
         track_num: usize,
+        start_pos: i64,
         end_pos: i64,
+        rows: u16,
         rd: &mut impl Reader,
     ) -> Result<Arc<Self>> {
         let block_reader = block_reader.clone();
         if track_num > 255 {
             return Err(err("track count > 255"));
         }
-        let meta = TrackMeta::read_from_footer_end(rd, end_pos)?;
+        let (meta, footer_pos) = TrackMeta::read_from_footer_end(rd, end_pos)?;
+        if footer_pos < start_pos {
+            return Err(Error::corruption("track meta starts before the track"));
+        }
         // FIXME: fetch bin-ness from column catalogue in block meta?
         let is_bin = false;
-        let map = TrackMap::new(&meta, is_bin);
+        let map = TrackMap::new(&meta, is_bin, rows, footer_pos - start_pos)?;
         Ok(Arc::new(TrackReader {
             block_reader,
             track_num,
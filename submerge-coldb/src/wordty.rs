@@ -67,6 +67,15 @@ impl WordTy256 {
         };
         self.bitmaps.set(i, val);
     }
+    // Sum of word lengths for chunks 0..=i, computed a word at a time; for a
+    // run of full chunks this is the byte offset just past chunk i.
+    pub(crate) fn len_sum_through(&self, i: u8) -> usize {
+        [WordTy::Word1, WordTy::Word2, WordTy::Word4, WordTy::Word8]
+            .iter()
+            .enumerate()
+            .map(|(code, ty)| self.bitmaps.rank_eq(code as u8, i) * ty.len())
+            .sum()
+    }
     pub(crate) fn write_annotated(&self, name: &str, wr: &mut impl Writer) -> Result<()> {
         self.bitmaps.write_annotated("word_tys", wr)
     }
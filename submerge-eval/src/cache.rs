@@ -0,0 +1,163 @@
+// A cache of what Exprs evaluate to, for queries that are asked again and
+// again with small changes, like a dashboard's. Every Expr is pure, so its
+// outcome depends only on the layers of the tables it reads, which never
+// change once written; an entry stays good until the set of layers visible
+// in one of those tables does, and set_layers() drops it then.
+//
+// Entries are keyed by the Expr's content hash, and hold its canonical
+// encoding to tell a collision from a hit. Evaluating an Expr that isn't in
+// the cache first evaluates, through the cache, each of its operands that's
+// closed (that is, doesn't read a let's or lambda's variable or a filtered
+// row's column) and isn't just a literal or path, and puts the outcomes in
+// as literals. So a query that shares a filter or group with one before it
+// only evaluates what's new. A fault is cached like a value, but since
+// operands that were cached don't run, its pc is of the opcodes that did:
+// the cache is for queries, whose faults no other node has to agree with.
+//
+// The cache holds at most its capacity in entries, dropping the one used
+// longest ago to make room.
+
+use crate::{reads, Outcome};
+use std::collections::BTreeMap;
+use submerge_base::Result;
+use submerge_lang::{Expr, Path, Tab, Vm};
+
+struct Entry {
+    canon: Vec<u8>,
+    reads: Vec<Path>,
+    outcome: Outcome,
+    used: u64,
+}
+
+pub struct Cache {
+    entries: BTreeMap<u64, Entry>,
+    // The visible layers of each table, by id.
+    layers: BTreeMap<String, Vec<u64>>,
+    capacity: usize,
+    tick: u64,
+    hits: usize,
+    misses: usize,
+}
+
+// Whether an entry that reads a path depends on a table's layers.
+fn depends(path: &Path, table: &str) -> bool {
+    match path.0.first() {
+        Some(w) => w.to_string() == table,
+        None => true,
+    }
+}
+
+// The operands of an Expr that can be evaluated before it, on their own.
+fn closed(e: &Expr) -> Vec<&Expr> {
+    match e {
+        Expr::Let(_, val, _) => vec![val],
+        Expr::Filter(x, _) => vec![x],
+        Expr::Lam(..) | Expr::If(..) | Expr::Quote(_) | Expr::Splice(_) => vec![],
+        _ => e.children(),
+    }
+}
+
+fn worth_caching(e: &Expr) -> bool {
+    !matches!(
+        e,
+        Expr::Pass | Expr::Lit(_) | Expr::Path(_) | Expr::Reify | Expr::Lam(..)
+    )
+}
+
+impl Cache {
+    pub fn new(capacity: usize) -> Cache {
+        Cache {
+            entries: BTreeMap::new(),
+            layers: BTreeMap::new(),
+            capacity,
+            tick: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    // Note the layers now visible in a table, dropping the entries that
+    // read it if they've changed.
+    pub fn set_layers(&mut self, table: &str, layers: &[u64]) {
+        if self.layers.get(table).map(Vec::as_slice) == Some(layers) {
+            return;
+        }
+        self.layers.insert(table.to_string(), layers.to_vec());
+        self.entries
+            .retain(|_, e| !e.reads.iter().any(|p| depends(p, table)));
+    }
+
+    // The outcome of an Expr over the context's tables, which must be the
+    // layers last passed to set_layers(). Traps are errors, and not cached.
+    pub fn eval(&mut self, e: &Expr, ctx: &[Tab]) -> Result<Outcome> {
+        let canon = e.canonical();
+        let hash = e.content_hash();
+        self.tick += 1;
+        if let Some(entry) = self.entries.get_mut(&hash).filter(|x| x.canon == canon) {
+            entry.used = self.tick;
+            self.hits += 1;
+            return Ok(entry.outcome.clone());
+        }
+        self.misses += 1;
+        let ops = closed(e);
+        let mut res = Ok(Ok(()));
+        let inlined = e.map_children(|c| {
+            let cached = ops.iter().any(|o| std::ptr::eq(*o, c)) && worth_caching(c);
+            if !cached || !matches!(res, Ok(Ok(()))) {
+                return c.clone();
+            }
+            match self.eval(c, ctx) {
+                Ok(Ok(v)) => Expr::Lit(v),
+                Ok(Err(f)) => {
+                    res = Ok(Err(f));
+                    c.clone()
+                }
+                Err(e) => {
+                    res = Err(e);
+                    c.clone()
+                }
+            }
+        });
+        let outcome = match res? {
+            Ok(()) => Vm::new(inlined.ops()?, ctx.to_vec()).eval()?,
+            Err(f) => Err(f),
+        };
+        self.insert(hash, canon, reads(e), outcome.clone());
+        Ok(outcome)
+    }
+
+    fn insert(&mut self, hash: u64, canon: Vec<u8>, reads: Vec<Path>, outcome: Outcome) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&hash) {
+            let oldest = self.entries.iter().min_by_key(|(_, e)| e.used);
+            if let Some(k) = oldest.map(|(k, _)| *k) {
+                self.entries.remove(&k);
+            }
+        }
+        let entry = Entry {
+            canon,
+            reads,
+            outcome,
+            used: self.tick,
+        };
+        self.entries.insert(hash, entry);
+    }
+}
@@ -0,0 +1,61 @@
+// Checkpoints. Everything an Evaluator holds is plain data: the Vm's opcodes,
+// frames, registers, operand stack and pc; the stage it's up to; the results
+// so far; and the counts its limits are checked against. So a long-running
+// evaluation can be saved between two run() calls and resumed, in this
+// process or a later one, exactly where it stopped, instead of running again
+// from the start. The resumed Evaluator is equal to the saved one, and runs
+// the same opcodes to the same outcome and fault pcs, so a node that resumes
+// still agrees with every other.
+//
+// A checkpoint is a version byte and the Evaluator in MessagePack. It holds
+// the Vm's context tables whole, since the Vm reads them as it goes, but not
+// its user-defined functions, which are code: with_udfs() gives them back. Bytes
+// of another version are a Format error, and bytes that don't decode, or
+// decode to a state no Evaluator gets into, are Corruption.
+
+use crate::Evaluator;
+use submerge_base::{err, Error, Result};
+
+const VERSION: u8 = 2;
+
+impl Evaluator {
+    pub fn checkpoint(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![VERSION];
+        rmp_serde::encode::write(&mut buf, self)?;
+        Ok(buf)
+    }
+
+    pub fn resume(bytes: &[u8]) -> Result<Evaluator> {
+        match bytes.split_first() {
+            Some((&VERSION, rest)) => {
+                let eval: Evaluator = rmp_serde::from_slice(rest)
+                    .map_err(|e| Error::corruption(format!("bad checkpoint: {}", e)))?;
+                eval.validate()
+                    .map_err(|e| Error::corruption(format!("bad checkpoint: {}", e.message())))?;
+                Ok(eval)
+            }
+            Some((v, _)) => Err(Error::format(format!("checkpoint version {}", v))),
+            None => Err(Error::corruption("empty checkpoint")),
+        }
+    }
+
+    // Check that the Vm can run, that no more stages have started than
+    // one past the most, and that the counts are within the limits they
+    // stop at, unless the evaluation stopped for them.
+    fn validate(&self) -> Result<()> {
+        self.cur.validate()?;
+        if let Some(stages) = &self.stages {
+            stages.validate(self.fault.is_some())?;
+        }
+        if let Some(ops) = self.limits.ops.filter(|ops| self.seq > *ops) {
+            return Err(err(format!(
+                "{} opcodes run, past the limit of {}",
+                self.seq, ops
+            )));
+        }
+        if self.exhausted.is_some() && self.fault.is_none() {
+            return Err(err("exhausted a limit without a fault"));
+        }
+        Ok(())
+    }
+}
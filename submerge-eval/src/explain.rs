@@ -0,0 +1,263 @@
+// Explaining a plan: what each of its scans reads, which blocks of which
+// layers it has to scan and about how many rows it keeps, going by the
+// layers' stats, and the operators the residual Expr runs over the scans'
+// output. An Explain is plain data, for a TUI to lay out as it likes, and
+// render() prints it as indented text:
+//
+//   scan t: cols [k, v], pushed v < 3
+//     stats: 1000 rows in 2 blocks; v 0 to 9, ~10 distinct
+//     layer 0: 1 of 2 blocks, ~120 of 1000 rows
+//     ~120 rows
+//   group [k] {n: count(v)}
+//     scan t
+//
+// The stats line is the snapshot the planner decided with (see plan.rs):
+// over all of the table's blocks, each column's least min, greatest max and
+// greatest distinct count, which is a lower bound on the table's.
+// A block is left out if its min and max rule out the pushed predicate, and
+// the rows a scan keeps are estimated from every other block's row count and
+// the predicate's selectivity there (see stats.rs in coldb). Without stats
+// for a table, nothing is known of its layers, and there's no estimate.
+
+use crate::{plan_with, Plan, Stats};
+use std::{collections::BTreeMap, fmt::Write};
+use submerge_coldb::{ColStats, LayerStats, Scan};
+use submerge_lang::{Build, Expr, JoinKind, Path, SortKey};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Explain {
+    pub scans: Vec<ScanExplain>,
+    pub pipeline: Op,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScanExplain {
+    pub scan: Scan,
+    // The table's stats, merged over its blocks, if there are any.
+    pub stats: Option<TableStats>,
+    pub layers: Vec<LayerExplain>,
+    // The rows the scan is estimated to keep, if there are stats.
+    pub rows: Option<f64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TableStats {
+    pub rows: u64,
+    pub blocks: usize,
+    pub cols: BTreeMap<String, ColStats>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayerExplain {
+    // The layer's index in the table's stats.
+    pub layer: usize,
+    // The blocks to scan, by index, and how many there are in all.
+    pub blocks: Vec<usize>,
+    pub total_blocks: usize,
+    pub total_rows: u64,
+    pub rows: f64,
+}
+
+// An operator of the residual Expr, with the operators giving its input.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Op {
+    pub name: &'static str,
+    pub detail: String,
+    pub inputs: Vec<Op>,
+}
+
+pub fn explain(e: &Expr, stats: &Stats) -> Explain {
+    plan_with(e, stats).explain()
+}
+
+impl Plan {
+    pub fn explain(&self) -> Explain {
+        let tables = self
+            .scans
+            .iter()
+            .map(|s| s.table.as_str())
+            .collect::<Vec<_>>();
+        let mut joins = self.joins.iter().copied();
+        Explain {
+            scans: self
+                .scans
+                .iter()
+                .map(|s| scan(s, self.stats.get(&s.table)))
+                .collect(),
+            pipeline: op(&self.expr, &tables, &mut joins),
+        }
+    }
+}
+
+fn merged(layers: &[LayerStats]) -> TableStats {
+    let blocks = layers.iter().flat_map(|l| &l.blocks);
+    let mut cols: BTreeMap<String, ColStats> = BTreeMap::new();
+    for (name, c) in blocks.clone().flat_map(|b| &b.cols) {
+        match cols.get_mut(name) {
+            Some(m) => {
+                m.min = m.min.clone().min(c.min.clone());
+                m.max = m.max.clone().max(c.max.clone());
+                m.distinct = m.distinct.max(c.distinct);
+            }
+            None => {
+                cols.insert(name.clone(), c.clone());
+            }
+        }
+    }
+    TableStats {
+        rows: layers.iter().map(LayerStats::rows).sum(),
+        blocks: blocks.count(),
+        cols,
+    }
+}
+
+fn scan(s: &Scan, layers: Option<&Vec<LayerStats>>) -> ScanExplain {
+    let layer = |(i, l): (usize, &LayerStats)| {
+        let blocks = l.blocks.iter().enumerate();
+        let kept = blocks.filter(|(_, b)| s.pred.may_match(b));
+        let (blocks, rows) = kept.fold((Vec::new(), 0.0), |(mut v, n), (j, b)| {
+            v.push(j);
+            (v, n + b.rows as f64 * s.pred.selectivity(b))
+        });
+        LayerExplain {
+            layer: i,
+            blocks,
+            total_blocks: l.blocks.len(),
+            total_rows: l.rows(),
+            rows,
+        }
+    };
+    let stats = layers.map(|ls| merged(ls));
+    let layers = layers.map(|ls| ls.iter().enumerate().map(layer).collect::<Vec<_>>());
+    ScanExplain {
+        scan: s.clone(),
+        stats,
+        rows: layers.as_ref().map(|ls| ls.iter().map(|l| l.rows).sum()),
+        layers: layers.unwrap_or_default(),
+    }
+}
+
+// The operators of an Expr: filters, groups, sorts, joins and queries, over
+// scans of the planned tables. Anything else is evaluated as it is. Each
+// join takes the next of the plan's build sides, which are in the order the
+// joins are written, so the joins in what isn't an operator are skipped.
+fn op(e: &Expr, tables: &[&str], joins: &mut dyn Iterator<Item = Build>) -> Op {
+    let list = |items: Vec<String>| format!("[{}]", items.join(", "));
+    let (name, detail, inputs): (_, _, Vec<&Expr>) = match e {
+        Expr::Path(Path(p))
+            if p.len() <= 2
+                && p.first()
+                    .is_some_and(|w| tables.contains(&w.to_string().as_str())) =>
+        {
+            ("scan", e.to_string(), vec![])
+        }
+        Expr::Filter(x, pred) => ("filter", pred.to_string(), vec![x]),
+        Expr::Group(x, g) => {
+            let keys = list(g.keys.iter().map(|k| k.to_string()).collect());
+            let aggs = g
+                .aggs
+                .iter()
+                .map(|a| format!("{}: {}({})", a.name, a.op.name(), a.arg));
+            let aggs = format!("{{{}}}", aggs.collect::<Vec<_>>().join(", "));
+            ("group", format!("{} {}", keys, aggs), vec![x])
+        }
+        Expr::Sort(x, keys) => {
+            let key = |k: &SortKey| match k.desc {
+                true => format!("{} desc", k.path),
+                false => k.path.to_string(),
+            };
+            ("sort", list(keys.iter().map(key).collect()), vec![x])
+        }
+        Expr::Join(a, b, j) => {
+            let on = j.on.iter().map(|(l, r)| format!("{} = {}", l, r));
+            let name = match j.kind {
+                JoinKind::Inner => "join",
+                JoinKind::Left => "left join",
+            };
+            let build = match joins.next().unwrap_or_default() {
+                Build::Left => "build left",
+                Build::Right => "build right",
+            };
+            (
+                name,
+                format!("{}, {}", list(on.collect()), build),
+                vec![a, b],
+            )
+        }
+        Expr::Query(x, p) => ("query", p.to_string(), vec![x]),
+        _ => {
+            skip_joins(e, joins);
+            ("eval", e.to_string(), vec![])
+        }
+    };
+    let inputs = inputs.into_iter().map(|x| op(x, tables, joins)).collect();
+    if let Expr::Filter(_, pred) = e {
+        skip_joins(pred, joins);
+    }
+    Op {
+        name,
+        detail,
+        inputs,
+    }
+}
+
+fn skip_joins(e: &Expr, joins: &mut dyn Iterator<Item = Build>) {
+    if let Expr::Join(..) = e {
+        joins.next();
+    }
+    for c in e.children() {
+        skip_joins(c, joins);
+    }
+}
+
+impl Explain {
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for s in &self.scans {
+            let _ = write!(out, "scan {}", s.scan.table);
+            if let Some(cols) = &s.scan.cols {
+                let _ = write!(out, ": cols [{}]", cols.join(", "));
+            }
+            if !s.scan.pred.is_true() {
+                let sep = if s.scan.cols.is_some() { "," } else { ":" };
+                let _ = write!(out, "{} pushed {}", sep, s.scan.pred);
+            }
+            out.push('\n');
+            if let Some(t) = &s.stats {
+                let _ = write!(out, "  stats: {} rows in {} blocks", t.rows, t.blocks);
+                for (i, (name, c)) in t.cols.iter().enumerate() {
+                    let sep = if i == 0 { ";" } else { "," };
+                    let _ = write!(
+                        out,
+                        "{} {} {} to {}, ~{} distinct",
+                        sep, name, c.min, c.max, c.distinct
+                    );
+                }
+                out.push('\n');
+            }
+            for l in &s.layers {
+                let _ = writeln!(
+                    out,
+                    "  layer {}: {} of {} blocks, ~{:.0} of {} rows",
+                    l.layer,
+                    l.blocks.len(),
+                    l.total_blocks,
+                    l.rows,
+                    l.total_rows
+                );
+            }
+            if let Some(rows) = s.rows {
+                let _ = writeln!(out, "  ~{:.0} rows", rows);
+            }
+        }
+        render_op(&mut out, &self.pipeline, 0);
+        out
+    }
+}
+
+fn render_op(out: &mut String, op: &Op, depth: usize) {
+    let _ = writeln!(out, "{}{} {}", "  ".repeat(depth), op.name, op.detail);
+    for i in &op.inputs {
+        render_op(out, i, depth + 1);
+    }
+}
@@ -0,0 +1,114 @@
+// A hybrid scan reads a table whose older rows have been flushed to coldb
+// layers and whose newer ones are still in the row store, as one table. A
+// primary key can have a version in several of them, and the scan gives
+// only the latest: the one with the greatest timestamp or, on a tie, the
+// row store's, then the later layer's, then the later row's. So the rows it
+// gives are the table as it stands, however much of it has been flushed.
+//
+// Before giving any rows, the scan reads the key and timestamp columns of
+// every layer and of the row store, to find each key's latest version. It
+// then gives the rows it kept a batch at a time: the layers' rows, oldest
+// layer first, then the row store's rows, each part's rows in their own
+// order. Every part must have the same columns.
+
+use crate::pipe::{slice, take};
+use crate::{Node, BATCH_ROWS};
+use std::collections::BTreeMap;
+use submerge_base::{err, Result};
+use submerge_lang::{Path, Tab, Val, Word};
+
+// The rows of a table that are in the row store and not yet in a layer.
+pub trait RowStore {
+    fn rows(&self, table: &Word) -> Result<Tab>;
+}
+
+pub struct Hybrid {
+    // The layers, oldest first, then the row store's rows.
+    parts: Vec<Tab>,
+    // The rows of each part that are their key's latest version.
+    keep: Vec<Vec<usize>>,
+    schema: Tab,
+    part: usize,
+    pos: usize,
+}
+
+impl Hybrid {
+    // A scan of a table's layers, oldest first, and of its rows in the row
+    // store, with rows keyed by some columns and versioned by another.
+    pub fn new(
+        table: &Word,
+        layers: Vec<Tab>,
+        store: &dyn RowStore,
+        key: &[Path],
+        time: &Path,
+    ) -> Result<Hybrid> {
+        let mut parts = layers;
+        parts.push(store.rows(table)?);
+        let schema = match parts.iter().find(|p| !p.cols().is_empty()) {
+            Some(p) => slice(p, 0..0)?,
+            None => Tab::default(),
+        };
+        parts.retain(|p| !p.is_empty());
+        let names = |t: &Tab| {
+            t.cols()
+                .iter()
+                .map(|c| c.name().clone())
+                .collect::<Vec<_>>()
+        };
+        if parts.iter().any(|p| names(p) != names(&parts[0])) {
+            return Err(err(format!(
+                "the layers and rows of {} have different columns",
+                table
+            )));
+        }
+        // Each key's latest version so far: its time, part and row.
+        let mut latest: BTreeMap<Vec<Val>, (Val, usize, usize)> = BTreeMap::new();
+        for (i, part) in parts.iter().enumerate() {
+            let col = |p: &Path| Ok(part.resolve(p)?.vals().to_rows());
+            let keys = key.iter().map(col).collect::<Result<Vec<_>>>()?;
+            for (row, t) in col(time)?.into_iter().enumerate() {
+                let k = keys.iter().map(|c| c[row].clone()).collect();
+                match latest.get(&k) {
+                    Some((u, ..)) if *u > t => {}
+                    _ => {
+                        latest.insert(k, (t, i, row));
+                    }
+                }
+            }
+        }
+        let mut keep = vec![Vec::new(); parts.len()];
+        for (_, i, row) in latest.into_values() {
+            keep[i].push(row);
+        }
+        for rows in &mut keep {
+            rows.sort_unstable();
+        }
+        Ok(Hybrid {
+            parts,
+            keep,
+            schema,
+            part: 0,
+            pos: 0,
+        })
+    }
+}
+
+impl Node for Hybrid {
+    fn next(&mut self) -> Result<Option<Tab>> {
+        while let Some(rows) = self.keep.get(self.part) {
+            if self.pos < rows.len() {
+                let end = (self.pos + BATCH_ROWS).min(rows.len());
+                let batch = take(&self.parts[self.part], &rows[self.pos..end])?;
+                self.pos = end;
+                return Ok(Some(batch));
+            }
+            self.part += 1;
+            self.pos = 0;
+        }
+        Ok(None)
+    }
+
+    fn schema(&self) -> Result<Tab> {
+        Ok(self.schema.clone())
+    }
+}
@@ -6,13 +6,306 @@
 //
 // Eval equips the system with a slightly richer complexity class, Dyn-FO, and
 // additionally allows program _staging_ / metaprogramming.
+//
+// Before anything runs, plan.rs works out what an expression reads from the
+// column store, so that coldb can filter on dict codes, and explain.rs shows
+// what it worked out. Pipelines of nodes run filters, projections and
+// aggregates over batches of rows (see pipe.rs), a block at a time on a pool
+// of threads (see par.rs), and can read a table's layers together with the
+// rows still in the row store (see hybrid.rs); their callers build them by
+// hand. The Evaluator doesn't use them: it runs a transaction's Expr in the
+// Vm over whole columns, stage by stage (see stage.rs), within its limits on opcodes, memory and result
+// rows (see limit.rs), and in a float mode that can make every node's floats
+// agree to the bit; any of its stages may call the user-defined functions
+// it's given. Many Evaluators share a replica by taking turns, a slice of
+// fuel each (see sched.rs). Views keep an Expr's result up to date as its
+// table gains layers (see view.rs), and a cache keeps what queries gave, for
+// as long as the layers they read are the same (see cache.rs). An Evaluator
+// part way through can be saved and resumed, by another process if need be
+// (see checkpoint.rs), and its results taken a batch at a time (see
+// stream.rs).
+
+mod cache;
+mod checkpoint;
+mod explain;
+mod hybrid;
+mod limit;
+mod par;
+mod pipe;
+mod plan;
+mod sched;
+mod stage;
+mod stream;
+mod view;
+pub use cache::Cache;
+pub use explain::{explain, Explain, LayerExplain, Op, ScanExplain, TableStats};
+pub use hybrid::{Hybrid, RowStore};
+pub use limit::{Limits, Resource, ResourceExhausted};
+pub use par::{blocks, Merge, Order, Parallel};
+pub use pipe::{collect, Agg, Filter, Node, Project, Source, BATCH_ROWS};
+pub use plan::{plan, plan_with, reads, Plan, Stats, PUSH_SELECTIVITY};
+pub use sched::{Class, Finished, JobId, Metrics, QueueMetrics, Scheduler};
+pub use stage::{overlaps, Footprint, Grain};
+pub use stream::{Results, TabBatch};
+pub use view::{View, Views};
+
+use serde::{Deserialize, Serialize};
+use stage::{is_code, Stages};
+use submerge_base::{err, Error, Result};
+use submerge_lang::{Col, Expr, Fault, FloatMode, Tab, Udfs, Vals, Vm, Word};
 
-use submerge_lang::{Tab, Vm};
+// What evaluating an expression comes to. A fault is an outcome like a
+// value, not an error: it's the same on every node, so a transaction records
+// it as its result. The only errors are traps, which no node can record.
+pub type Outcome = std::result::Result<Vals, Fault>;
 
-#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+// Where a run with some fuel left off.
+#[derive(Debug)]
+pub enum StepResult {
+    // The fuel ran out first. Running again resumes where this run stopped.
+    Pending,
+    // Evaluation finished. Running again returns the same outcome.
+    Done(Outcome),
+    // The Vm trapped, or its value couldn't be added to the results.
+    Failed(Error),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 pub struct Evaluator {
     tmp: Tab,
+    // The rows the evaluation has produced that results() hasn't let go
+    // of, and how many of them it has given.
     new: Tab,
+    sent: usize,
+    // How many opcodes have run, over every stage.
     seq: usize,
     cur: Vm,
+    // Where a staged evaluation is up to, and the fault of a stage that
+    // couldn't run.
+    stages: Option<Stages>,
+    fault: Option<Fault>,
+    limits: Limits,
+    float_mode: FloatMode,
+    // The most bytes the operand stack has held, if there's a byte limit
+    // to hold it to, and the limit the evaluation ran past, if it did.
+    peak: usize,
+    exhausted: Option<ResourceExhausted>,
+}
+
+impl Evaluator {
+    pub fn new(cur: Vm) -> Evaluator {
+        Evaluator {
+            tmp: Tab::default(),
+            new: Tab::default(),
+            sent: 0,
+            seq: 0,
+            cur,
+            stages: None,
+            fault: None,
+            limits: Limits::default(),
+            float_mode: FloatMode::default(),
+            peak: 0,
+            exhausted: None,
+        }
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Evaluator {
+        self.limits = limits;
+        self
+    }
+
+    // Run every stage's Vm in a float mode. A transaction's Evaluator runs
+    // under FloatMode::Deterministic, so that every replica gets the same
+    // bits (see float.rs in lang).
+    pub fn with_float_mode(mut self, mode: FloatMode) -> Evaluator {
+        self.float_mode = mode;
+        self.cur = self.cur.with_float_mode(mode);
+        self
+    }
+
+    // The user-defined functions the current and later stages can call
+    // (see udf.rs in lang). A resumed checkpoint needs them given again.
+    pub fn with_udfs(mut self, udfs: Udfs) -> Evaluator {
+        if let Some(stages) = &mut self.stages {
+            stages.set_udfs(udfs.clone());
+        }
+        self.cur = self.cur.with_udfs(udfs);
+        self
+    }
+
+    // An evaluation of an Expr, and of the code it gives, if it gives code,
+    // for at most max stages in all.
+    pub fn staged(expr: &Expr, ctx: Vec<Tab>, foot: Footprint, max: usize) -> Evaluator {
+        Evaluator::staged_with(expr, ctx, foot, max, Udfs::default())
+    }
+
+    // As staged(), for an Expr whose stages may call user-defined
+    // functions, the first one included.
+    pub fn staged_with(
+        expr: &Expr,
+        ctx: Vec<Tab>,
+        foot: Footprint,
+        max: usize,
+        udfs: Udfs,
+    ) -> Evaluator {
+        let mut stages = Stages::new(ctx, foot, max);
+        stages.set_udfs(udfs);
+        let (cur, fault) = match stages.next(expr) {
+            Ok(vm) => (vm, None),
+            Err(f) => (Vm::new(Vec::new(), Vec::new()), Some(f)),
+        };
+        Evaluator {
+            stages: Some(stages),
+            fault,
+            ..Evaluator::new(cur)
+        }
+    }
+
+    // How many stages have started, if the evaluation is staged.
+    pub fn stage(&self) -> Option<usize> {
+        self.stages.as_ref().map(Stages::count)
+    }
+
+    // The rows the evaluation has produced, less those results() has given
+    // and let go of (see stream.rs).
+    pub fn rows(&self) -> &Tab {
+        &self.new
+    }
+
+    pub fn seq(&self) -> usize {
+        self.seq
+    }
+
+    pub fn peak(&self) -> usize {
+        self.peak
+    }
+
+    pub fn exhausted(&self) -> Option<&ResourceExhausted> {
+        self.exhausted.as_ref()
+    }
+
+    // Stop with a ResourceExhausted fault, since the limit is past.
+    fn exhaust(&mut self, x: ResourceExhausted) {
+        self.fault = Some(x.fault());
+        self.exhausted = Some(x);
+    }
+
+    // Run up to fuel opcodes of the current stage, within the limits:
+    // one at a time if there's a byte limit, to check the stack after each.
+    fn step(&mut self, mut fuel: usize) -> Result<usize> {
+        let start = fuel;
+        while fuel > 0 && !self.cur.is_done() {
+            if let Some(x) = self.limits.check(Resource::Ops, self.seq + 1) {
+                self.exhaust(x);
+                break;
+            }
+            let mut n = match self.limits.ops {
+                Some(ops) => fuel.min(ops - self.seq),
+                None => fuel,
+            };
+            if self.limits.bytes.is_some() {
+                n = 1;
+            }
+            let ran = self.cur.step(n);
+            if let Ok(n) = ran {
+                self.seq += n;
+                fuel -= n;
+            }
+            if self.limits.bytes.is_some() {
+                self.peak = self.peak.max(self.cur.stack_bytes());
+                if let Some(x) = self.limits.check(Resource::Bytes, self.peak) {
+                    self.exhaust(x);
+                    break;
+                }
+            }
+            ran?;
+        }
+        Ok(start - fuel)
+    }
+
+    // Run up to fuel opcodes. The txn layer calls this repeatedly, between
+    // other work, until it's done; nothing else it does runs unboundedly.
+    // In a staged evaluation, the fuel carries over from one stage to the
+    // next.
+    pub fn run(&mut self, mut fuel: usize) -> StepResult {
+        while !self.cur.is_done() && self.fault.is_none() {
+            match self.step(fuel) {
+                Ok(n) => fuel -= n,
+                Err(e) if self.cur.fault().is_none() => return StepResult::Failed(e),
+                Err(_) => {}
+            }
+            if self.fault.is_some() {
+                break;
+            }
+            if !self.cur.is_done() {
+                return StepResult::Pending;
+            }
+            let Some(v) = self.cur.result().cloned() else {
+                break;
+            };
+            match &mut self.stages {
+                Some(stages) if is_code(&v) => match Expr::from_code(&v) {
+                    Ok(e) => match stages.next(&e) {
+                        Ok(vm) => self.cur = vm.with_float_mode(self.float_mode),
+                        Err(f) => self.fault = Some(f),
+                    },
+                    Err(e) => match e.downcast_ref::<Fault>() {
+                        Some(f) => self.fault = Some(stages.refuse(&f.msg)),
+                        None => return StepResult::Failed(e),
+                    },
+                },
+                _ => {
+                    if let Err(e) = self.accumulate(&v) {
+                        return StepResult::Failed(e);
+                    }
+                    if let Some(x) = self.limits.check(Resource::Rows, self.new.len()) {
+                        self.exhaust(x);
+                    }
+                }
+            }
+        }
+        if let Some(f) = &self.fault {
+            return StepResult::Done(Err(f.clone()));
+        }
+        match self.cur.eval() {
+            Ok(outcome) => StepResult::Done(outcome),
+            Err(e) => StepResult::Failed(e),
+        }
+    }
+
+    // Add a value's rows to the results: a record's fields are columns, and
+    // anything else is one unnamed column. The first value sets the columns
+    // that later ones must fit.
+    fn accumulate(&mut self, v: &Vals) -> Result<()> {
+        let col = |f: &Vals| match f {
+            Vals::Rich(c) => Some((**c).clone()),
+            _ => None,
+        };
+        let cols = match v {
+            Vals::All(fields) => fields.iter().map(col).collect(),
+            _ => None,
+        };
+        let cols = cols.unwrap_or_else(|| vec![Col::new(Word::positional(0), v.clone())]);
+        let tab = Tab::new(cols)?;
+        if self.new.cols().is_empty() {
+            self.new = tab;
+            return Ok(());
+        }
+        let names = |t: &Tab| {
+            t.cols()
+                .iter()
+                .map(|c| c.name().clone())
+                .collect::<Vec<_>>()
+        };
+        if names(&tab) != names(&self.new) {
+            return Err(err("result columns differ from earlier results"));
+        }
+        for row in tab.rows() {
+            self.new.push_row(row)?;
+        }
+        Ok(())
+    }
 }
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,75 @@
+// Limits on an evaluation: how many opcodes it may run, over every stage;
+// how many bytes the Vm's operand stack may hold at once, by Vals::bytes;
+// and how many rows of results it may produce. None is no limit.
+//
+// Every count is of the data, not of the machine running it, so every node
+// runs out at the same opcode and records the same fault, of kind
+// ResourceExhausted. A limit is checked between opcodes: one opcode can
+// briefly hold more than the byte limit before the check sees it.
+
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+use submerge_lang::{Fault, FaultKind};
+
+#[derive(
+    Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize,
+)]
+pub struct Limits {
+    pub ops: Option<usize>,
+    pub bytes: Option<usize>,
+    pub rows: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum Resource {
+    Ops,
+    Bytes,
+    Rows,
+}
+
+impl Resource {
+    pub fn name(self) -> &'static str {
+        match self {
+            Resource::Ops => "opcodes",
+            Resource::Bytes => "bytes",
+            Resource::Rows => "result rows",
+        }
+    }
+}
+
+// The limit an evaluation ran past.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct ResourceExhausted {
+    pub resource: Resource,
+    pub limit: usize,
+}
+
+impl Display for ResourceExhausted {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "more than {} {}", self.limit, self.resource.name())
+    }
+}
+
+impl std::error::Error for ResourceExhausted {}
+
+impl ResourceExhausted {
+    pub fn fault(&self) -> Fault {
+        Fault {
+            kind: FaultKind::ResourceExhausted,
+            pc: 0,
+            msg: self.to_string(),
+        }
+    }
+}
+
+impl Limits {
+    // The limit on a resource, if its use is past it.
+    pub fn check(&self, resource: Resource, used: usize) -> Option<ResourceExhausted> {
+        let limit = match resource {
+            Resource::Ops => self.ops,
+            Resource::Bytes => self.bytes,
+            Resource::Rows => self.rows,
+        }?;
+        (used > limit).then_some(ResourceExhausted { resource, limit })
+    }
+}
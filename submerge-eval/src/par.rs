@@ -0,0 +1,166 @@
+// Parallel evaluation splits a table into partitions, a coldb block each,
+// and runs a pipeline over every partition on a pool of threads. What the
+// partitions give is then merged: their rows put together, their partial
+// groups grouped again by key, or their sorted rows sorted together.
+//
+// With Order::Fixed, the merge takes the partitions in order, holding on to
+// any that finish early, so a parallel run gives what running the pipeline
+// and merge over the partitions one after another would, on every node and
+// with any number of threads. Replicas need that, since a float sum depends
+// on the order its partial sums are added in, and sorting keeps equal rows
+// in the order they come. With Order::Arrival, the merge takes partitions
+// as they finish, which doesn't wait on slow ones, for queries whose results
+// no other node has to agree with. An error stops the run; with a fixed
+// order it's the first partition's error, in partition order.
+
+use crate::pipe::{append, slice, Groups};
+use crate::{collect, Node, Source};
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc,
+    },
+};
+use submerge_base::Result;
+use submerge_coldb::BLOCK_ROWS;
+use submerge_lang::{Grouping, Overflow, SortKey, Tab};
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Order {
+    #[default]
+    Fixed,
+    Arrival,
+}
+
+// How the partitions' results come together.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Merge {
+    Concat,
+    Group(Grouping, Overflow),
+    Sort(Vec<SortKey>),
+}
+
+// The partitions of a table, a block's worth of rows each.
+pub fn blocks(tab: &Tab) -> Result<Vec<Tab>> {
+    let starts = (0..tab.len()).step_by(BLOCK_ROWS);
+    let block = |i| slice(tab, i..(i + BLOCK_ROWS).min(tab.len()));
+    starts.map(block).collect()
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Parallel {
+    threads: usize,
+    order: Order,
+}
+
+// The results merged so far.
+#[derive(Clone)]
+pub(crate) enum Merged {
+    Rows(Option<Tab>),
+    Groups(Groups),
+}
+
+impl Merged {
+    pub(crate) fn new(merge: &Merge) -> Merged {
+        match merge {
+            Merge::Group(g, overflow) => Merged::Groups(Groups::new(g, *overflow)),
+            _ => Merged::Rows(None),
+        }
+    }
+
+    // A partition's result, as the partial() of its grouping if it's grouped
+    // and sorted if it's sorted.
+    pub(crate) fn add(&mut self, part: Tab) -> Result<()> {
+        match self {
+            Merged::Rows(None) => *self = Merged::Rows(Some(part)),
+            Merged::Rows(Some(tab)) => append(tab, &part)?,
+            Merged::Groups(g) => g.add_partial(part)?,
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish(self, merge: &Merge) -> Result<Tab> {
+        match (self, merge) {
+            (Merged::Groups(g), _) => g.finish(),
+            (Merged::Rows(Some(tab)), Merge::Sort(keys)) => tab.sort_by(keys),
+            (Merged::Rows(tab), _) => Ok(tab.unwrap_or_default()),
+        }
+    }
+}
+
+// What merging a partition's rows needs of them: grouped by the merge's
+// grouping, if it groups, or sorted, if it sorts.
+pub(crate) fn partial(merge: &Merge, rows: Tab) -> Result<Tab> {
+    match merge {
+        Merge::Group(g, overflow) => Groups::new(g, *overflow).partial(rows),
+        Merge::Sort(keys) => rows.sort_by(keys),
+        Merge::Concat => Ok(rows),
+    }
+}
+
+impl Parallel {
+    pub fn new(threads: usize) -> Parallel {
+        Parallel {
+            threads: threads.max(1),
+            order: Order::default(),
+        }
+    }
+
+    pub fn with_order(mut self, order: Order) -> Parallel {
+        self.order = order;
+        self
+    }
+
+    // Run a pipeline, which build() makes from a partition's source, over
+    // every partition, and merge the results.
+    pub fn run(
+        &self,
+        parts: Vec<Tab>,
+        build: &(dyn Fn(Box<dyn Node>) -> Box<dyn Node> + Sync),
+        merge: &Merge,
+    ) -> Result<Tab> {
+        let mut merged = Merged::new(merge);
+        let work = |part: Tab| -> Result<Tab> {
+            let rows = collect(&mut *build(Box::new(Source::new(part))))?;
+            partial(merge, rows)
+        };
+        let n = parts.len();
+        let next = AtomicUsize::new(0);
+        let stop = AtomicBool::new(false);
+        let (tx, rx) = mpsc::channel();
+        std::thread::scope(|s| {
+            for _ in 0..self.threads.min(n) {
+                let tx = tx.clone();
+                let (parts, next, stop, work) = (&parts, &next, &stop, &work);
+                s.spawn(move || loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    if i >= n || stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    if tx.send((i, work(parts[i].clone()))).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop(tx);
+            let mut waiting = BTreeMap::new();
+            let mut turn = 0;
+            for (i, part) in rx {
+                waiting.insert(i, part);
+                while let Some(part) = match self.order {
+                    Order::Fixed => waiting.remove(&turn),
+                    Order::Arrival => waiting.pop_first().map(|(_, p)| p),
+                } {
+                    turn += 1;
+                    if let Err(e) = part.and_then(|p| merged.add(p)) {
+                        stop.store(true, Ordering::Relaxed);
+                        return Err(e);
+                    }
+                }
+            }
+            Ok(())
+        })?;
+        merged.finish(merge)
+    }
+}
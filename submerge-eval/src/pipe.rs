@@ -0,0 +1,417 @@
+// A pipeline runs filters, projections and an aggregate over batches of
+// rows flowing through a chain of nodes, each pulling batches from the one
+// before it. Between the source and the last node, only an aggregate holds
+// more than a batch at a time, and then only a row per group. Each batch is
+// run through the lang kernels whole, so they see BATCH_ROWS rows at a time
+// rather than a row or a whole column.
+//
+// Pipelines are built by hand: par.rs runs them over the blocks of an
+// in-memory table, and hybrid.rs's scan is a source for one. Nothing lowers
+// an Expr to a pipeline, and the Evaluator runs its Vm over whole columns
+// instead. collect() and the merges that append batches copy them a row at
+// a time.
+//
+// Batches are never empty, and have at most BATCH_ROWS rows. Filters keep
+// the rows' order, so the rows come out of a pipeline in the order its
+// source gives them. Every node also has a schema, a table of no rows with
+// the columns its batches have, so that a pipeline that gives no rows still
+// gives a table of the right columns.
+//
+// An aggregate groups each batch as it comes, then groups the partial
+// results again by key: counts are summed, and sums, mins and maxes are
+// taken again. A mean is carried as the sum and count of its rows, in
+// hidden columns, and divided out at the end. That gives the same groups as
+// aggregating the whole input, except that a float sum or mean adds its
+// batches' compensated sums, so it can differ in the last place from one
+// over the whole column (though every node, batching the same way, gets the
+// same one), and that a batch's sum, or the sum under a mean, overflows as
+// a sum does even where the whole column's wouldn't.
+
+use ordered_float::OrderedFloat;
+use std::ops::Range;
+use submerge_base::{err, Result};
+use submerge_lang::{
+    AggOp, Aggregate, Col, Grouping, Opcode, Overflow, Path, Tab, Val, Vals, Word,
+};
+
+pub const BATCH_ROWS: usize = 1024;
+
+pub trait Node {
+    // The next batch, or None once there are no more.
+    fn next(&mut self) -> Result<Option<Tab>>;
+
+    // A table of no rows with the columns of the node's batches.
+    fn schema(&self) -> Result<Tab>;
+}
+
+// Every row a node gives, as one table.
+pub fn collect(node: &mut dyn Node) -> Result<Tab> {
+    let mut out = match node.next()? {
+        Some(batch) => batch,
+        None => return node.schema(),
+    };
+    while let Some(batch) = node.next()? {
+        append(&mut out, &batch)?;
+    }
+    Ok(out)
+}
+
+pub(crate) fn append(tab: &mut Tab, batch: &Tab) -> Result<()> {
+    for row in batch.rows() {
+        tab.push_row(row)?;
+    }
+    Ok(())
+}
+
+// The batches of a table that's already in memory.
+pub struct Source {
+    tab: Tab,
+    pos: usize,
+}
+
+impl Source {
+    pub fn new(tab: Tab) -> Source {
+        Source { tab, pos: 0 }
+    }
+
+    // A source that carries on from a row that pos() gave before, as a
+    // checkpointed pipeline does.
+    pub fn at(tab: Tab, pos: usize) -> Source {
+        let pos = pos.min(tab.len());
+        Source { tab, pos }
+    }
+
+    // The row the next batch starts at.
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+}
+
+impl Node for Source {
+    fn next(&mut self) -> Result<Option<Tab>> {
+        if self.pos >= self.tab.len() {
+            return Ok(None);
+        }
+        let end = (self.pos + BATCH_ROWS).min(self.tab.len());
+        let batch = slice(&self.tab, self.pos..end)?;
+        self.pos = end;
+        Ok(Some(batch))
+    }
+
+    fn schema(&self) -> Result<Tab> {
+        slice(&self.tab, 0..0)
+    }
+}
+
+// Some consecutive rows of a table.
+pub(crate) fn slice(tab: &Tab, rows: Range<usize>) -> Result<Tab> {
+    take(tab, &rows.collect::<Vec<_>>())
+}
+
+// Some rows of a table, in the order given.
+pub(crate) fn take(tab: &Tab, rows: &[usize]) -> Result<Tab> {
+    let col = |c: &Col| {
+        let vals = c.vals().take(rows)?;
+        Ok(Col::new(c.name().clone(), vals)
+            .with_form(c.form())
+            .with_unit(c.unit()))
+    };
+    Tab::new(tab.cols().iter().map(col).collect::<Result<_>>()?)
+}
+
+// The rows of each batch for which a predicate, which sees the batch's
+// columns by name, is true.
+pub struct Filter {
+    input: Box<dyn Node>,
+    pred: Vec<Opcode>,
+}
+
+impl Filter {
+    pub fn new(input: Box<dyn Node>, pred: Vec<Opcode>) -> Filter {
+        Filter { input, pred }
+    }
+}
+
+impl Node for Filter {
+    fn next(&mut self) -> Result<Option<Tab>> {
+        while let Some(batch) = self.input.next()? {
+            let kept = batch.filter(&self.pred)?;
+            if !kept.is_empty() {
+                return Ok(Some(kept));
+            }
+        }
+        Ok(None)
+    }
+
+    fn schema(&self) -> Result<Tab> {
+        self.input.schema()
+    }
+}
+
+// The columns at some paths of each batch, in order, each named by the last
+// word of its path.
+pub struct Project {
+    input: Box<dyn Node>,
+    paths: Vec<Path>,
+}
+
+impl Project {
+    pub fn new(input: Box<dyn Node>, paths: Vec<Path>) -> Project {
+        Project { input, paths }
+    }
+}
+
+impl Project {
+    fn project(&self, batch: &Tab) -> Result<Tab> {
+        let col = |p: &Path| batch.resolve(p).cloned();
+        let cols = self.paths.iter().map(col);
+        Tab::new(cols.collect::<Result<_>>()?)
+    }
+}
+
+impl Node for Project {
+    fn next(&mut self) -> Result<Option<Tab>> {
+        let Some(batch) = self.input.next()? else {
+            return Ok(None);
+        };
+        Ok(Some(self.project(&batch)?))
+    }
+
+    fn schema(&self) -> Result<Tab> {
+        let schema = self.input.schema()?;
+        if schema.cols().is_empty() {
+            return Ok(schema);
+        }
+        self.project(&schema)
+    }
+}
+
+// A group-by over every batch, which gives its groups once the input is
+// used up.
+pub struct Agg {
+    input: Option<Box<dyn Node>>,
+    grouping: Grouping,
+    overflow: Overflow,
+    out: Option<Source>,
+}
+
+impl Agg {
+    pub fn new(input: Box<dyn Node>, grouping: Grouping, overflow: Overflow) -> Agg {
+        Agg {
+            input: Some(input),
+            grouping,
+            overflow,
+            out: None,
+        }
+    }
+
+    fn run(&self, mut input: Box<dyn Node>) -> Result<Tab> {
+        let mut groups = Groups::new(&self.grouping, self.overflow);
+        groups.add_schema(&*input)?;
+        while let Some(batch) = input.next()? {
+            groups.add(batch)?;
+        }
+        groups.finish()
+    }
+}
+
+// The groups of the rows seen so far. Each lot of rows added is grouped on
+// its own, and the partial results are merged by key when they've doubled
+// since the last merge, so there are few merges however many groups there
+// are.
+#[derive(Clone)]
+pub(crate) struct Groups {
+    partial: Grouping,
+    merging: Grouping,
+    // Each mean's column, and the hidden columns of its sum and count.
+    means: Vec<(Word, Word, Word)>,
+    overflow: Overflow,
+    acc: Option<Tab>,
+    merged: bool,
+    limit: usize,
+}
+
+impl Groups {
+    pub(crate) fn new(grouping: &Grouping, overflow: Overflow) -> Groups {
+        let mut partial = grouping.clone();
+        let mut means = Vec::new();
+        for (i, a) in grouping.aggs.iter().enumerate() {
+            if a.op != AggOp::Mean {
+                continue;
+            }
+            let sum = hidden(grouping, &format!("sum{}", i));
+            let count = hidden(grouping, &format!("count{}", i));
+            for (name, op) in [(&sum, AggOp::Sum), (&count, AggOp::Count)] {
+                partial.aggs.push(Aggregate {
+                    name: name.clone(),
+                    op,
+                    arg: a.arg.clone(),
+                });
+            }
+            means.push((a.name.clone(), sum, count));
+        }
+        Groups {
+            merging: merging(&partial),
+            partial,
+            means,
+            overflow,
+            acc: None,
+            merged: true,
+            limit: BATCH_ROWS,
+        }
+    }
+
+    // Rows, or the partial groups partial() made of them.
+    pub(crate) fn add_partial(&mut self, part: Tab) -> Result<()> {
+        let Some(tab) = &mut self.acc else {
+            self.acc = Some(part);
+            return Ok(());
+        };
+        append(tab, &part)?;
+        self.merged = false;
+        if tab.len() > self.limit {
+            *tab = tab.group_by(&self.merging, self.overflow)?;
+            self.limit = self.limit.max(tab.len() * 2);
+            self.merged = true;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn add(&mut self, rows: Tab) -> Result<()> {
+        let part = self.partial(rows)?;
+        self.add_partial(part)
+    }
+
+    // The schema of a node, so that no rows from it are no groups, with the
+    // groups' columns. A node with no columns at all, like a source of
+    // Tab::default(), has nothing to group.
+    pub(crate) fn add_schema(&mut self, node: &dyn Node) -> Result<()> {
+        let schema = node.schema()?;
+        if schema.cols().is_empty() {
+            return Ok(());
+        }
+        self.add(schema)
+    }
+
+    // What add() keeps of some rows, which can be worked out elsewhere and
+    // added with add_partial().
+    pub(crate) fn partial(&self, rows: Tab) -> Result<Tab> {
+        rows.group_by(&self.partial, self.overflow)
+    }
+
+    pub(crate) fn finish(self) -> Result<Tab> {
+        let tab = match self.acc {
+            None => return Ok(Tab::default()),
+            Some(tab) if self.merged => tab,
+            Some(tab) => tab.group_by(&self.merging, self.overflow)?,
+        };
+        if self.means.is_empty() {
+            return Ok(tab);
+        }
+        let rows = |w: &Word| -> Result<Vec<Val>> {
+            Ok(tab.resolve(&Path(vec![w.clone()]))?.vals().to_rows())
+        };
+        let mut cols = Vec::new();
+        for c in tab.cols() {
+            let hidden = self
+                .means
+                .iter()
+                .any(|(_, s, n)| c.name() == s || c.name() == n);
+            let Some((_, sum, count)) = self.means.iter().find(|(m, ..)| c.name() == m) else {
+                if !hidden {
+                    cols.push(c.clone());
+                }
+                continue;
+            };
+            let parts = rows(sum)?.into_iter().zip(rows(count)?);
+            let means = parts.map(|(s, n)| mean(c, s, n));
+            let vals = Vals::from_rows(c.vals(), means.collect::<Result<Vec<_>>>()?)?;
+            cols.push(
+                Col::new(c.name().clone(), vals)
+                    .with_form(c.form())
+                    .with_unit(c.unit()),
+            );
+        }
+        Tab::new(cols)
+    }
+}
+
+// A name for a hidden column that's none of a grouping's own.
+fn hidden(g: &Grouping, base: &str) -> Word {
+    let taken = |w: &Word| {
+        let keys = g.keys.iter().filter_map(|p| p.0.last());
+        keys.chain(g.aggs.iter().map(|a| &a.name)).any(|x| x == w)
+    };
+    let mut name = format!("_{}", base);
+    loop {
+        match Word::new(&name) {
+            Some(w) if !taken(&w) => return w,
+            _ => name.insert(0, '_'),
+        }
+    }
+}
+
+// A mean from the sum and count of a group's rows, typed like the mean
+// column: the same form as a decimal, and a float otherwise.
+fn mean(col: &Col, sum: Val, count: Val) -> Result<Val> {
+    let decimal = col.form().as_decimal().is_some();
+    Ok(match (sum, count) {
+        (_, Val::Int(0)) | (Val::Null, _) => Val::Null,
+        (Val::Int(s), Val::Int(n)) if decimal => Val::Int(s / n),
+        (Val::Int(s), Val::Int(n)) => Val::Flo(OrderedFloat(s as f64 / n as f64)),
+        (Val::Flo(s), Val::Int(n)) => Val::Flo(OrderedFloat(s.0 / n as f64)),
+        (s, n) => {
+            return Err(err(format!(
+                "no mean of {} from a sum {:?} of {:?} rows",
+                col.name(),
+                s,
+                n
+            )))
+        }
+    })
+}
+
+// The grouping that merges partial results, which have a column per key and
+// aggregate. A mean's column only carries its type until the mean is worked
+// out from its sum and count, so any of its values will do.
+fn merging(g: &Grouping) -> Grouping {
+    let name = |p: &Path| Path(p.0.last().into_iter().cloned().collect());
+    let agg = |a: &Aggregate| Aggregate {
+        name: a.name.clone(),
+        op: match a.op {
+            AggOp::Count => AggOp::Sum,
+            AggOp::Mean => AggOp::Min,
+            op => op,
+        },
+        arg: Path(vec![a.name.clone()]),
+    };
+    Grouping {
+        keys: g.keys.iter().map(name).collect(),
+        aggs: g.aggs.iter().map(agg).collect(),
+    }
+}
+
+impl Node for Agg {
+    fn next(&mut self) -> Result<Option<Tab>> {
+        if let Some(input) = self.input.take() {
+            let tab = self.run(input)?;
+            self.out = Some(Source::new(tab));
+        }
+        match &mut self.out {
+            Some(out) => out.next(),
+            None => Ok(None),
+        }
+    }
+
+    fn schema(&self) -> Result<Tab> {
+        match (&self.input, &self.out) {
+            (_, Some(out)) => out.schema(),
+            (Some(input), None) => {
+                let mut groups = Groups::new(&self.grouping, self.overflow);
+                groups.add_schema(&**input)?;
+                groups.finish()
+            }
+            (None, None) => Ok(Tab::default()),
+        }
+    }
+}
@@ -0,0 +1,405 @@
+// Planning splits what an Expr reads from the column store out of it, as one
+// coldb scan per table: the columns it needs, and the rows its filters keep,
+// as far as coldb can tell them apart on dict codes. Only a conjunct of a
+// filter's predicate that compares a column with a literal, like x < 3 or
+// "a" == name, can be pushed down that way; the rest stays in the residual
+// Expr, which runs against the scans' output.
+//
+// The planner has no schema, so it takes a one-word path in a predicate, or
+// in a group's keys and aggregates, to name a column of the table being
+// filtered or grouped, and any other path to name a table in the context,
+// then a column of it. A table that's read more than one way is scanned for
+// every column and row that any of its reads needs, and its filters stay
+// whole in the residual, since it isn't the only read any more. A reify can
+// read any table, so it turns pushdown off.
+//
+// Given coldb's stats for a table's layers (see stats.rs there), the planner
+// also decides what's worth doing: a conjunct is only pushed down if it
+// rules out a block or keeps at most PUSH_SELECTIVITY of the rows, since
+// otherwise testing every chunk's codes costs more than it saves; pushed
+// conjuncts are ordered to test the most selective first; and an inner join
+// builds its hash table over whichever side is estimated to be smaller (see
+// Build in lang). The plan keeps the stats it was made with, for explain.rs.
+
+use std::collections::{BTreeMap, BTreeSet};
+use submerge_coldb::{Cmp, Key, LayerStats, Pred, Scan};
+use submerge_lang::{Bin, Build, Expr, JoinKind, Path, PrimBinOp, Vals, Word};
+
+pub const PUSH_SELECTIVITY: f64 = 0.9;
+
+// The stats of each table's layers, by table.
+pub type Stats = BTreeMap<String, Vec<LayerStats>>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Plan {
+    pub scans: Vec<Scan>,
+    pub expr: Expr,
+    // The side to build each join in the residual over, in the order
+    // they're written.
+    pub joins: Vec<Build>,
+    // The stats of the scanned tables.
+    pub stats: Stats,
+}
+
+// One read of a table: the columns it needs, if not all of them, and the
+// rows it keeps.
+struct Read {
+    cols: Option<BTreeSet<String>>,
+    pred: Pred,
+}
+
+#[derive(Default)]
+struct Planner {
+    // Let- and lambda-bound words in scope.
+    bound: Vec<Word>,
+    reads: BTreeMap<String, Vec<Read>>,
+    // The columns named in the predicate being walked, if one is.
+    pred_cols: Option<BTreeSet<String>>,
+    reify: bool,
+    stats: Stats,
+}
+
+pub fn plan(e: &Expr) -> Plan {
+    plan_with(e, &Stats::new())
+}
+
+pub fn plan_with(e: &Expr, stats: &Stats) -> Plan {
+    let mut p = Planner {
+        stats: stats.clone(),
+        ..Planner::default()
+    };
+    p.walk(e);
+    let single = match p.reify {
+        true => BTreeSet::new(),
+        false => p
+            .reads
+            .iter()
+            .filter(|(_, rs)| rs.len() == 1)
+            .map(|(t, _)| t.clone())
+            .collect(),
+    };
+    let expr = p.residual(e, &single);
+    // The residual has the same joins, but may have lost the filters that
+    // tell how big their sides are.
+    let mut joins = Vec::new();
+    p.joins(e, &mut joins);
+    let stats = p
+        .stats
+        .iter()
+        .filter(|(t, _)| p.reads.contains_key(*t))
+        .map(|(t, s)| (t.clone(), s.clone()))
+        .collect();
+    let scans = p.reads.into_iter().map(|(table, reads)| {
+        let mut cols = Some(BTreeSet::new());
+        let mut preds = Vec::new();
+        for r in reads {
+            cols = cols.zip(r.cols).map(|(a, b)| &a | &b);
+            preds.push(r.pred);
+        }
+        let pred = match preds.len() {
+            _ if p.reify || preds.iter().any(Pred::is_true) => Pred::TRUE,
+            1 => preds.pop().unwrap(),
+            _ => Pred::Or(preds),
+        };
+        let cols = cols.filter(|_| !p.reify).map(|c| c.into_iter().collect());
+        Scan { table, cols, pred }
+    });
+    Plan {
+        scans: scans.collect(),
+        expr,
+        joins,
+        stats,
+    }
+}
+
+// The paths an Expr reads from the context: a table's columns, or the whole
+// table if it's read whole, or the empty path, the whole context, if it has
+// a reify.
+pub fn reads(e: &Expr) -> Vec<Path> {
+    let mut p = Planner::default();
+    p.walk(e);
+    let everything = vec![Path(Vec::new())];
+    if p.reify {
+        return everything;
+    }
+    let mut out = Vec::new();
+    for (table, reads) in p.reads {
+        let cols = reads
+            .into_iter()
+            .try_fold(BTreeSet::new(), |a, r| Some(&a | &r.cols?));
+        let Some(t) = Word::new(&table) else {
+            return everything;
+        };
+        match cols {
+            None => out.push(Path(vec![t])),
+            Some(cols) => {
+                for c in cols {
+                    let Some(c) = Word::new(&c) else {
+                        return everything;
+                    };
+                    out.push(Path(vec![t.clone(), c]));
+                }
+            }
+        }
+    }
+    out
+}
+
+fn cmp(op: PrimBinOp) -> Option<Cmp> {
+    match op {
+        PrimBinOp::Eq => Some(Cmp::Eq),
+        PrimBinOp::Ne => Some(Cmp::Ne),
+        PrimBinOp::Lt => Some(Cmp::Lt),
+        PrimBinOp::Le => Some(Cmp::Le),
+        PrimBinOp::Gt => Some(Cmp::Gt),
+        PrimBinOp::Ge => Some(Cmp::Ge),
+        _ => None,
+    }
+}
+
+// A one-row literal without a form or unit, as dictionaries sort it.
+fn key(e: &Expr) -> Option<Key> {
+    match e {
+        Expr::Lit(Vals::I64s(v)) if v.len() == 1 => Some(Key::Int(v[0])),
+        Expr::Lit(Vals::F64s(v)) if v.len() == 1 => Some(Key::Flo(v[0])),
+        Expr::Lit(Vals::Bins(v)) => match v.as_slice() {
+            [Bin::Mem(b)] => Some(Key::Bin(b.clone())),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// The conjuncts of a predicate, in order.
+fn conjuncts(e: &Expr) -> Vec<&Expr> {
+    match e {
+        Expr::BinOp(PrimBinOp::And, a, b) => {
+            let mut v = conjuncts(a);
+            v.extend(conjuncts(b));
+            v
+        }
+        _ => vec![e],
+    }
+}
+
+impl Planner {
+    fn is_bound(&self, w: &Word) -> bool {
+        self.bound.contains(w)
+    }
+
+    // The table an Expr names, if it's just an unbound one-word path.
+    fn table(&self, e: &Expr) -> Option<String> {
+        match e {
+            Expr::Path(Path(p)) if p.len() == 1 && !self.is_bound(&p[0]) => Some(p[0].to_string()),
+            _ => None,
+        }
+    }
+
+    // A column of the filtered table compared with a literal.
+    fn sargable(&self, e: &Expr) -> Option<Pred> {
+        let Expr::BinOp(op, a, b) = e else {
+            return None;
+        };
+        let op = cmp(*op)?;
+        let col = |e: &Expr| self.table(e);
+        match (col(a), key(a), col(b), key(b)) {
+            (Some(c), _, _, Some(k)) => Some(Pred::Cmp(c, op, k)),
+            (_, Some(k), Some(c), _) => Some(Pred::Cmp(c, op.flip(), k)),
+            _ => None,
+        }
+    }
+
+    // The fraction of a table's rows a predicate is estimated to keep, and
+    // whether it rules out any block, if there are stats for the table.
+    fn estimate(&self, table: &str, pred: &Pred) -> Option<(f64, bool)> {
+        let blocks = self.stats.get(table)?.iter().flat_map(|l| &l.blocks);
+        let (mut rows, mut kept, mut prunes) = (0.0, 0.0, false);
+        for b in blocks {
+            rows += b.rows as f64;
+            kept += b.rows as f64 * pred.selectivity(b);
+            prunes |= !pred.may_match(b);
+        }
+        Some((if rows > 0.0 { kept / rows } else { 1.0 }, prunes))
+    }
+
+    // A conjunct to push down to a table's scan, if it can be and it's
+    // worth it.
+    fn push(&self, table: &str, e: &Expr) -> Option<Pred> {
+        let p = self.sargable(e)?;
+        match self.estimate(table, &p) {
+            Some((sel, prunes)) if !prunes && sel > PUSH_SELECTIVITY => None,
+            _ => Some(p),
+        }
+    }
+
+    fn pushed(&self, table: &str, pred: &Expr) -> Pred {
+        let mut ps = conjuncts(pred)
+            .into_iter()
+            .filter_map(|c| self.push(table, c))
+            .collect::<Vec<_>>();
+        let sel = |p: &Pred| self.estimate(table, p).map_or(1.0, |(s, _)| s);
+        ps.sort_by(|a, b| sel(a).total_cmp(&sel(b)));
+        match ps.len() {
+            1 => ps.pop().unwrap(),
+            _ => Pred::And(ps),
+        }
+    }
+
+    fn path(&mut self, p: &Path) {
+        let Some(first) = p.0.first().filter(|w| !self.is_bound(w)) else {
+            return;
+        };
+        if let (Some(cols), [w]) = (&mut self.pred_cols, p.0.as_slice()) {
+            cols.insert(w.to_string());
+            return;
+        }
+        let cols = p.0.get(1).map(|w| BTreeSet::from([w.to_string()]));
+        self.read_table(first.to_string(), cols, Pred::TRUE);
+    }
+
+    fn read_table(&mut self, table: String, cols: Option<BTreeSet<String>>, pred: Pred) {
+        self.reads
+            .entry(table)
+            .or_default()
+            .push(Read { cols, pred });
+    }
+
+    fn walk(&mut self, e: &Expr) {
+        match e {
+            Expr::Path(p) => self.path(p),
+            Expr::Reify => self.reify = true,
+            Expr::Let(w, val, body) => {
+                self.walk(val);
+                self.bound.push(w.clone());
+                self.walk(body);
+                self.bound.pop();
+            }
+            Expr::Lam(ws, body) => {
+                let n = self.bound.len();
+                self.bound.extend(ws.iter().cloned());
+                self.walk(body);
+                self.bound.truncate(n);
+            }
+            // A query's path is looked up in its operand, not the context.
+            Expr::Query(x, _) => self.walk(x),
+            // An unbound name applied is a user-defined function, which
+            // reads only its arguments (see udf.rs in lang).
+            Expr::App(f, args) if self.table(f).is_some() => {
+                for a in args {
+                    self.walk(a);
+                }
+            }
+            Expr::Filter(..) => self.read(e, None),
+            Expr::Group(x, g) => {
+                let first = |p: &Path| p.0.first().map(|w| w.to_string());
+                let keys = g.keys.iter().filter_map(first);
+                let args = g.aggs.iter().filter_map(|a| first(&a.arg));
+                self.read(x, Some(keys.chain(args).collect()));
+            }
+            // Only a quote's holes are evaluated where it appears.
+            Expr::Quote(body) => {
+                for h in body.template().1 {
+                    self.walk(&h);
+                }
+            }
+            _ => {
+                for c in e.children() {
+                    self.walk(c);
+                }
+            }
+        }
+    }
+
+    // A table-valued Expr of which only some columns may be needed.
+    fn read(&mut self, e: &Expr, cols: Option<BTreeSet<String>>) {
+        if let Some(t) = self.table(e) {
+            if self.pred_cols.is_none() {
+                return self.read_table(t, cols, Pred::TRUE);
+            }
+        }
+        let Expr::Filter(x, pred) = e else {
+            return self.walk(e);
+        };
+        let outer = self.pred_cols.replace(BTreeSet::new());
+        self.walk(pred);
+        let pred_cols = std::mem::replace(&mut self.pred_cols, outer).unwrap_or_default();
+        let cols = cols.map(|c| &c | &pred_cols);
+        match self.table(x) {
+            Some(t) if self.pred_cols.is_none() => {
+                let pushed = self.pushed(&t, pred);
+                self.read_table(t, cols, pushed)
+            }
+            _ => self.read(x, cols),
+        }
+    }
+
+    // About how many rows a table-valued Expr has, if it's a table or a
+    // filter of one with stats.
+    fn rows(&self, e: &Expr) -> Option<f64> {
+        let t = self.table(e).or_else(|| match e {
+            Expr::Filter(x, _) | Expr::Sort(x, _) => self.table(x),
+            _ => None,
+        })?;
+        let total = self.stats.get(&t)?.iter().map(|l| l.rows()).sum::<u64>() as f64;
+        let Expr::Filter(_, pred) = e else {
+            return Some(total);
+        };
+        let sargable = conjuncts(pred).into_iter().filter_map(|c| self.sargable(c));
+        let sel = sargable.map(|p| self.estimate(&t, &p).map_or(1.0, |(s, _)| s));
+        Some(total * sel.product::<f64>())
+    }
+
+    // The side to build each join in an Expr over, in the order they're
+    // written.
+    fn joins(&self, e: &Expr, out: &mut Vec<Build>) {
+        if let Expr::Join(a, b, j) = e {
+            let smaller = match (self.rows(a), self.rows(b)) {
+                (Some(l), Some(r)) => l < r,
+                _ => false,
+            };
+            out.push(match j.kind == JoinKind::Inner && smaller {
+                true => Build::Left,
+                false => Build::Right,
+            });
+        }
+        for c in e.children() {
+            self.joins(c, out);
+        }
+    }
+
+    // The Expr with the pushed conjuncts of the only read of each of the
+    // single tables taken out of its filter.
+    fn residual(&mut self, e: &Expr, single: &BTreeSet<String>) -> Expr {
+        match e {
+            Expr::Let(w, val, body) => {
+                let val = self.residual(val, single);
+                self.bound.push(w.clone());
+                let body = self.residual(body, single);
+                self.bound.pop();
+                Expr::Let(w.clone(), Box::new(val), Box::new(body))
+            }
+            Expr::Lam(ws, body) => {
+                let n = self.bound.len();
+                self.bound.extend(ws.iter().cloned());
+                let body = self.residual(body, single);
+                self.bound.truncate(n);
+                Expr::Lam(ws.clone(), Box::new(body))
+            }
+            Expr::Quote(_) => e.clone(),
+            Expr::Filter(x, pred) if self.table(x).is_some_and(|t| single.contains(&t)) => {
+                let t = self.table(x).unwrap_or_default();
+                let rest = conjuncts(pred)
+                    .into_iter()
+                    .filter(|c| self.push(&t, c).is_none())
+                    .cloned()
+                    .reduce(|a, b| Expr::BinOp(PrimBinOp::And, Box::new(a), Box::new(b)));
+                match rest {
+                    Some(rest) => Expr::Filter(x.clone(), Box::new(rest)),
+                    None => (**x).clone(),
+                }
+            }
+            Expr::Filter(x, pred) => Expr::Filter(Box::new(self.residual(x, single)), pred.clone()),
+            _ => e.map_children(|c| self.residual(c, single)),
+        }
+    }
+}
@@ -0,0 +1,195 @@
+// A replica runs many evaluations at once: the thunks of the transactions
+// the watermark has released, and interactive reads. None of them may run
+// unboundedly, so the scheduler runs them cooperatively, a slice of fuel at
+// a time (see run() in lib.rs), and puts an evaluation that isn't done back
+// at the end of its class's queue.
+//
+// The two classes share the replica by weight: the favoured class, by
+// default transactions, gets up to `share` slices in a row while it has
+// work, and then the other class gets one if it has any. So a stream of
+// reads can't hold up transactions, which every replica must get through,
+// and a stream of transactions can't starve reads either. Within a class,
+// evaluations take turns in the order they were submitted.
+//
+// The metrics count what each class has queued, run and finished, and how
+// many slices its evaluations spent waiting in the queue while others ran.
+
+use crate::{Evaluator, StepResult};
+use std::collections::VecDeque;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Class {
+    #[default]
+    Txn,
+    Read,
+}
+
+impl Class {
+    fn other(self) -> Class {
+        match self {
+            Class::Txn => Class::Read,
+            Class::Read => Class::Txn,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct JobId(pub u64);
+
+// An evaluation that's done, with its Evaluator for its results.
+#[derive(Debug)]
+pub struct Finished {
+    pub id: JobId,
+    pub class: Class,
+    pub eval: Evaluator,
+    pub result: StepResult,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct QueueMetrics {
+    // Evaluations waiting for a slice.
+    pub queued: usize,
+    pub submitted: u64,
+    pub finished: u64,
+    pub slices: u64,
+    // Opcodes run in this class's slices.
+    pub fuel: u64,
+    // Slices that ran while one of this class's evaluations was queued, in
+    // sum over its evaluations.
+    pub waited: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Metrics {
+    pub txn: QueueMetrics,
+    pub read: QueueMetrics,
+}
+
+impl Metrics {
+    pub fn class(&self, class: Class) -> &QueueMetrics {
+        match class {
+            Class::Txn => &self.txn,
+            Class::Read => &self.read,
+        }
+    }
+
+    fn class_mut(&mut self, class: Class) -> &mut QueueMetrics {
+        match class {
+            Class::Txn => &mut self.txn,
+            Class::Read => &mut self.read,
+        }
+    }
+}
+
+struct Job {
+    id: JobId,
+    eval: Evaluator,
+}
+
+pub struct Scheduler {
+    quantum: usize,
+    favoured: Class,
+    share: usize,
+    // The slices the favoured class has had in a row.
+    streak: usize,
+    queues: [VecDeque<Job>; 2],
+    next_id: u64,
+    metrics: Metrics,
+}
+
+impl Scheduler {
+    // A scheduler that runs quantum opcodes a slice, favouring transactions
+    // 4 slices to 1.
+    pub fn new(quantum: usize) -> Scheduler {
+        Scheduler {
+            quantum: quantum.max(1),
+            favoured: Class::Txn,
+            share: 4,
+            streak: 0,
+            queues: [VecDeque::new(), VecDeque::new()],
+            next_id: 0,
+            metrics: Metrics::default(),
+        }
+    }
+
+    // Favour a class with share slices for each of the other's.
+    pub fn with_priority(mut self, favoured: Class, share: usize) -> Scheduler {
+        self.favoured = favoured;
+        self.share = share.max(1);
+        self
+    }
+
+    pub fn submit(&mut self, class: Class, eval: Evaluator) -> JobId {
+        let id = JobId(self.next_id);
+        self.next_id += 1;
+        self.queues[class.index()].push_back(Job { id, eval });
+        let m = self.metrics.class_mut(class);
+        m.queued += 1;
+        m.submitted += 1;
+        id
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.queues.iter().all(VecDeque::is_empty)
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        self.metrics
+    }
+
+    // The class whose turn it is, if any has work.
+    fn pick(&mut self) -> Option<Class> {
+        let (fav, other) = (self.favoured, self.favoured.other());
+        let has = |c: Class| !self.queues[c.index()].is_empty();
+        if has(fav) && (self.streak < self.share || !has(other)) {
+            self.streak += 1;
+            Some(fav)
+        } else if has(other) {
+            self.streak = 0;
+            Some(other)
+        } else {
+            None
+        }
+    }
+
+    // Run one slice of the evaluation whose turn it is, giving it back if
+    // that finished it.
+    pub fn step(&mut self) -> Option<Finished> {
+        let class = self.pick()?;
+        let mut job = self.queues[class.index()].pop_front()?;
+        let seq = job.eval.seq();
+        let result = job.eval.run(self.quantum);
+        for c in [Class::Txn, Class::Read] {
+            let waiting = self.queues[c.index()].len() as u64;
+            self.metrics.class_mut(c).waited += waiting;
+        }
+        let m = self.metrics.class_mut(class);
+        m.slices += 1;
+        m.fuel += (job.eval.seq() - seq) as u64;
+        if let StepResult::Pending = result {
+            self.queues[class.index()].push_back(job);
+            return None;
+        }
+        m.queued -= 1;
+        m.finished += 1;
+        Some(Finished {
+            id: job.id,
+            class,
+            eval: job.eval,
+            result,
+        })
+    }
+
+    // Run slices until every evaluation is done, in the order they finish.
+    pub fn run(&mut self) -> Vec<Finished> {
+        let mut done = Vec::new();
+        while !self.is_idle() {
+            done.extend(self.step());
+        }
+        done
+    }
+}
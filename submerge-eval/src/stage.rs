@@ -0,0 +1,153 @@
+// Staging. A staged Evaluator runs an Expr whose value may be code (see
+// quote.rs in lang), in which case the code is the next stage: it's checked
+// and run in turn, and so on up to a bound on the number of stages. Each
+// stage is a checked FO Expr, so the whole is Dyn-FO: a bounded sequence of
+// FO steps, each building the next.
+//
+// Before a stage runs, it must pass the FO checker, and every path it reads
+// (as plan.rs sees it) must be in the transaction's footprint, since a stage
+// built at run time can read what the thunk's own Expr never named. A stage
+// that fails either check, or can't be lowered (see lower.rs), or code that
+// would be a stage past the bound, is a fault like any other: every node
+// builds the same stages from the same data, so every node refuses the same
+// one.
+
+use crate::plan::reads;
+use serde::{Deserialize, Serialize};
+use submerge_base::err;
+use submerge_lang::{check_fo_with, Expr, Fault, FaultKind, Form, Path, Tab, Udfs, Vals, Vm};
+
+// A footprint indicates the set of keys that a given txn will read and write.
+// The writes will all get thunks written to them pointing to this txn. The
+// reads will all be considered dependencies of the txn, which it cannot execute
+// before the resolution-of. Both reads and writes can indicate "an entire
+// column", "an entire table", or "an entire database" (and may choose to do so
+// if there is no way to statically bound the read or write set), though doing
+// so will create an increasingly significant synchronization barrier, inhibiting
+// parallel execution through it.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct Footprint {
+    pub reads: Vec<Path>,
+    pub writes: Vec<Path>,
+}
+
+// How much of the database a footprint path denotes, by its length: the
+// empty path is the whole database, a path of one word a table, of two a
+// column, and anything longer a field nested within a column.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Grain {
+    Database,
+    Table,
+    Column,
+    Field,
+}
+
+impl Grain {
+    pub fn of(path: &Path) -> Grain {
+        match path.0.len() {
+            0 => Grain::Database,
+            1 => Grain::Table,
+            2 => Grain::Column,
+            _ => Grain::Field,
+        }
+    }
+}
+
+// Two paths overlap if one covers the other: a table overlaps each of its
+// columns, but two columns of a table don't overlap each other.
+pub fn overlaps(a: &Path, b: &Path) -> bool {
+    a.0.starts_with(&b.0) || b.0.starts_with(&a.0)
+}
+
+impl Footprint {
+    // A declared path covers every path under it; the empty path is the
+    // whole database.
+    pub fn allows_read(&self, path: &Path) -> bool {
+        self.reads.iter().any(|r| path.0.starts_with(&r.0))
+    }
+
+    pub fn allows_write(&self, path: &Path) -> bool {
+        self.writes.iter().any(|w| path.0.starts_with(&w.0))
+    }
+
+    // Whether two transactions' footprints order them: one writes somewhere
+    // the other reads or writes. Reads never conflict with reads.
+    pub fn conflicts(&self, other: &Footprint) -> bool {
+        let writes = |a: &Footprint, b: &Footprint| {
+            let touched = || b.reads.iter().chain(b.writes.iter());
+            a.writes.iter().any(|w| touched().any(|p| overlaps(w, p)))
+        };
+        writes(self, other) || writes(other, self)
+    }
+}
+
+pub(crate) fn is_code(v: &Vals) -> bool {
+    matches!(v, Vals::Rich(c) if c.form() == Form::CODE)
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub(crate) struct Stages {
+    ctx: Vec<Tab>,
+    foot: Footprint,
+    max: usize,
+    // How many stages have started.
+    n: usize,
+    #[serde(skip)]
+    udfs: Udfs,
+}
+
+impl Stages {
+    pub(crate) fn new(ctx: Vec<Tab>, foot: Footprint, max: usize) -> Stages {
+        Stages {
+            ctx,
+            foot,
+            max,
+            n: 0,
+            udfs: Udfs::default(),
+        }
+    }
+
+    pub(crate) fn set_udfs(&mut self, udfs: Udfs) {
+        self.udfs = udfs;
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        self.n
+    }
+
+    // A stage is started when the Stages are made, and one past the most
+    // only to be refused.
+    pub(crate) fn validate(&self, faulted: bool) -> submerge_base::Result<()> {
+        if self.n == 0 || self.n > self.max.saturating_add(1) || (self.n > self.max && !faulted) {
+            return Err(err(format!("stage {} of at most {}", self.n, self.max)));
+        }
+        Ok(())
+    }
+
+    // The fault of the stage that's starting.
+    pub(crate) fn refuse(&self, msg: impl std::fmt::Display) -> Fault {
+        Fault {
+            kind: FaultKind::Invalid,
+            pc: 0,
+            msg: format!("stage {}: {}", self.n, msg),
+        }
+    }
+
+    // A Vm for the next stage, if it can run.
+    pub(crate) fn next(&mut self, e: &Expr) -> Result<Vm, Fault> {
+        self.n += 1;
+        if self.n > self.max {
+            return Err(self.refuse(format!("more than {} stages", self.max)));
+        }
+        if let Err(err) = check_fo_with(e, None, &self.udfs) {
+            return Err(self.refuse(err));
+        }
+        if let Some(p) = reads(e).iter().find(|p| !self.foot.allows_read(p)) {
+            return Err(self.refuse(format!("{} is outside the footprint", p)));
+        }
+        match e.ops() {
+            Ok(ops) => Ok(Vm::new(ops, self.ctx.clone()).with_udfs(self.udfs.clone())),
+            Err(err) => Err(self.refuse(err.message())),
+        }
+    }
+}
@@ -0,0 +1,98 @@
+// Streaming results. An Evaluator keeps the rows its evaluation gives, and
+// results() hands them over a batch at a time, so a server can send a big
+// result to its client as it goes rather than copying the whole table into
+// a reply.
+//
+// Each pull gives a batch of the rows the evaluation has produced so far,
+// if there are any, and otherwise runs the evaluation on a slice of fuel at
+// a time, within its limits, until there are or it's done. The Evaluator
+// lets go of given rows once they're at least half of what it holds, so
+// each row is copied out of it a bounded number of times, and of all of
+// them once they've all been given. A fault or trap comes out as the
+// iterator's one error, after which it gives nothing more. Dropping the
+// iterator part way leaves the rest of the rows where they were, and
+// results() carries on from them, checkpointed or not.
+
+use crate::pipe::slice;
+use crate::{Evaluator, StepResult, BATCH_ROWS};
+use submerge_base::Result;
+use submerge_lang::Tab;
+
+// How many opcodes a pull runs at a time, between looking for rows.
+const FUEL: usize = 4096;
+
+// Some result rows: at most BATCH_ROWS, and never none.
+pub type TabBatch = Tab;
+
+pub struct Results<'a> {
+    eval: &'a mut Evaluator,
+    // Whether the evaluation has finished, and whether it failed, in which
+    // case its error has been given.
+    done: bool,
+    failed: bool,
+}
+
+impl Evaluator {
+    pub fn results(&mut self) -> Results<'_> {
+        Results {
+            eval: self,
+            done: false,
+            failed: false,
+        }
+    }
+
+    // The next batch of rows results() hasn't given, if there are any.
+    fn give(&mut self) -> Option<Result<TabBatch>> {
+        let (start, len) = (self.sent, self.new.len());
+        if start >= len {
+            return None;
+        }
+        let end = (start + BATCH_ROWS).min(len);
+        if end == len {
+            let rest = std::mem::take(&mut self.new);
+            self.sent = 0;
+            return match start {
+                0 => Some(Ok(rest)),
+                _ => Some(slice(&rest, start..end)),
+            };
+        }
+        let batch = slice(&self.new, start..end);
+        self.sent = end;
+        if end * 2 >= len {
+            match slice(&self.new, end..len) {
+                Ok(rest) => (self.new, self.sent) = (rest, 0),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        Some(batch)
+    }
+}
+
+impl Iterator for Results<'_> {
+    type Item = Result<TabBatch>;
+
+    fn next(&mut self) -> Option<Result<TabBatch>> {
+        if self.failed {
+            return None;
+        }
+        loop {
+            if let Some(batch) = self.eval.give() {
+                return Some(batch);
+            }
+            if self.done {
+                return None;
+            }
+            let failure = match self.eval.run(FUEL) {
+                StepResult::Pending => continue,
+                StepResult::Done(Ok(_)) => None,
+                StepResult::Done(Err(f)) => Some(f.into()),
+                StepResult::Failed(e) => Some(e),
+            };
+            self.done = true;
+            if let Some(e) = failure {
+                self.failed = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
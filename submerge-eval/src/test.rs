@@ -0,0 +1,1091 @@
+use crate::pipe::slice;
+use crate::{
+    blocks, collect, explain, plan, plan_with, reads, Agg, Cache, Class, Evaluator, Filter,
+    Footprint, Grain, Hybrid, Limits, Merge, Node, Order, Parallel, Project, QueueMetrics,
+    Resource, ResourceExhausted, RowStore, Scheduler, Source, Stats, StepResult, View, Views,
+    BATCH_ROWS,
+};
+use ordered_float::OrderedFloat;
+use submerge_base::ErrorKind;
+use submerge_coldb::{BlockStats, Cmp, ColStats, Key, LayerStats, Pred, Scan, BLOCK_ROWS};
+use submerge_lang::{
+    parse, AggOp, Aggregate, Build, Col, FaultKind, FloatMode, Form, Grouping, Opcode, Overflow,
+    Path, PrimBinOp, SortKey, Tab, TabBuilder, Ty, Udfs, Val, Vals, Vm, Word,
+};
+use test_log::test;
+
+fn word(s: &str) -> Word {
+    Word::new(s).unwrap()
+}
+
+fn ints(v: &[i64]) -> Vals {
+    Vals::I64s(v.to_vec())
+}
+
+// A Vm that adds 1 to t.x, n times, and zips the sum as a record field.
+fn adder(n: usize) -> Vm {
+    let mut ops = vec![Opcode::Path(Path(vec![word("t"), word("x")]))];
+    for _ in 0..n {
+        ops.push(Opcode::Literal(ints(&[1])));
+        ops.push(Opcode::PrimBinOp(PrimBinOp::Add));
+    }
+    ops.push(Opcode::Zip(vec![word("y")]));
+    let t = Tab::new(vec![Col::new(
+        word("t"),
+        Vals::zip(vec![(word("x"), ints(&[1, 2]))]).unwrap(),
+    )])
+    .unwrap();
+    Vm::new(ops, vec![t])
+}
+
+#[test]
+fn test_run_with_fuel() {
+    // 10 opcodes, run 3 at a time, resuming where each run stopped.
+    let mut eval = Evaluator::new(adder(4));
+    for seq in [3, 6, 9] {
+        assert!(matches!(eval.run(3), StepResult::Pending));
+        assert_eq!(eval.seq(), seq);
+        assert!(eval.rows().is_empty());
+    }
+    let StepResult::Done(Ok(v)) = eval.run(3) else {
+        panic!("not done")
+    };
+    assert_eq!(eval.seq(), 10);
+    assert_eq!(v, Vals::zip(vec![(word("y"), ints(&[5, 6]))]).unwrap());
+
+    // The record's rows are in the results, once, however often it's run.
+    let want = Tab::new(vec![Col::new(word("y"), ints(&[5, 6]))]).unwrap();
+    assert_eq!(eval.rows(), &want);
+    assert!(matches!(eval.run(3), StepResult::Done(Ok(_))));
+    assert_eq!(eval.rows(), &want);
+    assert_eq!(eval.seq(), 10);
+
+    // Any amount of fuel gets the same outcome.
+    let mut once = Evaluator::new(adder(4));
+    assert!(matches!(once.run(usize::MAX), StepResult::Done(Ok(x)) if x == v));
+    assert_eq!(once.rows(), &want);
+
+    // Faults are outcomes; traps are failures.
+    let div = vec![
+        Opcode::Literal(ints(&[1])),
+        Opcode::Literal(ints(&[0])),
+        Opcode::PrimBinOp(PrimBinOp::Div),
+    ];
+    let mut eval = Evaluator::new(Vm::new(div, vec![]));
+    assert!(matches!(eval.run(2), StepResult::Pending));
+    let StepResult::Done(Err(f)) = eval.run(2) else {
+        panic!("no fault")
+    };
+    assert_eq!((f.kind, f.pc), (FaultKind::DivideByZero, 2));
+    assert!(eval.rows().is_empty());
+    let add = vec![Opcode::PrimBinOp(PrimBinOp::Add)];
+    let mut eval = Evaluator::new(Vm::new(add, vec![]));
+    assert!(matches!(eval.run(1), StepResult::Failed(_)));
+}
+
+fn expr(src: &str) -> submerge_lang::Expr {
+    parse(src).unwrap().expr
+}
+
+fn scan(table: &str, cols: Option<&[&str]>, pred: Pred) -> Scan {
+    Scan {
+        table: table.into(),
+        cols: cols.map(|c| c.iter().map(|s| s.to_string()).collect()),
+        pred,
+    }
+}
+
+fn lt(col: &str, k: i64) -> Pred {
+    Pred::Cmp(col.into(), Cmp::Lt, Key::Int(k))
+}
+
+#[test]
+fn test_plan_pushdown() {
+    // Sargable conjuncts go to coldb, in either order, and the rest stays.
+    let p = plan(&expr(
+        "group(filter(t, x < 3 & 5 > y & x + y == z & name == \"a\"), [k], {s: sum(v)})",
+    ));
+    let pred = Pred::And(vec![
+        lt("x", 3),
+        lt("y", 5),
+        Pred::Cmp("name".into(), Cmp::Eq, Key::Bin(b"a".to_vec())),
+    ]);
+    let cols = ["k", "name", "v", "x", "y", "z"];
+    assert_eq!(p.scans, vec![scan("t", Some(&cols), pred)]);
+    assert_eq!(
+        p.expr,
+        expr("group(filter(t, x + y == z), [k], {s: sum(v)})")
+    );
+
+    // A filter that's all pushed down goes away, and a table's output is
+    // all its columns.
+    let p = plan(&expr("filter(t, x < 3)"));
+    assert_eq!(p.scans, vec![scan("t", None, lt("x", 3))]);
+    assert_eq!(p.expr, expr("t"));
+
+    // Paths name columns of tables in the context, and bound words aren't
+    // tables.
+    let p = plan(&expr("let u = 1 in t.a + t.b + s.c + u"));
+    assert_eq!(
+        p.scans,
+        vec![
+            scan("s", Some(&["c"]), Pred::TRUE),
+            scan("t", Some(&["a", "b"]), Pred::TRUE),
+        ]
+    );
+
+    // A table read twice is scanned for what either read needs, and keeps
+    // its filters.
+    let e = expr("merge(filter(t, x < 3), filter(t, x < 5 & y))");
+    let p = plan(&e);
+    let pred = Pred::Or(vec![lt("x", 3), lt("x", 5)]);
+    assert_eq!(p.scans, vec![scan("t", None, pred)]);
+    assert_eq!(p.expr, e);
+    let p = plan(&expr("merge(filter(t, x < 3), t.y)"));
+    assert_eq!(p.scans, vec![scan("t", None, Pred::TRUE)]);
+
+    // A reify can read anything.
+    let e = expr("merge(filter(t, x < 3), reify)");
+    assert_eq!(plan(&e).scans, vec![scan("t", None, Pred::TRUE)]);
+    assert_eq!(plan(&e).expr, e);
+}
+
+#[test]
+fn test_plan_stats() {
+    let block = |rows, cols: &[(&str, i64, i64)]| BlockStats {
+        rows,
+        cols: cols
+            .iter()
+            .map(|(c, min, max)| {
+                let s = ColStats {
+                    min: Key::Int(*min),
+                    max: Key::Int(*max),
+                    distinct: (max - min + 1) as u64,
+                };
+                (c.to_string(), s)
+            })
+            .collect(),
+    };
+    let stats: Stats = [
+        (
+            "t".to_string(),
+            vec![LayerStats {
+                blocks: vec![block(1000, &[("x", 0, 99), ("y", 0, 9)])],
+            }],
+        ),
+        (
+            "s".to_string(),
+            vec![LayerStats {
+                blocks: vec![block(20, &[("x", 0, 9)])],
+            }],
+        ),
+    ]
+    .into();
+
+    // A conjunct that keeps nearly every row and rules out no block isn't
+    // worth pushing down, and the rest go most selective first.
+    let p = plan_with(&expr("filter(t, x < 99 & y < 5 & x < 10)"), &stats);
+    assert_eq!(p.scans[0].pred, Pred::And(vec![lt("x", 10), lt("y", 5)]));
+    assert_eq!(p.expr, expr("filter(t, x < 99)"));
+    assert_eq!(p.stats.keys().collect::<Vec<_>>(), vec!["t"]);
+
+    // One that rules out every block is pushed down however it estimates.
+    let p = plan_with(&expr("filter(t, y > 9)"), &stats);
+    assert_eq!(p.scans[0].pred, Pred::Cmp("y".into(), Cmp::Gt, Key::Int(9)));
+    assert_eq!(p.expr, expr("t"));
+
+    // Without stats, everything sargable is pushed down as written.
+    let p = plan(&expr("filter(t, x < 99 & y < 5 & x < 10)"));
+    let pred = Pred::And(vec![lt("x", 99), lt("y", 5), lt("x", 10)]);
+    assert_eq!(p.scans[0].pred, pred);
+    assert!(p.joins.is_empty());
+
+    // An inner join builds over its smaller side; a left join always builds
+    // over its right.
+    let joins = |e: &str| plan_with(&expr(e), &stats).joins;
+    assert_eq!(joins("join(s, t, [x = x])"), vec![Build::Left]);
+    assert_eq!(joins("join(t, s, [x = x])"), vec![Build::Right]);
+    assert_eq!(
+        joins("join(filter(t, x < 1), s, [x = x])"),
+        vec![Build::Left]
+    );
+    assert_eq!(joins("left_join(s, t, [x = x])"), vec![Build::Right]);
+    assert_eq!(
+        explain(&expr("join(s, t, [x = x])"), &stats)
+            .pipeline
+            .detail,
+        "[x = x], build left"
+    );
+}
+
+fn grouping(aggs: &[(&str, AggOp)]) -> Grouping {
+    let agg = |(name, op): &(&str, AggOp)| Aggregate {
+        name: word(name),
+        op: *op,
+        arg: Path(vec![word("v")]),
+    };
+    Grouping {
+        keys: vec![Path(vec![word("k")])],
+        aggs: aggs.iter().map(agg).collect(),
+    }
+}
+
+#[test]
+fn test_pipeline() {
+    let n = 5000_i64;
+    let t = TabBuilder::new()
+        .col("k")
+        .i64s((0..n).map(|i| i % 7))
+        .col("v")
+        .i64s(0..n)
+        .col("x")
+        .f64s((0..n).map(|i| i as f64))
+        .build()
+        .unwrap();
+    let pred = vec![
+        Opcode::Path(Path(vec![word("v")])),
+        Opcode::Literal(ints(&[4000])),
+        Opcode::PrimBinOp(PrimBinOp::Lt),
+    ];
+    let scan = || {
+        let src = Box::new(Source::new(t.clone()));
+        let filter = Box::new(Filter::new(src, pred.clone()));
+        let paths = vec![Path(vec![word("k")]), Path(vec![word("v")])];
+        Box::new(Project::new(filter, paths))
+    };
+
+    // Batches are full until the filter runs out of rows.
+    let mut node = scan();
+    let mut lens = Vec::new();
+    while let Some(batch) = node.next().unwrap() {
+        assert_eq!(batch.cols().len(), 2);
+        lens.push(batch.len());
+    }
+    assert_eq!(
+        lens,
+        vec![BATCH_ROWS, BATCH_ROWS, BATCH_ROWS, 4000 - 3 * BATCH_ROWS]
+    );
+    let kept = t.filter(&pred).unwrap();
+    assert_eq!(collect(&mut *scan()).unwrap().len(), kept.len());
+
+    // Aggregating batch by batch gives the same groups as the whole table.
+    for aggs in [
+        &[
+            ("n", AggOp::Count),
+            ("s", AggOp::Sum),
+            ("lo", AggOp::Min),
+            ("hi", AggOp::Max),
+        ][..],
+        &[("n", AggOp::Count), ("m", AggOp::Mean)][..],
+    ] {
+        let g = grouping(aggs);
+        let whole = kept.group_by(&g, Overflow::Wrap).unwrap();
+        let mut agg = Agg::new(scan(), g, Overflow::Wrap);
+        assert_eq!(collect(&mut agg).unwrap(), whole);
+        assert!(agg.next().unwrap().is_none());
+    }
+
+    // No rows make no groups, but still have the groups' columns.
+    let src = Box::new(Source::new(Tab::default()));
+    let mut agg = Agg::new(src, grouping(&[("n", AggOp::Count)]), Overflow::Wrap);
+    assert!(agg.next().unwrap().is_none());
+    let none = vec![
+        Opcode::Path(Path(vec![word("v")])),
+        Opcode::Literal(ints(&[0])),
+        Opcode::PrimBinOp(PrimBinOp::Lt),
+    ];
+    let empty = t.filter(&none).unwrap();
+    let filtered = || Box::new(Filter::new(Box::new(Source::new(t.clone())), none.clone()));
+    assert_eq!(collect(&mut *filtered()).unwrap(), empty);
+    let g = grouping(&[("n", AggOp::Count), ("m", AggOp::Mean)]);
+    let mut agg = Agg::new(filtered(), g.clone(), Overflow::Wrap);
+    let groups = collect(&mut agg).unwrap();
+    assert_eq!(groups, empty.group_by(&g, Overflow::Wrap).unwrap());
+    assert_eq!(groups.cols().len(), 3);
+}
+
+#[test]
+fn test_pipeline_means() {
+    // Means of each kind of column, some of them null, over enough rows
+    // that they're merged from several batches' sums and counts.
+    let n = 3000_i64;
+    let mut t = TabBuilder::new()
+        .col("k")
+        .i64s([])
+        .col("i")
+        .i64s([])
+        .col("d")
+        .form(Form::decimal(9, 2).unwrap())
+        .i64s([])
+        .col("f")
+        .f64s([])
+        .col("b")
+        .bits([])
+        .build()
+        .unwrap();
+    for i in 0..n {
+        let k = i % 5;
+        let int = |v: i64| if k == 4 { Val::Null } else { Val::Int(v) };
+        let row = vec![
+            Val::Int(k),
+            int(i * 7 - 5000),
+            Val::Int(i * 13 % 1000),
+            Val::Flo(OrderedFloat((i % 17) as f64)),
+            Val::Bit(i % 3 == 0),
+        ];
+        t.push_row(row).unwrap();
+    }
+    let mean = |name: &str, arg: &str| Aggregate {
+        name: word(name),
+        op: AggOp::Mean,
+        arg: Path(vec![word(arg)]),
+    };
+    let g = Grouping {
+        keys: vec![Path(vec![word("k")])],
+        aggs: vec![
+            mean("i", "i"),
+            mean("d", "d"),
+            mean("f", "f"),
+            mean("b", "b"),
+            // A mean that has to go by another name than its sum's.
+            mean("_sum0", "i"),
+        ],
+    };
+    let whole = t.group_by(&g, Overflow::Check).unwrap();
+    let mut agg = Agg::new(Box::new(Source::new(t.clone())), g.clone(), Overflow::Check);
+    assert_eq!(collect(&mut agg).unwrap(), whole);
+    let merge = Merge::Group(g, Overflow::Check);
+    let part = |i: i64| slice(&t, (i * 1000) as usize..((i + 1) * 1000) as usize).unwrap();
+    let parts = (0..n / 1000).map(part).collect();
+    let build = |src: Box<dyn Node>| src;
+    let par = Parallel::new(3).run(parts, &build, &merge).unwrap();
+    assert_eq!(par, whole);
+}
+
+#[test]
+fn test_parallel() {
+    let n = BLOCK_ROWS + 10;
+    let t = TabBuilder::new()
+        .col("v")
+        .i64s(0..n as i64)
+        .build()
+        .unwrap();
+    let lens = blocks(&t).unwrap().iter().map(Tab::len).collect::<Vec<_>>();
+    assert_eq!(lens, vec![BLOCK_ROWS, 10]);
+
+    // Partitions whose float sums depend on the order they're added in.
+    let part = |i: i64| {
+        TabBuilder::new()
+            .col("k")
+            .i64s((0..300).map(|j| (i + j) % 5))
+            .col("v")
+            .f64s((0..300).map(|j| 10f64.powi(((i * 7 + j) % 40) as i32 - 20)))
+            .build()
+            .unwrap()
+    };
+    let parts = (0..16).map(part).collect::<Vec<_>>();
+    let pred = vec![
+        Opcode::Path(Path(vec![word("k")])),
+        Opcode::Literal(ints(&[4])),
+        Opcode::PrimBinOp(PrimBinOp::Lt),
+    ];
+    let build = |src: Box<dyn Node>| -> Box<dyn Node> { Box::new(Filter::new(src, pred.clone())) };
+    let g = grouping(&[("n", AggOp::Count), ("s", AggOp::Sum), ("hi", AggOp::Max)]);
+    let sort = vec![SortKey {
+        path: Path(vec![word("v")]),
+        desc: true,
+    }];
+    for merge in [
+        Merge::Concat,
+        Merge::Group(g, Overflow::Wrap),
+        Merge::Sort(sort),
+    ] {
+        // A fixed order gives the same result with any number of threads.
+        let one = Parallel::new(1).run(parts.clone(), &build, &merge).unwrap();
+        for threads in [2, 3, 8] {
+            let par = Parallel::new(threads);
+            assert_eq!(par.run(parts.clone(), &build, &merge).unwrap(), one);
+            let any = par.with_order(Order::Arrival);
+            assert_eq!(
+                any.run(parts.clone(), &build, &merge).unwrap().len(),
+                one.len()
+            );
+        }
+    }
+
+    // Partitions that filter out every row still group, to no groups.
+    let none = vec![
+        Opcode::Path(Path(vec![word("k")])),
+        Opcode::Literal(ints(&[0])),
+        Opcode::PrimBinOp(PrimBinOp::Lt),
+    ];
+    let build = |src: Box<dyn Node>| -> Box<dyn Node> { Box::new(Filter::new(src, none.clone())) };
+    let g = grouping(&[("n", AggOp::Count), ("m", AggOp::Mean)]);
+    let merge = Merge::Group(g.clone(), Overflow::Wrap);
+    let groups = Parallel::new(4).run(parts.clone(), &build, &merge).unwrap();
+    let empty = parts[0].filter(&none).unwrap();
+    assert_eq!(groups, empty.group_by(&g, Overflow::Wrap).unwrap());
+
+    // The first failing partition's error stops the run.
+    let bad = vec![Opcode::Path(Path(vec![word("nope")]))];
+    let build = |src: Box<dyn Node>| -> Box<dyn Node> { Box::new(Filter::new(src, bad.clone())) };
+    let res = Parallel::new(4).run(parts, &build, &Merge::Concat);
+    assert!(res.is_err());
+}
+
+// A row store holding a table's unflushed rows.
+struct Rows(Tab);
+
+impl RowStore for Rows {
+    fn rows(&self, _: &Word) -> submerge_base::Result<Tab> {
+        Ok(self.0.clone())
+    }
+}
+
+#[test]
+fn test_hybrid() {
+    let part = |ids: &[i64], ts: &[i64], vs: &[i64]| {
+        TabBuilder::new()
+            .col("id")
+            .i64s(ids.iter().copied())
+            .col("ts")
+            .i64s(ts.iter().copied())
+            .col("v")
+            .i64s(vs.iter().copied())
+            .build()
+            .unwrap()
+    };
+    let layers = vec![
+        part(&[1, 2, 3], &[1, 1, 1], &[10, 20, 30]),
+        part(&[2, 4], &[2, 2], &[21, 40]),
+    ];
+    let rows = Rows(part(&[3, 1, 5], &[3, 0, 3], &[31, 11, 50]));
+    let t = word("t");
+    let key = [Path(vec![word("id")])];
+    let ts = Path(vec![word("ts")]);
+
+    // Each key's latest version, layers' rows first; the row store's older
+    // version of 1 loses to the first layer's.
+    let mut scan = Hybrid::new(&t, layers.clone(), &rows, &key, &ts).unwrap();
+    let got = collect(&mut scan).unwrap();
+    assert_eq!(
+        got,
+        part(&[1, 2, 4, 3, 5], &[1, 2, 2, 3, 3], &[10, 21, 40, 31, 50])
+    );
+
+    // On a tie the row store wins, then the later layer; an empty row store
+    // leaves just the layers.
+    let rows = Rows(part(&[4], &[2], &[41]));
+    let mut scan = Hybrid::new(&t, layers.clone(), &rows, &key, &ts).unwrap();
+    let got = collect(&mut scan).unwrap();
+    assert_eq!(got, part(&[1, 3, 2, 4], &[1, 1, 2, 2], &[10, 30, 21, 41]));
+    let mut scan = Hybrid::new(&t, layers, &Rows(Tab::default()), &key, &ts).unwrap();
+    assert_eq!(collect(&mut scan).unwrap().len(), 4);
+
+    // Many versions of few keys come out in full batches.
+    let n = 3 * BATCH_ROWS as i64;
+    let big = |ts: i64| {
+        let ids = (0..n).collect::<Vec<_>>();
+        part(&ids, &vec![ts; n as usize], &ids)
+    };
+    let rows = Rows(big(2));
+    let mut scan = Hybrid::new(&t, vec![big(1), big(1)], &rows, &key, &ts).unwrap();
+    let mut lens = Vec::new();
+    while let Some(batch) = scan.next().unwrap() {
+        lens.push(batch.len());
+    }
+    assert_eq!(lens, vec![BATCH_ROWS; 3]);
+
+    // Parts must agree on their columns, and have the key and time.
+    let odd = TabBuilder::new().col("id").i64s([9]).build().unwrap();
+    assert!(Hybrid::new(&t, vec![odd.clone()], &rows, &key, &ts).is_err());
+    assert!(Hybrid::new(&t, vec![], &Rows(odd), &key, &ts).is_err());
+}
+
+#[test]
+fn test_staged() {
+    let t = Tab::new(vec![Col::new(
+        word("t"),
+        Vals::zip(vec![(word("n"), ints(&[1, 2, 3]))]).unwrap(),
+    )])
+    .unwrap();
+    let foot = |reads: &[&str]| Footprint {
+        reads: reads
+            .iter()
+            .map(|r| Path(r.split('.').map(word).collect()))
+            .collect(),
+        writes: vec![],
+    };
+    let staged = |src: &str, reads: &[&str], max: usize| {
+        let e = expr(src);
+        Evaluator::staged(&e, vec![t.clone()], foot(reads), max)
+    };
+    let done = |mut eval: Evaluator| loop {
+        match eval.run(1) {
+            StepResult::Pending => continue,
+            StepResult::Done(outcome) => return (eval, outcome),
+            StepResult::Failed(e) => panic!("{:?}", e),
+        }
+    };
+
+    // The first stage builds the second, which gives the result; the fuel
+    // runs both, an opcode at a time.
+    let src = "let k = 10 in quote(t.n + splice(k))";
+    let (eval, outcome) = done(staged(src, &["t"], 2));
+    assert_eq!(outcome, Ok(ints(&[11, 12, 13])));
+    assert_eq!(eval.stage(), Some(2));
+    assert_eq!(eval.seq(), 5);
+    assert_eq!(eval.rows().len(), 3);
+
+    // A stage past the bound, or reading outside the footprint, is a fault.
+    let refused = |src: &str, reads: &[&str], max: usize| {
+        let (eval, outcome) = done(staged(src, reads, max));
+        let f = outcome.unwrap_err();
+        assert_eq!(f.kind, FaultKind::Invalid);
+        (eval.stage(), f.msg)
+    };
+    let (stage, msg) = refused(src, &["t"], 1);
+    assert_eq!(stage, Some(2));
+    assert!(msg.contains("more than 1 stages"), "{}", msg);
+    let (stage, msg) = refused(src, &["t.m"], 2);
+    assert_eq!(stage, Some(2));
+    assert!(msg.contains("t.n is outside the footprint"), "{}", msg);
+    let (stage, _) = refused("{a: t.n, b: u.x}", &["t.n"], 2);
+    assert_eq!(stage, Some(1));
+    let (stage, _) = refused("if true then 1 else 2", &[], 2);
+    assert_eq!(stage, Some(1));
+
+    // Unstaged, code is just a value.
+    let e = expr(src);
+    let mut eval = Evaluator::new(Vm::new(e.ops().unwrap(), vec![t.clone()]));
+    let StepResult::Done(Ok(code)) = eval.run(100) else {
+        panic!("not done")
+    };
+    assert_eq!(
+        submerge_lang::Expr::from_code(&code).unwrap(),
+        expr("t.n + 10")
+    );
+
+    // The float mode carries over to every stage.
+    let run = |mode| {
+        let eval = staged("quote(sin(1.5))", &[], 2).with_float_mode(mode);
+        done(eval).1
+    };
+    assert!(run(FloatMode::Native).is_ok());
+    let f = run(FloatMode::Deterministic).unwrap_err();
+    assert_eq!(f.kind, FaultKind::Unportable);
+}
+
+#[test]
+fn test_udf_calls() {
+    let mut udfs = Udfs::new();
+    udfs.register("double", vec![Ty::int()], Ty::int(), |args| {
+        match &args[0] {
+            Vals::I64s(x) => Ok(Vals::I64s(x.iter().map(|x| x * 2).collect())),
+            _ => unreachable!(),
+        }
+    })
+    .unwrap();
+    let path = |s: &str| Path(s.split('.').map(word).collect());
+
+    // A call reads only its arguments.
+    assert_eq!(reads(&expr("double(t.n)")), vec![path("t.n")]);
+
+    // Every stage may call the registry's functions, the first included.
+    let t = Tab::new(vec![Col::new(
+        word("t"),
+        Vals::zip(vec![(word("n"), ints(&[1, 2, 3]))]).unwrap(),
+    )])
+    .unwrap();
+    let foot = Footprint {
+        reads: vec![path("t")],
+        writes: vec![],
+    };
+    let e = expr("let k = double(2) in quote(double(t.n) + splice(k))");
+    let mut eval = Evaluator::staged_with(&e, vec![t.clone()], foot.clone(), 2, udfs.clone());
+    let StepResult::Done(outcome) = eval.run(100) else {
+        panic!("not done")
+    };
+    assert_eq!(outcome, Ok(ints(&[6, 8, 10])));
+
+    // Without them, the first stage isn't FO.
+    let mut eval = Evaluator::staged(&e, vec![t.clone()], foot, 2);
+    let StepResult::Done(outcome) = eval.run(100) else {
+        panic!("not done")
+    };
+    let f = outcome.unwrap_err();
+    assert_eq!(f.kind, FaultKind::Invalid);
+    assert_eq!(eval.stage(), Some(1));
+
+    // The registry isn't checkpointed; resuming takes it again.
+    let e = expr("double(t.n)");
+    let mut eval = Evaluator::new(Vm::new(e.ops().unwrap(), vec![t]));
+    assert!(matches!(eval.run(1), StepResult::Pending));
+    let saved = eval.checkpoint().unwrap();
+    let mut eval = Evaluator::resume(&saved).unwrap().with_udfs(udfs);
+    let StepResult::Done(outcome) = eval.run(100) else {
+        panic!("not done")
+    };
+    assert_eq!(outcome, Ok(ints(&[2, 4, 6])));
+}
+
+#[test]
+fn test_checkpoint() {
+    let t = Tab::new(vec![Col::new(
+        word("t"),
+        Vals::zip(vec![(word("n"), ints(&[1, 2, 3]))]).unwrap(),
+    )])
+    .unwrap();
+    let foot = Footprint {
+        reads: vec![Path(vec![word("t")])],
+        writes: vec![],
+    };
+    let e = expr("let k = 10 in quote(t.n + splice(k))");
+    let limits = Limits {
+        ops: Some(100),
+        ..Limits::default()
+    };
+    let start = || Evaluator::staged(&e, vec![t.clone()], foot.clone(), 2).with_limits(limits);
+
+    // Saving and resuming after every opcode, through both stages, gets
+    // what running straight through does.
+    let mut whole = start();
+    let StepResult::Done(want) = whole.run(100) else {
+        panic!("not done")
+    };
+    let mut eval = start();
+    let got = loop {
+        let resumed = Evaluator::resume(&eval.checkpoint().unwrap()).unwrap();
+        assert_eq!(resumed, eval);
+        eval = resumed;
+        match eval.run(1) {
+            StepResult::Pending => continue,
+            StepResult::Done(outcome) => break outcome,
+            StepResult::Failed(e) => panic!("{:?}", e),
+        }
+    };
+    assert_eq!(got, want);
+    assert_eq!(eval, whole);
+
+    // Other versions, and bytes that aren't a checkpoint, are refused.
+    let mut bytes = start().checkpoint().unwrap();
+    assert!(Evaluator::resume(&bytes[..bytes.len() / 2])
+        .unwrap_err()
+        .is_corruption());
+    assert!(Evaluator::resume(&[]).unwrap_err().is_corruption());
+    bytes[0] += 1;
+    assert_eq!(
+        Evaluator::resume(&bytes).unwrap_err().kind(),
+        ErrorKind::Format
+    );
+
+    // So are checkpoints that decode to a state no Evaluator gets into: a
+    // Vm with no frames, or more opcodes run than the limit allows.
+    let frameless = (
+        Vec::<Opcode>::new(),
+        Vec::<()>::new(),
+        Overflow::default(),
+        FloatMode::default(),
+        None::<()>,
+    );
+    let mut eval = start();
+    eval.cur = rmp_serde::from_slice(&rmp_serde::to_vec(&frameless).unwrap()).unwrap();
+    let err = Evaluator::resume(&eval.checkpoint().unwrap()).unwrap_err();
+    assert!(err.is_corruption());
+    assert!(matches!(eval.run(1), StepResult::Failed(_)));
+    let mut eval = start();
+    eval.seq = 101;
+    let err = Evaluator::resume(&eval.checkpoint().unwrap()).unwrap_err();
+    assert!(err.is_corruption());
+
+    // A pipeline's source carries on from its position.
+    let tab = Tab::new(vec![Col::new(word("n"), ints(&[1, 2, 3]))]).unwrap();
+    let mut src = Source::new(tab.clone());
+    let first = src.next().unwrap();
+    assert_eq!(first.as_ref(), Some(&tab));
+    assert_eq!(src.pos(), 3);
+    assert_eq!(Source::at(tab.clone(), src.pos()).next().unwrap(), None);
+    assert_eq!(collect(&mut Source::at(tab.clone(), 0)).unwrap(), tab);
+}
+
+#[test]
+fn test_results() {
+    let n = 2 * BATCH_ROWS as i64 + 100;
+    let vals = Vals::zip(vec![
+        (word("k"), ints(&(0..n).collect::<Vec<_>>())),
+        (word("v"), ints(&(0..n).map(|i| i * 3).collect::<Vec<_>>())),
+    ])
+    .unwrap();
+    let t = Tab::new(vec![Col::new(word("t"), vals)]).unwrap();
+    let start = || Evaluator::new(Vm::new(expr("t").ops().unwrap(), vec![t.clone()]));
+    let mut whole = start();
+    assert!(matches!(whole.run(usize::MAX), StepResult::Done(Ok(_))));
+    let want = whole.rows().clone();
+
+    // The first pull runs the evaluation; the rows come out in full batches,
+    // and leave the Evaluator as they go.
+    let mut eval = start();
+    let mut got = Tab::default();
+    let mut lens = Vec::new();
+    let mut held = Vec::new();
+    while let Some(batch) = eval.results().next() {
+        let batch = batch.unwrap();
+        held.push(eval.rows().len());
+        lens.push(batch.len());
+        match got.cols().is_empty() {
+            true => got = batch,
+            false => crate::pipe::append(&mut got, &batch).unwrap(),
+        }
+    }
+    assert_eq!(lens, vec![BATCH_ROWS, BATCH_ROWS, 100]);
+    assert_eq!(held, vec![2 * BATCH_ROWS + 100, 100, 0]);
+    assert_eq!(got, want);
+    assert!(eval.rows().is_empty());
+    assert!(eval.results().next().is_none());
+
+    // A stream dropped part way carries on where it stopped, across a
+    // checkpoint too.
+    let mut eval = start();
+    let first = eval.results().next().unwrap().unwrap();
+    assert_eq!(first.len(), BATCH_ROWS);
+    let mut eval = Evaluator::resume(&eval.checkpoint().unwrap()).unwrap();
+    let rest = eval.results().map(|b| b.unwrap().len()).collect::<Vec<_>>();
+    assert_eq!(rest, vec![BATCH_ROWS, 100]);
+
+    // A fault is the stream's one error.
+    let div = vec![
+        Opcode::Literal(ints(&[1])),
+        Opcode::Literal(ints(&[0])),
+        Opcode::PrimBinOp(PrimBinOp::Div),
+    ];
+    let mut eval = Evaluator::new(Vm::new(div, vec![]));
+    let mut results = eval.results();
+    assert!(results.next().unwrap().is_err());
+    assert!(results.next().is_none());
+}
+
+#[test]
+fn test_scheduler() {
+    // Two transactions of 5 slices each, and a read of 2.
+    let mut sched = Scheduler::new(2);
+    let t1 = sched.submit(Class::Txn, Evaluator::new(adder(4)));
+    let t2 = sched.submit(Class::Txn, Evaluator::new(adder(4)));
+    let r = sched.submit(Class::Read, Evaluator::new(adder(1)));
+    assert_eq!(sched.metrics().txn.queued, 2);
+
+    // Transactions get 4 slices for each of the read's, taking turns.
+    let done = sched.run();
+    let ids = done.iter().map(|f| (f.id, f.class)).collect::<Vec<_>>();
+    assert_eq!(
+        ids,
+        vec![(r, Class::Read), (t1, Class::Txn), (t2, Class::Txn)]
+    );
+    assert!(matches!(done[0].result, StepResult::Done(Ok(_))));
+    let want = Tab::new(vec![Col::new(word("y"), ints(&[2, 3]))]).unwrap();
+    assert_eq!(done[0].eval.rows(), &want);
+    assert!(sched.is_idle() && sched.step().is_none());
+    let m = sched.metrics();
+    let txn = QueueMetrics {
+        queued: 0,
+        submitted: 2,
+        finished: 2,
+        slices: 10,
+        fuel: 20,
+        waited: 13,
+    };
+    assert_eq!(m.txn, txn);
+    assert_eq!((m.read.slices, m.read.fuel, m.read.waited), (2, 4, 8));
+
+    // Favouring reads one to one alternates, and a class with nothing
+    // queued doesn't hold up the other.
+    let mut sched = Scheduler::new(2).with_priority(Class::Read, 1);
+    let t = sched.submit(Class::Txn, Evaluator::new(adder(1)));
+    let r1 = sched.submit(Class::Read, Evaluator::new(adder(2)));
+    let r2 = sched.submit(Class::Read, Evaluator::new(adder(4)));
+    let order = sched.run().iter().map(|f| f.id).collect::<Vec<_>>();
+    assert_eq!(order, vec![t, r1, r2]);
+    assert_eq!(sched.metrics().read.slices, 8);
+    assert_eq!(sched.metrics().class(Class::Txn).slices, 2);
+}
+
+#[test]
+fn test_limits() {
+    // adder(4) runs 10 opcodes, holds at most 24 bytes (t.x and a literal)
+    // and gives 2 rows; each limit is met at exactly that.
+    let limited = |limits: Limits, fuel: usize| {
+        let mut eval = Evaluator::new(adder(4)).with_limits(limits);
+        loop {
+            match eval.run(fuel) {
+                StepResult::Pending => continue,
+                StepResult::Done(outcome) => return (eval, outcome),
+                StepResult::Failed(e) => panic!("{:?}", e),
+            }
+        }
+    };
+    let exact = Limits {
+        ops: Some(10),
+        bytes: Some(24),
+        rows: Some(2),
+    };
+    for fuel in [1, 3, 100] {
+        let (eval, outcome) = limited(exact, fuel);
+        assert!(outcome.is_ok());
+        assert_eq!((eval.seq(), eval.peak()), (10, 24));
+        assert_eq!(eval.exhausted(), None);
+    }
+
+    let past = |resource, limit| ResourceExhausted { resource, limit };
+    let cases = [
+        (
+            Limits {
+                ops: Some(9),
+                ..exact
+            },
+            past(Resource::Ops, 9),
+            9,
+        ),
+        (
+            Limits {
+                bytes: Some(23),
+                ..exact
+            },
+            past(Resource::Bytes, 23),
+            2,
+        ),
+        (
+            Limits {
+                rows: Some(1),
+                ..exact
+            },
+            past(Resource::Rows, 1),
+            10,
+        ),
+    ];
+    for (limits, want, seq) in cases {
+        for fuel in [1, 4, 100] {
+            let (eval, outcome) = limited(limits, fuel);
+            let f = outcome.unwrap_err();
+            assert_eq!(f.kind, FaultKind::ResourceExhausted);
+            assert_eq!(f.msg, want.to_string());
+            assert_eq!(eval.exhausted(), Some(&want));
+            assert_eq!(eval.seq(), seq);
+            // It stays exhausted.
+            let mut eval = eval;
+            assert!(matches!(eval.run(fuel), StepResult::Done(Err(g)) if g == f));
+        }
+    }
+}
+
+#[test]
+fn test_explain() {
+    let e = expr("group(filter(t, v < 3 & v + k > 0), [k], {n: count(v)})");
+    let block = |rows, min, max| BlockStats {
+        rows,
+        cols: [(
+            "v".to_string(),
+            ColStats {
+                min: Key::Int(min),
+                max: Key::Int(max),
+                distinct: (max - min + 1) as u64,
+            },
+        )]
+        .into(),
+    };
+    // The second block's values are all too big to keep.
+    let layers = vec![LayerStats {
+        blocks: vec![block(1000, 0, 10), block(500, 5, 9)],
+    }];
+    let x = explain(&e, &[("t".to_string(), layers)].into());
+    assert_eq!(x.scans.len(), 1);
+    assert_eq!(x.scans[0].layers[0].blocks, vec![0]);
+    assert_eq!(x.scans[0].rows.map(f64::round), Some(300.0));
+    assert_eq!(
+        x.render(),
+        "scan t: cols [k, v], pushed v < 3\n\
+         \x20 stats: 1500 rows in 2 blocks; v 0 to 10, ~11 distinct\n\
+         \x20 layer 0: 1 of 2 blocks, ~300 of 1500 rows\n\
+         \x20 ~300 rows\n\
+         group [k] {n: count(v)}\n\
+         \x20 filter v + k > 0\n\
+         \x20   scan t\n"
+    );
+
+    // Without stats there's no estimate, and what isn't a table operator
+    // is evaluated whole.
+    let x = explain(&expr("{s: sort(t, [a desc])}"), &Default::default());
+    assert_eq!(x.scans[0].rows, None);
+    assert_eq!(
+        x.render(),
+        "scan t\n\
+         eval {s: sort(t, [a desc])}\n"
+    );
+}
+
+#[test]
+fn test_views() {
+    let layer = |k: &[i64], v: &[i64]| {
+        Tab::new(vec![
+            Col::new(word("k"), ints(k)),
+            Col::new(word("v"), ints(v)),
+        ])
+        .unwrap()
+    };
+    let layers = [
+        layer(&[1, 2, 1, 3], &[5, 0, 7, 2]),
+        layer(&[2, 2], &[4, 9]),
+        layer(&[3, 1, 4], &[1, 8, 6]),
+    ];
+    let zip = |t: &Tab| {
+        let cols = t
+            .cols()
+            .iter()
+            .map(|c| (c.name().clone(), c.vals().clone()));
+        Vals::zip(cols.collect()).unwrap()
+    };
+    let mut all = Tab::default();
+    let views = [
+        "filter(t, v > 1)",
+        "sort(filter(t, v > 1), [v desc])",
+        "group(filter(filter(t, v > 1), v < 9), [k], {n: count(v), s: sum(v)})",
+        "group(t, [k], {m: mean(v), hi: max(v)})",
+    ];
+    let mut reg = Views::default();
+    for (i, src) in views.iter().enumerate() {
+        let view = View::new(&expr(src), Overflow::default()).unwrap();
+        reg.register(&i.to_string(), view).unwrap();
+    }
+    for l in &layers {
+        reg.add_layer(&word("t"), l).unwrap();
+        reg.add_layer(&word("u"), &Tab::default()).unwrap();
+        match all.cols().is_empty() {
+            true => all = l.clone(),
+            false => crate::pipe::append(&mut all, l).unwrap(),
+        }
+        // Each view's result is what evaluating it over every layer so far
+        // gives.
+        for (i, src) in views.iter().enumerate() {
+            let ctx = vec![Tab::new(vec![Col::new(word("t"), zip(&all))]).unwrap()];
+            let mut vm = Vm::new(expr(src).ops().unwrap(), ctx);
+            let whole = vm.eval().unwrap().unwrap();
+            let view = reg.get(&i.to_string()).unwrap();
+            assert_eq!(zip(view.result()), whole, "{}", src);
+        }
+    }
+    assert_eq!(reg.get("0").unwrap().layers(), 3);
+
+    // A layer a view can't take leaves every view as it was.
+    let before = reg.get("2").unwrap().result().clone();
+    let bad = Tab::new(vec![Col::new(word("k"), ints(&[1]))]).unwrap();
+    assert!(reg.add_layer(&word("t"), &bad).is_err());
+    assert_eq!(reg.get("2").unwrap().result(), &before);
+    assert_eq!(reg.get("0").unwrap().layers(), 3);
+    assert!(reg
+        .register("0", View::new(&expr("t"), Overflow::default()).unwrap())
+        .is_err());
+
+    // Anything that isn't filters, then a group or sort, of one table is
+    // refused, as is a filter that reads another table.
+    for src in [
+        "t.v + 1",
+        "filter(t, v > u.x)",
+        "sort(group(t, [k], {n: count(v)}), [n])",
+        "join(t, u, [k = k])",
+    ] {
+        assert!(
+            View::new(&expr(src), Overflow::default()).is_err(),
+            "{}",
+            src
+        );
+    }
+}
+
+#[test]
+fn test_cache() {
+    let table =
+        |name: &str, cols: Vec<(Word, Vals)>| Col::new(word(name), Vals::zip(cols).unwrap());
+    let t = table(
+        "t",
+        vec![
+            (word("k"), ints(&[1, 2, 1, 3])),
+            (word("v"), ints(&[5, 0, 7, 2])),
+        ],
+    );
+    let u = table("u", vec![(word("x"), ints(&[10, 20]))]);
+    let ctx = vec![Tab::new(vec![t]).unwrap(), Tab::new(vec![u]).unwrap()];
+    let direct = |src: &str| {
+        Vm::new(expr(src).ops().unwrap(), ctx.clone())
+            .eval()
+            .unwrap()
+    };
+    let mut cache = Cache::new(10);
+    cache.set_layers("t", &[1]);
+    cache.set_layers("u", &[1]);
+
+    // A query that shares a group with an earlier one only misses on what's
+    // new.
+    let q1 = "group(filter(t, v > 1), [k], {n: count(v)})";
+    let q2 = "sort(group(filter(t, v > 1), [k], {n: count(v)}), [n desc])";
+    assert_eq!(cache.eval(&expr(q1), &ctx).unwrap(), direct(q1));
+    assert_eq!((cache.hits(), cache.misses()), (0, 2));
+    assert_eq!(cache.eval(&expr(q2), &ctx).unwrap(), direct(q2));
+    assert_eq!((cache.hits(), cache.misses()), (1, 3));
+    assert_eq!(cache.eval(&expr(q2), &ctx).unwrap(), direct(q2));
+    assert_eq!((cache.hits(), cache.misses()), (2, 3));
+    let q3 = "u.x + 1";
+    assert_eq!(cache.eval(&expr(q3), &ctx).unwrap(), direct(q3));
+    assert_eq!(cache.len(), 4);
+
+    // New layers in a table drop what read it, and only that.
+    cache.set_layers("t", &[1]);
+    cache.set_layers("u", &[1]);
+    assert_eq!(cache.len(), 4);
+    cache.set_layers("t", &[1, 2]);
+    assert_eq!(cache.len(), 1);
+    assert_eq!(cache.eval(&expr(q3), &ctx).unwrap(), direct(q3));
+    assert_eq!(cache.hits(), 3);
+
+    // Faults are cached too, and so are the operands' values that led to
+    // them.
+    let q4 = "{a: 1 / 0, b: 2}";
+    let f = cache.eval(&expr(q4), &ctx).unwrap().unwrap_err();
+    assert_eq!(f.kind, FaultKind::DivideByZero);
+    assert_eq!(cache.eval(&expr(q4), &ctx).unwrap(), Err(f));
+
+    // The entry used longest ago makes room.
+    let mut small = Cache::new(2);
+    for src in ["1 + 2", "3 + 4", "1 + 2", "5 + 6", "1 + 2", "3 + 4"] {
+        assert_eq!(small.eval(&expr(src), &ctx).unwrap(), direct(src));
+    }
+    assert_eq!((small.hits(), small.misses()), (2, 4));
+}
+
+#[test]
+fn test_footprint_conflicts() {
+    let path = |p: &str| Path(p.split('.').filter(|w| !w.is_empty()).map(word).collect());
+    let foot = |reads: &[&str], writes: &[&str]| Footprint {
+        reads: reads.iter().map(|p| path(p)).collect(),
+        writes: writes.iter().map(|p| path(p)).collect(),
+    };
+    assert_eq!(Grain::of(&path("")), Grain::Database);
+    assert_eq!(Grain::of(&path("t")), Grain::Table);
+    assert_eq!(Grain::of(&path("t.a")), Grain::Column);
+    assert_eq!(Grain::of(&path("t.a.x")), Grain::Field);
+
+    // Reads never conflict, and neither do disjoint columns or tables.
+    assert!(!foot(&["t"], &[]).conflicts(&foot(&["t"], &[])));
+    assert!(!foot(&[], &["t.a"]).conflicts(&foot(&["t.b"], &["t.b"])));
+    assert!(!foot(&[], &["t"]).conflicts(&foot(&["u.a"], &[])));
+
+    // A write conflicts with any access above or beneath it, both ways.
+    let table = foot(&[], &["t"]);
+    assert!(table.conflicts(&foot(&["t.a"], &[])));
+    assert!(foot(&["t.a.x"], &[]).conflicts(&table));
+    assert!(foot(&["t"], &[]).conflicts(&foot(&[], &["t.b"])));
+    assert!(foot(&[], &["t.a"]).conflicts(&foot(&[], &["t.a"])));
+
+    // The whole database conflicts with everything.
+    let db = foot(&[""], &[""]);
+    assert!(db.conflicts(&foot(&["u"], &[])));
+    assert!(db.allows_write(&path("u.a")));
+    assert!(!table.allows_write(&path("u")));
+}
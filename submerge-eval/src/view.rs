@@ -0,0 +1,132 @@
+// Views. A view is an Expr over a table whose result is kept up to date as
+// the row store adds layers to the table, by evaluating it over each new
+// layer alone and merging what that gives into what the earlier layers gave,
+// the way par.rs merges partitions. A layer is then never read again, and
+// the result is what evaluating the Expr over every layer, in the order they
+// came, would give.
+//
+// That only works for Exprs whose result over some rows is a merge of their
+// results over any split of the rows: the table, or filters of it, keeping
+// the rows that pass (a filter's predicate sees only the rows' columns); and
+// a group or sort of those, keeping partial groups or sorted rows to group
+// or sort again. Any other Expr is refused when the view is registered, as
+// it would have to be recomputed from scratch. A view with a mean keeps the
+// rows it groups, as an aggregate does (see pipe.rs), since means don't merge.
+
+use crate::par::{partial, Merged};
+use crate::Merge;
+use std::collections::BTreeMap;
+use submerge_base::{err, Result};
+use submerge_lang::{Expr, Opcode, Overflow, Path, Tab, Word};
+
+#[derive(Clone)]
+pub struct View {
+    table: Word,
+    // The filters' predicates, innermost first.
+    filters: Vec<Vec<Opcode>>,
+    merge: Merge,
+    merged: Merged,
+    result: Tab,
+    layers: usize,
+}
+
+// Whether a predicate only reads the columns of the rows it's filtering.
+fn row_local(e: &Expr) -> bool {
+    match e {
+        Expr::Path(Path(p)) => p.len() == 1,
+        Expr::Reify | Expr::Quote(_) | Expr::Let(..) | Expr::Lam(..) => false,
+        _ => e.children().into_iter().all(row_local),
+    }
+}
+
+impl View {
+    pub fn new(expr: &Expr, overflow: Overflow) -> Result<View> {
+        let (rows, merge) = match expr {
+            Expr::Group(x, g) => (&**x, Merge::Group(g.clone(), overflow)),
+            Expr::Sort(x, keys) => (&**x, Merge::Sort(keys.clone())),
+            _ => (expr, Merge::Concat),
+        };
+        let mut filters = Vec::new();
+        let mut e = rows;
+        let table = loop {
+            match e {
+                Expr::Filter(x, pred) if row_local(pred) => {
+                    filters.push(pred.ops()?);
+                    e = x;
+                }
+                Expr::Path(Path(p)) if p.len() == 1 => break p[0].clone(),
+                _ => return Err(err(format!("can't maintain {} incrementally", expr))),
+            }
+        };
+        filters.reverse();
+        Ok(View {
+            table,
+            filters,
+            merged: Merged::new(&merge),
+            merge,
+            result: Tab::default(),
+            layers: 0,
+        })
+    }
+
+    pub fn table(&self) -> &Word {
+        &self.table
+    }
+
+    pub fn result(&self) -> &Tab {
+        &self.result
+    }
+
+    // How many layers the result covers.
+    pub fn layers(&self) -> usize {
+        self.layers
+    }
+
+    // Merge in the view's result over a new layer of its table. On an
+    // error, the view is as it was.
+    pub fn add_layer(&mut self, layer: &Tab) -> Result<()> {
+        let mut rows = layer.clone();
+        for pred in &self.filters {
+            rows = rows.filter(pred)?;
+        }
+        let mut merged = self.merged.clone();
+        merged.add(partial(&self.merge, rows)?)?;
+        self.result = merged.clone().finish(&self.merge)?;
+        self.merged = merged;
+        self.layers += 1;
+        Ok(())
+    }
+}
+
+// The registered views, by name.
+#[derive(Clone, Default)]
+pub struct Views {
+    views: BTreeMap<String, View>,
+}
+
+impl Views {
+    pub fn register(&mut self, name: &str, view: View) -> Result<()> {
+        if self.views.contains_key(name) {
+            return Err(err(format!("view {} is already registered", name)));
+        }
+        self.views.insert(name.to_string(), view);
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&View> {
+        self.views.get(name)
+    }
+
+    // Bring every view of a table up to date with a new layer of it. On an
+    // error, every view is as it was.
+    pub fn add_layer(&mut self, table: &Word, layer: &Tab) -> Result<()> {
+        let mut updated = Vec::new();
+        for (name, v) in self.views.iter().filter(|(_, v)| &v.table == table) {
+            let mut v = v.clone();
+            v.add_layer(layer)?;
+            updated.push((name.clone(), v));
+        }
+        self.views.extend(updated);
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+// Aggregates reduce a column to a single row. Every node must compute the
+// same result from the same rows, so integer sums are exact (accumulated in an
+// i128, then wrapped or checked like any other integer op) and float sums are
+// compensated, using Neumaier's variant of Kahan summation in row order.
+//
+// A count or sum of no rows is zero, but the min, max or mean of no rows is
+// no rows. Everything but a count keeps the column's unit. Decimal sums keep
+// the scale and widen to the full precision, and decimal means keep the form,
+// truncating like decimal division. Means of plain ints are flos.
+//
+// Null rows are skipped, so a count counts the rows that have values. An
+// aggregate of rows that are all null is a null, but a count of them is
+// zero.
+
+use crate::{
+    fault::fault,
+    kernel::{bits, enrich, type_name, unpack_bits},
+    null::{nulls, split},
+    tab::empty_like,
+    AggOp, Decimal, FaultKind, Form, Overflow, Unit, Val, Vals, MAX_DECIMAL_PRECISION,
+};
+use ordered_float::OrderedFloat;
+use submerge_base::{err, Result};
+
+type F64 = OrderedFloat<f64>;
+
+pub(crate) fn aggregate(op: AggOp, v: &Vals, overflow: Overflow) -> Result<Vals> {
+    if nulls(v).is_some() {
+        let (vals, valid, n) = split(&[v])?;
+        let out = aggregate(op, &vals[0], overflow)?;
+        if !valid.is_empty() || n == 0 || op == AggOp::Count {
+            return Ok(out);
+        }
+        let mut null = empty_like(&out);
+        null.push(Val::Null)?;
+        return Ok(null);
+    }
+    let (name, form, unit, vals) = match v {
+        Vals::Rich(col) => (Some(&col.name), col.form, col.unit, &col.vals),
+        _ => (None, Form::NONE, Unit::NONE, v),
+    };
+    if op == AggOp::Count {
+        return Ok(Vals::I64s(vec![vals.len() as i64]));
+    }
+    let (out, form) = match vals {
+        Vals::I64s(x) => agg_i64(op, x, form, overflow)?,
+        Vals::F64s(x) => (agg_f64(op, x), form),
+        Vals::Bits(n, x) => (agg_bits(op, unpack_bits(*n, x)), Form::NONE),
+        Vals::Bins(x) if matches!(op, AggOp::Min | AggOp::Max) => {
+            let v = match op {
+                AggOp::Min => x.iter().min(),
+                _ => x.iter().max(),
+            };
+            (Vals::Bins(v.cloned().into_iter().collect()), form)
+        }
+        _ => return Err(err(format!("no {} on {}", op.name(), type_name(vals)))),
+    };
+    Ok(enrich(name, form, unit, out))
+}
+
+fn agg_i64(op: AggOp, x: &[i64], form: Form, overflow: Overflow) -> Result<(Vals, Form)> {
+    let one = |v: Option<i64>| Vals::I64s(v.into_iter().collect());
+    let sum: i128 = x.iter().map(|v| *v as i128).sum();
+    Ok(match op {
+        AggOp::Count => (Vals::I64s(vec![x.len() as i64]), Form::NONE),
+        AggOp::Min => (one(x.iter().min().copied()), form),
+        AggOp::Max => (one(x.iter().max().copied()), form),
+        AggOp::Sum => {
+            let d = form.as_decimal();
+            // A decimal's range is symmetric, and a plain int's is i64's.
+            let (lo, hi) = match d {
+                Some(d) => {
+                    let limit = Decimal::new(MAX_DECIMAL_PRECISION, d.scale)
+                        .expect("scale fits")
+                        .limit() as i128
+                        - 1;
+                    (-limit, limit)
+                }
+                None => (i64::MIN as i128, i64::MAX as i128),
+            };
+            if overflow == Overflow::Check && !(lo..=hi).contains(&sum) {
+                return Err(fault(
+                    FaultKind::Overflow,
+                    format!("overflow: sum of {} rows is {}", x.len(), sum),
+                ));
+            }
+            let form = d.map_or(Form::NONE, |d| {
+                Form::decimal(MAX_DECIMAL_PRECISION, d.scale).expect("scale fits")
+            });
+            (Vals::I64s(vec![sum as i64]), form)
+        }
+        AggOp::Mean => {
+            let n = x.len();
+            match form.as_decimal() {
+                Some(_) => (one((n > 0).then(|| (sum / n as i128) as i64)), form),
+                None => {
+                    let mean = (n > 0).then(|| OrderedFloat(sum as f64 / n as f64));
+                    (Vals::F64s(mean.into_iter().collect()), form)
+                }
+            }
+        }
+    })
+}
+
+// Neumaier summation: the compensation term collects the low-order bits each
+// addition loses, whichever operand is larger.
+fn compensated_sum(x: &[F64]) -> f64 {
+    let (mut sum, mut c) = (0.0_f64, 0.0_f64);
+    for v in x.iter().map(|v| v.0) {
+        let t = sum + v;
+        if !t.is_finite() {
+            // Infinities and NaNs would poison the compensation.
+            return x.iter().map(|v| v.0).sum();
+        }
+        if sum.abs() >= v.abs() {
+            c += (sum - t) + v;
+        } else {
+            c += (v - t) + sum;
+        }
+        sum = t;
+    }
+    sum + c
+}
+
+fn agg_f64(op: AggOp, x: &[F64]) -> Vals {
+    let v = match op {
+        AggOp::Count => Some(OrderedFloat(x.len() as f64)),
+        AggOp::Sum => Some(OrderedFloat(compensated_sum(x))),
+        AggOp::Min => x.iter().min().copied(),
+        AggOp::Max => x.iter().max().copied(),
+        AggOp::Mean if x.is_empty() => None,
+        AggOp::Mean => Some(OrderedFloat(compensated_sum(x) / x.len() as f64)),
+    };
+    Vals::F64s(v.into_iter().collect())
+}
+
+// Bits sum as 0 and 1, so their min is "all" and their max is "any".
+fn agg_bits(op: AggOp, x: Vec<bool>) -> Vals {
+    let set = x.iter().filter(|b| **b).count();
+    match op {
+        AggOp::Count => Vals::I64s(vec![x.len() as i64]),
+        AggOp::Sum => Vals::I64s(vec![set as i64]),
+        AggOp::Mean if x.is_empty() => Vals::F64s(vec![]),
+        _ if x.is_empty() => bits(vec![]),
+        AggOp::Min => bits(vec![set == x.len()]),
+        AggOp::Max => bits(vec![set > 0]),
+        AggOp::Mean => Vals::F64s(vec![OrderedFloat(set as f64 / x.len() as f64)]),
+    }
+}
@@ -0,0 +1,112 @@
+// Operations over records. An All column holds a column per field, all of
+// the same length. A field is labelled by wrapping it in a Rich Col, and any
+// field is also known by its position (_0, _1, ...), as in paths (see
+// tab.rs). Records can be taken apart a field at a time, zipped together from
+// columns, or built up a row at a time.
+
+use crate::{
+    kernel::{enrich, type_name},
+    tab::{child_index, empty_like},
+    Col, Form, Unit, Val, Vals, Word,
+};
+use submerge_base::{err, Result};
+
+// The fields of the record under any Rich wrappers.
+fn record(v: &Vals) -> Result<&[Vals]> {
+    match v {
+        Vals::Rich(col) => record(&col.vals),
+        Vals::All(fields) => Ok(fields),
+        _ => Err(err(format!("expected an all, not {}", type_name(v)))),
+    }
+}
+
+// A field's values without its label, in a Rich wrapper only if it has a
+// form or unit.
+pub(crate) fn unlabelled(v: &Vals) -> Vals {
+    match v {
+        Vals::Rich(col) => enrich(Some(&col.name), col.form, col.unit, col.vals.clone()),
+        _ => v.clone(),
+    }
+}
+
+// A one-row column repeated to n rows.
+fn repeat(v: &Vals, n: usize) -> Result<Vals> {
+    match v.get(0) {
+        Some(row) if v.len() == 1 => Vals::from_rows(v, vec![row; n]),
+        _ => Err(err(format!("cannot repeat {} rows to {}", v.len(), n))),
+    }
+}
+
+impl Vals {
+    // A field of a record, by label or position, as stored.
+    pub fn field(&self, w: &Word) -> Result<&Vals> {
+        let fields = record(self)?;
+        match child_index(fields, w) {
+            Some(i) => Ok(&fields[i]),
+            // Unlabelled fields are only known by position.
+            None => (0..fields.len())
+                .find(|i| Word::positional(*i) == *w)
+                .map(|i| &fields[i])
+                .ok_or_else(|| err(format!("no field {}", w))),
+        }
+    }
+
+    // The field at the end of a sequence of nested fields.
+    pub fn project(&self, words: &[Word]) -> Result<&Vals> {
+        words.iter().try_fold(self, |v, w| v.field(w))
+    }
+
+    // A record of labelled columns. One-row columns broadcast against the
+    // others, which must all have the same length.
+    pub fn zip(fields: Vec<(Word, Vals)>) -> Result<Vals> {
+        let Some(n) = fields.iter().map(|(_, v)| v.len()).max() else {
+            return Err(err("a record needs at least one field"));
+        };
+        let mut out = Vec::with_capacity(fields.len());
+        for (i, (w, v)) in fields.iter().enumerate() {
+            if fields[..i].iter().any(|(x, _)| x == w) {
+                return Err(err(format!("duplicate field {}", w)));
+            }
+            let v = if v.len() == n {
+                v.clone()
+            } else {
+                repeat(v, n)?
+            };
+            let (form, unit, vals) = match v {
+                Vals::Rich(col) => (col.form, col.unit, col.vals),
+                v => (Form::NONE, Unit::NONE, v),
+            };
+            out.push(Vals::Rich(Box::new(Col {
+                name: w.clone(),
+                form,
+                unit,
+                vals,
+            })));
+        }
+        Ok(Vals::All(out))
+    }
+
+    // The inverse of zip(): each field with the word that addresses it.
+    pub fn unzip(&self) -> Result<Vec<(Word, Vals)>> {
+        let fields = record(self)?;
+        let mut out = Vec::with_capacity(fields.len());
+        for (i, f) in fields.iter().enumerate() {
+            let w = match f {
+                Vals::Rich(c) if child_index(fields, &c.name) == Some(i) => c.name.clone(),
+                _ => Word::positional(i),
+            };
+            out.push((w, unlabelled(f)));
+        }
+        Ok(out)
+    }
+
+    // A column shaped like the template, labels and all, holding the given
+    // rows; for records, each row is a Val::All with a value per field.
+    pub fn from_rows(template: &Vals, rows: impl IntoIterator<Item = Val>) -> Result<Vals> {
+        let mut out = empty_like(template);
+        for row in rows {
+            out.push(row)?;
+        }
+        Ok(out)
+    }
+}
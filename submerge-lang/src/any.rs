@@ -0,0 +1,228 @@
+// Operations over tagged unions. An Any column is a selector giving each
+// row's variant, plus a column per variant that holds just the rows
+// selecting it, in row order (see tab.rs). Splitting an Any yields each
+// variant's values along with the rows they came from; case() runs a
+// function over every variant and scatters the results back into row order,
+// which is how the Vm's Case opcode works.
+
+use crate::{
+    kernel::type_name,
+    null::{nulls, scatter, split},
+    tab::empty_like,
+    Val, Vals,
+};
+use submerge_base::{err, Result};
+
+// Every selector must name a variant, and every variant must have exactly
+// as many rows as select it.
+fn check_any(sel: &[i64], vars: &[Vals]) -> Result<()> {
+    let mut counts = vec![0usize; vars.len()];
+    for (row, s) in sel.iter().enumerate() {
+        match usize::try_from(*s).ok().filter(|k| *k < vars.len()) {
+            Some(k) => counts[k] += 1,
+            None => {
+                return Err(err(format!(
+                    "selector {} at row {} is out of range for {} variants",
+                    s,
+                    row,
+                    vars.len()
+                )))
+            }
+        }
+    }
+    for (k, (n, v)) in counts.iter().zip(vars).enumerate() {
+        if v.len() != *n {
+            return Err(err(format!(
+                "variant {} has {} rows, but {} rows select it",
+                k,
+                v.len(),
+                n
+            )));
+        }
+    }
+    Ok(())
+}
+
+// The union under any Rich wrappers.
+fn union(v: &Vals) -> Result<(&[i64], &[Vals])> {
+    match v {
+        Vals::Rich(col) => union(&col.vals),
+        Vals::Any(sel, vars) => Ok((sel, vars)),
+        _ => Err(err(format!("expected an any, not {}", type_name(v)))),
+    }
+}
+
+impl Vals {
+    pub fn any(sel: Vec<i64>, vars: Vec<Vals>) -> Result<Vals> {
+        check_any(&sel, &vars)?;
+        Ok(Vals::Any(sel, vars))
+    }
+
+    // The inverse of variants(): an n-row Any from each variant's values and
+    // the rows they belong to. The rows of a variant must be increasing, and
+    // the variants between them must cover every row exactly once.
+    pub fn any_from_parts(n: usize, parts: Vec<(Vals, Vec<usize>)>) -> Result<Vals> {
+        let mut sel = vec![-1; n];
+        let mut vars = Vec::with_capacity(parts.len());
+        for (k, (vals, rows)) in parts.into_iter().enumerate() {
+            if vals.len() != rows.len() {
+                return Err(err(format!(
+                    "variant {} has {} rows but {} row indices",
+                    k,
+                    vals.len(),
+                    rows.len()
+                )));
+            }
+            if rows.windows(2).any(|w| w[0] >= w[1]) {
+                return Err(err(format!("variant {} rows are not increasing", k)));
+            }
+            for row in rows {
+                match sel.get_mut(row) {
+                    Some(s) if *s < 0 => *s = k as i64,
+                    Some(s) => {
+                        return Err(err(format!(
+                            "row {} is in both variant {} and {}",
+                            row, s, k
+                        )))
+                    }
+                    None => return Err(err(format!("row {} is out of range for {}", row, n))),
+                }
+            }
+            vars.push(vals);
+        }
+        if let Some(row) = sel.iter().position(|s| *s < 0) {
+            return Err(err(format!("row {} is in no variant", row)));
+        }
+        Ok(Vals::Any(sel, vars))
+    }
+
+    // Check the invariants the type system can't: that the fields of an All
+    // have equal lengths, that Any selectors agree with their variants, and
+    // that an Opt's null rows are rows it has.
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            Vals::Rich(col) => col.vals.validate(),
+            Vals::All(fields) => {
+                for (i, f) in fields.iter().enumerate() {
+                    if f.len() != fields[0].len() {
+                        return Err(err(format!(
+                            "field {} has {} rows, but field 0 has {}",
+                            i,
+                            f.len(),
+                            fields[0].len()
+                        )));
+                    }
+                    f.validate()?;
+                }
+                Ok(())
+            }
+            Vals::Any(sel, vars) => {
+                check_any(sel, vars)?;
+                vars.iter().try_for_each(|v| v.validate())
+            }
+            Vals::Opt(ns, vals) => {
+                if let Some(row) = Vec::from(ns).into_iter().find(|r| *r >= vals.len()) {
+                    return Err(err(format!(
+                        "null row {} is out of range for {}",
+                        row,
+                        vals.len()
+                    )));
+                }
+                vals.validate()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Every variant's values, with the row each of them came from.
+    pub fn variants(&self) -> Result<Vec<(&Vals, Vec<usize>)>> {
+        let (sel, vars) = union(self)?;
+        check_any(sel, vars)?;
+        let mut rows = vec![Vec::new(); vars.len()];
+        for (row, k) in sel.iter().enumerate() {
+            rows[*k as usize].push(row);
+        }
+        Ok(vars.iter().zip(rows).collect())
+    }
+
+    // One variant's values, with the row each of them came from.
+    pub fn variant(&self, k: usize) -> Result<(&Vals, Vec<usize>)> {
+        let (sel, vars) = union(self)?;
+        check_any(sel, vars)?;
+        let Some(vals) = vars.get(k) else {
+            return Err(err(format!("no variant {} of {}", k, vars.len())));
+        };
+        let rows = (0..sel.len()).filter(|i| sel[*i] == k as i64).collect();
+        Ok((vals, rows))
+    }
+}
+
+// Run arm f(k) over variant k's values and scatter what the arms return back
+// into row order. There must be one arm per variant. Each arm returns a row
+// per row of its variant, or a single row to broadcast over all of them, and
+// every arm must return the same type, form and unit. A null scrutinee runs
+// no arm and gives a null row.
+pub(crate) fn case(
+    scrut: &Vals,
+    arms: usize,
+    mut f: impl FnMut(usize, &Vals) -> Result<Vals>,
+) -> Result<Vals> {
+    if nulls(scrut).is_some() {
+        let (scrut, valid, n) = split(&[scrut])?;
+        return scatter(case(&scrut[0], arms, f)?, &valid, n);
+    }
+    let variants = scrut.variants()?;
+    if variants.len() != arms {
+        return Err(err(format!(
+            "case has {} arms for {} variants",
+            arms,
+            variants.len()
+        )));
+    }
+    let mut out: Vec<Option<Val>> = vec![None; scrut.len()];
+    let mut result: Option<Vals> = None;
+    for (k, (vals, rows)) in variants.into_iter().enumerate() {
+        let res = f(k, vals)?;
+        let mut empty = empty_like(&res);
+        match &result {
+            None => result = Some(empty),
+            Some(first) => {
+                // Only the shape has to match; the name is the first arm's.
+                if let (Vals::Rich(c), Vals::Rich(fc)) = (&mut empty, first) {
+                    c.name = fc.name.clone();
+                }
+                if empty != *first {
+                    return Err(err(format!(
+                        "case arm {} returns {}, unlike arm 0's {}",
+                        k,
+                        type_name(&res),
+                        type_name(first)
+                    )));
+                }
+            }
+        }
+        let vals = if res.len() == rows.len() {
+            res.to_rows()
+        } else if res.len() == 1 {
+            vec![res.get(0).expect("one row"); rows.len()]
+        } else {
+            return Err(err(format!(
+                "case arm {} returns {} rows for {}",
+                k,
+                res.len(),
+                rows.len()
+            )));
+        };
+        if vals.len() != rows.len() {
+            return Err(err(format!("case arm {} returns a malformed column", k)));
+        }
+        for (row, v) in rows.into_iter().zip(vals) {
+            out[row] = Some(v);
+        }
+    }
+    let mut result = result.unwrap_or(Vals::I64s(Vec::new()));
+    for v in out {
+        result.push(v.expect("variants cover every row"))?;
+    }
+    Ok(result)
+}
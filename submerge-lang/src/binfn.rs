@@ -0,0 +1,211 @@
+// Functions over bins. Lengths, slices and prefixes count bytes; case
+// folding needs its bins to be UTF-8, and LIKE patterns match characters if
+// both sides are UTF-8 and bytes otherwise. In a pattern, '%' matches any
+// run, '_' any one character and '\' makes the next character literal.
+//
+// Bins read from a block may be handles into its heap, which only the block
+// can resolve, so the functions take a BinHeap to resolve them with. A
+// one-row argument applies to every row of the others, and a null argument
+// gives a null row, as with binops.
+
+use crate::{
+    fault::fault,
+    kernel::bits,
+    kernel::type_name,
+    null::{lift, nulls},
+    Bin, BinFn, BinHeap, FaultKind, Vals,
+};
+use submerge_base::{err, Result};
+
+// For callers that have no heap, where every bin should be in memory.
+pub(crate) struct NoHeap;
+
+impl BinHeap for NoHeap {
+    fn bytes(&self, block: i64, entry: i64) -> Result<&[u8]> {
+        Err(err(format!(
+            "bin {}:{} needs its block's heap",
+            block, entry
+        )))
+    }
+}
+
+impl Bin {
+    pub fn bytes<'a>(&'a self, heap: &'a dyn BinHeap) -> Result<&'a [u8]> {
+        match self {
+            Bin::Mem(v) => Ok(v),
+            Bin::Heap { block, entry } => heap.bytes(*block, *entry),
+        }
+    }
+}
+
+enum Arg<'a> {
+    Bins(&'a [Bin]),
+    Ints(&'a [i64]),
+}
+
+impl Arg<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Arg::Bins(v) => v.len(),
+            Arg::Ints(v) => v.len(),
+        }
+    }
+}
+
+fn arg(f: BinFn, v: &Vals) -> Result<Arg<'_>> {
+    match v {
+        Vals::Rich(col) => arg(f, &col.vals),
+        Vals::Bins(x) => Ok(Arg::Bins(x)),
+        Vals::I64s(x) => Ok(Arg::Ints(x)),
+        _ => Err(err(format!("no {} on {}", f.name(), type_name(v)))),
+    }
+}
+
+fn utf8(f: BinFn, b: &[u8]) -> Result<&str> {
+    std::str::from_utf8(b)
+        .map_err(|_| fault(FaultKind::Invalid, format!("{} needs utf-8 bins", f.name())))
+}
+
+enum Pat {
+    Lit(u32),
+    One,
+    Any,
+}
+
+fn units(b: &[u8], utf8: bool) -> Vec<u32> {
+    match std::str::from_utf8(b) {
+        Ok(s) if utf8 => s.chars().map(u32::from).collect(),
+        _ => b.iter().map(|c| *c as u32).collect(),
+    }
+}
+
+fn pattern(p: &[u32]) -> Result<Vec<Pat>> {
+    let mut out = Vec::with_capacity(p.len());
+    let mut it = p.iter();
+    while let Some(c) = it.next() {
+        out.push(match char::from_u32(*c) {
+            Some('%') => Pat::Any,
+            Some('_') => Pat::One,
+            Some('\\') => match it.next() {
+                Some(e) => Pat::Lit(*e),
+                None => return Err(fault(FaultKind::Invalid, "like pattern ends in an escape")),
+            },
+            _ => Pat::Lit(*c),
+        });
+    }
+    Ok(out)
+}
+
+// Backtracks only to the most recent '%', so this is O(len(s) * len(p)).
+fn like(s: &[u8], p: &[u8]) -> Result<bool> {
+    let utf8 = std::str::from_utf8(s).is_ok() && std::str::from_utf8(p).is_ok();
+    let s = units(s, utf8);
+    let pat = pattern(&units(p, utf8))?;
+    let (mut si, mut pi) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while si < s.len() {
+        match pat.get(pi) {
+            Some(Pat::Any) => {
+                star = Some((pi, si));
+                pi += 1;
+            }
+            Some(Pat::One) => {
+                si += 1;
+                pi += 1;
+            }
+            Some(Pat::Lit(c)) if *c == s[si] => {
+                si += 1;
+                pi += 1;
+            }
+            _ => match star {
+                Some((sp, ss)) => {
+                    pi = sp + 1;
+                    si = ss + 1;
+                    star = Some((sp, ss + 1));
+                }
+                None => return Ok(false),
+            },
+        }
+    }
+    Ok(pat[pi..].iter().all(|p| matches!(p, Pat::Any)))
+}
+
+pub(crate) fn apply(f: BinFn, args: &[Vals], heap: &dyn BinHeap) -> Result<Vals> {
+    if args.len() != f.arity() {
+        return Err(err(format!(
+            "{} takes {} arguments, not {}",
+            f.name(),
+            f.arity(),
+            args.len()
+        )));
+    }
+    if args.iter().any(|a| nulls(a).is_some()) {
+        return lift(&args.iter().collect::<Vec<_>>(), |x| apply(f, x, heap));
+    }
+    let args: Vec<Arg> = args.iter().map(|v| arg(f, v)).collect::<Result<_>>()?;
+    let n = args.iter().map(|a| a.len()).max().unwrap_or(0);
+    if let Some(a) = args.iter().find(|a| a.len() != n && a.len() != 1) {
+        return Err(err(format!("column length mismatch: {} vs {}", a.len(), n)));
+    }
+    let bin = |k: usize, row: usize| -> Result<&[u8]> {
+        match &args[k] {
+            Arg::Bins(v) => v[if v.len() == 1 { 0 } else { row }].bytes(heap),
+            Arg::Ints(_) => Err(err(format!("{} takes a bin as argument {}", f.name(), k))),
+        }
+    };
+    let int = |k: usize, row: usize| -> Result<i64> {
+        match &args[k] {
+            Arg::Ints(v) => Ok(v[if v.len() == 1 { 0 } else { row }]),
+            Arg::Bins(_) => Err(err(format!("{} takes an int as argument {}", f.name(), k))),
+        }
+    };
+    let rows = 0..n;
+    Ok(match f {
+        BinFn::Len => Vals::I64s(
+            rows.map(|i| Ok(bin(0, i)?.len() as i64))
+                .collect::<Result<_>>()?,
+        ),
+        BinFn::StartsWith | BinFn::EndsWith | BinFn::Like => bits(
+            rows.map(|i| {
+                let (s, p) = (bin(0, i)?, bin(1, i)?);
+                match f {
+                    BinFn::StartsWith => Ok(s.starts_with(p)),
+                    BinFn::EndsWith => Ok(s.ends_with(p)),
+                    _ => like(s, p),
+                }
+            })
+            .collect::<Result<_>>()?,
+        ),
+        BinFn::Concat | BinFn::Slice | BinFn::Lower | BinFn::Upper => Vals::Bins(
+            rows.map(|i| {
+                let s = bin(0, i)?;
+                let out = match f {
+                    BinFn::Concat => [s, bin(1, i)?].concat(),
+                    BinFn::Slice => {
+                        let (start, len) = (int(1, i)?, int(2, i)?);
+                        if start < 0 || len < 0 {
+                            return Err(fault(
+                                FaultKind::OutOfRange,
+                                format!("slice {}, {} is negative", start, len),
+                            ));
+                        }
+                        let lo = (start as usize).min(s.len());
+                        let hi = lo.saturating_add(len as usize).min(s.len());
+                        s[lo..hi].to_vec()
+                    }
+                    BinFn::Lower => utf8(f, s)?.to_lowercase().into_bytes(),
+                    _ => utf8(f, s)?.to_uppercase().into_bytes(),
+                };
+                Ok(Bin::Mem(out))
+            })
+            .collect::<Result<_>>()?,
+        ),
+    })
+}
+
+impl BinFn {
+    // Apply to columns whose heap bins, if any, resolve in the given heap.
+    pub fn apply(self, args: &[Vals], heap: &dyn BinHeap) -> Result<Vals> {
+        apply(self, args, heap)
+    }
+}
@@ -0,0 +1,117 @@
+// Functions over bit columns as sets of rows: the rows whose bit is set, as
+// a filter mask keeps them. rank(m, i) counts the set rows before row i,
+// select(m, k) is the k-th set row, counting from 0, and rows(m) is all the
+// set rows in order, as a selection vector of row indices. mask(r, n) is
+// the inverse of rows: the n-row mask with the rows in r set. So compacting
+// by m and taking rows(m) keep the same rows, and select(m, rank(m, i)) is
+// i wherever m is set at i. Popcount is bitcount(m), a plain unop, and the
+// other logic is And, Or, Xor and Not.
+//
+// A null bit counts as unset, as it does in a filter. Rank and select are
+// elementwise in their ints, so a null int gives a null row, and mask skips
+// null rows. A row or count outside the column is out of range.
+
+use crate::{
+    fault::fault,
+    kernel::{bits, type_name},
+    null::{lift, nulls, split},
+    BitFn, FaultKind, Vals,
+};
+use submerge_base::{err, Result};
+
+// A bit column's length and set rows.
+fn set(f: BitFn, v: &Vals) -> Result<(usize, bs::Bs)> {
+    match v {
+        Vals::Rich(col) => set(f, &col.vals),
+        Vals::Opt(ns, vals) => {
+            let (n, mut bs) = set(f, vals)?;
+            bs.difference_with(ns);
+            Ok((n, bs))
+        }
+        Vals::Bits(n, bs) => Ok((*n, bs.clone())),
+        _ => Err(err(format!("no {} on {}", f.name(), type_name(v)))),
+    }
+}
+
+fn ints(f: BitFn, v: &Vals) -> Result<&[i64]> {
+    match v {
+        Vals::Rich(col) => ints(f, &col.vals),
+        Vals::I64s(x) => Ok(x),
+        _ => Err(err(format!(
+            "{} takes ints, not {}",
+            f.name(),
+            type_name(v)
+        ))),
+    }
+}
+
+fn out_of_range<T>(msg: String) -> Result<T> {
+    Err(fault(FaultKind::OutOfRange, msg))
+}
+
+fn rows(n: usize, bs: &bs::Bs) -> Vec<usize> {
+    (0..n).filter(|i| bs.contains(*i)).collect()
+}
+
+pub(crate) fn apply(f: BitFn, args: &[Vals]) -> Result<Vals> {
+    if args.len() != f.arity() {
+        return Err(err(format!(
+            "{} takes {} arguments, not {}",
+            f.name(),
+            f.arity(),
+            args.len()
+        )));
+    }
+    match f {
+        BitFn::Rank | BitFn::Select if nulls(&args[1]).is_some() => {
+            let m = &args[0];
+            lift(&[&args[1]], |x| apply(f, &[m.clone(), x[0].clone()]))
+        }
+        BitFn::Rows => {
+            let (n, bs) = set(f, &args[0])?;
+            Ok(Vals::I64s(
+                rows(n, &bs).into_iter().map(|i| i as i64).collect(),
+            ))
+        }
+        BitFn::Rank => {
+            let (n, bs) = set(f, &args[0])?;
+            let mut ranks = Vec::with_capacity(n + 1);
+            ranks.push(0);
+            for i in 0..n {
+                ranks.push(ranks[i] + bs.contains(i) as i64);
+            }
+            let rank = |i: &i64| match usize::try_from(*i).ok().and_then(|i| ranks.get(i)) {
+                Some(r) => Ok(*r),
+                None => out_of_range(format!("rank of row {} in {} rows", i, n)),
+            };
+            let out = ints(f, &args[1])?.iter().map(rank);
+            Ok(Vals::I64s(out.collect::<Result<_>>()?))
+        }
+        BitFn::Select => {
+            let (n, bs) = set(f, &args[0])?;
+            let set = rows(n, &bs);
+            let select = |k: &i64| match usize::try_from(*k).ok().and_then(|k| set.get(k)) {
+                Some(r) => Ok(*r as i64),
+                None => out_of_range(format!("select of set row {} of {}", k, set.len())),
+            };
+            let out = ints(f, &args[1])?.iter().map(select);
+            Ok(Vals::I64s(out.collect::<Result<_>>()?))
+        }
+        BitFn::Mask => {
+            let n = match ints(f, &args[1])? {
+                [n] if *n >= 0 => *n as usize,
+                [n] => return out_of_range(format!("mask of {} rows", n)),
+                v => return Err(err(format!("mask takes one length, not {}", v.len()))),
+            };
+            let (valid, _, _) = split(&[&args[0]])?;
+            let mut out = vec![false; n];
+            for r in ints(f, &valid[0])? {
+                match usize::try_from(*r) {
+                    Ok(r) if r < n => out[r] = true,
+                    _ => return out_of_range(format!("row {} of a {}-row mask", r, n)),
+                }
+            }
+            Ok(bits(out))
+        }
+    }
+}
@@ -0,0 +1,639 @@
+// The canonical encoding of an Expr: a byte string that is the same for
+// equal Exprs on every node and every version, so it can be hashed to name
+// an Expr by its content. Thunks dedupe in replication by that name, caches
+// key on it, and nodes compare it to find divergent Exprs. Serde encodings
+// can't promise that: they follow field order and representation choices,
+// such as whether a bitset is stored small or big.
+//
+// The encoding starts with a version byte. Every node is then a tag byte
+// followed by its fields in declaration order. Integers are LEB128 varints,
+// zigzagged if signed; sequences and byte strings are prefixed with their
+// length; bools are a byte. Ops and fns are written as their names, so their
+// encodings don't depend on their order in the enums. Bits are written as
+// their length and the rows that are set. Floats are written as their IEEE
+// bits, so 0.0 and -0.0 differ as they do in arithmetic, except that every
+// NaN is written as the one canonical NaN, as all NaNs are equal as Vals.
+//
+// Decoding rejects anything the encoder wouldn't have written, including
+// overlong varints and trailing bytes, so each Expr has exactly one
+// encoding.
+//
+// Values and faults, as transaction results, have canonical encodings too,
+// which nodes hash and compare to find divergent executions. These are
+// only ever hashed, not decoded.
+
+use crate::{
+    AggOp, Aggregate, Bin, BinFn, BitFn, Cast, CastMode, Col, Expr, Fault, Form, Grouping, Join,
+    JoinKind, Major, Path, PrimBinOp, PrimUnOp, SortKey, TimeFn, Ty, Unit, Vals, Word,
+};
+use ordered_float::OrderedFloat;
+use submerge_base::{
+    varint::{decode_uvarint, encode_uvarint, zigzag_decode, zigzag_encode, MAX_VARINT_LEN},
+    Error, Result,
+};
+
+const VERSION: u8 = 1;
+
+// The deepest an encoded Expr may nest, since decoding recurses on nesting.
+// It's more than the parser allows (see parse.rs), leaving room for what
+// rewriting adds to a parsed Expr.
+const MAX_DEPTH: usize = 192;
+
+const EXPR_PASS: u8 = 0;
+const EXPR_LIT: u8 = 1;
+const EXPR_PATH: u8 = 2;
+const EXPR_LET: u8 = 3;
+const EXPR_LAM: u8 = 4;
+const EXPR_APP: u8 = 5;
+const EXPR_IF: u8 = 6;
+const EXPR_BINOP: u8 = 7;
+const EXPR_UNOP: u8 = 8;
+const EXPR_BINFN: u8 = 9;
+const EXPR_TIMEFN: u8 = 10;
+const EXPR_REC: u8 = 11;
+const EXPR_TAB: u8 = 12;
+const EXPR_REIFY: u8 = 13;
+const EXPR_QUERY: u8 = 14;
+const EXPR_MERGE: u8 = 15;
+const EXPR_CONVERT: u8 = 16;
+const EXPR_GROUP: u8 = 17;
+const EXPR_JOIN: u8 = 18;
+const EXPR_SORT: u8 = 19;
+const EXPR_FILTER: u8 = 20;
+const EXPR_CAST: u8 = 21;
+const EXPR_QUOTE: u8 = 22;
+const EXPR_SPLICE: u8 = 23;
+const EXPR_BITFN: u8 = 24;
+
+const VALS_I64S: u8 = 0;
+const VALS_F64S: u8 = 1;
+const VALS_BITS: u8 = 2;
+const VALS_BINS: u8 = 3;
+const VALS_RICH: u8 = 4;
+const VALS_ALL: u8 = 5;
+const VALS_ANY: u8 = 6;
+const VALS_OPT: u8 = 7;
+// Past every Vals tag, so no fault encodes as a value.
+const FAULT: u8 = 255;
+
+const BIN_MEM: u8 = 0;
+const BIN_HEAP: u8 = 1;
+
+const JOIN_INNER: u8 = 0;
+const JOIN_LEFT: u8 = 1;
+
+const CAST_BIT: u8 = 0;
+const CAST_INT: u8 = 1;
+const CAST_FLO: u8 = 2;
+const CAST_BIN: u8 = 3;
+const CAST_OTHER: u8 = 4;
+
+struct Enc(Vec<u8>);
+
+impl Enc {
+    fn byte(&mut self, b: u8) {
+        self.0.push(b);
+    }
+    fn uint(&mut self, v: u64) {
+        let mut buf = [0_u8; MAX_VARINT_LEN];
+        let n = encode_uvarint(v, &mut buf);
+        self.0.extend_from_slice(&buf[..n]);
+    }
+    fn int(&mut self, v: i64) {
+        self.uint(zigzag_encode(v));
+    }
+    fn len(&mut self, n: usize) {
+        self.uint(n as u64);
+    }
+    fn bytes(&mut self, b: &[u8]) {
+        self.len(b.len());
+        self.0.extend_from_slice(b);
+    }
+    fn name(&mut self, s: &str) {
+        self.bytes(s.as_bytes());
+    }
+
+    fn bin(&mut self, b: &Bin) {
+        match b {
+            Bin::Mem(v) => {
+                self.byte(BIN_MEM);
+                self.bytes(v);
+            }
+            Bin::Heap { block, entry } => {
+                self.byte(BIN_HEAP);
+                self.int(*block);
+                self.int(*entry);
+            }
+        }
+    }
+    fn word(&mut self, w: &Word) {
+        self.bin(&w.0);
+    }
+    fn words(&mut self, ws: &[Word]) {
+        self.len(ws.len());
+        ws.iter().for_each(|w| self.word(w));
+    }
+    fn path(&mut self, p: &Path) {
+        self.words(&p.0);
+    }
+
+    fn vals(&mut self, v: &Vals) {
+        match v {
+            Vals::I64s(x) => {
+                self.byte(VALS_I64S);
+                self.len(x.len());
+                x.iter().for_each(|i| self.int(*i));
+            }
+            Vals::F64s(x) => {
+                self.byte(VALS_F64S);
+                self.len(x.len());
+                for f in x {
+                    let f = if f.is_nan() { f64::NAN } else { f.0 };
+                    self.0.extend_from_slice(&f.to_bits().to_le_bytes());
+                }
+            }
+            Vals::Bits(n, bs) => {
+                self.byte(VALS_BITS);
+                self.len(*n);
+                self.rows(bs);
+            }
+            Vals::Bins(x) => {
+                self.byte(VALS_BINS);
+                self.len(x.len());
+                x.iter().for_each(|b| self.bin(b));
+            }
+            Vals::Rich(col) => {
+                self.byte(VALS_RICH);
+                self.word(&col.name);
+                self.int(col.form.0);
+                self.int(col.unit.0);
+                self.vals(&col.vals);
+            }
+            Vals::All(fields) => {
+                self.byte(VALS_ALL);
+                self.len(fields.len());
+                fields.iter().for_each(|f| self.vals(f));
+            }
+            Vals::Any(sel, vars) => {
+                self.byte(VALS_ANY);
+                self.len(sel.len());
+                sel.iter().for_each(|s| self.int(*s));
+                self.len(vars.len());
+                vars.iter().for_each(|v| self.vals(v));
+            }
+            Vals::Opt(ns, vals) => {
+                self.byte(VALS_OPT);
+                self.rows(ns);
+                self.vals(vals);
+            }
+        }
+    }
+    // The set rows of a bitset, ascending.
+    fn rows(&mut self, bs: &bs::Bs) {
+        let rows = Vec::from(bs);
+        self.len(rows.len());
+        rows.iter().for_each(|r| self.len(*r));
+    }
+
+    fn fields(&mut self, fs: &[(Word, Expr)]) {
+        self.len(fs.len());
+        for (w, e) in fs {
+            self.word(w);
+            self.expr(e);
+        }
+    }
+    fn exprs(&mut self, es: &[Expr]) {
+        self.len(es.len());
+        es.iter().for_each(|e| self.expr(e));
+    }
+
+    fn expr(&mut self, e: &Expr) {
+        match e {
+            Expr::Pass => self.byte(EXPR_PASS),
+            Expr::Lit(v) => {
+                self.byte(EXPR_LIT);
+                self.vals(v);
+            }
+            Expr::Path(p) => {
+                self.byte(EXPR_PATH);
+                self.path(p);
+            }
+            Expr::Let(w, val, body) => {
+                self.byte(EXPR_LET);
+                self.word(w);
+                self.expr(val);
+                self.expr(body);
+            }
+            Expr::Lam(params, body) => {
+                self.byte(EXPR_LAM);
+                self.words(params);
+                self.expr(body);
+            }
+            Expr::App(func, args) => {
+                self.byte(EXPR_APP);
+                self.expr(func);
+                self.exprs(args);
+            }
+            Expr::If(c, t, e) => {
+                self.byte(EXPR_IF);
+                self.expr(c);
+                self.expr(t);
+                self.expr(e);
+            }
+            Expr::BinOp(op, a, b) => {
+                self.byte(EXPR_BINOP);
+                self.name(op.name());
+                self.expr(a);
+                self.expr(b);
+            }
+            Expr::UnOp(op, a) => {
+                self.byte(EXPR_UNOP);
+                self.name(op.name());
+                self.expr(a);
+            }
+            Expr::BinFn(f, args) => {
+                self.byte(EXPR_BINFN);
+                self.name(f.name());
+                self.exprs(args);
+            }
+            Expr::TimeFn(f, args) => {
+                self.byte(EXPR_TIMEFN);
+                self.name(f.name());
+                self.exprs(args);
+            }
+            Expr::BitFn(f, args) => {
+                self.byte(EXPR_BITFN);
+                self.name(f.name());
+                self.exprs(args);
+            }
+            Expr::Rec(fs) => {
+                self.byte(EXPR_REC);
+                self.fields(fs);
+            }
+            Expr::Tab(fs) => {
+                self.byte(EXPR_TAB);
+                self.fields(fs);
+            }
+            Expr::Reify => self.byte(EXPR_REIFY),
+            Expr::Query(a, p) => {
+                self.byte(EXPR_QUERY);
+                self.expr(a);
+                self.path(p);
+            }
+            Expr::Merge(a, b) => {
+                self.byte(EXPR_MERGE);
+                self.expr(a);
+                self.expr(b);
+            }
+            Expr::Convert(a, unit) => {
+                self.byte(EXPR_CONVERT);
+                self.expr(a);
+                self.int(unit.0);
+            }
+            Expr::Cast(a, c) => {
+                self.byte(EXPR_CAST);
+                self.expr(a);
+                // Only scalars can be cast to. Anything else is written out
+                // so that it still has its own hash, but doesn't decode.
+                match &c.to {
+                    Major::Bit => self.byte(CAST_BIT),
+                    Major::Int => self.byte(CAST_INT),
+                    Major::Flo => self.byte(CAST_FLO),
+                    Major::Bin => self.byte(CAST_BIN),
+                    to => {
+                        self.byte(CAST_OTHER);
+                        self.name(&Ty::new(to.clone()).to_string());
+                    }
+                }
+                self.int(c.form.0);
+                self.name(c.mode.name());
+            }
+            Expr::Group(a, g) => {
+                self.byte(EXPR_GROUP);
+                self.expr(a);
+                self.len(g.keys.len());
+                g.keys.iter().for_each(|k| self.path(k));
+                self.len(g.aggs.len());
+                for agg in &g.aggs {
+                    self.word(&agg.name);
+                    self.name(agg.op.name());
+                    self.path(&agg.arg);
+                }
+            }
+            Expr::Join(a, b, j) => {
+                self.byte(EXPR_JOIN);
+                self.expr(a);
+                self.expr(b);
+                self.byte(match j.kind {
+                    JoinKind::Inner => JOIN_INNER,
+                    JoinKind::Left => JOIN_LEFT,
+                });
+                self.len(j.on.len());
+                for (l, r) in &j.on {
+                    self.path(l);
+                    self.path(r);
+                }
+            }
+            Expr::Sort(a, keys) => {
+                self.byte(EXPR_SORT);
+                self.expr(a);
+                self.len(keys.len());
+                for k in keys {
+                    self.path(&k.path);
+                    self.byte(k.desc as u8);
+                }
+            }
+            Expr::Filter(a, pred) => {
+                self.byte(EXPR_FILTER);
+                self.expr(a);
+                self.expr(pred);
+            }
+            Expr::Quote(a) => {
+                self.byte(EXPR_QUOTE);
+                self.expr(a);
+            }
+            Expr::Splice(a) => {
+                self.byte(EXPR_SPLICE);
+                self.expr(a);
+            }
+        }
+    }
+}
+
+fn corrupt(what: &str) -> Error {
+    Error::corruption(format!("bad canonical expr: {}", what))
+}
+
+struct Dec<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl Dec<'_> {
+    fn byte(&mut self) -> Result<u8> {
+        let b = *self.buf.get(self.pos).ok_or_else(|| corrupt("truncated"))?;
+        self.pos += 1;
+        Ok(b)
+    }
+    fn uint(&mut self) -> Result<u64> {
+        let (v, n) = decode_uvarint(&self.buf[self.pos..])?;
+        self.pos += n;
+        Ok(v)
+    }
+    fn int(&mut self) -> Result<i64> {
+        Ok(zigzag_decode(self.uint()?))
+    }
+    // A length, which can't be more than the bytes left: every element
+    // takes at least one.
+    fn len(&mut self) -> Result<usize> {
+        let n = self.uint()?;
+        if n > (self.buf.len() - self.pos) as u64 {
+            return Err(corrupt("length past the end"));
+        }
+        Ok(n as usize)
+    }
+    fn bool(&mut self) -> Result<bool> {
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(corrupt("bad bool")),
+        }
+    }
+    fn bytes(&mut self) -> Result<Vec<u8>> {
+        let n = self.len()?;
+        let b = self.buf[self.pos..self.pos + n].to_vec();
+        self.pos += n;
+        Ok(b)
+    }
+    fn name<T>(&mut self, from_name: fn(&str) -> Option<T>) -> Result<T> {
+        let b = self.bytes()?;
+        std::str::from_utf8(&b)
+            .ok()
+            .and_then(from_name)
+            .ok_or_else(|| corrupt("unknown op"))
+    }
+    fn many<T>(&mut self, mut f: impl FnMut(&mut Self) -> Result<T>) -> Result<Vec<T>> {
+        let n = self.len()?;
+        (0..n).map(|_| f(self)).collect()
+    }
+
+    fn bin(&mut self) -> Result<Bin> {
+        match self.byte()? {
+            BIN_MEM => Ok(Bin::Mem(self.bytes()?)),
+            BIN_HEAP => Ok(Bin::Heap {
+                block: self.int()?,
+                entry: self.int()?,
+            }),
+            _ => Err(corrupt("bad bin tag")),
+        }
+    }
+    fn word(&mut self) -> Result<Word> {
+        Ok(Word(self.bin()?))
+    }
+    fn path(&mut self) -> Result<Path> {
+        Ok(Path(self.many(Self::word)?))
+    }
+    // The set rows of a bitset of n rows. Checking them against n first
+    // keeps a bad row from allocating a huge bitset.
+    fn rows(&mut self, n: usize) -> Result<bs::Bs> {
+        let rows = self.many(|d| Ok(d.uint()? as usize))?;
+        if rows.iter().any(|r| *r >= n) {
+            return Err(corrupt("row past the end"));
+        }
+        Ok(rows.into_iter().collect())
+    }
+
+    fn vals(&mut self) -> Result<Vals> {
+        Ok(match self.byte()? {
+            VALS_I64S => Vals::I64s(self.many(Self::int)?),
+            VALS_F64S => Vals::F64s(self.many(|d| {
+                let end = d.pos + 8;
+                let bytes = d.buf.get(d.pos..end).ok_or_else(|| corrupt("truncated"))?;
+                d.pos = end;
+                let bits = u64::from_le_bytes(bytes.try_into().expect("8 bytes"));
+                Ok(OrderedFloat(f64::from_bits(bits)))
+            })?),
+            VALS_BITS => {
+                let n = self.uint()? as usize;
+                Vals::Bits(n, self.rows(n)?)
+            }
+            VALS_BINS => Vals::Bins(self.many(Self::bin)?),
+            VALS_RICH => Vals::Rich(Box::new(Col {
+                name: self.word()?,
+                form: Form(self.int()?),
+                unit: Unit(self.int()?),
+                vals: self.vals()?,
+            })),
+            VALS_ALL => Vals::All(self.many(Self::vals)?),
+            VALS_ANY => Vals::Any(self.many(Self::int)?, self.many(Self::vals)?),
+            VALS_OPT => {
+                // The null rows come first, but can only be checked against
+                // the length of the column after them.
+                let start = self.pos;
+                self.many(|d| d.uint())?;
+                let vals = self.vals()?;
+                let end = self.pos;
+                self.pos = start;
+                let ns = self.rows(vals.len())?;
+                self.pos = end;
+                Vals::Opt(ns, Box::new(vals))
+            }
+            _ => return Err(corrupt("bad vals tag")),
+        })
+    }
+
+    fn fields(&mut self) -> Result<Vec<(Word, Expr)>> {
+        self.many(|d| Ok((d.word()?, d.expr()?)))
+    }
+    fn boxed(&mut self) -> Result<Box<Expr>> {
+        Ok(Box::new(self.expr()?))
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        if self.depth >= MAX_DEPTH {
+            return Err(corrupt("nested too deeply"));
+        }
+        self.depth += 1;
+        let e = self.node();
+        self.depth -= 1;
+        e
+    }
+
+    fn node(&mut self) -> Result<Expr> {
+        Ok(match self.byte()? {
+            EXPR_PASS => Expr::Pass,
+            EXPR_LIT => Expr::Lit(self.vals()?),
+            EXPR_PATH => Expr::Path(self.path()?),
+            EXPR_LET => Expr::Let(self.word()?, self.boxed()?, self.boxed()?),
+            EXPR_LAM => Expr::Lam(self.many(Self::word)?, self.boxed()?),
+            EXPR_APP => Expr::App(self.boxed()?, self.many(Self::expr)?),
+            EXPR_IF => Expr::If(self.boxed()?, self.boxed()?, self.boxed()?),
+            EXPR_BINOP => Expr::BinOp(
+                self.name(PrimBinOp::from_name)?,
+                self.boxed()?,
+                self.boxed()?,
+            ),
+            EXPR_UNOP => Expr::UnOp(self.name(PrimUnOp::from_name)?, self.boxed()?),
+            EXPR_BINFN => Expr::BinFn(self.name(BinFn::from_name)?, self.many(Self::expr)?),
+            EXPR_TIMEFN => Expr::TimeFn(self.name(TimeFn::from_name)?, self.many(Self::expr)?),
+            EXPR_BITFN => Expr::BitFn(self.name(BitFn::from_name)?, self.many(Self::expr)?),
+            EXPR_REC => Expr::Rec(self.fields()?),
+            EXPR_TAB => Expr::Tab(self.fields()?),
+            EXPR_REIFY => Expr::Reify,
+            EXPR_QUERY => Expr::Query(self.boxed()?, self.path()?),
+            EXPR_MERGE => Expr::Merge(self.boxed()?, self.boxed()?),
+            EXPR_QUOTE => Expr::Quote(self.boxed()?),
+            EXPR_SPLICE => Expr::Splice(self.boxed()?),
+            EXPR_CONVERT => Expr::Convert(self.boxed()?, Unit(self.int()?)),
+            EXPR_GROUP => Expr::Group(self.boxed()?, self.grouping()?),
+            EXPR_JOIN => Expr::Join(self.boxed()?, self.boxed()?, self.join()?),
+            EXPR_SORT => Expr::Sort(self.boxed()?, self.many(Self::sort_key)?),
+            EXPR_FILTER => Expr::Filter(self.boxed()?, self.boxed()?),
+            EXPR_CAST => Expr::Cast(self.boxed()?, self.cast()?),
+            _ => return Err(corrupt("bad expr tag")),
+        })
+    }
+
+    // The forms' other fields are decoded apart, to keep what each level of
+    // nesting takes of the stack small.
+    fn grouping(&mut self) -> Result<Grouping> {
+        let keys = self.many(Self::path)?;
+        let aggs = self.many(|d| {
+            Ok(Aggregate {
+                name: d.word()?,
+                op: d.name(AggOp::from_name)?,
+                arg: d.path()?,
+            })
+        })?;
+        Ok(Grouping { keys, aggs })
+    }
+
+    fn join(&mut self) -> Result<Join> {
+        let kind = match self.byte()? {
+            JOIN_INNER => JoinKind::Inner,
+            JOIN_LEFT => JoinKind::Left,
+            _ => return Err(corrupt("bad join kind")),
+        };
+        let on = self.many(|d| Ok((d.path()?, d.path()?)))?;
+        Ok(Join { kind, on })
+    }
+
+    fn sort_key(&mut self) -> Result<SortKey> {
+        Ok(SortKey {
+            path: self.path()?,
+            desc: self.bool()?,
+        })
+    }
+
+    fn cast(&mut self) -> Result<Cast> {
+        let to = match self.byte()? {
+            CAST_BIT => Major::Bit,
+            CAST_INT => Major::Int,
+            CAST_FLO => Major::Flo,
+            CAST_BIN => Major::Bin,
+            _ => return Err(corrupt("bad cast type")),
+        };
+        let form = Form(self.int()?);
+        let mode = self.name(CastMode::from_name)?;
+        Ok(Cast { to, form, mode })
+    }
+}
+
+impl Expr {
+    pub fn canonical(&self) -> Vec<u8> {
+        let mut enc = Enc(vec![VERSION]);
+        enc.expr(self);
+        enc.0
+    }
+
+    pub fn from_canonical(bytes: &[u8]) -> Result<Expr> {
+        let mut dec = Dec {
+            buf: bytes,
+            pos: 0,
+            depth: 0,
+        };
+        if dec.byte()? != VERSION {
+            return Err(corrupt("unknown version"));
+        }
+        let e = dec.expr()?;
+        // Anything else the decoder accepts, like an overlong varint or the
+        // rows of a bitset out of order, encodes differently.
+        if e.canonical() != bytes {
+            return Err(corrupt("not in canonical form"));
+        }
+        Ok(e)
+    }
+
+    // A hash of the canonical encoding. Equal Exprs have equal hashes on
+    // every node; where a collision would matter, compare the encodings.
+    pub fn content_hash(&self) -> u64 {
+        rapidhash::rapidhash(&self.canonical())
+    }
+}
+
+impl Vals {
+    pub fn canonical(&self) -> Vec<u8> {
+        let mut enc = Enc(vec![VERSION]);
+        enc.vals(self);
+        enc.0
+    }
+
+    pub fn content_hash(&self) -> u64 {
+        rapidhash::rapidhash(&self.canonical())
+    }
+}
+
+impl Fault {
+    pub fn canonical(&self) -> Vec<u8> {
+        let mut enc = Enc(vec![VERSION, FAULT]);
+        enc.name(self.kind.name());
+        enc.len(self.pc);
+        enc.name(&self.msg);
+        enc.0
+    }
+
+    pub fn content_hash(&self) -> u64 {
+        rapidhash::rapidhash(&self.canonical())
+    }
+}
@@ -0,0 +1,461 @@
+// Casts change a scalar column's major type or form, where Convert only
+// rescales a number to another unit. A cast to a number keeps the column's
+// unit. The casts there are, from a row's type to a column's:
+//
+//              bit  int  dec  temporal  flo  bin
+//    bit        =    x    x      -       x    x
+//    int        x    =    x      x       x    x
+//    dec        x    x    x      -       x    x
+//    temporal   -    x    -      *       -    x
+//    flo        x    x    x      -       =    x
+//    bin        x    x    x      x       x    =
+//
+// where * is between timestamps and dates, or from a temporal form to
+// itself, and int is a plain int, with no form. Bits are 0 and 1 as numbers
+// and "true" and "false" as text. Ints take a temporal form as they are, as
+// time.rs's functions take plain ints, so 1 is a microsecond after the epoch
+// as a timestamp and a day after it as a date. The text of a number or time
+// is what the printer and Decimal::format() write for it, and time::format()
+// for times, and casting from text reads those shapes back.
+//
+// A value the target can't hold exactly is either inexact (a fraction of a
+// unit of the target, like 1.5 as an int, a time within a day as a date, or
+// an int too big for a flo's mantissa), out of the target's range (including
+// a decimal's precision, and ints other than 0 and 1 as bits) or invalid
+// (NaN, or text of the wrong shape). Inexact values round to nearest, with
+// ties away from zero, except that times round down to their day. Then a
+// Check cast fails on any of the three. Saturate rounds inexact values,
+// clamps out-of-range ones to the nearest bound, and fails on invalid ones.
+// Lossy rounds inexact values and makes the other two null.
+
+use crate::{
+    fault::fault,
+    kernel::{enrich, type_name},
+    null::{lift, nulls, with_nulls},
+    time::{self, MICROS_PER_DAY},
+    Bin, Cast, CastMode, Decimal, FaultKind, Form, Major, Temporal, Ty, Unit, Vals,
+};
+use ordered_float::OrderedFloat;
+use std::fmt::{self, Display, Formatter};
+use submerge_base::{err, Result};
+
+// What a column holds, as far as casts are concerned.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Kind {
+    Bit,
+    Int(Num),
+    Flo,
+    Bin,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Num {
+    Plain,
+    Dec(Decimal),
+    Time(Temporal),
+    Other(Form), // A form only the layers above know about
+}
+
+fn kind(major: &Major, form: Form) -> Option<Kind> {
+    let num = match (form.as_decimal(), form.as_temporal()) {
+        _ if form == Form::NONE => Num::Plain,
+        (Some(d), _) => Num::Dec(d),
+        (_, Some(t)) => Num::Time(t),
+        _ => Num::Other(form),
+    };
+    match (major, num) {
+        (Major::Int, _) => Some(Kind::Int(num)),
+        (_, Num::Plain) => match major {
+            Major::Bit => Some(Kind::Bit),
+            Major::Flo => Some(Kind::Flo),
+            Major::Bin => Some(Kind::Bin),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn allowed(from: Kind, to: Kind) -> bool {
+    use Kind::*;
+    use Num::*;
+    match (from, to) {
+        (Int(Other(a)), Int(Other(b))) => a == b,
+        (Int(Other(_)), _) | (_, Int(Other(_))) => false,
+        (Int(Time(a)), Int(Time(b))) => {
+            a == b || (a != Temporal::Duration && b != Temporal::Duration)
+        }
+        (Int(Time(_)), Int(Dec(_)) | Bit | Flo) | (Int(Dec(_)) | Bit | Flo, Int(Time(_))) => false,
+        _ => true,
+    }
+}
+
+// Whether a column of the given major type and form can be cast as asked.
+pub(crate) fn castable(major: &Major, form: Form, c: &Cast) -> bool {
+    match (kind(major, form), kind(&c.to, c.form)) {
+        (Some(from), Some(to)) => allowed(from, to),
+        _ => false,
+    }
+}
+
+// How well a value fits the target, from best to worst.
+#[derive(Clone, Debug, PartialEq)]
+enum Fit<T> {
+    Exact(T),
+    Inexact(T),    // Rounded
+    OutOfRange(T), // Clamped
+    Invalid,
+}
+
+impl<T> Fit<T> {
+    fn rank(&self) -> u8 {
+        match self {
+            Fit::Exact(_) => 0,
+            Fit::Inexact(_) => 1,
+            Fit::OutOfRange(_) => 2,
+            Fit::Invalid => 3,
+        }
+    }
+
+    // Feed the value on through another step, keeping the worse fit.
+    fn then<U>(self, f: impl FnOnce(T) -> Fit<U>) -> Fit<U> {
+        let rank = self.rank();
+        let next = match self {
+            Fit::Exact(x) | Fit::Inexact(x) | Fit::OutOfRange(x) => f(x),
+            Fit::Invalid => return Fit::Invalid,
+        };
+        match (rank.max(next.rank()), next) {
+            (_, Fit::Invalid) => Fit::Invalid,
+            (0, next) => next,
+            (1, Fit::Exact(x) | Fit::Inexact(x)) => Fit::Inexact(x),
+            (_, Fit::Exact(x) | Fit::Inexact(x) | Fit::OutOfRange(x)) => Fit::OutOfRange(x),
+        }
+    }
+}
+
+fn pow10(scale: u8) -> i64 {
+    10i64.pow(scale as u32)
+}
+
+// The scale and bound on magnitudes of an int or decimal.
+fn bounds(n: Num) -> (u8, i64) {
+    match n {
+        Num::Dec(d) => (d.scale, d.limit() - 1),
+        _ => (0, i64::MAX),
+    }
+}
+
+// Rescale a fixed-point value, rounding to nearest with ties away from zero.
+fn rescale(v: i64, from: u8, to: u8) -> Fit<i64> {
+    if to >= from {
+        return match v.checked_mul(pow10(to - from)) {
+            Some(x) => Fit::Exact(x),
+            None if v < 0 => Fit::OutOfRange(i64::MIN),
+            None => Fit::OutOfRange(i64::MAX),
+        };
+    }
+    let p = pow10(from - to);
+    let (q, r) = (v / p, v % p);
+    if r == 0 {
+        Fit::Exact(q)
+    } else if r.unsigned_abs() >= (p as u64).div_ceil(2) {
+        Fit::Inexact(q + v.signum())
+    } else {
+        Fit::Inexact(q)
+    }
+}
+
+fn clamp(v: i64, max: i64) -> Fit<i64> {
+    if v > max {
+        Fit::OutOfRange(max)
+    } else if v < -max {
+        Fit::OutOfRange(-max)
+    } else {
+        Fit::Exact(v)
+    }
+}
+
+fn int_to_int(v: i64, from: Num, to: Num) -> Fit<i64> {
+    use Num::*;
+    match (from, to) {
+        _ if from == to => Fit::Exact(v),
+        (Plain, Time(_)) | (Time(_), Plain) => Fit::Exact(v),
+        (Time(Temporal::Timestamp), Time(Temporal::Date)) => match v.rem_euclid(MICROS_PER_DAY) {
+            0 => Fit::Exact(v.div_euclid(MICROS_PER_DAY)),
+            _ => Fit::Inexact(v.div_euclid(MICROS_PER_DAY)),
+        },
+        (Time(Temporal::Date), Time(Temporal::Timestamp)) => match v.checked_mul(MICROS_PER_DAY) {
+            Some(x) => Fit::Exact(x),
+            None if v < 0 => Fit::OutOfRange(i64::MIN),
+            None => Fit::OutOfRange(i64::MAX),
+        },
+        _ => {
+            let ((s, _), (t, max)) = (bounds(from), bounds(to));
+            rescale(v, s, t).then(|x| clamp(x, max))
+        }
+    }
+}
+
+fn int_to_bit(v: i64) -> Fit<bool> {
+    match v {
+        0 => Fit::Exact(false),
+        1 => Fit::Exact(true),
+        _ => Fit::OutOfRange(v > 0),
+    }
+}
+
+fn int_to_flo(v: i64, from: Num) -> Fit<f64> {
+    let (s, _) = bounds(from);
+    let p = pow10(s) as f64;
+    let f = v as f64 / p;
+    if (f * p).round() as i128 == v as i128 {
+        Fit::Exact(f)
+    } else {
+        Fit::Inexact(f)
+    }
+}
+
+fn flo_to_int(f: f64, to: Num) -> Fit<i64> {
+    if f.is_nan() {
+        return Fit::Invalid;
+    }
+    let (s, max) = bounds(to);
+    let p = pow10(s) as f64;
+    let r = (f * p).round() as i128;
+    let min = if to == Num::Plain { i64::MIN } else { -max };
+    if r > max as i128 {
+        Fit::OutOfRange(max)
+    } else if r < min as i128 {
+        Fit::OutOfRange(min)
+    } else if r as f64 / p == f {
+        Fit::Exact(r as i64)
+    } else {
+        Fit::Inexact(r as i64)
+    }
+}
+
+fn flo_text(f: f64) -> String {
+    match f {
+        _ if f.is_nan() => "nan".to_string(),
+        f64::INFINITY => "inf".to_string(),
+        f64::NEG_INFINITY => "-inf".to_string(),
+        _ => format!("{:?}", f),
+    }
+}
+
+fn int_text(v: i64, from: Num) -> Fit<String> {
+    match from {
+        Num::Dec(d) => Fit::Exact(d.format(v)),
+        Num::Time(t) => match time::format(t, v) {
+            Ok(s) => Fit::Exact(s),
+            Err(_) => Fit::Invalid,
+        },
+        _ => Fit::Exact(v.to_string()),
+    }
+}
+
+fn text_int(s: &str, to: Num) -> Fit<i64> {
+    let v = match to {
+        Num::Dec(d) => d.parse(s),
+        Num::Time(t) => time::parse(t, s),
+        _ => s.parse().ok(),
+    };
+    match v {
+        Some(v) => Fit::Exact(v),
+        None => Fit::Invalid,
+    }
+}
+
+fn ok_or_invalid<T>(v: Option<T>) -> Fit<T> {
+    match v {
+        Some(x) => Fit::Exact(x),
+        None => Fit::Invalid,
+    }
+}
+
+// One row of a source column.
+#[derive(Clone, Copy)]
+enum Row<'a> {
+    Bit(bool),
+    Int(i64),
+    Flo(f64),
+    Text(Option<&'a str>), // None if the bin isn't UTF-8
+}
+
+fn rows(vals: &Vals) -> Result<Vec<Row<'_>>> {
+    Ok(match vals {
+        Vals::Bits(n, bs) => (0..*n).map(|i| Row::Bit(bs.contains(i))).collect(),
+        Vals::I64s(x) => x.iter().map(|v| Row::Int(*v)).collect(),
+        Vals::F64s(x) => x.iter().map(|f| Row::Flo(f.0)).collect(),
+        Vals::Bins(x) => x
+            .iter()
+            .map(|b| match b {
+                Bin::Mem(b) => Ok(Row::Text(std::str::from_utf8(b).ok())),
+                Bin::Heap { .. } => Err(err("cannot cast a bin on the heap")),
+            })
+            .collect::<Result<_>>()?,
+        v => return Err(err(format!("cannot cast {}", type_name(v)))),
+    })
+}
+
+fn to_bit(row: Row, from: Num) -> Fit<bool> {
+    match row {
+        Row::Bit(b) => Fit::Exact(b),
+        Row::Int(v) => int_to_int(v, from, Num::Plain).then(int_to_bit),
+        Row::Flo(f) => flo_to_int(f, Num::Plain).then(int_to_bit),
+        Row::Text(Some("true")) => Fit::Exact(true),
+        Row::Text(Some("false")) => Fit::Exact(false),
+        Row::Text(_) => Fit::Invalid,
+    }
+}
+
+fn to_int(row: Row, from: Num, to: Num) -> Fit<i64> {
+    match row {
+        Row::Bit(b) => int_to_int(b as i64, Num::Plain, to),
+        Row::Int(v) => int_to_int(v, from, to),
+        Row::Flo(f) => flo_to_int(f, to),
+        Row::Text(s) => ok_or_invalid(s).then(|s| text_int(s, to)),
+    }
+}
+
+fn to_flo(row: Row, from: Num) -> Fit<f64> {
+    match row {
+        Row::Bit(b) => Fit::Exact(b as i64 as f64),
+        Row::Int(v) => int_to_flo(v, from),
+        Row::Flo(f) => Fit::Exact(f),
+        Row::Text(s) => ok_or_invalid(s.and_then(|s| s.parse().ok())),
+    }
+}
+
+fn to_text(row: Row, from: Num) -> Fit<String> {
+    match row {
+        Row::Bit(b) => Fit::Exact(b.to_string()),
+        Row::Int(v) => int_text(v, from),
+        Row::Flo(f) => Fit::Exact(flo_text(f)),
+        Row::Text(s) => ok_or_invalid(s.map(str::to_string)),
+    }
+}
+
+// Settle each row's fit by the cast's mode, giving the values and the rows
+// that become null.
+fn settle<T: Default>(fits: Vec<Fit<T>>, c: &Cast) -> Result<(Vec<T>, Vec<usize>)> {
+    let target = || Ty::new(c.to.clone()).with_minor(c.form);
+    let mut out = Vec::with_capacity(fits.len());
+    let mut nulls = Vec::new();
+    for (row, fit) in fits.into_iter().enumerate() {
+        let v = match (fit, c.mode) {
+            (Fit::Exact(x), _) | (Fit::Inexact(x), CastMode::Saturate | CastMode::Lossy) => x,
+            (Fit::OutOfRange(x), CastMode::Saturate) => x,
+            (Fit::OutOfRange(_) | Fit::Invalid, CastMode::Lossy) => {
+                nulls.push(row);
+                T::default()
+            }
+            (Fit::Inexact(_), _) => {
+                return Err(fault(
+                    FaultKind::Inexact,
+                    format!("row {} is not exact as {}", row, target()),
+                ))
+            }
+            (Fit::OutOfRange(_), _) => {
+                return Err(fault(
+                    FaultKind::Overflow,
+                    format!("row {} is out of range for {}", row, target()),
+                ))
+            }
+            (Fit::Invalid, _) => {
+                return Err(fault(
+                    FaultKind::Invalid,
+                    format!("row {} is not a valid {}", row, target()),
+                ))
+            }
+        };
+        out.push(v);
+    }
+    Ok((out, nulls))
+}
+
+pub(crate) fn cast(v: &Vals, c: &Cast) -> Result<Vals> {
+    if nulls(v).is_some() {
+        return lift(&[v], |x| cast(&x[0], c));
+    }
+    let (name, form, unit, vals) = match v {
+        Vals::Rich(col) => (Some(&col.name), col.form, col.unit, &col.vals),
+        _ => (None, Form::NONE, Unit::NONE, v),
+    };
+    let major = match vals {
+        Vals::Bits(..) => Major::Bit,
+        Vals::I64s(_) => Major::Int,
+        Vals::F64s(_) => Major::Flo,
+        Vals::Bins(_) => Major::Bin,
+        _ => return Err(err(format!("cannot cast {}", type_name(v)))),
+    };
+    let from_ty = Ty::new(major.clone()).with_minor(form);
+    let to_ty = Ty::new(c.to.clone()).with_minor(c.form);
+    let (Some(from), Some(to)) = (kind(&major, form), kind(&c.to, c.form)) else {
+        return Err(err(format!("cannot cast {} to {}", from_ty, to_ty)));
+    };
+    if !allowed(from, to) {
+        return Err(err(format!("cannot cast {} to {}", from_ty, to_ty)));
+    }
+    if from == to {
+        return Ok(v.clone());
+    }
+    let num = match from {
+        Kind::Int(n) => n,
+        _ => Num::Plain,
+    };
+    let rows = rows(vals)?;
+    let (out, unit, nulls) = match to {
+        Kind::Bit => {
+            let (bits, nulls) = settle(rows.iter().map(|r| to_bit(*r, num)).collect(), c)?;
+            let set = bits.iter().enumerate().filter(|(_, b)| **b).map(|(i, _)| i);
+            (Vals::Bits(bits.len(), set.collect()), Unit::NONE, nulls)
+        }
+        Kind::Int(n) => {
+            let (ints, nulls) = settle(rows.iter().map(|r| to_int(*r, num, n)).collect(), c)?;
+            (Vals::I64s(ints), unit, nulls)
+        }
+        Kind::Flo => {
+            let (flos, nulls) = settle(rows.iter().map(|r| to_flo(*r, num)).collect(), c)?;
+            let flos = flos.into_iter().map(OrderedFloat).collect();
+            (Vals::F64s(flos), unit, nulls)
+        }
+        Kind::Bin => {
+            let (text, nulls) = settle(rows.iter().map(|r| to_text(*r, num)).collect(), c)?;
+            let bins = text.into_iter().map(|s| Bin::Mem(s.into_bytes())).collect();
+            (Vals::Bins(bins), Unit::NONE, nulls)
+        }
+    };
+    Ok(with_nulls(enrich(name, c.form, unit, out), &nulls))
+}
+
+impl CastMode {
+    pub const ALL: [CastMode; 3] = [CastMode::Check, CastMode::Saturate, CastMode::Lossy];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CastMode::Check => "check",
+            CastMode::Saturate => "saturate",
+            CastMode::Lossy => "lossy",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<CastMode> {
+        CastMode::ALL.into_iter().find(|m| m.name() == name)
+    }
+}
+
+// A cast's target as the surface syntax writes it, eg. 'dec(9, 2)' or
+// 'int, saturate'; the mode is left out when it's Check.
+impl Display for Cast {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.form != Form::NONE {
+            write!(f, "{}", self.form)?;
+        } else {
+            write!(f, "{}", Ty::new(self.to.clone()))?;
+        }
+        if self.mode != CastMode::Check {
+            write!(f, ", {}", self.mode.name())?;
+        }
+        Ok(())
+    }
+}
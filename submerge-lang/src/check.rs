@@ -0,0 +1,1035 @@
+// The static typechecker. It infers a Ty for every node of an Expr using
+// plain (monomorphic) unification: fn parameters get inference variables that
+// are fixed by how the parameters are used or by what the fn is applied to.
+// Checking happens before an Expr is shipped as a thunk, so everything must
+// resolve to a concrete type; anything left ambiguous is an error.
+//
+// Node types come back in pre-order, the same order as SpanTree::preorder(),
+// and errors carry the pre-order index of the offending node plus its span
+// when the caller has one.
+
+use crate::{
+    cast,
+    form::{decimal_plan, temporal_plan},
+    time,
+    ty::{Major, Ty},
+    unit::{binop_unit, unop_unit},
+    AggOp, BinFn, BitFn, Cast, Decimal, Expr, Form, Grouping, Join, Path, PrimBinOp, PrimUnOp,
+    SortKey, Span, SpanTree, TimeFn, Unit, Word, MAX_DECIMAL_PRECISION,
+};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+
+// Types of the paths an Expr can refer to without binding them itself: the
+// schema of the environment it will be evaluated in.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TyEnv {
+    paths: BTreeMap<Path, Ty>,
+}
+
+impl TyEnv {
+    pub fn new() -> TyEnv {
+        TyEnv::default()
+    }
+    pub fn insert(&mut self, path: Path, ty: Ty) {
+        self.paths.insert(path, ty);
+    }
+    pub fn get(&self, path: &Path) -> Option<&Ty> {
+        self.paths.get(path)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Typing {
+    pub tys: Vec<Ty>,
+}
+
+impl Typing {
+    pub fn root(&self) -> &Ty {
+        &self.tys[0]
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TypeErrorKind {
+    Mismatch { expected: Ty, found: Ty },
+    // The type isn't in the class an operator needs, eg. "a number".
+    NotA { class: &'static str, found: Ty },
+    Unbound(Path),
+    NoField { field: Word, found: Ty },
+    DuplicateField(Word),
+    Arity { expected: usize, found: usize },
+    Ambiguous(Ty),
+    // A splice with no quote around it to fill.
+    Unquoted,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TypeError {
+    pub node: usize,
+    pub span: Option<Span>,
+    pub kind: Box<TypeErrorKind>,
+}
+
+impl Display for TypeErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeErrorKind::Mismatch { expected, found } => {
+                write!(f, "expected {}, found {}", expected, found)
+            }
+            TypeErrorKind::NotA { class, found } => {
+                write!(f, "expected {}, found {}", class, found)
+            }
+            TypeErrorKind::Unbound(path) => write!(f, "unbound path {}", path),
+            TypeErrorKind::NoField { field, found } => write!(f, "no field {} in {}", field, found),
+            TypeErrorKind::DuplicateField(field) => write!(f, "duplicate field {}", field),
+            TypeErrorKind::Arity { expected, found } => {
+                write!(f, "expected {} arguments, found {}", expected, found)
+            }
+            TypeErrorKind::Ambiguous(ty) => write!(f, "cannot infer a type (got {})", ty),
+            TypeErrorKind::Unquoted => f.write_str("splice outside quote"),
+        }
+    }
+}
+
+impl Display for TypeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.span {
+            Some(s) => write!(f, "type error at {}..{}: {}", s.lo, s.hi, self.kind),
+            None => write!(f, "type error at node {}: {}", self.node, self.kind),
+        }
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+pub fn check(expr: &Expr, env: &TyEnv, spans: Option<&SpanTree>) -> Result<Typing, TypeError> {
+    let mut c = Checker {
+        env,
+        scope: Vec::new(),
+        subst: Vec::new(),
+        tys: Vec::new(),
+        deferred: Vec::new(),
+    };
+    c.run(expr).map_err(|(node, kind)| TypeError {
+        node,
+        span: spans.and_then(|s| s.preorder().get(node).copied()),
+        kind: Box::new(kind),
+    })
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Class {
+    Num,    // Int or Flo
+    Logic,  // Bit or Int
+    Scalar, // Any of the 4 normal column types
+}
+
+impl Class {
+    fn name(self) -> &'static str {
+        match self {
+            Class::Num => "a number",
+            Class::Logic => "a bit or int",
+            Class::Scalar => "a bit, int, flo or bin",
+        }
+    }
+    fn admits(self, major: &Major) -> bool {
+        match self {
+            Class::Num => matches!(major, Major::Int | Major::Flo),
+            Class::Logic => matches!(major, Major::Bit | Major::Int),
+            Class::Scalar => matches!(major, Major::Bit | Major::Int | Major::Flo | Major::Bin),
+        }
+    }
+}
+
+type CheckResult<T> = Result<T, (usize, TypeErrorKind)>;
+
+struct Checker<'a> {
+    env: &'a TyEnv,
+    scope: Vec<(Word, Ty)>,
+    subst: Vec<Option<Ty>>,
+    tys: Vec<Ty>,
+    // Class constraints on types that were still variables when we met them.
+    deferred: Vec<(usize, Ty, Class)>,
+}
+
+impl<'a> Checker<'a> {
+    fn run(&mut self, expr: &Expr) -> CheckResult<Typing> {
+        self.infer(expr)?;
+        for (node, ty, class) in std::mem::take(&mut self.deferred) {
+            self.require(node, &ty, class, false)?;
+        }
+        let mut tys = Vec::with_capacity(self.tys.len());
+        for (node, ty) in std::mem::take(&mut self.tys).iter().enumerate() {
+            let ty = self.resolve(ty);
+            if has_var(&ty) {
+                return Err((node, TypeErrorKind::Ambiguous(ty)));
+            }
+            tys.push(ty);
+        }
+        Ok(Typing { tys })
+    }
+
+    fn fresh(&mut self) -> Ty {
+        self.subst.push(None);
+        Ty::new(Major::Var(self.subst.len() as u32 - 1))
+    }
+
+    // Follow variable bindings at the top level only.
+    fn shallow(&self, ty: &Ty) -> Ty {
+        let mut ty = ty.clone();
+        while let Major::Var(v) = ty.major {
+            match &self.subst[v as usize] {
+                Some(t) => ty = t.clone(),
+                None => break,
+            }
+        }
+        ty
+    }
+
+    fn resolve(&self, ty: &Ty) -> Ty {
+        let ty = self.shallow(ty);
+        let fields = |fs: &[(Word, Ty)]| {
+            fs.iter()
+                .map(|(w, t)| (w.clone(), self.resolve(t)))
+                .collect()
+        };
+        let major = match &ty.major {
+            Major::All(fs) => Major::All(fields(fs)),
+            Major::Tab(fs) => Major::Tab(fields(fs)),
+            Major::Any(vs) => Major::Any(vs.iter().map(|t| self.resolve(t)).collect()),
+            Major::Fn(ps, r) => Major::Fn(
+                ps.iter().map(|t| self.resolve(t)).collect(),
+                Box::new(self.resolve(r)),
+            ),
+            m => m.clone(),
+        };
+        Ty { major, ..ty }
+    }
+
+    fn occurs(&self, v: u32, ty: &Ty) -> bool {
+        let ty = self.resolve(ty);
+        let mut found = false;
+        visit_tys(&ty, &mut |t| found |= t.major == Major::Var(v));
+        found
+    }
+
+    // Unify two types, returning the unified type or the pair (resolved)
+    // that failed to unify. A minor or role that's unset on one side takes
+    // the other side's, so that literals combine with formed and united
+    // columns.
+    fn unify(&mut self, a: &Ty, b: &Ty) -> Result<Ty, (Ty, Ty)> {
+        let (a, b) = (self.shallow(a), self.shallow(b));
+        let fail = |c: &Checker<'_>| (c.resolve(&a), c.resolve(&b));
+        if let Major::Var(v) = a.major {
+            if a == b {
+                return Ok(a);
+            }
+            if self.occurs(v, &b) {
+                return Err(fail(self));
+            }
+            self.subst[v as usize] = Some(b.clone());
+            return Ok(b);
+        }
+        if let Major::Var(_) = b.major {
+            return self.unify(&b, &a);
+        }
+        let minor = match (a.minor, b.minor) {
+            (x, y) if x == y => x,
+            (Form::NONE, y) => y,
+            (x, Form::NONE) => x,
+            _ => return Err(fail(self)),
+        };
+        let role = match (a.role, b.role) {
+            (x, y) if x == y => x,
+            (Unit::NONE, y) => y,
+            (x, Unit::NONE) => x,
+            _ => return Err(fail(self)),
+        };
+        let major = match (&a.major, &b.major) {
+            (Major::All(x), Major::All(y)) => {
+                Major::All(self.unify_fields(x, y).map_err(|_| fail(self))?)
+            }
+            (Major::Tab(x), Major::Tab(y)) => {
+                Major::Tab(self.unify_fields(x, y).map_err(|_| fail(self))?)
+            }
+            (Major::Any(x), Major::Any(y)) if x.len() == y.len() => {
+                let mut vs = Vec::new();
+                for (x, y) in x.iter().zip(y) {
+                    vs.push(self.unify(x, y).map_err(|_| fail(self))?);
+                }
+                Major::Any(vs)
+            }
+            (Major::Fn(xp, xr), Major::Fn(yp, yr)) if xp.len() == yp.len() => {
+                let mut ps = Vec::new();
+                for (x, y) in xp.iter().zip(yp) {
+                    ps.push(self.unify(x, y).map_err(|_| fail(self))?);
+                }
+                let r = self.unify(xr, yr).map_err(|_| fail(self))?;
+                Major::Fn(ps, Box::new(r))
+            }
+            (x, y) if x == y => x.clone(),
+            _ => return Err(fail(self)),
+        };
+        Ok(Ty { major, minor, role })
+    }
+
+    fn unify_fields(&mut self, x: &[(Word, Ty)], y: &[(Word, Ty)]) -> Result<Vec<(Word, Ty)>, ()> {
+        if x.len() != y.len() {
+            return Err(());
+        }
+        let mut out = Vec::new();
+        for ((xw, xt), (yw, yt)) in x.iter().zip(y) {
+            if xw != yw {
+                return Err(());
+            }
+            out.push((xw.clone(), self.unify(xt, yt).map_err(|_| ())?));
+        }
+        Ok(out)
+    }
+
+    fn expect(&mut self, node: usize, found: &Ty, expected: &Ty) -> CheckResult<Ty> {
+        self.unify(expected, found)
+            .map_err(|(expected, found)| (node, TypeErrorKind::Mismatch { expected, found }))
+    }
+
+    // Check a class constraint now if the type is known, else defer it (when
+    // allowed) to the end of checking.
+    fn require(&mut self, node: usize, ty: &Ty, class: Class, defer: bool) -> CheckResult<()> {
+        let ty = self.shallow(ty);
+        match &ty.major {
+            Major::Var(_) if defer => {
+                self.deferred.push((node, ty, class));
+                Ok(())
+            }
+            Major::Var(_) => Err((node, TypeErrorKind::Ambiguous(ty))),
+            m if class.admits(m) => Ok(()),
+            _ => Err((
+                node,
+                TypeErrorKind::NotA {
+                    class: class.name(),
+                    found: self.resolve(&ty),
+                },
+            )),
+        }
+    }
+
+    // Infer a child node's type, returning its pre-order index too.
+    fn child(&mut self, e: &Expr) -> CheckResult<(usize, Ty)> {
+        let node = self.tys.len();
+        let ty = self.infer(e)?;
+        Ok((node, ty))
+    }
+
+    fn infer(&mut self, e: &Expr) -> CheckResult<Ty> {
+        let node = self.tys.len();
+        self.tys.push(Ty::new(Major::Nil));
+        let ty = self.infer_node(node, e)?;
+        self.tys[node] = ty.clone();
+        Ok(ty)
+    }
+
+    fn infer_node(&mut self, node: usize, e: &Expr) -> CheckResult<Ty> {
+        match e {
+            Expr::Pass => Ok(Ty::new(Major::Nil)),
+            Expr::Reify => Ok(Ty::new(Major::Env)),
+            Expr::Lit(vals) => Ok(Ty::of_vals(vals)),
+            Expr::Path(path) => self.path(node, path),
+            Expr::Let(name, val, body) => {
+                let (_, vt) = self.child(val)?;
+                self.scope.push((name.clone(), vt));
+                let bt = self.infer(body);
+                self.scope.pop();
+                bt
+            }
+            Expr::Lam(params, body) => {
+                let pts: Vec<Ty> = params.iter().map(|_| self.fresh()).collect();
+                for (p, t) in params.iter().zip(&pts) {
+                    self.scope.push((p.clone(), t.clone()));
+                }
+                let bt = self.infer(body);
+                self.scope.truncate(self.scope.len() - params.len());
+                Ok(Ty::new(Major::Fn(pts, Box::new(bt?))))
+            }
+            Expr::App(func, args) => {
+                let (fnode, ft) = self.child(func)?;
+                let mut ats = Vec::new();
+                for a in args {
+                    ats.push(self.child(a)?.1);
+                }
+                if let Major::Fn(ps, _) = &self.shallow(&ft).major {
+                    if ps.len() != ats.len() {
+                        let kind = TypeErrorKind::Arity {
+                            expected: ps.len(),
+                            found: ats.len(),
+                        };
+                        return Err((node, kind));
+                    }
+                }
+                let ret = self.fresh();
+                let want = Ty::new(Major::Fn(ats, Box::new(ret.clone())));
+                self.expect(fnode, &ft, &want)?;
+                Ok(ret)
+            }
+            Expr::If(c, t, f) => {
+                let (cn, ct) = self.child(c)?;
+                self.expect(cn, &ct, &Ty::bit())?;
+                let (_, tt) = self.child(t)?;
+                let (fnode, ft) = self.child(f)?;
+                self.expect(fnode, &ft, &tt)
+            }
+            Expr::BinOp(op, a, b) => {
+                let (an, at) = self.child(a)?;
+                let (bn, bt) = self.child(b)?;
+                self.binop(*op, (an, at), (bn, bt))
+            }
+            Expr::UnOp(op, a) => {
+                let (an, at) = self.child(a)?;
+                self.unop(*op, an, at)
+            }
+            Expr::BinFn(f, args) => self.binfn(node, *f, args),
+            Expr::TimeFn(f, args) => self.timefn(node, *f, args),
+            Expr::BitFn(f, args) => self.bitfn(node, *f, args),
+            Expr::Rec(fields) => Ok(Ty::new(Major::All(self.fields(node, fields)?))),
+            Expr::Tab(fields) => Ok(Ty::new(Major::Tab(self.fields(node, fields)?))),
+            Expr::Query(env, path) => {
+                let (en, et) = self.child(env)?;
+                self.expect(en, &et, &Ty::new(Major::Env))?;
+                match self.env.get(path) {
+                    Some(t) => Ok(t.clone()),
+                    None => Err((node, TypeErrorKind::Unbound(path.clone()))),
+                }
+            }
+            Expr::Merge(a, b) => {
+                let (an, at) = self.child(a)?;
+                let (bn, bt) = self.child(b)?;
+                self.merge((an, at), (bn, bt))
+            }
+            Expr::Convert(a, unit) => {
+                let (an, at) = self.child(a)?;
+                self.convert(an, at, *unit)
+            }
+            Expr::Cast(a, c) => {
+                let (an, at) = self.child(a)?;
+                self.cast(an, at, c)
+            }
+            Expr::Group(tab, g) => {
+                let (tn, tt) = self.child(tab)?;
+                self.group(node, tn, tt, g)
+            }
+            Expr::Join(a, b, j) => {
+                let (an, at) = self.child(a)?;
+                let (bn, bt) = self.child(b)?;
+                self.join(node, (an, at), (bn, bt), j)
+            }
+            Expr::Sort(tab, keys) => {
+                let (tn, tt) = self.child(tab)?;
+                self.sort(node, tn, tt, keys)
+            }
+            Expr::Filter(tab, pred) => {
+                let (tn, tt) = self.child(tab)?;
+                self.filter(tn, tt, pred)
+            }
+            Expr::Quote(body) => {
+                self.quoted(body, 0)?;
+                Ok(Ty::bin().with_minor(Form::CODE))
+            }
+            Expr::Splice(_) => Err((node, TypeErrorKind::Unquoted)),
+        }
+    }
+
+    // Quoted nodes run in a later stage, so they get no type here, except
+    // the operands of the holes, which run now and must be data or code.
+    fn quoted(&mut self, e: &Expr, depth: usize) -> CheckResult<()> {
+        self.tys.push(Ty::new(Major::Nil));
+        match e {
+            Expr::Splice(x) if depth == 0 => {
+                let (xn, xt) = self.child(x)?;
+                if let Major::Fn(..) = self.shallow(&xt).major {
+                    let class = "data or code";
+                    let found = self.resolve(&xt);
+                    return Err((xn, TypeErrorKind::NotA { class, found }));
+                }
+                Ok(())
+            }
+            Expr::Splice(x) => self.quoted(x, depth - 1),
+            Expr::Quote(x) => self.quoted(x, depth + 1),
+            _ => e
+                .children()
+                .into_iter()
+                .try_for_each(|c| self.quoted(c, depth)),
+        }
+    }
+
+    fn fields(&mut self, node: usize, fields: &[(Word, Expr)]) -> CheckResult<Vec<(Word, Ty)>> {
+        let mut out: Vec<(Word, Ty)> = Vec::new();
+        for (w, e) in fields {
+            if out.iter().any(|(x, _)| x == w) {
+                return Err((node, TypeErrorKind::DuplicateField(w.clone())));
+            }
+            let (_, t) = self.child(e)?;
+            out.push((w.clone(), t));
+        }
+        Ok(out)
+    }
+
+    fn path(&mut self, node: usize, path: &Path) -> CheckResult<Ty> {
+        let Some((first, rest)) = path.0.split_first() else {
+            return Err((node, TypeErrorKind::Unbound(path.clone())));
+        };
+        // A path that starts at a local projects through its fields.
+        if let Some((_, ty)) = self.scope.iter().rev().find(|(w, _)| w == first) {
+            return self.project(node, ty.clone(), rest);
+        }
+        if let Some(t) = self.env.get(path) {
+            return Ok(t.clone());
+        }
+        // So does one that runs past a record in the environment.
+        for n in (1..path.0.len()).rev() {
+            if let Some(t) = self.env.get(&Path(path.0[..n].to_vec())) {
+                if matches!(t.major, Major::All(_)) {
+                    return self.project(node, t.clone(), &path.0[n..]);
+                }
+            }
+        }
+        Err((node, TypeErrorKind::Unbound(path.clone())))
+    }
+
+    fn project(&mut self, node: usize, mut ty: Ty, fields: &[Word]) -> CheckResult<Ty> {
+        for field in fields {
+            let t = self.resolve(&ty);
+            let fs = match &t.major {
+                Major::All(fs) | Major::Tab(fs) => fs,
+                Major::Var(_) => return Err((node, TypeErrorKind::Ambiguous(t))),
+                _ => {
+                    let kind = TypeErrorKind::NoField {
+                        field: field.clone(),
+                        found: t.clone(),
+                    };
+                    return Err((node, kind));
+                }
+            };
+            // As at runtime, a label shadows a position.
+            let by_label = fs.iter().position(|(w, _)| w == field);
+            let by_pos = || (0..fs.len()).find(|i| Word::positional(*i) == *field);
+            match by_label.or_else(by_pos).map(|i| &fs[i]) {
+                Some((_, ft)) => ty = ft.clone(),
+                None => {
+                    let kind = TypeErrorKind::NoField {
+                        field: field.clone(),
+                        found: t.clone(),
+                    };
+                    return Err((node, kind));
+                }
+            }
+        }
+        Ok(ty)
+    }
+
+    // Once both operands' types are known, their units combine by the
+    // kernel's rules and the rest of the check ignores them. Until then,
+    // unification makes the units agree.
+    fn binop(&mut self, op: PrimBinOp, a: (usize, Ty), b: (usize, Ty)) -> CheckResult<Ty> {
+        let (at, bt) = (self.shallow(&a.1), self.shallow(&b.1));
+        if at.is_var() || bt.is_var() {
+            return self.binop_shape(op, a, b);
+        }
+        let Some(unit) = binop_unit(op, at.role, bt.role) else {
+            return Err(self.mismatch(b.0, &at, &bt));
+        };
+        let a = (a.0, at.with_role(Unit::NONE));
+        let b = (b.0, bt.with_role(Unit::NONE));
+        Ok(self.binop_shape(op, a, b)?.with_role(unit))
+    }
+
+    fn binop_shape(&mut self, op: PrimBinOp, a: (usize, Ty), b: (usize, Ty)) -> CheckResult<Ty> {
+        use PrimBinOp::*;
+        let (an, at) = a;
+        let (bn, bt) = b;
+        if let Some(t) = self.temporal_binop(op, (an, &at), (bn, &bt))? {
+            return Ok(t);
+        }
+        if let Some(t) = self.decimal_binop(op, (an, &at), (bn, &bt))? {
+            return Ok(t);
+        }
+        match op {
+            Shl | Shr | Asr | Rol | Ror => {
+                let t = self.expect(an, &at, &Ty::int())?;
+                self.expect(bn, &bt, &Ty::int())?;
+                return Ok(t);
+            }
+            _ => {}
+        }
+        let t = self.expect(bn, &bt, &at)?;
+        let class = match op {
+            Add | Sub | Mul | Div | Mod | Pow | Min | Max => Class::Num,
+            And | Or | Xor => Class::Logic,
+            _ => Class::Scalar,
+        };
+        self.require(an, &t, class, true)?;
+        Ok(match op {
+            Eq | Ne | Lt | Le | Gt | Ge => Ty::bit(),
+            Cmp => Ty::int(),
+            _ => t,
+        })
+    }
+
+    // Temporal operands combine as form.rs says. Returns None if neither
+    // side is known to be temporal.
+    fn temporal_binop(
+        &mut self,
+        op: PrimBinOp,
+        a: (usize, &Ty),
+        b: (usize, &Ty),
+    ) -> CheckResult<Option<Ty>> {
+        use PrimBinOp::*;
+        let (at, bt) = (self.shallow(a.1), self.shallow(b.1));
+        let temporal = |t: &Ty| t.minor.as_temporal().is_some();
+        if (!temporal(&at) && !temporal(&bt)) || at.is_var() || bt.is_var() {
+            return Ok(None);
+        }
+        if at.major != Major::Int {
+            return Err(self.mismatch(a.0, &bt, &at));
+        }
+        if bt.major != Major::Int {
+            return Err(self.mismatch(b.0, &at, &bt));
+        }
+        let Some(form) = temporal_plan(op, at.minor, bt.minor) else {
+            return Err(self.mismatch(b.0, &at, &bt));
+        };
+        Ok(Some(match op {
+            Eq | Ne | Lt | Le | Gt | Ge => Ty::bit(),
+            _ => Ty::int().with_minor(form),
+        }))
+    }
+
+    // Decimal operands follow the kernel's rescaling rules, with a plain int
+    // acting as a decimal of scale 0. Returns None if neither side is known
+    // to be a decimal.
+    fn decimal_binop(
+        &mut self,
+        op: PrimBinOp,
+        a: (usize, &Ty),
+        b: (usize, &Ty),
+    ) -> CheckResult<Option<Ty>> {
+        use PrimBinOp::*;
+        let (at, bt) = (self.shallow(a.1), self.shallow(b.1));
+        let decimal = |t: &Ty| match (&t.major, t.minor.as_decimal()) {
+            (Major::Int, Some(d)) => Some(d),
+            (Major::Int, None) if t.minor == Form::NONE => Some(Decimal::INTEGER),
+            _ => None,
+        };
+        let is_dec = |t: &Ty| t.major == Major::Int && t.minor.as_decimal().is_some();
+        if !is_dec(&at) && !is_dec(&bt) {
+            return Ok(None);
+        }
+        if at.is_var() || bt.is_var() {
+            return Ok(None);
+        }
+        let (da, db) = match (decimal(&at), decimal(&bt)) {
+            (Some(da), Some(db)) => (da, db),
+            (None, _) => return Err(self.mismatch(a.0, &bt, &at)),
+            (_, None) => return Err(self.mismatch(b.0, &at, &bt)),
+        };
+        let Some(plan) = decimal_plan(op, da, db) else {
+            let class = "a non-decimal operand";
+            return Err((a.0, TypeErrorKind::NotA { class, found: at }));
+        };
+        Ok(Some(match (op, plan.result) {
+            (Eq | Ne | Lt | Le | Gt | Ge, _) => Ty::bit(),
+            (Cmp, _) => Ty::int(),
+            (_, Some(d)) => Ty::int().with_minor(d.into()),
+            (_, None) => Ty::int(),
+        }))
+    }
+
+    fn mismatch(&self, node: usize, expected: &Ty, found: &Ty) -> (usize, TypeErrorKind) {
+        let kind = TypeErrorKind::Mismatch {
+            expected: self.resolve(expected),
+            found: self.resolve(found),
+        };
+        (node, kind)
+    }
+
+    // Units go around unops as they do binops.
+    fn unop(&mut self, op: PrimUnOp, an: usize, at: Ty) -> CheckResult<Ty> {
+        let at = self.shallow(&at);
+        if at.is_var() {
+            return self.unop_shape(op, an, at);
+        }
+        let Some(unit) = unop_unit(op, at.role) else {
+            let class = match op {
+                PrimUnOp::Sqrt => "a number in square units",
+                _ => "a dimensionless number",
+            };
+            return Err((an, TypeErrorKind::NotA { class, found: at }));
+        };
+        let t = self.unop_shape(op, an, at.with_role(Unit::NONE))?;
+        Ok(t.with_role(unit))
+    }
+
+    fn unop_shape(&mut self, op: PrimUnOp, an: usize, at: Ty) -> CheckResult<Ty> {
+        use PrimUnOp::*;
+        match op {
+            Neg | Abs => {
+                self.require(an, &at, Class::Num, true)?;
+                Ok(at)
+            }
+            // A sign is a plain number, whatever the operand's scale.
+            Sgn => {
+                self.require(an, &at, Class::Num, true)?;
+                let at = self.shallow(&at);
+                match at.minor.as_decimal() {
+                    Some(_) => Ok(at.with_minor(Form::NONE)),
+                    None => Ok(at),
+                }
+            }
+            Not => {
+                self.require(an, &at, Class::Logic, true)?;
+                Ok(at)
+            }
+            Popcnt | Clz | Ctz | Bitrev | ByteSwap => {
+                let t = self.expect(an, &at, &Ty::int())?;
+                if t.minor.as_decimal().is_some() {
+                    let class = "a plain int";
+                    return Err((an, TypeErrorKind::NotA { class, found: t }));
+                }
+                Ok(t)
+            }
+            BitCount => {
+                self.expect(an, &at, &Ty::bit())?;
+                Ok(Ty::int())
+            }
+            BitParity => self.expect(an, &at, &Ty::bit()),
+            Sqrt | Exp | Exp2 | Exp10 | Log | Log2 | Log10 | Sin | Cos | Tan | Asin | Acos
+            | Atan | Sinh | Cosh | Tanh | Asinh | Acosh | Atanh | Floor | Ceil | Trunc | Recip => {
+                self.expect(an, &at, &Ty::flo())
+            }
+        }
+    }
+
+    // Bin functions take bins, but for slice's start and length.
+    fn binfn(&mut self, node: usize, f: BinFn, args: &[Expr]) -> CheckResult<Ty> {
+        if args.len() != f.arity() {
+            let kind = TypeErrorKind::Arity {
+                expected: f.arity(),
+                found: args.len(),
+            };
+            return Err((node, kind));
+        }
+        for (i, a) in args.iter().enumerate() {
+            let (an, at) = self.child(a)?;
+            let want = if i > 0 && f == BinFn::Slice {
+                Ty::int()
+            } else {
+                Ty::bin()
+            };
+            self.expect(an, &at, &want)?;
+        }
+        Ok(match f {
+            BinFn::Len => Ty::int(),
+            BinFn::StartsWith | BinFn::EndsWith | BinFn::Like => Ty::bit(),
+            BinFn::Concat | BinFn::Slice | BinFn::Lower | BinFn::Upper => Ty::bin(),
+        })
+    }
+
+    // Bit functions take a bit column as a set of rows, but for mask, which
+    // takes the rows, and the ints to look up or the length.
+    fn bitfn(&mut self, node: usize, f: BitFn, args: &[Expr]) -> CheckResult<Ty> {
+        if args.len() != f.arity() {
+            let kind = TypeErrorKind::Arity {
+                expected: f.arity(),
+                found: args.len(),
+            };
+            return Err((node, kind));
+        }
+        for (i, a) in args.iter().enumerate() {
+            let (an, at) = self.child(a)?;
+            let want = if i > 0 || f == BitFn::Mask {
+                Ty::int()
+            } else {
+                Ty::bit()
+            };
+            self.expect(an, &at, &want)?;
+        }
+        Ok(match f {
+            BitFn::Mask => Ty::bit(),
+            BitFn::Rank | BitFn::Select | BitFn::Rows => Ty::int(),
+        })
+    }
+
+    // Calendar functions take an int of a temporal form, or a plain int to
+    // convert, and add_months a plain count as well.
+    fn timefn(&mut self, node: usize, f: TimeFn, args: &[Expr]) -> CheckResult<Ty> {
+        if args.len() != f.arity() {
+            let kind = TypeErrorKind::Arity {
+                expected: f.arity(),
+                found: args.len(),
+            };
+            return Err((node, kind));
+        }
+        let (an, at) = self.child(&args[0])?;
+        let at = self.expect(an, &at, &Ty::int())?;
+        let at = self.resolve(&at);
+        let t = at.minor.as_temporal();
+        let form = match time::result_form(f, t) {
+            Some(form) if t.is_some() || at.minor == Form::NONE => form,
+            _ => {
+                let class = "a timestamp or date";
+                return Err((an, TypeErrorKind::NotA { class, found: at }));
+            }
+        };
+        if let Some(n) = args.get(1) {
+            let (nn, nt) = self.child(n)?;
+            let nt = self.expect(nn, &nt, &Ty::int())?;
+            let nt = self.resolve(&nt);
+            if nt.minor != Form::NONE {
+                return Err(self.mismatch(nn, &Ty::int(), &nt));
+            }
+        }
+        Ok(Ty::int().with_minor(form))
+    }
+
+    // A number with no unit takes on the target unit; one with a unit must
+    // have the target's dimension.
+    fn convert(&mut self, an: usize, at: Ty, to: Unit) -> CheckResult<Ty> {
+        let at = self.shallow(&at);
+        if at.is_var() {
+            return Err((an, TypeErrorKind::Ambiguous(at)));
+        }
+        self.require(an, &at, Class::Num, false)?;
+        if at.role != Unit::NONE && at.role.conversion(to).is_none() {
+            return Err(self.mismatch(an, &at.clone().with_role(to), &at));
+        }
+        Ok(at.with_role(to))
+    }
+
+    // Casts keep the unit of a number that stays a number.
+    fn cast(&mut self, an: usize, at: Ty, c: &Cast) -> CheckResult<Ty> {
+        let at = self.shallow(&at);
+        if at.is_var() {
+            return Err((an, TypeErrorKind::Ambiguous(at)));
+        }
+        self.require(an, &at, Class::Scalar, false)?;
+        let to = Ty::new(c.to.clone()).with_minor(c.form);
+        if !cast::castable(&at.major, at.minor, c) {
+            return Err(self.mismatch(an, &to, &at));
+        }
+        match c.to {
+            Major::Int | Major::Flo => Ok(to.with_role(at.role)),
+            _ => Ok(to),
+        }
+    }
+
+    // Keys must be scalars. Aggregates type as the kernels in agg.rs
+    // compute them, and the result is shaped like the input: a table, or a
+    // record of columns.
+    fn group(&mut self, node: usize, tn: usize, tt: Ty, g: &Grouping) -> CheckResult<Ty> {
+        let tt = self.resolve(&tt);
+        let is_tab = match &tt.major {
+            Major::Tab(_) => true,
+            Major::All(_) => false,
+            Major::Var(_) => return Err((tn, TypeErrorKind::Ambiguous(tt))),
+            _ => {
+                let class = "a table or record";
+                return Err((tn, TypeErrorKind::NotA { class, found: tt }));
+            }
+        };
+        let mut out: Vec<(Word, Ty)> = Vec::new();
+        for key in &g.keys {
+            let kt = self.project(node, tt.clone(), &key.0)?;
+            let kt = self.resolve(&kt);
+            self.require(node, &kt, Class::Scalar, false)?;
+            let Some(name) = key.0.last() else {
+                return Err((node, TypeErrorKind::Unbound(key.clone())));
+            };
+            out.push((name.clone(), kt));
+        }
+        for a in &g.aggs {
+            let at = self.project(node, tt.clone(), &a.arg.0)?;
+            let at = self.resolve(&at);
+            let decimal = at.minor.as_decimal();
+            let ty = match (a.op, &at.major) {
+                (AggOp::Count, _) => Ty::int(),
+                (AggOp::Min | AggOp::Max, _) => {
+                    self.require(node, &at, Class::Scalar, false)?;
+                    at
+                }
+                (AggOp::Sum, Major::Bit) => Ty::int(),
+                (AggOp::Mean, Major::Bit) => Ty::flo(),
+                (AggOp::Sum, Major::Int) => match decimal {
+                    Some(d) => at.with_minor(
+                        Form::decimal(MAX_DECIMAL_PRECISION, d.scale).expect("scale fits"),
+                    ),
+                    None => at,
+                },
+                (AggOp::Mean, Major::Int) if decimal.is_none() => Ty::flo().with_role(at.role),
+                (AggOp::Sum | AggOp::Mean, Major::Int | Major::Flo) => at,
+                (AggOp::Sum | AggOp::Mean, _) => {
+                    let class = "a number or bit";
+                    return Err((node, TypeErrorKind::NotA { class, found: at }));
+                }
+            };
+            out.push((a.name.clone(), ty));
+        }
+        for (i, (w, _)) in out.iter().enumerate() {
+            if out[..i].iter().any(|(x, _)| x == w) {
+                return Err((node, TypeErrorKind::DuplicateField(w.clone())));
+            }
+        }
+        Ok(Ty::new(if is_tab {
+            Major::Tab(out)
+        } else {
+            Major::All(out)
+        }))
+    }
+
+    // The predicate sees the table's fields as locals, and must be a bit.
+    fn filter(&mut self, tn: usize, tt: Ty, pred: &Expr) -> CheckResult<Ty> {
+        let tt = self.resolve(&tt);
+        let fields = match &tt.major {
+            Major::Tab(fs) | Major::All(fs) => fs.clone(),
+            Major::Var(_) => return Err((tn, TypeErrorKind::Ambiguous(tt))),
+            _ => {
+                let class = "a table or record";
+                return Err((tn, TypeErrorKind::NotA { class, found: tt }));
+            }
+        };
+        let n = fields.len();
+        self.scope.extend(fields);
+        let pt = self.child(pred);
+        self.scope.truncate(self.scope.len() - n);
+        let (pn, pt) = pt?;
+        self.expect(pn, &pt, &Ty::bit())?;
+        Ok(tt)
+    }
+
+    // Sorting a table or record by scalar keys leaves its type alone.
+    fn sort(&mut self, node: usize, tn: usize, tt: Ty, keys: &[SortKey]) -> CheckResult<Ty> {
+        let tt = self.resolve(&tt);
+        match &tt.major {
+            Major::Tab(_) | Major::All(_) => {}
+            Major::Var(_) => return Err((tn, TypeErrorKind::Ambiguous(tt))),
+            _ => {
+                let class = "a table or record";
+                return Err((tn, TypeErrorKind::NotA { class, found: tt }));
+            }
+        }
+        for k in keys {
+            let kt = self.project(node, tt.clone(), &k.path.0)?;
+            let kt = self.resolve(&kt);
+            self.require(node, &kt, Class::Scalar, false)?;
+        }
+        Ok(tt)
+    }
+
+    // Both sides must be tables, or both records. Keys are ints or bins of
+    // the same type on each side, and the output has the left fields, then
+    // the right ones but for keys with the same name as their left key.
+    fn join(&mut self, node: usize, a: (usize, Ty), b: (usize, Ty), j: &Join) -> CheckResult<Ty> {
+        let (an, at) = (a.0, self.resolve(&a.1));
+        let (bn, bt) = (b.0, self.resolve(&b.1));
+        let (is_tab, x, y) = match (&at.major, &bt.major) {
+            (Major::Tab(x), Major::Tab(y)) => (true, x.clone(), y.clone()),
+            (Major::All(x), Major::All(y)) => (false, x.clone(), y.clone()),
+            (Major::Var(_), _) => return Err((an, TypeErrorKind::Ambiguous(at))),
+            (_, Major::Var(_)) => return Err((bn, TypeErrorKind::Ambiguous(bt))),
+            (Major::Tab(_) | Major::All(_), _) => return Err(self.mismatch(bn, &at, &bt)),
+            _ => {
+                let class = "a table or record";
+                return Err((an, TypeErrorKind::NotA { class, found: at }));
+            }
+        };
+        for (l, r) in &j.on {
+            let lt = self.project(node, at.clone(), &l.0)?;
+            let lt = self.resolve(&lt);
+            if !matches!(lt.major, Major::Int | Major::Bin) {
+                let class = "an int or bin";
+                return Err((node, TypeErrorKind::NotA { class, found: lt }));
+            }
+            let rt = self.project(node, bt.clone(), &r.0)?;
+            self.expect(node, &rt, &lt)?;
+        }
+        let mut out = x;
+        for (w, t) in y {
+            let merged =
+                j.on.iter()
+                    .any(|(l, r)| r.0 == [w.clone()] && l.0 == [w.clone()]);
+            if merged {
+                continue;
+            }
+            if out.iter().any(|(x, _)| *x == w) {
+                return Err((node, TypeErrorKind::DuplicateField(w)));
+            }
+            out.push((w, t));
+        }
+        Ok(Ty::new(if is_tab {
+            Major::Tab(out)
+        } else {
+            Major::All(out)
+        }))
+    }
+
+    // Merging prefers the right-hand side's fields where labels collide,
+    // except that colliding records merge recursively, as env.rs describes.
+    fn merge(&mut self, a: (usize, Ty), b: (usize, Ty)) -> CheckResult<Ty> {
+        let (an, at) = (a.0, self.resolve(&a.1));
+        let (bn, bt) = (b.0, self.resolve(&b.1));
+        fn merged(x: &[(Word, Ty)], y: &[(Word, Ty)]) -> Vec<(Word, Ty)> {
+            let mut out: Vec<(Word, Ty)> = x.to_vec();
+            for (w, t) in y {
+                match out.iter_mut().find(|(v, _)| v == w) {
+                    Some(slot) => {
+                        slot.1 = match (&slot.1.major, &t.major) {
+                            (Major::All(p), Major::All(q)) => Ty::new(Major::All(merged(p, q))),
+                            (Major::Tab(p), Major::Tab(q)) => Ty::new(Major::Tab(merged(p, q))),
+                            _ => t.clone(),
+                        }
+                    }
+                    None => out.push((w.clone(), t.clone())),
+                }
+            }
+            out
+        }
+        match (&at.major, &bt.major) {
+            (Major::Env, Major::Env) => Ok(Ty::new(Major::Env)),
+            (Major::All(x), Major::All(y)) => Ok(Ty::new(Major::All(merged(x, y)))),
+            (Major::Tab(x), Major::Tab(y)) => Ok(Ty::new(Major::Tab(merged(x, y)))),
+            (Major::Var(_), _) => Err((an, TypeErrorKind::Ambiguous(at))),
+            (_, Major::Var(_)) => Err((bn, TypeErrorKind::Ambiguous(bt))),
+            (Major::Env | Major::All(_) | Major::Tab(_), _) => Err((
+                bn,
+                TypeErrorKind::Mismatch {
+                    expected: at.clone(),
+                    found: bt,
+                },
+            )),
+            _ => Err((
+                an,
+                TypeErrorKind::NotA {
+                    class: "a record, table or environment",
+                    found: at,
+                },
+            )),
+        }
+    }
+}
+
+fn visit_tys(ty: &Ty, f: &mut impl FnMut(&Ty)) {
+    f(ty);
+    match &ty.major {
+        Major::All(fs) | Major::Tab(fs) => fs.iter().for_each(|(_, t)| visit_tys(t, f)),
+        Major::Any(vs) => vs.iter().for_each(|t| visit_tys(t, f)),
+        Major::Fn(ps, r) => {
+            ps.iter().for_each(|t| visit_tys(t, f));
+            visit_tys(r, f)
+        }
+        _ => {}
+    }
+}
+
+fn has_var(ty: &Ty) -> bool {
+    let mut found = false;
+    visit_tys(ty, &mut |t| found |= t.is_var());
+    found
+}
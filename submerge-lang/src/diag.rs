@@ -0,0 +1,90 @@
+// Diagnostics. Parse errors, type errors, FO rejections and runtime faults
+// all come down to a message and, where it's known, the span of source text
+// it's about. A Diagnostic is that pair plus what kind of problem it is, and
+// render() prints it against the source the way compilers do:
+//
+//   type error: expected int, found flo
+//    --> 1:7
+//     |
+//   1 | t.n + 1.0
+//     |       ^^^
+//
+// Lines and columns count from 1, and columns count chars, not bytes. A span
+// running over several lines is underlined to the end of its first, and an
+// empty span (the end of the input, say) gets one caret. The output is
+// plain text with no colour or terminal codes, so a CLI can print it and a
+// TUI can show it in a widget as it is.
+
+use crate::{Fault, FoError, ParseError, Span, TypeError};
+use std::fmt::Write;
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Diagnostic {
+    // What kind of problem: "parse error", "type error" and so on.
+    pub class: &'static str,
+    pub span: Option<Span>,
+    pub msg: String,
+}
+
+impl Diagnostic {
+    pub fn new(class: &'static str, span: Option<Span>, msg: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            class,
+            span,
+            msg: msg.into(),
+        }
+    }
+
+    // A fault has an opcode index rather than a span, so the caller supplies
+    // the span of the Expr that opcode came from, if it knows it.
+    pub fn fault(f: &Fault, span: Option<Span>) -> Diagnostic {
+        Diagnostic::new("fault", span, format!("{}: {}", f.kind, f.msg))
+    }
+
+    pub fn render(&self, src: &str) -> String {
+        let mut out = format!("{}: {}\n", self.class, self.msg);
+        let Some(span) = self.span else {
+            return out;
+        };
+        let lo = floor_char(src, span.lo.min(src.len()));
+        let start = src[..lo].rfind('\n').map_or(0, |i| i + 1);
+        let end = src[lo..].find('\n').map_or(src.len(), |i| lo + i);
+        let hi = floor_char(src, span.hi.clamp(lo, end));
+        let line = src[..start].matches('\n').count() + 1;
+        let col = src[start..lo].chars().count();
+        let carets = src[lo..hi].chars().count().max(1);
+        let number = line.to_string();
+        let pad = " ".repeat(number.len());
+        let _ = writeln!(out, "{}--> {}:{}", pad, line, col + 1);
+        let _ = writeln!(out, "{} |", pad);
+        let _ = writeln!(out, "{} | {}", number, &src[start..end]);
+        let _ = writeln!(out, "{} | {}{}", pad, " ".repeat(col), "^".repeat(carets));
+        out
+    }
+}
+
+// The nearest char boundary at or before a byte offset.
+fn floor_char(s: &str, mut i: usize) -> usize {
+    while !s.is_char_boundary(i) {
+        i -= 1;
+    }
+    i
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(e: &ParseError) -> Diagnostic {
+        Diagnostic::new("parse error", Some(e.span), e.msg.clone())
+    }
+}
+
+impl From<&TypeError> for Diagnostic {
+    fn from(e: &TypeError) -> Diagnostic {
+        Diagnostic::new("type error", e.span, e.kind.to_string())
+    }
+}
+
+impl From<&FoError> for Diagnostic {
+    fn from(e: &FoError) -> Diagnostic {
+        Diagnostic::new("not FO", e.span, e.kind.to_string())
+    }
+}
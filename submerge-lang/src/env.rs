@@ -0,0 +1,129 @@
+// Reified environments. A Vm frame's context is a stack of tables, and Reify
+// captures it as a value: a record with a field per table, innermost last,
+// each of which is a record of that table's columns. Unlike other records,
+// an environment's fields needn't have the same number of rows, since they
+// are separate tables; only Query and Merge look inside one.
+//
+// A path is looked up in a context, or in an environment, by the same rule
+// either way: the innermost table in which the whole path resolves wins. So
+// an inner table shadows an outer one path by path, not name by name: if
+// the inner one has a t without an x, t.x still comes from an outer one.
+// Filter predicates run with the filtered table's columns as the innermost
+// table, and case arms with their frame's context, so query(reify, p) is
+// the same as p wherever it's evaluated.
+//
+// Merge combines two environments or two records, the right-hand one
+// taking precedence. Environments merge by stacking: the right-hand one's
+// tables go innermost, so they shadow the left-hand one's by the lookup
+// rule above. Records merge field by field: a label only on one side is
+// kept, in order, left-hand fields first; a label on both sides takes the
+// right-hand field, unless both fields are themselves records, which merge
+// recursively. The merged record's fields must then have the same number
+// of rows, or one row, which is repeated, as in a zip. At runtime an
+// environment is the record whose fields are all unlabelled records (or
+// none at all), which no record literal can be.
+
+use crate::{kernel::type_name, Col, Form, Path, Tab, Unit, Vals, Word};
+use submerge_base::{err, Result};
+
+// The values at a path in a context, Rich if they have a form or unit.
+pub(crate) fn lookup(ctx: &[Tab], path: &Path) -> Result<Vals> {
+    for tab in ctx.iter().rev() {
+        if let Ok(vals) = tab.load(path) {
+            return Ok(vals);
+        }
+    }
+    Err(err(format!("no column {} in context", path)))
+}
+
+pub(crate) fn reify(ctx: &[Tab]) -> Vals {
+    let tab = |t: &Tab| {
+        Vals::All(
+            t.cols
+                .iter()
+                .map(|c| Vals::Rich(Box::new(c.clone())))
+                .collect(),
+        )
+    };
+    Vals::All(ctx.iter().map(tab).collect())
+}
+
+// The inverse of reify().
+pub(crate) fn tabs(env: &Vals) -> Result<Vec<Tab>> {
+    let Vals::All(tabs) = env else {
+        return Err(err("not an environment"));
+    };
+    let tab = |v: &Vals| -> Result<Tab> {
+        let Vals::All(cols) = v else {
+            return Err(err("environment holds a non-table"));
+        };
+        let col = |v: &Vals| -> Result<Col> {
+            match v {
+                Vals::Rich(c) => Ok((**c).clone()),
+                _ => Err(err("environment table has an unnamed column")),
+            }
+        };
+        Tab::new(cols.iter().map(col).collect::<Result<_>>()?)
+    };
+    tabs.iter().map(tab).collect()
+}
+
+pub(crate) fn query(env: &Vals, path: &Path) -> Result<Vals> {
+    lookup(&tabs(env)?, path)
+}
+
+fn is_env(v: &Vals) -> bool {
+    matches!(v, Vals::All(fs) if fs.iter().all(|f| matches!(f, Vals::All(_))))
+}
+
+// The fields of a record under any Rich wrapper, by label or position.
+fn fields(v: &Vals) -> Option<Vec<(Word, Vals)>> {
+    let Vals::All(fs) = v else {
+        return None;
+    };
+    let label = |(i, f): (usize, &Vals)| match f {
+        Vals::Rich(c) => (c.name.clone(), f.clone()),
+        _ => (Word::positional(i), f.clone()),
+    };
+    Some(fs.iter().enumerate().map(label).collect())
+}
+
+// A field's values with any label stripped, for recursion.
+fn record(v: &Vals) -> &Vals {
+    match v {
+        Vals::Rich(c) if c.form == Form::NONE && c.unit == Unit::NONE => &c.vals,
+        _ => v,
+    }
+}
+
+pub(crate) fn merge(a: &Vals, b: &Vals) -> Result<Vals> {
+    if is_env(a) && is_env(b) {
+        let (Vals::All(x), Vals::All(y)) = (a, b) else {
+            unreachable!()
+        };
+        return Ok(Vals::All(x.iter().chain(y).cloned().collect()));
+    }
+    if is_env(a) != is_env(b) {
+        return Err(err("cannot merge an environment with a record"));
+    }
+    let (Some(mut out), Some(right)) = (fields(a), fields(b)) else {
+        return Err(err(format!(
+            "cannot merge {} with {}",
+            type_name(a),
+            type_name(b)
+        )));
+    };
+    for (w, f) in right {
+        match out.iter_mut().find(|(x, _)| *x == w) {
+            Some(slot) => {
+                let (x, y) = (record(&slot.1), record(&f));
+                slot.1 = match (x, y) {
+                    (Vals::All(_), Vals::All(_)) => merge(x, y)?,
+                    _ => f,
+                };
+            }
+            None => out.push((w, f)),
+        }
+    }
+    Vals::zip(out)
+}
@@ -0,0 +1,136 @@
+// Runtime errors. The checker rules out most failures before an Expr runs,
+// but some depend on the data: an integer division by zero, an overflow
+// under Overflow::Check, a cast that doesn't fit, a date out of range. When
+// an opcode fails like that, the Vm stops and the failure is a Fault: its
+// kind, the index of the opcode that failed and a message. A Fault is plain
+// data that compares, hashes and serializes like a value. It carries no
+// backtrace, address or timing, so every node running the same Program over
+// the same data stops at the same opcode with the same Fault, and a
+// transaction can record it as its result.
+//
+// Not every error is a Fault. The policy is per opcode:
+//
+//   - Literal and Reify can't fail.
+//   - Every other opcode faults when its operands don't suit it. Most of
+//     these are Mismatch, which a checked Expr never hits; the others are
+//     the kinds below, by kernel. Null rows never fault, since kernels only
+//     run over the rows that have values.
+//   - A fault inside a Case arm or a Filter predicate propagates out, as a
+//     fault of the Case or Filter opcode itself.
+//   - Popping an empty operand stack, finishing with nothing on it and
+//     opcodes that aren't implemented trap instead: they are plain Errors,
+//     which mean the Program is malformed (or the Vm is wrong), not that the
+//     data is, so they are never recorded as results.
+//
+// Kernels raise faults with fault(), and the Vm's own errors use trap(). The
+// Vm classifies whatever else an opcode returns as Mismatch, with the
+// error's message, and fills in the opcode index.
+
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+use submerge_base::{Error, ErrorKind};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum FaultKind {
+    // An integer Div or Mod by zero.
+    DivideByZero,
+    // A result too big for its type, under Overflow::Check or in a cast or
+    // a sum.
+    Overflow,
+    // A result that would lose digits, in a Convert or a Check cast.
+    Inexact,
+    // An argument outside an op's domain: a negative exponent or slice, or
+    // a date past the calendar's range.
+    OutOfRange,
+    // Bytes that don't parse as what an op needs: non-utf-8 text, a bad
+    // like pattern, an unparseable cast.
+    Invalid,
+    // Operands of the wrong type or shape, or a path that doesn't resolve.
+    Mismatch,
+    // An evaluation went past one of its limits on opcodes, memory or
+    // output rows. The Vm has no limits, so only whoever runs it raises
+    // this (see eval).
+    ResourceExhausted,
+    // A float op whose result can differ between platforms, under
+    // FloatMode::Deterministic (see float.rs).
+    Unportable,
+}
+
+impl FaultKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            FaultKind::DivideByZero => "division by zero",
+            FaultKind::Overflow => "overflow",
+            FaultKind::Inexact => "inexact",
+            FaultKind::OutOfRange => "out of range",
+            FaultKind::Invalid => "invalid",
+            FaultKind::Mismatch => "mismatch",
+            FaultKind::ResourceExhausted => "resource exhausted",
+            FaultKind::Unportable => "unportable",
+        }
+    }
+}
+
+impl Display for FaultKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct Fault {
+    pub kind: FaultKind,
+    // The index of the failing opcode in the Vm's top-level opcodes.
+    pub pc: usize,
+    pub msg: String,
+}
+
+impl Display for Fault {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at opcode {}: {}", self.kind, self.pc, self.msg)
+    }
+}
+
+impl std::error::Error for Fault {}
+
+// An error the Vm can't attribute to the data.
+#[derive(Debug)]
+struct Trap(String);
+
+impl Display for Trap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Trap {}
+
+// A fault whose opcode index the Vm fills in.
+pub(crate) fn fault(kind: FaultKind, msg: impl Into<String>) -> Error {
+    let msg = msg.into();
+    Error::new_with_kind(ErrorKind::Eval, Fault { kind, pc: 0, msg })
+}
+
+pub(crate) fn trap(msg: impl Into<String>) -> Error {
+    Error::new_with_kind(ErrorKind::Internal, Trap(msg.into()))
+}
+
+// The Fault an opcode's error amounts to, or the error back if it traps.
+pub(crate) fn classify(e: Error, pc: usize) -> Result<Fault, Error> {
+    if e.downcast_ref::<Trap>().is_some() {
+        return Err(e);
+    }
+    Ok(match e.downcast_ref::<Fault>() {
+        Some(f) => Fault { pc, ..f.clone() },
+        None => Fault {
+            kind: FaultKind::Mismatch,
+            pc,
+            msg: e.message(),
+        },
+    })
+}
+
+// An error carrying a Fault, as the Vm returns it.
+pub(crate) fn raise(f: Fault) -> Error {
+    Error::new_with_kind(ErrorKind::Eval, f)
+}
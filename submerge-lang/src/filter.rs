@@ -0,0 +1,60 @@
+// Filtering evaluates a predicate over a table's rows to a mask of bits, one
+// per row, then compacts every column to the rows whose bit is set, keeping
+// their order. A one-row mask applies to every row, so a constant predicate
+// keeps all rows or none, and a null in the mask drops its row. The predicate
+// sees the table's columns by name, in front of whatever context it runs in;
+// in the Vm, where a table is a record of labelled columns, the record's
+// fields are put in front of the frame's.
+
+use crate::{kernel::type_name, Opcode, Tab, Vals, Vm};
+use submerge_base::{err, Result};
+
+// The rows of an n-row table that a mask keeps.
+fn kept(mask: &Vals, n: usize) -> Result<Vec<usize>> {
+    match mask {
+        Vals::Rich(col) => kept(&col.vals, n),
+        Vals::Opt(ns, vals) if vals.len() == 1 && ns.contains(0) => Ok(Vec::new()),
+        Vals::Opt(ns, vals) => Ok(kept(vals, n)?
+            .into_iter()
+            .filter(|i| !ns.contains(*i))
+            .collect()),
+        Vals::Bits(1, bs) => Ok(if bs.contains(0) {
+            (0..n).collect()
+        } else {
+            Vec::new()
+        }),
+        Vals::Bits(m, bs) if *m == n => Ok((0..n).filter(|i| bs.contains(*i)).collect()),
+        Vals::Bits(m, _) => Err(err(format!("mask has {} rows for {}", m, n))),
+        _ => Err(err(format!("mask is {}, not bits", type_name(mask)))),
+    }
+}
+
+impl Vals {
+    // The rows whose bit is set in the mask. Records and unions compact
+    // field by field and variant by variant.
+    pub fn compact(&self, mask: &Vals) -> Result<Vals> {
+        self.take(&kept(mask, self.len())?)
+    }
+}
+
+impl Tab {
+    pub fn compact(&self, mask: &Vals) -> Result<Tab> {
+        let rows = kept(mask, self.len())?;
+        let cols = self
+            .named()
+            .into_iter()
+            .map(|(w, v)| Ok((w, v.take(&rows)?)));
+        Tab::from_named(cols.collect::<Result<_>>()?)
+    }
+
+    // Run a predicate with this table's columns as its context.
+    pub fn mask(&self, pred: &[Opcode]) -> Result<Vals> {
+        let mask = Vm::new(pred.to_vec(), vec![self.clone()]).run()?;
+        kept(&mask, self.len())?;
+        Ok(mask)
+    }
+
+    pub fn filter(&self, pred: &[Opcode]) -> Result<Tab> {
+        self.compact(&self.mask(pred)?)
+    }
+}
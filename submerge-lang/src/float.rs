@@ -0,0 +1,107 @@
+// Float determinism. Replicas evaluate the same opcodes over the same data
+// and must agree on the result to the bit, or the txn layer sees them
+// diverge. Most float ops already do: IEEE 754 gives +, -, *, /, %, sqrt,
+// abs, floor, ceil and trunc one correctly rounded result, and Rust never
+// contracts a multiply and add into an FMA or reassociates float arithmetic,
+// so no build flag or target changes them. Reductions have a fixed order:
+// a sum adds its column in row order (see agg.rs), and eval merges batches,
+// and partitions under Order::Fixed, in order too.
+//
+// What's left differs between platforms:
+//
+//   - Exp, log, trig and hyperbolic functions and Pow call the platform's
+//     libm, which may round their last place differently on another OS or
+//     architecture.
+//   - An op that makes a NaN gives whatever sign and payload the hardware
+//     does: x86 sets the sign bit where ARM doesn't.
+//
+// Under FloatMode::Deterministic, the Vm faults with Unportable on the
+// first, before running it, and gives every NaN an op results in the bits
+// of f64::NAN. The default, Native, runs both as the platform does, for
+// queries that no other node has to agree with. Min and max of a zero and a
+// negative zero are -0 and +0 in either mode, where Rust leaves it open.
+
+use crate::{fault::fault, FaultKind, PrimBinOp, PrimUnOp, Vals};
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+use submerge_base::Result;
+
+#[derive(
+    Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize,
+)]
+pub enum FloatMode {
+    #[default]
+    Native,
+    Deterministic,
+}
+
+fn is_flo(v: &Vals) -> bool {
+    match v {
+        Vals::F64s(_) => true,
+        Vals::Rich(col) => is_flo(&col.vals),
+        Vals::Opt(_, vals) => is_flo(vals),
+        _ => false,
+    }
+}
+
+fn unportable(name: &str) -> Result<()> {
+    Err(fault(
+        FaultKind::Unportable,
+        format!("{} of flo differs between platforms", name),
+    ))
+}
+
+pub(crate) fn check_unop(op: PrimUnOp, a: &Vals) -> Result<()> {
+    use PrimUnOp::*;
+    match op {
+        Exp | Exp2 | Exp10 | Log | Log2 | Log10 | Sin | Cos | Tan | Asin | Acos | Atan | Sinh
+        | Cosh | Tanh | Asinh | Acosh | Atanh
+            if is_flo(a) =>
+        {
+            unportable(op.name())
+        }
+        _ => Ok(()),
+    }
+}
+
+pub(crate) fn check_binop(op: PrimBinOp, a: &Vals, b: &Vals) -> Result<()> {
+    match op {
+        PrimBinOp::Pow if is_flo(a) || is_flo(b) => unportable(op.name()),
+        _ => Ok(()),
+    }
+}
+
+// A value with every NaN in it made f64::NAN.
+pub(crate) fn canonical_nans(v: Vals) -> Vals {
+    let canon = |f: OrderedFloat<f64>| match f.is_nan() {
+        true => OrderedFloat(f64::NAN),
+        false => f,
+    };
+    match v {
+        Vals::F64s(x) => Vals::F64s(x.into_iter().map(canon).collect()),
+        Vals::Rich(mut col) => {
+            col.vals = canonical_nans(col.vals);
+            Vals::Rich(col)
+        }
+        Vals::All(fields) => Vals::All(fields.into_iter().map(canonical_nans).collect()),
+        Vals::Any(sel, vars) => Vals::Any(sel, vars.into_iter().map(canonical_nans).collect()),
+        Vals::Opt(ns, vals) => Vals::Opt(ns, Box::new(canonical_nans(*vals))),
+        v => v,
+    }
+}
+
+// Min and max that order -0 below +0, and otherwise take the non-NaN
+// operand as f64's do. Equal values differ at most in the sign bit.
+pub(crate) fn min(a: f64, b: f64) -> f64 {
+    match a == b {
+        true => f64::from_bits(a.to_bits() | b.to_bits()),
+        false => a.min(b),
+    }
+}
+
+pub(crate) fn max(a: f64, b: f64) -> f64 {
+    match a == b {
+        true => f64::from_bits(a.to_bits() & b.to_bits()),
+        false => a.max(b),
+    }
+}
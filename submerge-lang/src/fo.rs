@@ -0,0 +1,201 @@
+// The FO checker. Lang promises that every Expr terminates, and that for a
+// fixed Expr the work it does is polynomial in the size of its data (FO,
+// plus the counting and ordering that aggregates and sorts add). There are
+// no loops: the only iteration is over the rows of columns, by the kernels
+// and table operators, and each of those does a bounded amount of work per
+// row. So the only way to run forever is recursion through functions, and
+// this checker rules it out syntactically, without needing types:
+//
+//   - A fn can only be applied or bound by let. It can't be passed as an
+//     argument, returned, stored in a record or compared.
+//   - The fn of an application must be a fn literal or a let-bound name of
+//     one. Since a let's name isn't in scope in its own value, every fn can
+//     only call fns bound before it, and the calls form a DAG.
+//
+// Fn parameters therefore always hold data, never fns, so every fn is
+// first-order and every call can be inlined in finitely many steps. This is
+// for the txn layer to refuse thunks that would break that promise. Quoted
+// code isn't run where it's written, so only the operands of a quote's
+// holes are checked with it; the Vm checks the code it builds. Under
+// check_fo_with(), a name registered as a native function may be applied
+// too, unless it's shadowed, since natives don't call back into Exprs (see
+// udf.rs). Like the
+// typechecker, errors carry the offending node's pre-order index and its
+// span when the caller has one.
+
+use crate::{Expr, Path, Span, SpanTree, Udfs, Word};
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FoErrorKind {
+    // A fn used as a value rather than applied or let-bound.
+    FnValue,
+    // An application of a fn parameter, which would make a higher-order fn.
+    HigherOrder(Word),
+    // An application of a path that isn't a let-bound fn.
+    NotAFn(Path),
+    // An application of an expression that isn't a fn literal or a path.
+    Callee,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FoError {
+    pub node: usize,
+    pub span: Option<Span>,
+    pub kind: FoErrorKind,
+}
+
+impl Display for FoErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FoErrorKind::FnValue => {
+                f.write_str("a fn can only be applied or bound by let, so that calls can't recurse")
+            }
+            FoErrorKind::HigherOrder(w) => write!(
+                f,
+                "parameter {} is applied as a fn, but fns must be first-order",
+                w
+            ),
+            FoErrorKind::NotAFn(p) => write!(
+                f,
+                "{} is not a fn literal or a let-bound fn, so it may not be applied",
+                p
+            ),
+            FoErrorKind::Callee => f.write_str("only fn literals and let-bound fns may be applied"),
+        }
+    }
+}
+
+impl Display for FoError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.span {
+            Some(s) => write!(f, "not FO at {}..{}: {}", s.lo, s.hi, self.kind),
+            None => write!(f, "not FO at node {}: {}", self.node, self.kind),
+        }
+    }
+}
+
+impl std::error::Error for FoError {}
+
+pub fn check_fo(expr: &Expr, spans: Option<&SpanTree>) -> Result<(), FoError> {
+    check_fo_with(expr, spans, &Udfs::default())
+}
+
+pub fn check_fo_with(expr: &Expr, spans: Option<&SpanTree>, udfs: &Udfs) -> Result<(), FoError> {
+    let mut c = FoChecker {
+        scope: Vec::new(),
+        udfs,
+        node: 0,
+    };
+    c.walk(expr, Pos::Value).map_err(|(node, kind)| FoError {
+        node,
+        span: spans.and_then(|s| s.preorder().get(node).copied()),
+        kind,
+    })
+}
+
+// Where an Expr appears, which decides whether it may be a fn.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Pos {
+    Value,
+    Callee,
+    Bound, // The value of a let
+}
+
+// What a local name is bound to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Binding {
+    Fn,
+    Data,
+}
+
+struct FoChecker<'a> {
+    scope: Vec<(Word, Binding)>,
+    udfs: &'a Udfs,
+    node: usize,
+}
+
+type FoResult<T> = Result<T, (usize, FoErrorKind)>;
+
+impl FoChecker<'_> {
+    fn lookup(&self, path: &Path) -> Option<(&Word, Binding)> {
+        let [w] = path.0.as_slice() else {
+            return None;
+        };
+        self.scope
+            .iter()
+            .rev()
+            .find(|(x, _)| x == w)
+            .map(|(x, b)| (x, *b))
+    }
+
+    // Whether an Expr in a callee or let-bound position is a fn.
+    fn is_fn(&self, e: &Expr) -> bool {
+        match e {
+            Expr::Lam(..) => true,
+            Expr::Path(p) => matches!(self.lookup(p), Some((_, Binding::Fn))),
+            _ => false,
+        }
+    }
+
+    fn walk(&mut self, e: &Expr, pos: Pos) -> FoResult<()> {
+        let node = self.node;
+        self.node += 1;
+        if pos == Pos::Value && self.is_fn(e) {
+            return Err((node, FoErrorKind::FnValue));
+        }
+        match e {
+            Expr::Path(p) if pos == Pos::Callee => match self.lookup(p) {
+                Some((_, Binding::Fn)) => Ok(()),
+                Some((w, Binding::Data)) => Err((node, FoErrorKind::HigherOrder(w.clone()))),
+                None if matches!(p.0.as_slice(), [w] if self.udfs.contains(w)) => Ok(()),
+                None => Err((node, FoErrorKind::NotAFn(p.clone()))),
+            },
+            Expr::Let(w, val, body) => {
+                self.walk(val, Pos::Bound)?;
+                let b = if self.is_fn(val) {
+                    Binding::Fn
+                } else {
+                    Binding::Data
+                };
+                self.scope.push((w.clone(), b));
+                let res = self.walk(body, Pos::Value);
+                self.scope.pop();
+                res
+            }
+            Expr::Lam(params, body) => {
+                let n = self.scope.len();
+                self.scope
+                    .extend(params.iter().map(|p| (p.clone(), Binding::Data)));
+                let res = self.walk(body, Pos::Value);
+                self.scope.truncate(n);
+                res
+            }
+            Expr::App(func, args) => {
+                if !self.is_fn(func) && !matches!(**func, Expr::Path(_)) {
+                    return Err((self.node, FoErrorKind::Callee));
+                }
+                self.walk(func, Pos::Callee)?;
+                args.iter().try_for_each(|a| self.walk(a, Pos::Value))
+            }
+            Expr::Quote(body) => self.quoted(body, 0),
+            _ => e
+                .children()
+                .into_iter()
+                .try_for_each(|c| self.walk(c, Pos::Value)),
+        }
+    }
+
+    fn quoted(&mut self, e: &Expr, depth: usize) -> FoResult<()> {
+        self.node += 1;
+        match e {
+            Expr::Splice(x) if depth == 0 => self.walk(x, Pos::Value),
+            Expr::Splice(x) => self.quoted(x, depth - 1),
+            Expr::Quote(x) => self.quoted(x, depth + 1),
+            _ => e
+                .children()
+                .into_iter()
+                .try_for_each(|c| self.quoted(c, depth)),
+        }
+    }
+}
@@ -0,0 +1,227 @@
+// Forms are packed into an i64: the top byte is the kind of form, and the
+// remaining bytes hold that kind's parameters. Kind 0 with no parameters is
+// Form::NONE; other values with an unknown kind are carried along opaquely.
+//
+// A decimal form marks an I64 column as fixed-point: a stored value v means
+// v / 10^scale, and precision bounds the total number of decimal digits, so
+// every stored value has magnitude below 10^precision.
+//
+// A temporal form marks an I64 column as a point or span in time: timestamps
+// count microseconds since the Unix epoch in UTC, as NodeTime does, dates
+// count days since it, and durations count microseconds. Arithmetic on them
+// is plain integer arithmetic that only changes the form, so eg. subtracting
+// timestamps gives a duration and adding an int to a date moves it by days.
+//
+// The code form is the one form on a Bin column: each row is the canonical
+// encoding of an Expr, as quote.rs makes them.
+
+use crate::{Form, PrimBinOp};
+use std::fmt::{self, Display, Formatter};
+
+const KIND_SHIFT: u32 = 56;
+const KIND_DECIMAL: i64 = 1;
+const KIND_TEMPORAL: i64 = 2;
+const KIND_CODE: i64 = 3;
+
+// The most decimal digits an i64 can always hold.
+pub const MAX_DECIMAL_PRECISION: u8 = 18;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct Decimal {
+    pub precision: u8,
+    pub scale: u8,
+}
+
+impl Decimal {
+    // Plain ints act as decimals with no fractional digits.
+    pub const INTEGER: Decimal = Decimal {
+        precision: MAX_DECIMAL_PRECISION,
+        scale: 0,
+    };
+
+    pub fn new(precision: u8, scale: u8) -> Option<Decimal> {
+        if precision == 0 || precision > MAX_DECIMAL_PRECISION || scale > precision {
+            return None;
+        }
+        Some(Decimal { precision, scale })
+    }
+
+    // The exclusive bound on stored magnitudes.
+    pub(crate) fn limit(self) -> i64 {
+        10i64.pow(self.precision as u32)
+    }
+
+    // Render a stored value as a decimal string, eg. 1234 at scale 2 is
+    // "12.34". This is the form CSV export and the UI should show.
+    pub fn format(self, v: i64) -> String {
+        let sign = if v < 0 { "-" } else { "" };
+        let digits = v.unsigned_abs().to_string();
+        let scale = self.scale as usize;
+        if scale == 0 {
+            return format!("{}{}", sign, digits);
+        }
+        let digits = format!("{:0>width$}", digits, width = scale + 1);
+        let (int, frac) = digits.split_at(digits.len() - scale);
+        format!("{}{}.{}", sign, int, frac)
+    }
+
+    // Parse a decimal string at this scale, rejecting extra fractional
+    // digits rather than rounding them away.
+    pub fn parse(self, s: &str) -> Option<i64> {
+        let (neg, s) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+        let all_digits = |t: &str| t.bytes().all(|b| b.is_ascii_digit());
+        if int.is_empty() || !all_digits(int) || !all_digits(frac) {
+            return None;
+        }
+        if frac.len() > self.scale as usize {
+            return None;
+        }
+        let digits = format!("{}{:0<width$}", int, frac, width = self.scale as usize);
+        let v: i64 = digits.parse().ok()?;
+        if v >= self.limit() {
+            return None;
+        }
+        Some(if neg { -v } else { v })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Temporal {
+    Timestamp,
+    Date,
+    Duration,
+}
+
+impl Form {
+    pub const TIMESTAMP: Form = Form((KIND_TEMPORAL << KIND_SHIFT) | 1);
+    pub const DATE: Form = Form((KIND_TEMPORAL << KIND_SHIFT) | 2);
+    pub const DURATION: Form = Form((KIND_TEMPORAL << KIND_SHIFT) | 3);
+    pub const CODE: Form = Form(KIND_CODE << KIND_SHIFT);
+
+    pub fn as_temporal(self) -> Option<Temporal> {
+        match self {
+            Form::TIMESTAMP => Some(Temporal::Timestamp),
+            Form::DATE => Some(Temporal::Date),
+            Form::DURATION => Some(Temporal::Duration),
+            _ => None,
+        }
+    }
+
+    pub fn decimal(precision: u8, scale: u8) -> Option<Form> {
+        Decimal::new(precision, scale).map(Form::from)
+    }
+
+    pub fn as_decimal(self) -> Option<Decimal> {
+        if self.0 >> KIND_SHIFT != KIND_DECIMAL {
+            return None;
+        }
+        Decimal::new((self.0 >> 8) as u8, self.0 as u8)
+    }
+}
+
+impl From<Decimal> for Form {
+    fn from(d: Decimal) -> Form {
+        Form((KIND_DECIMAL << KIND_SHIFT) | ((d.precision as i64) << 8) | d.scale as i64)
+    }
+}
+
+impl From<Temporal> for Form {
+    fn from(t: Temporal) -> Form {
+        match t {
+            Temporal::Timestamp => Form::TIMESTAMP,
+            Temporal::Date => Form::DATE,
+            Temporal::Duration => Form::DURATION,
+        }
+    }
+}
+
+impl Display for Form {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match (self.as_decimal(), self.as_temporal()) {
+            (Some(d), _) => write!(f, "dec({}, {})", d.precision, d.scale),
+            (_, Some(Temporal::Timestamp)) => f.write_str("timestamp"),
+            (_, Some(Temporal::Date)) => f.write_str("date"),
+            (_, Some(Temporal::Duration)) => f.write_str("duration"),
+            _ if *self == Form::CODE => f.write_str("code"),
+            _ => write!(f, "{:#x}", self.0),
+        }
+    }
+}
+
+// The form of a binop's result when either operand is temporal, where
+// Form::NONE means a plain int or, for comparisons, bits. None if the op
+// makes no sense on the operands.
+pub(crate) fn temporal_plan(op: PrimBinOp, a: Form, b: Form) -> Option<Form> {
+    use PrimBinOp::*;
+    use Temporal::*;
+    let plain = |f: Form| f == Form::NONE;
+    let (ta, tb) = (a.as_temporal(), b.as_temporal());
+    Some(match (op, ta, tb) {
+        (Eq | Ne | Lt | Le | Gt | Ge | Cmp, Some(x), Some(y)) if x == y => Form::NONE,
+        (Min | Max, Some(x), Some(y)) if x == y => a,
+        (Sub, Some(Timestamp), Some(Timestamp)) => Form::DURATION,
+        (Sub, Some(Date), Some(Date)) => Form::NONE,
+        (Add | Sub | Mod, Some(Duration), Some(Duration)) => Form::DURATION,
+        (Div, Some(Duration), Some(Duration)) => Form::NONE,
+        (Add | Sub, Some(Timestamp), Some(Duration)) => Form::TIMESTAMP,
+        (Add, Some(Duration), Some(Timestamp)) => Form::TIMESTAMP,
+        (Add | Sub, Some(Date), None) if plain(b) => Form::DATE,
+        (Add, None, Some(Date)) if plain(a) => Form::DATE,
+        (Mul | Div, Some(Duration), None) if plain(b) => Form::DURATION,
+        (Mul, None, Some(Duration)) if plain(a) => Form::DURATION,
+        _ => return None,
+    })
+}
+
+// How a binop runs on decimal operands: the integer op runs once both sides
+// are rescaled to lhs_scale and rhs_scale, and its result has the given
+// form, or none for comparisons. None if the op makes no sense on decimals.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct DecimalPlan {
+    pub lhs_scale: u8,
+    pub rhs_scale: u8,
+    pub result: Option<Decimal>,
+}
+
+pub(crate) fn decimal_plan(op: PrimBinOp, a: Decimal, b: Decimal) -> Option<DecimalPlan> {
+    use PrimBinOp::*;
+    let cap = |p: u8| p.min(MAX_DECIMAL_PRECISION);
+    let scale = a.scale.max(b.scale);
+    let int_digits = (a.precision - a.scale).max(b.precision - b.scale);
+    let (lhs_scale, rhs_scale, result) = match op {
+        // Results may gain a digit from carry.
+        Add | Sub => (scale, scale, Some((cap(int_digits + 1 + scale), scale))),
+        Min | Max | Mod => (scale, scale, Some((cap(int_digits + scale), scale))),
+        Eq | Ne | Lt | Le | Gt | Ge | Cmp => (scale, scale, None),
+        Mul => {
+            let scale = a.scale + b.scale;
+            if scale > MAX_DECIMAL_PRECISION {
+                return None;
+            }
+            (
+                a.scale,
+                b.scale,
+                Some((cap(a.precision + b.precision), scale)),
+            )
+        }
+        // Pre-scaling the dividend by the divisor's scale leaves a quotient
+        // at the dividend's scale.
+        Div => {
+            let lhs = a.scale + b.scale;
+            if lhs > MAX_DECIMAL_PRECISION {
+                return None;
+            }
+            (lhs, b.scale, Some((cap(a.precision + b.scale), a.scale)))
+        }
+        Pow | And | Or | Xor | Shl | Shr | Asr | Rol | Ror => return None,
+    };
+    Some(DecimalPlan {
+        lhs_scale,
+        rhs_scale,
+        result: result.and_then(|(p, s)| Decimal::new(p, s)),
+    })
+}
@@ -0,0 +1,80 @@
+// Group-by sorts rows by their key columns, so groups come out in key order
+// on every node and each group's rows keep their original order. The output
+// has a row per distinct key: the key columns, then a column per Aggregate
+// (see agg.rs). With no keys, every row is in the one group, if there are any
+// rows. Nulls are keys like any other, so rows with null keys are grouped
+// together, after the rest. A table in the Vm is a record of labelled
+// columns, so grouping works on either a Tab or such a record.
+
+use crate::{agg::aggregate, all::unlabelled, Grouping, Overflow, Path, Tab, Val, Vals, Word};
+use submerge_base::{err, Result};
+
+// The rows of each group, in key order.
+pub(crate) fn groups(keys: &[Vals], n: usize) -> Vec<Vec<usize>> {
+    let mut cols: Vec<_> = keys.iter().map(|k| k.to_rows().into_iter()).collect();
+    let rows: Vec<Vec<Val>> = (0..n)
+        .map(|_| cols.iter_mut().filter_map(|c| c.next()).collect())
+        .collect();
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|a, b| rows[*a].cmp(&rows[*b]));
+    let mut out: Vec<Vec<usize>> = Vec::new();
+    for i in order {
+        match out.last_mut() {
+            Some(g) if rows[g[0]] == rows[i] => g.push(i),
+            _ => out.push(vec![i]),
+        }
+    }
+    out
+}
+
+fn group_cols(
+    g: &Grouping,
+    n: usize,
+    col: impl Fn(&Path) -> Result<Vals>,
+    overflow: Overflow,
+) -> Result<Vec<(Word, Vals)>> {
+    let keys: Vec<Vals> = g.keys.iter().map(&col).collect::<Result<_>>()?;
+    if let Some((p, _)) = g.keys.iter().zip(&keys).find(|(_, k)| k.len() != n) {
+        return Err(err(format!("group key {} doesn't have {} rows", p, n)));
+    }
+    let groups = groups(&keys, n);
+    let firsts: Vec<usize> = groups.iter().map(|g| g[0]).collect();
+    let mut out: Vec<(Word, Vals)> = Vec::new();
+    for (p, k) in g.keys.iter().zip(&keys) {
+        let Some(name) = p.0.last() else {
+            return Err(err("cannot group by an empty path"));
+        };
+        out.push((name.clone(), k.take(&firsts)?));
+    }
+    for a in &g.aggs {
+        let v = col(&a.arg)?;
+        // Aggregating no rows gives the result's type, form and unit.
+        let template = aggregate(a.op, &v.take(&[])?, overflow)?;
+        let mut res = Vals::from_rows(&template, [])?;
+        for rows in &groups {
+            for row in aggregate(a.op, &v.take(rows)?, overflow)?.to_rows() {
+                res.push(row)?;
+            }
+        }
+        out.push((a.name.clone(), res));
+    }
+    for (i, (w, _)) in out.iter().enumerate() {
+        if out[..i].iter().any(|(x, _)| x == w) {
+            return Err(err(format!("duplicate group column {}", w)));
+        }
+    }
+    Ok(out)
+}
+
+// Group a table held as a record of labelled columns, giving another.
+pub(crate) fn group_record(table: &Vals, g: &Grouping, overflow: Overflow) -> Result<Vals> {
+    let col = |p: &Path| table.project(&p.0).map(unlabelled);
+    Vals::zip(group_cols(g, table.len(), col, overflow)?)
+}
+
+impl Tab {
+    pub fn group_by(&self, g: &Grouping, overflow: Overflow) -> Result<Tab> {
+        let col = |p: &Path| self.load(p);
+        Tab::from_named(group_cols(g, self.len(), col, overflow)?)
+    }
+}
@@ -0,0 +1,730 @@
+// Packed instruction format, for shipping compact thunks between nodes.
+//
+// A packed Insn is a u64: a 16-bit opcode field followed by the 16-bit
+// indices of operands a, b and c. The opcode field has two forms:
+//
+//   binary: 10 bits of opcode, then 2 flag bits each for a, b, c
+//   unary:  12 bits of opcode, then 2 flag bits each for a, b
+//
+// Unary opcodes all have their top two bits set, which no binary opcode
+// does, so the forms are distinguished by those two bits. The flag bits for
+// an operand are lit (idx is a pool index, not a register) and vector.
+//
+// Binary insns read a and b and write c. Unary insns read a and write b, and
+// leave c zero. Literal and Path insns take a pool index in a. Convert is in
+// binary form: it reads a and writes c, and b is an index into the unit pool.
+// Cast is the same, with b indexing the cast pool.
+// Case is too: b indexes the case pool, whose entries hold a Program per arm.
+// Each arm is assembled as if its variant's values were already in register 0.
+// Project, Query and Zip are binary too, with b indexing the path pool: for
+// Project and Query it's the path to look up, and for Zip it holds the
+// record's labels.
+// Zip reads the registers from a up, one per label, and writes a record to
+// c = a.
+//
+// Group and Sort are binary like Convert, with b indexing the grouping or sort
+// pool, and so is Filter, with b indexing the filter pool's predicates, which
+// start from an empty stack. Join reads
+// both a and b and writes a, so its c indexes the join pool instead.
+// Agg is unary, and its result is always a scalar. A BinFn, TimeFn or BitFn reads
+// the registers from a up, one per argument, and writes c = a; b is the last
+// of them, so the span read is explicit. Quote reads the registers from a
+// up, one per hole of its template, and writes c = a; b indexes the quote
+// pool. A template without holes reads nothing, and writes the register
+// above the stack, in a and c alike. Call is the same, with b indexing the
+// call pool and a register per argument.
+//
+// A Program is assembled from the postfix Opcode sequence the Vm runs, with
+// stack slot n becoming register n, and can be turned back into one. That's
+// the fewest registers a postfix sequence can use: a value's register is
+// free again once the insn that reads it has run, and the next value to be
+// written takes the lowest free one. A register holds a scalar or a vector
+// as its flag says, and a Program records how many of each it ever holds at
+// once, so a Frame can size its register files before it runs.
+
+use crate::{
+    quote, AggOp, BinFn, BitFn, Call, Cast, Expr, Grouping, Insn, Join, Opcode, Operand, Path,
+    PrimBinOp, PrimUnOp, SortKey, TimeFn, Unit, Vals,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use submerge_base::{Error, Result};
+
+const BIN_MERGE: u16 = 0x100;
+const BIN_QUERY: u16 = 0x101;
+const BIN_EVAL: u16 = 0x102;
+const BIN_CONVERT: u16 = 0x103;
+const BIN_CASE: u16 = 0x104;
+const BIN_PROJECT: u16 = 0x105;
+const BIN_ZIP: u16 = 0x106;
+const BIN_GROUP: u16 = 0x107;
+const BIN_JOIN: u16 = 0x108;
+const BIN_SORT: u16 = 0x109;
+const BIN_FILTER: u16 = 0x10a;
+const BIN_CAST: u16 = 0x10b;
+const BIN_QUOTE: u16 = 0x10c;
+const BIN_CALL: u16 = 0x10d;
+const BIN_BINFN_BASE: u16 = 0x110;
+const BIN_TIMEFN_BASE: u16 = 0x120;
+const BIN_BITFN_BASE: u16 = 0x130;
+const BIN_LIMIT: u16 = 0x300;
+
+const UN_BASE: u16 = 0xc00;
+const UN_LITERAL: u16 = 0xd00;
+const UN_PATH: u16 = 0xd01;
+const UN_REIFY: u16 = 0xd03;
+const UN_AGG_BASE: u16 = 0xe00;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct Program {
+    pub insns: Vec<u64>,
+    pub lits: Vec<Vals>,
+    pub paths: Vec<Path>,
+    pub units: Vec<Unit>,
+    pub casts: Vec<Cast>,
+    pub cases: Vec<Vec<Program>>,
+    pub groupings: Vec<Grouping>,
+    pub joins: Vec<Join>,
+    pub sorts: Vec<Vec<SortKey>>,
+    pub filters: Vec<Program>,
+    pub quotes: Vec<Expr>,
+    pub calls: Vec<Call>,
+    pub scalar_regs: u16,
+    pub vector_regs: u16,
+}
+
+enum Form {
+    Binary(u16),
+    Unary(u16),
+}
+
+fn form_of(op: &Opcode) -> Form {
+    match op {
+        Opcode::PrimBinOp(op) => Form::Binary(*op as u16),
+        Opcode::Merge => Form::Binary(BIN_MERGE),
+        Opcode::Query(_) => Form::Binary(BIN_QUERY),
+        Opcode::Eval => Form::Binary(BIN_EVAL),
+        Opcode::Convert(_) => Form::Binary(BIN_CONVERT),
+        Opcode::Cast(_) => Form::Binary(BIN_CAST),
+        Opcode::Case(_) => Form::Binary(BIN_CASE),
+        Opcode::Project(_) => Form::Binary(BIN_PROJECT),
+        Opcode::Zip(_) => Form::Binary(BIN_ZIP),
+        Opcode::Group(_) => Form::Binary(BIN_GROUP),
+        Opcode::Join(_) => Form::Binary(BIN_JOIN),
+        Opcode::Sort(_) => Form::Binary(BIN_SORT),
+        Opcode::Filter(_) => Form::Binary(BIN_FILTER),
+        Opcode::Quote(_) => Form::Binary(BIN_QUOTE),
+        Opcode::Call(_) => Form::Binary(BIN_CALL),
+        Opcode::BinFn(f) => Form::Binary(BIN_BINFN_BASE + *f as u16),
+        Opcode::TimeFn(f) => Form::Binary(BIN_TIMEFN_BASE + *f as u16),
+        Opcode::BitFn(f) => Form::Binary(BIN_BITFN_BASE + *f as u16),
+        Opcode::PrimUnOp(op) => Form::Unary(UN_BASE + *op as u16),
+        Opcode::Literal(_) => Form::Unary(UN_LITERAL),
+        Opcode::Path(_) => Form::Unary(UN_PATH),
+        Opcode::Reify => Form::Unary(UN_REIFY),
+        Opcode::Agg(op) => Form::Unary(UN_AGG_BASE + *op as u16),
+    }
+}
+
+// The number of arguments a function opcode reads.
+fn arity(op: &Opcode) -> usize {
+    match op {
+        Opcode::BinFn(f) => f.arity(),
+        Opcode::TimeFn(f) => f.arity(),
+        Opcode::BitFn(f) => f.arity(),
+        _ => unreachable!("not a function opcode"),
+    }
+}
+
+impl Operand {
+    pub fn reg(idx: u16, vector: bool) -> Operand {
+        Operand {
+            idx,
+            lit: false,
+            vector,
+        }
+    }
+    pub fn lit(idx: u16, vector: bool) -> Operand {
+        Operand {
+            idx,
+            lit: true,
+            vector,
+        }
+    }
+    fn flags(self) -> u64 {
+        ((self.lit as u64) << 1) | self.vector as u64
+    }
+    fn from_parts(idx: u64, flags: u64) -> Operand {
+        Operand {
+            idx: idx as u16,
+            lit: flags & 2 != 0,
+            vector: flags & 1 != 0,
+        }
+    }
+}
+
+impl Insn {
+    // Literal and Path payloads aren't packed: operand a holds their pool
+    // index, and unpack() fetches them back from the Program's pools.
+    pub fn pack(&self) -> u64 {
+        let (a, b, c) = (self.a, self.b, self.c);
+        let field = match form_of(&self.op) {
+            Form::Binary(code) => {
+                ((code as u64) << 6) | (a.flags() << 4) | (b.flags() << 2) | c.flags()
+            }
+            Form::Unary(code) => ((code as u64) << 4) | (a.flags() << 2) | b.flags(),
+        };
+        (field << 48) | ((a.idx as u64) << 32) | ((b.idx as u64) << 16) | c.idx as u64
+    }
+
+    pub fn unpack(word: u64, prog: &Program) -> Result<Insn> {
+        let field = word >> 48;
+        let idx = |shift: u32| (word >> shift) & 0xffff;
+        if field >> 14 != 0b11 {
+            let code = (field >> 6) as u16;
+            let b = Operand::from_parts(idx(16), (field >> 2) & 3);
+            let c = Operand::from_parts(idx(0), field & 3);
+            let op = match code {
+                BIN_CONVERT if !b.lit => {
+                    return Err(Error::corruption("insn unit operand is not a literal"))
+                }
+                BIN_CONVERT => match prog.units.get(b.idx as usize) {
+                    Some(u) => Opcode::Convert(*u),
+                    None => return Err(Error::corruption("insn unit index out of range")),
+                },
+                BIN_CAST if !b.lit => {
+                    return Err(Error::corruption("insn cast operand is not a literal"))
+                }
+                BIN_CAST => match prog.casts.get(b.idx as usize) {
+                    Some(c) => Opcode::Cast(c.clone()),
+                    None => return Err(Error::corruption("insn cast index out of range")),
+                },
+                BIN_CASE if !b.lit => {
+                    return Err(Error::corruption("insn case operand is not a literal"))
+                }
+                BIN_CASE => match prog.cases.get(b.idx as usize) {
+                    Some(arms) => {
+                        Opcode::Case(arms.iter().map(|p| p.ops_from(1)).collect::<Result<_>>()?)
+                    }
+                    None => return Err(Error::corruption("insn case index out of range")),
+                },
+                BIN_PROJECT | BIN_QUERY | BIN_ZIP if !b.lit => {
+                    return Err(Error::corruption("insn path operand is not a literal"))
+                }
+                BIN_PROJECT | BIN_QUERY | BIN_ZIP => match prog.paths.get(b.idx as usize) {
+                    Some(p) if code == BIN_PROJECT => Opcode::Project(p.clone()),
+                    Some(p) if code == BIN_QUERY => Opcode::Query(p.clone()),
+                    Some(p) => Opcode::Zip(p.0.clone()),
+                    None => return Err(Error::corruption("insn path index out of range")),
+                },
+                BIN_GROUP if !b.lit => {
+                    return Err(Error::corruption("insn grouping operand is not a literal"))
+                }
+                BIN_GROUP => match prog.groupings.get(b.idx as usize) {
+                    Some(g) => Opcode::Group(g.clone()),
+                    None => return Err(Error::corruption("insn grouping index out of range")),
+                },
+                BIN_FILTER if !b.lit => {
+                    return Err(Error::corruption("insn filter operand is not a literal"))
+                }
+                BIN_FILTER => match prog.filters.get(b.idx as usize) {
+                    Some(p) => Opcode::Filter(p.ops()?),
+                    None => return Err(Error::corruption("insn filter index out of range")),
+                },
+                BIN_QUOTE if !b.lit => {
+                    return Err(Error::corruption("insn quote operand is not a literal"))
+                }
+                BIN_QUOTE => match prog.quotes.get(b.idx as usize) {
+                    Some(e) => Opcode::Quote(e.clone()),
+                    None => return Err(Error::corruption("insn quote index out of range")),
+                },
+                BIN_CALL if !b.lit => {
+                    return Err(Error::corruption("insn call operand is not a literal"))
+                }
+                BIN_CALL => match prog.calls.get(b.idx as usize) {
+                    Some(c) => Opcode::Call(c.clone()),
+                    None => return Err(Error::corruption("insn call index out of range")),
+                },
+                BIN_SORT if !b.lit => {
+                    return Err(Error::corruption("insn sort operand is not a literal"))
+                }
+                BIN_SORT => match prog.sorts.get(b.idx as usize) {
+                    Some(k) => Opcode::Sort(k.clone()),
+                    None => return Err(Error::corruption("insn sort index out of range")),
+                },
+                BIN_JOIN if !c.lit => {
+                    return Err(Error::corruption("insn join operand is not a literal"))
+                }
+                BIN_JOIN => match prog.joins.get(c.idx as usize) {
+                    Some(j) => Opcode::Join(j.clone()),
+                    None => return Err(Error::corruption("insn join index out of range")),
+                },
+                _ if code >= BIN_BITFN_BASE => {
+                    match BitFn::ALL.get((code - BIN_BITFN_BASE) as usize) {
+                        Some(f) => Opcode::BitFn(*f),
+                        None => return Err(Error::corruption("unknown binary insn opcode")),
+                    }
+                }
+                _ if code >= BIN_TIMEFN_BASE => {
+                    match TimeFn::ALL.get((code - BIN_TIMEFN_BASE) as usize) {
+                        Some(f) => Opcode::TimeFn(*f),
+                        None => return Err(Error::corruption("unknown binary insn opcode")),
+                    }
+                }
+                _ if code >= BIN_BINFN_BASE => {
+                    match BinFn::ALL.get((code - BIN_BINFN_BASE) as usize) {
+                        Some(f) => Opcode::BinFn(*f),
+                        None => return Err(Error::corruption("unknown binary insn opcode")),
+                    }
+                }
+                BIN_MERGE => Opcode::Merge,
+                BIN_EVAL => Opcode::Eval,
+                _ => match PrimBinOp::ALL.get(code as usize) {
+                    Some(op) => Opcode::PrimBinOp(*op),
+                    None => return Err(Error::corruption("unknown binary insn opcode")),
+                },
+            };
+            Ok(Insn {
+                op,
+                a: Operand::from_parts(idx(32), (field >> 4) & 3),
+                b,
+                c,
+            })
+        } else {
+            if idx(0) != 0 {
+                return Err(Error::corruption("unary insn with nonzero third operand"));
+            }
+            let code = (field >> 4) as u16;
+            let a = Operand::from_parts(idx(32), (field >> 2) & 3);
+            let b = Operand::from_parts(idx(16), field & 3);
+            let pool_idx = || {
+                if a.lit {
+                    Ok(a.idx as usize)
+                } else {
+                    Err(Error::corruption("insn pool operand is not a literal"))
+                }
+            };
+            let op = match code {
+                UN_LITERAL => match prog.lits.get(pool_idx()?) {
+                    Some(v) => Opcode::Literal(v.clone()),
+                    None => return Err(Error::corruption("insn literal index out of range")),
+                },
+                UN_PATH => match prog.paths.get(pool_idx()?) {
+                    Some(p) => Opcode::Path(p.clone()),
+                    None => return Err(Error::corruption("insn path index out of range")),
+                },
+                UN_REIFY => Opcode::Reify,
+                _ if code >= UN_AGG_BASE => match AggOp::ALL.get((code - UN_AGG_BASE) as usize) {
+                    Some(op) => Opcode::Agg(*op),
+                    None => return Err(Error::corruption("unknown unary insn opcode")),
+                },
+                _ => match PrimUnOp::ALL.get(code.wrapping_sub(UN_BASE) as usize) {
+                    Some(op) => Opcode::PrimUnOp(*op),
+                    None => return Err(Error::corruption("unknown unary insn opcode")),
+                },
+            };
+            Ok(Insn {
+                op,
+                a,
+                b,
+                c: Operand::default(),
+            })
+        }
+    }
+}
+
+fn intern_path(pool: &mut Vec<Path>, idx: &mut BTreeMap<Path, u16>, p: &Path) -> Result<u16> {
+    if let Some(i) = idx.get(p) {
+        return Ok(*i);
+    }
+    let i = reg_idx(pool.len())?;
+    pool.push(p.clone());
+    idx.insert(p.clone(), i);
+    Ok(i)
+}
+
+fn reg_idx(depth: usize) -> Result<u16> {
+    u16::try_from(depth).map_err(|_| Error::internal("program needs more than 64k registers"))
+}
+
+impl Program {
+    pub fn assemble(ops: &[Opcode]) -> Result<Program> {
+        Program::assemble_on(ops, Vec::new())
+    }
+
+    // Assemble over a stack already holding values with the given vector
+    // flags.
+    fn assemble_on(ops: &[Opcode], mut stack: Vec<bool>) -> Result<Program> {
+        let mut prog = Program::default();
+        let mut lit_idx: BTreeMap<&Vals, u16> = BTreeMap::new();
+        let mut path_idx: BTreeMap<Path, u16> = BTreeMap::new();
+        let mut unit_idx: BTreeMap<&Unit, u16> = BTreeMap::new();
+        let mut cast_idx: BTreeMap<&Cast, u16> = BTreeMap::new();
+        let underflow = || Error::internal("opcode sequence underflows the stack");
+        for op in ops {
+            let insn = match (op, form_of(op)) {
+                (Opcode::Literal(v), _) => {
+                    let next = reg_idx(prog.lits.len())?;
+                    let i = *lit_idx.entry(v).or_insert_with(|| {
+                        prog.lits.push(v.clone());
+                        next
+                    });
+                    let vector = v.len() != 1;
+                    let dst = Operand::reg(reg_idx(stack.len())?, vector);
+                    stack.push(vector);
+                    Insn {
+                        op: op.clone(),
+                        a: Operand::lit(i, vector),
+                        b: dst,
+                        c: Operand::default(),
+                    }
+                }
+                (Opcode::Path(p), _) => {
+                    let i = intern_path(&mut prog.paths, &mut path_idx, p)?;
+                    let dst = Operand::reg(reg_idx(stack.len())?, true);
+                    stack.push(true);
+                    Insn {
+                        op: op.clone(),
+                        a: Operand::lit(i, true),
+                        b: dst,
+                        c: Operand::default(),
+                    }
+                }
+                (Opcode::Convert(u), _) => {
+                    let next = reg_idx(prog.units.len())?;
+                    let i = *unit_idx.entry(u).or_insert_with(|| {
+                        prog.units.push(*u);
+                        next
+                    });
+                    let va = *stack.last().ok_or_else(underflow)?;
+                    let r = reg_idx(stack.len() - 1)?;
+                    Insn {
+                        op: op.clone(),
+                        a: Operand::reg(r, va),
+                        b: Operand::lit(i, false),
+                        c: Operand::reg(r, va),
+                    }
+                }
+                (Opcode::Cast(c), _) => {
+                    let next = reg_idx(prog.casts.len())?;
+                    let i = *cast_idx.entry(c).or_insert_with(|| {
+                        prog.casts.push(c.clone());
+                        next
+                    });
+                    let va = *stack.last().ok_or_else(underflow)?;
+                    let r = reg_idx(stack.len() - 1)?;
+                    Insn {
+                        op: op.clone(),
+                        a: Operand::reg(r, va),
+                        b: Operand::lit(i, false),
+                        c: Operand::reg(r, va),
+                    }
+                }
+                (Opcode::Case(arms), _) => {
+                    let i = reg_idx(prog.cases.len())?;
+                    let arms = arms.iter().map(|a| Program::assemble_on(a, vec![true]));
+                    prog.cases.push(arms.collect::<Result<_>>()?);
+                    let va = *stack.last().ok_or_else(underflow)?;
+                    let r = reg_idx(stack.len() - 1)?;
+                    Insn {
+                        op: op.clone(),
+                        a: Operand::reg(r, va),
+                        b: Operand::lit(i, false),
+                        c: Operand::reg(r, va),
+                    }
+                }
+                (Opcode::Group(g), _) => {
+                    let i = reg_idx(prog.groupings.len())?;
+                    prog.groupings.push(g.clone());
+                    let va = *stack.last().ok_or_else(underflow)?;
+                    let r = reg_idx(stack.len() - 1)?;
+                    Insn {
+                        op: op.clone(),
+                        a: Operand::reg(r, va),
+                        b: Operand::lit(i, false),
+                        c: Operand::reg(r, va),
+                    }
+                }
+                (Opcode::Filter(pred), _) => {
+                    let i = reg_idx(prog.filters.len())?;
+                    prog.filters.push(Program::assemble(pred)?);
+                    let va = *stack.last().ok_or_else(underflow)?;
+                    let r = reg_idx(stack.len() - 1)?;
+                    Insn {
+                        op: op.clone(),
+                        a: Operand::reg(r, va),
+                        b: Operand::lit(i, false),
+                        c: Operand::reg(r, va),
+                    }
+                }
+                (Opcode::Sort(k), _) => {
+                    let i = reg_idx(prog.sorts.len())?;
+                    prog.sorts.push(k.clone());
+                    let va = *stack.last().ok_or_else(underflow)?;
+                    let r = reg_idx(stack.len() - 1)?;
+                    Insn {
+                        op: op.clone(),
+                        a: Operand::reg(r, va),
+                        b: Operand::lit(i, false),
+                        c: Operand::reg(r, va),
+                    }
+                }
+                (Opcode::Project(p) | Opcode::Query(p), _) => {
+                    let i = intern_path(&mut prog.paths, &mut path_idx, p)?;
+                    let va = *stack.last().ok_or_else(underflow)?;
+                    let r = reg_idx(stack.len() - 1)?;
+                    Insn {
+                        op: op.clone(),
+                        a: Operand::reg(r, va),
+                        b: Operand::lit(i, true),
+                        c: Operand::reg(r, va),
+                    }
+                }
+                (Opcode::Zip(labels), _) => {
+                    let p = Path(labels.clone());
+                    let i = intern_path(&mut prog.paths, &mut path_idx, &p)?;
+                    if labels.is_empty() || stack.len() < labels.len() {
+                        return Err(underflow());
+                    }
+                    let fields = stack.split_off(stack.len() - labels.len());
+                    let vector = fields.iter().any(|v| *v);
+                    let r = reg_idx(stack.len())?;
+                    stack.push(vector);
+                    Insn {
+                        op: op.clone(),
+                        a: Operand::reg(r, fields[0]),
+                        b: Operand::lit(i, true),
+                        c: Operand::reg(r, vector),
+                    }
+                }
+                (Opcode::Quote(tpl), _) => {
+                    let i = reg_idx(prog.quotes.len())?;
+                    prog.quotes.push(tpl.clone());
+                    let n = quote::holes(tpl);
+                    if stack.len() < n {
+                        return Err(underflow());
+                    }
+                    let holes = stack.split_off(stack.len() - n);
+                    let r = reg_idx(stack.len())?;
+                    stack.push(false);
+                    Insn {
+                        op: op.clone(),
+                        a: Operand::reg(r, holes.first().copied().unwrap_or(false)),
+                        b: Operand::lit(i, false),
+                        c: Operand::reg(r, false),
+                    }
+                }
+                (Opcode::Call(c), _) => {
+                    let i = reg_idx(prog.calls.len())?;
+                    prog.calls.push(c.clone());
+                    if stack.len() < c.arity {
+                        return Err(underflow());
+                    }
+                    let args = stack.split_off(stack.len() - c.arity);
+                    let vector = args.iter().any(|v| *v);
+                    let r = reg_idx(stack.len())?;
+                    stack.push(vector);
+                    Insn {
+                        op: op.clone(),
+                        a: Operand::reg(r, args.first().copied().unwrap_or(false)),
+                        b: Operand::lit(i, false),
+                        c: Operand::reg(r, vector),
+                    }
+                }
+                (Opcode::BinFn(_) | Opcode::TimeFn(_) | Opcode::BitFn(_), _) => {
+                    let n = arity(op);
+                    if stack.len() < n {
+                        return Err(underflow());
+                    }
+                    let args = stack.split_off(stack.len() - n);
+                    let vector = args.iter().any(|v| *v);
+                    let r = reg_idx(stack.len())?;
+                    stack.push(vector);
+                    Insn {
+                        op: op.clone(),
+                        a: Operand::reg(r, args[0]),
+                        b: Operand::reg(r + n as u16 - 1, args[n - 1]),
+                        c: Operand::reg(r, vector),
+                    }
+                }
+                (Opcode::Agg(_), _) => {
+                    let va = stack.pop().ok_or_else(underflow)?;
+                    let r = reg_idx(stack.len())?;
+                    stack.push(false);
+                    Insn {
+                        op: op.clone(),
+                        a: Operand::reg(r, va),
+                        b: Operand::reg(r, false),
+                        c: Operand::default(),
+                    }
+                }
+                (Opcode::Reify, _) => {
+                    let dst = Operand::reg(reg_idx(stack.len())?, false);
+                    stack.push(false);
+                    Insn {
+                        op: op.clone(),
+                        a: Operand::default(),
+                        b: dst,
+                        c: Operand::default(),
+                    }
+                }
+                (_, Form::Unary(_)) => {
+                    let va = *stack.last().ok_or_else(underflow)?;
+                    let r = reg_idx(stack.len() - 1)?;
+                    Insn {
+                        op: op.clone(),
+                        a: Operand::reg(r, va),
+                        b: Operand::reg(r, va),
+                        c: Operand::default(),
+                    }
+                }
+                (Opcode::Join(j), _) => {
+                    let i = reg_idx(prog.joins.len())?;
+                    prog.joins.push(j.clone());
+                    let vb = stack.pop().ok_or_else(underflow)?;
+                    let va = stack.pop().ok_or_else(underflow)?;
+                    let r = reg_idx(stack.len())?;
+                    stack.push(true);
+                    Insn {
+                        op: op.clone(),
+                        a: Operand::reg(r, va),
+                        b: Operand::reg(r + 1, vb),
+                        c: Operand::lit(i, true),
+                    }
+                }
+                (_, Form::Binary(_)) => {
+                    let vb = stack.pop().ok_or_else(underflow)?;
+                    let va = stack.pop().ok_or_else(underflow)?;
+                    let r = reg_idx(stack.len())?;
+                    stack.push(va || vb);
+                    Insn {
+                        op: op.clone(),
+                        a: Operand::reg(r, va),
+                        b: Operand::reg(r + 1, vb),
+                        c: Operand::reg(r, va || vb),
+                    }
+                }
+            };
+            prog.insns.push(insn.pack());
+            let vectors = stack.iter().filter(|v| **v).count();
+            prog.vector_regs = prog.vector_regs.max(reg_idx(vectors)?);
+            prog.scalar_regs = prog.scalar_regs.max(reg_idx(stack.len() - vectors)?);
+        }
+        Ok(prog)
+    }
+
+    pub fn unpack(&self) -> Result<Vec<Insn>> {
+        self.insns.iter().map(|w| Insn::unpack(*w, self)).collect()
+    }
+
+    // Recover the Vm's opcode sequence, checking that registers follow the
+    // stack discipline assemble() produces.
+    pub fn ops(&self) -> Result<Vec<Opcode>> {
+        self.ops_from(0)
+    }
+
+    // As ops(), for a program that starts with depth values on the stack.
+    fn ops_from(&self, mut depth: u16) -> Result<Vec<Opcode>> {
+        let bad = || Error::corruption("insn registers don't follow stack order");
+        let mut ops = Vec::with_capacity(self.insns.len());
+        for insn in self.unpack()? {
+            let (a, b, c) = (insn.a, insn.b, insn.c);
+            match form_of(&insn.op) {
+                Form::Unary(UN_LITERAL | UN_PATH | UN_REIFY) => {
+                    if b.lit || b.idx != depth {
+                        return Err(bad());
+                    }
+                    depth += 1;
+                }
+                Form::Unary(_) => {
+                    if a.lit || b.lit || depth == 0 || a.idx != depth - 1 || b.idx != a.idx {
+                        return Err(bad());
+                    }
+                }
+                Form::Binary(BIN_ZIP) => {
+                    let Opcode::Zip(labels) = &insn.op else {
+                        unreachable!()
+                    };
+                    let n = labels.len() as u16;
+                    if a.lit || !b.lit || c.lit || n == 0 || depth < n {
+                        return Err(bad());
+                    }
+                    if a.idx != depth - n || c.idx != a.idx {
+                        return Err(bad());
+                    }
+                    depth -= n - 1;
+                }
+                Form::Binary(BIN_QUOTE) => {
+                    let Opcode::Quote(tpl) = &insn.op else {
+                        unreachable!()
+                    };
+                    let n = quote::holes(tpl) as u16;
+                    if a.lit || !b.lit || c.lit || depth < n {
+                        return Err(bad());
+                    }
+                    if a.idx != depth - n || c.idx != a.idx {
+                        return Err(bad());
+                    }
+                    depth = depth - n + 1;
+                }
+                Form::Binary(BIN_CALL) => {
+                    let Opcode::Call(call) = &insn.op else {
+                        unreachable!()
+                    };
+                    let n = reg_idx(call.arity)?;
+                    if a.lit || !b.lit || c.lit || depth < n {
+                        return Err(bad());
+                    }
+                    if a.idx != depth - n || c.idx != a.idx {
+                        return Err(bad());
+                    }
+                    depth = depth - n + 1;
+                }
+                Form::Binary(code) if code >= BIN_BINFN_BASE => {
+                    let n = arity(&insn.op) as u16;
+                    if a.lit || b.lit || c.lit || depth < n {
+                        return Err(bad());
+                    }
+                    if a.idx != depth - n || b.idx != depth - 1 || c.idx != a.idx {
+                        return Err(bad());
+                    }
+                    depth -= n - 1;
+                }
+                Form::Binary(
+                    BIN_CONVERT | BIN_CAST | BIN_CASE | BIN_PROJECT | BIN_QUERY | BIN_GROUP
+                    | BIN_SORT | BIN_FILTER,
+                ) => {
+                    if a.lit
+                        || !b.lit
+                        || c.lit
+                        || depth == 0
+                        || a.idx != depth - 1
+                        || c.idx != a.idx
+                    {
+                        return Err(bad());
+                    }
+                }
+                Form::Binary(BIN_JOIN) => {
+                    if a.lit || b.lit || !c.lit || depth < 2 {
+                        return Err(bad());
+                    }
+                    if a.idx != depth - 2 || b.idx != depth - 1 {
+                        return Err(bad());
+                    }
+                    depth -= 1;
+                }
+                Form::Binary(_) => {
+                    if a.lit || b.lit || c.lit || depth < 2 {
+                        return Err(bad());
+                    }
+                    if a.idx != depth - 2 || b.idx != depth - 1 || c.idx != a.idx {
+                        return Err(bad());
+                    }
+                    depth -= 1;
+                }
+            }
+            ops.push(insn.op);
+        }
+        Ok(ops)
+    }
+}
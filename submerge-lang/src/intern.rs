@@ -0,0 +1,120 @@
+// Hash-consing for Exprs. Big queries repeat the same subtrees many times,
+// and an Interner stores each distinct subtree once: an Interned is a
+// shared node whose children are themselves Interned, and interning a
+// node that is already in the table returns the node from the table. So
+// within one Interner, structurally equal Exprs are the same node, and
+// comparing or hashing them is a pointer comparison, whatever their size.
+//
+// A node is kept as its shell (the Expr with every child replaced by Pass)
+// and its Interned children, in the order of Expr::children(), so this
+// doesn't need a second copy of the Expr enum. Interned values from
+// different Interners are never equal, even when their Exprs are.
+
+use crate::{parse, Expr, ParseError, SpanTree};
+use std::{
+    collections::HashSet,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+#[derive(Debug)]
+struct Node {
+    shell: Expr,
+    children: Vec<Interned>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Interned(Arc<Node>);
+
+impl PartialEq for Interned {
+    fn eq(&self, other: &Interned) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Interned {}
+
+impl Hash for Interned {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Arc::as_ptr(&self.0).hash(state)
+    }
+}
+
+impl Interned {
+    // This node with its children replaced by Pass.
+    pub fn shell(&self) -> &Expr {
+        &self.0.shell
+    }
+
+    pub fn children(&self) -> &[Interned] {
+        &self.0.children
+    }
+
+    // The Expr this node stands for, with shared subtrees copied out.
+    pub fn to_expr(&self) -> Expr {
+        let mut children = self.0.children.iter();
+        self.0.shell.map_children(|_| {
+            children
+                .next()
+                .expect("interned node has a child per hole")
+                .to_expr()
+        })
+    }
+}
+
+// The table's key: a node, compared by its shell and by the identity of its
+// children, which are already interned.
+struct Key(Interned);
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Key) -> bool {
+        self.0.shell() == other.0.shell() && self.0.children() == other.0.children()
+    }
+}
+
+impl Eq for Key {}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.shell().hash(state);
+        self.0.children().hash(state);
+    }
+}
+
+#[derive(Default)]
+pub struct Interner {
+    table: HashSet<Key>,
+}
+
+impl Interner {
+    pub fn new() -> Interner {
+        Interner::default()
+    }
+
+    // The number of distinct nodes interned so far.
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    pub fn intern(&mut self, e: &Expr) -> Interned {
+        let children = e.children().into_iter().map(|c| self.intern(c)).collect();
+        let shell = e.map_children(|_| Expr::Pass);
+        let key = Key(Interned(Arc::new(Node { shell, children })));
+        if let Some(Key(found)) = self.table.get(&key) {
+            return found.clone();
+        }
+        let node = key.0.clone();
+        self.table.insert(key);
+        node
+    }
+
+    // Parse and intern an Expr, with the spans of the Expr as parsed.
+    pub fn parse(&mut self, src: &str) -> Result<(Interned, SpanTree), ParseError> {
+        let parsed = parse(src)?;
+        Ok((self.intern(&parsed.expr), parsed.spans))
+    }
+}
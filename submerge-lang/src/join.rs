@@ -0,0 +1,213 @@
+// Equi-joins build a hash table over the right table's keys and probe it
+// with each left row, so output rows come in left row order and, within a
+// left row, in right row order. An inner join can build over the left
+// table's keys instead, if it's smaller (see Build), and then puts its pairs
+// in that order after probing. Keys are ints or bins. Bins are compared as
+// coldb's dictionaries compare them: first by their big-endian 8-byte
+// prefix, length and 16-bit hash, which is what the table hashes, and only
+// then by their full bytes.
+//
+// A left join keeps left rows that match nothing, with nulls in the
+// right-hand columns. A null key matches nothing, not even another null.
+//
+// The output has the left columns, then the right ones, except for a right
+// key column with the same name as the left key it's equal to.
+
+use crate::{
+    all::unlabelled, kernel::type_name, null::nulls, Bin, Build, Form, Join, JoinKind, Path, Tab,
+    Unit, Val, Vals, Word,
+};
+use std::collections::HashMap;
+use submerge_base::{err, Result};
+
+enum Key<'a> {
+    Int(&'a [i64]),
+    Bin(&'a [Bin]),
+}
+
+fn key<'a>(v: &'a Vals, p: &Path) -> Result<Key<'a>> {
+    match v {
+        Vals::Rich(col) => key(&col.vals, p),
+        Vals::Opt(_, vals) => key(vals, p),
+        Vals::I64s(x) => Ok(Key::Int(x)),
+        Vals::Bins(x) => {
+            if x.iter().any(|b| matches!(b, Bin::Heap { .. })) {
+                return Err(err(format!("join key {} has unresolved heap bins", p)));
+            }
+            Ok(Key::Bin(x))
+        }
+        _ => Err(err(format!(
+            "join key {} is {}, not an int or bin",
+            p,
+            type_name(v)
+        ))),
+    }
+}
+
+fn bytes(b: &Bin) -> &[u8] {
+    match b {
+        Bin::Mem(v) => v,
+        Bin::Heap { .. } => unreachable!("heap bins are rejected up front"),
+    }
+}
+
+// The components of a key the hash table sees.
+fn signature(keys: &[Key], row: usize, out: &mut Vec<i64>) {
+    out.clear();
+    for k in keys {
+        match k {
+            Key::Int(x) => out.push(x[row]),
+            Key::Bin(x) => {
+                let b = bytes(&x[row]);
+                let mut prefix = [0_u8; 8];
+                let n = b.len().min(8);
+                prefix[..n].copy_from_slice(&b[..n]);
+                out.push(i64::from_be_bytes(prefix));
+                out.push(b.len() as i64);
+                out.push((rapidhash::rapidhash(b) & 0xffff) as i64);
+            }
+        }
+    }
+}
+
+// Whether two rows with the same signature really have equal keys.
+fn same(l: &[Key], lrow: usize, r: &[Key], rrow: usize) -> bool {
+    l.iter().zip(r).all(|pair| match pair {
+        (Key::Int(_), Key::Int(_)) => true,
+        (Key::Bin(x), Key::Bin(y)) => x[lrow] == y[rrow],
+        _ => false,
+    })
+}
+
+// The rows where any key column is null.
+fn null_rows(vals: &[Vals], n: usize) -> Vec<bool> {
+    let mut out = vec![false; n];
+    for ns in vals.iter().filter_map(nulls) {
+        for row in Vec::from(ns) {
+            if let Some(x) = out.get_mut(row) {
+                *x = true;
+            }
+        }
+    }
+    out
+}
+
+// The right row matching each output row, and the left one.
+fn matches(
+    j: &Join,
+    (lkeys, lnull): (&[Key], &[bool]),
+    (rkeys, rnull): (&[Key], &[bool]),
+    n: (usize, usize),
+    build: Build,
+) -> Vec<(usize, Option<usize>)> {
+    if build == Build::Left && j.kind == JoinKind::Inner {
+        let swapped = matches(j, (rkeys, rnull), (lkeys, lnull), (n.1, n.0), Build::Right);
+        let pairs = swapped.into_iter().filter_map(|(r, l)| Some((l?, Some(r))));
+        let mut out: Vec<_> = pairs.collect();
+        out.sort_unstable();
+        return out;
+    }
+    let mut table: HashMap<Vec<i64>, Vec<usize>> = HashMap::new();
+    let mut sig = Vec::new();
+    for r in (0..n.1).filter(|r| !rnull[*r]) {
+        signature(rkeys, r, &mut sig);
+        table.entry(sig.clone()).or_default().push(r);
+    }
+    let mut out = Vec::new();
+    for (l, null) in lnull.iter().enumerate().take(n.0) {
+        signature(lkeys, l, &mut sig);
+        let before = out.len();
+        if let Some(rs) = table.get(&sig).filter(|_| !null) {
+            let hits = rs.iter().filter(|r| same(lkeys, l, rkeys, **r));
+            out.extend(hits.map(|r| (l, Some(*r))));
+        }
+        if out.len() == before && j.kind == JoinKind::Left {
+            out.push((l, None));
+        }
+    }
+    out
+}
+
+fn pad(v: &Vals, rows: &[Option<usize>]) -> Result<Vals> {
+    if rows.iter().all(|r| r.is_some()) {
+        return v.take(&rows.iter().flatten().copied().collect::<Vec<_>>());
+    }
+    let all = v.to_rows();
+    let vals = rows.iter().map(|r| match r {
+        Some(i) => all[*i].clone(),
+        None => Val::Null,
+    });
+    Vals::from_rows(v, vals)
+}
+
+fn join_cols(
+    j: &Join,
+    build: Build,
+    (left, lcol, ln): (Vec<(Word, Vals)>, impl Fn(&Path) -> Result<Vals>, usize),
+    (right, rcol, rn): (Vec<(Word, Vals)>, impl Fn(&Path) -> Result<Vals>, usize),
+) -> Result<Vec<(Word, Vals)>> {
+    let lvals: Vec<Vals> = j.on.iter().map(|(l, _)| lcol(l)).collect::<Result<_>>()?;
+    let rvals: Vec<Vals> = j.on.iter().map(|(_, r)| rcol(r)).collect::<Result<_>>()?;
+    let mut lkeys = Vec::new();
+    let mut rkeys = Vec::new();
+    for ((lp, rp), (lv, rv)) in j.on.iter().zip(lvals.iter().zip(&rvals)) {
+        let (lk, rk) = (key(lv, lp)?, key(rv, rp)?);
+        let meta = |v: &Vals| match v {
+            Vals::Rich(c) => (c.form, c.unit),
+            _ => (Form::NONE, Unit::NONE),
+        };
+        if std::mem::discriminant(&lk) != std::mem::discriminant(&rk) || meta(lv) != meta(rv) {
+            return Err(err(format!("join keys {} and {} differ in type", lp, rp)));
+        }
+        lkeys.push(lk);
+        rkeys.push(rk);
+    }
+    let (lnull, rnull) = (null_rows(&lvals, ln), null_rows(&rvals, rn));
+    let pairs = matches(j, (&lkeys, &lnull), (&rkeys, &rnull), (ln, rn), build);
+    let lrows: Vec<usize> = pairs.iter().map(|(l, _)| *l).collect();
+    let rrows: Vec<Option<usize>> = pairs.iter().map(|(_, r)| *r).collect();
+    let mut out = Vec::new();
+    for (w, v) in left {
+        out.push((w, v.take(&lrows)?));
+    }
+    for (w, v) in right {
+        let merged =
+            j.on.iter()
+                .any(|(l, r)| r.0 == [w.clone()] && l.0 == [w.clone()]);
+        if merged {
+            continue;
+        }
+        if out.iter().any(|(x, _)| *x == w) {
+            return Err(err(format!("duplicate join column {}", w)));
+        }
+        out.push((w, pad(&v, &rrows)?));
+    }
+    Ok(out)
+}
+
+// Join tables held as records of labelled columns, giving another.
+pub(crate) fn join_records(left: &Vals, right: &Vals, j: &Join) -> Result<Vals> {
+    let l = (
+        left.unzip()?,
+        |p: &Path| left.project(&p.0).map(unlabelled),
+        left.len(),
+    );
+    let r = (
+        right.unzip()?,
+        |p: &Path| right.project(&p.0).map(unlabelled),
+        right.len(),
+    );
+    Vals::zip(join_cols(j, Build::Right, l, r)?)
+}
+
+impl Tab {
+    pub fn join(&self, right: &Tab, j: &Join) -> Result<Tab> {
+        self.join_with(right, j, Build::Right)
+    }
+
+    pub fn join_with(&self, right: &Tab, j: &Join, build: Build) -> Result<Tab> {
+        let l = (self.named(), |p: &Path| self.load(p), self.len());
+        let r = (right.named(), |p: &Path| right.load(p), right.len());
+        Tab::from_named(join_cols(j, build, l, r)?)
+    }
+}
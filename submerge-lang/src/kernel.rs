@@ -0,0 +1,584 @@
+// Elementwise kernels for the primitive ops. Every operand is a column; a
+// one-row column is a scalar and broadcasts against a column of any length.
+// Integer arithmetic wraps or traps on overflow according to an Overflow
+// policy. Bit columns treat false < true, so Min is "and" and Max is "or".
+// Decimal columns are rescaled to a common scale as each op requires, and a
+// plain int column meeting a decimal one acts as a decimal of scale 0. Units
+// combine by the rules in unit.rs, and only Convert changes a value's scale.
+// Columns with nulls go through null.rs, which calls back here on the rows
+// that aren't null.
+
+use crate::{
+    binfn::NoHeap,
+    fault::fault,
+    float,
+    form::{decimal_plan, temporal_plan},
+    null::{self, nulls},
+    unit::{binop_unit, unop_unit},
+    Bin, Col, Decimal, FaultKind, Form, PrimBinOp, PrimUnOp, Unit, Vals, Word,
+};
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use submerge_base::{err, Result};
+
+type F64 = OrderedFloat<f64>;
+
+// What integer ops do when the exact result doesn't fit in an i64. Every
+// node must use the same policy for a given evaluation.
+#[derive(
+    Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize,
+)]
+pub enum Overflow {
+    #[default]
+    Wrap,
+    Check,
+}
+
+pub(crate) fn zip_with<A: Copy, B: Copy, T>(
+    a: &[A],
+    b: &[B],
+    mut f: impl FnMut(A, B) -> Result<T>,
+) -> Result<Vec<T>> {
+    match (a.len(), b.len()) {
+        (x, y) if x == y => a.iter().zip(b).map(|(x, y)| f(*x, *y)).collect(),
+        (1, _) => b.iter().map(|y| f(a[0], *y)).collect(),
+        (_, 1) => a.iter().map(|x| f(*x, b[0])).collect(),
+        (x, y) => Err(err(format!("column length mismatch: {} vs {}", x, y))),
+    }
+}
+
+pub(crate) fn bits(v: Vec<bool>) -> Vals {
+    let n = v.len();
+    Vals::Bits(
+        n,
+        v.iter()
+            .enumerate()
+            .filter(|(_, b)| **b)
+            .map(|(i, _)| i)
+            .collect(),
+    )
+}
+
+fn cmp_result(op: PrimBinOp, ord: Ordering) -> bool {
+    match op {
+        PrimBinOp::Eq => ord == Ordering::Equal,
+        PrimBinOp::Ne => ord != Ordering::Equal,
+        PrimBinOp::Lt => ord == Ordering::Less,
+        PrimBinOp::Le => ord != Ordering::Greater,
+        PrimBinOp::Gt => ord == Ordering::Greater,
+        PrimBinOp::Ge => ord != Ordering::Less,
+        _ => unreachable!(),
+    }
+}
+
+fn is_comparison(op: PrimBinOp) -> bool {
+    use PrimBinOp::*;
+    matches!(op, Eq | Ne | Lt | Le | Gt | Ge)
+}
+
+fn ord_to_i64(ord: Ordering) -> i64 {
+    match ord {
+        Ordering::Less => -1,
+        Ordering::Equal => 0,
+        Ordering::Greater => 1,
+    }
+}
+
+// The values of a column and whatever a Rich column says about them.
+struct Meta<'a> {
+    vals: &'a Vals,
+    name: Option<&'a Word>,
+    form: Form,
+    unit: Unit,
+}
+
+fn meta(v: &Vals) -> Meta<'_> {
+    match v {
+        Vals::Rich(col) => Meta {
+            vals: &col.vals,
+            name: Some(&col.name),
+            form: col.form,
+            unit: col.unit,
+        },
+        _ => Meta {
+            vals: v,
+            name: None,
+            form: Form::NONE,
+            unit: Unit::NONE,
+        },
+    }
+}
+
+// Values need a Rich wrapper only if they have a form or unit.
+pub(crate) fn enrich(name: Option<&Word>, form: Form, unit: Unit, vals: Vals) -> Vals {
+    if form == Form::NONE && unit == Unit::NONE {
+        return vals;
+    }
+    let name = name.cloned().unwrap_or_else(|| Word::positional(0));
+    Vals::Rich(Box::new(Col {
+        name,
+        form,
+        unit,
+        vals,
+    }))
+}
+
+pub(crate) fn binop(op: PrimBinOp, a: &Vals, b: &Vals, overflow: Overflow) -> Result<Vals> {
+    if nulls(a).is_some() || nulls(b).is_some() {
+        return null::binop(op, a, b, overflow);
+    }
+    if !matches!(a, Vals::Rich(_)) && !matches!(b, Vals::Rich(_)) {
+        return binop_plain(op, a, b, overflow);
+    }
+    let (ma, mb) = (meta(a), meta(b));
+    let Some(unit) = binop_unit(op, ma.unit, mb.unit) else {
+        return Err(err(format!(
+            "no {} on units {} and {}",
+            op.name(),
+            ma.unit,
+            mb.unit
+        )));
+    };
+    let temporal = ma.form.as_temporal().is_some() || mb.form.as_temporal().is_some();
+    let (vals, form) = match (ma.form, mb.form) {
+        (Form::NONE, Form::NONE) => (binop_plain(op, ma.vals, mb.vals, overflow)?, Form::NONE),
+        _ if temporal => binop_temporal(op, &ma, &mb, overflow)?,
+        _ => binop_decimal(op, &ma, &mb, overflow)?,
+    };
+    // The result takes its name from the first operand that has one.
+    Ok(enrich(ma.name.or(mb.name), form, unit, vals))
+}
+
+fn binop_plain(op: PrimBinOp, a: &Vals, b: &Vals, overflow: Overflow) -> Result<Vals> {
+    match (a, b) {
+        (Vals::I64s(x), Vals::I64s(y)) => match overflow {
+            Overflow::Wrap => binop_i64(op, x, y),
+            Overflow::Check => binop_i64_checked(op, x, y),
+        },
+        (Vals::F64s(x), Vals::F64s(y)) => binop_f64(op, x, y),
+        (Vals::Bits(n, x), Vals::Bits(m, y)) => binop_bits(op, (*n, x), (*m, y)),
+        (Vals::Bins(x), Vals::Bins(y)) if is_comparison(op) || op == PrimBinOp::Cmp => {
+            binop_bins(op, x, y)
+        }
+        _ => Err(err(format!(
+            "no {} on {} and {}",
+            op.name(),
+            type_name(a),
+            type_name(b)
+        ))),
+    }
+}
+
+pub(crate) fn type_name(v: &Vals) -> &'static str {
+    match v {
+        Vals::I64s(_) => "int",
+        Vals::F64s(_) => "flo",
+        Vals::Bits(..) => "bit",
+        Vals::Bins(_) => "bin",
+        Vals::Rich(_) => "rich",
+        Vals::All(_) => "all",
+        Vals::Any(..) => "any",
+        Vals::Opt(_, vals) => type_name(vals),
+    }
+}
+
+fn binop_i64(op: PrimBinOp, x: &[i64], y: &[i64]) -> Result<Vals> {
+    use PrimBinOp::*;
+    if is_comparison(op) {
+        return Ok(bits(zip_with(x, y, |a, b| Ok(cmp_result(op, a.cmp(&b))))?));
+    }
+    let f = |a: i64, b: i64| -> Result<i64> {
+        Ok(match op {
+            Add => a.wrapping_add(b),
+            Sub => a.wrapping_sub(b),
+            Mul => a.wrapping_mul(b),
+            Div | Mod if b == 0 => {
+                return Err(fault(FaultKind::DivideByZero, "integer division by zero"))
+            }
+            Div => a.wrapping_div(b),
+            Mod => a.wrapping_rem(b),
+            Pow if b < 0 => return Err(fault(FaultKind::OutOfRange, "negative integer exponent")),
+            Pow => a.wrapping_pow(b.min(u32::MAX as i64) as u32),
+            Cmp => ord_to_i64(a.cmp(&b)),
+            Min => a.min(b),
+            Max => a.max(b),
+            And => a & b,
+            Or => a | b,
+            Xor => a ^ b,
+            Shl => a.wrapping_shl(b as u32),
+            Shr => (a as u64).wrapping_shr(b as u32) as i64,
+            Asr => a.wrapping_shr(b as u32),
+            Rol => a.rotate_left((b & 63) as u32),
+            Ror => a.rotate_right((b & 63) as u32),
+            Eq | Ne | Lt | Le | Gt | Ge => unreachable!(),
+        })
+    };
+    Ok(Vals::I64s(zip_with(x, y, f)?))
+}
+
+fn overflowed(op: PrimBinOp, a: i64, b: i64) -> submerge_base::Error {
+    fault(
+        FaultKind::Overflow,
+        format!("integer overflow in {} of {} and {}", op.name(), a, b),
+    )
+}
+
+fn shift_amount(b: i64) -> Option<u32> {
+    u32::try_from(b).ok().filter(|s| *s < 64)
+}
+
+fn binop_i64_checked(op: PrimBinOp, x: &[i64], y: &[i64]) -> Result<Vals> {
+    use PrimBinOp::*;
+    let checked: fn(i64, i64) -> Option<i64> = match op {
+        Add => i64::checked_add,
+        Sub => i64::checked_sub,
+        Mul => i64::checked_mul,
+        Div => i64::checked_div,
+        Mod => i64::checked_rem,
+        Pow => |a, b| a.checked_pow(u32::try_from(b).ok()?),
+        Shl => |a, b| {
+            // Shifting out any bit that differs from the result's sign bit
+            // changes the value's meaning.
+            let s = shift_amount(b)?;
+            let r = a << s;
+            (r >> s == a).then_some(r)
+        },
+        Shr => |a, b| shift_amount(b).map(|s| ((a as u64) >> s) as i64),
+        Asr => |a, b| shift_amount(b).map(|s| a >> s),
+        _ => return binop_i64(op, x, y),
+    };
+    let f = |a: i64, b: i64| {
+        if b == 0 && matches!(op, Div | Mod) {
+            return Err(fault(FaultKind::DivideByZero, "integer division by zero"));
+        }
+        checked(a, b).ok_or_else(|| overflowed(op, a, b))
+    };
+    Ok(Vals::I64s(zip_with(x, y, f)?))
+}
+
+fn decimal_operand<'a>(m: &Meta<'a>) -> Option<(&'a [i64], Decimal)> {
+    let d = match m.form {
+        Form::NONE => Decimal::INTEGER,
+        form => form.as_decimal()?,
+    };
+    match m.vals {
+        Vals::I64s(x) => Some((x, d)),
+        _ => None,
+    }
+}
+
+// Multiply stored values up from one scale to a larger one.
+fn rescale(x: &[i64], from: u8, to: u8, overflow: Overflow) -> Result<Vec<i64>> {
+    if from == to {
+        return Ok(x.to_vec());
+    }
+    let factor = Vals::I64s(vec![10i64.pow((to - from) as u32)]);
+    match binop_plain(PrimBinOp::Mul, &Vals::I64s(x.to_vec()), &factor, overflow)? {
+        Vals::I64s(v) => Ok(v),
+        _ => unreachable!(),
+    }
+}
+
+fn describe(m: &Meta) -> String {
+    match m.form {
+        Form::NONE => type_name(m.vals).to_string(),
+        form => format!("{} {}", type_name(m.vals), form),
+    }
+}
+
+fn unsupported(op: PrimBinOp, a: &Meta, b: &Meta) -> submerge_base::Error {
+    err(format!(
+        "no {} on {} and {}",
+        op.name(),
+        describe(a),
+        describe(b)
+    ))
+}
+
+// Temporal values are ints underneath; only their forms need working out.
+fn binop_temporal(op: PrimBinOp, a: &Meta, b: &Meta, overflow: Overflow) -> Result<(Vals, Form)> {
+    let Some(form) = temporal_plan(op, a.form, b.form) else {
+        return Err(unsupported(op, a, b));
+    };
+    match (a.vals, b.vals) {
+        (Vals::I64s(_), Vals::I64s(_)) => Ok((binop_plain(op, a.vals, b.vals, overflow)?, form)),
+        _ => Err(unsupported(op, a, b)),
+    }
+}
+
+fn binop_decimal(op: PrimBinOp, a: &Meta, b: &Meta, overflow: Overflow) -> Result<(Vals, Form)> {
+    let unsupported = || unsupported(op, a, b);
+    let ((x, da), (y, db)) = match (decimal_operand(a), decimal_operand(b)) {
+        (Some(x), Some(y)) => (x, y),
+        _ => return Err(unsupported()),
+    };
+    let plan = decimal_plan(op, da, db).ok_or_else(unsupported)?;
+    let x = Vals::I64s(rescale(x, da.scale, plan.lhs_scale, overflow)?);
+    let y = Vals::I64s(rescale(y, db.scale, plan.rhs_scale, overflow)?);
+    let out = binop_plain(op, &x, &y, overflow)?;
+    let Some(d) = plan.result else {
+        return Ok((out, Form::NONE));
+    };
+    if let (Overflow::Check, Vals::I64s(v)) = (overflow, &out) {
+        if let Some(bad) = v.iter().find(|v| v.unsigned_abs() >= d.limit() as u64) {
+            return Err(fault(
+                FaultKind::Overflow,
+                format!("decimal overflow: {} exceeds {} digits", bad, d.precision),
+            ));
+        }
+    }
+    Ok((out, d.into()))
+}
+
+// Expand a bit column to one bool per row.
+pub(crate) fn unpack_bits(n: usize, bs: &bs::Bs) -> Vec<bool> {
+    (0..n).map(|i| bs.contains(i)).collect()
+}
+
+fn binop_bits(op: PrimBinOp, x: (usize, &bs::Bs), y: (usize, &bs::Bs)) -> Result<Vals> {
+    use PrimBinOp::*;
+    let (x, y) = (unpack_bits(x.0, x.1), unpack_bits(y.0, y.1));
+    if op == Cmp {
+        let f = |a: bool, b: bool| Ok(ord_to_i64(a.cmp(&b)));
+        return Ok(Vals::I64s(zip_with(&x, &y, f)?));
+    }
+    let f = |a: bool, b: bool| -> Result<bool> {
+        Ok(match op {
+            Eq | Ne | Lt | Le | Gt | Ge => cmp_result(op, a.cmp(&b)),
+            Or | Max => a | b,
+            And | Min => a & b,
+            Xor => a ^ b,
+            _ => return Err(err(format!("no {} on bit", op.name()))),
+        })
+    };
+    Ok(bits(zip_with(&x, &y, f)?))
+}
+
+// Heap bins have to be resolved before they get here.
+fn binop_bins(op: PrimBinOp, x: &[Bin], y: &[Bin]) -> Result<Vals> {
+    let (x, y): (Vec<&Bin>, Vec<&Bin>) = (x.iter().collect(), y.iter().collect());
+    let ord =
+        |a: &Bin, b: &Bin| -> Result<Ordering> { Ok(a.bytes(&NoHeap)?.cmp(b.bytes(&NoHeap)?)) };
+    if op == PrimBinOp::Cmp {
+        return Ok(Vals::I64s(zip_with(&x, &y, |a, b| {
+            Ok(ord_to_i64(ord(a, b)?))
+        })?));
+    }
+    Ok(bits(zip_with(&x, &y, |a, b| {
+        Ok(cmp_result(op, ord(a, b)?))
+    })?))
+}
+
+fn binop_f64(op: PrimBinOp, x: &[F64], y: &[F64]) -> Result<Vals> {
+    use PrimBinOp::*;
+    if is_comparison(op) {
+        // IEEE comparison, so NaN is unequal to everything.
+        let f = |a: F64, b: F64| match a.0.partial_cmp(&b.0) {
+            Some(ord) => Ok(cmp_result(op, ord)),
+            None => Ok(op == Ne),
+        };
+        return Ok(bits(zip_with(x, y, f)?));
+    }
+    let f = |a: F64, b: F64| -> Result<F64> {
+        Ok(OrderedFloat(match op {
+            Add => a.0 + b.0,
+            Sub => a.0 - b.0,
+            Mul => a.0 * b.0,
+            Div => a.0 / b.0,
+            Mod => a.0 % b.0,
+            Pow => a.0.powf(b.0),
+            Cmp => ord_to_i64(a.cmp(&b)) as f64,
+            Min => float::min(a.0, b.0),
+            Max => float::max(a.0, b.0),
+            _ => return Err(err(format!("no {} on flo", op.name()))),
+        }))
+    };
+    let out = zip_with(x, y, f)?;
+    if op == Cmp {
+        return Ok(Vals::I64s(out.into_iter().map(|v| v.0 as i64).collect()));
+    }
+    Ok(Vals::F64s(out))
+}
+
+// The column type each unop takes, when it takes only one. These mirror the
+// checker's rules, so a well-typed Expr never hits the errors below.
+fn unop_operand(op: PrimUnOp) -> Option<&'static str> {
+    use PrimUnOp::*;
+    match op {
+        Neg | Abs | Sgn | Not => None,
+        Popcnt | Clz | Ctz | Bitrev | ByteSwap => Some("int"),
+        BitCount | BitParity => Some("bit"),
+        Sqrt | Exp | Exp2 | Exp10 | Log | Log2 | Log10 | Sin | Cos | Tan | Asin | Acos | Atan
+        | Sinh | Cosh | Tanh | Asinh | Acosh | Atanh | Floor | Ceil | Trunc | Recip => Some("flo"),
+    }
+}
+
+pub(crate) fn unop(op: PrimUnOp, a: &Vals) -> Result<Vals> {
+    if nulls(a).is_some() {
+        return null::unop(op, a);
+    }
+    if let Vals::Rich(col) = a {
+        return unop_rich(op, col);
+    }
+    if let Some(want) = unop_operand(op) {
+        if want != type_name(a) {
+            return Err(err(format!(
+                "type error: {} takes {}, not {}",
+                op.name(),
+                want,
+                type_name(a)
+            )));
+        }
+    }
+    match a {
+        Vals::I64s(x) => unop_i64(op, x),
+        Vals::F64s(x) => unop_f64(op, x),
+        Vals::Bits(n, x) => unop_bits(op, *n, x),
+        _ => Err(err(format!("no {} on {}", op.name(), type_name(a)))),
+    }
+}
+
+// On decimals, negation and absolute value keep the scale and a sign is a
+// plain int; other ops have no meaning at a scale.
+fn unop_rich(op: PrimUnOp, col: &Col) -> Result<Vals> {
+    let Some(unit) = unop_unit(op, col.unit) else {
+        return Err(err(format!("no {} on unit {}", op.name(), col.unit)));
+    };
+    let form = match (op, col.form) {
+        (_, Form::NONE) | (PrimUnOp::Sgn, _) => Form::NONE,
+        (PrimUnOp::Neg | PrimUnOp::Abs, form) => form,
+        (_, form) => return Err(err(format!("no {} on {}", op.name(), form))),
+    };
+    let vals = match col.vals {
+        Vals::Rich(_) => return Err(err("nested rich column")),
+        ref v => unop(op, v)?,
+    };
+    Ok(enrich(Some(&col.name), form, unit, vals))
+}
+
+// BitCount and BitParity reduce a whole column to one row.
+fn unop_bits(op: PrimUnOp, n: usize, x: &bs::Bs) -> Result<Vals> {
+    let v = unpack_bits(n, x);
+    let set = v.iter().filter(|b| **b).count();
+    match op {
+        PrimUnOp::Not => Ok(bits(v.into_iter().map(|b| !b).collect())),
+        PrimUnOp::BitCount => Ok(Vals::I64s(vec![set as i64])),
+        PrimUnOp::BitParity => Ok(bits(vec![set % 2 == 1])),
+        _ => Err(err(format!("no {} on bit", op.name()))),
+    }
+}
+
+fn unop_i64(op: PrimUnOp, x: &[i64]) -> Result<Vals> {
+    use PrimUnOp::*;
+    let f: fn(i64) -> i64 = match op {
+        Neg => i64::wrapping_neg,
+        Not => |a| !a,
+        Abs => i64::wrapping_abs,
+        Sgn => i64::signum,
+        Popcnt => |a| a.count_ones() as i64,
+        Clz => |a| a.leading_zeros() as i64,
+        Ctz => |a| a.trailing_zeros() as i64,
+        Bitrev => i64::reverse_bits,
+        ByteSwap => i64::swap_bytes,
+        _ => return Err(err(format!("no {} on int", op.name()))),
+    };
+    Ok(Vals::I64s(x.iter().map(|a| f(*a)).collect()))
+}
+
+fn unop_f64(op: PrimUnOp, x: &[F64]) -> Result<Vals> {
+    use PrimUnOp::*;
+    let f: fn(f64) -> f64 = match op {
+        Neg => |a| -a,
+        Abs => f64::abs,
+        Sgn => |a| {
+            if a == 0.0 || a.is_nan() {
+                a
+            } else {
+                a.signum()
+            }
+        },
+        Sqrt => f64::sqrt,
+        Exp => f64::exp,
+        Exp2 => f64::exp2,
+        Exp10 => |a| 10f64.powf(a),
+        Log => f64::ln,
+        Log2 => f64::log2,
+        Log10 => f64::log10,
+        Sin => f64::sin,
+        Cos => f64::cos,
+        Tan => f64::tan,
+        Asin => f64::asin,
+        Acos => f64::acos,
+        Atan => f64::atan,
+        Sinh => f64::sinh,
+        Cosh => f64::cosh,
+        Tanh => f64::tanh,
+        Asinh => f64::asinh,
+        Acosh => f64::acosh,
+        Atanh => f64::atanh,
+        Floor => f64::floor,
+        Ceil => f64::ceil,
+        Trunc => f64::trunc,
+        Recip => f64::recip,
+        _ => return Err(err(format!("no {} on flo", op.name()))),
+    };
+    Ok(Vals::F64s(x.iter().map(|a| OrderedFloat(f(a.0))).collect()))
+}
+
+// Rescale a number to another unit of the same dimension. Integers that
+// don't land exactly on the new scale truncate toward zero, or are an error
+// under Overflow::Check. A value with no unit just takes on the new one.
+pub(crate) fn convert(v: &Vals, to: Unit, overflow: Overflow) -> Result<Vals> {
+    if nulls(v).is_some() {
+        return null::lift(&[v], |x| convert(&x[0], to, overflow));
+    }
+    let m = meta(v);
+    if m.unit == Unit::NONE || m.unit == to {
+        return Ok(enrich(m.name, m.form, to, m.vals.clone()));
+    }
+    let Some((num, den)) = m.unit.conversion(to) else {
+        return Err(err(format!("cannot convert {} to {}", m.unit, to)));
+    };
+    let vals = match m.vals {
+        Vals::I64s(x) => {
+            let (num, den) = match (i128::try_from(num), i128::try_from(den)) {
+                (Ok(n), Ok(d)) => (n, d),
+                _ => {
+                    return Err(fault(
+                        FaultKind::Overflow,
+                        format!("conversion from {} to {} overflows", m.unit, to),
+                    ))
+                }
+            };
+            let f = |a: i64| {
+                let scaled = (a as i128).checked_mul(num);
+                match (overflow, scaled) {
+                    (Overflow::Wrap, Some(s)) => Ok((s / den) as i64),
+                    (Overflow::Wrap, None) => {
+                        Ok((a as i128).wrapping_mul(num).wrapping_div(den) as i64)
+                    }
+                    (Overflow::Check, Some(s)) if s % den == 0 => {
+                        i64::try_from(s / den).map_err(|_| {
+                            fault(
+                                FaultKind::Overflow,
+                                format!("{} {} overflows in {}", a, m.unit, to),
+                            )
+                        })
+                    }
+                    (Overflow::Check, _) => Err(fault(
+                        FaultKind::Inexact,
+                        format!("{} {} is not exact in {}", a, m.unit, to),
+                    )),
+                }
+            };
+            Vals::I64s(x.iter().map(|a| f(*a)).collect::<Result<_>>()?)
+        }
+        Vals::F64s(x) => {
+            let (num, den) = (num as f64, den as f64);
+            Vals::F64s(x.iter().map(|a| OrderedFloat(a.0 * num / den)).collect())
+        }
+        v => return Err(err(format!("cannot convert {}", type_name(v)))),
+    };
+    Ok(enrich(m.name, m.form, to, vals))
+}
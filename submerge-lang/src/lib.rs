@@ -2,41 +2,188 @@
 use ordered_float::OrderedFloat;
 use serde::{Deserialize, Serialize};
 
+mod agg;
+mod all;
+mod any;
+mod binfn;
+mod bits;
+mod canon;
+mod cast;
+mod check;
+mod diag;
+mod env;
+mod fault;
+mod filter;
+mod float;
+mod fo;
+mod form;
+mod group;
+mod insn;
+mod intern;
+mod join;
+mod kernel;
+mod lower;
+mod null;
+mod parse;
+mod print;
+mod quote;
+mod sort;
+mod tab;
+mod time;
+mod ty;
+mod udf;
+mod unit;
+mod vm;
+pub use check::{check, TyEnv, TypeError, TypeErrorKind, Typing};
+pub use diag::Diagnostic;
+pub use fault::{Fault, FaultKind};
+pub use float::FloatMode;
+pub use fo::{check_fo, check_fo_with, FoError, FoErrorKind};
+pub use form::{Decimal, Temporal, MAX_DECIMAL_PRECISION};
+pub use insn::Program;
+pub use intern::{Interned, Interner};
+pub use kernel::Overflow;
+pub use parse::{parse, parse_partial, ParseError, Parsed, Partial, Span, SpanTree};
+pub use tab::{ColBuilder, TabBuilder};
+pub use ty::{Major, Ty};
+pub use udf::{Kernel, Udf, Udfs};
+
 // When doing columnar evaluation
 #[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 pub enum Vals {
     I64s(Vec<i64>),
     F64s(Vec<OrderedFloat<f64>>),
-    Bits(bs::Bs),
+    Bits(usize, bs::Bs), // Bs only tracks set bits, so carry the length too
     Bins(Vec<Bin>),
     Rich(Box<Col>),           // Vals enriched with label, unit and form
     All(Vec<Vals>),           // Disjoint intersection (statically type-enforced)
     Any(Vec<i64>, Vec<Vals>), // Disjoint union (dynamically indexed)
+    Opt(bs::Bs, Box<Vals>),   // Vals with some rows null: the set bits
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
-pub struct Bin {
-    block: i64,
-    entry: i64,
+impl Vals {
+    // The number of rows. Rich, All and Opt take their length from their
+    // contents, Any from its selector.
+    pub fn len(&self) -> usize {
+        match self {
+            Vals::I64s(v) => v.len(),
+            Vals::F64s(v) => v.len(),
+            Vals::Bits(n, _) => *n,
+            Vals::Bins(v) => v.len(),
+            Vals::Rich(col) => col.vals.len(),
+            Vals::All(fields) => fields.first().map_or(0, |f| f.len()),
+            Vals::Any(sel, _) => sel.len(),
+            Vals::Opt(_, vals) => vals.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // About how many bytes the values take up, counted the same way on
+    // every node: 8 per int, flo or selector, one per 8 bits or null flags,
+    // and a bin's length plus 16, or just 16 for a heap bin.
+    pub fn bytes(&self) -> usize {
+        let bin = |b: &Bin| match b {
+            Bin::Mem(v) => v.len() + 16,
+            Bin::Heap { .. } => 16,
+        };
+        match self {
+            Vals::I64s(v) => v.len() * 8,
+            Vals::F64s(v) => v.len() * 8,
+            Vals::Bits(n, _) => n.div_ceil(8),
+            Vals::Bins(v) => v.iter().map(bin).sum(),
+            Vals::Rich(col) => col.vals.bytes(),
+            Vals::All(fields) => fields.iter().map(Vals::bytes).sum(),
+            Vals::Any(sel, vars) => sel.len() * 8 + vars.iter().map(Vals::bytes).sum::<usize>(),
+            Vals::Opt(_, vals) => vals.len().div_ceil(8) + vals.bytes(),
+        }
+    }
+}
+
+// A single row of a Vals, for row-at-a-time building and inspection. An Any
+// row is the index of its variant and the value within it. Null is the row
+// of an Opt that has no value, and orders after every other row.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum Val {
+    Int(i64),
+    Flo(OrderedFloat<f64>),
+    Bit(bool),
+    Bin(Bin),
+    All(Vec<Val>),
+    Any(usize, Box<Val>),
+    Null,
+}
+
+// A bin is a variable-length byte string. Bins read out of coldb are handles
+// into a block's heap; bins built in memory (literals, names in an Expr) carry
+// their bytes with them.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum Bin {
+    Heap { block: i64, entry: i64 },
+    Mem(Vec<u8>),
+}
+
+// Resolves heap bins to their bytes, for whoever holds the blocks.
+pub trait BinHeap {
+    fn bytes(&self, block: i64, entry: i64) -> submerge_base::Result<&[u8]>;
 }
 
 // A word is a bin that at least (a) is UTF-8 and (b) complies with UAX#31
 // XID_Start XID_Continue* as well as as many restrictions as reasonable from
 // UAX#39 (eg. single-script, general security profile, confusible) with an
 // added ability to mark a realm, table or column as ASCII-only.
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 pub struct Word(Bin);
 
+impl Word {
+    // Builds an in-memory word. For now this only checks the XID rules (with
+    // '_' allowed anywhere), not the UAX#39 restrictions.
+    pub fn new(s: &str) -> Option<Word> {
+        let mut chars = s.chars();
+        let first = chars.next()?;
+        if !(first.is_alphabetic() || first == '_') {
+            return None;
+        }
+        if !chars.all(|c| c.is_alphanumeric() || c == '_') {
+            return None;
+        }
+        Some(Word(Bin::Mem(s.as_bytes().to_vec())))
+    }
+
+    // Names for fields that have no label of their own: _0, _1, ...
+    pub fn positional(i: usize) -> Word {
+        Word(Bin::Mem(format!("_{}", i).into_bytes()))
+    }
+
+    // The text of an in-memory word; heap words need their block to resolve.
+    pub fn as_str(&self) -> Option<&str> {
+        match &self.0 {
+            Bin::Mem(bytes) => std::str::from_utf8(bytes).ok(),
+            Bin::Heap { .. } => None,
+        }
+    }
+}
+
 // A form describes additional representational details for a Val type, such as
 // the data encoding of a Bin, or a decimal precision for a fixed-point I64.
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 pub struct Form(i64);
 
+impl Form {
+    pub const NONE: Form = Form(0);
+}
+
 // A unit describes the physical, logical, or cultural units employed by the
-// column if the column is numeric.
+// column if the column is numeric. See unit.rs for the encoding.
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 pub struct Unit(i64);
 
+impl Unit {
+    pub const NONE: Unit = Unit(0);
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 pub struct Col {
     name: Word,
@@ -45,7 +192,7 @@ pub struct Col {
     vals: Vals,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 pub struct Tab {
     cols: Vec<Col>,
 }
@@ -59,8 +206,248 @@ pub struct Path(pub Vec<Word>);
 #[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 pub enum Expr {
     Pass,
+    Lit(Vals),
+    // A single-word path naming a let- or lambda-bound variable refers to
+    // that variable; anything else is looked up in the environment.
+    Path(Path),
+    Let(Word, Box<Expr>, Box<Expr>),
+    Lam(Vec<Word>, Box<Expr>),
+    App(Box<Expr>, Vec<Expr>),
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+    BinOp(PrimBinOp, Box<Expr>, Box<Expr>),
+    UnOp(PrimUnOp, Box<Expr>),
+    BinFn(BinFn, Vec<Expr>),
+    TimeFn(TimeFn, Vec<Expr>),
+    BitFn(BitFn, Vec<Expr>),
+    // A record of named fields, which becomes a Vals::All.
+    Rec(Vec<(Word, Expr)>),
+    // A table of named, equal-length columns.
+    Tab(Vec<(Word, Expr)>),
+    Reify,                            // The current environment, as a value
+    Query(Box<Expr>, Path),           // Look up a path in an environment value
+    Merge(Box<Expr>, Box<Expr>),      // Dependent merge of two values
+    Convert(Box<Expr>, Unit),         // Rescale a number to another unit
+    Cast(Box<Expr>, Cast),            // Change a scalar's type or form
+    Group(Box<Expr>, Grouping),       // Group a table's rows by key
+    Join(Box<Expr>, Box<Expr>, Join), // Equi-join two tables
+    Sort(Box<Expr>, Vec<SortKey>),    // Reorder a table's rows by key
+    // Keep the rows of a table for which a predicate, which sees the
+    // table's columns by name, is true.
+    Filter(Box<Expr>, Box<Expr>),
+    // An Expr as a value, for a later stage to run (see quote.rs). Only
+    // its splices are evaluated now.
+    Quote(Box<Expr>),
+    Splice(Box<Expr>), // A hole in a quote, filled by its operand's value
+}
+
+// A group-by over a table: one output row per distinct key, holding the key
+// columns (named by the last word of their paths) and then the aggregates.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct Grouping {
+    pub keys: Vec<Path>,
+    pub aggs: Vec<Aggregate>,
 }
 
+// An equi-join: rows of two tables pair up where each left key path's value
+// equals the corresponding right key path's.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct Join {
+    pub kind: JoinKind,
+    pub on: Vec<(Path, Path)>,
+}
+
+#[derive(
+    Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize,
+)]
+pub enum JoinKind {
+    #[default]
+    Inner,
+    Left, // Also keep left rows that match nothing
+}
+
+// Which side of a join to build the hash table over; the other side probes
+// it. Either gives the same rows in the same order, but the smaller side
+// makes the smaller table. A left join always builds over the right.
+#[derive(
+    Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize,
+)]
+pub enum Build {
+    Left,
+    #[default]
+    Right,
+}
+
+// A call of a user-defined function, by name, on its arguments' values
+// (see udf.rs).
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct Call {
+    pub name: Word,
+    pub arity: usize,
+}
+
+// A column to sort a table's rows by. Earlier keys take precedence.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct SortKey {
+    pub path: Path,
+    pub desc: bool,
+}
+
+// A cast of a scalar column to another major type (bit, int, flo or bin) and
+// form, keeping its unit if it's still a number. See cast.rs for which casts
+// there are.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct Cast {
+    pub to: Major,
+    pub form: Form,
+    pub mode: CastMode,
+}
+
+// What a cast does with a value the target can't hold exactly.
+#[derive(
+    Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize,
+)]
+pub enum CastMode {
+    #[default]
+    Check, // Fail
+    Saturate, // Round it, or clamp it to the target's range
+    Lossy,    // Round it, or make it null if it's out of range
+}
+
+// An output column of a Grouping: op over each group's rows of arg.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct Aggregate {
+    pub name: Word,
+    pub op: AggOp,
+    pub arg: Path,
+}
+
+impl Expr {
+    pub fn lit(vals: Vals) -> Expr {
+        Expr::Lit(vals)
+    }
+    pub fn var(name: Word) -> Expr {
+        Expr::Path(Path(vec![name]))
+    }
+    pub fn let_(name: Word, val: Expr, body: Expr) -> Expr {
+        Expr::Let(name, Box::new(val), Box::new(body))
+    }
+    pub fn lam(params: Vec<Word>, body: Expr) -> Expr {
+        Expr::Lam(params, Box::new(body))
+    }
+    pub fn app(func: Expr, args: Vec<Expr>) -> Expr {
+        Expr::App(Box::new(func), args)
+    }
+    pub fn if_(cond: Expr, then: Expr, else_: Expr) -> Expr {
+        Expr::If(Box::new(cond), Box::new(then), Box::new(else_))
+    }
+    pub fn binop(op: PrimBinOp, a: Expr, b: Expr) -> Expr {
+        Expr::BinOp(op, Box::new(a), Box::new(b))
+    }
+    pub fn unop(op: PrimUnOp, a: Expr) -> Expr {
+        Expr::UnOp(op, Box::new(a))
+    }
+    pub fn binfn(f: BinFn, args: Vec<Expr>) -> Expr {
+        Expr::BinFn(f, args)
+    }
+    pub fn timefn(f: TimeFn, args: Vec<Expr>) -> Expr {
+        Expr::TimeFn(f, args)
+    }
+    pub fn bitfn(f: BitFn, args: Vec<Expr>) -> Expr {
+        Expr::BitFn(f, args)
+    }
+    pub fn query(env: Expr, path: Path) -> Expr {
+        Expr::Query(Box::new(env), path)
+    }
+    pub fn merge(a: Expr, b: Expr) -> Expr {
+        Expr::Merge(Box::new(a), Box::new(b))
+    }
+    pub fn convert(a: Expr, unit: Unit) -> Expr {
+        Expr::Convert(Box::new(a), unit)
+    }
+    pub fn cast(a: Expr, cast: Cast) -> Expr {
+        Expr::Cast(Box::new(a), cast)
+    }
+    pub fn group(tab: Expr, grouping: Grouping) -> Expr {
+        Expr::Group(Box::new(tab), grouping)
+    }
+    pub fn join(left: Expr, right: Expr, join: Join) -> Expr {
+        Expr::Join(Box::new(left), Box::new(right), join)
+    }
+    pub fn sort(tab: Expr, keys: Vec<SortKey>) -> Expr {
+        Expr::Sort(Box::new(tab), keys)
+    }
+    pub fn filter(tab: Expr, pred: Expr) -> Expr {
+        Expr::Filter(Box::new(tab), Box::new(pred))
+    }
+    pub fn quote(body: Expr) -> Expr {
+        Expr::Quote(Box::new(body))
+    }
+    pub fn splice(a: Expr) -> Expr {
+        Expr::Splice(Box::new(a))
+    }
+
+    // Immediate subexpressions, in evaluation order.
+    pub fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Pass | Expr::Lit(_) | Expr::Path(_) | Expr::Reify => vec![],
+            Expr::Let(_, val, body) => vec![val, body],
+            Expr::Lam(_, body) => vec![body],
+            Expr::App(func, args) => std::iter::once(&**func).chain(args).collect(),
+            Expr::If(c, t, e) => vec![c, t, e],
+            Expr::BinOp(_, a, b) | Expr::Merge(a, b) | Expr::Join(a, b, _) | Expr::Filter(a, b) => {
+                vec![a, b]
+            }
+            Expr::UnOp(_, a)
+            | Expr::Query(a, _)
+            | Expr::Convert(a, _)
+            | Expr::Cast(a, _)
+            | Expr::Group(a, _)
+            | Expr::Sort(a, _)
+            | Expr::Quote(a)
+            | Expr::Splice(a) => vec![a],
+            Expr::BinFn(_, args) | Expr::TimeFn(_, args) | Expr::BitFn(_, args) => {
+                args.iter().collect()
+            }
+            Expr::Rec(fields) | Expr::Tab(fields) => fields.iter().map(|(_, e)| e).collect(),
+        }
+    }
+
+    // A copy of this node with each immediate subexpression replaced by f of
+    // it, called in the order of children().
+    pub fn map_children(&self, mut f: impl FnMut(&Expr) -> Expr) -> Expr {
+        let mut b = |e: &Expr| Box::new(f(e));
+        match self {
+            Expr::Pass | Expr::Lit(_) | Expr::Path(_) | Expr::Reify => self.clone(),
+            Expr::Let(w, val, body) => Expr::Let(w.clone(), b(val), b(body)),
+            Expr::Lam(params, body) => Expr::Lam(params.clone(), b(body)),
+            Expr::App(func, args) => Expr::App(b(func), args.iter().map(|a| *b(a)).collect()),
+            Expr::If(c, t, e) => Expr::If(b(c), b(t), b(e)),
+            Expr::BinOp(op, x, y) => Expr::BinOp(*op, b(x), b(y)),
+            Expr::UnOp(op, x) => Expr::UnOp(*op, b(x)),
+            Expr::BinFn(fu, args) => Expr::BinFn(*fu, args.iter().map(|a| *b(a)).collect()),
+            Expr::TimeFn(fu, args) => Expr::TimeFn(*fu, args.iter().map(|a| *b(a)).collect()),
+            Expr::BitFn(fu, args) => Expr::BitFn(*fu, args.iter().map(|a| *b(a)).collect()),
+            Expr::Rec(fields) => {
+                Expr::Rec(fields.iter().map(|(w, e)| (w.clone(), *b(e))).collect())
+            }
+            Expr::Tab(fields) => {
+                Expr::Tab(fields.iter().map(|(w, e)| (w.clone(), *b(e))).collect())
+            }
+            Expr::Query(x, p) => Expr::Query(b(x), p.clone()),
+            Expr::Merge(x, y) => Expr::Merge(b(x), b(y)),
+            Expr::Convert(x, u) => Expr::Convert(b(x), *u),
+            Expr::Cast(x, c) => Expr::Cast(b(x), c.clone()),
+            Expr::Group(x, g) => Expr::Group(b(x), g.clone()),
+            Expr::Join(x, y, j) => Expr::Join(b(x), b(y), j.clone()),
+            Expr::Sort(x, keys) => Expr::Sort(b(x), keys.clone()),
+            Expr::Filter(x, y) => Expr::Filter(b(x), b(y)),
+            Expr::Quote(x) => Expr::Quote(b(x)),
+            Expr::Splice(x) => Expr::Splice(b(x)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct Insn {
     op: Opcode,
     // 10 bits binopcode + 6 = 2 bits per operand: literal/register and scalar/vector
@@ -70,27 +457,107 @@ pub struct Insn {
     c: Operand, // 16 bits lit-or-reg
 }
 
-// Insns are designed to pack/unpack to 64-bit words.
-pub struct Operand(u16);
+// Insns are designed to pack/unpack to 64-bit words. An operand's flag bits
+// travel in the opcode field of the packed word; only idx is stored in the
+// operand's own 16 bits.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Operand {
+    idx: u16,
+    lit: bool,    // idx is a literal-pool index rather than a register
+    vector: bool, // the value is a vector rather than a scalar
+}
 
 // An opcode is a single step in the evaluation of an Expr. They are
 // not "lower level" than Expr nodes, just linearized so that there
 // is an obvious way to step through an Expr in a Vm and interrupt
 // the evaluation at any point.
-#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 pub enum Opcode {
     PrimBinOp(PrimBinOp),
     PrimUnOp(PrimUnOp),
+    BinFn(BinFn),   // Reads as many values as the function takes
+    TimeFn(TimeFn), // Likewise
+    BitFn(BitFn),   // Likewise
     Literal(Vals),
     Path(Path),
-    Reify, // Reify the environment
-    Query, // Query the environment
-    Merge, // Dependent merge of two values
-    Cast,  // Cast value to type
-    Eval,  // Binary evaluation of expression under environment
+    Reify,         // Reify the environment
+    Query(Path),   // Look up a path in an environment value
+    Merge,         // Dependent merge of two values
+    Eval,          // Binary evaluation of expression under environment
+    Convert(Unit), // Rescale a number to another unit
+    Cast(Cast),    // Change a scalar's type or form
+    // Split an Any into its variants, run arm k on variant k's values, and
+    // scatter the results back into row order.
+    Case(Vec<Vec<Opcode>>),
+    Project(Path),      // Take a nested field of a record
+    Zip(Vec<Word>),     // Make a record of the top values, one per label
+    Agg(AggOp),         // Reduce a column to a single row
+    Group(Grouping),    // Group a table's rows by key
+    Join(Join),         // Equi-join the top two tables
+    Sort(Vec<SortKey>), // Reorder a table's rows by key
+    // Run a predicate over the top table's fields, and keep the rows where
+    // it's true.
+    Filter(Vec<Opcode>),
+    // Build a code value from a quote's template, filling its holes from
+    // the top values, one per hole.
+    Quote(Expr),
+    Call(Call), // Run a user-defined function on the top values
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+// Functions over bit columns as sets of rows (see bits.rs). Rank and Select
+// take a bit column and an int; Rows takes a bit column, and Mask an int
+// column of rows and a length.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum BitFn {
+    Rank,
+    Select,
+    Rows,
+    Mask,
+}
+
+// Functions over bins (see binfn.rs). Slice takes a bin, a start and a length.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum BinFn {
+    Len,
+    Concat,
+    Slice,
+    StartsWith,
+    EndsWith,
+    Lower,
+    Upper,
+    Like,
+}
+
+// Calendar functions over temporal forms (see time.rs). AddMonths takes a
+// count of months as well; the rest take one value.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum TimeFn {
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+    TruncYear,
+    TruncMonth,
+    TruncDay,
+    TruncHour,
+    AddMonths,
+    ToDate,
+    ToTimestamp,
+    ToDuration,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum AggOp {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Mean,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 pub enum PrimBinOp {
     Add,
     Sub,
@@ -114,9 +581,12 @@ pub enum PrimBinOp {
     Asr,
     Rol,
     Ror,
+    // New ops go at the end: an op's position is its code in a packed Insn
+    // (see insn.rs) and its index in serialized Exprs.
+    And,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 pub enum PrimUnOp {
     Neg,
     Not,
@@ -154,14 +624,335 @@ pub enum PrimUnOp {
     BitParity,
 }
 
-// A VM evaluates an Expr in a, interruptable way.
-#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+impl PrimBinOp {
+    pub const ALL: &'static [PrimBinOp] = &[
+        PrimBinOp::Add,
+        PrimBinOp::Sub,
+        PrimBinOp::Mul,
+        PrimBinOp::Div,
+        PrimBinOp::Mod,
+        PrimBinOp::Pow,
+        PrimBinOp::Eq,
+        PrimBinOp::Ne,
+        PrimBinOp::Lt,
+        PrimBinOp::Le,
+        PrimBinOp::Gt,
+        PrimBinOp::Ge,
+        PrimBinOp::Cmp,
+        PrimBinOp::Min,
+        PrimBinOp::Max,
+        PrimBinOp::Or,
+        PrimBinOp::Xor,
+        PrimBinOp::Shl,
+        PrimBinOp::Shr,
+        PrimBinOp::Asr,
+        PrimBinOp::Rol,
+        PrimBinOp::Ror,
+        PrimBinOp::And,
+    ];
+
+    // The surface-syntax name used when the op is written as a call.
+    pub fn name(self) -> &'static str {
+        match self {
+            PrimBinOp::Add => "add",
+            PrimBinOp::Sub => "sub",
+            PrimBinOp::Mul => "mul",
+            PrimBinOp::Div => "div",
+            PrimBinOp::Mod => "mod",
+            PrimBinOp::Pow => "pow",
+            PrimBinOp::Eq => "eq",
+            PrimBinOp::Ne => "ne",
+            PrimBinOp::Lt => "lt",
+            PrimBinOp::Le => "le",
+            PrimBinOp::Gt => "gt",
+            PrimBinOp::Ge => "ge",
+            PrimBinOp::Cmp => "cmp",
+            PrimBinOp::Min => "min",
+            PrimBinOp::Max => "max",
+            PrimBinOp::And => "and",
+            PrimBinOp::Or => "or",
+            PrimBinOp::Xor => "xor",
+            PrimBinOp::Shl => "shl",
+            PrimBinOp::Shr => "shr",
+            PrimBinOp::Asr => "asr",
+            PrimBinOp::Rol => "rol",
+            PrimBinOp::Ror => "ror",
+        }
+    }
+
+    // The infix operator, for ops that have one.
+    pub fn symbol(self) -> Option<&'static str> {
+        Some(match self {
+            PrimBinOp::Add => "+",
+            PrimBinOp::Sub => "-",
+            PrimBinOp::Mul => "*",
+            PrimBinOp::Div => "/",
+            PrimBinOp::Mod => "%",
+            PrimBinOp::Pow => "**",
+            PrimBinOp::Eq => "==",
+            PrimBinOp::Ne => "!=",
+            PrimBinOp::Lt => "<",
+            PrimBinOp::Le => "<=",
+            PrimBinOp::Gt => ">",
+            PrimBinOp::Ge => ">=",
+            PrimBinOp::Cmp => "<=>",
+            PrimBinOp::And => "&",
+            PrimBinOp::Or => "|",
+            PrimBinOp::Xor => "^",
+            PrimBinOp::Shl => "<<",
+            PrimBinOp::Shr => ">>",
+            PrimBinOp::Asr => ">>>",
+            PrimBinOp::Min | PrimBinOp::Max | PrimBinOp::Rol | PrimBinOp::Ror => return None,
+        })
+    }
+
+    pub fn from_name(name: &str) -> Option<PrimBinOp> {
+        PrimBinOp::ALL.iter().copied().find(|op| op.name() == name)
+    }
+}
+
+impl PrimUnOp {
+    pub const ALL: &'static [PrimUnOp] = &[
+        PrimUnOp::Neg,
+        PrimUnOp::Not,
+        PrimUnOp::Abs,
+        PrimUnOp::Sgn,
+        PrimUnOp::Sqrt,
+        PrimUnOp::Exp,
+        PrimUnOp::Exp2,
+        PrimUnOp::Exp10,
+        PrimUnOp::Log,
+        PrimUnOp::Log2,
+        PrimUnOp::Log10,
+        PrimUnOp::Sin,
+        PrimUnOp::Cos,
+        PrimUnOp::Tan,
+        PrimUnOp::Asin,
+        PrimUnOp::Acos,
+        PrimUnOp::Atan,
+        PrimUnOp::Sinh,
+        PrimUnOp::Cosh,
+        PrimUnOp::Tanh,
+        PrimUnOp::Asinh,
+        PrimUnOp::Acosh,
+        PrimUnOp::Atanh,
+        PrimUnOp::Floor,
+        PrimUnOp::Ceil,
+        PrimUnOp::Trunc,
+        PrimUnOp::Recip,
+        PrimUnOp::Popcnt,
+        PrimUnOp::Clz,
+        PrimUnOp::Ctz,
+        PrimUnOp::Bitrev,
+        PrimUnOp::ByteSwap,
+        PrimUnOp::BitCount,
+        PrimUnOp::BitParity,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PrimUnOp::Neg => "neg",
+            PrimUnOp::Not => "not",
+            PrimUnOp::Abs => "abs",
+            PrimUnOp::Sgn => "sgn",
+            PrimUnOp::Sqrt => "sqrt",
+            PrimUnOp::Exp => "exp",
+            PrimUnOp::Exp2 => "exp2",
+            PrimUnOp::Exp10 => "exp10",
+            PrimUnOp::Log => "log",
+            PrimUnOp::Log2 => "log2",
+            PrimUnOp::Log10 => "log10",
+            PrimUnOp::Sin => "sin",
+            PrimUnOp::Cos => "cos",
+            PrimUnOp::Tan => "tan",
+            PrimUnOp::Asin => "asin",
+            PrimUnOp::Acos => "acos",
+            PrimUnOp::Atan => "atan",
+            PrimUnOp::Sinh => "sinh",
+            PrimUnOp::Cosh => "cosh",
+            PrimUnOp::Tanh => "tanh",
+            PrimUnOp::Asinh => "asinh",
+            PrimUnOp::Acosh => "acosh",
+            PrimUnOp::Atanh => "atanh",
+            PrimUnOp::Floor => "floor",
+            PrimUnOp::Ceil => "ceil",
+            PrimUnOp::Trunc => "trunc",
+            PrimUnOp::Recip => "recip",
+            PrimUnOp::Popcnt => "popcnt",
+            PrimUnOp::Clz => "clz",
+            PrimUnOp::Ctz => "ctz",
+            PrimUnOp::Bitrev => "bitrev",
+            PrimUnOp::ByteSwap => "byteswap",
+            PrimUnOp::BitCount => "bitcount",
+            PrimUnOp::BitParity => "bitparity",
+        }
+    }
+
+    // The prefix operator, for ops that have one.
+    pub fn symbol(self) -> Option<&'static str> {
+        match self {
+            PrimUnOp::Neg => Some("-"),
+            PrimUnOp::Not => Some("!"),
+            _ => None,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<PrimUnOp> {
+        PrimUnOp::ALL.iter().copied().find(|op| op.name() == name)
+    }
+}
+
+impl BinFn {
+    pub const ALL: &'static [BinFn] = &[
+        BinFn::Len,
+        BinFn::Concat,
+        BinFn::Slice,
+        BinFn::StartsWith,
+        BinFn::EndsWith,
+        BinFn::Lower,
+        BinFn::Upper,
+        BinFn::Like,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BinFn::Len => "len",
+            BinFn::Concat => "concat",
+            BinFn::Slice => "slice",
+            BinFn::StartsWith => "starts_with",
+            BinFn::EndsWith => "ends_with",
+            BinFn::Lower => "lower",
+            BinFn::Upper => "upper",
+            BinFn::Like => "like",
+        }
+    }
+
+    pub fn arity(self) -> usize {
+        match self {
+            BinFn::Len | BinFn::Lower | BinFn::Upper => 1,
+            BinFn::Concat | BinFn::StartsWith | BinFn::EndsWith | BinFn::Like => 2,
+            BinFn::Slice => 3,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<BinFn> {
+        BinFn::ALL.iter().copied().find(|f| f.name() == name)
+    }
+}
+
+impl TimeFn {
+    pub const ALL: &'static [TimeFn] = &[
+        TimeFn::Year,
+        TimeFn::Month,
+        TimeFn::Day,
+        TimeFn::Hour,
+        TimeFn::Minute,
+        TimeFn::Second,
+        TimeFn::TruncYear,
+        TimeFn::TruncMonth,
+        TimeFn::TruncDay,
+        TimeFn::TruncHour,
+        TimeFn::AddMonths,
+        TimeFn::ToDate,
+        TimeFn::ToTimestamp,
+        TimeFn::ToDuration,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TimeFn::Year => "year",
+            TimeFn::Month => "month",
+            TimeFn::Day => "day",
+            TimeFn::Hour => "hour",
+            TimeFn::Minute => "minute",
+            TimeFn::Second => "second",
+            TimeFn::TruncYear => "trunc_year",
+            TimeFn::TruncMonth => "trunc_month",
+            TimeFn::TruncDay => "trunc_day",
+            TimeFn::TruncHour => "trunc_hour",
+            TimeFn::AddMonths => "add_months",
+            TimeFn::ToDate => "date",
+            TimeFn::ToTimestamp => "timestamp",
+            TimeFn::ToDuration => "duration",
+        }
+    }
+
+    pub fn arity(self) -> usize {
+        match self {
+            TimeFn::AddMonths => 2,
+            _ => 1,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<TimeFn> {
+        TimeFn::ALL.iter().copied().find(|f| f.name() == name)
+    }
+}
+
+impl BitFn {
+    pub const ALL: &'static [BitFn] = &[BitFn::Rank, BitFn::Select, BitFn::Rows, BitFn::Mask];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BitFn::Rank => "rank",
+            BitFn::Select => "select",
+            BitFn::Rows => "rows",
+            BitFn::Mask => "mask",
+        }
+    }
+
+    pub fn arity(self) -> usize {
+        match self {
+            BitFn::Rows => 1,
+            _ => 2,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<BitFn> {
+        BitFn::ALL.iter().copied().find(|f| f.name() == name)
+    }
+}
+
+impl AggOp {
+    pub const ALL: &'static [AggOp] = &[
+        AggOp::Count,
+        AggOp::Sum,
+        AggOp::Min,
+        AggOp::Max,
+        AggOp::Mean,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AggOp::Count => "count",
+            AggOp::Sum => "sum",
+            AggOp::Min => "min",
+            AggOp::Max => "max",
+            AggOp::Mean => "mean",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<AggOp> {
+        AggOp::ALL.iter().copied().find(|op| op.name() == name)
+    }
+}
+
+// A VM evaluates an Expr in a, interruptable way. Its state is plain data,
+// so a Vm stopped between opcodes can be serialized and carried on later.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 pub struct Vm {
     ops: Vec<Opcode>,
     stack: Vec<Frame>,
+    overflow: Overflow,
+    float_mode: FloatMode,
+    // Not saved with the Vm, since kernels are code: whoever carries on
+    // with a deserialized Vm gives it the registry again.
+    #[serde(skip)]
+    udfs: Udfs,
+    fault: Option<Fault>,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 pub struct Frame {
     ctx: Vec<Tab>,
     scalar_bit_regs: Vec<u64>,
@@ -169,5 +960,9 @@ pub struct Frame {
     bin_regs: Vec<u64>,
     //flo_regs: Vec<f64>,
     int_regs: Vec<i64>,
+    vals: Vec<Vals>, // Operand stack
     pc: usize,
 }
+
+#[cfg(test)]
+mod test;
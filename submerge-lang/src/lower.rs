@@ -0,0 +1,209 @@
+// Lowering linearizes an Expr into the Opcodes the Vm runs, operands first,
+// in the order of Expr::children(). A record or table becomes a Zip of its
+// fields, a filter's predicate its own opcodes, and a quote its holes'
+// operands and then a Quote of its template.
+//
+// There are no opcodes for variables or functions, so a let is inlined into
+// its body, as is a lambda applied where it's written. An unbound name
+// applied to arguments is a call of a user-defined function (see udf.rs),
+// and anything else to do with functions is an error, as are if (which has no opcode yet), pass
+// and a splice outside a quote. Inlining copies a let's value into each use,
+// so it's only for the small Exprs a stage builds; a value whose paths an
+// inner binding would capture is an error rather than a renaming.
+//
+// Compiling goes on to assemble the opcodes into a packed Program (see
+// insn.rs), for storing or sending them compactly; Vm::from_program runs
+// one. A thunk ships its Expr, not a Program: each replica checks the Expr
+// before it runs (see stage.rs in eval), and lowers it itself.
+
+use crate::{quote::fill, Call, Expr, Opcode, Path, Program, Word};
+use submerge_base::{err, Result};
+
+// The one-word paths in an Expr, which might be variables.
+fn words(e: &Expr, out: &mut Vec<Word>) {
+    if let Expr::Path(Path(p)) = e {
+        if let [w] = p.as_slice() {
+            out.push(w.clone());
+        }
+    }
+    for c in e.children() {
+        words(c, out);
+    }
+}
+
+// e with v in place of the variable w. Only a quote's holes are
+// substituted, since its body is evaluated later, somewhere else.
+fn subst(e: &Expr, w: &Word, v: &Expr, free: &[Word]) -> Result<Expr> {
+    let capture = |x: &Word| match free.contains(x) {
+        true => Err(err(format!("inlining {} would capture {}", w, x))),
+        false => Ok(()),
+    };
+    Ok(match e {
+        Expr::Path(Path(p)) if p.as_slice() == [w.clone()] => v.clone(),
+        Expr::Let(x, val, body) => {
+            let val = subst(val, w, v, free)?;
+            let body = match x == w {
+                true => (**body).clone(),
+                false => {
+                    capture(x)?;
+                    subst(body, w, v, free)?
+                }
+            };
+            Expr::Let(x.clone(), Box::new(val), Box::new(body))
+        }
+        Expr::Lam(xs, _) if xs.contains(w) => e.clone(),
+        Expr::Lam(xs, body) => {
+            xs.iter().try_for_each(capture)?;
+            Expr::Lam(xs.clone(), Box::new(subst(body, w, v, free)?))
+        }
+        Expr::Quote(body) => {
+            let mut res = Ok(());
+            let body = fill(body, 0, &mut |x| match subst(x, w, v, free) {
+                Ok(x) => Expr::splice(x),
+                Err(e) => {
+                    res = Err(e);
+                    Expr::splice(x.clone())
+                }
+            });
+            res?;
+            Expr::quote(body)
+        }
+        _ => {
+            let mut res = Ok(());
+            let out = e.map_children(|c| match subst(c, w, v, free) {
+                Ok(c) => c,
+                Err(e) => {
+                    res = Err(e);
+                    c.clone()
+                }
+            });
+            res?;
+            out
+        }
+    })
+}
+
+fn inline(body: &Expr, w: &Word, v: &Expr) -> Result<Expr> {
+    let mut free = Vec::new();
+    words(v, &mut free);
+    subst(body, w, v, &free)
+}
+
+impl Expr {
+    pub fn ops(&self) -> Result<Vec<Opcode>> {
+        let mut ops = Vec::new();
+        lower(self, &mut ops)?;
+        Ok(ops)
+    }
+
+    pub fn compile(&self) -> Result<Program> {
+        Program::assemble(&self.ops()?)
+    }
+}
+
+fn lower(e: &Expr, ops: &mut Vec<Opcode>) -> Result<()> {
+    let op = match e {
+        Expr::Lit(v) => Opcode::Literal(v.clone()),
+        Expr::Path(p) => Opcode::Path(p.clone()),
+        Expr::Reify => Opcode::Reify,
+        Expr::Let(w, val, body) => return lower(&inline(body, w, val)?, ops),
+        Expr::App(f, args) => match &**f {
+            Expr::Lam(params, body) if params.len() == args.len() => {
+                // As lets, one inside another, which is only the same as
+                // binding them all at once if no argument names a param.
+                let mut free = Vec::new();
+                args.iter().for_each(|a| words(a, &mut free));
+                if let Some(w) = params.iter().find(|w| free.contains(w)) {
+                    return Err(err(format!("inlining {} would capture {}", f, w)));
+                }
+                let body = params
+                    .iter()
+                    .zip(args)
+                    .rev()
+                    .fold((**body).clone(), |b, (w, a)| {
+                        Expr::Let(w.clone(), Box::new(a.clone()), Box::new(b))
+                    });
+                return lower(&body, ops);
+            }
+            Expr::Path(Path(p)) if p.len() == 1 => {
+                for a in args {
+                    lower(a, ops)?;
+                }
+                Opcode::Call(Call {
+                    name: p[0].clone(),
+                    arity: args.len(),
+                })
+            }
+            _ => return Err(err(format!("no opcodes for applying {}", f))),
+        },
+        Expr::Pass | Expr::Lam(..) | Expr::If(..) | Expr::Splice(_) => {
+            return Err(err(format!("no opcodes for {}", e)))
+        }
+        Expr::BinOp(op, a, b) => {
+            lower(a, ops)?;
+            lower(b, ops)?;
+            Opcode::PrimBinOp(*op)
+        }
+        Expr::UnOp(op, a) => {
+            lower(a, ops)?;
+            Opcode::PrimUnOp(*op)
+        }
+        Expr::BinFn(f, args) => {
+            args.iter().try_for_each(|a| lower(a, ops))?;
+            Opcode::BinFn(*f)
+        }
+        Expr::TimeFn(f, args) => {
+            args.iter().try_for_each(|a| lower(a, ops))?;
+            Opcode::TimeFn(*f)
+        }
+        Expr::BitFn(f, args) => {
+            args.iter().try_for_each(|a| lower(a, ops))?;
+            Opcode::BitFn(*f)
+        }
+        Expr::Rec(fields) | Expr::Tab(fields) => {
+            fields.iter().try_for_each(|(_, f)| lower(f, ops))?;
+            Opcode::Zip(fields.iter().map(|(w, _)| w.clone()).collect())
+        }
+        Expr::Query(a, p) => {
+            lower(a, ops)?;
+            Opcode::Query(p.clone())
+        }
+        Expr::Merge(a, b) => {
+            lower(a, ops)?;
+            lower(b, ops)?;
+            Opcode::Merge
+        }
+        Expr::Convert(a, u) => {
+            lower(a, ops)?;
+            Opcode::Convert(*u)
+        }
+        Expr::Cast(a, c) => {
+            lower(a, ops)?;
+            Opcode::Cast(c.clone())
+        }
+        Expr::Group(a, g) => {
+            lower(a, ops)?;
+            Opcode::Group(g.clone())
+        }
+        Expr::Join(a, b, j) => {
+            lower(a, ops)?;
+            lower(b, ops)?;
+            Opcode::Join(j.clone())
+        }
+        Expr::Sort(a, keys) => {
+            lower(a, ops)?;
+            Opcode::Sort(keys.clone())
+        }
+        Expr::Filter(a, pred) => {
+            lower(a, ops)?;
+            Opcode::Filter(pred.ops()?)
+        }
+        Expr::Quote(body) => {
+            let (tpl, holes) = body.template();
+            holes.iter().try_for_each(|h| lower(h, ops))?;
+            Opcode::Quote(tpl)
+        }
+    };
+    ops.push(op);
+    Ok(())
+}
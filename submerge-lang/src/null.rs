@@ -0,0 +1,230 @@
+// Nulls. A column with missing values wraps them in an Opt, whose set bits
+// are the null rows. The rows under a null hold a placeholder of the right
+// shape (a zero, an empty bin and so on), so the wrapped column keeps its
+// length and the kernels can run over it as it is. An Opt goes under any Rich
+// wrapper, so that a column's label, form and unit stay outermost, and a
+// null record is a record of nulls.
+//
+// Nulls propagate: an elementwise op gives a null row wherever any operand
+// is null, and a null scalar makes every row null. The exceptions follow
+// SQL. Or and Max of bits are true if either side is true, and And and Min
+// of bits are false if either side is false, whether or not the other is
+// null.
+// Aggregates and bit counts skip null rows, so a count counts values, and an
+// aggregate of nothing but nulls is null. Filters drop rows whose mask is
+// null, joins never match a null key, group-by puts all null keys in one
+// group, and sorts put nulls last, or first when descending.
+//
+// coldb stores which rows have values as a bit track: a Bitmap256 per chunk
+// of 256 rows, with a bit set for each row that isn't null. validity() and
+// with_validity() convert to and from that.
+
+use crate::{kernel, Bin, Col, Overflow, PrimBinOp, PrimUnOp, Val, Vals};
+use submerge_base::{err, Bitmap256, Result};
+
+const CHUNK_ROWS: usize = 256;
+
+// The null rows of a column, under any Rich wrapper.
+pub(crate) fn nulls(v: &Vals) -> Option<&bs::Bs> {
+    match v {
+        Vals::Rich(col) => nulls(&col.vals),
+        Vals::Opt(ns, _) => Some(ns),
+        _ => None,
+    }
+}
+
+// Whether a row is null, with one-row columns standing for every row.
+fn is_null(v: &Vals, row: usize) -> bool {
+    let row = if v.len() == 1 { 0 } else { row };
+    nulls(v).is_some_and(|ns| ns.contains(row))
+}
+
+// A column without its null mask, keeping any Rich wrapper. Null rows are
+// left holding their placeholders.
+pub(crate) fn strip(v: &Vals) -> Vals {
+    match v {
+        Vals::Rich(col) => Vals::Rich(Box::new(Col {
+            name: col.name.clone(),
+            form: col.form,
+            unit: col.unit,
+            vals: strip(&col.vals),
+        })),
+        Vals::Opt(_, vals) => (**vals).clone(),
+        _ => v.clone(),
+    }
+}
+
+// Make the given rows of a column null, as well as any that already are.
+pub(crate) fn with_nulls(v: Vals, rows: &[usize]) -> Vals {
+    if rows.is_empty() {
+        return v;
+    }
+    match v {
+        Vals::Rich(mut col) => {
+            col.vals = with_nulls(col.vals, rows);
+            Vals::Rich(col)
+        }
+        Vals::Opt(ns, vals) => {
+            let all = Vec::from(&ns).into_iter().chain(rows.iter().copied());
+            Vals::Opt(all.collect(), vals)
+        }
+        Vals::All(fields) => Vals::All(fields.into_iter().map(|f| with_nulls(f, rows)).collect()),
+        v => Vals::Opt(rows.iter().copied().collect(), Box::new(v)),
+    }
+}
+
+// A placeholder of the same shape as the column's rows, to stand under a
+// null.
+pub(crate) fn blank(v: &Vals) -> Result<Val> {
+    Ok(match v {
+        Vals::I64s(_) => Val::Int(0),
+        Vals::F64s(_) => Val::Flo(0.0.into()),
+        Vals::Bits(..) => Val::Bit(false),
+        Vals::Bins(_) => Val::Bin(Bin::Mem(Vec::new())),
+        Vals::Rich(col) => blank(&col.vals)?,
+        Vals::Opt(_, vals) => blank(vals)?,
+        Vals::All(fields) => Val::All(fields.iter().map(blank).collect::<Result<_>>()?),
+        Vals::Any(_, vars) => match vars.first() {
+            Some(v) => Val::Any(0, Box::new(blank(v)?)),
+            None => return Err(err("an any with no variants has no placeholder")),
+        },
+    })
+}
+
+// Take apart the arguments of an elementwise op: the rows where none of
+// them is null, and each argument's values at those rows, without nulls.
+// One-row arguments are kept whole, to broadcast as before.
+pub(crate) fn split(args: &[&Vals]) -> Result<(Vec<Vals>, Vec<usize>, usize)> {
+    let n = args.iter().map(|a| a.len()).max().unwrap_or(0);
+    if let Some(a) = args.iter().find(|a| a.len() != n && a.len() != 1) {
+        return Err(err(format!("column length mismatch: {} vs {}", a.len(), n)));
+    }
+    let valid: Vec<usize> = (0..n)
+        .filter(|row| !args.iter().any(|a| is_null(a, *row)))
+        .collect();
+    let mut out = Vec::with_capacity(args.len());
+    for a in args {
+        let a = strip(a);
+        out.push(if a.len() == 1 && n != 1 {
+            a
+        } else {
+            a.take(&valid)?
+        });
+    }
+    Ok((out, valid, n))
+}
+
+// The inverse of split(): spread the rows computed for the valid rows back
+// out to n rows, with nulls in between.
+pub(crate) fn scatter(out: Vals, valid: &[usize], n: usize) -> Result<Vals> {
+    if out.len() != valid.len() {
+        return Err(err(format!(
+            "{} rows computed for {} non-null rows",
+            out.len(),
+            valid.len()
+        )));
+    }
+    if valid.len() == n {
+        return Ok(out);
+    }
+    // Every null row takes the placeholder appended after the real rows.
+    let mut padded = out;
+    padded.push(blank(&padded)?)?;
+    let mut rows = vec![valid.len(); n];
+    for (i, row) in valid.iter().enumerate() {
+        rows[*row] = i;
+    }
+    let nulls: Vec<usize> = (0..n).filter(|row| rows[*row] == valid.len()).collect();
+    Ok(with_nulls(padded.take(&rows)?, &nulls))
+}
+
+// Run an elementwise op over the rows where no argument is null.
+pub(crate) fn lift(args: &[&Vals], f: impl FnOnce(&[Vals]) -> Result<Vals>) -> Result<Vals> {
+    let (args, valid, n) = split(args)?;
+    scatter(f(&args)?, &valid, n)
+}
+
+fn is_bits(v: &Vals) -> bool {
+    match v {
+        Vals::Rich(col) => is_bits(&col.vals),
+        Vals::Opt(_, vals) => is_bits(vals),
+        Vals::Bits(..) => true,
+        _ => false,
+    }
+}
+
+pub(crate) fn binop(op: PrimBinOp, a: &Vals, b: &Vals, overflow: Overflow) -> Result<Vals> {
+    let logic = matches!(
+        op,
+        PrimBinOp::And | PrimBinOp::Or | PrimBinOp::Max | PrimBinOp::Min
+    );
+    if logic && is_bits(a) && is_bits(b) {
+        return kleene(op, a, b, overflow);
+    }
+    lift(&[a, b], |x| kernel::binop(op, &x[0], &x[1], overflow))
+}
+
+// A known operand of Or or Max decides the result if it's true, and one of
+// And or Min decides it if it's false; otherwise a null operand makes a null.
+fn kleene(op: PrimBinOp, a: &Vals, b: &Vals, overflow: Overflow) -> Result<Vals> {
+    let out = kernel::binop(op, &strip(a), &strip(b), overflow)?;
+    let decides = !matches!(op, PrimBinOp::And | PrimBinOp::Min);
+    let known = |v: &Vals, row: usize| {
+        let row = if v.len() == 1 { 0 } else { row };
+        match v.get(row) {
+            Some(Val::Bit(x)) => Some(x),
+            _ => None,
+        }
+    };
+    let nulls: Vec<usize> = (0..out.len())
+        .filter(|row| {
+            let (x, y) = (known(a, *row), known(b, *row));
+            (x.is_none() || y.is_none()) && x != Some(decides) && y != Some(decides)
+        })
+        .collect();
+    Ok(with_nulls(out, &nulls))
+}
+
+// Bit counts reduce a column to one row, so they skip nulls as aggregates
+// do; the other unops are elementwise.
+pub(crate) fn unop(op: PrimUnOp, a: &Vals) -> Result<Vals> {
+    if matches!(op, PrimUnOp::BitCount | PrimUnOp::BitParity) {
+        let (args, _, _) = split(&[a])?;
+        return kernel::unop(op, &args[0]);
+    }
+    lift(&[a], |x| kernel::unop(op, &x[0]))
+}
+
+impl Vals {
+    // Which rows have values, as coldb stores it: a bitmap per chunk of 256
+    // rows, with a bit set for each row that isn't null.
+    pub fn validity(&self) -> Vec<Bitmap256> {
+        let n = self.len();
+        let mut out = Vec::with_capacity(n.div_ceil(CHUNK_ROWS));
+        for start in (0..n).step_by(CHUNK_ROWS) {
+            let mut bm = Bitmap256::new();
+            for row in start..n.min(start + CHUNK_ROWS) {
+                bm.set((row - start) as u8, !is_null(self, row));
+            }
+            out.push(bm);
+        }
+        out
+    }
+
+    // The inverse of validity(): this column with every row whose bit is
+    // clear made null, and every other row not.
+    pub fn with_validity(&self, chunks: &[Bitmap256]) -> Result<Vals> {
+        let n = self.len();
+        if chunks.len() != n.div_ceil(CHUNK_ROWS) {
+            return Err(err(format!(
+                "{} validity chunks for {} rows",
+                chunks.len(),
+                n
+            )));
+        }
+        let nulls: Vec<usize> = (0..n)
+            .filter(|row| !chunks[row / CHUNK_ROWS].get((row % CHUNK_ROWS) as u8))
+            .collect();
+        Ok(with_nulls(strip(self), &nulls))
+    }
+}
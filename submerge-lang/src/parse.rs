@@ -0,0 +1,1114 @@
+// A hand-written recursive-descent parser for the textual surface syntax.
+//
+//   expr   := 'let' word '=' expr 'in' expr
+//           | 'fn' '(' [word {',' word}] ')' expr
+//           | 'if' expr 'then' expr 'else' expr
+//           | binary
+//   binary := unary {infix unary}           (precedence climbing, see below)
+//   unary  := '-' unary | '!' unary | postfix
+//   postfix:= atom {'(' [expr {',' expr}] ')'}
+//   atom   := int | float | string | 'true' | 'false' | 'nan' | 'inf'
+//           | '[' lit {',' lit} ']' | ty '[' ']'
+//           | path | '(' expr ')' | '{' fields '}' | 'tab' '{' fields '}'
+//           | 'pass' | 'reify' | 'query' '(' expr ',' path ')'
+//           | 'convert' '(' expr ',' string ')'
+//           | 'cast' '(' expr ',' target [',' mode] ')'
+//           | 'merge' '(' expr ',' expr ')' | 'filter' '(' expr ',' expr ')'
+//           | 'quote' '(' expr ')' | 'splice' '(' expr ')'
+//           | opname '(' args ')'
+//           | 'group' '(' expr ',' '[' [path {',' path}] ']' ',' '{' aggs '}' ')'
+//           | 'sort' '(' expr ',' '[' [key {',' key}] ']' ')'
+//           | ('join' | 'left_join') '(' expr ',' expr ',' '[' [on {',' on}] ']' ')'
+//   path   := word {'.' word} | '``'
+//   word   := name | '`' name '`'
+//   fields := [word ':' expr {',' word ':' expr}]
+//   aggs   := [word ':' aggname '(' path ')' {',' word ':' aggname '(' path ')'}]
+//   on     := path '=' path
+//   key    := path ['asc' | 'desc']
+//   target := 'bit' | 'int' | 'flo' | 'bin' | 'timestamp' | 'date'
+//           | 'duration' | 'dec' '(' int ',' int ')'
+//   mode   := 'check' | 'saturate' | 'lossy'
+//
+// Comments run from '//' to end of line. Primitive ops without an infix
+// symbol (and, optionally, those with one) are written as calls by name, eg.
+// 'min(a, b)' or 'sqrt(x)', as are bin and calendar functions like 'len(s)'
+// or 'year(t)', and bit functions like 'rank(m, i)'; those names are
+// reserved when followed by '('. A splice is only valid inside a quote.
+//
+// A name in backquotes is a word even if it's a keyword, and a call of it is
+// an application even if it's an op or function name, so that any Expr can
+// be printed: eg. '`filter`.x' is a path, and '`sqrt`(x)' applies whatever
+// 'sqrt' is bound to. Empty backquotes are the empty path.
+//
+// The parser doesn't put spans in the Expr itself, since Exprs are replicated
+// and hashed. Instead it returns a SpanTree alongside, shaped like the Expr:
+// each node's kids line up with Expr::children().
+
+use crate::{
+    AggOp, Aggregate, Bin, BinFn, BitFn, Cast, CastMode, Expr, Form, Grouping, Join, JoinKind,
+    Major, Path, PrimBinOp, PrimUnOp, SortKey, TimeFn, Unit, Vals, Word,
+};
+use ordered_float::OrderedFloat;
+use std::str::FromStr;
+
+// A half-open range of byte offsets into the source text.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Span {
+    pub lo: usize,
+    pub hi: usize,
+}
+
+impl Span {
+    pub fn new(lo: usize, hi: usize) -> Span {
+        Span { lo, hi }
+    }
+    pub fn join(self, other: Span) -> Span {
+        Span::new(self.lo.min(other.lo), self.hi.max(other.hi))
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct SpanTree {
+    pub span: Span,
+    pub kids: Vec<SpanTree>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Parsed {
+    pub expr: Expr,
+    pub spans: SpanTree,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseError {
+    pub span: Span,
+    pub msg: String,
+    // Whether the input ended before the expression did, so that more input
+    // might make it parse: an open bracket or string, a trailing infix
+    // operator, a let without its body and so on.
+    pub incomplete: bool,
+}
+
+// What a REPL makes of the input so far: a whole expression, the start of
+// one that needs more lines, or a syntax error that no more input can fix.
+// Input with nothing but whitespace and comments is Incomplete.
+#[derive(Clone, Debug)]
+pub enum Partial {
+    Complete(Parsed),
+    Incomplete,
+    Invalid(ParseError),
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "parse error at {}..{}: {}",
+            self.span.lo, self.span.hi, self.msg
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub fn parse(src: &str) -> Result<Parsed, ParseError> {
+    let parsed = lex(src).and_then(|toks| {
+        let mut p = Parser {
+            toks,
+            pos: 0,
+            quotes: 0,
+            depth: 0,
+        };
+        let (expr, spans) = p.expr()?;
+        p.expect_eof()?;
+        Ok(Parsed { expr, spans })
+    });
+    // Every token but Eof starts before the end of the input, so an error
+    // there is one that the parser ran out of input to avoid.
+    parsed.map_err(|mut e| {
+        e.incomplete |= e.span.lo >= src.len();
+        e
+    })
+}
+
+pub fn parse_partial(src: &str) -> Partial {
+    match parse(src) {
+        Ok(parsed) => Partial::Complete(parsed),
+        Err(e) if e.incomplete => Partial::Incomplete,
+        Err(e) => Partial::Invalid(e),
+    }
+}
+
+impl FromStr for Expr {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Expr, ParseError> {
+        parse(s).map(|p| p.expr)
+    }
+}
+
+const KEYWORDS: &[&str] = &[
+    "let",
+    "in",
+    "fn",
+    "if",
+    "then",
+    "else",
+    "true",
+    "false",
+    "nan",
+    "inf",
+    "tab",
+    "pass",
+    "reify",
+    "query",
+    "merge",
+    "convert",
+    "cast",
+    "group",
+    "join",
+    "left_join",
+    "sort",
+    "filter",
+    "quote",
+    "splice",
+];
+
+// Type names that prefix an empty list literal, eg. 'i64[]'.
+const LIST_TYS: &[&str] = &["i64", "f64", "bit", "bin"];
+
+// Infix operators, longest first so lexing is greedy.
+const SYMBOLS: &[&str] = &[
+    "<=>", ">>>", "**", "==", "!=", "<=", ">=", "<<", ">>", "=", "<", ">", "+", "-", "*", "/", "%",
+    "&", "|", "^", "!", "(", ")", "[", "]", "{", "}", ",", ".", ":",
+];
+
+// Binding power of infix operators; higher binds tighter. Pow is the only
+// right-associative operator.
+pub(crate) fn precedence(op: PrimBinOp) -> u8 {
+    match op {
+        PrimBinOp::Or | PrimBinOp::Xor => 1,
+        PrimBinOp::And => 2,
+        PrimBinOp::Eq
+        | PrimBinOp::Ne
+        | PrimBinOp::Lt
+        | PrimBinOp::Le
+        | PrimBinOp::Gt
+        | PrimBinOp::Ge
+        | PrimBinOp::Cmp => 3,
+        PrimBinOp::Shl | PrimBinOp::Shr | PrimBinOp::Asr => 4,
+        PrimBinOp::Add | PrimBinOp::Sub => 5,
+        PrimBinOp::Mul | PrimBinOp::Div | PrimBinOp::Mod => 6,
+        PrimBinOp::Pow => 7,
+        PrimBinOp::Min | PrimBinOp::Max | PrimBinOp::Rol | PrimBinOp::Ror => 0,
+    }
+}
+
+pub(crate) fn is_keyword(s: &str) -> bool {
+    KEYWORDS.contains(&s)
+}
+
+// Whether a call of a word parses as something other than an application.
+pub(crate) fn is_builtin(s: &str) -> bool {
+    is_keyword(s)
+        || LIST_TYS.contains(&s)
+        || PrimUnOp::from_name(s).is_some()
+        || PrimBinOp::from_name(s).is_some()
+        || BinFn::from_name(s).is_some()
+        || BitFn::from_name(s).is_some()
+        || TimeFn::from_name(s).is_some()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Tok {
+    Int(String),
+    Float(f64),
+    Str(Vec<u8>),
+    Word(String),
+    // A word in backquotes, which is never a keyword.
+    Quoted(String),
+    Sym(&'static str),
+    Eof,
+}
+
+fn err<T>(span: Span, msg: impl Into<String>) -> Result<T, ParseError> {
+    Err(ParseError {
+        span,
+        msg: msg.into(),
+        incomplete: false,
+    })
+}
+
+fn unterminated<T>(lo: usize, src: &str, what: &str) -> Result<T, ParseError> {
+    Err(ParseError {
+        span: Span::new(lo, src.len()),
+        msg: format!("unterminated {}", what),
+        incomplete: true,
+    })
+}
+
+fn lex(src: &str) -> Result<Vec<(Tok, Span)>, ParseError> {
+    let bytes = src.as_bytes();
+    let mut toks = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        let lo = i;
+        if c.is_ascii_whitespace() {
+            i += 1;
+        } else if src[i..].starts_with("//") {
+            while i < bytes.len() && bytes[i] != b'\n' {
+                i += 1;
+            }
+        } else if c.is_ascii_digit() {
+            let tok = lex_number(src, &mut i)?;
+            toks.push((tok, Span::new(lo, i)));
+        } else if c == b'"' {
+            let tok = lex_string(src, &mut i)?;
+            toks.push((tok, Span::new(lo, i)));
+        } else if c == b'`' {
+            let Some(len) = src[i + 1..].find('`') else {
+                return unterminated(lo, src, "word");
+            };
+            i += len + 2;
+            toks.push((
+                Tok::Quoted(src[lo + 1..i - 1].to_string()),
+                Span::new(lo, i),
+            ));
+        } else if let Some(sym) = SYMBOLS.iter().find(|s| src[i..].starts_with(**s)) {
+            i += sym.len();
+            toks.push((Tok::Sym(sym), Span::new(lo, i)));
+        } else {
+            let ch = src[i..].chars().next().unwrap();
+            if !(ch.is_alphabetic() || ch == '_') {
+                return err(Span::new(lo, lo + ch.len_utf8()), "unexpected character");
+            }
+            let len = src[i..]
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(src.len() - i);
+            i += len;
+            toks.push((Tok::Word(src[lo..i].to_string()), Span::new(lo, i)));
+        }
+    }
+    toks.push((Tok::Eof, Span::new(src.len(), src.len())));
+    Ok(toks)
+}
+
+fn lex_number(src: &str, i: &mut usize) -> Result<Tok, ParseError> {
+    let bytes = src.as_bytes();
+    let lo = *i;
+    let digits = |i: &mut usize| {
+        while *i < bytes.len() && bytes[*i].is_ascii_digit() {
+            *i += 1;
+        }
+    };
+    digits(i);
+    let mut float = false;
+    if *i + 1 < bytes.len() && bytes[*i] == b'.' && bytes[*i + 1].is_ascii_digit() {
+        float = true;
+        *i += 1;
+        digits(i);
+    }
+    if *i < bytes.len() && (bytes[*i] == b'e' || bytes[*i] == b'E') {
+        let mut j = *i + 1;
+        if j < bytes.len() && (bytes[j] == b'+' || bytes[j] == b'-') {
+            j += 1;
+        }
+        if j < bytes.len() && bytes[j].is_ascii_digit() {
+            float = true;
+            *i = j;
+            digits(i);
+        }
+    }
+    let text = &src[lo..*i];
+    if float {
+        match text.parse::<f64>() {
+            Ok(f) => Ok(Tok::Float(f)),
+            Err(_) => err(Span::new(lo, *i), "malformed float"),
+        }
+    } else {
+        Ok(Tok::Int(text.to_string()))
+    }
+}
+
+fn lex_string(src: &str, i: &mut usize) -> Result<Tok, ParseError> {
+    let bytes = src.as_bytes();
+    let lo = *i;
+    let mut out = Vec::new();
+    *i += 1;
+    loop {
+        if *i >= bytes.len() {
+            return unterminated(lo, src, "string");
+        }
+        match bytes[*i] {
+            b'"' => {
+                *i += 1;
+                return Ok(Tok::Str(out));
+            }
+            b'\\' => {
+                let esc = Span::new(*i, (*i + 2).min(bytes.len()));
+                // An escape cut off by the end of the input is unfinished,
+                // not wrong.
+                let width = if bytes.get(*i + 1) == Some(&b'x') {
+                    4
+                } else {
+                    2
+                };
+                if *i + width > bytes.len() {
+                    return unterminated(lo, src, "string");
+                }
+                match bytes.get(*i + 1) {
+                    Some(b'"') => out.push(b'"'),
+                    Some(b'\\') => out.push(b'\\'),
+                    Some(b'n') => out.push(b'\n'),
+                    Some(b't') => out.push(b'\t'),
+                    Some(b'x') => {
+                        let hex = src.get(*i + 2..*i + 4);
+                        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                            Some(b) => out.push(b),
+                            None => return err(Span::new(*i, *i + 2), "bad \\x escape"),
+                        }
+                        *i += 2;
+                    }
+                    _ => return err(esc, "unknown escape"),
+                }
+                *i += 2;
+            }
+            b => {
+                out.push(b);
+                *i += 1;
+            }
+        }
+    }
+}
+
+struct Parser {
+    toks: Vec<(Tok, Span)>,
+    pos: usize,
+    // How many quotes enclose the current position, less splices.
+    quotes: usize,
+    // How deeply the expressions around the current position nest.
+    depth: usize,
+}
+
+// The deepest expressions may nest. The parser recurses on nesting, as does
+// everything that walks the Expr it builds, so a few thousand open parens
+// would otherwise overflow the stack.
+const MAX_DEPTH: usize = 128;
+
+type Node = (Expr, SpanTree);
+type Fields = (Vec<(Word, Expr)>, Vec<SpanTree>);
+
+fn node(expr: Expr, span: Span, kids: Vec<SpanTree>) -> Node {
+    (expr, SpanTree { span, kids })
+}
+
+impl Parser {
+    fn peek(&self) -> &Tok {
+        &self.toks[self.pos].0
+    }
+    fn peek_at(&self, n: usize) -> &Tok {
+        &self.toks[(self.pos + n).min(self.toks.len() - 1)].0
+    }
+    fn span(&self) -> Span {
+        self.toks[self.pos].1
+    }
+    fn prev_span(&self) -> Span {
+        self.toks[self.pos.saturating_sub(1)].1
+    }
+    fn bump(&mut self) -> (Tok, Span) {
+        let t = self.toks[self.pos].clone();
+        if self.pos + 1 < self.toks.len() {
+            self.pos += 1;
+        }
+        t
+    }
+    fn is_sym(&self, sym: &str) -> bool {
+        matches!(self.peek(), Tok::Sym(s) if *s == sym)
+    }
+    fn is_kw(&self, kw: &str) -> bool {
+        matches!(self.peek(), Tok::Word(w) if w == kw)
+    }
+    fn eat_sym(&mut self, sym: &str) -> bool {
+        let hit = self.is_sym(sym);
+        if hit {
+            self.bump();
+        }
+        hit
+    }
+    fn expect_sym(&mut self, sym: &str) -> Result<Span, ParseError> {
+        if self.is_sym(sym) {
+            Ok(self.bump().1)
+        } else {
+            err(self.span(), format!("expected '{}'", sym))
+        }
+    }
+    fn expect_kw(&mut self, kw: &str) -> Result<(), ParseError> {
+        if self.is_kw(kw) {
+            self.bump();
+            Ok(())
+        } else {
+            err(self.span(), format!("expected '{}'", kw))
+        }
+    }
+    fn expect_eof(&self) -> Result<(), ParseError> {
+        match self.peek() {
+            Tok::Eof => Ok(()),
+            _ => err(self.span(), "expected end of input"),
+        }
+    }
+
+    fn word(&mut self) -> Result<Word, ParseError> {
+        match self.bump() {
+            (Tok::Word(w), span) if is_keyword(&w) => err(span, format!("'{}' is a keyword", w)),
+            (Tok::Word(w) | Tok::Quoted(w), span) => match Word::new(&w) {
+                Some(w) => Ok(w),
+                None => err(span, "malformed word"),
+            },
+            (_, span) => err(span, "expected a word"),
+        }
+    }
+
+    fn path(&mut self) -> Result<(Path, Span), ParseError> {
+        let lo = self.span();
+        let mut words = vec![self.word()?];
+        while self.eat_sym(".") {
+            words.push(self.word()?);
+        }
+        Ok((Path(words), lo.join(self.prev_span())))
+    }
+
+    // Run a nested part of the parse, unless it's nested too deeply.
+    fn nested<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        if self.depth >= MAX_DEPTH {
+            return err(self.span(), "expression nested too deeply");
+        }
+        self.depth += 1;
+        let res = f(self);
+        self.depth -= 1;
+        res
+    }
+
+    // Check that an operator chain, which the parser folds in a loop rather
+    // than recursing, doesn't nest its Expr too deeply either.
+    fn fold(&self, links: &mut usize) -> Result<(), ParseError> {
+        *links += 1;
+        if self.depth + *links > MAX_DEPTH {
+            return err(self.span(), "expression nested too deeply");
+        }
+        Ok(())
+    }
+
+    // The forms with keywords are parsed apart, to keep what each level of
+    // nesting takes of the stack small.
+    fn expr(&mut self) -> Result<Node, ParseError> {
+        self.nested(|p| {
+            if p.is_kw("let") {
+                p.let_expr()
+            } else if p.is_kw("fn") {
+                p.fn_expr()
+            } else if p.is_kw("if") {
+                p.if_expr()
+            } else {
+                p.binary(1)
+            }
+        })
+    }
+
+    fn let_expr(&mut self) -> Result<Node, ParseError> {
+        let lo = self.span();
+        self.bump();
+        let name = self.word()?;
+        self.expect_sym("=")?;
+        let (val, vs) = self.expr()?;
+        self.expect_kw("in")?;
+        let (body, bs) = self.expr()?;
+        let span = lo.join(bs.span);
+        Ok(node(Expr::let_(name, val, body), span, vec![vs, bs]))
+    }
+
+    fn fn_expr(&mut self) -> Result<Node, ParseError> {
+        let lo = self.span();
+        self.bump();
+        self.expect_sym("(")?;
+        let mut params = Vec::new();
+        if !self.is_sym(")") {
+            params.push(self.word()?);
+            while self.eat_sym(",") {
+                params.push(self.word()?);
+            }
+        }
+        self.expect_sym(")")?;
+        let (body, bs) = self.expr()?;
+        let span = lo.join(bs.span);
+        Ok(node(Expr::lam(params, body), span, vec![bs]))
+    }
+
+    fn if_expr(&mut self) -> Result<Node, ParseError> {
+        let lo = self.span();
+        self.bump();
+        let (c, cs) = self.expr()?;
+        self.expect_kw("then")?;
+        let (t, ts) = self.expr()?;
+        self.expect_kw("else")?;
+        let (e, es) = self.expr()?;
+        let span = lo.join(es.span);
+        Ok(node(Expr::if_(c, t, e), span, vec![cs, ts, es]))
+    }
+
+    fn infix_op(&self) -> Option<PrimBinOp> {
+        match self.peek() {
+            Tok::Sym(s) => PrimBinOp::ALL
+                .iter()
+                .copied()
+                .find(|op| op.symbol() == Some(*s)),
+            _ => None,
+        }
+    }
+
+    fn binary(&mut self, min_prec: u8) -> Result<Node, ParseError> {
+        let (mut lhs, mut ls) = self.unary()?;
+        let mut links = 0;
+        while let Some(op) = self.infix_op() {
+            let prec = precedence(op);
+            if prec < min_prec {
+                break;
+            }
+            self.fold(&mut links)?;
+            self.bump();
+            let next = if op == PrimBinOp::Pow { prec } else { prec + 1 };
+            let (rhs, rs) = self.nested(|p| p.binary(next))?;
+            let span = ls.span.join(rs.span);
+            (lhs, ls) = node(Expr::binop(op, lhs, rhs), span, vec![ls, rs]);
+        }
+        Ok((lhs, ls))
+    }
+
+    fn unary(&mut self) -> Result<Node, ParseError> {
+        let lo = self.span();
+        if self.is_sym("-") {
+            // The sign folds into numeric literals, so that eg. i64::MIN is
+            // expressible and negative literals print and reparse as-is.
+            if let Some(vals) = self.scalar()? {
+                return Ok(node(Expr::lit(vals), lo.join(self.prev_span()), vec![]));
+            }
+            self.bump();
+            let (a, s) = self.nested(Parser::unary)?;
+            let span = lo.join(s.span);
+            Ok(node(Expr::unop(PrimUnOp::Neg, a), span, vec![s]))
+        } else if self.is_sym("!") {
+            self.bump();
+            let (a, s) = self.nested(Parser::unary)?;
+            let span = lo.join(s.span);
+            Ok(node(Expr::unop(PrimUnOp::Not, a), span, vec![s]))
+        } else {
+            self.postfix()
+        }
+    }
+
+    fn postfix(&mut self) -> Result<Node, ParseError> {
+        let (mut func, mut fs) = self.atom()?;
+        let mut links = 0;
+        while self.is_sym("(") {
+            self.fold(&mut links)?;
+            let (args, mut kids) = self.args()?;
+            let span = fs.span.join(self.prev_span());
+            kids.insert(0, fs);
+            (func, fs) = node(Expr::app(func, args), span, kids);
+        }
+        Ok((func, fs))
+    }
+
+    fn args(&mut self) -> Result<(Vec<Expr>, Vec<SpanTree>), ParseError> {
+        self.expect_sym("(")?;
+        let mut args = Vec::new();
+        let mut spans = Vec::new();
+        if !self.is_sym(")") {
+            loop {
+                let (a, s) = self.expr()?;
+                args.push(a);
+                spans.push(s);
+                if !self.eat_sym(",") {
+                    break;
+                }
+            }
+        }
+        self.expect_sym(")")?;
+        Ok((args, spans))
+    }
+
+    fn fields(&mut self) -> Result<Fields, ParseError> {
+        self.expect_sym("{")?;
+        let mut fields = Vec::new();
+        let mut spans = Vec::new();
+        if !self.is_sym("}") {
+            loop {
+                let name = self.word()?;
+                self.expect_sym(":")?;
+                let (e, s) = self.expr()?;
+                fields.push((name, e));
+                spans.push(s);
+                if !self.eat_sym(",") {
+                    break;
+                }
+            }
+        }
+        self.expect_sym("}")?;
+        Ok((fields, spans))
+    }
+
+    fn aggs(&mut self) -> Result<Vec<Aggregate>, ParseError> {
+        self.expect_sym("{")?;
+        let mut aggs = Vec::new();
+        if !self.is_sym("}") {
+            loop {
+                let name = self.word()?;
+                self.expect_sym(":")?;
+                let span = self.span();
+                let op = self.word()?;
+                let Some(op) = op.as_str().and_then(AggOp::from_name) else {
+                    return err(span, "expected an aggregate");
+                };
+                self.expect_sym("(")?;
+                let (arg, _) = self.path()?;
+                self.expect_sym(")")?;
+                aggs.push(Aggregate { name, op, arg });
+                if !self.eat_sym(",") {
+                    break;
+                }
+            }
+        }
+        self.expect_sym("}")?;
+        Ok(aggs)
+    }
+
+    fn atom(&mut self) -> Result<Node, ParseError> {
+        let lo = self.span();
+        if let Some(vals) = self.scalar()? {
+            return Ok(node(Expr::lit(vals), lo.join(self.prev_span()), vec![]));
+        }
+        match self.peek().clone() {
+            Tok::Sym("(") => {
+                self.bump();
+                let (e, mut s) = self.expr()?;
+                let hi = self.expect_sym(")")?;
+                // The parens belong to the inner node's span; there's no
+                // separate node for them.
+                s.span = lo.join(hi);
+                Ok((e, s))
+            }
+            Tok::Sym("[") => self.list(None),
+            Tok::Sym("{") => {
+                let (fields, kids) = self.fields()?;
+                Ok(node(Expr::Rec(fields), lo.join(self.prev_span()), kids))
+            }
+            Tok::Word(w) => self.word_atom(&w),
+            Tok::Quoted(w) if w.is_empty() => {
+                self.bump();
+                Ok(node(Expr::Path(Path(vec![])), lo, vec![]))
+            }
+            Tok::Quoted(_) => {
+                let (path, span) = self.path()?;
+                Ok(node(Expr::Path(path), span, vec![]))
+            }
+            _ => err(lo, "expected an expression"),
+        }
+    }
+
+    fn word_atom(&mut self, w: &str) -> Result<Node, ParseError> {
+        let lo = self.span();
+        let call = matches!(self.peek_at(1), Tok::Sym("("));
+        match w {
+            "pass" => {
+                self.bump();
+                Ok(node(Expr::Pass, lo, vec![]))
+            }
+            "reify" => {
+                self.bump();
+                Ok(node(Expr::Reify, lo, vec![]))
+            }
+            "tab" => {
+                self.bump();
+                let (fields, kids) = self.fields()?;
+                Ok(node(Expr::Tab(fields), lo.join(self.prev_span()), kids))
+            }
+            "query" => {
+                self.bump();
+                self.expect_sym("(")?;
+                let (env, es) = self.expr()?;
+                self.expect_sym(",")?;
+                let (path, _) = self.path()?;
+                let hi = self.expect_sym(")")?;
+                Ok(node(Expr::query(env, path), lo.join(hi), vec![es]))
+            }
+            "convert" => {
+                self.bump();
+                self.expect_sym("(")?;
+                let (val, vs) = self.expr()?;
+                self.expect_sym(",")?;
+                let (tok, us) = self.bump();
+                let unit = match tok {
+                    Tok::Str(bytes) => std::str::from_utf8(&bytes).ok().and_then(Unit::parse),
+                    _ => return err(us, "expected a unit string"),
+                };
+                let Some(unit) = unit else {
+                    return err(us, "unknown unit");
+                };
+                let hi = self.expect_sym(")")?;
+                Ok(node(Expr::convert(val, unit), lo.join(hi), vec![vs]))
+            }
+            "cast" => {
+                self.bump();
+                self.expect_sym("(")?;
+                let (val, vs) = self.expr()?;
+                self.expect_sym(",")?;
+                let (to, form) = self.cast_target()?;
+                let mode = if self.eat_sym(",") {
+                    match self.bump() {
+                        (Tok::Word(w), ms) => match CastMode::from_name(&w) {
+                            Some(m) => m,
+                            None => return err(ms, "unknown cast mode"),
+                        },
+                        (_, ms) => return err(ms, "expected a cast mode"),
+                    }
+                } else {
+                    CastMode::Check
+                };
+                let hi = self.expect_sym(")")?;
+                let cast = Cast { to, form, mode };
+                Ok(node(Expr::cast(val, cast), lo.join(hi), vec![vs]))
+            }
+            "group" => {
+                self.bump();
+                self.expect_sym("(")?;
+                let (tab, ts) = self.expr()?;
+                self.expect_sym(",")?;
+                self.expect_sym("[")?;
+                let mut keys = Vec::new();
+                if !self.is_sym("]") {
+                    loop {
+                        keys.push(self.path()?.0);
+                        if !self.eat_sym(",") {
+                            break;
+                        }
+                    }
+                }
+                self.expect_sym("]")?;
+                self.expect_sym(",")?;
+                let aggs = self.aggs()?;
+                let hi = self.expect_sym(")")?;
+                let grouping = Grouping { keys, aggs };
+                Ok(node(Expr::group(tab, grouping), lo.join(hi), vec![ts]))
+            }
+            "sort" => {
+                self.bump();
+                self.expect_sym("(")?;
+                let (tab, ts) = self.expr()?;
+                self.expect_sym(",")?;
+                self.expect_sym("[")?;
+                let mut keys = Vec::new();
+                if !self.is_sym("]") {
+                    loop {
+                        let (path, _) = self.path()?;
+                        // Not keywords, so that they can still name columns.
+                        let desc = self.is_kw("desc");
+                        if desc || self.is_kw("asc") {
+                            self.bump();
+                        }
+                        keys.push(SortKey { path, desc });
+                        if !self.eat_sym(",") {
+                            break;
+                        }
+                    }
+                }
+                self.expect_sym("]")?;
+                let hi = self.expect_sym(")")?;
+                Ok(node(Expr::sort(tab, keys), lo.join(hi), vec![ts]))
+            }
+            "join" | "left_join" => {
+                let kind = if w == "join" {
+                    JoinKind::Inner
+                } else {
+                    JoinKind::Left
+                };
+                self.bump();
+                self.expect_sym("(")?;
+                let (left, ls) = self.expr()?;
+                self.expect_sym(",")?;
+                let (right, rs) = self.expr()?;
+                self.expect_sym(",")?;
+                self.expect_sym("[")?;
+                let mut on = Vec::new();
+                if !self.is_sym("]") {
+                    loop {
+                        let (l, _) = self.path()?;
+                        self.expect_sym("=")?;
+                        let (r, _) = self.path()?;
+                        on.push((l, r));
+                        if !self.eat_sym(",") {
+                            break;
+                        }
+                    }
+                }
+                self.expect_sym("]")?;
+                let hi = self.expect_sym(")")?;
+                let j = Join { kind, on };
+                Ok(node(Expr::join(left, right, j), lo.join(hi), vec![ls, rs]))
+            }
+            "filter" => {
+                self.bump();
+                let (mut args, kids) = self.args()?;
+                if args.len() != 2 {
+                    return err(lo.join(self.prev_span()), "filter takes 2 arguments");
+                }
+                let pred = args.pop().unwrap();
+                let tab = args.pop().unwrap();
+                Ok(node(
+                    Expr::filter(tab, pred),
+                    lo.join(self.prev_span()),
+                    kids,
+                ))
+            }
+            "quote" | "splice" => {
+                self.bump();
+                let quote = w == "quote";
+                if !quote && self.quotes == 0 {
+                    return err(lo, "splice outside quote");
+                }
+                self.expect_sym("(")?;
+                let depth = self.quotes;
+                self.quotes = if quote { depth + 1 } else { depth - 1 };
+                let body = self.expr();
+                self.quotes = depth;
+                let (body, bs) = body?;
+                let hi = self.expect_sym(")")?;
+                let e = if quote {
+                    Expr::quote(body)
+                } else {
+                    Expr::splice(body)
+                };
+                Ok(node(e, lo.join(hi), vec![bs]))
+            }
+            "merge" => {
+                self.bump();
+                let (mut args, kids) = self.args()?;
+                if args.len() != 2 {
+                    return err(lo.join(self.prev_span()), "merge takes 2 arguments");
+                }
+                let b = args.pop().unwrap();
+                let a = args.pop().unwrap();
+                Ok(node(Expr::merge(a, b), lo.join(self.prev_span()), kids))
+            }
+            _ if LIST_TYS.contains(&w) && matches!(self.peek_at(1), Tok::Sym("[")) => {
+                self.bump();
+                self.list(Some(lo))
+            }
+            _ if call && PrimUnOp::from_name(w).is_some() => {
+                let op = PrimUnOp::from_name(w).unwrap();
+                self.bump();
+                let (mut args, kids) = self.args()?;
+                let span = lo.join(self.prev_span());
+                if args.len() != 1 {
+                    return err(span, format!("{} takes 1 argument", w));
+                }
+                Ok(node(Expr::unop(op, args.pop().unwrap()), span, kids))
+            }
+            _ if call && PrimBinOp::from_name(w).is_some() => {
+                let op = PrimBinOp::from_name(w).unwrap();
+                self.bump();
+                let (mut args, kids) = self.args()?;
+                let span = lo.join(self.prev_span());
+                if args.len() != 2 {
+                    return err(span, format!("{} takes 2 arguments", w));
+                }
+                let b = args.pop().unwrap();
+                let a = args.pop().unwrap();
+                Ok(node(Expr::binop(op, a, b), span, kids))
+            }
+            _ if call && BinFn::from_name(w).is_some() => {
+                let f = BinFn::from_name(w).unwrap();
+                self.bump();
+                let (args, kids) = self.args()?;
+                let span = lo.join(self.prev_span());
+                if args.len() != f.arity() {
+                    return err(span, format!("{} takes {} arguments", w, f.arity()));
+                }
+                Ok(node(Expr::binfn(f, args), span, kids))
+            }
+            _ if call && BitFn::from_name(w).is_some() => {
+                let f = BitFn::from_name(w).unwrap();
+                self.bump();
+                let (args, kids) = self.args()?;
+                let span = lo.join(self.prev_span());
+                if args.len() != f.arity() {
+                    return err(span, format!("{} takes {} arguments", w, f.arity()));
+                }
+                Ok(node(Expr::bitfn(f, args), span, kids))
+            }
+            _ if call && TimeFn::from_name(w).is_some() => {
+                let f = TimeFn::from_name(w).unwrap();
+                self.bump();
+                let (args, kids) = self.args()?;
+                let span = lo.join(self.prev_span());
+                if args.len() != f.arity() {
+                    return err(span, format!("{} takes {} arguments", w, f.arity()));
+                }
+                Ok(node(Expr::timefn(f, args), span, kids))
+            }
+            _ => {
+                let (path, span) = self.path()?;
+                Ok(node(Expr::Path(path), span, vec![]))
+            }
+        }
+    }
+
+    // A single literal value, as a one-row Vals, or None if the next token
+    // doesn't start one.
+    fn scalar(&mut self) -> Result<Option<Vals>, ParseError> {
+        let span = self.span();
+        let vals = match self.peek().clone() {
+            Tok::Int(digits) => Vals::I64s(vec![int_literal(&digits, span)?]),
+            Tok::Float(f) => Vals::F64s(vec![OrderedFloat(f)]),
+            Tok::Str(bytes) => Vals::Bins(vec![Bin::Mem(bytes)]),
+            Tok::Word(w) if w == "true" => Vals::Bits(1, [0].into_iter().collect()),
+            Tok::Word(w) if w == "false" => Vals::Bits(1, bs::Bs::new()),
+            Tok::Word(w) if w == "nan" => Vals::F64s(vec![OrderedFloat(f64::NAN)]),
+            Tok::Word(w) if w == "inf" => Vals::F64s(vec![OrderedFloat(f64::INFINITY)]),
+            Tok::Sym("-") => match self.peek_at(1).clone() {
+                Tok::Int(digits) => {
+                    let span = span.join(self.toks[self.pos + 1].1);
+                    self.bump();
+                    Vals::I64s(vec![int_literal(&format!("-{}", digits), span)?])
+                }
+                Tok::Float(f) => {
+                    self.bump();
+                    Vals::F64s(vec![OrderedFloat(-f)])
+                }
+                Tok::Word(w) if w == "inf" => {
+                    self.bump();
+                    Vals::F64s(vec![OrderedFloat(f64::NEG_INFINITY)])
+                }
+                _ => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        self.bump();
+        Ok(Some(vals))
+    }
+
+    // A cast's target type, with its form if it's an int with one.
+    fn cast_target(&mut self) -> Result<(Major, Form), ParseError> {
+        let (tok, ts) = self.bump();
+        let Tok::Word(w) = tok else {
+            return err(ts, "expected a type to cast to");
+        };
+        Ok(match w.as_str() {
+            "bit" => (Major::Bit, Form::NONE),
+            "int" => (Major::Int, Form::NONE),
+            "flo" => (Major::Flo, Form::NONE),
+            "bin" => (Major::Bin, Form::NONE),
+            "timestamp" => (Major::Int, Form::TIMESTAMP),
+            "date" => (Major::Int, Form::DATE),
+            "duration" => (Major::Int, Form::DURATION),
+            "dec" => {
+                self.expect_sym("(")?;
+                let precision = self.small_int()?;
+                self.expect_sym(",")?;
+                let scale = self.small_int()?;
+                let hi = self.expect_sym(")")?;
+                match Form::decimal(precision, scale) {
+                    Some(form) => (Major::Int, form),
+                    None => return err(ts.join(hi), "bad decimal precision or scale"),
+                }
+            }
+            _ => return err(ts, "unknown type to cast to"),
+        })
+    }
+
+    fn small_int(&mut self) -> Result<u8, ParseError> {
+        match self.bump() {
+            (Tok::Int(text), span) => match text.parse() {
+                Ok(n) => Ok(n),
+                Err(_) => err(span, "number out of range"),
+            },
+            (_, span) => err(span, "expected a number"),
+        }
+    }
+
+    // A bracketed list of scalars of one type. Empty lists need a type
+    // prefix, which the caller has already consumed.
+    fn list(&mut self, ty: Option<Span>) -> Result<Node, ParseError> {
+        let lo = ty.unwrap_or(self.span());
+        let ty_name = ty.map(|_| match &self.toks[self.pos - 1].0 {
+            Tok::Word(w) => w.clone(),
+            _ => unreachable!(),
+        });
+        self.expect_sym("[")?;
+        let mut items = Vec::new();
+        if !self.is_sym("]") {
+            loop {
+                let s = self.span();
+                match self.scalar()? {
+                    Some(v) => items.push((v, s)),
+                    None => return err(s, "expected a literal"),
+                }
+                if !self.eat_sym(",") {
+                    break;
+                }
+            }
+        }
+        let hi = self.expect_sym("]")?;
+        let span = lo.join(hi);
+        let vals = match (ty_name.as_deref(), items.is_empty()) {
+            (Some("i64"), true) => Vals::I64s(vec![]),
+            (Some("f64"), true) => Vals::F64s(vec![]),
+            (Some("bit"), true) => Vals::Bits(0, bs::Bs::new()),
+            (Some("bin"), true) => Vals::Bins(vec![]),
+            (Some(_), false) => return err(span, "only empty lists take a type prefix"),
+            (_, true) => return err(span, "empty list needs a type, eg. i64[]"),
+            (_, false) => concat_scalars(items)?,
+        };
+        Ok(node(Expr::lit(vals), span, vec![]))
+    }
+}
+
+fn int_literal(text: &str, span: Span) -> Result<i64, ParseError> {
+    match text.parse::<i64>() {
+        Ok(n) => Ok(n),
+        Err(_) => err(span, "integer literal out of range"),
+    }
+}
+
+fn concat_scalars(items: Vec<(Vals, Span)>) -> Result<Vals, ParseError> {
+    let mut items = items.into_iter();
+    let (mut out, _) = items.next().unwrap();
+    for (v, span) in items {
+        match (&mut out, v) {
+            (Vals::I64s(a), Vals::I64s(b)) => a.extend(b),
+            (Vals::F64s(a), Vals::F64s(b)) => a.extend(b),
+            (Vals::Bins(a), Vals::Bins(b)) => a.extend(b),
+            (Vals::Bits(n, a), Vals::Bits(_, b)) => {
+                if b.contains(0) {
+                    a.insert(*n);
+                }
+                *n += 1;
+            }
+            _ => return err(span, "list elements must all have the same type"),
+        }
+    }
+    Ok(out)
+}
+
+impl SpanTree {
+    // The spans of all nodes in pre-order, matching a pre-order walk of
+    // Expr::children().
+    pub fn preorder(&self) -> Vec<Span> {
+        let mut out = vec![self.span];
+        for k in &self.kids {
+            out.extend(k.preorder());
+        }
+        out
+    }
+}
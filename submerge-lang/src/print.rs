@@ -0,0 +1,362 @@
+// Display impls that print in the surface syntax accepted by the parser, so
+// that printing then parsing gives back an equal Expr. The exceptions are
+// values with no literal syntax (heap bins, Rich/All/Any columns), which
+// print in a bracketed debug form that the parser rejects.
+//
+// Parentheses are only emitted where precedence requires them, and backquotes
+// only around words that would otherwise parse as keywords or builtins.
+
+use crate::{
+    parse::{is_builtin, is_keyword, precedence},
+    Bin, Col, Expr, JoinKind, Path, PrimBinOp, PrimUnOp, Tab, Unit, Vals, Word,
+};
+use ordered_float::OrderedFloat;
+use std::fmt::{self, Display, Formatter, Write};
+
+// Levels mirror the parser: 0 is a full expr (let / fn / if, which extend as
+// far right as possible), 1..=7 are infix precedences, then prefix, then
+// atoms and calls.
+const LEVEL_EXPR: u8 = 0;
+const LEVEL_PREFIX: u8 = 8;
+const LEVEL_ATOM: u8 = 9;
+
+fn level(e: &Expr) -> u8 {
+    match e {
+        Expr::Let(..) | Expr::Lam(..) | Expr::If(..) => LEVEL_EXPR,
+        Expr::BinOp(op, ..) if op.symbol().is_some() => precedence(*op),
+        Expr::UnOp(op, _) if op.symbol().is_some() => LEVEL_PREFIX,
+        Expr::Lit(v) if is_negative_scalar(v) => LEVEL_PREFIX,
+        _ => LEVEL_ATOM,
+    }
+}
+
+// Negative numeric literals print with a leading '-', which the parser folds
+// back into the literal; that makes them bind like a prefix op.
+fn is_negative_scalar(v: &Vals) -> bool {
+    match v {
+        Vals::I64s(v) => v.len() == 1 && v[0] < 0,
+        Vals::F64s(v) => v.len() == 1 && v[0].is_sign_negative() && !v[0].is_nan(),
+        _ => false,
+    }
+}
+
+fn write_expr(f: &mut Formatter<'_>, e: &Expr, min_level: u8) -> fmt::Result {
+    if level(e) < min_level {
+        f.write_char('(')?;
+        write_expr(f, e, LEVEL_EXPR)?;
+        return f.write_char(')');
+    }
+    match e {
+        Expr::Pass => f.write_str("pass"),
+        Expr::Reify => f.write_str("reify"),
+        Expr::Lit(v) => write!(f, "{}", v),
+        Expr::Path(p) => write!(f, "{}", p),
+        Expr::Let(name, val, body) => {
+            write!(f, "let {} = ", name)?;
+            write_expr(f, val, LEVEL_EXPR)?;
+            f.write_str(" in ")?;
+            write_expr(f, body, LEVEL_EXPR)
+        }
+        Expr::Lam(params, body) => {
+            f.write_str("fn(")?;
+            write_sep(f, params, |f, p| write!(f, "{}", p))?;
+            f.write_str(") ")?;
+            write_expr(f, body, LEVEL_EXPR)
+        }
+        Expr::If(c, t, e) => {
+            f.write_str("if ")?;
+            write_expr(f, c, LEVEL_EXPR)?;
+            f.write_str(" then ")?;
+            write_expr(f, t, LEVEL_EXPR)?;
+            f.write_str(" else ")?;
+            write_expr(f, e, LEVEL_EXPR)
+        }
+        Expr::App(func, args) => {
+            match &**func {
+                // A call of a builtin's name would parse as the builtin.
+                Expr::Path(Path(words)) if words.len() == 1 => match words[0].as_str() {
+                    Some(s) if is_builtin(s) => write!(f, "`{}`", s)?,
+                    _ => write_expr(f, func, LEVEL_ATOM)?,
+                },
+                _ => write_expr(f, func, LEVEL_ATOM)?,
+            }
+            write_args(f, args.iter())
+        }
+        Expr::BinOp(op, a, b) => match op.symbol() {
+            Some(sym) => {
+                let p = precedence(*op);
+                let (lp, rp) = if *op == PrimBinOp::Pow {
+                    (p + 1, p)
+                } else {
+                    (p, p + 1)
+                };
+                write_expr(f, a, lp)?;
+                write!(f, " {} ", sym)?;
+                write_expr(f, b, rp)
+            }
+            None => {
+                f.write_str(op.name())?;
+                write_args(f, [&**a, &**b].into_iter())
+            }
+        },
+        Expr::UnOp(op, a) => match op.symbol() {
+            Some(sym) => {
+                f.write_str(sym)?;
+                // Keep '-(1)' from reparsing as the literal -1.
+                let numeric = matches!(&**a, Expr::Lit(Vals::I64s(_) | Vals::F64s(_)));
+                let min = if numeric && *op == PrimUnOp::Neg {
+                    LEVEL_ATOM + 1
+                } else {
+                    LEVEL_PREFIX
+                };
+                write_expr(f, a, min)
+            }
+            None => {
+                f.write_str(op.name())?;
+                write_args(f, std::iter::once(&**a))
+            }
+        },
+        Expr::BinFn(func, args) => {
+            f.write_str(func.name())?;
+            write_args(f, args.iter())
+        }
+        Expr::TimeFn(func, args) => {
+            f.write_str(func.name())?;
+            write_args(f, args.iter())
+        }
+        Expr::BitFn(func, args) => {
+            f.write_str(func.name())?;
+            write_args(f, args.iter())
+        }
+        Expr::Rec(fields) => write_fields(f, fields),
+        Expr::Tab(fields) => {
+            f.write_str("tab")?;
+            write_fields(f, fields)
+        }
+        Expr::Query(env, path) => {
+            f.write_str("query(")?;
+            write_expr(f, env, LEVEL_EXPR)?;
+            write!(f, ", {})", path)
+        }
+        Expr::Merge(a, b) => {
+            f.write_str("merge")?;
+            write_args(f, [&**a, &**b].into_iter())
+        }
+        Expr::Quote(a) => {
+            f.write_str("quote")?;
+            write_args(f, [&**a].into_iter())
+        }
+        Expr::Splice(a) => {
+            f.write_str("splice")?;
+            write_args(f, [&**a].into_iter())
+        }
+        Expr::Filter(tab, pred) => {
+            f.write_str("filter")?;
+            write_args(f, [&**tab, &**pred].into_iter())
+        }
+        Expr::Convert(a, unit) => {
+            f.write_str("convert(")?;
+            write_expr(f, a, LEVEL_EXPR)?;
+            write!(f, ", \"{}\")", unit)
+        }
+        Expr::Cast(a, c) => {
+            f.write_str("cast(")?;
+            write_expr(f, a, LEVEL_EXPR)?;
+            write!(f, ", {})", c)
+        }
+        Expr::Group(tab, g) => {
+            f.write_str("group(")?;
+            write_expr(f, tab, LEVEL_EXPR)?;
+            f.write_str(", [")?;
+            write_sep(f, &g.keys, |f, k| write!(f, "{}", k))?;
+            f.write_str("], {")?;
+            write_sep(f, &g.aggs, |f, a| {
+                write!(f, "{}: {}({})", a.name, a.op.name(), a.arg)
+            })?;
+            f.write_str("})")
+        }
+        Expr::Join(a, b, j) => {
+            f.write_str(match j.kind {
+                JoinKind::Inner => "join(",
+                JoinKind::Left => "left_join(",
+            })?;
+            write_expr(f, a, LEVEL_EXPR)?;
+            f.write_str(", ")?;
+            write_expr(f, b, LEVEL_EXPR)?;
+            f.write_str(", [")?;
+            write_sep(f, &j.on, |f, (l, r)| write!(f, "{} = {}", l, r))?;
+            f.write_str("])")
+        }
+        Expr::Sort(tab, keys) => {
+            f.write_str("sort(")?;
+            write_expr(f, tab, LEVEL_EXPR)?;
+            f.write_str(", [")?;
+            write_sep(f, keys, |f, k| {
+                write!(f, "{}{}", k.path, if k.desc { " desc" } else { "" })
+            })?;
+            f.write_str("])")
+        }
+    }
+}
+
+fn write_sep<T>(
+    f: &mut Formatter<'_>,
+    items: impl IntoIterator<Item = T>,
+    mut each: impl FnMut(&mut Formatter<'_>, T) -> fmt::Result,
+) -> fmt::Result {
+    for (i, item) in items.into_iter().enumerate() {
+        if i != 0 {
+            f.write_str(", ")?;
+        }
+        each(f, item)?;
+    }
+    Ok(())
+}
+
+fn write_args<'a>(f: &mut Formatter<'_>, args: impl Iterator<Item = &'a Expr>) -> fmt::Result {
+    f.write_char('(')?;
+    write_sep(f, args, |f, a| write_expr(f, a, LEVEL_EXPR))?;
+    f.write_char(')')
+}
+
+fn write_fields(f: &mut Formatter<'_>, fields: &[(Word, Expr)]) -> fmt::Result {
+    f.write_char('{')?;
+    write_sep(f, fields, |f, (name, e)| {
+        write!(f, "{}: ", name)?;
+        write_expr(f, e, LEVEL_EXPR)
+    })?;
+    f.write_char('}')
+}
+
+fn write_f64(f: &mut Formatter<'_>, x: OrderedFloat<f64>) -> fmt::Result {
+    if x.is_nan() {
+        f.write_str("nan")
+    } else if x.is_infinite() {
+        f.write_str(if x.0 < 0.0 { "-inf" } else { "inf" })
+    } else {
+        // Debug always includes a '.' or an exponent, so it lexes as a float.
+        write!(f, "{:?}", x.0)
+    }
+}
+
+fn write_bin(f: &mut Formatter<'_>, b: &Bin) -> fmt::Result {
+    match b {
+        Bin::Heap { block, entry } => write!(f, "<bin {}:{}>", block, entry),
+        Bin::Mem(bytes) => {
+            f.write_char('"')?;
+            for &c in bytes {
+                match c {
+                    b'"' => f.write_str("\\\"")?,
+                    b'\\' => f.write_str("\\\\")?,
+                    b'\n' => f.write_str("\\n")?,
+                    b'\t' => f.write_str("\\t")?,
+                    0x20..=0x7e => f.write_char(c as char)?,
+                    _ => write!(f, "\\x{:02x}", c)?,
+                }
+            }
+            f.write_char('"')
+        }
+    }
+}
+
+fn write_list<T>(
+    f: &mut Formatter<'_>,
+    ty: &str,
+    items: &[T],
+    each: impl FnMut(&mut Formatter<'_>, &T) -> fmt::Result,
+) -> fmt::Result {
+    match items.len() {
+        0 => write!(f, "{}[]", ty),
+        1 => write_sep(f, items, each),
+        _ => {
+            f.write_char('[')?;
+            write_sep(f, items, each)?;
+            f.write_char(']')
+        }
+    }
+}
+
+impl Display for Vals {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Vals::I64s(v) => write_list(f, "i64", v, |f, n| write!(f, "{}", n)),
+            Vals::F64s(v) => write_list(f, "f64", v, |f, x| write_f64(f, *x)),
+            Vals::Bins(v) => write_list(f, "bin", v, write_bin),
+            Vals::Bits(n, bits) => {
+                let v: Vec<bool> = (0..*n).map(|i| bits.contains(i)).collect();
+                write_list(f, "bit", &v, |f, b| write!(f, "{}", b))
+            }
+            Vals::Rich(col) => write!(f, "<rich {}>", col),
+            Vals::All(fields) => {
+                f.write_str("<all ")?;
+                write_sep(f, fields, |f, v| write!(f, "{}", v))?;
+                f.write_char('>')
+            }
+            Vals::Any(sel, vars) => {
+                write!(f, "<any {:?} ", sel)?;
+                write_sep(f, vars, |f, v| write!(f, "{}", v))?;
+                f.write_char('>')
+            }
+            Vals::Opt(ns, vals) => write!(f, "<opt {:?} {}>", Vec::from(ns), vals),
+        }
+    }
+}
+
+impl Display for Word {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match (self.as_str(), &self.0) {
+            (Some(s), _) if is_keyword(s) => write!(f, "`{}`", s),
+            (Some(s), _) => f.write_str(s),
+            (None, b) => write_bin(f, b),
+        }
+    }
+}
+
+impl Display for Path {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str("``");
+        }
+        for (i, w) in self.0.iter().enumerate() {
+            if i != 0 {
+                f.write_char('.')?;
+            }
+            write!(f, "{}", w)?;
+        }
+        Ok(())
+    }
+}
+
+// Decimal columns print their values at scale, after their form, and units
+// follow the values.
+impl Display for Col {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match (self.form.as_decimal(), &self.vals) {
+            (Some(d), Vals::I64s(v)) => {
+                write!(f, "{}: {}[", self.name, self.form)?;
+                write_sep(f, v, |f, n| f.write_str(&d.format(*n)))?;
+                f.write_char(']')?;
+            }
+            _ => write!(f, "{}: {}", self.name, self.vals)?,
+        }
+        if self.unit != Unit::NONE {
+            write!(f, " {}", self.unit)?;
+        }
+        Ok(())
+    }
+}
+
+// Tabs print as the tab{...} expression that would construct them.
+impl Display for Tab {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("tab{")?;
+        write_sep(f, &self.cols, |f, c| write!(f, "{}", c))?;
+        f.write_char('}')
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write_expr(f, self, LEVEL_EXPR)
+    }
+}
@@ -0,0 +1,105 @@
+// Staging. quote(e) is e itself as a value, a one-row bin column of form
+// code holding e's canonical encoding, so a query can build a specialized
+// query and hand it on to be checked and run later. Inside a quote, splice
+// marks a hole: its operand is evaluated now, when the quote is, and its
+// value fills the hole. A code value fills it with the Expr it encodes, and
+// any other value with a literal of it, so quote(t.x + splice(k)) with k = 3
+// builds t.x + 3, and splicing a quote of 3 builds the same thing.
+//
+// Quotes nest. A splice belongs to the innermost quote around it, and only
+// the splices of the outermost quote being evaluated are holes; the others
+// stay in the built Expr, for whoever evaluates the inner quotes later.
+// Quote bodies are otherwise not checked where they appear, only their
+// holes' operands are, and those must be data or code, not fns.
+//
+// The Vm runs a quote as Opcode::Quote, whose template is the body with its
+// holes' operands replaced by Pass, after opcodes that push the operands'
+// values in pre-order. Every Expr it builds must pass the FO checker on its
+// own, as a thunk would, or the opcode faults: code can't be used to build
+// a recursion that the FO checker would refuse to see written out. The
+// checker knows the running Vm's user-defined functions, so built code may
+// call them.
+
+use crate::{
+    check_fo_with,
+    fault::{fault, FaultKind},
+    kernel::enrich,
+    Bin, Expr, Form, Udfs, Unit, Vals,
+};
+use submerge_base::{Error, Result};
+
+impl Expr {
+    // The template of a quote's body, and its holes' operands in pre-order.
+    pub fn template(&self) -> (Expr, Vec<Expr>) {
+        let mut holes = Vec::new();
+        let tpl = fill(self, 0, &mut |x| {
+            holes.push(x.clone());
+            Expr::splice(Expr::Pass)
+        });
+        (tpl, holes)
+    }
+
+    pub fn to_code(&self) -> Vals {
+        let bytes = vec![Bin::Mem(self.canonical())];
+        enrich(None, Form::CODE, Unit::NONE, Vals::Bins(bytes))
+    }
+
+    // The Expr of a one-row code value.
+    pub fn from_code(vals: &Vals) -> Result<Expr> {
+        match vals {
+            Vals::Rich(c) if c.form == Form::CODE => match &c.vals {
+                Vals::Bins(v) => match v.as_slice() {
+                    [Bin::Mem(bytes)] => Expr::from_canonical(bytes),
+                    [Bin::Heap { .. }] => Err(Error::corruption("code bin is not in memory")),
+                    _ => Err(fault(
+                        FaultKind::Invalid,
+                        format!("{} rows of code where one is needed", v.len()),
+                    )),
+                },
+                _ => Err(Error::corruption("code column is not a bin column")),
+            },
+            _ => Err(fault(FaultKind::Mismatch, "not a code value")),
+        }
+    }
+}
+
+fn is_code(vals: &Vals) -> bool {
+    matches!(vals, Vals::Rich(c) if c.form == Form::CODE)
+}
+
+// A copy of e, at a quote depth, with each hole replaced by f of its
+// operand, in pre-order.
+pub(crate) fn fill(e: &Expr, depth: usize, f: &mut impl FnMut(&Expr) -> Expr) -> Expr {
+    match e {
+        Expr::Splice(x) if depth == 0 => f(x),
+        Expr::Splice(x) => Expr::splice(fill(x, depth - 1, f)),
+        Expr::Quote(x) => Expr::quote(fill(x, depth + 1, f)),
+        _ => e.map_children(|c| fill(c, depth, f)),
+    }
+}
+
+pub(crate) fn holes(tpl: &Expr) -> usize {
+    let mut n = 0;
+    fill(tpl, 0, &mut |x| {
+        n += 1;
+        x.clone()
+    });
+    n
+}
+
+// Fill a template's holes with the values of their operands.
+pub(crate) fn build(tpl: &Expr, args: Vec<Vals>, udfs: &Udfs) -> Result<Vals> {
+    let exprs = args
+        .into_iter()
+        .map(|v| match is_code(&v) {
+            true => Expr::from_code(&v),
+            false => Ok(Expr::Lit(v)),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut exprs = exprs.into_iter();
+    let e = fill(tpl, 0, &mut |_| exprs.next().unwrap_or(Expr::Pass));
+    if let Err(e) = check_fo_with(&e, None, udfs) {
+        return Err(fault(FaultKind::Invalid, format!("built code is {}", e)));
+    }
+    Ok(e.to_code())
+}
@@ -0,0 +1,61 @@
+// Sorting reorders a table's rows by one or more key columns, each ascending
+// or descending, and keeps rows with equal keys in their original order, so
+// that every node sorts a table the same way. Keys compare as Vals do: ints
+// and floats numerically (NaN after everything), bits false first, and bins
+// bytewise, since there are no collations yet. Nulls come after everything,
+// so they're last ascending and first descending. As with grouping, a table
+// in the Vm is a record of labelled columns, so sorting works on either.
+
+use crate::{all::unlabelled, Path, SortKey, Tab, Val, Vals, Word};
+use std::cmp::Ordering;
+use submerge_base::{err, Result};
+
+// The permutation that sorts n rows by the given key columns.
+pub(crate) fn order(keys: &[(Vals, bool)], n: usize) -> Vec<usize> {
+    let cols: Vec<(Vec<Val>, bool)> = keys.iter().map(|(k, d)| (k.to_rows(), *d)).collect();
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|a, b| {
+        for (rows, desc) in &cols {
+            let o = rows[*a].cmp(&rows[*b]);
+            let o = if *desc { o.reverse() } else { o };
+            if o != Ordering::Equal {
+                return o;
+            }
+        }
+        Ordering::Equal
+    });
+    order
+}
+
+fn sort_cols(
+    keys: &[SortKey],
+    cols: Vec<(Word, Vals)>,
+    n: usize,
+    col: impl Fn(&Path) -> Result<Vals>,
+) -> Result<Vec<(Word, Vals)>> {
+    let mut kvs = Vec::with_capacity(keys.len());
+    for k in keys {
+        let v = col(&k.path)?;
+        if v.len() != n {
+            return Err(err(format!("sort key {} doesn't have {} rows", k.path, n)));
+        }
+        kvs.push((v, k.desc));
+    }
+    let order = order(&kvs, n);
+    cols.into_iter()
+        .map(|(w, v)| Ok((w, v.take(&order)?)))
+        .collect()
+}
+
+// Sort a table held as a record of labelled columns, giving another.
+pub(crate) fn sort_record(table: &Vals, keys: &[SortKey]) -> Result<Vals> {
+    let col = |p: &Path| table.project(&p.0).map(unlabelled);
+    Vals::zip(sort_cols(keys, table.unzip()?, table.len(), col)?)
+}
+
+impl Tab {
+    pub fn sort_by(&self, keys: &[SortKey]) -> Result<Tab> {
+        let col = |p: &Path| self.load(p);
+        Tab::from_named(sort_cols(keys, self.named(), self.len(), col)?)
+    }
+}
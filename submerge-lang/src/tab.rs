@@ -0,0 +1,558 @@
+// Paths address Cols within a Tab. The first word of a path names one of the
+// Tab's columns, and each later word names a field of the column before it:
+// a Rich field of an All or Any, by its label or by its position (_0, _1,
+// ...). A label shadows a position that spells the same word. A column whose
+// values are themselves Rich is transparent, so paths walk through it to the
+// fields inside.
+//
+// Tabs can also be built and read a row at a time. The variants of an Any
+// are stored densely: variant k holds just the rows whose selector is k, in
+// row order.
+
+use crate::{
+    kernel::enrich,
+    null::{blank, with_nulls},
+    Bin, Col, Form, Path, Tab, Unit, Val, Vals, Word,
+};
+use ordered_float::OrderedFloat;
+use submerge_base::{err, Error, Result};
+
+// The values under any Rich wrappers.
+pub(crate) fn inner_vals(col: &Col) -> &Vals {
+    let mut v = &col.vals;
+    while let Vals::Rich(c) = v {
+        v = &c.vals;
+    }
+    v
+}
+
+fn inner_vals_mut(col: &mut Col) -> &mut Vals {
+    let mut v = &mut col.vals;
+    while let Vals::Rich(c) = v {
+        v = &mut c.vals;
+    }
+    v
+}
+
+fn fields(vals: &Vals) -> &[Vals] {
+    match vals {
+        Vals::All(fs) | Vals::Any(_, fs) => fs,
+        _ => &[],
+    }
+}
+
+// The index of the field a word names, preferring labels to positions.
+pub(crate) fn child_index(fields: &[Vals], w: &Word) -> Option<usize> {
+    let labelled = fields
+        .iter()
+        .position(|f| matches!(f, Vals::Rich(c) if c.name == *w));
+    labelled.or_else(|| {
+        let i = (0..fields.len()).find(|i| Word::positional(*i) == *w)?;
+        matches!(fields[i], Vals::Rich(_)).then_some(i)
+    })
+}
+
+fn not_found(path: &Path, depth: usize) -> Error {
+    let prefix = Path(path.0[..depth].to_vec());
+    if depth == path.0.len() {
+        err(format!("no column {}", path))
+    } else {
+        err(format!("no column {} (resolving {})", prefix, path))
+    }
+}
+
+impl Tab {
+    pub fn resolve(&self, path: &Path) -> Result<&Col> {
+        let Some((first, rest)) = path.0.split_first() else {
+            return Err(err("cannot resolve an empty path"));
+        };
+        let mut col = self
+            .cols
+            .iter()
+            .find(|c| c.name == *first)
+            .ok_or_else(|| not_found(path, 1))?;
+        for (depth, w) in rest.iter().enumerate() {
+            let fs = fields(inner_vals(col));
+            col = match child_index(fs, w).map(|i| &fs[i]) {
+                Some(Vals::Rich(c)) => c,
+                _ => return Err(not_found(path, depth + 2)),
+            };
+        }
+        Ok(col)
+    }
+
+    pub fn resolve_mut(&mut self, path: &Path) -> Result<&mut Col> {
+        let Some((first, rest)) = path.0.split_first() else {
+            return Err(err("cannot resolve an empty path"));
+        };
+        let mut col = self
+            .cols
+            .iter_mut()
+            .find(|c| c.name == *first)
+            .ok_or_else(|| not_found(path, 1))?;
+        for (depth, w) in rest.iter().enumerate() {
+            let fs = match inner_vals_mut(col) {
+                Vals::All(fs) | Vals::Any(_, fs) => fs,
+                _ => return Err(not_found(path, depth + 2)),
+            };
+            col = match child_index(fs, w).map(|i| &mut fs[i]) {
+                Some(Vals::Rich(c)) => c,
+                _ => return Err(not_found(path, depth + 2)),
+            };
+        }
+        Ok(col)
+    }
+
+    // Take the Col at a path out of the Tab.
+    pub fn into_resolved(mut self, path: &Path) -> Result<Col> {
+        let col = self.resolve_mut(path)?;
+        let empty = Col {
+            name: col.name.clone(),
+            form: col.form,
+            unit: col.unit,
+            vals: Vals::I64s(Vec::new()),
+        };
+        Ok(std::mem::replace(col, empty))
+    }
+
+    // Every addressable path, each Col before the Cols nested in it. Fields
+    // are listed by label unless an earlier field shadows the label, in
+    // which case they're listed by position if that isn't shadowed too.
+    pub fn paths(&self) -> Vec<Path> {
+        let mut out = Vec::new();
+        for (i, col) in self.cols.iter().enumerate() {
+            // Later columns with a duplicate name can't be addressed.
+            if self.cols[..i].iter().all(|c| c.name != col.name) {
+                walk(col, &mut vec![col.name.clone()], &mut out);
+            }
+        }
+        out
+    }
+}
+
+fn walk(col: &Col, prefix: &mut Vec<Word>, out: &mut Vec<Path>) {
+    out.push(Path(prefix.clone()));
+    let fs = fields(inner_vals(col));
+    for (i, f) in fs.iter().enumerate() {
+        let Vals::Rich(c) = f else {
+            continue;
+        };
+        let name = if child_index(fs, &c.name) == Some(i) {
+            c.name.clone()
+        } else if child_index(fs, &Word::positional(i)) == Some(i) {
+            Word::positional(i)
+        } else {
+            continue;
+        };
+        prefix.push(name);
+        walk(c, prefix, out);
+        prefix.pop();
+    }
+}
+
+impl Col {
+    pub fn new(name: Word, vals: Vals) -> Col {
+        Col {
+            name,
+            form: Form::NONE,
+            unit: Unit::NONE,
+            vals,
+        }
+    }
+    pub fn with_form(mut self, form: Form) -> Col {
+        self.form = form;
+        self
+    }
+    pub fn with_unit(mut self, unit: Unit) -> Col {
+        self.unit = unit;
+        self
+    }
+    pub fn name(&self) -> &Word {
+        &self.name
+    }
+    pub fn form(&self) -> Form {
+        self.form
+    }
+    pub fn unit(&self) -> Unit {
+        self.unit
+    }
+    pub fn vals(&self) -> &Vals {
+        &self.vals
+    }
+}
+
+impl Vals {
+    // The value of one row, or None past the end.
+    pub fn get(&self, row: usize) -> Option<Val> {
+        if row >= self.len() {
+            return None;
+        }
+        Some(match self {
+            Vals::I64s(v) => Val::Int(v[row]),
+            Vals::F64s(v) => Val::Flo(v[row]),
+            Vals::Bits(_, bs) => Val::Bit(bs.contains(row)),
+            Vals::Bins(v) => Val::Bin(v[row].clone()),
+            Vals::Rich(col) => col.vals.get(row)?,
+            Vals::All(fields) => {
+                Val::All(fields.iter().map(|f| f.get(row)).collect::<Option<_>>()?)
+            }
+            Vals::Any(sel, vars) => {
+                let k = usize::try_from(sel[row]).ok()?;
+                let rank = sel[..row].iter().filter(|s| **s == sel[row]).count();
+                Val::Any(k, Box::new(vars.get(k)?.get(rank)?))
+            }
+            Vals::Opt(ns, _) if ns.contains(row) => Val::Null,
+            Vals::Opt(_, vals) => vals.get(row)?,
+        })
+    }
+
+    // Every row in order; unlike repeated get()s, linear for Any columns.
+    // A malformed Any (selector out of range, or a variant too short) ends
+    // the rows early.
+    pub fn to_rows(&self) -> Vec<Val> {
+        match self {
+            Vals::Rich(col) => col.vals.to_rows(),
+            Vals::All(fields) => {
+                let mut cols: Vec<_> = fields.iter().map(|f| f.to_rows().into_iter()).collect();
+                (0..self.len())
+                    .map_while(|_| cols.iter_mut().map(|c| c.next()).collect::<Option<_>>())
+                    .map(Val::All)
+                    .collect()
+            }
+            Vals::Any(sel, vars) => {
+                let mut vars: Vec<_> = vars.iter().map(|v| v.to_rows().into_iter()).collect();
+                sel.iter()
+                    .map_while(|k| {
+                        let k = usize::try_from(*k).ok()?;
+                        Some(Val::Any(k, Box::new(vars.get_mut(k)?.next()?)))
+                    })
+                    .collect()
+            }
+            Vals::Opt(ns, vals) => {
+                let rows = vals.to_rows().into_iter().enumerate();
+                rows.map(|(i, v)| if ns.contains(i) { Val::Null } else { v })
+                    .collect()
+            }
+            _ => (0..self.len()).filter_map(|i| self.get(i)).collect(),
+        }
+    }
+
+    // Whether push() would take a value. Any column takes a null, as long as
+    // there's a placeholder to put under it.
+    pub fn accepts(&self, val: &Val) -> bool {
+        match (self, val) {
+            (v, Val::Null) => blank(v).is_ok(),
+            (Vals::I64s(_), Val::Int(_))
+            | (Vals::F64s(_), Val::Flo(_))
+            | (Vals::Bits(..), Val::Bit(_))
+            | (Vals::Bins(_), Val::Bin(_)) => true,
+            (Vals::Rich(col), v) => col.vals.accepts(v),
+            (Vals::All(fields), Val::All(vs)) => {
+                fields.len() == vs.len() && fields.iter().zip(vs).all(|(f, v)| f.accepts(v))
+            }
+            (Vals::Any(_, vars), Val::Any(k, v)) => vars.get(*k).is_some_and(|f| f.accepts(v)),
+            (Vals::Opt(_, vals), v) => vals.accepts(v),
+            _ => false,
+        }
+    }
+
+    // Append a row, or fail without changing anything if it doesn't fit. A
+    // null makes a column without nulls into one with them.
+    pub fn push(&mut self, val: Val) -> Result<()> {
+        if !self.accepts(&val) {
+            return Err(err(format!(
+                "row value {:?} doesn't fit {} column",
+                val,
+                kind(self)
+            )));
+        }
+        match (self, val) {
+            (Vals::I64s(v), Val::Int(x)) => v.push(x),
+            (Vals::F64s(v), Val::Flo(x)) => v.push(x),
+            (Vals::Bits(n, bs), Val::Bit(x)) => {
+                if x {
+                    bs.insert(*n);
+                }
+                *n += 1;
+            }
+            (Vals::Bins(v), Val::Bin(x)) => v.push(x),
+            (Vals::Rich(col), v) => col.vals.push(v)?,
+            (Vals::Opt(ns, vals), Val::Null) => {
+                ns.insert(vals.len());
+                let b = blank(vals)?;
+                vals.push(b)?;
+            }
+            (Vals::Opt(_, vals), v) => vals.push(v)?,
+            (Vals::All(fields), Val::Null) => {
+                for f in fields {
+                    f.push(Val::Null)?;
+                }
+            }
+            (v, Val::Null) => {
+                let vals = std::mem::replace(v, Vals::I64s(Vec::new()));
+                *v = Vals::Opt(bs::Bs::new(), Box::new(vals));
+                v.push(Val::Null)?;
+            }
+            (Vals::All(fields), Val::All(vs)) => {
+                for (f, v) in fields.iter_mut().zip(vs) {
+                    f.push(v)?;
+                }
+            }
+            (Vals::Any(sel, vars), Val::Any(k, v)) => {
+                sel.push(k as i64);
+                vars[k].push(*v)?;
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+}
+
+impl Vals {
+    // The given rows, in the given order.
+    pub fn take(&self, rows: &[usize]) -> Result<Vals> {
+        let n = self.len();
+        if let Some(bad) = rows.iter().find(|r| **r >= n) {
+            return Err(err(format!("row {} is out of range for {}", bad, n)));
+        }
+        Ok(match self {
+            Vals::I64s(v) => Vals::I64s(rows.iter().map(|r| v[*r]).collect()),
+            Vals::F64s(v) => Vals::F64s(rows.iter().map(|r| v[*r]).collect()),
+            Vals::Bits(_, bs) => Vals::Bits(
+                rows.len(),
+                (0..rows.len()).filter(|i| bs.contains(rows[*i])).collect(),
+            ),
+            Vals::Bins(v) => Vals::Bins(rows.iter().map(|r| v[*r].clone()).collect()),
+            Vals::Rich(col) => Vals::Rich(Box::new(Col {
+                name: col.name.clone(),
+                form: col.form,
+                unit: col.unit,
+                vals: col.vals.take(rows)?,
+            })),
+            Vals::All(fields) => {
+                Vals::All(fields.iter().map(|f| f.take(rows)).collect::<Result<_>>()?)
+            }
+            Vals::Any(..) => {
+                let all = self.to_rows();
+                if all.len() != n {
+                    return Err(err("cannot take rows of a malformed any"));
+                }
+                Vals::from_rows(self, rows.iter().map(|r| all[*r].clone()))?
+            }
+            Vals::Opt(ns, vals) => {
+                let nulls: Vec<usize> = (0..rows.len()).filter(|i| ns.contains(rows[*i])).collect();
+                with_nulls(vals.take(rows)?, &nulls)
+            }
+        })
+    }
+}
+
+fn kind(v: &Vals) -> &'static str {
+    match v {
+        Vals::I64s(_) => "an int",
+        Vals::F64s(_) => "a flo",
+        Vals::Bits(..) => "a bit",
+        Vals::Bins(_) => "a bin",
+        Vals::Rich(col) => kind(&col.vals),
+        Vals::All(_) => "a record",
+        Vals::Any(..) => "a union",
+        Vals::Opt(_, vals) => kind(vals),
+    }
+}
+
+// A column of no rows with the same type, form and unit as v. Nothing
+// about a column's nulls is part of its type.
+pub(crate) fn empty_like(v: &Vals) -> Vals {
+    match v {
+        Vals::I64s(_) => Vals::I64s(Vec::new()),
+        Vals::F64s(_) => Vals::F64s(Vec::new()),
+        Vals::Bits(..) => Vals::Bits(0, bs::Bs::new()),
+        Vals::Bins(_) => Vals::Bins(Vec::new()),
+        Vals::Rich(col) => Vals::Rich(Box::new(Col {
+            name: col.name.clone(),
+            form: col.form,
+            unit: col.unit,
+            vals: empty_like(&col.vals),
+        })),
+        Vals::All(fields) => Vals::All(fields.iter().map(empty_like).collect()),
+        Vals::Any(_, vars) => Vals::Any(Vec::new(), vars.iter().map(empty_like).collect()),
+        Vals::Opt(_, vals) => empty_like(vals),
+    }
+}
+
+impl Tab {
+    // Columns must have distinct names and equal lengths.
+    pub fn new(cols: Vec<Col>) -> Result<Tab> {
+        for (i, col) in cols.iter().enumerate() {
+            if cols[..i].iter().any(|c| c.name == col.name) {
+                return Err(err(format!("duplicate column {}", col.name)));
+            }
+            if col.vals.len() != cols[0].vals.len() {
+                return Err(err(format!(
+                    "column {} has {} rows, but {} has {}",
+                    col.name,
+                    col.vals.len(),
+                    cols[0].name,
+                    cols[0].vals.len()
+                )));
+            }
+        }
+        Ok(Tab { cols })
+    }
+
+    // The values at a path, Rich if they have a form or unit.
+    pub(crate) fn load(&self, path: &Path) -> Result<Vals> {
+        let c = self.resolve(path)?;
+        Ok(enrich(Some(&c.name), c.form, c.unit, c.vals.clone()))
+    }
+
+    // Each column's name and values, as load() gives them.
+    pub(crate) fn named(&self) -> Vec<(Word, Vals)> {
+        let cols = self.cols.iter();
+        cols.map(|c| {
+            (
+                c.name.clone(),
+                enrich(Some(&c.name), c.form, c.unit, c.vals.clone()),
+            )
+        })
+        .collect()
+    }
+
+    // The inverse of named().
+    pub(crate) fn from_named(cols: Vec<(Word, Vals)>) -> Result<Tab> {
+        let cols = cols.into_iter().map(|(name, v)| match v {
+            Vals::Rich(c) => Col::new(name, c.vals).with_form(c.form).with_unit(c.unit),
+            v => Col::new(name, v),
+        });
+        Tab::new(cols.collect())
+    }
+
+    pub fn cols(&self) -> &[Col] {
+        &self.cols
+    }
+
+    // The number of rows.
+    pub fn len(&self) -> usize {
+        self.cols.first().map_or(0, |c| c.vals.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // One row, with a value per column.
+    pub fn row(&self, row: usize) -> Option<Vec<Val>> {
+        self.cols.iter().map(|c| c.vals.get(row)).collect()
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = Vec<Val>> {
+        let mut cols: Vec<_> = self
+            .cols
+            .iter()
+            .map(|c| c.vals.to_rows().into_iter())
+            .collect();
+        (0..self.len()).map_while(move |_| cols.iter_mut().map(|c| c.next()).collect())
+    }
+
+    // Append a row, with a value per column. Nothing changes unless every
+    // value fits its column.
+    pub fn push_row(&mut self, row: Vec<Val>) -> Result<()> {
+        if row.len() != self.cols.len() {
+            return Err(err(format!(
+                "row has {} values for {} columns",
+                row.len(),
+                self.cols.len()
+            )));
+        }
+        for (c, v) in self.cols.iter().zip(&row) {
+            if !c.vals.accepts(v) {
+                return Err(err(format!(
+                    "row value {:?} doesn't fit {} column {}",
+                    v,
+                    kind(&c.vals),
+                    c.name
+                )));
+            }
+        }
+        for (c, v) in self.cols.iter_mut().zip(row) {
+            c.vals.push(v)?;
+        }
+        Ok(())
+    }
+}
+
+// Builds a Tab a column at a time, eg.
+//
+//   TabBuilder::new().col("x").i64s([1, 2]).col("y").f64s([0.5, 1.5]).build()
+//
+// Bad names and mismatched lengths are reported by build().
+#[derive(Clone, Debug, Default)]
+pub struct TabBuilder {
+    cols: Vec<(String, Form, Unit, Vals)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ColBuilder {
+    tab: TabBuilder,
+    name: String,
+    form: Form,
+    unit: Unit,
+}
+
+impl TabBuilder {
+    pub fn new() -> TabBuilder {
+        TabBuilder::default()
+    }
+
+    pub fn col(self, name: &str) -> ColBuilder {
+        ColBuilder {
+            tab: self,
+            name: name.to_string(),
+            form: Form::NONE,
+            unit: Unit::NONE,
+        }
+    }
+
+    pub fn build(self) -> Result<Tab> {
+        let mut cols = Vec::with_capacity(self.cols.len());
+        for (name, form, unit, vals) in self.cols {
+            let Some(name) = Word::new(&name) else {
+                return Err(err(format!("invalid column name {:?}", name)));
+            };
+            cols.push(Col::new(name, vals).with_form(form).with_unit(unit));
+        }
+        Tab::new(cols)
+    }
+}
+
+impl ColBuilder {
+    pub fn form(mut self, form: Form) -> ColBuilder {
+        self.form = form;
+        self
+    }
+    pub fn unit(mut self, unit: Unit) -> ColBuilder {
+        self.unit = unit;
+        self
+    }
+    pub fn vals(mut self, vals: Vals) -> TabBuilder {
+        self.tab.cols.push((self.name, self.form, self.unit, vals));
+        self.tab
+    }
+    pub fn i64s(self, v: impl IntoIterator<Item = i64>) -> TabBuilder {
+        self.vals(Vals::I64s(v.into_iter().collect()))
+    }
+    pub fn f64s(self, v: impl IntoIterator<Item = f64>) -> TabBuilder {
+        self.vals(Vals::F64s(v.into_iter().map(OrderedFloat).collect()))
+    }
+    pub fn bits(self, v: impl IntoIterator<Item = bool>) -> TabBuilder {
+        let mut vals = Vals::Bits(0, bs::Bs::new());
+        for b in v {
+            vals.push(Val::Bit(b)).expect("bit columns take bits");
+        }
+        self.vals(vals)
+    }
+    pub fn bins<B: AsRef<[u8]>>(self, v: impl IntoIterator<Item = B>) -> TabBuilder {
+        let bins = v.into_iter().map(|b| Bin::Mem(b.as_ref().to_vec()));
+        self.vals(Vals::Bins(bins.collect()))
+    }
+}
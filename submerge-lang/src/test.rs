@@ -0,0 +1,3735 @@
+use crate::{
+    check, check_fo, check_fo_with, parse, parse_partial, AggOp, Aggregate, Bin, BinFn, BinHeap,
+    BitFn, Build, Call, Cast, Col, Decimal, Diagnostic, Expr, Fault, FaultKind, FloatMode,
+    FoErrorKind, Form, Grouping, Insn, Interner, Join, JoinKind, Major, Opcode, Operand, Overflow,
+    Partial, Path, PrimBinOp, PrimUnOp, Program, SortKey, Span, Tab, TabBuilder, TimeFn, Ty, TyEnv,
+    TypeErrorKind, Udfs, Unit, Val, Vals, Vm, Word,
+};
+use test_log::test;
+
+fn word(s: &str) -> Word {
+    Word::new(s).unwrap()
+}
+
+#[test]
+fn test_words() {
+    assert_eq!(word("x_1").as_str(), Some("x_1"));
+    assert_eq!(word("_tmp").as_str(), Some("_tmp"));
+    assert!(Word::new("").is_none());
+    assert!(Word::new("1x").is_none());
+    assert!(Word::new("a-b").is_none());
+}
+
+#[test]
+fn test_expr_serde_roundtrip() {
+    // let x = [1, 2] in if x < t.y then {a: x} else reify
+    let e = Expr::let_(
+        word("x"),
+        Expr::lit(Vals::I64s(vec![1, 2])),
+        Expr::if_(
+            Expr::binop(
+                PrimBinOp::Lt,
+                Expr::var(word("x")),
+                Expr::Path(Path(vec![word("t"), word("y")])),
+            ),
+            Expr::Rec(vec![(word("a"), Expr::var(word("x")))]),
+            Expr::merge(Expr::Reify, Expr::Pass),
+        ),
+    );
+    let bytes = rmp_serde::to_vec(&e).unwrap();
+    let back: Expr = rmp_serde::from_slice(&bytes).unwrap();
+    assert_eq!(e, back);
+    assert_eq!(e.children().len(), 2);
+}
+
+fn int(n: i64) -> Expr {
+    Expr::lit(Vals::I64s(vec![n]))
+}
+
+#[test]
+fn test_parse_precedence() {
+    let e: Expr = "1 + 2 * 3 ** 2 ** 2 < x.y | !b".parse().unwrap();
+    let pow = Expr::binop(
+        PrimBinOp::Pow,
+        int(3),
+        Expr::binop(PrimBinOp::Pow, int(2), int(2)),
+    );
+    let sum = Expr::binop(
+        PrimBinOp::Add,
+        int(1),
+        Expr::binop(PrimBinOp::Mul, int(2), pow),
+    );
+    let path = Expr::Path(Path(vec![word("x"), word("y")]));
+    let cmp = Expr::binop(PrimBinOp::Lt, sum, path);
+    let not = Expr::unop(PrimUnOp::Not, Expr::var(word("b")));
+    assert_eq!(e, Expr::binop(PrimBinOp::Or, cmp, not));
+
+    let e: Expr = "a - -9223372036854775808 - -(1)".parse().unwrap();
+    let lhs = Expr::binop(PrimBinOp::Sub, Expr::var(word("a")), int(i64::MIN));
+    let rhs = Expr::unop(PrimUnOp::Neg, int(1));
+    assert_eq!(e, Expr::binop(PrimBinOp::Sub, lhs, rhs));
+
+    // And binds tighter than Or and looser than comparisons.
+    let e: Expr = "a | b & c < 1".parse().unwrap();
+    let lt = Expr::binop(PrimBinOp::Lt, Expr::var(word("c")), int(1));
+    let and = Expr::binop(PrimBinOp::And, Expr::var(word("b")), lt);
+    assert_eq!(e, Expr::binop(PrimBinOp::Or, Expr::var(word("a")), and));
+}
+
+#[test]
+fn test_parse_forms() {
+    let src = r#"
+        // a comment
+        let f = fn(x, y) if x <= y then min(x, y) else sqrt(1.5e1) in
+        merge({a: f(1, 2), b: ["p", "q\x21"]}, query(reify, t.c))
+    "#;
+    let e: Expr = src.parse().unwrap();
+    let Expr::Let(name, val, body) = e else {
+        panic!("expected let")
+    };
+    assert_eq!(name.as_str(), Some("f"));
+    let x = || Expr::var(word("x"));
+    let y = || Expr::var(word("y"));
+    let lam = Expr::lam(
+        vec![word("x"), word("y")],
+        Expr::if_(
+            Expr::binop(PrimBinOp::Le, x(), y()),
+            Expr::binop(PrimBinOp::Min, x(), y()),
+            Expr::unop(PrimUnOp::Sqrt, Expr::lit(Vals::F64s(vec![15.0.into()]))),
+        ),
+    );
+    assert_eq!(*val, lam);
+    let bins = vec![Bin::Mem(b"p".to_vec()), Bin::Mem(b"q!".to_vec())];
+    let rec = Expr::Rec(vec![
+        (
+            word("a"),
+            Expr::app(Expr::var(word("f")), vec![int(1), int(2)]),
+        ),
+        (word("b"), Expr::lit(Vals::Bins(bins))),
+    ]);
+    let query = Expr::query(Expr::Reify, Path(vec![word("t"), word("c")]));
+    assert_eq!(*body, Expr::merge(rec, query));
+
+    let e: Expr = "tab{k: [1, 2], v: [true, false, true], w: f64[]}"
+        .parse()
+        .unwrap();
+    let Expr::Tab(cols) = e else {
+        panic!("expected tab")
+    };
+    assert_eq!(cols[0].1, Expr::lit(Vals::I64s(vec![1, 2])));
+    let bits = [0, 2].into_iter().collect();
+    assert_eq!(cols[1].1, Expr::lit(Vals::Bits(3, bits)));
+    assert_eq!(cols[2].1, Expr::lit(Vals::F64s(vec![])));
+}
+
+#[test]
+fn test_parse_spans() {
+    let src = "let x = 10 in (x + 2)";
+    let p = parse(src).unwrap();
+    let spans = p.spans.preorder();
+    let text: Vec<&str> = spans.iter().map(|s| &src[s.lo..s.hi]).collect();
+    assert_eq!(text, vec![src, "10", "(x + 2)", "x", "2"]);
+}
+
+#[test]
+fn test_parse_errors() {
+    let cases = [
+        ("1 +", Span::new(3, 3), true),
+        ("let in = 1 in 2", Span::new(4, 6), false),
+        ("[1, 2.0]", Span::new(4, 7), false),
+        ("[]", Span::new(0, 2), false),
+        ("99999999999999999999", Span::new(0, 20), false),
+        ("\"abc", Span::new(0, 4), true),
+        ("sqrt(1, 2)", Span::new(0, 10), false),
+        ("a $ b", Span::new(2, 3), false),
+        ("(1", Span::new(2, 2), true),
+        ("convert(x, \"1000^2147483647\")", Span::new(11, 28), false),
+    ];
+    for (src, span, incomplete) in cases {
+        let e = parse(src).unwrap_err();
+        assert_eq!(e.span, span, "{}: {}", src, e);
+        assert_eq!(e.incomplete, incomplete, "{}: {}", src, e);
+    }
+}
+
+#[test]
+fn test_parse_depth() {
+    let nest =
+        |open: &str, close: &str, n: usize| format!("{}1{}", open.repeat(n), close.repeat(n));
+    assert!(parse(&nest("(", ")", 127)).is_ok());
+    assert!(parse(&nest("-", "", 100)).is_ok());
+    assert!(parse(&nest("1 + ", "", 100)).is_ok());
+    for src in [
+        nest("(", ")", 2000),
+        nest("-", "", 2000),
+        nest("!", "", 2000),
+        nest("1 ** ", "", 2000),
+        nest("1 + ", "", 2000),
+        format!("f{}", "()".repeat(2000)),
+        nest("f(", ")", 2000),
+        nest("let x = 1 in ", "", 2000),
+    ] {
+        let e = parse(&src).unwrap_err();
+        assert!(e.msg.contains("nested too deeply"), "{}", e);
+        assert!(!e.incomplete);
+    }
+}
+
+#[test]
+fn test_parse_partial() {
+    let incomplete = [
+        "",
+        "  // nothing yet\n",
+        "let x = 1",
+        "let x = 1 in\n",
+        "f(1,\n  2",
+        "{a: 1,",
+        "if a then b",
+        "merge(reify",
+        "\"ab\\",
+        "\"\\x4",
+        "cast(x, dec(9,",
+        "1 **",
+    ];
+    for src in incomplete {
+        assert!(
+            matches!(parse_partial(src), Partial::Incomplete),
+            "{:?}",
+            src
+        );
+    }
+    let invalid = ["1 2", "(1))", "a $", "merge(a)", "\"\\q", "let in"];
+    for src in invalid {
+        assert!(
+            matches!(parse_partial(src), Partial::Invalid(_)),
+            "{:?}",
+            src
+        );
+    }
+
+    // A REPL feeds it each line in turn until it completes.
+    let mut buf = String::new();
+    let mut states = Vec::new();
+    for line in ["let f = fn(x)", "  x + 1", "in f(2)"] {
+        buf.push_str(line);
+        buf.push('\n');
+        states.push(match parse_partial(&buf) {
+            Partial::Complete(p) => Some(p.expr),
+            _ => None,
+        });
+    }
+    assert_eq!(states[..2], [None, None]);
+    assert_eq!(
+        states[2],
+        Some(parse("let f = fn(x) x + 1 in f(2)").unwrap().expr)
+    );
+}
+
+#[test]
+fn test_print_roundtrip() {
+    let srcs = [
+        "let f = fn(x, y) if x <= y then min(x, y) else sqrt(15.0) in f(1, 2)",
+        "1 + 2 * 3 ** 2 ** 2 < x.y | !b",
+        "(1 + 2) * 3 - (4 - 5)",
+        "(2 ** 3) ** 4",
+        "a - -9223372036854775808 - -(1)",
+        "-x ** 2 + (-1)(2)",
+        "(let x = 1 in x) + 1",
+        "f(if a then b else c)(let y = 2 in y)",
+        "merge({a: [1, 2], b: [\"p\", \"q\\x00\\\"\"]}, query(reify, t.c))",
+        "tab{k: [true, false], v: f64[], w: [nan, -inf, 1e300, -0.5]}",
+        "{}",
+        "pass",
+        "group(t, [k, r.j], {n: count(v), top: max(r.v)})",
+        "group(t, [], {})",
+        "join(a, b, [k = k, r.j = id])",
+        "left_join(a, tab{k: 1}, [])",
+        "sort(t, [k, r.v desc])",
+        "sort(t, [])",
+        "filter(t, k > 1 | r.v == x)",
+        "len(concat(s, \"!\")) + 1",
+        "add_months(date(t.ts), 2) - day",
+        "like(lower(slice(s, 1, n)), \"a\\\\%%\")",
+        "`sqrt`(x) + `min`(x, y) + sqrt + min.x",
+        "`let`.`filter` + `true` + t.`sort`",
+        "let `in` = fn(`if`) `if` in `in`(``)",
+        "{`tab`: 1, x: `query`}",
+    ];
+    for src in srcs {
+        let e: Expr = src.parse().unwrap();
+        let printed = e.to_string();
+        assert_eq!(printed, src);
+        assert_eq!(printed.parse::<Expr>().unwrap(), e);
+    }
+
+    // Structurally-built trees print with whatever parens they need.
+    let e = Expr::binop(
+        PrimBinOp::Mul,
+        Expr::binop(PrimBinOp::Add, int(1), Expr::unop(PrimUnOp::Neg, int(2))),
+        Expr::let_(word("z"), int(3), Expr::var(word("z"))),
+    );
+    assert_eq!(e.to_string(), "(1 + -(2)) * (let z = 3 in z)");
+    assert_eq!(e.to_string().parse::<Expr>().unwrap(), e);
+
+    // Applications of names that are builtins aren't the builtins, and
+    // keywords in paths, or no words at all, still print as paths.
+    let x = Expr::var(word("x"));
+    let cases = [
+        (
+            Expr::app(Expr::var(word("sqrt")), vec![x.clone()]),
+            "`sqrt`(x)",
+        ),
+        (
+            Expr::app(Expr::var(word("min")), vec![x.clone(), x.clone()]),
+            "`min`(x, x)",
+        ),
+        (Expr::Path(path("let.filter")), "`let`.`filter`"),
+        (Expr::Path(Path(vec![])), "``"),
+        (Expr::app(Expr::Path(path("min.x")), vec![x]), "min.x(x)"),
+    ];
+    for (e, printed) in cases {
+        assert_eq!(e.to_string(), printed);
+        assert_eq!(printed.parse::<Expr>().unwrap(), e);
+    }
+    assert!("`".parse::<Expr>().is_err());
+    assert!("`1x`".parse::<Expr>().is_err());
+    assert!("``.x".parse::<Expr>().is_err());
+}
+
+fn dec(precision: u8, scale: u8) -> Form {
+    Form::decimal(precision, scale).unwrap()
+}
+
+fn schema() -> TyEnv {
+    let mut env = TyEnv::new();
+    env.insert(path("t.n"), Ty::int());
+    env.insert(path("t.x"), Ty::flo());
+    env.insert(path("t.name"), Ty::bin());
+    env.insert(path("t.len"), Ty::flo().with_role(Unit(1)));
+    env.insert(path("t.dur"), Ty::flo().with_role(Unit(2)));
+    env.insert(path("t.price"), Ty::int().with_minor(dec(9, 2)));
+    env.insert(path("t.rate"), Ty::int().with_minor(dec(5, 4)));
+    env.insert(path("t.ts"), Ty::int().with_minor(Form::TIMESTAMP));
+    env.insert(path("t.day"), Ty::int().with_minor(Form::DATE));
+    env
+}
+
+fn check_src(src: &str) -> Result<Ty, TypeErrorKind> {
+    let p = parse(src).unwrap();
+    match check(&p.expr, &schema(), Some(&p.spans)) {
+        Ok(typing) => {
+            assert_eq!(typing.tys.len(), p.spans.preorder().len());
+            Ok(typing.root().clone())
+        }
+        Err(e) => Err(*e.kind),
+    }
+}
+
+#[test]
+fn test_check_well_typed() {
+    assert_eq!(check_src("t.n + 1"), Ok(Ty::int()));
+    assert_eq!(check_src("t.x < 2.5 | !(t.n == 3)"), Ok(Ty::bit()));
+    assert_eq!(check_src("t.len + 1.0"), Ok(Ty::flo().with_role(Unit(1))));
+    assert_eq!(
+        check_src("let f = fn(a, b) if a < b then b else a in f(t.n, 4)"),
+        Ok(Ty::int())
+    );
+    assert_eq!(
+        check_src("let r = {a: t.n, b: t.name} in r.b"),
+        Ok(Ty::bin())
+    );
+    assert_eq!(check_src("merge(reify, reify)"), Ok(Ty::new(Major::Env)));
+    assert_eq!(check_src("query(reify, t.x) * 2.0"), Ok(Ty::flo()));
+    let rec = check_src("merge({a: 1, b: 2}, {b: 1.5, c: true})").unwrap();
+    assert_eq!(rec.to_string(), "{a: int, b: flo, c: bit}");
+    let nested = check_src("merge({p: {x: 1, y: 2}}, {p: {y: true}})").unwrap();
+    assert_eq!(nested.to_string(), "{p: {x: int, y: bit}}");
+    let f = check_src("fn(s) sqrt(s) + t.x").unwrap();
+    assert_eq!(f.to_string(), "fn(flo) -> flo");
+}
+
+#[test]
+fn test_check_errors() {
+    let mismatch = |expected, found| Err(TypeErrorKind::Mismatch { expected, found });
+    assert_eq!(check_src("t.n + 1.0"), mismatch(Ty::int(), Ty::flo()));
+    assert_eq!(
+        check_src("if t.n then 1 else 2"),
+        mismatch(Ty::bit(), Ty::int())
+    );
+    assert!(matches!(
+        check_src("t.name + t.name"),
+        Err(TypeErrorKind::NotA { .. })
+    ));
+    assert!(matches!(
+        check_src("sqrt(t.n)"),
+        Err(TypeErrorKind::Mismatch { .. })
+    ));
+    assert!(matches!(
+        check_src("t.len + t.dur"),
+        Err(TypeErrorKind::Mismatch { .. })
+    ));
+    assert!(matches!(check_src("u.v"), Err(TypeErrorKind::Unbound(_))));
+    assert!(matches!(
+        check_src("(fn(a) a)(1, 2)"),
+        Err(TypeErrorKind::Arity {
+            expected: 1,
+            found: 2
+        })
+    ));
+    assert!(matches!(
+        check_src("fn(a) a"),
+        Err(TypeErrorKind::Ambiguous(_))
+    ));
+    assert!(matches!(
+        check_src("fn(a, b) a + b"),
+        Err(TypeErrorKind::Ambiguous(_))
+    ));
+    assert!(matches!(
+        check_src("{a: 1, a: 2}"),
+        Err(TypeErrorKind::DuplicateField(_))
+    ));
+    assert!(matches!(
+        check_src("let r = {a: 1} in r.b"),
+        Err(TypeErrorKind::NoField { .. })
+    ));
+
+    // Errors point at the offending subexpression.
+    let src = "let y = 1 in y * (t.x - 2.0)";
+    let p = parse(src).unwrap();
+    let e = check(&p.expr, &schema(), Some(&p.spans)).unwrap_err();
+    let span = e.span.unwrap();
+    assert_eq!(&src[span.lo..span.hi], "(t.x - 2.0)");
+    assert_eq!(
+        e.to_string(),
+        "type error at 17..28: expected int, found flo"
+    );
+}
+
+fn col(name: &str, vals: Vals) -> Col {
+    Col {
+        name: word(name),
+        form: Form::NONE,
+        unit: Unit::NONE,
+        vals,
+    }
+}
+
+fn flo(v: &[f64]) -> Vals {
+    Vals::F64s(v.iter().map(|x| (*x).into()).collect())
+}
+
+#[test]
+fn test_vm_step_and_run() {
+    // (x * 2 + y) < 10, over a 3-row context.
+    let ctx = Tab {
+        cols: vec![
+            col("x", Vals::I64s(vec![1, 2, 3])),
+            col("y", Vals::I64s(vec![4, 5, 6])),
+        ],
+    };
+    let ops = vec![
+        Opcode::Path(Path(vec![word("x")])),
+        Opcode::Literal(Vals::I64s(vec![2])),
+        Opcode::PrimBinOp(PrimBinOp::Mul),
+        Opcode::Path(Path(vec![word("y")])),
+        Opcode::PrimBinOp(PrimBinOp::Add),
+        Opcode::Literal(Vals::I64s(vec![10])),
+        Opcode::PrimBinOp(PrimBinOp::Lt),
+    ];
+    let mut vm = Vm::new(ops, vec![ctx]);
+    assert_eq!(vm.step(3).unwrap(), 3);
+    assert!(!vm.is_done());
+    assert_eq!(vm.result(), None);
+    assert_eq!(vm.step(3).unwrap(), 3);
+    assert_eq!(vm.step(3).unwrap(), 1);
+    assert!(vm.is_done());
+    assert_eq!(vm.step(3).unwrap(), 0);
+    let expect = Vals::Bits(3, [0, 1].into_iter().collect());
+    assert_eq!(vm.result(), Some(&expect));
+
+    let mut again = Vm::new(vm.ops.clone(), vm.stack[0].ctx.clone());
+    assert_eq!(again.run().unwrap(), expect);
+}
+
+#[test]
+fn test_vm_prim_ops() {
+    let run = |ops: Vec<Opcode>| Vm::new(ops, vec![]).run();
+    let lit = |v: Vals| Opcode::Literal(v);
+    let bin = |a: Vals, op, b: Vals| run(vec![lit(a), lit(b), Opcode::PrimBinOp(op)]);
+    let un = |op, a: Vals| run(vec![lit(a), Opcode::PrimUnOp(op)]);
+
+    let ints = |v: &[i64]| Vals::I64s(v.to_vec());
+    assert_eq!(
+        bin(ints(&[i64::MAX]), PrimBinOp::Add, ints(&[1])).unwrap(),
+        ints(&[i64::MIN])
+    );
+    assert_eq!(
+        bin(ints(&[7, -7]), PrimBinOp::Mod, ints(&[3])).unwrap(),
+        ints(&[1, -1])
+    );
+    assert_eq!(
+        bin(ints(&[1, 5]), PrimBinOp::Cmp, ints(&[3])).unwrap(),
+        ints(&[-1, 1])
+    );
+    assert_eq!(
+        bin(ints(&[-8]), PrimBinOp::Asr, ints(&[1])).unwrap(),
+        ints(&[-4])
+    );
+    assert_eq!(
+        bin(ints(&[-8]), PrimBinOp::Shr, ints(&[60])).unwrap(),
+        ints(&[15])
+    );
+    assert_eq!(
+        bin(ints(&[3]), PrimBinOp::Pow, ints(&[4])).unwrap(),
+        ints(&[81])
+    );
+    assert!(bin(ints(&[1]), PrimBinOp::Div, ints(&[0])).is_err());
+    assert!(bin(ints(&[1, 2]), PrimBinOp::Add, ints(&[1, 2, 3])).is_err());
+    assert!(bin(ints(&[1]), PrimBinOp::Add, flo(&[1.0])).is_err());
+
+    assert_eq!(
+        bin(flo(&[1.5]), PrimBinOp::Mul, flo(&[2.0, 4.0])).unwrap(),
+        flo(&[3.0, 6.0])
+    );
+    let nan_ne = bin(flo(&[f64::NAN]), PrimBinOp::Ne, flo(&[f64::NAN])).unwrap();
+    assert_eq!(nan_ne, Vals::Bits(1, [0].into_iter().collect()));
+    assert!(bin(flo(&[1.0]), PrimBinOp::Shl, flo(&[1.0])).is_err());
+
+    assert_eq!(
+        un(PrimUnOp::Popcnt, ints(&[0xff, -1])).unwrap(),
+        ints(&[8, 64])
+    );
+    assert_eq!(
+        un(PrimUnOp::Neg, ints(&[i64::MIN])).unwrap(),
+        ints(&[i64::MIN])
+    );
+    assert_eq!(un(PrimUnOp::Sqrt, flo(&[9.0])).unwrap(), flo(&[3.0]));
+    assert_eq!(un(PrimUnOp::Exp10, flo(&[2.0])).unwrap(), flo(&[100.0]));
+    assert!(un(PrimUnOp::Sqrt, ints(&[9])).is_err());
+
+    // Stack underflow and unimplemented opcodes are errors, not panics.
+    assert!(run(vec![Opcode::PrimBinOp(PrimBinOp::Add)]).is_err());
+    assert!(run(vec![Opcode::Reify, Opcode::Eval]).is_err());
+}
+
+#[test]
+fn test_float_determinism() {
+    // Golden bits, which every platform must give under either mode.
+    let run = |mode, ops: Vec<Opcode>| Vm::new(ops, vec![]).with_float_mode(mode).eval();
+    let bits = |mode, ops: Vec<Opcode>| match run(mode, ops).unwrap().unwrap() {
+        Vals::F64s(v) => v.iter().map(|f| f.to_bits()).collect::<Vec<_>>(),
+        v => panic!("{:?}", v),
+    };
+    let lit = |v: &[f64]| Opcode::Literal(flo(v));
+    let bin = |a: &[f64], op, b: &[f64]| vec![lit(a), lit(b), Opcode::PrimBinOp(op)];
+    let un = |op, a: &[f64]| vec![lit(a), Opcode::PrimUnOp(op)];
+    let golden = [
+        (bin(&[0.1], PrimBinOp::Add, &[0.2]), 0x3fd3_3333_3333_3334),
+        (bin(&[1.0], PrimBinOp::Div, &[3.0]), 0x3fd5_5555_5555_5555),
+        (bin(&[5.5], PrimBinOp::Mod, &[2.0]), 0x3ff8_0000_0000_0000),
+        (un(PrimUnOp::Sqrt, &[2.0]), 0x3ff6_a09e_667f_3bcd),
+        (un(PrimUnOp::Recip, &[3.0]), 0x3fd5_5555_5555_5555),
+        // A sum is compensated, in row order: ten 0.1s are exactly 1.
+        (
+            vec![lit(&[0.1; 10]), Opcode::Agg(AggOp::Sum)],
+            0x3ff0_0000_0000_0000,
+        ),
+        (
+            vec![lit(&[0.1, 0.2, 0.4]), Opcode::Agg(AggOp::Mean)],
+            0x3fcd_dddd_dddd_dddf,
+        ),
+        // Zeros order by sign.
+        (bin(&[0.0], PrimBinOp::Min, &[-0.0]), 0x8000_0000_0000_0000),
+        (bin(&[-0.0], PrimBinOp::Max, &[0.0]), 0),
+    ];
+    for mode in [FloatMode::Native, FloatMode::Deterministic] {
+        for (ops, want) in &golden {
+            assert_eq!(bits(mode, ops.clone()), vec![*want], "{:?}", ops);
+        }
+    }
+
+    // Deterministic NaNs all have f64::NAN's bits, whatever the hardware
+    // makes, and keep them through a Case or Filter.
+    let nans = [
+        bin(&[0.0], PrimBinOp::Div, &[0.0]),
+        bin(&[f64::NEG_INFINITY], PrimBinOp::Add, &[f64::INFINITY]),
+        un(PrimUnOp::Sqrt, &[-1.0]),
+        vec![lit(&[f64::from_bits(0xfff8_0000_0000_0001)])],
+    ];
+    for ops in nans {
+        let got = bits(FloatMode::Deterministic, ops);
+        assert_eq!(got, vec![0x7ff8_0000_0000_0000]);
+    }
+
+    // Ops that call the platform's libm fault, before they run, on floats
+    // only.
+    for ops in [
+        un(PrimUnOp::Sin, &[1.0]),
+        un(PrimUnOp::Exp10, &[2.0]),
+        bin(&[2.0], PrimBinOp::Pow, &[0.5]),
+    ] {
+        let f = run(FloatMode::Deterministic, ops.clone()).unwrap();
+        assert_eq!(f.unwrap_err().kind, FaultKind::Unportable);
+        assert!(run(FloatMode::Native, ops).unwrap().is_ok());
+    }
+    let pow = vec![
+        Opcode::Literal(Vals::I64s(vec![3])),
+        Opcode::Literal(Vals::I64s(vec![4])),
+        Opcode::PrimBinOp(PrimBinOp::Pow),
+    ];
+    assert_eq!(
+        run(FloatMode::Deterministic, pow).unwrap(),
+        Ok(Vals::I64s(vec![81]))
+    );
+    let e = parse("filter({x: [1.0, 2.0]}, sin(x) > 0.5)").unwrap().expr;
+    let f = run(FloatMode::Deterministic, e.ops().unwrap()).unwrap();
+    assert_eq!(f.unwrap_err().kind, FaultKind::Unportable);
+}
+
+#[test]
+fn test_insn_pack_roundtrip_exhaustive() {
+    for (i, op) in PrimBinOp::ALL.iter().enumerate() {
+        assert_eq!(*op as usize, i);
+    }
+    // Ops keep their codes as new ones are added.
+    assert_eq!(PrimBinOp::Ror as usize, 21);
+    assert_eq!(PrimBinOp::And as usize, 22);
+    for (i, op) in PrimUnOp::ALL.iter().enumerate() {
+        assert_eq!(*op as usize, i);
+    }
+    for (i, f) in BinFn::ALL.iter().enumerate() {
+        assert_eq!(*f as usize, i);
+    }
+    for (i, f) in TimeFn::ALL.iter().enumerate() {
+        assert_eq!(*f as usize, i);
+    }
+    for (i, f) in BitFn::ALL.iter().enumerate() {
+        assert_eq!(*f as usize, i);
+    }
+    let pool = Program {
+        insns: vec![],
+        lits: vec![Vals::I64s(vec![1]), Vals::I64s(vec![2, 3])],
+        paths: vec![Path(vec![word("p")])],
+        units: vec![],
+        casts: vec![],
+        cases: vec![],
+        groupings: vec![],
+        joins: vec![],
+        sorts: vec![],
+        filters: vec![],
+        quotes: vec![],
+        calls: vec![],
+        scalar_regs: 0,
+        vector_regs: 0,
+    };
+    let mut binary: Vec<Opcode> = PrimBinOp::ALL
+        .iter()
+        .map(|op| Opcode::PrimBinOp(*op))
+        .collect();
+    binary.extend([Opcode::Merge, Opcode::Eval]);
+    binary.extend(BinFn::ALL.iter().map(|f| Opcode::BinFn(*f)));
+    binary.extend(TimeFn::ALL.iter().map(|f| Opcode::TimeFn(*f)));
+    binary.extend(BitFn::ALL.iter().map(|f| Opcode::BitFn(*f)));
+    let mut unary: Vec<Opcode> = PrimUnOp::ALL
+        .iter()
+        .map(|op| Opcode::PrimUnOp(*op))
+        .collect();
+    unary.push(Opcode::Reify);
+    unary.extend(AggOp::ALL.iter().map(|op| Opcode::Agg(*op)));
+
+    let idxs = [0_u16, 1, 0x7fff, 0xffff];
+    let operands: Vec<Operand> = idxs
+        .iter()
+        .flat_map(|i| {
+            [false, true].into_iter().flat_map(move |lit| {
+                [false, true].map(|vector| Operand {
+                    idx: *i,
+                    lit,
+                    vector,
+                })
+            })
+        })
+        .collect();
+    let roundtrip = |insn: Insn| {
+        let back = Insn::unpack(insn.pack(), &pool).unwrap();
+        assert_eq!(back, insn);
+    };
+    let mut count = 0;
+    for op in &binary {
+        for a in &operands {
+            for b in &operands {
+                for c in &operands {
+                    let (a, b, c) = (*a, *b, *c);
+                    roundtrip(Insn {
+                        op: op.clone(),
+                        a,
+                        b,
+                        c,
+                    });
+                    count += 1;
+                }
+            }
+        }
+    }
+    for op in &unary {
+        for a in &operands {
+            for b in &operands {
+                let (a, b, c) = (*a, *b, Operand::default());
+                roundtrip(Insn {
+                    op: op.clone(),
+                    a,
+                    b,
+                    c,
+                });
+                count += 1;
+            }
+        }
+    }
+    for (op, i) in [
+        (Opcode::Literal(pool.lits[0].clone()), 0),
+        (Opcode::Literal(pool.lits[1].clone()), 1),
+        (Opcode::Path(pool.paths[0].clone()), 0),
+    ] {
+        for vector in [false, true] {
+            for b in &operands {
+                let a = Operand::lit(i, vector);
+                roundtrip(Insn {
+                    op: op.clone(),
+                    a,
+                    b: *b,
+                    c: Operand::default(),
+                });
+                count += 1;
+            }
+        }
+    }
+    assert_eq!(count, 51 * 16 * 16 * 16 + 40 * 16 * 16 + 3 * 2 * 16);
+
+    // Out-of-range codes and pool indices are rejected.
+    let lit = Insn {
+        op: Opcode::Literal(pool.lits[0].clone()),
+        a: Operand::lit(0, false),
+        b: Operand::reg(0, false),
+        c: Operand::default(),
+    }
+    .pack();
+    assert!(Insn::unpack(lit | (5 << 32), &pool).is_err());
+    assert!(Insn::unpack(lit | 1, &pool).is_err());
+    assert!(Insn::unpack(0x2ff_u64 << 54, &pool).is_err());
+    assert!(Insn::unpack(0xfff_u64 << 52, &pool).is_err());
+}
+
+#[test]
+fn test_program_assemble() {
+    let ctx = Tab {
+        cols: vec![col("x", Vals::I64s(vec![1, 2, 3]))],
+    };
+    let x = || Opcode::Path(Path(vec![word("x")]));
+    let two = || Opcode::Literal(Vals::I64s(vec![2]));
+    let ops = vec![
+        x(),
+        two(),
+        Opcode::PrimBinOp(PrimBinOp::Mul),
+        x(),
+        Opcode::PrimUnOp(PrimUnOp::Neg),
+        two(),
+        Opcode::PrimBinOp(PrimBinOp::Pow),
+        Opcode::PrimBinOp(PrimBinOp::Add),
+    ];
+    let prog = Program::assemble(&ops).unwrap();
+    assert_eq!(prog.insns.len(), ops.len());
+    assert_eq!(prog.lits.len(), 1);
+    assert_eq!(prog.paths.len(), 1);
+
+    let insns = prog.unpack().unwrap();
+    assert_eq!(insns[2].c, Operand::reg(0, true));
+    assert_eq!(insns[6].a, Operand::reg(1, true));
+    assert_eq!(insns[6].b, Operand::reg(2, false));
+
+    let shipped: Program = rmp_serde::from_slice(&rmp_serde::to_vec(&prog).unwrap()).unwrap();
+    let back = shipped.ops().unwrap();
+    assert_eq!(back, ops);
+    let direct = Vm::new(ops, vec![ctx.clone()]).run().unwrap();
+    assert_eq!(Vm::new(back, vec![ctx]).run().unwrap(), direct);
+    assert_eq!(direct, Vals::I64s(vec![3, 8, 15]));
+
+    // Insns that break stack order don't decode to opcodes.
+    let mut broken = prog.clone();
+    broken.insns.swap(0, 2);
+    assert!(broken.ops().is_err());
+    assert!(Program::assemble(&[Opcode::PrimBinOp(PrimBinOp::Add)]).is_err());
+}
+
+fn bin_with(a: Vals, op: PrimBinOp, b: Vals, overflow: Overflow) -> submerge_base::Result<Vals> {
+    let ops = vec![
+        Opcode::Literal(a),
+        Opcode::Literal(b),
+        Opcode::PrimBinOp(op),
+    ];
+    Vm::new(ops, vec![]).with_overflow(overflow).run()
+}
+
+fn bitvals(bits: &[bool]) -> Vals {
+    let set = bits.iter().enumerate().filter(|(_, b)| **b).map(|(i, _)| i);
+    Vals::Bits(bits.len(), set.collect())
+}
+
+#[test]
+fn test_bin_comparison() {
+    let bins = |v: &[&str]| Vals::Bins(v.iter().map(|s| Bin::Mem(s.as_bytes().to_vec())).collect());
+    let t = Tab::new(vec![Col::new(
+        word("t"),
+        Vals::zip(vec![(word("name"), bins(&["ab", "b", "a"]))]).unwrap(),
+    )])
+    .unwrap();
+    let run = |s: &str| {
+        let p = parse(s).unwrap();
+        check(&p.expr, &schema(), Some(&p.spans)).unwrap();
+        Vm::new(p.expr.ops().unwrap(), vec![t.clone()])
+            .run()
+            .unwrap()
+    };
+    // Whatever the checker admits, the kernels can run: bins compare
+    // byte-wise, so a prefix sorts first.
+    assert_eq!(check_src("t.name == \"ab\""), Ok(Ty::bit()));
+    assert_eq!(run("\"ab\" == \"ab\""), bitvals(&[true]));
+    assert_eq!(run("\"ab\" < \"b\""), bitvals(&[true]));
+    assert_eq!(run("t.name >= \"ab\""), bitvals(&[true, true, false]));
+    assert_eq!(run("t.name != \"ab\""), bitvals(&[false, true, true]));
+    assert_eq!(run("cmp(t.name, \"ab\")"), Vals::I64s(vec![0, 1, -1]));
+
+    // Heap bins need their block's heap, which the kernels don't have.
+    let heap = Vals::Bins(vec![Bin::Heap { block: 0, entry: 0 }]);
+    assert!(bin_with(heap, PrimBinOp::Eq, bins(&["ab"]), Overflow::Wrap).is_err());
+}
+
+#[test]
+fn test_kernel_overflow_policy() {
+    use PrimBinOp::*;
+    let ints = |v: &[i64]| Vals::I64s(v.to_vec());
+    let cases: &[(i64, PrimBinOp, i64)] = &[
+        (i64::MAX, Add, 1),
+        (i64::MIN, Sub, 1),
+        (i64::MAX / 2 + 1, Mul, 2),
+        (i64::MIN, Div, -1),
+        (i64::MIN, Mod, -1),
+        (2, Pow, 63),
+        (1, Shl, 63),
+        (-1, Shl, 64),
+        (1, Shr, -1),
+        (1, Asr, 64),
+    ];
+    for (a, op, b) in cases {
+        let (x, y) = (ints(&[1, *a]), ints(&[*b]));
+        assert!(bin_with(x.clone(), *op, y.clone(), Overflow::Wrap).is_ok());
+        assert!(
+            bin_with(x, *op, y, Overflow::Check).is_err(),
+            "{} {:?} {}",
+            a,
+            op,
+            b
+        );
+    }
+    let ok: &[(i64, PrimBinOp, i64, i64)] = &[
+        (i64::MAX - 1, Add, 1, i64::MAX),
+        (-3, Mul, 4, -12),
+        (2, Pow, 62, 1 << 62),
+        (-1, Shl, 63, i64::MIN),
+        (-8, Shr, 60, 15),
+        (-8, Asr, 2, -2),
+        (7, Mod, -2, 1),
+        (5, Rol, 64, 5),
+    ];
+    for (a, op, b, r) in ok {
+        let got = bin_with(ints(&[*a]), *op, ints(&[*b]), Overflow::Check).unwrap();
+        assert_eq!(got, ints(&[*r]), "{} {:?} {}", a, op, b);
+    }
+    assert!(bin_with(ints(&[1]), Div, ints(&[0]), Overflow::Check).is_err());
+}
+
+#[test]
+fn test_kernel_bits() {
+    use PrimBinOp::*;
+    let a = bitvals(&[false, false, true, true]);
+    let b = bitvals(&[false, true, false, true]);
+    let run = |op| bin_with(a.clone(), op, b.clone(), Overflow::Wrap).unwrap();
+    assert_eq!(run(Or), bitvals(&[false, true, true, true]));
+    assert_eq!(run(Max), run(Or));
+    assert_eq!(run(Min), bitvals(&[false, false, false, true]));
+    assert_eq!(run(And), run(Min));
+    assert_eq!(run(Xor), bitvals(&[false, true, true, false]));
+    assert_eq!(run(Eq), bitvals(&[true, false, false, true]));
+    assert_eq!(run(Lt), bitvals(&[false, true, false, false]));
+    assert_eq!(run(Ge), bitvals(&[true, false, true, true]));
+    assert_eq!(run(Cmp), Vals::I64s(vec![0, -1, 1, 0]));
+    assert!(bin_with(a.clone(), Add, b.clone(), Overflow::Wrap).is_err());
+
+    // Scalars broadcast; other length mismatches are errors.
+    let t = bitvals(&[true]);
+    let r = bin_with(a.clone(), Xor, t, Overflow::Wrap).unwrap();
+    assert_eq!(r, bitvals(&[true, true, false, false]));
+    assert!(bin_with(a, Or, bitvals(&[true, false]), Overflow::Wrap).is_err());
+    assert!(bin_with(bitvals(&[]), Or, bitvals(&[]), Overflow::Wrap)
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn test_kernel_unops() {
+    let un = |op, a: Vals| Vm::new(vec![Opcode::Literal(a), Opcode::PrimUnOp(op)], vec![]).run();
+    let ints = |v: &[i64]| Vals::I64s(v.to_vec());
+    // Every op accepts exactly the columns the checker allows it.
+    for op in PrimUnOp::ALL {
+        let src = |arg: &str| format!("{}({})", op.name(), arg);
+        for (arg, vals) in [
+            ("1", ints(&[1])),
+            ("1.0", flo(&[1.0])),
+            ("true", bitvals(&[true])),
+        ] {
+            let typed = check_src(&src(arg)).is_ok();
+            assert_eq!(un(*op, vals).is_ok(), typed, "{}", src(arg));
+        }
+    }
+    let err = un(PrimUnOp::Sin, ints(&[0])).unwrap_err();
+    assert!(err.to_string().contains("type error"), "{}", err);
+
+    assert_eq!(un(PrimUnOp::Clz, ints(&[1])).unwrap(), ints(&[63]));
+    assert_eq!(un(PrimUnOp::Ctz, ints(&[8, 0])).unwrap(), ints(&[3, 64]));
+    assert_eq!(un(PrimUnOp::Floor, flo(&[-1.5])).unwrap(), flo(&[-2.0]));
+    assert_eq!(un(PrimUnOp::Trunc, flo(&[-1.5])).unwrap(), flo(&[-1.0]));
+    assert_eq!(un(PrimUnOp::Log2, flo(&[8.0])).unwrap(), flo(&[3.0]));
+    let b = bitvals(&[true, false, true]);
+    assert_eq!(
+        un(PrimUnOp::Not, b.clone()).unwrap(),
+        bitvals(&[false, true, false])
+    );
+    assert_eq!(un(PrimUnOp::BitCount, b.clone()).unwrap(), ints(&[2]));
+    assert_eq!(un(PrimUnOp::BitParity, b).unwrap(), bitvals(&[false]));
+}
+
+#[test]
+fn test_decimal_form() {
+    let d = Decimal::new(5, 2).unwrap();
+    assert_eq!(Form::from(d).as_decimal(), Some(d));
+    assert_eq!(Form::NONE.as_decimal(), None);
+    assert!(Decimal::new(19, 2).is_none());
+    assert!(Decimal::new(2, 3).is_none());
+    for (v, s) in [
+        (1234, "12.34"),
+        (-5, "-0.05"),
+        (0, "0.00"),
+        (99999, "999.99"),
+    ] {
+        assert_eq!(d.format(v), s);
+        assert_eq!(d.parse(s), Some(v));
+    }
+    assert_eq!(d.parse("3"), Some(300));
+    assert_eq!(d.parse("1.234"), None);
+    assert_eq!(d.parse("1000"), None);
+    assert_eq!(d.parse("1.x"), None);
+    assert_eq!(Decimal::INTEGER.format(i64::MIN), i64::MIN.to_string());
+    let t = Ty::int().with_minor(Form::from(d));
+    assert_eq!(t.to_string(), "int form dec(5, 2)");
+}
+
+#[test]
+fn test_decimal_arith() {
+    let decs = |form: Form, v: &[i64]| {
+        let mut c = col("d", Vals::I64s(v.to_vec()));
+        c.form = form;
+        Vals::Rich(Box::new(c))
+    };
+    let run = |a: Vals, op, b: Vals| bin_with(a, op, b, Overflow::Check);
+    let price = decs(dec(5, 2), &[150, -25]);
+    let rate = decs(dec(3, 1), &[15]);
+
+    // 1.50 + 1.5 = 3.00: the scales are aligned before adding.
+    let sum = run(price.clone(), PrimBinOp::Add, rate.clone()).unwrap();
+    assert_eq!(sum, decs(dec(6, 2), &[300, 125]));
+    // Plain ints count as whole numbers.
+    let sum = run(price.clone(), PrimBinOp::Sub, Vals::I64s(vec![1])).unwrap();
+    assert_eq!(sum, decs(dec(18, 2), &[50, -125]));
+    // 1.50 * 1.5 = 2.250: scales add.
+    let prod = run(price.clone(), PrimBinOp::Mul, rate.clone()).unwrap();
+    assert_eq!(prod, decs(dec(8, 3), &[2250, -375]));
+    // 1.50 / 1.5 = 1.00: the quotient keeps the dividend's scale.
+    let quot = run(price.clone(), PrimBinOp::Div, rate.clone()).unwrap();
+    assert_eq!(quot, decs(dec(6, 2), &[100, -16]));
+    let lt = run(price.clone(), PrimBinOp::Lt, rate.clone()).unwrap();
+    assert_eq!(lt, bitvals(&[false, true]));
+    assert!(run(price.clone(), PrimBinOp::Shl, rate.clone()).is_err());
+    assert!(run(price.clone(), PrimBinOp::Add, flo(&[1.0])).is_err());
+
+    // Results past their precision are overflow, when checking.
+    let big = decs(dec(18, 2), &[999_999_999_999_999_999]);
+    assert!(run(big.clone(), PrimBinOp::Add, rate.clone()).is_err());
+    let wrapped = bin_with(big, PrimBinOp::Add, rate, Overflow::Wrap);
+    assert!(wrapped.is_ok());
+
+    let un = |op, a: Vals| Vm::new(vec![Opcode::Literal(a), Opcode::PrimUnOp(op)], vec![]).run();
+    let neg = un(PrimUnOp::Neg, price.clone()).unwrap();
+    assert_eq!(neg, decs(dec(5, 2), &[-150, 25]));
+    let sgn = un(PrimUnOp::Sgn, price.clone()).unwrap();
+    assert_eq!(sgn, Vals::I64s(vec![1, -1]));
+    assert!(un(PrimUnOp::Popcnt, price.clone()).is_err());
+    assert_eq!(price.to_string(), "<rich d: dec(5, 2)[1.50, -0.25]>");
+}
+
+#[test]
+fn test_check_decimal() {
+    let int_dec = |p, s| Ok(Ty::int().with_minor(dec(p, s)));
+    assert_eq!(check_src("t.price + t.rate"), int_dec(12, 4));
+    assert_eq!(check_src("t.price * t.rate"), int_dec(14, 6));
+    assert_eq!(check_src("t.price / t.rate"), int_dec(13, 2));
+    assert_eq!(check_src("t.price + 1"), int_dec(18, 2));
+    assert_eq!(check_src("t.price < t.rate"), Ok(Ty::bit()));
+    assert_eq!(check_src("sgn(t.price)"), Ok(Ty::int()));
+    assert_eq!(check_src("-t.price"), int_dec(9, 2));
+    assert!(matches!(
+        check_src("t.price ^ t.rate"),
+        Err(TypeErrorKind::NotA { .. })
+    ));
+    assert!(matches!(
+        check_src("popcnt(t.price)"),
+        Err(TypeErrorKind::NotA { .. })
+    ));
+    assert!(matches!(
+        check_src("t.price + t.x"),
+        Err(TypeErrorKind::Mismatch { .. })
+    ));
+}
+
+fn unit(s: &str) -> Unit {
+    Unit::parse(s).unwrap()
+}
+
+#[test]
+fn test_units() {
+    assert_eq!(unit("N"), unit("kg*m/s^2"));
+    assert_eq!(unit("J/s"), unit("W"));
+    assert_eq!(unit("km").checked_mul(unit("1/h")), Some(unit("km/h")));
+    assert_eq!(unit("m").checked_div(unit("m")), Some(Unit::NONE));
+    assert_eq!(unit("m^2").root(2), Some(unit("m")));
+    assert_eq!(unit("m^3").root(2), None);
+    assert_eq!(unit("USD").checked_mul(unit("EUR")), None);
+    assert_eq!(unit("m^7").checked_mul(unit("m")), None);
+    assert_eq!(Unit::parse("furlong"), None);
+    assert_eq!(Unit::parse("7*m"), None);
+    assert_eq!(Unit::parse("1000^2147483647"), None);
+    assert_eq!(Unit::parse("m/m^-2147483648"), None);
+    assert_eq!(Unit::parse("2^-33"), None);
+    assert_eq!(unit("m^7/m^4"), unit("m^3"));
+    assert_eq!(unit("m").root(0), None);
+
+    for s in [
+        "1",
+        "m",
+        "kg",
+        "USD",
+        "N",
+        "kWh",
+        "km",
+        "h",
+        "Hz",
+        "5*m/18/s",
+        "m^2*USD/s",
+    ] {
+        let u = unit(s);
+        assert_eq!(u.to_string(), s);
+        assert_eq!(unit(&u.to_string()), u);
+    }
+    assert_eq!(unit("km").dimension(), unit("m"));
+    assert_eq!(unit("km").conversion(unit("m")), Some((1000, 1)));
+    assert_eq!(unit("min").conversion(unit("h")), Some((1, 60)));
+    assert_eq!(unit("km/h").conversion(unit("m/s")), Some((5, 18)));
+    assert_eq!(unit("km").conversion(unit("s")), None);
+    assert_eq!(unit("USD").conversion(unit("EUR")), None);
+}
+
+#[test]
+fn test_unit_arith() {
+    let united = |u: &str, vals: Vals| {
+        let mut c = col("q", vals);
+        c.unit = unit(u);
+        Vals::Rich(Box::new(c))
+    };
+    let run = |a: Vals, op, b: Vals| bin_with(a, op, b, Overflow::Check);
+    let convert = |a: Vals, u: &str, overflow| {
+        let ops = vec![Opcode::Literal(a), Opcode::Convert(unit(u))];
+        Vm::new(ops, vec![]).with_overflow(overflow).run()
+    };
+    let dist = united("km", Vals::I64s(vec![3, 5]));
+    let time = united("h", Vals::I64s(vec![1, 2]));
+
+    assert!(run(dist.clone(), PrimBinOp::Add, time.clone()).is_err());
+    assert!(run(
+        dist.clone(),
+        PrimBinOp::Add,
+        united("m", Vals::I64s(vec![1]))
+    )
+    .is_err());
+    let sum = run(dist.clone(), PrimBinOp::Add, Vals::I64s(vec![1])).unwrap();
+    assert_eq!(sum, united("km", Vals::I64s(vec![4, 6])));
+    let speed = run(dist.clone(), PrimBinOp::Div, time.clone()).unwrap();
+    assert_eq!(speed, united("km/h", Vals::I64s(vec![3, 2])));
+    let lt = run(dist.clone(), PrimBinOp::Lt, Vals::I64s(vec![4])).unwrap();
+    assert_eq!(lt, bitvals(&[true, false]));
+    assert!(run(dist.clone(), PrimBinOp::Pow, Vals::I64s(vec![2])).is_err());
+
+    let m = convert(dist.clone(), "m", Overflow::Check).unwrap();
+    assert_eq!(m, united("m", Vals::I64s(vec![3000, 5000])));
+    assert!(convert(m.clone(), "km", Overflow::Check).is_ok());
+    assert!(convert(united("m", Vals::I64s(vec![1500])), "km", Overflow::Check).is_err());
+    let trunc = convert(united("m", Vals::I64s(vec![1500])), "km", Overflow::Wrap);
+    assert_eq!(trunc.unwrap(), united("km", Vals::I64s(vec![1])));
+    assert!(convert(dist.clone(), "s", Overflow::Check).is_err());
+    let secs = convert(united("min", flo(&[1.5])), "s", Overflow::Check).unwrap();
+    assert_eq!(secs, united("s", flo(&[90.0])));
+    let tagged = convert(Vals::I64s(vec![7]), "USD", Overflow::Check).unwrap();
+    assert_eq!(tagged.to_string(), "<rich _0: 7 USD>");
+
+    let un = |op, a: Vals| Vm::new(vec![Opcode::Literal(a), Opcode::PrimUnOp(op)], vec![]).run();
+    let area = united("m^2", flo(&[16.0]));
+    assert_eq!(un(PrimUnOp::Sqrt, area).unwrap(), united("m", flo(&[4.0])));
+    assert!(un(PrimUnOp::Exp, united("s", flo(&[1.0]))).is_err());
+    let sgn = un(PrimUnOp::Sgn, dist).unwrap();
+    assert_eq!(sgn, Vals::I64s(vec![1, 1]));
+}
+
+#[test]
+fn test_check_units() {
+    let len = || Ty::flo().with_role(Unit(1));
+    assert_eq!(
+        check_src("t.len * t.len"),
+        Ok(Ty::flo().with_role(unit("m^2")))
+    );
+    assert_eq!(check_src("t.len / 2.0"), Ok(len()));
+    assert_eq!(check_src("t.len < 2.0"), Ok(Ty::bit()));
+    assert_eq!(check_src("sqrt(t.len * t.len)"), Ok(len()));
+    assert_eq!(
+        check_src("convert(t.len, \"km\")"),
+        Ok(Ty::flo().with_role(unit("km")))
+    );
+    assert_eq!(
+        check_src("convert(2, \"s\")"),
+        Ok(Ty::int().with_role(unit("s")))
+    );
+    assert!(matches!(
+        check_src("convert(t.len, \"s\")"),
+        Err(TypeErrorKind::Mismatch { .. })
+    ));
+    assert!(matches!(
+        check_src("t.len + convert(t.len, \"km\")"),
+        Err(TypeErrorKind::Mismatch { .. })
+    ));
+    assert!(matches!(
+        check_src("exp(t.len)"),
+        Err(TypeErrorKind::NotA { .. })
+    ));
+    assert!(matches!(
+        check_src("sqrt(t.len)"),
+        Err(TypeErrorKind::NotA { .. })
+    ));
+
+    let e: Expr = "convert(fn(x) x * 2, \"km\")".parse().unwrap();
+    assert_eq!(e.to_string().parse::<Expr>().unwrap(), e);
+    assert!("convert(1, \"furlong\")".parse::<Expr>().is_err());
+    assert!("convert(1, km)".parse::<Expr>().is_err());
+
+    // Convert ships in a Program with its unit in the unit pool.
+    let ops = vec![
+        Opcode::Literal(Vals::I64s(vec![2])),
+        Opcode::Convert(unit("km")),
+        Opcode::Convert(unit("m")),
+    ];
+    let prog = Program::assemble(&ops).unwrap();
+    assert_eq!(prog.units, vec![unit("km"), unit("m")]);
+    assert_eq!(prog.ops().unwrap(), ops);
+    let mut broken = prog.clone();
+    broken.units.pop();
+    assert!(broken.ops().is_err());
+}
+
+fn path(s: &str) -> Path {
+    Path(s.split('.').map(word).collect())
+}
+
+fn rich(name: &str, vals: Vals) -> Vals {
+    Vals::Rich(Box::new(col(name, vals)))
+}
+
+fn nested_tab() -> Tab {
+    let z = rich("z", flo(&[0.5]));
+    let fields = vec![
+        rich("x", Vals::I64s(vec![1])),
+        rich("y", Vals::All(vec![z])),
+        Vals::I64s(vec![2]),
+        // Shadowed by the first field's label, so only reachable as _3.
+        rich("x", Vals::I64s(vec![3])),
+    ];
+    let any = Vals::Any(vec![0], vec![rich("p", Vals::I64s(vec![4]))]);
+    Tab {
+        cols: vec![
+            col("a", Vals::I64s(vec![7])),
+            col("r", Vals::All(fields)),
+            col("s", rich("inner", any)),
+        ],
+    }
+}
+
+#[test]
+fn test_tab_resolve() {
+    let tab = nested_tab();
+    let vals = |p: &str| tab.resolve(&path(p)).map(|c| c.vals.clone());
+    assert_eq!(vals("a").unwrap(), Vals::I64s(vec![7]));
+    assert_eq!(vals("r.x").unwrap(), Vals::I64s(vec![1]));
+    assert_eq!(vals("r._0").unwrap(), Vals::I64s(vec![1]));
+    assert_eq!(vals("r._3").unwrap(), Vals::I64s(vec![3]));
+    assert_eq!(vals("r.y.z").unwrap(), flo(&[0.5]));
+    assert_eq!(vals("s.p").unwrap(), Vals::I64s(vec![4]));
+    assert!(vals("r._2").is_err());
+    assert!(vals("a.b").is_err());
+    assert!(tab.resolve(&Path(vec![])).is_err());
+    let e = vals("r.q.z").unwrap_err();
+    assert!(
+        e.to_string().contains("no column r.q (resolving r.q.z)"),
+        "{}",
+        e
+    );
+
+    let listed: Vec<String> = tab.paths().iter().map(|p| p.to_string()).collect();
+    assert_eq!(
+        listed,
+        ["a", "r", "r.x", "r.y", "r.y.z", "r._3", "s", "s.p"]
+    );
+    for p in tab.paths() {
+        assert!(tab.resolve(&p).is_ok(), "{}", p);
+    }
+
+    let mut tab = nested_tab();
+    tab.resolve_mut(&path("r.y.z")).unwrap().vals = flo(&[1.5]);
+    assert_eq!(tab.resolve(&path("r.y.z")).unwrap().vals, flo(&[1.5]));
+    let taken = tab.clone().into_resolved(&path("s.p")).unwrap();
+    assert_eq!(taken, col("p", Vals::I64s(vec![4])));
+
+    // The Vm loads nested paths from its context.
+    let ops = vec![
+        Opcode::Path(path("r.y.z")),
+        Opcode::Path(path("s.p")),
+        Opcode::PrimUnOp(PrimUnOp::Neg),
+        Opcode::PrimBinOp(PrimBinOp::Lt),
+    ];
+    let r = Vm::new(ops, vec![nested_tab()]).run();
+    assert!(r.unwrap_err().to_string().contains("no lt on flo and int"));
+}
+
+#[test]
+fn test_tab_builder_and_rows() {
+    let tab = TabBuilder::new()
+        .col("n")
+        .i64s([1, 2, 3])
+        .col("x")
+        .unit(unit("m"))
+        .f64s([0.5, 1.5, 2.5])
+        .col("b")
+        .bits([true, false, true])
+        .col("s")
+        .bins(["a", "b", "c"])
+        .build()
+        .unwrap();
+    assert_eq!(tab.len(), 3);
+    assert_eq!(tab.cols()[1].unit(), unit("m"));
+    assert_eq!(
+        tab.resolve(&path("b")).unwrap().vals(),
+        &bitvals(&[true, false, true])
+    );
+    let bin = |s: &str| Val::Bin(Bin::Mem(s.as_bytes().to_vec()));
+    let row1 = vec![Val::Int(2), Val::Flo(1.5.into()), Val::Bit(false), bin("b")];
+    assert_eq!(tab.row(1), Some(row1.clone()));
+    assert_eq!(tab.row(3), None);
+    let rows: Vec<_> = tab.rows().collect();
+    assert_eq!(rows.len(), 3);
+    assert_eq!(rows[1], row1);
+
+    assert!(TabBuilder::new()
+        .col("n")
+        .i64s([1])
+        .col("m")
+        .i64s([])
+        .build()
+        .is_err());
+    assert!(TabBuilder::new()
+        .col("n")
+        .i64s([1])
+        .col("n")
+        .i64s([2])
+        .build()
+        .is_err());
+    assert!(TabBuilder::new().col("no good").i64s([1]).build().is_err());
+    assert!(TabBuilder::new().build().unwrap().is_empty());
+
+    // Appending checks every value before changing any column.
+    let mut t = tab.clone();
+    let bad = vec![Val::Int(4), Val::Flo(3.5.into()), Val::Int(1), bin("d")];
+    assert!(t.push_row(bad).is_err());
+    assert!(t.push_row(vec![Val::Int(4)]).is_err());
+    assert_eq!(t, tab);
+    let good = vec![Val::Int(4), Val::Flo(3.5.into()), Val::Bit(true), bin("d")];
+    t.push_row(good.clone()).unwrap();
+    assert_eq!(t.len(), 4);
+    assert_eq!(t.rows().last(), Some(good));
+}
+
+#[test]
+fn test_nested_rows() {
+    // Any variants hold only their own rows, densely.
+    let bin = |s: &str| Val::Bin(Bin::Mem(s.as_bytes().to_vec()));
+    let rows = [
+        Val::All(vec![Val::Int(1), Val::Any(1, Box::new(bin("x")))]),
+        Val::All(vec![Val::Int(2), Val::Any(0, Box::new(Val::Int(11)))]),
+        Val::All(vec![Val::Int(3), Val::Any(1, Box::new(bin("y")))]),
+    ];
+    let any = Vals::Any(vec![], vec![Vals::I64s(vec![]), Vals::Bins(vec![])]);
+    let mut tab = Tab::new(vec![col("r", Vals::All(vec![Vals::I64s(vec![]), any]))]).unwrap();
+    assert!(tab.push_row(vec![Val::Int(1)]).is_err());
+    for r in &rows {
+        tab.push_row(vec![r.clone()]).unwrap();
+    }
+    let got: Vec<Val> = tab.rows().map(|mut r| r.remove(0)).collect();
+    assert_eq!(got, rows);
+    assert_eq!(tab.row(2), Some(vec![rows[2].clone()]));
+    let r = tab.resolve(&path("r")).unwrap();
+    let want = Vals::Any(
+        vec![1, 0, 1],
+        vec![
+            Vals::I64s(vec![11]),
+            Vals::Bins(vec![Bin::Mem(b"x".to_vec()), Bin::Mem(b"y".to_vec())]),
+        ],
+    );
+    assert_eq!(r.vals(), &Vals::All(vec![Vals::I64s(vec![1, 2, 3]), want]));
+    let bad = Val::All(vec![Val::Int(4), Val::Any(2, Box::new(Val::Int(0)))]);
+    assert!(tab.push_row(vec![bad]).is_err());
+    assert_eq!(tab.len(), 3);
+}
+
+#[test]
+fn test_any_variants() {
+    let bins = |v: &[&str]| Vals::Bins(v.iter().map(|s| Bin::Mem(s.as_bytes().to_vec())).collect());
+    let any = Vals::any(
+        vec![1, 0, 1, 1],
+        vec![Vals::I64s(vec![7]), bins(&["a", "b", "c"])],
+    )
+    .unwrap();
+    let (vals, rows) = any.variant(1).unwrap();
+    assert_eq!((vals, rows), (&bins(&["a", "b", "c"]), vec![0, 2, 3]));
+    let parts: Vec<(Vals, Vec<usize>)> = any
+        .variants()
+        .unwrap()
+        .into_iter()
+        .map(|(v, r)| (v.clone(), r))
+        .collect();
+    assert_eq!(parts[0], (Vals::I64s(vec![7]), vec![1]));
+    assert_eq!(Vals::any_from_parts(4, parts.clone()).unwrap(), any);
+    assert!(any.variant(2).is_err());
+    assert!(Vals::I64s(vec![1]).variants().is_err());
+
+    // Selectors must be in range and agree with the variant lengths.
+    assert!(Vals::any(vec![0, 2], vec![Vals::I64s(vec![1]), Vals::I64s(vec![])]).is_err());
+    assert!(Vals::any(vec![0, -1], vec![Vals::I64s(vec![1, 2])]).is_err());
+    assert!(Vals::any(vec![0, 0], vec![Vals::I64s(vec![1])]).is_err());
+    let bad = Vals::All(vec![
+        Vals::I64s(vec![1, 2]),
+        Vals::Any(vec![0, 1], vec![Vals::I64s(vec![1])]),
+    ]);
+    assert!(bad.validate().is_err());
+    assert!(Vals::All(vec![Vals::I64s(vec![1, 2]), any.clone()])
+        .validate()
+        .is_err());
+    assert!(Vals::All(vec![Vals::I64s(vec![1, 2, 3, 4]), any])
+        .validate()
+        .is_ok());
+
+    // Parts must cover every row once, in order within each variant.
+    let mut gap = parts.clone();
+    gap[0].1 = vec![4];
+    assert!(Vals::any_from_parts(4, gap).is_err());
+    let mut twice = parts.clone();
+    twice[1] = (bins(&["a", "b", "c"]), vec![0, 1, 3]);
+    assert!(Vals::any_from_parts(4, twice).is_err());
+    let mut unordered = parts;
+    unordered[1].1 = vec![3, 2, 0];
+    assert!(Vals::any_from_parts(4, unordered).is_err());
+}
+
+#[test]
+fn test_vm_case() {
+    // Ints are scaled by the context's k; bins become -1.
+    let ctx = Tab {
+        cols: vec![
+            col(
+                "u",
+                Vals::Any(
+                    vec![0, 1, 0, 0],
+                    vec![
+                        Vals::I64s(vec![1, 2, 3]),
+                        Vals::Bins(vec![Bin::Mem(b"x".to_vec())]),
+                    ],
+                ),
+            ),
+            col("k", Vals::I64s(vec![10])),
+        ],
+    };
+    let case = |arms: Vec<Vec<Opcode>>| vec![Opcode::Path(path("u")), Opcode::Case(arms)];
+    let scale = vec![Opcode::Path(path("k")), Opcode::PrimBinOp(PrimBinOp::Mul)];
+    let ops = case(vec![
+        scale.clone(),
+        vec![Opcode::Literal(Vals::I64s(vec![-1]))],
+    ]);
+    let mut vm = Vm::new(ops.clone(), vec![ctx.clone()]);
+    assert_eq!(vm.run().unwrap(), Vals::I64s(vec![10, -1, 20, 30]));
+    // The context survives the arms.
+    assert_eq!(vm.stack[0].ctx, vec![ctx.clone()]);
+
+    let prog = Program::assemble(&ops).unwrap();
+    assert_eq!(prog.cases.len(), 1);
+    // Arms start with their variant already on the stack.
+    assert!(prog.cases[0][0].ops().is_err());
+    assert_eq!(prog.ops().unwrap(), ops);
+
+    let run = |ops| Vm::new(ops, vec![ctx.clone()]).run();
+    let flo = vec![Opcode::Literal(flo(&[0.5]))];
+    assert!(run(case(vec![scale.clone(), flo])).is_err());
+    assert!(run(case(vec![scale.clone()])).is_err());
+    let two_rows = vec![Opcode::Literal(Vals::I64s(vec![1, 2]))];
+    assert!(run(case(vec![scale, two_rows])).is_err());
+}
+
+#[test]
+fn test_all_fields() {
+    let len = Vals::Rich(Box::new(col("len", flo(&[1.5, 2.5])).with_unit(unit("m"))));
+    let rec = Vals::zip(vec![
+        (word("n"), Vals::I64s(vec![1, 2])),
+        (word("len"), len),
+        (word("k"), Vals::I64s(vec![9])),
+    ])
+    .unwrap();
+    assert_eq!(
+        rec.field(&word("n")).unwrap(),
+        &rich("n", Vals::I64s(vec![1, 2]))
+    );
+    assert_eq!(
+        rec.field(&word("_2")).unwrap(),
+        rec.field(&word("k")).unwrap()
+    );
+    assert_eq!(rec.field(&word("k")).unwrap().len(), 2);
+    assert!(rec.field(&word("m")).is_err());
+    assert!(Vals::I64s(vec![1]).field(&word("n")).is_err());
+
+    // Unzipping keeps forms and units but drops bare labels.
+    let parts = rec.unzip().unwrap();
+    assert_eq!(parts[0], (word("n"), Vals::I64s(vec![1, 2])));
+    assert_eq!(parts[1].1.len(), 2);
+    assert!(matches!(&parts[1].1, Vals::Rich(c) if c.unit == unit("m")));
+    assert_eq!(Vals::zip(parts).unwrap(), rec);
+    assert!(Vals::zip(vec![]).is_err());
+    assert!(Vals::zip(vec![(word("a"), Vals::I64s(vec![1])); 2]).is_err());
+    let ragged = vec![
+        (word("a"), Vals::I64s(vec![1, 2])),
+        (word("b"), Vals::I64s(vec![1, 2, 3])),
+    ];
+    assert!(Vals::zip(ragged).is_err());
+
+    // Rows go in and come out as Val::Alls, under the template's labels.
+    let rows = rec.to_rows();
+    assert_eq!(
+        rows[1],
+        Val::All(vec![Val::Int(2), Val::Flo(2.5.into()), Val::Int(9)])
+    );
+    assert_eq!(Vals::from_rows(&rec, rows.clone()).unwrap(), rec);
+    assert!(Vals::from_rows(&rec, [Val::Int(1)]).is_err());
+
+    // Nested fields project the way paths resolve in a Tab.
+    let tab = nested_tab();
+    let r = &tab.cols[1].vals;
+    let z = r.project(&path("y.z").0).unwrap();
+    let resolved = tab.resolve(&path("r.y.z")).unwrap().clone();
+    assert_eq!(z, &Vals::Rich(Box::new(resolved)));
+    assert_eq!(r.project(&path("_2").0).unwrap(), &Vals::I64s(vec![2]));
+}
+
+#[test]
+fn test_vm_records() {
+    let ctx = nested_tab();
+    let ops = vec![
+        Opcode::Path(path("r")),
+        Opcode::Project(path("y.z")),
+        Opcode::Path(path("a")),
+        Opcode::Literal(Vals::I64s(vec![1])),
+        Opcode::PrimBinOp(PrimBinOp::Add),
+        Opcode::Zip(vec![word("z"), word("b")]),
+        Opcode::Project(path("b")),
+    ];
+    assert_eq!(
+        Vm::new(ops[..6].to_vec(), vec![ctx.clone()]).run().unwrap(),
+        Vals::All(vec![rich("z", flo(&[0.5])), rich("b", Vals::I64s(vec![8]))])
+    );
+    assert_eq!(
+        Vm::new(ops.clone(), vec![ctx.clone()]).run().unwrap(),
+        Vals::I64s(vec![8])
+    );
+    let prog = Program::assemble(&ops).unwrap();
+    assert_eq!(prog.paths.len(), 5);
+    assert_eq!(prog.ops().unwrap(), ops);
+    let mut vm = Vm::new(prog.ops().unwrap(), vec![ctx.clone()]);
+    assert_eq!(vm.run().unwrap(), Vals::I64s(vec![8]));
+
+    let run = |ops| Vm::new(ops, vec![ctx.clone()]).run();
+    assert!(run(vec![Opcode::Path(path("a")), Opcode::Project(path("x"))]).is_err());
+    assert!(run(vec![
+        Opcode::Path(path("a")),
+        Opcode::Zip(vec![word("p"), word("q")])
+    ])
+    .is_err());
+    assert!(Program::assemble(&[Opcode::Zip(vec![])]).is_err());
+}
+
+#[test]
+fn test_check_record_paths() {
+    let mut env = schema();
+    let rec = Major::All(vec![
+        (word("a"), Ty::int()),
+        (word("b"), Ty::new(Major::All(vec![(word("c"), Ty::flo())]))),
+    ]);
+    env.insert(path("t.r"), Ty::new(rec));
+    let check_with = |src: &str| {
+        let p = parse(src).unwrap();
+        check(&p.expr, &env, Some(&p.spans)).map(|t| t.root().clone())
+    };
+    assert_eq!(check_with("t.r.a + 1").unwrap(), Ty::int());
+    assert_eq!(check_with("t.r.b.c").unwrap(), Ty::flo());
+    assert_eq!(check_with("t.r._1._0").unwrap(), Ty::flo());
+    let err = check_with("t.r.d").unwrap_err();
+    assert!(matches!(*err.kind, TypeErrorKind::NoField { .. }));
+    assert!(check_with("t.n.a").is_err());
+    assert_eq!(check_src("let r = {a: 1, b: 2.0} in r._1"), Ok(Ty::flo()));
+}
+
+fn agg(op: AggOp, v: Vals) -> submerge_base::Result<Vals> {
+    Vm::new(vec![Opcode::Literal(v), Opcode::Agg(op)], vec![]).run()
+}
+
+#[test]
+fn test_aggregates() {
+    use AggOp::*;
+    let ints = Vals::I64s(vec![3, -1, 4, 1]);
+    assert_eq!(agg(Count, ints.clone()).unwrap(), Vals::I64s(vec![4]));
+    assert_eq!(agg(Sum, ints.clone()).unwrap(), Vals::I64s(vec![7]));
+    assert_eq!(agg(Min, ints.clone()).unwrap(), Vals::I64s(vec![-1]));
+    assert_eq!(agg(Max, ints.clone()).unwrap(), Vals::I64s(vec![4]));
+    assert_eq!(agg(Mean, ints).unwrap(), flo(&[1.75]));
+
+    // Sums are exact even when partial sums overflow, and check the total.
+    let big = Vals::I64s(vec![i64::MAX, 1, -2]);
+    assert_eq!(
+        agg(Sum, big.clone()).unwrap(),
+        Vals::I64s(vec![i64::MAX - 1])
+    );
+    let over = Vals::I64s(vec![i64::MAX, 1]);
+    assert_eq!(agg(Sum, over.clone()).unwrap(), Vals::I64s(vec![i64::MIN]));
+    let checked = |v| {
+        let ops = vec![Opcode::Literal(v), Opcode::Agg(Sum)];
+        Vm::new(ops, vec![]).with_overflow(Overflow::Check).run()
+    };
+    assert!(checked(over).is_err());
+    assert!(checked(big).is_ok());
+    // i64::MIN fits, though its magnitude is one past i64::MAX.
+    let min = checked(Vals::I64s(vec![i64::MIN])).unwrap();
+    assert_eq!(min, Vals::I64s(vec![i64::MIN]));
+    assert!(checked(Vals::I64s(vec![i64::MIN + 1, -1])).is_ok());
+    assert!(checked(Vals::I64s(vec![i64::MIN, -1])).is_err());
+
+    // Compensated float sums don't lose the small terms.
+    let mut xs = vec![1.0e16];
+    xs.extend([1.0; 10]);
+    xs.push(-1.0e16);
+    assert_eq!(agg(Sum, flo(&xs)).unwrap(), flo(&[10.0]));
+    assert_eq!(
+        agg(Mean, flo(&[0.5, 1.5, f64::INFINITY])).unwrap(),
+        flo(&[f64::INFINITY])
+    );
+
+    // Empty columns count and sum to zero, but have no min, max or mean.
+    assert_eq!(agg(Sum, Vals::I64s(vec![])).unwrap(), Vals::I64s(vec![0]));
+    assert_eq!(agg(Count, flo(&[])).unwrap(), Vals::I64s(vec![0]));
+    assert_eq!(agg(Min, Vals::I64s(vec![])).unwrap(), Vals::I64s(vec![]));
+    assert_eq!(agg(Mean, flo(&[])).unwrap(), flo(&[]));
+
+    let bs = bitvals(&[true, false, true]);
+    assert_eq!(agg(Sum, bs.clone()).unwrap(), Vals::I64s(vec![2]));
+    assert_eq!(agg(Min, bs.clone()).unwrap(), bitvals(&[false]));
+    assert_eq!(agg(Max, bs).unwrap(), bitvals(&[true]));
+    let bins = Vals::Bins(vec![Bin::Mem(b"b".to_vec()), Bin::Mem(b"a".to_vec())]);
+    assert_eq!(
+        agg(Min, bins.clone()).unwrap(),
+        Vals::Bins(vec![Bin::Mem(b"a".to_vec())])
+    );
+    assert!(agg(Sum, bins).is_err());
+    assert_eq!(
+        agg(Count, Vals::All(vec![Vals::I64s(vec![1, 2])])).unwrap(),
+        Vals::I64s(vec![2])
+    );
+}
+
+#[test]
+fn test_aggregate_forms_and_units() {
+    let price = Vals::Rich(Box::new(
+        col("p", Vals::I64s(vec![150, 225, 100])).with_form(dec(5, 2)),
+    ));
+    let sum = agg(AggOp::Sum, price.clone()).unwrap();
+    assert_eq!(sum.to_string(), "<rich p: dec(18, 2)[4.75]>");
+    let mean = agg(AggOp::Mean, price.clone()).unwrap();
+    assert_eq!(mean.to_string(), "<rich p: dec(5, 2)[1.58]>");
+    assert_eq!(agg(AggOp::Count, price).unwrap(), Vals::I64s(vec![3]));
+
+    let len = Vals::Rich(Box::new(col("len", flo(&[2.0, 4.0])).with_unit(unit("m"))));
+    let max = agg(AggOp::Max, len.clone()).unwrap();
+    assert_eq!(
+        max,
+        Vals::Rich(Box::new(col("len", flo(&[4.0])).with_unit(unit("m"))))
+    );
+
+    let prog = Program::assemble(&[Opcode::Literal(len), Opcode::Agg(AggOp::Mean)]).unwrap();
+    let insns = prog.unpack().unwrap();
+    assert_eq!(insns[1].b, Operand::reg(0, false));
+    assert_eq!(prog.ops().unwrap()[1], Opcode::Agg(AggOp::Mean));
+}
+
+fn grouping(keys: &[&str], aggs: &[(&str, AggOp, &str)]) -> Grouping {
+    Grouping {
+        keys: keys.iter().map(|k| path(k)).collect(),
+        aggs: aggs
+            .iter()
+            .map(|(name, op, arg)| Aggregate {
+                name: word(name),
+                op: *op,
+                arg: path(arg),
+            })
+            .collect(),
+    }
+}
+
+#[test]
+fn test_group_by() {
+    let tab = TabBuilder::new()
+        .col("k")
+        .bins(["b", "a", "b", "a", "c"])
+        .col("j")
+        .i64s([1, 1, 1, 2, 1])
+        .col("len")
+        .unit(unit("m"))
+        .f64s([1.0, 2.0, 3.0, 4.0, 5.0])
+        .build()
+        .unwrap();
+    let g = grouping(
+        &["k"],
+        &[("n", AggOp::Count, "len"), ("total", AggOp::Sum, "len")],
+    );
+    let out = tab.group_by(&g, Overflow::Wrap).unwrap();
+    let want = TabBuilder::new()
+        .col("k")
+        .bins(["a", "b", "c"])
+        .col("n")
+        .i64s([2, 2, 1])
+        .col("total")
+        .unit(unit("m"))
+        .f64s([6.0, 4.0, 5.0])
+        .build()
+        .unwrap();
+    assert_eq!(out, want);
+
+    // Two keys, and groups in key order whatever the row order.
+    let g2 = grouping(&["j", "k"], &[("first", AggOp::Min, "len")]);
+    let out = tab.group_by(&g2, Overflow::Wrap).unwrap();
+    let rows: Vec<Vec<Val>> = out.rows().collect();
+    let bin = |s: &str| Val::Bin(Bin::Mem(s.as_bytes().to_vec()));
+    assert_eq!(rows.len(), 4);
+    assert_eq!(rows[0], vec![Val::Int(1), bin("a"), Val::Flo(2.0.into())]);
+    assert_eq!(rows[3], vec![Val::Int(2), bin("a"), Val::Flo(4.0.into())]);
+
+    // No keys is one group of everything.
+    let all = grouping(&[], &[("mean", AggOp::Mean, "j")]);
+    let out = tab.group_by(&all, Overflow::Wrap).unwrap();
+    assert_eq!(out.cols()[0].vals(), &flo(&[1.2]));
+    assert!(tab
+        .group_by(&grouping(&["nope"], &[]), Overflow::Wrap)
+        .is_err());
+    let dup = grouping(&["k"], &[("k", AggOp::Count, "j")]);
+    assert!(tab.group_by(&dup, Overflow::Wrap).is_err());
+
+    // In the Vm, tables are records of columns.
+    let ctx = Tab {
+        cols: vec![col(
+            "t",
+            Vals::zip(vec![
+                (word("k"), Vals::I64s(vec![2, 1, 2])),
+                (word("v"), Vals::I64s(vec![10, 20, 30])),
+            ])
+            .unwrap(),
+        )],
+    };
+    let ops = vec![
+        Opcode::Path(path("t")),
+        Opcode::Group(grouping(&["k"], &[("s", AggOp::Sum, "v")])),
+        Opcode::Project(path("s")),
+    ];
+    let prog = Program::assemble(&ops).unwrap();
+    assert_eq!(prog.groupings.len(), 1);
+    let ops = prog.ops().unwrap();
+    assert_eq!(
+        Vm::new(ops, vec![ctx]).run().unwrap(),
+        Vals::I64s(vec![20, 40])
+    );
+}
+
+#[test]
+fn test_check_group() {
+    let src = |aggs: &str| {
+        format!(
+            "group(tab{{k: [1, 2], x: [0.5, 1.5], b: [true, false], p: [\"a\", \"b\"]}}, [k], {{{}}})",
+            aggs
+        )
+    };
+    let ty = check_src(&src(
+        "n: count(p), s: sum(x), m: mean(k), h: max(p), t: sum(b)",
+    ))
+    .unwrap();
+    assert_eq!(
+        ty.to_string(),
+        "tab{k: int, n: int, s: flo, m: flo, h: bin, t: int}"
+    );
+    assert!(matches!(
+        check_src(&src("s: sum(p)")),
+        Err(TypeErrorKind::NotA { .. })
+    ));
+    assert!(matches!(
+        check_src(&src("s: sum(q)")),
+        Err(TypeErrorKind::NoField { .. })
+    ));
+    assert_eq!(
+        check_src(&src("k: count(x)")),
+        Err(TypeErrorKind::DuplicateField(word("k")))
+    );
+    assert!(check_src("group(t.n, [], {})").is_err());
+    assert!(check_src("fn(t) group(t, [k], {})").is_err());
+}
+
+fn join(kind: JoinKind, on: &[(&str, &str)]) -> Join {
+    Join {
+        kind,
+        on: on.iter().map(|(l, r)| (path(l), path(r))).collect(),
+    }
+}
+
+#[test]
+fn test_join() {
+    let long = "a bin longer than its eight byte prefix";
+    let left = TabBuilder::new()
+        .col("k")
+        .i64s([1, 2, 3, 2])
+        .col("s")
+        .bins(["x", long, "y", "z"])
+        .build()
+        .unwrap();
+    let right = TabBuilder::new()
+        .col("k")
+        .i64s([2, 4, 1, 2])
+        .col("name")
+        .bins(["two", "four", "one", long])
+        .col("len")
+        .unit(unit("m"))
+        .f64s([2.0, 4.0, 1.0, 2.5])
+        .build()
+        .unwrap();
+
+    // Rows come out in left order, then right order; the shared key column
+    // appears once.
+    let out = left
+        .join(&right, &join(JoinKind::Inner, &[("k", "k")]))
+        .unwrap();
+    let want = TabBuilder::new()
+        .col("k")
+        .i64s([1, 2, 2, 2, 2])
+        .col("s")
+        .bins(["x", long, long, "z", "z"])
+        .col("name")
+        .bins(["one", "two", long, "two", long])
+        .col("len")
+        .unit(unit("m"))
+        .f64s([1.0, 2.0, 2.5, 2.0, 2.5])
+        .build()
+        .unwrap();
+    assert_eq!(out, want);
+    // Building over the left side gives the same rows, in the same order,
+    // and a left join builds over the right whatever it's asked.
+    for kind in [JoinKind::Inner, JoinKind::Left] {
+        let j = join(kind, &[("k", "k")]);
+        let built = left.join_with(&right, &j, Build::Left).unwrap();
+        assert_eq!(built, left.join(&right, &j).unwrap());
+    }
+
+    // A left join pads unmatched rows with nulls.
+    let out = left
+        .join(&right, &join(JoinKind::Left, &[("k", "k")]))
+        .unwrap();
+    assert_eq!(out.len(), 6);
+    let rows: Vec<Vec<Val>> = out.rows().collect();
+    let bin = |s: &str| Val::Bin(Bin::Mem(s.as_bytes().to_vec()));
+    assert_eq!(rows[3], vec![Val::Int(3), bin("y"), Val::Null, Val::Null]);
+    assert_eq!(out.cols()[3].unit(), unit("m"));
+
+    // Bin keys that share a prefix only match on all their bytes.
+    let other = long.replace("prefix", "suffix");
+    let names = TabBuilder::new()
+        .col("n")
+        .bins([long, other.as_str(), "x"])
+        .build()
+        .unwrap();
+    let out = left
+        .join(&names, &join(JoinKind::Inner, &[("s", "n")]))
+        .unwrap();
+    assert_eq!(out.cols()[0].vals(), &Vals::I64s(vec![1, 2]));
+    assert_eq!(out.cols().len(), 3);
+
+    // Keys must be ints or bins of the same type, and names must not clash.
+    assert!(left
+        .join(&right, &join(JoinKind::Inner, &[("k", "name")]))
+        .is_err());
+    assert!(right
+        .join(&right, &join(JoinKind::Inner, &[("len", "len")]))
+        .is_err());
+    assert!(left
+        .join(&right, &join(JoinKind::Inner, &[("s", "name")]))
+        .is_err());
+
+    // In the Vm, tables are records of columns.
+    let ctx = Tab {
+        cols: vec![
+            col(
+                "a",
+                Vals::zip(vec![
+                    (word("id"), Vals::I64s(vec![1, 2, 3])),
+                    (word("v"), Vals::I64s(vec![10, 20, 30])),
+                ])
+                .unwrap(),
+            ),
+            col(
+                "b",
+                Vals::zip(vec![
+                    (word("ref"), Vals::I64s(vec![3, 1])),
+                    (word("w"), Vals::I64s(vec![300, 100])),
+                ])
+                .unwrap(),
+            ),
+        ],
+    };
+    let ops = vec![
+        Opcode::Path(path("a")),
+        Opcode::Path(path("b")),
+        Opcode::Join(join(JoinKind::Left, &[("id", "ref")])),
+        Opcode::Project(path("w")),
+    ];
+    let prog = Program::assemble(&ops).unwrap();
+    assert_eq!(prog.joins.len(), 1);
+    let ops = prog.ops().unwrap();
+    assert_eq!(
+        Vm::new(ops, vec![ctx]).run().unwrap().to_rows(),
+        vec![Val::Int(100), Val::Null, Val::Int(300)]
+    );
+}
+
+#[test]
+fn test_check_join() {
+    let src = |on: &str| {
+        format!(
+            "join(tab{{k: [1, 2], s: [\"a\", \"b\"]}}, tab{{k: [2], id: [1], x: [0.5]}}, [{}])",
+            on
+        )
+    };
+    let ty = check_src(&src("k = k")).unwrap();
+    assert_eq!(ty.to_string(), "tab{k: int, s: bin, id: int, x: flo}");
+    assert_eq!(
+        check_src(&src("k = id")),
+        Err(TypeErrorKind::DuplicateField(word("k")))
+    );
+    assert!(matches!(
+        check_src(&src("s = k")),
+        Err(TypeErrorKind::Mismatch { .. })
+    ));
+    assert!(matches!(
+        check_src(&src("k = x")),
+        Err(TypeErrorKind::Mismatch { .. })
+    ));
+    assert!(matches!(
+        check_src(&src("k = q")),
+        Err(TypeErrorKind::NoField { .. })
+    ));
+    assert!(check_src("join({k: 1}, tab{k: [1]}, [k = k])").is_err());
+    assert!(check_src("fn(t) join(t, t, [])").is_err());
+}
+
+fn sort_keys(keys: &[(&str, bool)]) -> Vec<SortKey> {
+    keys.iter()
+        .map(|(p, desc)| SortKey {
+            path: path(p),
+            desc: *desc,
+        })
+        .collect()
+}
+
+#[test]
+fn test_sort() {
+    let tab = TabBuilder::new()
+        .col("k")
+        .bins(["b", "a", "b", "ab", "a"])
+        .col("n")
+        .i64s([1, 2, 3, 4, 5])
+        .col("x")
+        .unit(unit("m"))
+        .f64s([0.5, f64::NAN, -1.0, 2.0, 0.5])
+        .build()
+        .unwrap();
+
+    // Bins sort bytewise, and equal keys keep their row order.
+    let out = tab.sort_by(&sort_keys(&[("k", false)])).unwrap();
+    let want = TabBuilder::new()
+        .col("k")
+        .bins(["a", "a", "ab", "b", "b"])
+        .col("n")
+        .i64s([2, 5, 4, 1, 3])
+        .col("x")
+        .unit(unit("m"))
+        .f64s([f64::NAN, 0.5, 2.0, 0.5, -1.0])
+        .build()
+        .unwrap();
+    assert_eq!(out, want);
+
+    // Later keys break ties, each in its own direction.
+    let out = tab
+        .sort_by(&sort_keys(&[("k", true), ("x", false)]))
+        .unwrap();
+    assert_eq!(out.cols()[1].vals(), &Vals::I64s(vec![3, 1, 4, 5, 2]));
+    let out = tab.sort_by(&sort_keys(&[("x", true)])).unwrap();
+    assert_eq!(out.cols()[1].vals(), &Vals::I64s(vec![2, 4, 1, 5, 3]));
+    assert_eq!(tab.sort_by(&[]).unwrap(), tab);
+    assert!(tab.sort_by(&sort_keys(&[("nope", false)])).is_err());
+
+    // In the Vm, tables are records of columns.
+    let ctx = Tab {
+        cols: vec![col(
+            "t",
+            Vals::zip(vec![
+                (word("k"), Vals::I64s(vec![2, 1, 3])),
+                (word("v"), Vals::I64s(vec![20, 10, 30])),
+            ])
+            .unwrap(),
+        )],
+    };
+    let ops = vec![
+        Opcode::Path(path("t")),
+        Opcode::Sort(sort_keys(&[("k", true)])),
+        Opcode::Project(path("v")),
+    ];
+    let prog = Program::assemble(&ops).unwrap();
+    assert_eq!(prog.sorts.len(), 1);
+    let ops = prog.ops().unwrap();
+    assert_eq!(
+        Vm::new(ops, vec![ctx]).run().unwrap(),
+        Vals::I64s(vec![30, 20, 10])
+    );
+}
+
+#[test]
+fn test_check_sort() {
+    let t = "tab{k: [1, 2], s: [\"a\", \"b\"], r: {v: [0.5, 1.5]}}";
+    let ty = check_src(&format!("sort({}, [s desc, r.v, k asc])", t)).unwrap();
+    assert_eq!(ty.to_string(), "tab{k: int, s: bin, r: {v: flo}}");
+    assert!(matches!(
+        check_src(&format!("sort({}, [r])", t)),
+        Err(TypeErrorKind::NotA { .. })
+    ));
+    assert!(matches!(
+        check_src(&format!("sort({}, [q])", t)),
+        Err(TypeErrorKind::NoField { .. })
+    ));
+    // Only special after a key, so they can still name columns.
+    assert!(check_src("sort(tab{desc: [1]}, [desc desc])").is_ok());
+    assert!(check_src("sort([1, 2], [])").is_err());
+}
+
+#[test]
+fn test_filter() {
+    let tab = TabBuilder::new()
+        .col("k")
+        .i64s([1, 2, 3, 4])
+        .col("s")
+        .bins(["a", "b", "c", "d"])
+        .col("x")
+        .unit(unit("m"))
+        .f64s([0.5, 1.5, 2.5, 3.5])
+        .build()
+        .unwrap();
+
+    // Compaction keeps the masked rows of every column, in order.
+    let mask = bitvals(&[false, true, false, true]);
+    let out = tab.compact(&mask).unwrap();
+    let want = TabBuilder::new()
+        .col("k")
+        .i64s([2, 4])
+        .col("s")
+        .bins(["b", "d"])
+        .col("x")
+        .unit(unit("m"))
+        .f64s([1.5, 3.5])
+        .build()
+        .unwrap();
+    assert_eq!(out, want);
+    let rec = Vals::zip(vec![
+        (word("k"), Vals::I64s(vec![1, 2, 3, 4])),
+        (word("b"), bitvals(&[true, true, false, false])),
+    ])
+    .unwrap();
+    assert_eq!(
+        rec.compact(&mask).unwrap(),
+        Vals::zip(vec![
+            (word("k"), Vals::I64s(vec![2, 4])),
+            (word("b"), bitvals(&[true, false])),
+        ])
+        .unwrap()
+    );
+
+    // A one-row mask keeps everything or nothing; any other length is an
+    // error, as is a mask that isn't bits.
+    assert_eq!(tab.compact(&bitvals(&[true])).unwrap(), tab);
+    assert_eq!(tab.compact(&bitvals(&[false])).unwrap().len(), 0);
+    assert!(tab.compact(&bitvals(&[true, false])).is_err());
+    assert!(tab.compact(&Vals::I64s(vec![1, 0, 1, 0])).is_err());
+
+    // A predicate sees the table's columns by name.
+    let pred = vec![
+        Opcode::Path(path("k")),
+        Opcode::Literal(Vals::I64s(vec![2])),
+        Opcode::PrimBinOp(PrimBinOp::Gt),
+    ];
+    assert_eq!(
+        tab.mask(&pred).unwrap(),
+        bitvals(&[false, false, true, true])
+    );
+    let out = tab.filter(&pred).unwrap();
+    assert_eq!(
+        out.cols()[1].vals(),
+        &Vals::Bins(vec![Bin::Mem(b"c".to_vec()), Bin::Mem(b"d".to_vec())])
+    );
+    assert!(tab.filter(&[Opcode::Path(path("k"))]).is_err());
+
+    // In the Vm, a record's fields shadow the context's columns.
+    let ctx = Tab {
+        cols: vec![
+            col(
+                "t",
+                Vals::zip(vec![
+                    (word("k"), Vals::I64s(vec![5, 1, 3])),
+                    (word("v"), Vals::I64s(vec![50, 10, 30])),
+                ])
+                .unwrap(),
+            ),
+            col("k", Vals::I64s(vec![0])),
+            col("lo", Vals::I64s(vec![2])),
+        ],
+    };
+    let ops = vec![
+        Opcode::Path(path("t")),
+        Opcode::Filter(vec![
+            Opcode::Path(path("k")),
+            Opcode::Path(path("lo")),
+            Opcode::PrimBinOp(PrimBinOp::Gt),
+        ]),
+        Opcode::Project(path("v")),
+    ];
+    let prog = Program::assemble(&ops).unwrap();
+    assert_eq!(prog.filters.len(), 1);
+    assert_eq!(prog.ops().unwrap(), ops);
+    let mut vm = Vm::new(ops, vec![ctx]);
+    assert_eq!(vm.run().unwrap(), Vals::I64s(vec![50, 30]));
+}
+
+#[test]
+fn test_check_filter() {
+    let t = "tab{k: [1, 2], s: [\"a\", \"b\"], r: {v: [0.5, 1.5]}}";
+    let ty = check_src(&format!("filter({}, k > 1 | r.v < 1.0)", t)).unwrap();
+    assert_eq!(ty.to_string(), "tab{k: int, s: bin, r: {v: flo}}");
+    assert!(matches!(
+        check_src(&format!("filter({}, k)", t)),
+        Err(TypeErrorKind::Mismatch { .. })
+    ));
+    assert!(matches!(
+        check_src(&format!("filter({}, q)", t)),
+        Err(TypeErrorKind::Unbound(_))
+    ));
+    // The fields are only in scope in the predicate.
+    assert!(check_src(&format!("let t = {} in filter(t, k == 1) == k", t)).is_err());
+    assert!(check_src("filter([1, 2], true)").is_err());
+}
+
+// A heap holding one long bin, as a block's would.
+struct OneBin(Vec<u8>);
+
+impl BinHeap for OneBin {
+    fn bytes(&self, block: i64, entry: i64) -> submerge_base::Result<&[u8]> {
+        match (block, entry) {
+            (7, 0) => Ok(&self.0),
+            _ => Err(submerge_base::err("no such bin")),
+        }
+    }
+}
+
+#[test]
+fn test_binfn() {
+    let bins = |v: &[&str]| Vals::Bins(v.iter().map(|s| Bin::Mem(s.as_bytes().to_vec())).collect());
+    let heap = OneBin(b"a heap bin".to_vec());
+    let f = |f: BinFn, args: Vec<Vals>| f.apply(&args, &heap);
+    let s = bins(&["Hello", "", "\u{e9}t\u{e9}"]);
+
+    assert_eq!(
+        f(BinFn::Len, vec![s.clone()]).unwrap(),
+        Vals::I64s(vec![5, 0, 5])
+    );
+    assert_eq!(
+        f(BinFn::Concat, vec![s.clone(), bins(&["!"])]).unwrap(),
+        bins(&["Hello!", "!", "\u{e9}t\u{e9}!"])
+    );
+    assert_eq!(
+        f(
+            BinFn::Slice,
+            vec![s.clone(), Vals::I64s(vec![1]), Vals::I64s(vec![3, 1, 9])]
+        )
+        .unwrap(),
+        Vals::Bins(vec![
+            Bin::Mem(b"ell".to_vec()),
+            Bin::Mem(vec![]),
+            // Bytes, not characters, so this starts inside the first.
+            Bin::Mem(vec![0xa9, b't', 0xc3, 0xa9]),
+        ])
+    );
+    assert!(f(
+        BinFn::Slice,
+        vec![s.clone(), Vals::I64s(vec![-1]), Vals::I64s(vec![1])]
+    )
+    .is_err());
+    assert_eq!(
+        f(BinFn::StartsWith, vec![s.clone(), bins(&["He"])]).unwrap(),
+        bitvals(&[true, false, false])
+    );
+    assert_eq!(
+        f(BinFn::EndsWith, vec![s.clone(), bins(&["", "", "\u{e9}"])]).unwrap(),
+        bitvals(&[true, true, true])
+    );
+    assert_eq!(
+        f(BinFn::Upper, vec![s.clone()]).unwrap(),
+        bins(&["HELLO", "", "\u{c9}T\u{c9}"])
+    );
+    assert_eq!(
+        f(BinFn::Lower, vec![s.clone()]).unwrap(),
+        bins(&["hello", "", "\u{e9}t\u{e9}"])
+    );
+    let not_utf8 = Vals::Bins(vec![Bin::Mem(vec![0xff])]);
+    assert!(f(BinFn::Lower, vec![not_utf8.clone()]).is_err());
+
+    // LIKE matches characters, unless either side isn't UTF-8.
+    let like = |s: &[&str], p: &str| f(BinFn::Like, vec![bins(s), bins(&[p])]).unwrap();
+    assert_eq!(
+        like(&["abc", "ac", "abbc", "xabc"], "a%c"),
+        bitvals(&[true, true, true, false])
+    );
+    assert_eq!(
+        like(&["\u{e9}t\u{e9}", "ete"], "_t_"),
+        bitvals(&[true, true])
+    );
+    assert_eq!(like(&["50%", "50x"], "50\\%"), bitvals(&[true, false]));
+    assert_eq!(like(&["aaaaaaaaab"], "%a%a%a%b"), bitvals(&[true]));
+    assert_eq!(like(&[""], "%"), bitvals(&[true]));
+    assert!(f(BinFn::Like, vec![bins(&["a"]), bins(&["a\\"])]).is_err());
+    assert_eq!(
+        f(
+            BinFn::Like,
+            vec![not_utf8, Vals::Bins(vec![Bin::Mem(b"_".to_vec())])]
+        )
+        .unwrap(),
+        bitvals(&[true])
+    );
+
+    // Heap bins resolve through the heap they're given.
+    let handle = Vals::Bins(vec![Bin::Heap { block: 7, entry: 0 }]);
+    assert_eq!(
+        f(BinFn::Len, vec![handle.clone()]).unwrap(),
+        Vals::I64s(vec![10])
+    );
+    let missing = Vals::Bins(vec![Bin::Heap { block: 7, entry: 1 }]);
+    assert!(f(BinFn::Len, vec![missing]).is_err());
+    assert!(f(BinFn::Len, vec![Vals::I64s(vec![1])]).is_err());
+    assert!(f(BinFn::Concat, vec![s.clone(), bins(&["a", "b"])]).is_err());
+    assert!(f(BinFn::Concat, vec![s.clone()]).is_err());
+
+    // The Vm has no heap, so its bins must be in memory.
+    let ops = vec![
+        Opcode::Literal(s.clone()),
+        Opcode::Literal(Vals::I64s(vec![0])),
+        Opcode::Literal(Vals::I64s(vec![2])),
+        Opcode::BinFn(BinFn::Slice),
+        Opcode::BinFn(BinFn::Len),
+    ];
+    let prog = Program::assemble(&ops).unwrap();
+    assert_eq!(prog.ops().unwrap(), ops);
+    assert_eq!(
+        Vm::new(ops, vec![]).run().unwrap(),
+        Vals::I64s(vec![2, 0, 2])
+    );
+    let ops = vec![Opcode::Literal(handle), Opcode::BinFn(BinFn::Len)];
+    assert!(Vm::new(ops, vec![]).run().is_err());
+}
+
+#[test]
+fn test_check_binfn() {
+    let ty = check_src("len(concat(\"a\", [\"b\", \"c\"]))").unwrap();
+    assert_eq!(ty, Ty::int());
+    assert_eq!(check_src("like(upper(\"a\"), \"A%\")").unwrap(), Ty::bit());
+    assert_eq!(check_src("slice(\"abc\", 1, 1)").unwrap(), Ty::bin());
+    assert!(matches!(
+        check_src("slice(\"abc\", \"a\", 1)"),
+        Err(TypeErrorKind::Mismatch { .. })
+    ));
+    assert!(matches!(
+        check_src("len(1)"),
+        Err(TypeErrorKind::Mismatch { .. })
+    ));
+    assert!(parse("len(\"a\", \"b\")").is_err());
+}
+
+fn timed(form: Form, v: &[i64]) -> Vals {
+    Vals::Rich(Box::new(col("_0", Vals::I64s(v.to_vec())).with_form(form)))
+}
+
+#[test]
+fn test_temporal() {
+    let bin = |a, op, b| bin_with(a, op, b, Overflow::Check);
+    let time = |f: TimeFn, args: Vec<Vals>| f.apply(&args).unwrap();
+    // 2024-02-29T13:45:30.5Z, and a second before the epoch.
+    let ts = timed(Form::TIMESTAMP, &[1_709_214_330_500_000, -1_000_000]);
+    let day = timed(Form::DATE, &[19_782, -1]);
+    let hour = timed(Form::DURATION, &[3_600_000_000]);
+
+    assert_eq!(
+        time(TimeFn::Year, vec![ts.clone()]),
+        Vals::I64s(vec![2024, 1969])
+    );
+    assert_eq!(
+        time(TimeFn::Month, vec![ts.clone()]),
+        Vals::I64s(vec![2, 12])
+    );
+    assert_eq!(
+        time(TimeFn::Day, vec![day.clone()]),
+        Vals::I64s(vec![29, 31])
+    );
+    assert_eq!(
+        time(TimeFn::Hour, vec![ts.clone()]),
+        Vals::I64s(vec![13, 23])
+    );
+    assert_eq!(
+        time(TimeFn::Minute, vec![ts.clone()]),
+        Vals::I64s(vec![45, 59])
+    );
+    assert_eq!(
+        time(TimeFn::Second, vec![ts.clone()]),
+        Vals::I64s(vec![30, 59])
+    );
+    assert_eq!(time(TimeFn::ToDate, vec![ts.clone()]), day);
+    assert_eq!(
+        time(TimeFn::TruncMonth, vec![ts.clone()]),
+        timed(
+            Form::TIMESTAMP,
+            &[1_706_745_600_000_000, -2_678_400_000_000]
+        )
+    );
+    assert_eq!(
+        time(TimeFn::TruncYear, vec![day.clone()]),
+        timed(Form::DATE, &[19_723, -365])
+    );
+    assert_eq!(
+        time(TimeFn::TruncHour, vec![ts.clone()]),
+        timed(Form::TIMESTAMP, &[1_709_211_600_000_000, -3_600_000_000])
+    );
+    assert_eq!(
+        time(TimeFn::TruncDay, vec![ts.clone()]),
+        time(TimeFn::ToTimestamp, vec![day.clone()])
+    );
+
+    // Adding months keeps the time of day and clamps to the month's end.
+    assert_eq!(
+        time(TimeFn::AddMonths, vec![ts.clone(), Vals::I64s(vec![12])]),
+        timed(
+            Form::TIMESTAMP,
+            &[1_740_750_330_500_000, 31_535_999_000_000]
+        )
+    );
+    assert_eq!(
+        time(
+            TimeFn::AddMonths,
+            vec![day.clone(), Vals::I64s(vec![-3, 2])]
+        ),
+        timed(Form::DATE, &[19_690, 58])
+    );
+    assert!(TimeFn::AddMonths
+        .apply(&[day.clone(), day.clone()])
+        .is_err());
+    assert!(TimeFn::Hour.apply(std::slice::from_ref(&day)).is_err());
+    assert!(TimeFn::Year.apply(&[Vals::I64s(vec![0])]).is_err());
+    assert!(TimeFn::Year
+        .apply(&[timed(Form::DATE, &[i64::MAX])])
+        .is_err());
+    assert!(TimeFn::ToTimestamp
+        .apply(&[timed(Form::DATE, &[1 << 40])])
+        .is_err());
+
+    // Arithmetic changes forms, and mixing them wrongly is an error.
+    let d = bin(ts.clone(), PrimBinOp::Sub, ts.clone()).unwrap();
+    assert_eq!(d, timed(Form::DURATION, &[0, 0]));
+    let later = bin(ts.clone(), PrimBinOp::Add, hour.clone()).unwrap();
+    assert_eq!(
+        time(TimeFn::Hour, vec![later.clone()]),
+        Vals::I64s(vec![14, 0])
+    );
+    assert_eq!(
+        bin(later, PrimBinOp::Gt, ts.clone()).unwrap(),
+        bitvals(&[true, true])
+    );
+    assert_eq!(
+        bin(day.clone(), PrimBinOp::Add, Vals::I64s(vec![1])).unwrap(),
+        timed(Form::DATE, &[19_783, 0])
+    );
+    assert_eq!(
+        bin(day.clone(), PrimBinOp::Sub, day.clone()).unwrap(),
+        Vals::I64s(vec![0, 0])
+    );
+    assert_eq!(
+        bin(hour.clone(), PrimBinOp::Mul, Vals::I64s(vec![2])).unwrap(),
+        timed(Form::DURATION, &[7_200_000_000])
+    );
+    assert!(bin(ts.clone(), PrimBinOp::Add, ts.clone()).is_err());
+    assert!(bin(ts.clone(), PrimBinOp::Add, Vals::I64s(vec![1])).is_err());
+    assert!(bin(day.clone(), PrimBinOp::Add, hour.clone()).is_err());
+    assert!(bin(ts.clone(), PrimBinOp::Lt, day.clone()).is_err());
+
+    let ops = vec![
+        Opcode::Literal(ts),
+        Opcode::Literal(Vals::I64s(vec![1])),
+        Opcode::TimeFn(TimeFn::AddMonths),
+        Opcode::TimeFn(TimeFn::Month),
+    ];
+    let prog = Program::assemble(&ops).unwrap();
+    assert_eq!(prog.ops().unwrap(), ops);
+    assert_eq!(Vm::new(ops, vec![]).run().unwrap(), Vals::I64s(vec![3, 1]));
+}
+
+#[test]
+fn test_check_temporal() {
+    let ty = |src: &str| check_src(src).map(|t| t.to_string());
+    assert_eq!(ty("t.ts - t.ts").unwrap(), "int form duration");
+    assert_eq!(ty("t.ts + duration(1)").unwrap(), "int form timestamp");
+    assert_eq!(ty("t.day + 1").unwrap(), "int form date");
+    assert_eq!(ty("t.day - date(t.ts)").unwrap(), "int");
+    assert_eq!(ty("t.ts < timestamp(t.day)").unwrap(), "bit");
+    assert_eq!(ty("year(t.day) + month(t.ts)").unwrap(), "int");
+    assert_eq!(ty("trunc_month(t.ts)").unwrap(), "int form timestamp");
+    assert_eq!(ty("add_months(t.day, 1)").unwrap(), "int form date");
+    assert!(matches!(
+        ty("t.ts + t.ts"),
+        Err(TypeErrorKind::Mismatch { .. })
+    ));
+    assert!(matches!(
+        ty("t.day + t.ts"),
+        Err(TypeErrorKind::Mismatch { .. })
+    ));
+    assert!(matches!(ty("hour(t.day)"), Err(TypeErrorKind::NotA { .. })));
+    assert!(matches!(
+        ty("year(t.price)"),
+        Err(TypeErrorKind::NotA { .. })
+    ));
+    assert!(matches!(
+        ty("add_months(t.day, t.day)"),
+        Err(TypeErrorKind::Mismatch { .. })
+    ));
+    assert!(matches!(
+        ty("year(1.5)"),
+        Err(TypeErrorKind::Mismatch { .. })
+    ));
+}
+
+fn opt(v: Vals, nulls: &[usize]) -> Vals {
+    Vals::Opt(nulls.iter().copied().collect(), Box::new(v))
+}
+
+#[test]
+fn test_nulls() {
+    use AggOp::*;
+    let bin = |a, op, b| bin_with(a, op, b, Overflow::Check).map(|v| v.to_rows());
+    let ints = opt(Vals::I64s(vec![1, 0, 3]), &[1]);
+    assert_eq!(ints.len(), 3);
+    assert_eq!(ints.get(1), Some(Val::Null));
+    assert_eq!(ints.to_rows(), vec![Val::Int(1), Val::Null, Val::Int(3)]);
+    assert_eq!(Ty::of_vals(&ints), Ty::int());
+    assert!(opt(Vals::I64s(vec![1]), &[1]).validate().is_err());
+
+    // Pushing a null into a column without nulls gives it some.
+    let mut v = rich("x", Vals::F64s(vec![]));
+    v.push(Val::Flo(1.0.into())).unwrap();
+    v.push(Val::Null).unwrap();
+    assert_eq!(v.to_rows(), vec![Val::Flo(1.0.into()), Val::Null]);
+    assert!(matches!(&v, Vals::Rich(c) if matches!(c.vals(), Vals::Opt(..))));
+    assert_eq!(v.take(&[1, 0, 1]).unwrap().get(2), Some(Val::Null));
+    assert_eq!(v.take(&[0]).unwrap(), rich("x", flo(&[1.0])));
+
+    // Elementwise ops give nulls where either side is null, and a null
+    // scalar nulls everything. The op never sees the placeholders, so a
+    // null divisor isn't a division by zero.
+    assert_eq!(
+        bin(ints.clone(), PrimBinOp::Add, Vals::I64s(vec![10])).unwrap(),
+        vec![Val::Int(11), Val::Null, Val::Int(13)]
+    );
+    assert_eq!(
+        bin(
+            Vals::I64s(vec![1, 2, 3]),
+            PrimBinOp::Lt,
+            opt(Vals::I64s(vec![0]), &[0])
+        )
+        .unwrap(),
+        vec![Val::Null; 3]
+    );
+    assert_eq!(
+        bin(Vals::I64s(vec![6, 6, 6]), PrimBinOp::Div, ints.clone()).unwrap(),
+        vec![Val::Int(6), Val::Null, Val::Int(2)]
+    );
+    assert!(bin(ints.clone(), PrimBinOp::Add, Vals::I64s(vec![1, 2])).is_err());
+    let cents = |v| Vals::Rich(Box::new(col("c", v).with_form(dec(10, 2))));
+    let double = |v| {
+        bin_with(
+            cents(v),
+            PrimBinOp::Mul,
+            Vals::I64s(vec![2]),
+            Overflow::Check,
+        )
+    };
+    let out = double(ints.clone()).unwrap();
+    let want = double(Vals::I64s(vec![1, 0, 3])).unwrap();
+    assert_eq!(Ty::of_vals(&out), Ty::of_vals(&want));
+    assert_eq!(out.get(1), Some(Val::Null));
+    let neg = Vm::new(
+        vec![
+            Opcode::Literal(ints.clone()),
+            Opcode::PrimUnOp(PrimUnOp::Neg),
+        ],
+        vec![],
+    )
+    .run()
+    .unwrap();
+    assert_eq!(neg.to_rows(), vec![Val::Int(-1), Val::Null, Val::Int(-3)]);
+
+    // Or and Max of bits are true if either side is, and Min false if
+    // either side is, whatever the other side is.
+    let tf = bitvals(&[true, false, true, false]);
+    let some = opt(bitvals(&[false, false, true, false]), &[0, 1]);
+    let (t, f) = (Val::Bit(true), Val::Bit(false));
+    assert_eq!(
+        bin(tf.clone(), PrimBinOp::Or, some.clone()).unwrap(),
+        vec![t.clone(), Val::Null, t.clone(), f.clone()]
+    );
+    assert_eq!(
+        bin(some.clone(), PrimBinOp::Min, tf.clone()).unwrap(),
+        vec![Val::Null, f.clone(), t.clone(), f.clone()]
+    );
+    assert_eq!(
+        bin(some.clone(), PrimBinOp::Xor, tf.clone()).unwrap(),
+        vec![Val::Null, Val::Null, f.clone(), f.clone()]
+    );
+    let count = |v: Vals| {
+        Vm::new(
+            vec![Opcode::Literal(v), Opcode::PrimUnOp(PrimUnOp::BitCount)],
+            vec![],
+        )
+        .run()
+    };
+    assert_eq!(count(some.clone()).unwrap(), Vals::I64s(vec![1]));
+
+    // Aggregates skip nulls, and only a count of nothing but nulls isn't
+    // null.
+    assert_eq!(agg(Count, ints.clone()).unwrap(), Vals::I64s(vec![2]));
+    assert_eq!(agg(Sum, ints.clone()).unwrap(), Vals::I64s(vec![4]));
+    assert_eq!(agg(Mean, ints.clone()).unwrap(), flo(&[2.0]));
+    assert_eq!(agg(Min, ints.clone()).unwrap(), Vals::I64s(vec![1]));
+    let none = opt(Vals::I64s(vec![0, 0]), &[0, 1]);
+    assert_eq!(agg(Count, none.clone()).unwrap(), Vals::I64s(vec![0]));
+    for op in [Sum, Min, Max, Mean] {
+        assert_eq!(agg(op, none.clone()).unwrap().to_rows(), vec![Val::Null]);
+    }
+    assert_eq!(
+        agg(Max, opt(Vals::I64s(vec![]), &[])).unwrap(),
+        Vals::I64s(vec![])
+    );
+
+    // Bin and calendar functions propagate nulls too.
+    let words = opt(
+        Vals::Bins(vec![Bin::Mem(b"abc".to_vec()), Bin::Mem(vec![])]),
+        &[1],
+    );
+    assert_eq!(
+        BinFn::Len
+            .apply(&[words], &OneBin(vec![]))
+            .unwrap()
+            .to_rows(),
+        vec![Val::Int(3), Val::Null]
+    );
+    let days = Vals::Rich(Box::new(
+        col("_0", opt(Vals::I64s(vec![0, 0]), &[0])).with_form(Form::DATE),
+    ));
+    assert_eq!(
+        TimeFn::Year.apply(&[days]).unwrap().to_rows(),
+        vec![Val::Null, Val::Int(1970)]
+    );
+}
+
+#[test]
+fn test_null_tables() {
+    let tab = Tab::from_named(vec![
+        (word("k"), opt(Vals::I64s(vec![2, 0, 1, 0, 2]), &[1, 3])),
+        (word("v"), Vals::I64s(vec![10, 20, 30, 40, 50])),
+    ])
+    .unwrap();
+    let column = |t: &Tab, i: usize| t.cols()[i].vals().to_rows();
+    let ints = |v: &[Option<i64>]| -> Vec<Val> {
+        v.iter().map(|x| x.map_or(Val::Null, Val::Int)).collect()
+    };
+
+    // Sorts put nulls last, or first when descending.
+    let out = tab.sort_by(&sort_keys(&[("k", false)])).unwrap();
+    assert_eq!(
+        column(&out, 0),
+        ints(&[Some(1), Some(2), Some(2), None, None])
+    );
+    assert_eq!(
+        column(&out, 1),
+        ints(&[Some(30), Some(10), Some(50), Some(20), Some(40)])
+    );
+    let out = tab.sort_by(&sort_keys(&[("k", true)])).unwrap();
+    assert_eq!(
+        column(&out, 0),
+        ints(&[None, None, Some(2), Some(2), Some(1)])
+    );
+
+    // Null keys group together.
+    let g = grouping(&["k"], &[("n", AggOp::Count, "v"), ("s", AggOp::Sum, "v")]);
+    let out = tab.group_by(&g, Overflow::Check).unwrap();
+    assert_eq!(column(&out, 0), ints(&[Some(1), Some(2), None]));
+    assert_eq!(column(&out, 1), ints(&[Some(1), Some(2), Some(2)]));
+    assert_eq!(column(&out, 2), ints(&[Some(30), Some(60), Some(60)]));
+
+    // A null in a filter's mask drops its row.
+    let mask = opt(bitvals(&[true, true, false, true, true]), &[0]);
+    let out = tab.compact(&mask).unwrap();
+    assert_eq!(column(&out, 1), ints(&[Some(20), Some(40), Some(50)]));
+    assert_eq!(tab.compact(&opt(bitvals(&[true]), &[0])).unwrap().len(), 0);
+    let pred = vec![
+        Opcode::Path(path("k")),
+        Opcode::Literal(Vals::I64s(vec![1])),
+        Opcode::PrimBinOp(PrimBinOp::Gt),
+    ];
+    assert_eq!(
+        column(&tab.filter(&pred).unwrap(), 1),
+        ints(&[Some(10), Some(50)])
+    );
+
+    // Null keys match nothing, even each other, and a left join keeps them.
+    let right = Tab::from_named(vec![
+        (word("k"), opt(Vals::I64s(vec![2, 0]), &[1])),
+        (word("w"), Vals::I64s(vec![7, 8])),
+    ])
+    .unwrap();
+    let out = tab
+        .join(&right, &join(JoinKind::Inner, &[("k", "k")]))
+        .unwrap();
+    assert_eq!(column(&out, 1), ints(&[Some(10), Some(50)]));
+    let out = tab
+        .join(&right, &join(JoinKind::Left, &[("k", "k")]))
+        .unwrap();
+    assert_eq!(column(&out, 2), ints(&[Some(7), None, None, None, Some(7)]));
+
+    // A record with nulls goes through the Vm like any other.
+    let rec = Vals::zip(tab.named()).unwrap();
+    let ops = vec![
+        Opcode::Literal(rec),
+        Opcode::Sort(sort_keys(&[("k", false)])),
+    ];
+    let out = Vm::new(ops, vec![]).run().unwrap();
+    assert_eq!(out.field(&word("k")).unwrap().get(4), Some(Val::Null));
+}
+
+#[test]
+fn test_null_validity() {
+    let nulls = [0, 5, 255, 256, 599];
+    let v = rich("x", opt(Vals::I64s(vec![7; 600]), &nulls));
+    let chunks = v.validity();
+    assert_eq!(chunks.len(), 3);
+    assert_eq!(
+        chunks.iter().map(|c| c.count()).collect::<Vec<_>>(),
+        vec![253, 255, 87]
+    );
+    assert!(!chunks[1].get(0) && chunks[1].get(1) && !chunks[2].get(87));
+    let plain = rich("x", Vals::I64s(vec![7; 600]));
+    assert!(plain
+        .validity()
+        .iter()
+        .all(|c| c.count() == 256 || c.count() == 88));
+    assert_eq!(plain.with_validity(&chunks).unwrap(), v);
+    assert_eq!(v.with_validity(&plain.validity()).unwrap(), plain);
+    assert!(plain.with_validity(&chunks[..2]).is_err());
+    assert!(Vals::I64s(vec![]).validity().is_empty());
+}
+
+fn fo_src(src: &str) -> Result<(), (FoErrorKind, Span)> {
+    let p = parse(src).unwrap();
+    check_fo(&p.expr, Some(&p.spans)).map_err(|e| (e.kind, e.span.unwrap()))
+}
+
+#[test]
+fn test_check_fo() {
+    // First-order fns, bound by let or applied directly, are fine, as is
+    // everything without fns.
+    for src in [
+        "t.n + 1",
+        "let f = fn(x, y) x + y in f(1, f(2, 3))",
+        "let f = fn(x) x * 2 in let g = fn(y) f(y) + f(1) in g(t.n)",
+        "(fn(a) a + 1)(2)",
+        "let f = fn(x) x in let g = f in g(1)",
+        "let f = fn(x) x in let f = fn(y) f(y) in f(1)",
+        "filter(t, (fn(x) x > 1)(k))",
+    ] {
+        assert_eq!(fo_src(src), Ok(()), "{}", src);
+    }
+
+    // A fn can't be passed, returned or stored, so nothing can call itself.
+    let fn_value = |src: &str| match fo_src(src) {
+        Err((FoErrorKind::FnValue, span)) => span,
+        other => panic!("{}: {:?}", src, other),
+    };
+    assert_eq!(fn_value("fn(x) x"), Span::new(0, 7));
+    assert_eq!(fn_value("let f = fn(x) x in f"), Span::new(19, 20));
+    assert_eq!(fn_value("let f = fn(x) x in {g: f}"), Span::new(23, 24));
+    assert_eq!(fn_value("let f = fn(x) fn(y) x in 1"), Span::new(14, 21));
+    assert_eq!(
+        fn_value("let f = fn(x) x in (fn(g) 1)(f)"),
+        Span::new(29, 30)
+    );
+    assert_eq!(
+        fn_value("if true then fn(x) x else fn(x) 1"),
+        Span::new(13, 20)
+    );
+
+    // Only let-bound fns and fn literals can be applied.
+    assert_eq!(
+        fo_src("(fn(g) g(1))(2)"),
+        Err((FoErrorKind::HigherOrder(word("g")), Span::new(7, 8)))
+    );
+    assert_eq!(
+        fo_src("let x = 1 in x(2)"),
+        Err((FoErrorKind::HigherOrder(word("x")), Span::new(13, 14)))
+    );
+    assert_eq!(
+        fo_src("t.n(1)"),
+        Err((FoErrorKind::NotAFn(path("t.n")), Span::new(0, 3)))
+    );
+    // A let's name isn't in scope in its own value.
+    assert_eq!(
+        fo_src("let f = fn(x) f(x) in f(1)"),
+        Err((FoErrorKind::NotAFn(path("f")), Span::new(14, 15)))
+    );
+    assert!(matches!(
+        fo_src("(if true then t.n else t.n)(1)"),
+        Err((FoErrorKind::Callee, _))
+    ));
+
+    let e = check_fo(&parse("fn(x) x").unwrap().expr, None).unwrap_err();
+    assert_eq!(e.node, 0);
+    assert!(e.to_string().starts_with("not FO at node 0: a fn can only"));
+}
+
+#[test]
+fn test_canonical() {
+    let srcs = [
+        "let f = fn(x, y) if x <= y then min(x, y) else sqrt(15.0) in f(1, 2)",
+        "1 + 2 * 3 ** 2 ** 2 < x.y | !b",
+        "merge({a: [1, 2], b: [\"p\", \"q\\x00\\\"\"]}, query(reify, t.c))",
+        "tab{k: [true, false], v: f64[], w: [nan, -inf, 1e300, -0.5, -0.0]}",
+        "pass",
+        "group(t, [k, r.j], {n: count(v), top: max(r.v)})",
+        "left_join(a, tab{k: 1}, [k = k, r.j = id])",
+        "sort(filter(t, k > 1 | r.v == x), [k, r.v desc])",
+        "add_months(date(t.ts), 2) - len(concat(s, \"!\"))",
+        "cast(cast(t.x, dec(9, 2), lossy), bin)",
+    ];
+    for src in srcs {
+        let e: Expr = src.parse().unwrap();
+        let bytes = e.canonical();
+        assert_eq!(Expr::from_canonical(&bytes).unwrap(), e);
+        assert_eq!(e.clone().canonical(), bytes);
+    }
+    let lits = [
+        Vals::Bits(70, [0, 69].into_iter().collect()),
+        Vals::Bins(vec![Bin::Heap {
+            block: 3,
+            entry: -1,
+        }]),
+        Vals::Rich(Box::new(col("d", Vals::I64s(vec![5])).with_form(dec(9, 2)))),
+        opt(Vals::I64s(vec![1, 2, 3]), &[1]),
+        Vals::All(vec![
+            Vals::I64s(vec![]),
+            opt(Vals::Bits(2, bs::Bs::new()), &[0]),
+        ]),
+        Vals::Any(
+            vec![1, 0],
+            vec![Vals::I64s(vec![7]), Vals::Bits(1, bs::Bs::new())],
+        ),
+    ];
+    for v in lits {
+        let e = Expr::Lit(v);
+        assert_eq!(Expr::from_canonical(&e.canonical()).unwrap(), e);
+    }
+
+    // The encoding is stable across versions of the code.
+    assert_eq!(Expr::Pass.canonical(), [1, 0]);
+    assert_eq!(int(-2).canonical(), [1, 1, 0, 1, 3]);
+    let e = Expr::binop(PrimBinOp::Add, int(1), Expr::var(word("x")));
+    assert_eq!(
+        e.canonical(),
+        [1, 7, 3, b'a', b'd', b'd', 1, 0, 1, 2, 2, 1, 0, 1, b'x']
+    );
+
+    // Equal Exprs encode alike, whatever their representation.
+    let mut big: bs::Bs = [3, 100].into_iter().collect();
+    big.remove(100);
+    let small: bs::Bs = [3].into_iter().collect();
+    assert_ne!(big, small);
+    let (a, b) = (
+        Expr::Lit(Vals::Bits(4, big)),
+        Expr::Lit(Vals::Bits(4, small)),
+    );
+    assert_eq!(a.canonical(), b.canonical());
+    assert_eq!(a.content_hash(), b.content_hash());
+    let nan = |bits| Expr::Lit(Vals::F64s(vec![f64::from_bits(bits).into()]));
+    assert_eq!(
+        nan(0x7ff8_0000_0000_0001).canonical(),
+        nan(0xfff8_0000_0000_0000).canonical()
+    );
+    assert_eq!(
+        "(1 + 2)".parse::<Expr>().unwrap().content_hash(),
+        e_hash("1 + 2")
+    );
+    assert_ne!(e_hash("1 + 2"), e_hash("2 + 1"));
+    assert_ne!(e_hash("0.0"), e_hash("-0.0"));
+
+    // Anything else is rejected.
+    let good = e.canonical();
+    let bad = [
+        vec![],
+        vec![2, 0],
+        vec![1, 99],
+        vec![1, 0, 0],
+        good[..good.len() - 1].to_vec(),
+        // An overlong varint for the length of the op's name.
+        [&good[..2], &[0x83, 0x00], &good[3..]].concat(),
+        // A bits literal whose set row is past its end.
+        vec![1, 1, 2, 1, 1, 1],
+        // Rows of a bitset out of order.
+        vec![1, 1, 2, 4, 2, 2, 1],
+        // An unknown op.
+        [&good[..3], b"abc", &good[6..]].concat(),
+    ];
+    for b in bad {
+        assert!(Expr::from_canonical(&b).is_err(), "{:?}", b);
+    }
+
+    // Nesting is bounded, rather than overflowing the stack.
+    let quoted = |n: usize| [&[1][..], &vec![22; n], &[0]].concat();
+    let e = Expr::from_canonical(&quoted(191)).unwrap();
+    assert_eq!(Expr::from_canonical(&e.canonical()).unwrap(), e);
+    assert!(Expr::from_canonical(&quoted(192)).is_err());
+    let e = Expr::from_canonical(&quoted(100_000)).unwrap_err();
+    assert!(e.is_corruption(), "{}", e);
+}
+
+fn e_hash(src: &str) -> u64 {
+    src.parse::<Expr>().unwrap().content_hash()
+}
+
+#[test]
+fn test_intern() {
+    let mut interner = Interner::new();
+    let src = "let y = (x + 1) * (x + 1) in {a: x + 1, b: f(x + 1, 2)}";
+    let (a, _) = interner.parse(src).unwrap();
+    // let, *, the two + and their x and 1, rec, app, f and 2.
+    assert_eq!(interner.len(), 9);
+    assert_eq!(a.to_expr(), src.parse::<Expr>().unwrap());
+
+    // Equal subtrees are the same node.
+    let [_, body] = a.children() else { panic!() };
+    let [sum, _] = body.children() else { panic!() };
+    let [_, app] = body.children() else { panic!() };
+    assert_eq!(&app.children()[1], sum);
+    assert_eq!(interner.intern(&"x + 1".parse().unwrap()), *sum);
+    let (again, _) = interner.parse(src).unwrap();
+    assert_eq!(again, a);
+    assert_eq!(interner.len(), 9);
+
+    // Unequal ones aren't, nor are nodes from another interner.
+    assert_ne!(interner.intern(&"1 + x".parse().unwrap()), *sum);
+    assert_ne!(Interner::new().intern(&"x + 1".parse().unwrap()), *sum);
+    assert_eq!(a.shell(), &Expr::let_(word("y"), Expr::Pass, Expr::Pass));
+}
+
+fn cast_to(target: &str) -> Cast {
+    match format!("cast(x, {})", target).parse::<Expr>().unwrap() {
+        Expr::Cast(_, c) => c,
+        _ => unreachable!(),
+    }
+}
+
+fn bins(v: &[&str]) -> Vals {
+    Vals::Bins(v.iter().map(|s| Bin::Mem(s.as_bytes().to_vec())).collect())
+}
+
+#[test]
+fn test_cast() {
+    let cast = |v: &Vals, target: &str| {
+        let ops = vec![Opcode::Literal(v.clone()), Opcode::Cast(cast_to(target))];
+        Vm::new(ops, vec![]).run()
+    };
+    let ints = |v: &[i64]| Vals::I64s(v.to_vec());
+    let bits = |v: &[bool]| bitvals(v);
+
+    // Ints and flos.
+    assert_eq!(cast(&ints(&[1, -2]), "flo").unwrap(), flo(&[1.0, -2.0]));
+    assert!(cast(&ints(&[i64::MAX]), "flo").is_err());
+    assert_eq!(
+        cast(&ints(&[i64::MAX]), "flo, saturate").unwrap(),
+        flo(&[9.223372036854776e18])
+    );
+    let halves = flo(&[1.5, -2.5, 2.0]);
+    assert!(cast(&halves, "int").is_err());
+    assert_eq!(cast(&halves, "int, saturate").unwrap(), ints(&[2, -3, 2]));
+    assert_eq!(cast(&flo(&[2.0]), "int").unwrap(), ints(&[2]));
+    let odd = flo(&[f64::NAN, 1e300, -1e300]);
+    assert!(cast(&odd, "int, saturate").is_err());
+    assert_eq!(
+        cast(&flo(&[1e300, -1e300]), "int, saturate").unwrap(),
+        ints(&[i64::MAX, i64::MIN])
+    );
+    assert_eq!(
+        cast(&odd, "int, lossy").unwrap().to_rows(),
+        vec![Val::Null; 3]
+    );
+
+    // Decimals rescale, and their precision bounds them.
+    let d52 = dec(5, 2);
+    assert_eq!(
+        cast(&ints(&[123, -7]), "dec(5, 2)").unwrap(),
+        timed(d52, &[12300, -700])
+    );
+    assert!(cast(&ints(&[1000]), "dec(5, 2)").is_err());
+    assert_eq!(
+        cast(&ints(&[1000, -1000]), "dec(5, 2), saturate").unwrap(),
+        timed(d52, &[99999, -99999])
+    );
+    assert_eq!(
+        cast(&ints(&[1000, 1]), "dec(5, 2), lossy").unwrap(),
+        Vals::Rich(Box::new(
+            col("_0", opt(ints(&[0, 100]), &[0])).with_form(d52)
+        ))
+    );
+    let prices = timed(d52, &[12345, -250]);
+    assert!(cast(&prices, "int").is_err());
+    assert_eq!(cast(&prices, "int, saturate").unwrap(), ints(&[123, -3]));
+    assert_eq!(
+        cast(&prices, "dec(9, 3)").unwrap(),
+        timed(dec(9, 3), &[123450, -2500])
+    );
+    assert_eq!(
+        cast(&prices, "dec(4, 1), saturate").unwrap(),
+        timed(dec(4, 1), &[1235, -25])
+    );
+    assert_eq!(cast(&prices, "flo").unwrap(), flo(&[123.45, -2.5]));
+    assert_eq!(cast(&flo(&[0.29]), "dec(5, 2)").unwrap(), timed(d52, &[29]));
+    assert!(cast(&flo(&[0.125]), "dec(5, 2)").is_err());
+    assert_eq!(
+        cast(&flo(&[0.125]), "dec(5, 2), saturate").unwrap(),
+        timed(d52, &[13])
+    );
+
+    // Bits are 0 and 1.
+    assert_eq!(cast(&bits(&[true, false]), "int").unwrap(), ints(&[1, 0]));
+    assert_eq!(
+        cast(&bits(&[true]), "dec(5, 2)").unwrap(),
+        timed(d52, &[100])
+    );
+    let some = ints(&[0, 1, 2, -1]);
+    assert!(cast(&some, "bit").is_err());
+    assert_eq!(
+        cast(&some, "bit, saturate").unwrap(),
+        bits(&[false, true, true, false])
+    );
+    assert_eq!(
+        cast(&some, "bit, lossy").unwrap(),
+        opt(bits(&[false, true, false, false]), &[2, 3])
+    );
+    assert_eq!(
+        cast(&flo(&[0.0, 1.0]), "bit").unwrap(),
+        bits(&[false, true])
+    );
+
+    // Text is what the printer writes, and reads back.
+    let noon = 1_706_702_400_000_000;
+    let texts = [
+        (ints(&[42, -1]), vec!["42", "-1"]),
+        (prices.clone(), vec!["123.45", "-2.50"]),
+        (bits(&[true, false]), vec!["true", "false"]),
+        (
+            timed(Form::TIMESTAMP, &[noon, -1]),
+            vec!["2024-01-31T12:00:00Z", "1969-12-31T23:59:59.999999Z"],
+        ),
+        (
+            timed(Form::DATE, &[19_753, -719_529]),
+            vec!["2024-01-31", "-0001-12-31"],
+        ),
+        (timed(Form::DURATION, &[90]), vec!["90"]),
+    ];
+    for (v, text) in texts {
+        assert_eq!(cast(&v, "bin").unwrap(), bins(&text));
+        let back = match &v {
+            Vals::Rich(col) => col.form.to_string(),
+            Vals::Bits(..) => "bit".to_string(),
+            _ => "int".to_string(),
+        };
+        assert_eq!(cast(&bins(&text), &back).unwrap(), v);
+    }
+    assert_eq!(
+        cast(&flo(&[0.5, f64::NAN, f64::NEG_INFINITY, 1e300]), "bin").unwrap(),
+        bins(&["0.5", "nan", "-inf", "1e300"])
+    );
+    assert_eq!(
+        cast(&bins(&["-inf"]), "flo").unwrap(),
+        flo(&[f64::NEG_INFINITY])
+    );
+    for (text, target) in [
+        ("x", "int"),
+        ("1.234", "dec(5, 2)"),
+        ("yes", "bit"),
+        ("2024-02-30", "date"),
+        ("2024-1-31", "date"),
+        ("2024-01-31T24:00:00Z", "timestamp"),
+        ("2024-01-31T12:00:00.5Z", "timestamp"),
+    ] {
+        assert!(cast(&bins(&[text]), target).is_err(), "{}", text);
+        assert!(cast(&bins(&[text]), &format!("{}, saturate", target)).is_err());
+        let lossy = cast(&bins(&[text]), &format!("{}, lossy", target)).unwrap();
+        assert_eq!(lossy.to_rows(), vec![Val::Null]);
+    }
+    let heap = Vals::Bins(vec![Bin::Heap { block: 0, entry: 0 }]);
+    assert!(cast(&heap, "int").is_err());
+    assert_eq!(cast(&heap, "bin").unwrap(), heap);
+
+    // Times move between timestamps, dates and plain ints, rounding down to
+    // the day.
+    let ts = timed(Form::TIMESTAMP, &[noon, -1]);
+    assert!(cast(&ts, "date").is_err());
+    assert_eq!(
+        cast(&ts, "date, saturate").unwrap(),
+        timed(Form::DATE, &[19_753, -1])
+    );
+    assert_eq!(
+        cast(&timed(Form::DATE, &[19_753]), "timestamp").unwrap(),
+        timed(Form::TIMESTAMP, &[noon - 12 * 3_600_000_000])
+    );
+    assert_eq!(cast(&ts, "int").unwrap(), ints(&[noon, -1]));
+    assert_eq!(
+        cast(&ints(&[5]), "duration").unwrap(),
+        timed(Form::DURATION, &[5])
+    );
+    for (v, target) in [
+        (timed(Form::DURATION, &[5]), "date"),
+        (prices.clone(), "date"),
+        (flo(&[1.0]), "timestamp"),
+        (ts.clone(), "flo"),
+        (rich("a", Vals::All(vec![ints(&[1])])), "int"),
+    ] {
+        assert!(cast(&v, target).is_err());
+    }
+
+    // Numbers keep their units.
+    let len = Vals::Rich(Box::new(col("len", ints(&[3])).with_unit(unit("m"))));
+    let as_flo = Vals::Rich(Box::new(col("len", flo(&[3.0])).with_unit(unit("m"))));
+    assert_eq!(cast(&len, "flo").unwrap(), as_flo);
+    assert_eq!(cast(&len, "bin").unwrap(), bins(&["3"]));
+
+    // Nulls stay null.
+    assert_eq!(
+        cast(&opt(ints(&[1, 0, 3]), &[1]), "flo").unwrap(),
+        opt(flo(&[1.0, 0.0, 3.0]), &[1])
+    );
+    assert_eq!(
+        cast(&opt(ints(&[0, 1, 5]), &[0]), "bit, lossy")
+            .unwrap()
+            .to_rows(),
+        vec![Val::Null, Val::Bit(true), Val::Null]
+    );
+
+    // The checker types casts as they run.
+    assert_eq!(check_src("cast(t.x, int)"), Ok(Ty::int()));
+    assert_eq!(
+        check_src("cast(t.n, dec(5, 2), saturate)"),
+        Ok(Ty::int().with_minor(d52))
+    );
+    assert_eq!(
+        check_src("cast(t.len, int)"),
+        Ok(Ty::int().with_role(Unit(1)))
+    );
+    assert_eq!(check_src("cast(t.len, bin)"), Ok(Ty::bin()));
+    assert_eq!(
+        check_src("cast(t.ts, date, lossy)"),
+        Ok(Ty::int().with_minor(Form::DATE))
+    );
+    assert!(matches!(
+        check_src("cast(t.ts, flo)"),
+        Err(TypeErrorKind::Mismatch { .. })
+    ));
+    assert!(matches!(
+        check_src("cast({a: 1}, int)"),
+        Err(TypeErrorKind::NotA { .. })
+    ));
+
+    for src in [
+        "cast(t.x, dec(9, 2), saturate)",
+        "cast(1.5, int)",
+        "cast(t.ts, date, lossy) + 1",
+        "cast(cast(s, bit), bin)",
+    ] {
+        let e: Expr = src.parse().unwrap();
+        assert_eq!(e.to_string(), src);
+    }
+    for src in [
+        "cast(1, dec(0, 1))",
+        "cast(1, i64)",
+        "cast(1, int, sloppy)",
+        "cast(1)",
+    ] {
+        assert!(src.parse::<Expr>().is_err(), "{}", src);
+    }
+
+    // Cast ships in a Program with its target in the cast pool.
+    let ops = vec![
+        Opcode::Literal(flo(&[1.5])),
+        Opcode::Cast(cast_to("int, saturate")),
+        Opcode::Cast(cast_to("bin")),
+    ];
+    let prog = Program::assemble(&ops).unwrap();
+    assert_eq!(prog.casts, vec![cast_to("int, saturate"), cast_to("bin")]);
+    assert_eq!(prog.ops().unwrap(), ops);
+    assert_eq!(
+        Vm::new(prog.ops().unwrap(), vec![]).run().unwrap(),
+        bins(&["2"])
+    );
+    let mut broken = prog.clone();
+    broken.casts.pop();
+    assert!(broken.ops().is_err());
+
+    // A cast to a non-scalar has an encoding of its own, but no decoding.
+    let bad = Expr::cast(
+        int(1),
+        Cast {
+            to: Major::Nil,
+            ..cast_to("bin")
+        },
+    );
+    assert_ne!(
+        bad.canonical(),
+        Expr::cast(int(1), cast_to("bin")).canonical()
+    );
+    assert!(Expr::from_canonical(&bad.canonical()).is_err());
+}
+
+#[test]
+fn test_reify_query() {
+    let outer = Tab::new(vec![
+        col(
+            "t",
+            Vals::zip(vec![
+                (word("x"), Vals::I64s(vec![1, 2])),
+                (word("y"), Vals::I64s(vec![3, 4])),
+            ])
+            .unwrap(),
+        ),
+        col("u", flo(&[0.5, 1.5])).with_unit(unit("m")),
+    ])
+    .unwrap();
+    let inner = Tab::new(vec![col(
+        "t",
+        Vals::zip(vec![(word("x"), Vals::I64s(vec![5]))]).unwrap(),
+    )])
+    .unwrap();
+    let ctx = vec![outer, inner];
+    let run = |ops: Vec<Opcode>| Vm::new(ops, ctx.clone()).run();
+    let query = |p: &str| run(vec![Opcode::Reify, Opcode::Query(path(p))]);
+
+    // Queries shadow path by path, as plain path lookups do.
+    for p in ["t.x", "t.y", "u", "t"] {
+        assert_eq!(query(p).unwrap(), run(vec![Opcode::Path(path(p))]).unwrap());
+    }
+    assert_eq!(query("t.x").unwrap(), Vals::I64s(vec![5]));
+    assert_eq!(query("t.y").unwrap(), Vals::I64s(vec![3, 4]));
+    assert_eq!(
+        query("u").unwrap(),
+        Vals::Rich(Box::new(col("u", flo(&[0.5, 1.5])).with_unit(unit("m"))))
+    );
+    assert!(query("t.z").is_err());
+    assert!(run(vec![
+        Opcode::Literal(Vals::I64s(vec![1])),
+        Opcode::Query(path("t"))
+    ])
+    .is_err());
+
+    // An empty context reifies to an empty environment.
+    let empty = Vm::new(vec![Opcode::Reify], vec![]).run().unwrap();
+    assert_eq!(empty, Vals::All(vec![]));
+
+    // A filter's predicate sees the table's columns innermost.
+    let ops = vec![
+        Opcode::Path(path("t")),
+        Opcode::Filter(vec![
+            Opcode::Reify,
+            Opcode::Query(path("y")),
+            Opcode::Literal(Vals::I64s(vec![3])),
+            Opcode::PrimBinOp(PrimBinOp::Gt),
+        ]),
+        Opcode::Project(path("x")),
+    ];
+    let outer_only = vec![ctx[0].clone()];
+    let mut vm = Vm::new(ops.clone(), outer_only);
+    assert_eq!(vm.run().unwrap(), Vals::I64s(vec![2]));
+
+    // Query ships in a Program with its path in the path pool.
+    let prog = Program::assemble(&ops).unwrap();
+    assert_eq!(prog.ops().unwrap(), ops);
+    let prog = Program::assemble(&[Opcode::Reify, Opcode::Query(path("t.x"))]).unwrap();
+    assert_eq!(prog.paths, vec![path("t.x")]);
+    let mut broken = prog.clone();
+    broken.paths.clear();
+    assert!(broken.ops().is_err());
+}
+
+#[test]
+fn test_merge() {
+    let rec = |fields: Vec<(&str, Vals)>| {
+        Vals::zip(fields.into_iter().map(|(w, v)| (word(w), v)).collect()).unwrap()
+    };
+    let merge = |a: &Vals, b: &Vals| {
+        let ops = vec![
+            Opcode::Literal(a.clone()),
+            Opcode::Literal(b.clone()),
+            Opcode::Merge,
+        ];
+        Vm::new(ops, vec![]).run()
+    };
+
+    // The right-hand side wins collisions; other fields keep their order.
+    let a = rec(vec![
+        ("a", Vals::I64s(vec![1, 2])),
+        ("b", Vals::I64s(vec![3, 4])),
+    ]);
+    let b = rec(vec![("b", flo(&[0.5, 1.5])), ("c", Vals::I64s(vec![5, 6]))]);
+    assert_eq!(
+        merge(&a, &b).unwrap(),
+        rec(vec![
+            ("a", Vals::I64s(vec![1, 2])),
+            ("b", flo(&[0.5, 1.5])),
+            ("c", Vals::I64s(vec![5, 6]))
+        ])
+    );
+
+    // Nested records merge recursively, a replaced field keeps its unit,
+    // and a one-row field broadcasts.
+    let a = rec(vec![(
+        "p",
+        rec(vec![
+            ("x", Vals::I64s(vec![1, 2])),
+            ("y", Vals::I64s(vec![3, 4])),
+        ]),
+    )]);
+    let b = rec(vec![
+        (
+            "p",
+            rec(vec![(
+                "y",
+                Vals::Rich(Box::new(col("y", Vals::I64s(vec![7])).with_unit(unit("m")))),
+            )]),
+        ),
+        ("q", Vals::I64s(vec![9])),
+    ]);
+    let merged = merge(&a, &b).unwrap();
+    assert_eq!(
+        merged.project(&[word("p"), word("x")]).unwrap(),
+        &Vals::Rich(Box::new(col("x", Vals::I64s(vec![1, 2]))))
+    );
+    assert_eq!(
+        merged.project(&[word("q")]).unwrap(),
+        &Vals::Rich(Box::new(col("q", Vals::I64s(vec![9, 9]))))
+    );
+    let y = Vals::Rich(Box::new(
+        col("y", Vals::I64s(vec![7, 7])).with_unit(unit("m")),
+    ));
+    assert_eq!(merged.project(&[word("p"), word("y")]).unwrap(), &y);
+
+    // Fields of different lengths don't make a record.
+    let c = rec(vec![("c", Vals::I64s(vec![1, 2, 3]))]);
+    assert!(merge(&a, &c).is_err());
+    assert!(merge(&a, &Vals::I64s(vec![1, 2])).is_err());
+
+    // Environments stack, the right-hand one innermost, so it shadows.
+    let outer = Tab::new(vec![
+        col("x", Vals::I64s(vec![1])),
+        col("y", Vals::I64s(vec![2])),
+    ])
+    .unwrap();
+    let inner = Tab::new(vec![col("x", Vals::I64s(vec![3]))]).unwrap();
+    let ops = vec![
+        Opcode::Reify,
+        Opcode::Literal(Vals::All(vec![Vals::All(vec![Vals::Rich(Box::new(col(
+            "x",
+            Vals::I64s(vec![3]),
+        )))])])),
+        Opcode::Merge,
+    ];
+    let env = Vm::new(ops.clone(), vec![outer.clone()]).run().unwrap();
+    let mut query = ops.clone();
+    query.push(Opcode::Query(path("x")));
+    assert_eq!(
+        Vm::new(query, vec![outer.clone()]).run().unwrap(),
+        Vals::I64s(vec![3])
+    );
+    let both = Vm::new(vec![Opcode::Reify], vec![outer.clone(), inner])
+        .run()
+        .unwrap();
+    assert_eq!(env, both);
+
+    // An environment doesn't merge with a record.
+    let mut mixed = ops;
+    mixed[1] = Opcode::Literal(rec(vec![("x", Vals::I64s(vec![3]))]));
+    assert!(Vm::new(mixed, vec![outer]).run().is_err());
+
+    // Merge ships in a Program like any other opcode.
+    let prog = Program::assemble(&[Opcode::Reify, Opcode::Reify, Opcode::Merge]).unwrap();
+    assert_eq!(
+        prog.ops().unwrap(),
+        vec![Opcode::Reify, Opcode::Reify, Opcode::Merge]
+    );
+}
+
+#[test]
+fn test_faults() {
+    use Opcode::{Case, Eval, Filter, Literal, Reify};
+    let ints = |v: &[i64]| Vals::I64s(v.to_vec());
+    let t = Tab::new(vec![col(
+        "t",
+        Vals::zip(vec![(word("x"), ints(&[1, 2]))]).unwrap(),
+    )])
+    .unwrap();
+    let eval = |ops: Vec<Opcode>| Vm::new(ops, vec![t.clone()]).eval();
+    let fault = |ops: Vec<Opcode>| eval(ops).unwrap().unwrap_err();
+    let div = |a: Vals, b: Vals| vec![Literal(a), Literal(b), Opcode::PrimBinOp(PrimBinOp::Div)];
+
+    // A fault is a result, with its kind and the failing opcode's index.
+    let mut ops = div(ints(&[1, 2]), ints(&[1, 0]));
+    ops.insert(0, Literal(ints(&[7])));
+    let f = fault(ops.clone());
+    assert_eq!(f.kind, FaultKind::DivideByZero);
+    assert_eq!(f.pc, 3);
+    assert_eq!(
+        f.to_string(),
+        "division by zero at opcode 3: integer division by zero"
+    );
+
+    // The Vm stops and keeps it; run() and step() return it as an error.
+    let mut vm = Vm::new(ops.clone(), vec![]);
+    let e = vm.run().unwrap_err();
+    assert!(e.is_eval());
+    assert_eq!(e.downcast_ref::<Fault>(), Some(&f));
+    assert!(vm.is_done());
+    assert_eq!(vm.result(), None);
+    assert_eq!(vm.fault(), Some(&f));
+    assert!(vm.step(1).is_err());
+    assert_eq!(vm.eval().unwrap(), Err(f.clone()));
+
+    // Null rows don't fault.
+    let ok = eval(div(ints(&[1, 2]), opt(ints(&[0, 2]), &[0]))).unwrap();
+    assert_eq!(ok, Ok(opt(ints(&[0, 1]), &[0])));
+
+    // Kernels give specific kinds; other failures are mismatches.
+    let overflow = vec![
+        Literal(ints(&[i64::MAX])),
+        Literal(ints(&[1])),
+        Opcode::PrimBinOp(PrimBinOp::Add),
+    ];
+    let checked = Vm::new(overflow.clone(), vec![])
+        .with_overflow(Overflow::Check)
+        .eval();
+    assert_eq!(checked.unwrap().unwrap_err().kind, FaultKind::Overflow);
+    assert!(Vm::new(overflow, vec![]).eval().unwrap().is_ok());
+    let cast = vec![Literal(bins(&["x"])), Opcode::Cast(cast_to("int"))];
+    assert_eq!(fault(cast).kind, FaultKind::Invalid);
+    let missing = fault(vec![Opcode::Path(path("y"))]);
+    assert_eq!(missing.kind, FaultKind::Mismatch);
+    assert_eq!(missing.msg, "no column y in context");
+
+    // A fault in a filter predicate is a fault of the Filter opcode.
+    let mut pred = div(ints(&[1]), ints(&[0]));
+    pred.extend([Literal(ints(&[0])), Opcode::PrimBinOp(PrimBinOp::Gt)]);
+    let f = fault(vec![
+        Literal(ints(&[0])),
+        Opcode::Path(path("t")),
+        Filter(pred),
+    ]);
+    assert_eq!((f.kind, f.pc), (FaultKind::DivideByZero, 2));
+
+    // Malformed programs trap instead: they're errors, not faults.
+    for ops in [
+        vec![Opcode::PrimBinOp(PrimBinOp::Add)],
+        vec![Reify, Eval],
+        vec![],
+    ] {
+        let mut vm = Vm::new(ops, vec![]);
+        let e = vm.eval().unwrap_err();
+        assert!(!e.is_eval());
+        assert_eq!(vm.fault(), None);
+    }
+    let trap_in_arm = vec![
+        Literal(Vals::Any(vec![0], vec![ints(&[1])])),
+        Case(vec![vec![Opcode::PrimBinOp(PrimBinOp::Add)]]),
+    ];
+    assert!(eval(trap_in_arm).is_err());
+
+    // Faults are the same however many times they're evaluated.
+    let again = div(ints(&[1]), ints(&[0]));
+    assert_eq!(fault(again.clone()), fault(again));
+}
+
+#[test]
+fn test_diagnostics() {
+    let src = "t.n + 1.0";
+    let p = parse(src).unwrap();
+    let e = check(&p.expr, &schema(), Some(&p.spans)).unwrap_err();
+    assert_eq!(
+        Diagnostic::from(&e).render(src),
+        "type error: expected int, found flo\n --> 1:7\n  |\n1 | t.n + 1.0\n  |       ^^^\n"
+    );
+
+    // Columns count chars, and a span past its line stops at the line's end.
+    let src = "let s = \"é\" in\n  s + 1";
+    let p = parse(src).unwrap();
+    let e = check(&p.expr, &schema(), Some(&p.spans)).unwrap_err();
+    let d = Diagnostic::from(&e);
+    assert!(d.render(src).ends_with("2 |   s + 1\n  |       ^\n"));
+    let whole = Diagnostic::new("note", Some(Span::new(4, src.len())), "here");
+    assert_eq!(
+        whole.render(src),
+        "note: here\n --> 1:5\n  |\n1 | let s = \"é\" in\n  |     ^^^^^^^^^^\n"
+    );
+
+    // An error at the end of the input gets one caret.
+    let src = "f(1,";
+    let e = parse(src).unwrap_err();
+    let out = Diagnostic::from(&e).render(src);
+    assert!(out.starts_with("parse error: "));
+    assert!(out.ends_with("1 | f(1,\n  |     ^\n"));
+
+    // FO rejections and faults render the same way; without a span there's
+    // just the message.
+    let src = "let f = fn(x) x in f";
+    let p = parse(src).unwrap();
+    let e = check_fo(&p.expr, Some(&p.spans)).unwrap_err();
+    assert!(Diagnostic::from(&e)
+        .render(src)
+        .starts_with("not FO: a fn can only"));
+    let mut vm = Vm::new(
+        vec![
+            Opcode::Literal(Vals::I64s(vec![1])),
+            Opcode::Literal(Vals::I64s(vec![0])),
+            Opcode::PrimBinOp(PrimBinOp::Div),
+        ],
+        vec![],
+    );
+    let f = vm.eval().unwrap().unwrap_err();
+    assert_eq!(
+        Diagnostic::fault(&f, None).render("1 / 0"),
+        "fault: division by zero: integer division by zero\n"
+    );
+    let at = Diagnostic::fault(&f, Some(Span::new(0, 5))).render("1 / 0");
+    assert!(at.ends_with("1 | 1 / 0\n  | ^^^^^\n"));
+}
+
+#[test]
+fn test_quote_splice() {
+    use Opcode::{Literal, Quote};
+    let src = |s: &str| parse(s).unwrap().expr;
+    let body = |s: &str| match src(s) {
+        Expr::Quote(b) => *b,
+        e => panic!("not a quote: {}", e),
+    };
+    let run = |ops: Vec<Opcode>| Vm::new(ops, vec![]).eval().unwrap();
+
+    // Quotes and splices print and parse back, nested or not.
+    for s in [
+        "quote(t.n + splice(k))",
+        "quote(quote(splice(splice(k))) + splice(j))",
+    ] {
+        let e = src(s);
+        assert_eq!(e.to_string(), s);
+        assert_eq!(src(&e.to_string()), e);
+        assert_eq!(Expr::from_canonical(&e.canonical()).unwrap(), e);
+    }
+    assert!(parse("splice(k)").is_err());
+    assert!(parse("quote(splice(splice(k)))").is_err());
+
+    // A quote is code. Its body isn't typed, but its holes' operands are.
+    let code = Ty::bin().with_minor(Form::CODE);
+    assert_eq!(check_src("quote(t.n + splice(t.x))"), Ok(code.clone()));
+    assert_eq!(check_src("quote(u.v(1))"), Ok(code));
+    assert!(matches!(
+        check_src("quote(splice(u.v))"),
+        Err(TypeErrorKind::Unbound(_))
+    ));
+    assert!(matches!(
+        check_src("let f = fn(x) x in quote(splice(f))"),
+        Err(TypeErrorKind::NotA { .. })
+    ));
+    let bare = check(&Expr::splice(int(1)), &schema(), None).unwrap_err();
+    assert_eq!(*bare.kind, TypeErrorKind::Unquoted);
+
+    // Likewise the FO checker only walks the holes' operands.
+    assert!(check_fo(&src("quote(let f = splice(c) in f(1))"), None).is_ok());
+    let e = check_fo(&src("let f = fn(x) x in quote(t.n + splice(f))"), None).unwrap_err();
+    assert_eq!(e.kind, FoErrorKind::FnValue);
+    assert_eq!(e.node, 7);
+
+    // The template keeps the holes of the outermost quote only.
+    let (tpl, holes) = body("quote(quote(splice(splice(k))) + splice(j))").template();
+    assert_eq!(holes, vec![src("k"), src("j")]);
+    assert_eq!(
+        tpl,
+        body("quote(quote(splice(splice(pass))) + splice(pass))")
+    );
+
+    // Data fills a hole with a literal, and code with what it encodes.
+    let (tpl, _) = body("quote(t.n + splice(k))").template();
+    let built = |v: Vals| {
+        let out = run(vec![Literal(v), Quote(tpl.clone())]).unwrap();
+        Expr::from_code(&out).unwrap()
+    };
+    assert_eq!(built(Vals::I64s(vec![3])), src("t.n + 3"));
+    assert_eq!(built(src("t.x * 2").to_code()), src("t.n + t.x * 2"));
+    assert!(Expr::from_code(&Vals::I64s(vec![3])).is_err());
+
+    // Built code must be FO on its own, or the quote faults.
+    let (tpl, _) = body("quote(let f = splice(c) in f)").template();
+    let f = run(vec![Literal(src("fn(x) x").to_code()), Quote(tpl)]).unwrap_err();
+    assert_eq!((f.kind, f.pc), (FaultKind::Invalid, 1));
+
+    // Quote insns read one register per hole and assemble back.
+    let (tpl, _) = body("quote(splice(a) + splice(b))").template();
+    let ops = vec![
+        Quote(body("quote(1)")),
+        Literal(Vals::I64s(vec![1])),
+        Literal(Vals::I64s(vec![2])),
+        Quote(tpl),
+    ];
+    let prog = Program::assemble(&ops).unwrap();
+    assert_eq!(prog.quotes.len(), 2);
+    assert_eq!(prog.ops().unwrap(), ops);
+    assert!(Program::assemble(&ops[3..]).is_err());
+}
+
+#[test]
+fn test_bitfns() {
+    use BitFn::*;
+    let ints = |v: &[i64]| Vals::I64s(v.to_vec());
+    let call = |f: BitFn, args: Vec<Vals>| {
+        let mut ops: Vec<Opcode> = args.into_iter().map(Opcode::Literal).collect();
+        ops.push(Opcode::BitFn(f));
+        Vm::new(ops, vec![]).eval().unwrap()
+    };
+    let ok = |f: BitFn, args: Vec<Vals>| call(f, args).unwrap();
+    let m = bitvals(&[false, true, true, false, true]);
+
+    // Rank counts set rows before a row, and select inverts it.
+    assert_eq!(
+        ok(Rank, vec![m.clone(), ints(&[0, 1, 2, 3, 5])]),
+        ints(&[0, 0, 1, 2, 3])
+    );
+    assert_eq!(
+        ok(Select, vec![m.clone(), ints(&[0, 1, 2])]),
+        ints(&[1, 2, 4])
+    );
+    assert_eq!(ok(Rows, vec![m.clone()]), ints(&[1, 2, 4]));
+    assert_eq!(ok(Mask, vec![ints(&[4, 1, 2, 1]), ints(&[5])]), m);
+    assert_eq!(ok(Mask, vec![ints(&[]), ints(&[0])]), bitvals(&[]));
+
+    // Rows keep what a filter by the same mask keeps.
+    let t = Tab::new(vec![col("x", ints(&[10, 11, 12, 13, 14]))]).unwrap();
+    let rows = ok(Rows, vec![m.clone()]);
+    let Vals::I64s(rows) = rows else { panic!() };
+    let rows: Vec<usize> = rows.iter().map(|r| *r as usize).collect();
+    assert_eq!(
+        t.compact(&m).unwrap(),
+        Tab::new(vec![col(
+            "x",
+            ints(&[10, 11, 12, 13, 14]).take(&rows).unwrap()
+        )])
+        .unwrap()
+    );
+
+    // Out of range rows and counts fault.
+    for (f, args) in [
+        (Rank, vec![m.clone(), ints(&[6])]),
+        (Rank, vec![m.clone(), ints(&[-1])]),
+        (Select, vec![m.clone(), ints(&[3])]),
+        (Mask, vec![ints(&[5]), ints(&[5])]),
+        (Mask, vec![ints(&[0]), ints(&[-1])]),
+    ] {
+        let e = call(f, args).unwrap_err();
+        assert_eq!(e.kind, FaultKind::OutOfRange, "{:?}", f);
+    }
+
+    // Null bits are unset, null ints give null rows, and mask skips them.
+    let nm = opt(m.clone(), &[1]);
+    assert_eq!(ok(Rows, vec![nm.clone()]), ints(&[2, 4]));
+    assert_eq!(
+        ok(Select, vec![nm, opt(ints(&[0, 1]), &[0])]),
+        opt(ints(&[0, 4]), &[0])
+    );
+    assert_eq!(
+        ok(Mask, vec![opt(ints(&[0, 9, 2]), &[1]), ints(&[3])]),
+        bitvals(&[true, false, true])
+    );
+
+    // Popcount and the rest of the logic are prim ops, and And works on
+    // ints as well.
+    let a = bitvals(&[true, true, false]);
+    let b = bitvals(&[true, false, false]);
+    let bin = |x: Vals, op, y: Vals| bin_with(x, op, y, Overflow::Wrap).unwrap();
+    assert_eq!(bin(a.clone(), PrimBinOp::And, b.clone()), b);
+    assert_eq!(
+        bin(a.clone(), PrimBinOp::Xor, b),
+        bitvals(&[false, true, false])
+    );
+    assert_eq!(
+        bin(ints(&[0b1100]), PrimBinOp::And, ints(&[0b1010])),
+        ints(&[0b1000])
+    );
+    let count = vec![Opcode::Literal(a), Opcode::PrimUnOp(PrimUnOp::BitCount)];
+    assert_eq!(Vm::new(count, vec![]).run().unwrap(), ints(&[2]));
+    let null = opt(bitvals(&[true]), &[0]);
+    let f = bitvals(&[false]);
+    assert_eq!(bin(f.clone(), PrimBinOp::And, null), f);
+
+    // They parse, print and check.
+    for src in ["rank(t.n > 1, t.n)", "mask(rows(t.n > 1 & t.n < 5), 3)"] {
+        let e = parse(src).unwrap().expr;
+        assert_eq!(e.to_string(), src);
+        assert_eq!(Expr::from_canonical(&e.canonical()).unwrap(), e);
+    }
+    assert_eq!(check_src("rank(t.n > 1, t.n)"), Ok(Ty::int()));
+    assert_eq!(check_src("mask(rows(t.n > 1), 3)"), Ok(Ty::bit()));
+    assert!(check_src("select(t.n, 0)").is_err());
+    assert!(parse("rows(a, b)").is_err());
+
+    // And BitFn insns assemble.
+    let ops = vec![
+        Opcode::Literal(m),
+        Opcode::Literal(ints(&[1])),
+        Opcode::BitFn(Rank),
+    ];
+    assert_eq!(Program::assemble(&ops).unwrap().ops().unwrap(), ops);
+}
+
+#[test]
+fn test_lower() {
+    use Opcode::{Literal, Path as Load, PrimBinOp as Bin, Zip};
+    let src = |s: &str| parse(s).unwrap().expr;
+    let ints = |v: &[i64]| Vals::I64s(v.to_vec());
+    let t = Tab::new(vec![Col::new(
+        word("t"),
+        Vals::zip(vec![(word("n"), ints(&[1, 2, 3]))]).unwrap(),
+    )])
+    .unwrap();
+    let run = |s: &str| Vm::new(src(s).ops().unwrap(), vec![t.clone()]).run();
+
+    // Operands come first, and lets and applied lambdas are inlined.
+    assert_eq!(
+        src("let k = 2 in {a: t.n * k}").ops().unwrap(),
+        vec![
+            Load(path("t.n")),
+            Literal(ints(&[2])),
+            Bin(PrimBinOp::Mul),
+            Zip(vec![word("a")]),
+        ]
+    );
+    assert_eq!(run("(fn(a, b) a - b)(t.n, 1)").unwrap(), ints(&[0, 1, 2]));
+    assert_eq!(run("let n = 5 in let n = n + 1 in n").unwrap(), ints(&[6]));
+
+    // Filters and quotes lower their parts.
+    let kept = run("filter(t, n > 1)").unwrap();
+    assert_eq!(kept.project(&path("n").0).unwrap().len(), 2);
+    let code = run("let k = 3 in quote(t.n + splice(k))").unwrap();
+    assert_eq!(Expr::from_code(&code).unwrap(), src("t.n + 3"));
+
+    // What has no opcodes, and inlining that would capture a path, fail.
+    for s in [
+        "if t.n > 1 then 1 else 2",
+        "fn(x) x",
+        "let f = fn(x) x in f",
+        "let k = t in let t = 1 in k",
+        "(fn(a, b) a)(b, 1)",
+    ] {
+        assert!(src(s).ops().is_err(), "{}", s);
+    }
+}
+
+#[test]
+fn test_compile() {
+    let src = |s: &str| parse(s).unwrap().expr;
+    let t = Tab::new(vec![Col::new(
+        word("t"),
+        Vals::zip(vec![(word("n"), Vals::I64s(vec![1, 2, 3]))]).unwrap(),
+    )])
+    .unwrap();
+
+    // A compiled Program ships, and runs as the Expr's opcodes do.
+    for s in [
+        "t.n * 2 + -t.n ** 2",
+        "{a: t.n, b: 1}",
+        "filter(t, n > 1)",
+        "let k = 3 in quote(t.n + splice(k))",
+    ] {
+        let prog = src(s).compile().unwrap();
+        let shipped: Program = rmp_serde::from_slice(&rmp_serde::to_vec(&prog).unwrap()).unwrap();
+        let direct = Vm::new(src(s).ops().unwrap(), vec![t.clone()]).run();
+        let vm = Vm::from_program(&shipped, vec![t.clone()]).unwrap();
+        assert_eq!(vm.clone().run().unwrap(), direct.unwrap(), "{}", s);
+    }
+
+    // The register files hold, at most, the two vectors t.n * 2 and t.n,
+    // and the scalar 2 above them.
+    let prog = src("t.n * 2 + -t.n ** 2").compile().unwrap();
+    assert_eq!((prog.scalar_regs, prog.vector_regs), (1, 2));
+    let prog = src("1 + 2 * 3").compile().unwrap();
+    assert_eq!((prog.scalar_regs, prog.vector_regs), (3, 0));
+    assert!(src("fn(x) x").compile().is_err());
+}
+
+#[test]
+fn test_udfs() {
+    let mut udfs = Udfs::new();
+    // scale(x, k) = x * k, over int columns.
+    udfs.register(
+        "scale",
+        vec![Ty::int(), Ty::int()],
+        Ty::int(),
+        |args| match (&args[0], &args[1]) {
+            (Vals::I64s(x), Vals::I64s(k)) if k.len() == 1 => {
+                Ok(Vals::I64s(x.iter().map(|x| x * k[0]).collect()))
+            }
+            _ => Err(Fault {
+                kind: FaultKind::OutOfRange,
+                pc: 0,
+                msg: "scale takes a one-row factor".into(),
+            }
+            .into()),
+        },
+    )
+    .unwrap();
+    let int = || Ty::int();
+    assert!(udfs
+        .register("scale", vec![], int(), |_| unreachable!())
+        .is_err());
+    for name in ["sqrt", "filter", "min", "i64", "not a word"] {
+        assert!(udfs
+            .register(name, vec![], int(), |_| unreachable!())
+            .is_err());
+    }
+
+    // Calls type by their signature, and only the registry's may be FO.
+    let src = "scale(t.n, 2) + 1";
+    let e = parse(src).unwrap().expr;
+    let mut env = TyEnv::new();
+    env.insert(path("t.n"), Ty::int());
+    assert!(check(&e, &env, None).is_err());
+    udfs.declare(&mut env);
+    assert_eq!(check(&e, &env, None).unwrap().root(), &Ty::int());
+    assert!(check(&parse("scale(t.n)").unwrap().expr, &env, None).is_err());
+    let fo = check_fo(&e, None).unwrap_err();
+    assert_eq!(fo.kind, FoErrorKind::NotAFn(path("scale")));
+    assert!(check_fo_with(&e, None, &udfs).is_ok());
+    let shadowed = parse("let scale = 1 in scale(2, 3)").unwrap().expr;
+    assert!(check_fo_with(&shadowed, None, &udfs).is_err());
+
+    // A call ships by name in a Program and runs the registered kernel.
+    let t = Tab::new(vec![Col::new(
+        word("t"),
+        Vals::zip(vec![(word("n"), Vals::I64s(vec![1, 2, 3]))]).unwrap(),
+    )])
+    .unwrap();
+    let prog = e.compile().unwrap();
+    assert_eq!(
+        prog.calls,
+        vec![Call {
+            name: word("scale"),
+            arity: 2
+        }]
+    );
+    let shipped: Program = rmp_serde::from_slice(&rmp_serde::to_vec(&prog).unwrap()).unwrap();
+    let run = |prog: &Program, udfs: &Udfs| {
+        Vm::from_program(prog, vec![t.clone()])
+            .unwrap()
+            .with_udfs(udfs.clone())
+            .eval()
+            .unwrap()
+    };
+    assert_eq!(run(&shipped, &udfs), Ok(Vals::I64s(vec![3, 5, 7])));
+
+    // Without the function, or with a kernel that faults, the call faults.
+    let f = run(&shipped, &Udfs::new()).unwrap_err();
+    assert_eq!((f.kind, f.pc), (FaultKind::Mismatch, 2));
+    assert!(f.msg.contains("no function named scale"), "{}", f.msg);
+    let bad = parse("scale(t.n, t.n)").unwrap().expr.compile().unwrap();
+    assert_eq!(run(&bad, &udfs).unwrap_err().kind, FaultKind::OutOfRange);
+
+    // Calls run inside filter predicates too.
+    let e = parse("filter({n: t.n}, scale(n, 2) > 3)").unwrap().expr;
+    let kept = run(&e.compile().unwrap(), &udfs).unwrap();
+    assert_eq!(
+        kept,
+        Vals::zip(vec![(word("n"), Vals::I64s(vec![2, 3]))]).unwrap()
+    );
+}
@@ -0,0 +1,271 @@
+// Calendar functions over temporal columns (see form.rs): extracting fields,
+// truncating to the start of a year, month, day or hour, adding calendar
+// months, and moving between timestamps, dates, durations and plain ints.
+// All of them work in UTC on the proleptic Gregorian calendar, and give
+// null rows where their arguments are null.
+
+use crate::{
+    fault::fault,
+    kernel::{enrich, type_name, zip_with},
+    null::{lift, nulls},
+    FaultKind, Form, Temporal, TimeFn, Unit, Vals,
+};
+use submerge_base::{err, Result};
+
+pub(crate) const MICROS_PER_DAY: i64 = 86_400_000_000;
+const MICROS_PER_HOUR: i64 = 3_600_000_000;
+
+// Days this far from the epoch are beyond any calendar arithmetic below
+// overflowing, and far beyond what any timestamp can reach.
+const MAX_DAYS: i64 = 1 << 40;
+
+// The year, month and day of a count of days since 1970-01-01, after
+// Howard Hinnant's civil_from_days.
+fn civil(days: i64) -> Result<(i64, i64, i64)> {
+    if days.abs() > MAX_DAYS {
+        return Err(fault(
+            FaultKind::OutOfRange,
+            format!("date {} is out of range", days),
+        ));
+    }
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    Ok((yoe + era * 400 + (m <= 2) as i64, m, d))
+}
+
+fn out_of_range(day: i64) -> submerge_base::Error {
+    fault(
+        FaultKind::OutOfRange,
+        format!("day {} is out of range for a timestamp", day),
+    )
+}
+
+// The inverse of civil().
+fn days(y: i64, m: i64, d: i64) -> i64 {
+    let y = y - (m <= 2) as i64;
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (m + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn month_len(y: i64, m: i64) -> i64 {
+    let (ny, nm) = if m == 12 { (y + 1, 1) } else { (y, m + 1) };
+    days(ny, nm, 1) - days(y, m, 1)
+}
+
+fn add_months(day: i64, n: i64) -> Result<i64> {
+    let (y, m, d) = civil(day)?;
+    if n.abs() > MAX_DAYS {
+        return Err(fault(
+            FaultKind::OutOfRange,
+            format!("cannot add {} months", n),
+        ));
+    }
+    let total = y * 12 + (m - 1) + n;
+    let (y, m) = (total.div_euclid(12), total.rem_euclid(12) + 1);
+    Ok(days(y, m, d.min(month_len(y, m))))
+}
+
+// A temporal value as text: dates as 2024-01-31, timestamps as
+// 2024-01-31T12:00:00Z, with six fractional digits if they aren't all zero,
+// and durations as a count of microseconds. Years outside 0..=9999 get a
+// sign and at least four digits.
+pub(crate) fn format(t: Temporal, v: i64) -> Result<String> {
+    let date = |day: i64| -> Result<String> {
+        let (y, m, d) = civil(day)?;
+        Ok(if (0..=9999).contains(&y) {
+            format!("{:04}-{:02}-{:02}", y, m, d)
+        } else {
+            format!("{:+05}-{:02}-{:02}", y, m, d)
+        })
+    };
+    Ok(match t {
+        Temporal::Date => date(v)?,
+        Temporal::Timestamp => {
+            let (day, micros) = (v.div_euclid(MICROS_PER_DAY), v.rem_euclid(MICROS_PER_DAY));
+            let secs = micros / 1_000_000;
+            let hms = format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60);
+            match micros % 1_000_000 {
+                0 => format!("{}T{}Z", date(day)?, hms),
+                frac => format!("{}T{}.{:06}Z", date(day)?, hms, frac),
+            }
+        }
+        Temporal::Duration => v.to_string(),
+    })
+}
+
+// The inverse of format(), which only accepts what format() writes.
+pub(crate) fn parse(t: Temporal, s: &str) -> Option<i64> {
+    let num = |s: &str| -> Option<i64> {
+        match s.bytes().all(|b| b.is_ascii_digit()) {
+            true => s.parse().ok(),
+            false => None,
+        }
+    };
+    let date = |s: &str| -> Option<i64> {
+        let (neg, rest) = match s.as_bytes().first()? {
+            b'+' => (false, &s[1..]),
+            b'-' => (true, &s[1..]),
+            _ => (false, s),
+        };
+        let mut parts = rest.splitn(3, '-');
+        let (y, m, d) = (
+            num(parts.next()?)?,
+            num(parts.next()?)?,
+            num(parts.next()?)?,
+        );
+        let y = if neg { -y } else { y };
+        if y.abs() > MAX_DAYS / 366 || !(1..=12).contains(&m) || !(1..=31).contains(&d) {
+            return None;
+        }
+        Some(days(y, m, d))
+    };
+    let v = match t {
+        Temporal::Date => date(s)?,
+        Temporal::Timestamp => {
+            let (day, time) = s.strip_suffix('Z')?.split_once('T')?;
+            let (hms, frac) = match time.split_once('.') {
+                Some((hms, frac)) if frac.len() == 6 => (hms, num(frac)?),
+                Some(_) => return None,
+                None => (time, 0),
+            };
+            let mut parts = hms.splitn(3, ':');
+            let (h, m, sec) = (
+                num(parts.next()?)?,
+                num(parts.next()?)?,
+                num(parts.next()?)?,
+            );
+            if h > 23 || m > 59 || sec > 59 {
+                return None;
+            }
+            let micros = ((h * 60 + m) * 60 + sec) * 1_000_000 + frac;
+            date(day)?
+                .checked_mul(MICROS_PER_DAY)?
+                .checked_add(micros)?
+        }
+        Temporal::Duration => s.parse().ok()?,
+    };
+    // Anything that doesn't read back the same, like February 30th or a
+    // year with leading zeros, isn't what format() would write.
+    match format(t, v) {
+        Ok(back) if back == s => Some(v),
+        _ => None,
+    }
+}
+
+// A column's stored ints and its temporal form, if any.
+fn operand(f: TimeFn, v: &Vals) -> Result<(&[i64], Option<Temporal>)> {
+    match v {
+        Vals::Rich(col) if col.form == Form::NONE => operand(f, &col.vals),
+        Vals::Rich(col) => match (&col.vals, col.form.as_temporal()) {
+            (Vals::I64s(x), Some(t)) => Ok((x, Some(t))),
+            _ => Err(err(format!("no {} on {}", f.name(), col.form))),
+        },
+        Vals::I64s(x) => Ok((x, None)),
+        _ => Err(err(format!("no {} on {}", f.name(), type_name(v)))),
+    }
+}
+
+// The function's result form, given its first operand's.
+pub(crate) fn result_form(f: TimeFn, t: Option<Temporal>) -> Option<Form> {
+    use Temporal::*;
+    use TimeFn::*;
+    Some(match (f, t) {
+        (Year | Month | Day, Some(Timestamp | Date)) => Form::NONE,
+        (Hour | Minute | Second, Some(Timestamp)) => Form::NONE,
+        (TruncYear | TruncMonth | TruncDay | AddMonths, Some(Timestamp | Date)) => t?.into(),
+        (TruncHour, Some(Timestamp)) => Form::TIMESTAMP,
+        (ToDate, None | Some(Timestamp)) => Form::DATE,
+        (ToTimestamp, None | Some(Date)) => Form::TIMESTAMP,
+        (ToDuration, None) => Form::DURATION,
+        _ => return None,
+    })
+}
+
+fn apply_one(f: TimeFn, t: Option<Temporal>, v: i64, n: i64) -> Result<i64> {
+    use TimeFn::*;
+    let is_ts = t == Some(Temporal::Timestamp);
+    let (day, micros) = if is_ts {
+        (v.div_euclid(MICROS_PER_DAY), v.rem_euclid(MICROS_PER_DAY))
+    } else {
+        (v, 0)
+    };
+    // Back to the operand's form from a day and a time within it.
+    let at = |day: i64, micros: i64| -> Result<i64> {
+        if !is_ts {
+            return Ok(day);
+        }
+        day.checked_mul(MICROS_PER_DAY)
+            .and_then(|t| t.checked_add(micros))
+            .ok_or_else(|| out_of_range(day))
+    };
+    Ok(match f {
+        Year => civil(day)?.0,
+        Month => civil(day)?.1,
+        Day => civil(day)?.2,
+        Hour => micros / MICROS_PER_HOUR,
+        Minute => micros / 60_000_000 % 60,
+        Second => micros / 1_000_000 % 60,
+        TruncYear => at(days(civil(day)?.0, 1, 1), 0)?,
+        TruncMonth => {
+            let (y, m, _) = civil(day)?;
+            at(days(y, m, 1), 0)?
+        }
+        TruncDay => at(day, 0)?,
+        TruncHour => at(day, micros - micros % MICROS_PER_HOUR)?,
+        AddMonths => at(add_months(day, n)?, micros)?,
+        ToDate => day,
+        ToTimestamp => v
+            .checked_mul(if t.is_none() { 1 } else { MICROS_PER_DAY })
+            .ok_or_else(|| out_of_range(v))?,
+        ToDuration => v,
+    })
+}
+
+// A plain int passed to ToDate is already a day count, and one passed to
+// ToTimestamp is already microseconds.
+pub(crate) fn apply(f: TimeFn, args: &[Vals]) -> Result<Vals> {
+    if args.len() != f.arity() {
+        return Err(err(format!(
+            "{} takes {} arguments, not {}",
+            f.name(),
+            f.arity(),
+            args.len()
+        )));
+    }
+    if args.iter().any(|a| nulls(a).is_some()) {
+        return lift(&args.iter().collect::<Vec<_>>(), |x| apply(f, x));
+    }
+    let (x, t) = operand(f, &args[0])?;
+    let Some(form) = result_form(f, t) else {
+        let found = t.map_or("int".to_string(), |t| Form::from(t).to_string());
+        return Err(err(format!("no {} on {}", f.name(), found)));
+    };
+    let out = match args.get(1) {
+        None => x.iter().map(|v| apply_one(f, t, *v, 0)).collect(),
+        Some(n) => match operand(f, n)? {
+            (y, None) => zip_with(x, y, |v, n| apply_one(f, t, v, n)),
+            (_, Some(t)) => Err(err(format!(
+                "{} takes a plain int, not {}",
+                f.name(),
+                Form::from(t)
+            ))),
+        },
+    };
+    Ok(enrich(None, form, Unit::NONE, Vals::I64s(out?)))
+}
+
+impl TimeFn {
+    pub fn apply(self, args: &[Vals]) -> Result<Vals> {
+        apply(self, args)
+    }
+}
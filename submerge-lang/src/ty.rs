@@ -0,0 +1,142 @@
+// Every value has a major/minor/role type-triple, as coldb columns do. The
+// major type is the shape of the value (one of the 4 normal column types, a
+// structure, or one of the few non-column values an Expr can produce), the
+// minor type is its Form, and its role is its Unit.
+
+use crate::{Form, Unit, Vals, Word};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter, Write};
+
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct Ty {
+    pub major: Major,
+    pub minor: Form,
+    pub role: Unit,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum Major {
+    Var(u32), // Inference variable; never appears in a checker result
+    Nil,      // The value of Pass
+    Bit,
+    Int,
+    Flo,
+    Bin,
+    All(Vec<(Word, Ty)>),
+    Any(Vec<Ty>),
+    Tab(Vec<(Word, Ty)>),
+    Fn(Vec<Ty>, Box<Ty>),
+    Env, // A reified environment
+}
+
+impl Ty {
+    pub fn new(major: Major) -> Ty {
+        Ty {
+            major,
+            minor: Form::NONE,
+            role: Unit::NONE,
+        }
+    }
+    pub fn bit() -> Ty {
+        Ty::new(Major::Bit)
+    }
+    pub fn int() -> Ty {
+        Ty::new(Major::Int)
+    }
+    pub fn flo() -> Ty {
+        Ty::new(Major::Flo)
+    }
+    pub fn bin() -> Ty {
+        Ty::new(Major::Bin)
+    }
+    pub fn with_minor(mut self, minor: Form) -> Ty {
+        self.minor = minor;
+        self
+    }
+    pub fn with_role(mut self, role: Unit) -> Ty {
+        self.role = role;
+        self
+    }
+
+    // The type of a literal column. Any's variants are typed individually;
+    // All's fields have no names of their own, so they get positional ones.
+    // Any column may have nulls, so they don't change its type.
+    pub fn of_vals(vals: &Vals) -> Ty {
+        match vals {
+            Vals::I64s(_) => Ty::int(),
+            Vals::F64s(_) => Ty::flo(),
+            Vals::Bits(..) => Ty::bit(),
+            Vals::Bins(_) => Ty::bin(),
+            Vals::Rich(col) => Ty::of_vals(&col.vals)
+                .with_minor(col.form)
+                .with_role(col.unit),
+            Vals::All(fields) => Ty::new(Major::All(
+                fields
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| (Word::positional(i), Ty::of_vals(v)))
+                    .collect(),
+            )),
+            Vals::Any(_, vars) => Ty::new(Major::Any(vars.iter().map(Ty::of_vals).collect())),
+            Vals::Opt(_, vals) => Ty::of_vals(vals),
+        }
+    }
+
+    pub fn is_var(&self) -> bool {
+        matches!(self.major, Major::Var(_))
+    }
+}
+
+impl Display for Ty {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let fields = |f: &mut Formatter<'_>, fs: &[(Word, Ty)]| -> fmt::Result {
+            f.write_char('{')?;
+            for (i, (w, t)) in fs.iter().enumerate() {
+                if i != 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{}: {}", w, t)?;
+            }
+            f.write_char('}')
+        };
+        match &self.major {
+            Major::Var(n) => write!(f, "?{}", n)?,
+            Major::Nil => f.write_str("nil")?,
+            Major::Bit => f.write_str("bit")?,
+            Major::Int => f.write_str("int")?,
+            Major::Flo => f.write_str("flo")?,
+            Major::Bin => f.write_str("bin")?,
+            Major::All(fs) => fields(f, fs)?,
+            Major::Tab(fs) => {
+                f.write_str("tab")?;
+                fields(f, fs)?
+            }
+            Major::Any(vs) => {
+                for (i, v) in vs.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(" | ")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+            }
+            Major::Fn(params, ret) => {
+                f.write_str("fn(")?;
+                for (i, p) in params.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", p)?;
+                }
+                write!(f, ") -> {}", ret)?;
+            }
+            Major::Env => f.write_str("env")?,
+        }
+        if self.minor != Form::NONE {
+            write!(f, " form {}", self.minor)?;
+        }
+        if self.role != Unit::NONE {
+            write!(f, " unit {}", self.role)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+// User-defined functions. An embedder can extend the primitives with pure
+// native functions, each with a name, a signature and a kernel. A kernel is
+// vectorized like the built-in ones: it takes a column per argument, where a
+// one-row column stands for every row, and gives a column.
+//
+// A call is written like any application, f(x, y), of a name that isn't
+// bound. The checker types it by its signature once declare() has put the
+// registry in its TyEnv, the FO checker lets it be called under
+// check_fo_with(), it lowers to a Call opcode and the Vm runs the kernel.
+// Functions are looked up by name when they run, so a Program that calls
+// one ships its name, not its code, and every node has to register the same
+// functions under the same names. Calling a name that isn't registered is a
+// Mismatch fault.
+//
+// The registry trusts its kernels to be pure: to depend only on their
+// arguments, to finish in time polynomial in them, and to give the same bits
+// on every platform (see float.rs). So a call reads nothing but its
+// arguments, and a footprint (see plan.rs in eval) only needs theirs. A
+// kernel's error stops the Vm with a Mismatch fault, or with the Fault it
+// returns.
+//
+// Names the parser takes for keywords or built-in functions can't be
+// registered, since a call to them would never reach the registry.
+
+use crate::{fault::fault, parse::is_builtin, FaultKind, Major, Path, Ty, TyEnv, Vals, Word};
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    fmt::{self, Debug, Formatter},
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+use submerge_base::{err, Result};
+
+pub type Kernel = dyn Fn(&[Vals]) -> Result<Vals> + Send + Sync;
+
+#[derive(Clone)]
+pub struct Udf {
+    pub params: Vec<Ty>,
+    pub ret: Ty,
+    kernel: Arc<Kernel>,
+}
+
+impl Debug for Udf {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Udf")
+            .field("params", &self.params)
+            .field("ret", &self.ret)
+            .finish_non_exhaustive()
+    }
+}
+
+// The registered functions, by name. Cloning shares them. Registries are
+// compared and hashed by their names and signatures, since kernels can't be.
+#[derive(Clone, Debug, Default)]
+pub struct Udfs {
+    fns: Arc<BTreeMap<Word, Udf>>,
+}
+
+impl Udfs {
+    pub fn new() -> Udfs {
+        Udfs::default()
+    }
+
+    pub fn register(
+        &mut self,
+        name: &str,
+        params: Vec<Ty>,
+        ret: Ty,
+        kernel: impl Fn(&[Vals]) -> Result<Vals> + Send + Sync + 'static,
+    ) -> Result<()> {
+        let Some(w) = Word::new(name) else {
+            return Err(err(format!("{:?} is not a word", name)));
+        };
+        if is_builtin(name) {
+            return Err(err(format!("{} is a built-in name", name)));
+        }
+        if self.fns.contains_key(&w) {
+            return Err(err(format!("{} is already registered", name)));
+        }
+        let udf = Udf {
+            params,
+            ret,
+            kernel: Arc::new(kernel),
+        };
+        Arc::make_mut(&mut self.fns).insert(w, udf);
+        Ok(())
+    }
+
+    pub fn get(&self, name: &Word) -> Option<&Udf> {
+        self.fns.get(name)
+    }
+
+    pub fn contains(&self, name: &Word) -> bool {
+        self.fns.contains_key(name)
+    }
+
+    // Put each function in a TyEnv, as a fn of its signature, for the
+    // checker to type its calls.
+    pub fn declare(&self, env: &mut TyEnv) {
+        for (name, f) in self.fns.iter() {
+            let ty = Ty::new(Major::Fn(f.params.clone(), Box::new(f.ret.clone())));
+            env.insert(Path(vec![name.clone()]), ty);
+        }
+    }
+
+    pub(crate) fn call(&self, name: &Word, args: &[Vals]) -> Result<Vals> {
+        let Some(f) = self.fns.get(name) else {
+            return Err(fault(
+                FaultKind::Mismatch,
+                format!("no function named {}", name),
+            ));
+        };
+        if args.len() != f.params.len() {
+            return Err(fault(
+                FaultKind::Mismatch,
+                format!("{} takes {} arguments", name, f.params.len()),
+            ));
+        }
+        (f.kernel)(args)
+    }
+
+    fn signatures(&self) -> impl Iterator<Item = (&Word, &Vec<Ty>, &Ty)> {
+        self.fns.iter().map(|(w, f)| (w, &f.params, &f.ret))
+    }
+}
+
+impl PartialEq for Udfs {
+    fn eq(&self, other: &Udfs) -> bool {
+        self.signatures().eq(other.signatures())
+    }
+}
+
+impl Eq for Udfs {}
+
+impl PartialOrd for Udfs {
+    fn partial_cmp(&self, other: &Udfs) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Udfs {
+    fn cmp(&self, other: &Udfs) -> Ordering {
+        self.signatures().cmp(other.signatures())
+    }
+}
+
+impl Hash for Udfs {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for s in self.signatures() {
+            s.hash(state);
+        }
+    }
+}
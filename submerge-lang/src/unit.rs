@@ -0,0 +1,412 @@
+// Units are packed into an i64 as a vector of small signed exponents, so
+// that multiplying units is adding vectors. From the low bits up:
+//
+//   bits  0..32  4-bit exponents of m, kg, s, A, K, mol, cd and count
+//   bits 32..42  ISO 4217 numeric code of a currency, or 0
+//   bits 42..46  4-bit exponent of that currency
+//   bits 46..52  6-bit exponent of 2 in the unit's scale factor
+//   bits 52..57  5-bit exponent of 3
+//   bits 57..63  6-bit exponent of 5
+//
+// The scale factor relates a unit to the coherent unit of its dimension: km
+// is m scaled by 10^3 = 2^3 * 5^3, and h is s scaled by 2^4 * 3^2 * 5^2.
+// Powers of 2, 3 and 5 cover the decimal prefixes and the usual clock units,
+// and make every conversion an exact ratio of integers. Unit::NONE, all
+// zeroes, is the dimensionless unit with factor 1.
+//
+// Units that differ only in scale have the same dimension, and values move
+// between them only by an explicit conversion.
+
+use crate::{PrimBinOp, PrimUnOp, Unit};
+use std::fmt::{self, Display, Formatter};
+
+const BASE: [&str; 8] = ["m", "kg", "s", "A", "K", "mol", "cd", "count"];
+const DIM_WIDTH: u32 = 4;
+const CURRENCY_SHIFT: u32 = 32;
+const CURRENCY_WIDTH: u32 = 10;
+const CURRENCY_EXP_SHIFT: u32 = 42;
+const PRIMES: [u128; 3] = [2, 3, 5];
+const SCALE_FIELDS: [(u32, u32); 3] = [(46, 6), (52, 5), (57, 6)];
+// The widest exponent field. A unit raised to a power that doesn't fit it
+// can't pack, whatever else it's multiplied by.
+const EXP_WIDTH: u32 = 6;
+
+const CURRENCIES: &[(&str, u16)] = &[
+    ("AUD", 36),
+    ("CAD", 124),
+    ("CHF", 756),
+    ("CNY", 156),
+    ("EUR", 978),
+    ("GBP", 826),
+    ("INR", 356),
+    ("JPY", 392),
+    ("USD", 840),
+];
+
+// Named units, defined in terms of base units and each other. Names that
+// would print the same unit come first in the order they should print.
+const DERIVED: &[(&str, &str)] = &[
+    ("km", "1000*m"),
+    ("cm", "m/100"),
+    ("mm", "m/1000"),
+    ("um", "mm/1000"),
+    ("nm", "um/1000"),
+    ("g", "kg/1000"),
+    ("mg", "g/1000"),
+    ("t", "1000*kg"),
+    ("ms", "s/1000"),
+    ("us", "ms/1000"),
+    ("ns", "us/1000"),
+    ("min", "60*s"),
+    ("h", "60*min"),
+    ("d", "24*h"),
+    ("mA", "A/1000"),
+    ("L", "m^3/1000"),
+    ("mL", "L/1000"),
+    ("Hz", "1/s"),
+    ("kHz", "1000*Hz"),
+    ("MHz", "1000*kHz"),
+    ("GHz", "1000*MHz"),
+    ("N", "kg*m/s^2"),
+    ("Pa", "N/m^2"),
+    ("J", "N*m"),
+    ("kJ", "1000*J"),
+    ("W", "J/s"),
+    ("kW", "1000*W"),
+    ("kWh", "kW*h"),
+    ("C", "A*s"),
+    ("V", "W/A"),
+    ("ohm", "V/A"),
+];
+
+// A unit unpacked into wide fields, for arithmetic.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+struct Parts {
+    dims: [i32; 8],
+    currency: u16,
+    currency_exp: i32,
+    scale: [i32; 3],
+}
+
+fn field(u: i64, shift: u32, width: u32) -> i32 {
+    let v = (u as u64) >> shift << (64 - width);
+    ((v as i64) >> (64 - width)) as i32
+}
+
+fn fits(x: i32, width: u32) -> bool {
+    let lim = 1 << (width - 1);
+    -lim <= x && x < lim
+}
+
+fn put(x: i32, shift: u32, width: u32) -> u64 {
+    ((x as u64) & ((1 << width) - 1)) << shift
+}
+
+impl Parts {
+    fn of(u: Unit) -> Parts {
+        let mut p = Parts::default();
+        for (i, d) in p.dims.iter_mut().enumerate() {
+            *d = field(u.0, DIM_WIDTH * i as u32, DIM_WIDTH);
+        }
+        p.currency = ((u.0 as u64 >> CURRENCY_SHIFT) & ((1 << CURRENCY_WIDTH) - 1)) as u16;
+        p.currency_exp = field(u.0, CURRENCY_EXP_SHIFT, DIM_WIDTH);
+        for (s, (shift, width)) in p.scale.iter_mut().zip(SCALE_FIELDS) {
+            *s = field(u.0, shift, width);
+        }
+        p
+    }
+
+    // None if any exponent is out of range for its field.
+    fn pack(self) -> Option<Unit> {
+        let mut bits = 0;
+        for (i, d) in self.dims.iter().enumerate() {
+            if !fits(*d, DIM_WIDTH) {
+                return None;
+            }
+            bits |= put(*d, DIM_WIDTH * i as u32, DIM_WIDTH);
+        }
+        if self.currency_exp != 0 {
+            if !fits(self.currency_exp, DIM_WIDTH) || self.currency >= 1 << CURRENCY_WIDTH {
+                return None;
+            }
+            bits |= (self.currency as u64) << CURRENCY_SHIFT;
+            bits |= put(self.currency_exp, CURRENCY_EXP_SHIFT, DIM_WIDTH);
+        }
+        for (s, (shift, width)) in self.scale.iter().zip(SCALE_FIELDS) {
+            if !fits(*s, width) {
+                return None;
+            }
+            bits |= put(*s, shift, width);
+        }
+        Some(Unit(bits as i64))
+    }
+
+    fn mul(self, other: Parts) -> Option<Parts> {
+        let currency = match (self.currency_exp, other.currency_exp) {
+            (0, _) => other.currency,
+            (_, 0) => self.currency,
+            _ if self.currency == other.currency => self.currency,
+            _ => return None,
+        };
+        let mut p = Parts {
+            currency,
+            currency_exp: self.currency_exp.checked_add(other.currency_exp)?,
+            ..self
+        };
+        for (d, e) in p.dims.iter_mut().zip(other.dims) {
+            *d = d.checked_add(e)?;
+        }
+        for (s, e) in p.scale.iter_mut().zip(other.scale) {
+            *s = s.checked_add(e)?;
+        }
+        Some(p)
+    }
+
+    // None if the power overflows.
+    fn pow(self, n: i32) -> Option<Parts> {
+        let mut p = self;
+        let all = p
+            .dims
+            .iter_mut()
+            .chain(&mut p.scale)
+            .chain([&mut p.currency_exp]);
+        for e in all {
+            *e = e.checked_mul(n)?;
+        }
+        Some(p)
+    }
+
+    // The nth root, if every exponent divides by n.
+    fn root(self, n: i32) -> Option<Parts> {
+        let mut p = self;
+        let all = p
+            .dims
+            .iter_mut()
+            .chain(&mut p.scale)
+            .chain([&mut p.currency_exp]);
+        for e in all {
+            if e.checked_rem(n)? != 0 {
+                return None;
+            }
+            *e /= n;
+        }
+        Some(p)
+    }
+}
+
+// The integer 2^a * 3^b * 5^c for nonnegative exponents, if it fits.
+fn scale_value(exps: [i32; 3]) -> Option<u128> {
+    let mut v: u128 = 1;
+    for (p, e) in PRIMES.iter().zip(exps) {
+        v = v.checked_mul(p.checked_pow(e as u32)?)?;
+    }
+    Some(v)
+}
+
+// The exponents of 2, 3 and 5 in n, if it has no other factors.
+fn factor(mut n: u128) -> Option<[i32; 3]> {
+    if n == 0 {
+        return None;
+    }
+    let mut exps = [0; 3];
+    for (p, e) in PRIMES.iter().zip(exps.iter_mut()) {
+        while n.is_multiple_of(*p) {
+            n /= p;
+            *e += 1;
+        }
+    }
+    (n == 1).then_some(exps)
+}
+
+impl Unit {
+    // Look up a unit by name: a base unit, a currency code or one of the
+    // derived units, eg. "m", "USD" or "kWh".
+    pub fn named(name: &str) -> Option<Unit> {
+        let mut p = Parts::default();
+        if let Some(i) = BASE.iter().position(|b| *b == name) {
+            p.dims[i] = 1;
+        } else if let Some((_, code)) = CURRENCIES.iter().find(|(c, _)| *c == name) {
+            p.currency = *code;
+            p.currency_exp = 1;
+        } else {
+            let (_, def) = DERIVED.iter().find(|(n, _)| *n == name)?;
+            return Unit::parse(def);
+        }
+        p.pack()
+    }
+
+    // Parse a product of named units and integer factors, each optionally
+    // raised to an integer power, eg. "kg*m/s^2" or "1000*m". Every '/'
+    // divides by just the term after it, and "1" is the unit of no units.
+    pub fn parse(s: &str) -> Option<Unit> {
+        let s: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+        let mut acc = Parts::default();
+        let mut rest = s.as_str();
+        let mut invert = false;
+        loop {
+            let end = rest.find(['*', '/']).unwrap_or(rest.len());
+            let (term, tail) = rest.split_at(end);
+            let (base, exp) = match term.split_once('^') {
+                Some((b, e)) => (b, e.parse::<i32>().ok()?),
+                None => (term, 1),
+            };
+            if !fits(exp, EXP_WIDTH) {
+                return None;
+            }
+            let unit = match base.parse::<u128>() {
+                Ok(n) => Parts {
+                    scale: factor(n)?,
+                    ..Parts::default()
+                },
+                Err(_) => Parts::of(Unit::named(base)?),
+            };
+            let exp = if invert { exp.checked_neg()? } else { exp };
+            acc = acc.mul(unit.pow(exp)?)?;
+            match tail.chars().next() {
+                None => break,
+                Some(c) => {
+                    invert = c == '/';
+                    rest = &tail[1..];
+                }
+            }
+        }
+        acc.pack()
+    }
+
+    pub fn checked_mul(self, other: Unit) -> Option<Unit> {
+        Parts::of(self).mul(Parts::of(other))?.pack()
+    }
+
+    pub fn inverse(self) -> Option<Unit> {
+        Parts::of(self).pow(-1)?.pack()
+    }
+
+    pub fn checked_div(self, other: Unit) -> Option<Unit> {
+        self.checked_mul(other.inverse()?)
+    }
+
+    pub fn root(self, n: i32) -> Option<Unit> {
+        Parts::of(self).root(n)?.pack()
+    }
+
+    // The coherent unit of the same dimension, with scale factor 1.
+    pub fn dimension(self) -> Unit {
+        let mut p = Parts::of(self);
+        p.scale = [0; 3];
+        p.pack().expect("clearing a unit's scale keeps it in range")
+    }
+
+    // The ratio (num, den) such that a value in this unit, times num and
+    // divided by den, is the same quantity in the other unit. None if the
+    // units differ in dimension or the ratio doesn't fit in a u128.
+    pub fn conversion(self, to: Unit) -> Option<(u128, u128)> {
+        if self.dimension() != to.dimension() {
+            return None;
+        }
+        let (from, to) = (Parts::of(self).scale, Parts::of(to).scale);
+        let mut num = [0; 3];
+        let mut den = [0; 3];
+        for i in 0..3 {
+            let d = from[i] - to[i];
+            num[i] = d.max(0);
+            den[i] = (-d).max(0);
+        }
+        Some((scale_value(num)?, scale_value(den)?))
+    }
+}
+
+impl Display for Unit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if *self == Unit::NONE {
+            return f.write_str("1");
+        }
+        let names = BASE.iter().chain(CURRENCIES.iter().map(|(c, _)| c));
+        let names = names.chain(DERIVED.iter().map(|(n, _)| n));
+        for name in names {
+            if Unit::named(name) == Some(*self) {
+                return f.write_str(name);
+            }
+        }
+        // Otherwise, the coherent base units scaled by integer factors.
+        let p = Parts::of(*self);
+        let mut num: Vec<(String, i32)> = Vec::new();
+        let mut den: Vec<(String, i32)> = Vec::new();
+        let mut push = |term: String, e: i32| match e {
+            0 => {}
+            e if e > 0 => num.push((term, e)),
+            e => den.push((term, -e)),
+        };
+        let up = p.scale.map(|e| e.max(0));
+        let down = p.scale.map(|e| (-e).max(0));
+        for (exps, sign) in [(up, 1), (down, -1)] {
+            match scale_value(exps) {
+                Some(1) => {}
+                Some(v) => push(v.to_string(), sign),
+                // Too big to print as one integer; print the primes.
+                None => {
+                    for (prime, e) in PRIMES.iter().zip(exps) {
+                        push(prime.to_string(), sign * e);
+                    }
+                }
+            }
+        }
+        for (name, e) in BASE.iter().zip(p.dims) {
+            push(name.to_string(), e);
+        }
+        if let Some((code, _)) = CURRENCIES.iter().find(|(_, n)| *n == p.currency) {
+            push(code.to_string(), p.currency_exp);
+        }
+        let term = |f: &mut Formatter<'_>, (t, e): &(String, i32)| match e {
+            1 => f.write_str(t),
+            e => write!(f, "{}^{}", t, e),
+        };
+        if num.is_empty() {
+            f.write_str("1")?;
+        }
+        for (i, t) in num.iter().enumerate() {
+            if i != 0 {
+                f.write_str("*")?;
+            }
+            term(f, t)?;
+        }
+        for t in &den {
+            f.write_str("/")?;
+            term(f, t)?;
+        }
+        Ok(())
+    }
+}
+
+// The unit of a binop's result, or None if the operands' units don't
+// combine. As in the checker, an unset unit takes the other side's.
+pub(crate) fn binop_unit(op: PrimBinOp, a: Unit, b: Unit) -> Option<Unit> {
+    use PrimBinOp::*;
+    let same = match (a, b) {
+        _ if a == b => Some(a),
+        (Unit::NONE, _) => Some(b),
+        (_, Unit::NONE) => Some(a),
+        _ => None,
+    };
+    match op {
+        Mul => a.checked_mul(b),
+        Div => a.checked_div(b),
+        Pow => (a == Unit::NONE && b == Unit::NONE).then_some(Unit::NONE),
+        Shl | Shr | Asr | Rol | Ror => (b == Unit::NONE).then_some(a),
+        Eq | Ne | Lt | Le | Gt | Ge | Cmp => same.map(|_| Unit::NONE),
+        Add | Sub | Mod | Min | Max | And | Or | Xor => same,
+    }
+}
+
+// The unit of a unop's result, or None if the op needs a dimensionless
+// operand (or a square, for Sqrt) and didn't get one.
+pub(crate) fn unop_unit(op: PrimUnOp, a: Unit) -> Option<Unit> {
+    use PrimUnOp::*;
+    match op {
+        Neg | Not | Abs | Floor | Ceil | Trunc | Bitrev | ByteSwap => Some(a),
+        Sgn | Popcnt | Clz | Ctz | BitCount | BitParity => Some(Unit::NONE),
+        Sqrt => a.root(2),
+        Recip => a.inverse(),
+        Exp | Exp2 | Exp10 | Log | Log2 | Log10 | Sin | Cos | Tan | Asin | Acos | Atan | Sinh
+        | Cosh | Tanh | Asinh | Acosh | Atanh => (a == Unit::NONE).then_some(a),
+    }
+}
@@ -0,0 +1,352 @@
+// The Vm runs a linearized Expr: a sequence of Opcodes in postfix order over
+// an operand stack of columns. Each opcode is one step, and step() runs a
+// bounded number of them, so a caller can stop and resume evaluation between
+// any two opcodes. The exceptions are Case and Filter, whose arms and
+// predicate run to completion within their one step.
+//
+// An opcode that fails on its data stops the Vm with a Fault, as fault.rs
+// describes; the Vm keeps it, and step() and run() return it as an Eval
+// error. eval() returns it as a result instead.
+
+use crate::{
+    agg, all, any, binfn, bits, cast, env,
+    fault::{self, trap},
+    float, group, join, kernel, quote, sort, time, Fault, FloatMode, Frame, Opcode, Overflow, Path,
+    Program, Tab, Udfs, Vals, Vm,
+};
+use submerge_base::Result;
+
+impl Frame {
+    fn new(ctx: Vec<Tab>) -> Frame {
+        Frame {
+            ctx,
+            scalar_bit_regs: Vec::new(),
+            bin_regs: Vec::new(),
+            int_regs: Vec::new(),
+            vals: Vec::new(),
+            pc: 0,
+        }
+    }
+
+    fn pop(&mut self) -> Result<Vals> {
+        match self.vals.pop() {
+            Some(v) => Ok(v),
+            None => Err(trap("vm operand stack underflow")),
+        }
+    }
+
+    // Later context tabs shadow earlier ones, as env.rs describes. Columns
+    // with a form or unit load as Rich values, so that the kernels can see
+    // them.
+    fn load(&self, path: &Path) -> Result<Vals> {
+        env::lookup(&self.ctx, path)
+    }
+}
+
+impl Vm {
+    pub fn new(ops: Vec<Opcode>, ctx: Vec<Tab>) -> Vm {
+        Vm {
+            ops,
+            stack: vec![Frame::new(ctx)],
+            overflow: Overflow::default(),
+            float_mode: FloatMode::default(),
+            udfs: Udfs::default(),
+            fault: None,
+        }
+    }
+
+    // A Vm for a compiled Program, with its operand stack sized for the
+    // Program's registers.
+    pub fn from_program(prog: &Program, ctx: Vec<Tab>) -> Result<Vm> {
+        let mut vm = Vm::new(prog.ops()?, ctx);
+        let regs = prog.scalar_regs as usize + prog.vector_regs as usize;
+        vm.frame_mut()?.vals.reserve_exact(regs);
+        Ok(vm)
+    }
+
+    pub fn with_overflow(mut self, overflow: Overflow) -> Vm {
+        self.overflow = overflow;
+        self
+    }
+
+    pub fn with_float_mode(mut self, mode: FloatMode) -> Vm {
+        self.float_mode = mode;
+        self
+    }
+
+    // The user-defined functions that Call opcodes run.
+    pub fn with_udfs(mut self, udfs: Udfs) -> Vm {
+        self.udfs = udfs;
+        self
+    }
+
+    // Check the invariants a Vm keeps but a decoded one might not: that it
+    // has a frame, and that the frame's pc is within its opcodes.
+    pub fn validate(&self) -> Result<()> {
+        let frame = self.frame()?;
+        if frame.pc > self.ops.len() {
+            return Err(trap(format!(
+                "vm pc {} is past its {} opcodes",
+                frame.pc,
+                self.ops.len()
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.fault.is_some() || self.frame().is_ok_and(|f| f.pc >= self.ops.len())
+    }
+
+    // The fault that stopped evaluation, if one did.
+    pub fn fault(&self) -> Option<&Fault> {
+        self.fault.as_ref()
+    }
+
+    // About how many bytes the values on the operand stacks take up (see
+    // Vals::bytes), not counting a Case arm's or Filter predicate's own.
+    pub fn stack_bytes(&self) -> usize {
+        let frame = |f: &Frame| f.vals.iter().map(Vals::bytes).sum::<usize>();
+        self.stack.iter().map(frame).sum()
+    }
+
+    // The value on top of the operand stack once evaluation is done.
+    pub fn result(&self) -> Option<&Vals> {
+        if self.is_done() && self.fault.is_none() {
+            self.frame().ok()?.vals.last()
+        } else {
+            None
+        }
+    }
+
+    // Execute up to n opcodes, returning how many were executed. Fewer than
+    // n means evaluation finished.
+    pub fn step(&mut self, n: usize) -> Result<usize> {
+        if let Some(f) = &self.fault {
+            return Err(fault::raise(f.clone()));
+        }
+        let mut count = 0;
+        while count < n && !self.is_done() {
+            let pc = self.frame()?.pc;
+            let op = self.ops[pc].clone();
+            if let Err(e) = self.exec(&op) {
+                let f = fault::classify(e, pc)?;
+                self.fault = Some(f.clone());
+                return Err(fault::raise(f));
+            }
+            self.frame_mut()?.pc += 1;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    pub fn run(&mut self) -> Result<Vals> {
+        while !self.is_done() {
+            self.step(usize::MAX)?;
+        }
+        if let Some(f) = &self.fault {
+            return Err(fault::raise(f.clone()));
+        }
+        match self.result() {
+            Some(v) => Ok(v.clone()),
+            None => Err(trap("vm finished with an empty operand stack")),
+        }
+    }
+
+    // Run to completion, with a fault as a result like a value. The only
+    // errors are traps.
+    pub fn eval(&mut self) -> Result<std::result::Result<Vals, Fault>> {
+        match self.run() {
+            Ok(v) => Ok(Ok(v)),
+            Err(e) => match self.fault.clone() {
+                Some(f) => Ok(Err(f)),
+                None => Err(e),
+            },
+        }
+    }
+
+    // A Vm always has a frame, unless it was decoded from bad bytes.
+    fn frame(&self) -> Result<&Frame> {
+        self.stack.last().ok_or_else(no_frame)
+    }
+
+    fn frame_mut(&mut self) -> Result<&mut Frame> {
+        self.stack.last_mut().ok_or_else(no_frame)
+    }
+
+    fn exec(&mut self, op: &Opcode) -> Result<()> {
+        if let Opcode::Case(arms) = op {
+            let val = self.case(arms)?;
+            self.frame_mut()?.vals.push(val);
+            return Ok(());
+        }
+        if let Opcode::Filter(pred) = op {
+            let val = self.filter(pred)?;
+            self.frame_mut()?.vals.push(val);
+            return Ok(());
+        }
+        if let Opcode::Call(c) = op {
+            let frame = self.frame_mut()?;
+            if frame.vals.len() < c.arity {
+                return Err(trap("vm operand stack underflow"));
+            }
+            let args = frame.vals.split_off(frame.vals.len() - c.arity);
+            let val = self.udfs.call(&c.name, &args)?;
+            self.frame_mut()?.vals.push(val);
+            return Ok(());
+        }
+        let overflow = self.overflow;
+        let deterministic = self.float_mode == FloatMode::Deterministic;
+        let udfs = &self.udfs;
+        let frame = self.stack.last_mut().ok_or_else(no_frame)?;
+        let val = match op {
+            Opcode::Literal(v) => v.clone(),
+            Opcode::Path(p) => frame.load(p)?,
+            Opcode::Reify => env::reify(&frame.ctx),
+            Opcode::Query(p) => {
+                let a = frame.pop()?;
+                env::query(&a, p)?
+            }
+            Opcode::PrimUnOp(op) => {
+                let a = frame.pop()?;
+                if deterministic {
+                    float::check_unop(*op, &a)?;
+                }
+                kernel::unop(*op, &a)?
+            }
+            Opcode::PrimBinOp(op) => {
+                let b = frame.pop()?;
+                let a = frame.pop()?;
+                if deterministic {
+                    float::check_binop(*op, &a, &b)?;
+                }
+                kernel::binop(*op, &a, &b, overflow)?
+            }
+            Opcode::BinFn(f) => {
+                if frame.vals.len() < f.arity() {
+                    return Err(trap("vm operand stack underflow"));
+                }
+                let args = frame.vals.split_off(frame.vals.len() - f.arity());
+                binfn::apply(*f, &args, &binfn::NoHeap)?
+            }
+            Opcode::TimeFn(f) => {
+                if frame.vals.len() < f.arity() {
+                    return Err(trap("vm operand stack underflow"));
+                }
+                let args = frame.vals.split_off(frame.vals.len() - f.arity());
+                time::apply(*f, &args)?
+            }
+            Opcode::BitFn(f) => {
+                if frame.vals.len() < f.arity() {
+                    return Err(trap("vm operand stack underflow"));
+                }
+                let args = frame.vals.split_off(frame.vals.len() - f.arity());
+                bits::apply(*f, &args)?
+            }
+            Opcode::Convert(unit) => {
+                let a = frame.pop()?;
+                kernel::convert(&a, *unit, overflow)?
+            }
+            Opcode::Cast(c) => {
+                let a = frame.pop()?;
+                cast::cast(&a, c)?
+            }
+            Opcode::Project(p) => {
+                let a = frame.pop()?;
+                all::unlabelled(a.project(&p.0)?)
+            }
+            Opcode::Zip(labels) => {
+                if frame.vals.len() < labels.len() {
+                    return Err(trap("vm operand stack underflow"));
+                }
+                let vals = frame.vals.split_off(frame.vals.len() - labels.len());
+                Vals::zip(labels.iter().cloned().zip(vals).collect())?
+            }
+            Opcode::Agg(op) => {
+                let a = frame.pop()?;
+                agg::aggregate(*op, &a, overflow)?
+            }
+            Opcode::Group(g) => {
+                let a = frame.pop()?;
+                group::group_record(&a, g, overflow)?
+            }
+            Opcode::Join(j) => {
+                let b = frame.pop()?;
+                let a = frame.pop()?;
+                join::join_records(&a, &b, j)?
+            }
+            Opcode::Sort(keys) => {
+                let a = frame.pop()?;
+                sort::sort_record(&a, keys)?
+            }
+            Opcode::Case(_) | Opcode::Filter(_) | Opcode::Call(_) => unreachable!(),
+            Opcode::Merge => {
+                let b = frame.pop()?;
+                let a = frame.pop()?;
+                env::merge(&a, &b)?
+            }
+            Opcode::Quote(tpl) => {
+                let n = quote::holes(tpl);
+                let mut args = Vec::with_capacity(n);
+                for _ in 0..n {
+                    args.push(frame.pop()?);
+                }
+                args.reverse();
+                quote::build(tpl, args, udfs)?
+            }
+            Opcode::Eval => {
+                return Err(trap(format!("{:?} is not implemented yet", op)));
+            }
+        };
+        let val = match deterministic {
+            true => float::canonical_nans(val),
+            false => val,
+        };
+        frame.vals.push(val);
+        Ok(())
+    }
+
+    // Each arm runs in a Vm of its own, sharing this frame's context, and
+    // starts with its variant's values on the operand stack.
+    fn case(&mut self, arms: &[Vec<Opcode>]) -> Result<Vals> {
+        let frame = self.frame_mut()?;
+        let scrut = frame.pop()?;
+        let ctx = std::mem::take(&mut frame.ctx);
+        let mut arm_vm = Vm::new(Vec::new(), ctx)
+            .with_overflow(self.overflow)
+            .with_float_mode(self.float_mode)
+            .with_udfs(self.udfs.clone());
+        let res = any::case(&scrut, arms.len(), |k, vals| {
+            arm_vm.ops = arms[k].clone();
+            let frame = arm_vm.frame_mut()?;
+            frame.pc = 0;
+            frame.vals = vec![vals.clone()];
+            arm_vm.run()
+        });
+        self.frame_mut()?.ctx = std::mem::take(&mut arm_vm.frame_mut()?.ctx);
+        res
+    }
+
+    // The predicate runs to completion in a Vm of its own, with the table's
+    // fields shadowing this frame's context.
+    fn filter(&mut self, pred: &[Opcode]) -> Result<Vals> {
+        let frame = self.frame_mut()?;
+        let table = frame.pop()?;
+        let fields = Tab::from_named(table.unzip()?)?;
+        let mut ctx = std::mem::take(&mut frame.ctx);
+        ctx.push(fields);
+        let mut pred_vm = Vm::new(pred.to_vec(), ctx)
+            .with_overflow(self.overflow)
+            .with_float_mode(self.float_mode)
+            .with_udfs(self.udfs.clone());
+        let mask = pred_vm.run();
+        let mut ctx = std::mem::take(&mut pred_vm.frame_mut()?.ctx);
+        ctx.pop();
+        self.frame_mut()?.ctx = ctx;
+        table.compact(&mask?)
+    }
+}
+
+fn no_frame() -> submerge_base::Error {
+    trap("vm has no frame")
+}
@@ -0,0 +1,189 @@
+// Compression of large message buffers. Thunks carrying big Tabs serialize
+// to big buffers, and they compress well.
+//
+// Every buffer a Node queues starts with a three-byte header. The low four
+// bits of its first byte say which codec the body is compressed with, if
+// any, and its high four bits which codecs the node that sent it can
+// decompress; the other two bytes are the protocol version the body is
+// encoded at, little-endian (see version.rs). So two nodes
+// negotiate compression just by talking: a node only compresses what it
+// sends a peer once it's heard from the peer which codecs it takes, and a
+// node that hasn't opted in never says it takes any, and is never sent a
+// compressed buffer. A node that's opted in compresses buffers past a size
+// threshold with the first of its codecs the peer takes, and sends those
+// that don't shrink as they are.
+//
+// A buffer is decompressed before it's decoded, to no more than MAX_FRAME
+// bytes, so a small corrupt buffer can't swell without bound.
+
+use crate::transport::MAX_FRAME;
+use ruzstd::decoding::StreamingDecoder;
+use ruzstd::encoding::{compress_to_vec, CompressionLevel};
+use std::borrow::Cow;
+use std::io::Read;
+use submerge_base::{Error, Result};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Codec {
+    Lz4,
+    Zstd,
+}
+
+impl Codec {
+    const ALL: [Codec; 2] = [Codec::Lz4, Codec::Zstd];
+
+    // The codec's number in a header; 0 is no codec.
+    fn id(self) -> u8 {
+        match self {
+            Codec::Lz4 => 1,
+            Codec::Zstd => 2,
+        }
+    }
+
+    // The codec's bit in a set of them.
+    fn bit(self) -> u8 {
+        1 << (self.id() - 1)
+    }
+
+    fn compress(self, buf: &[u8]) -> Vec<u8> {
+        match self {
+            Codec::Lz4 => lz4_flex::compress_prepend_size(buf),
+            Codec::Zstd => compress_to_vec(buf, CompressionLevel::Fastest),
+        }
+    }
+
+    fn decompress(self, buf: &[u8]) -> Result<Vec<u8>> {
+        let out = match self {
+            Codec::Lz4 => {
+                // The decompressed size comes first; check it before
+                // making room for it.
+                let (size, _) = lz4_flex::block::uncompressed_size(buf)
+                    .map_err(|e| Error::corruption(format!("lz4: {}", e)))?;
+                if size > MAX_FRAME {
+                    return Err(too_large(size));
+                }
+                lz4_flex::decompress_size_prepended(buf)
+                    .map_err(|e| Error::corruption(format!("lz4: {}", e)))?
+            }
+            Codec::Zstd => {
+                let decoder = StreamingDecoder::new(buf)
+                    .map_err(|e| Error::corruption(format!("zstd: {}", e)))?;
+                let mut out = Vec::new();
+                decoder.take(MAX_FRAME as u64 + 1).read_to_end(&mut out)?;
+                if out.len() > MAX_FRAME {
+                    return Err(too_large(out.len()));
+                }
+                out
+            }
+        };
+        Ok(out)
+    }
+}
+
+fn too_large(size: usize) -> Error {
+    Error::corruption(format!("buffer decompresses to over {} bytes", size))
+}
+
+// Which codecs a node takes, and which buffers it compresses.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Compression {
+    // In the order the node prefers to send with them.
+    codecs: Vec<Codec>,
+    threshold: usize,
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression::new()
+    }
+}
+
+impl Compression {
+    // Take either codec, preferring lz4, and compress buffers of 4KiB or
+    // more.
+    pub fn new() -> Compression {
+        Compression {
+            codecs: Codec::ALL.to_vec(),
+            threshold: 4096,
+        }
+    }
+
+    pub fn with_codecs(mut self, codecs: impl IntoIterator<Item = Codec>) -> Compression {
+        self.codecs = Vec::new();
+        for codec in codecs {
+            if !self.codecs.contains(&codec) {
+                self.codecs.push(codec);
+            }
+        }
+        self
+    }
+
+    pub fn with_threshold(mut self, threshold: usize) -> Compression {
+        self.threshold = threshold;
+        self
+    }
+
+    // The set of codecs the node takes, as a header carries it.
+    fn takes(&self) -> u8 {
+        self.codecs.iter().fold(0, |set, c| set | c.bit())
+    }
+}
+
+// A header for a buffer from a node with some compression, if any, and the
+// codec its body is compressed with, if any.
+pub(crate) const HEADER: usize = 3;
+
+// A header for a buffer from a node with some compression, if any, whose
+// body is compressed with a codec, if any, and encoded at a version.
+pub(crate) fn header(
+    compression: Option<&Compression>,
+    codec: Option<Codec>,
+    version: u16,
+) -> [u8; HEADER] {
+    let takes = compression.map_or(0, |c| c.takes());
+    let [lo, hi] = version.to_le_bytes();
+    [takes << 4 | codec.map_or(0, |c| c.id()), lo, hi]
+}
+
+// Compress a buffer, header and all, for a peer that takes a set of codecs,
+// if it's worth it.
+pub(crate) fn pack(compression: Option<&Compression>, peer: u8, buf: Vec<u8>) -> Vec<u8> {
+    let Some(compression) = compression else {
+        return buf;
+    };
+    let body = &buf[HEADER..];
+    if body.len() < compression.threshold {
+        return buf;
+    }
+    let codecs = compression.codecs.iter();
+    let Some(codec) = codecs.copied().find(|c| peer & c.bit() != 0) else {
+        return buf;
+    };
+    let compressed = codec.compress(body);
+    if compressed.len() >= body.len() {
+        return buf;
+    }
+    let version = u16::from_le_bytes([buf[1], buf[2]]);
+    let mut out = Vec::with_capacity(HEADER + compressed.len());
+    out.extend_from_slice(&header(Some(compression), Some(codec), version));
+    out.extend_from_slice(&compressed);
+    out
+}
+
+// The set of codecs a buffer's sender takes, the version its body is
+// encoded at, and its body, decompressed.
+pub(crate) fn unpack(buf: &[u8]) -> Result<(u8, u16, Cow<'_, [u8]>)> {
+    let Some(([header, lo, hi], body)) = buf.split_first_chunk::<HEADER>() else {
+        return Err(Error::protocol("truncated message header"));
+    };
+    let takes = header >> 4;
+    let version = u16::from_le_bytes([*lo, *hi]);
+    let body = match header & 0xf {
+        0 => Cow::Borrowed(body),
+        id => match Codec::ALL.iter().find(|c| c.id() == id) {
+            Some(codec) => Cow::Owned(codec.decompress(body)?),
+            None => return Err(Error::protocol(format!("unknown codec {}", id))),
+        },
+    };
+    Ok((takes, version, body))
+}
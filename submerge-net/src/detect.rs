@@ -0,0 +1,162 @@
+// A phi-accrual failure detector. Rather than calling a peer dead after a
+// fixed timeout, it learns how often each peer is usually heard from, from
+// the times messages from it arrive, and says how suspicious the silence
+// since the last one is: phi is -log10 of the chance that a peer behaving
+// as it has would have stayed quiet this long, so phi 1 means a 10% chance
+// and phi 8 one in a hundred million. The intervals are taken to be
+// normally distributed, with a floor on their deviation so that a peer
+// heard like clockwork isn't suspected at the first hiccup.
+//
+// Two thresholds turn phi into a verdict: past the first, a peer is slow,
+// behind but maybe only loaded or paused; past the second, it's taken for
+// dead. Until a peer's been heard from twice there's nothing to go on, and
+// its suspicion is unknown.
+//
+// Any message counts. A peer that has nothing to say to this node would
+// look dead, so the detector also says which peers have been quiet for a
+// ping interval, for whoever drives it to ping; their replies feed it like
+// any other message.
+
+use crate::{Duration, NodeID, NodeTime};
+use std::collections::{BTreeMap, VecDeque};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Suspicion {
+    Unknown,
+    Alive,
+    Slow,
+    Dead,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct History {
+    last: Option<NodeTime>,
+    // The latest intervals between arrivals, in microseconds.
+    intervals: VecDeque<i64>,
+    pinged: Option<NodeTime>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Detector {
+    window: usize,
+    min_std: Duration,
+    slow: f64,
+    dead: f64,
+    ping: Duration,
+    peers: BTreeMap<NodeID, History>,
+}
+
+impl Default for Detector {
+    fn default() -> Detector {
+        Detector::new()
+    }
+}
+
+impl Detector {
+    // A window of 100 intervals, deviating by at least 100ms, with a peer
+    // slow from phi 3 and dead from phi 8, pinged after a second's quiet.
+    pub fn new() -> Detector {
+        Detector {
+            window: 100,
+            min_std: Duration::from_millis(100),
+            slow: 3.0,
+            dead: 8.0,
+            ping: Duration::from_millis(1000),
+            peers: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_window(mut self, window: usize) -> Detector {
+        self.window = window.max(1);
+        self
+    }
+
+    pub fn with_min_std(mut self, min_std: Duration) -> Detector {
+        self.min_std = Duration::from_micros(min_std.micros().max(1));
+        self
+    }
+
+    pub fn with_thresholds(mut self, slow: f64, dead: f64) -> Detector {
+        self.slow = slow;
+        self.dead = dead.max(slow);
+        self
+    }
+
+    pub fn with_ping(mut self, ping: Duration) -> Detector {
+        self.ping = ping;
+        self
+    }
+
+    // A message from a peer arrived.
+    pub fn heard(&mut self, node: NodeID, now: NodeTime) {
+        let history = self.peers.entry(node).or_default();
+        if let Some(last) = history.last {
+            if now <= last {
+                return;
+            }
+            if history.intervals.len() == self.window {
+                history.intervals.pop_front();
+            }
+            history.intervals.push_back(now.micros() - last.micros());
+        }
+        history.last = Some(now);
+    }
+
+    // How suspicious a peer's silence is, if it's been heard from enough
+    // to tell.
+    pub fn phi(&self, node: NodeID, now: NodeTime) -> Option<f64> {
+        let history = self.peers.get(&node)?;
+        let last = history.last?;
+        if history.intervals.is_empty() {
+            return None;
+        }
+        let n = history.intervals.len() as f64;
+        let mean = history.intervals.iter().sum::<i64>() as f64 / n;
+        let var = history
+            .intervals
+            .iter()
+            .map(|i| (*i as f64 - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        let std = var.sqrt().max(self.min_std.micros() as f64);
+        let elapsed = (now.micros() - last.micros()) as f64;
+        // A logistic approximation of the normal distribution's tail.
+        let y = (elapsed - mean) / std;
+        let e = (-y * (1.5976 + 0.070566 * y * y)).exp();
+        let phi = match elapsed > mean {
+            true => -(e / (1.0 + e)).log10(),
+            false => -(1.0 - 1.0 / (1.0 + e)).log10(),
+        };
+        Some(phi.max(0.0))
+    }
+
+    pub fn suspicion(&self, node: NodeID, now: NodeTime) -> Suspicion {
+        match self.phi(node, now) {
+            None => Suspicion::Unknown,
+            Some(phi) if phi >= self.dead => Suspicion::Dead,
+            Some(phi) if phi >= self.slow => Suspicion::Slow,
+            Some(_) => Suspicion::Alive,
+        }
+    }
+
+    // Every peer known of, and how suspicious it is.
+    pub fn all(&self, now: NodeTime) -> BTreeMap<NodeID, Suspicion> {
+        let peers = self.peers.keys();
+        peers.map(|p| (*p, self.suspicion(*p, now))).collect()
+    }
+
+    // The peers among some that have been quiet for a ping interval, and
+    // haven't been pinged in one; they're taken to be pinged now.
+    pub fn quiet(&mut self, peers: impl Iterator<Item = NodeID>, now: NodeTime) -> Vec<NodeID> {
+        let mut quiet = Vec::new();
+        for peer in peers {
+            let history = self.peers.entry(peer).or_default();
+            let due = |t: Option<NodeTime>| t.is_none_or(|t| t + self.ping <= now);
+            if due(history.last) && due(history.pinged) {
+                history.pinged = Some(now);
+                quiet.push(peer);
+            }
+        }
+        quiet
+    }
+}
@@ -0,0 +1,236 @@
+// Anti-entropy gossip, for spreading what every node should eventually
+// know without each node telling every other: which nodes are alive, which
+// configuration epoch each is in, and whatever else the layers above plug
+// in, such as the txn layer's watermark maps.
+//
+// Once a period, a node bumps its own heartbeat and sends a digest to a
+// few peers picked at random (the fanout): the heartbeat and epoch of every
+// node it knows of, and a payload from each provider. A peer merges the
+// digest into its own and replies with its own, so both ends learn what
+// the other knew; in a few rounds word reaches every node. A node's
+// heartbeat only goes up, so a digest with an older one is old news about
+// that node, and is ignored. A node whose heartbeat hasn't gone up for a
+// while is suspected of being down.
+//
+// Providers decide what their payloads mean. Each has a kind, naming its
+// payloads in digests, gives its node's view when a digest goes out, and
+// merges the views that arrive, which it should do idempotently and in any
+// order: digests are duplicated, reordered and lost like any message.
+//
+// Peers are picked with a seeded generator, so a simulated run is a
+// function of its seed.
+
+use crate::{Duration, Msg, Node, NodeID, NodeTime, RealmTime, SpecificMsg};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use submerge_base::Result;
+
+// Something for gossip to spread.
+pub trait GossipPayload {
+    fn kind(&self) -> &'static str;
+    // This node's view, to send.
+    fn provide(&self) -> Result<Vec<u8>>;
+    // Another node's view, to merge.
+    fn merge(&mut self, from: NodeID, bytes: &[u8]) -> Result<()>;
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+struct Digest {
+    // Each node's heartbeat and epoch.
+    members: BTreeMap<NodeID, (i64, i64)>,
+    payloads: BTreeMap<String, Vec<u8>>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Member {
+    heartbeat: i64,
+    epoch: i64,
+    // When its heartbeat last went up, as far as this node knows.
+    seen: NodeTime,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Gossip {
+    me: NodeID,
+    period: Duration,
+    fanout: usize,
+    suspect: Duration,
+    rng: u64,
+    last: Option<NodeTime>,
+    members: BTreeMap<NodeID, Member>,
+}
+
+impl Gossip {
+    // Gossip to 3 peers a period, suspecting nodes not heard from in 10.
+    pub fn new(me: NodeID, period: Duration, seed: u64) -> Gossip {
+        let member = Member {
+            heartbeat: 0,
+            epoch: 0,
+            seen: NodeTime::from_micros(0),
+        };
+        Gossip {
+            me,
+            period,
+            fanout: 3,
+            suspect: Duration::from_micros(period.micros().saturating_mul(10)),
+            rng: seed,
+            last: None,
+            members: BTreeMap::from([(me, member)]),
+        }
+    }
+
+    pub fn with_fanout(mut self, fanout: usize) -> Gossip {
+        self.fanout = fanout.max(1);
+        self
+    }
+
+    // Suspect a node whose heartbeat hasn't gone up for this long.
+    pub fn with_suspect(mut self, suspect: Duration) -> Gossip {
+        self.suspect = suspect;
+        self
+    }
+
+    // Start gossiping with some nodes, as well as those heard of.
+    pub fn meet(&mut self, nodes: impl IntoIterator<Item = NodeID>, now: NodeTime) {
+        for node in nodes {
+            let member = Member {
+                heartbeat: 0,
+                epoch: 0,
+                seen: now,
+            };
+            self.members.entry(node).or_insert(member);
+        }
+    }
+
+    // Stop gossiping with a node, say once it's left the configuration.
+    // Gossip from a node that still knows of it brings it back.
+    pub fn forget(&mut self, node: NodeID) {
+        if node != self.me {
+            self.members.remove(&node);
+        }
+    }
+
+    // Say this node has moved to a configuration epoch.
+    pub fn set_epoch(&mut self, epoch: i64) {
+        if let Some(me) = self.members.get_mut(&self.me) {
+            me.epoch = me.epoch.max(epoch);
+        }
+    }
+
+    fn random(&mut self, n: u64) -> u64 {
+        self.rng = self
+            .rng
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.rng >> 33) % n
+    }
+
+    fn digest(&self, payloads: &[&mut dyn GossipPayload]) -> Result<Vec<u8>> {
+        let members = self.members.iter();
+        let digest = Digest {
+            members: members.map(|(n, m)| (*n, (m.heartbeat, m.epoch))).collect(),
+            payloads: payloads
+                .iter()
+                .map(|p| Ok((p.kind().to_string(), p.provide()?)))
+                .collect::<Result<_>>()?,
+        };
+        Ok(rmp_serde::to_vec(&digest)?)
+    }
+
+    // Run a round, if a period has passed since the last.
+    pub fn poll(
+        &mut self,
+        net: &mut Node,
+        now: NodeTime,
+        payloads: &mut [&mut dyn GossipPayload],
+    ) -> Result<()> {
+        if self.last.is_some_and(|t| now < t + self.period) {
+            return Ok(());
+        }
+        self.last = Some(now);
+        if let Some(me) = self.members.get_mut(&self.me) {
+            me.heartbeat += 1;
+            me.seen = now;
+        }
+        let mut peers = self.peers().into_iter().collect::<Vec<_>>();
+        let digest = self.digest(payloads)?;
+        let msg_time = RealmTime::new(now, self.me, 0);
+        for _ in 0..self.fanout.min(peers.len()) {
+            let peer = peers.swap_remove(self.random(peers.len() as u64) as usize);
+            let gossip = SpecificMsg::Gossip(digest.clone());
+            net.send_msg(Msg::new(self.me, peer, msg_time, msg_time, 0, gossip))?;
+        }
+        Ok(())
+    }
+
+    // Merge a digest from a peer, and reply to one that isn't a reply.
+    pub fn recv(
+        &mut self,
+        net: &mut Node,
+        msg: &Msg,
+        now: NodeTime,
+        payloads: &mut [&mut dyn GossipPayload],
+    ) -> Result<()> {
+        let bytes = match msg.specific() {
+            SpecificMsg::Gossip(bytes) | SpecificMsg::GossipReply(bytes) => bytes,
+            _ => return Ok(()),
+        };
+        let digest: Digest = rmp_serde::from_slice(bytes)?;
+        for (node, (heartbeat, epoch)) in digest.members {
+            if node == self.me {
+                continue;
+            }
+            let fresh = Member {
+                heartbeat,
+                epoch,
+                seen: now,
+            };
+            let member = self.members.entry(node).or_insert(fresh);
+            if heartbeat > member.heartbeat {
+                *member = Member {
+                    epoch: member.epoch.max(epoch),
+                    ..fresh
+                };
+            }
+        }
+        for payload in payloads.iter_mut() {
+            if let Some(bytes) = digest.payloads.get(payload.kind()) {
+                payload.merge(msg.src(), bytes)?;
+            }
+        }
+        if let SpecificMsg::Gossip(_) = msg.specific() {
+            let msg_time = RealmTime::new(now, self.me, 0);
+            let reply = SpecificMsg::GossipReply(self.digest(payloads)?);
+            net.reply(msg, msg_time, reply)?;
+        }
+        Ok(())
+    }
+
+    // Every node known of but this one.
+    pub fn peers(&self) -> BTreeSet<NodeID> {
+        let nodes = self.members.keys().filter(|n| **n != self.me);
+        nodes.copied().collect()
+    }
+
+    // The nodes whose heartbeats have gone up recently, this one included.
+    pub fn alive(&self, now: NodeTime) -> BTreeSet<NodeID> {
+        let members = self.members.iter();
+        let alive = members.filter(|(_, m)| now < m.seen + self.suspect);
+        alive.map(|(n, _)| *n).collect()
+    }
+
+    pub fn suspected(&self, now: NodeTime) -> BTreeSet<NodeID> {
+        let alive = self.alive(now);
+        self.peers().difference(&alive).copied().collect()
+    }
+
+    pub fn heartbeat(&self, node: NodeID) -> Option<i64> {
+        self.members.get(&node).map(|m| m.heartbeat)
+    }
+
+    // The latest epoch each node known of has said it's in.
+    pub fn epochs(&self) -> BTreeMap<NodeID, i64> {
+        let members = self.members.iter();
+        members.map(|(n, m)| (*n, m.epoch)).collect()
+    }
+}
@@ -1,13 +1,44 @@
 use core::fmt::Debug;
 use core::hash::Hash;
+use core::ops::Add;
+use queue::Queued;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, VecDeque};
-use submerge_base::{err, Error};
-use submerge_lang::{Expr, Path, Vals};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use submerge_base::{err, telemetry, Error};
+use tracing::{debug, warn};
+
+mod compress;
+mod detect;
+mod gossip;
+mod pool;
+mod queue;
+mod request;
+mod sim;
+mod transport;
+#[cfg(unix)]
+mod unix;
+mod version;
+
+pub use compress::{Codec, Compression};
+pub use detect::{Detector, Suspicion};
+pub use gossip::{Gossip, GossipPayload};
+pub use queue::{Backpressure, QueueLimit, QueueStats, Urgency};
+pub use request::RequestId;
+pub use sim::{Delivery, Latency, LinkFault, SimNet, SimTransport};
+pub use transport::{frame, hello, parse_hello, Frames, Transport, MAX_FRAME};
+#[cfg(unix)]
+pub use unix::{socket_path, UnixTransport};
+pub use version::{Compatibility, OLDEST_PROTOCOL, PROTOCOL};
+
+#[cfg(test)]
+mod test;
 
 pub trait Data: Clone + Debug + Eq + PartialEq + Ord + Hash {}
 impl<T> Data for T where T: Clone + Debug + Eq + PartialEq + Ord + Hash {}
 
+// A buffer queued to go out, and the node it's going to.
+pub type Outgoing = (NodeID, Box<[u8]>);
+
 // A given Realm is a single, coherent, distributed system. It is composed of
 // a set of Nodes, each of which has a unique NodeID.
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
@@ -18,11 +49,43 @@ pub struct NodeID(pub i64);
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 pub struct NodeTime(i64);
 
+impl NodeTime {
+    pub fn from_micros(us: i64) -> NodeTime {
+        NodeTime(us)
+    }
+
+    pub fn micros(self) -> i64 {
+        self.0
+    }
+}
+
+impl Add<Duration> for NodeTime {
+    type Output = NodeTime;
+
+    fn add(self, d: Duration) -> NodeTime {
+        NodeTime(self.0.saturating_add(d.0))
+    }
+}
+
 // Duration is a time-span in signed 64-bit microseconds relative to
 // some NodeTime or RealmTime.
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 pub struct Duration(i64);
 
+impl Duration {
+    pub fn from_micros(us: i64) -> Duration {
+        Duration(us)
+    }
+
+    pub fn from_millis(ms: i64) -> Duration {
+        Duration(ms.saturating_mul(1000))
+    }
+
+    pub fn micros(self) -> i64 {
+        self.0
+    }
+}
+
 // RealmTimes are realm-local extended timestamps. The most
 // significant (time) field stores a NodeTime (microsecond count), but
 // this is then followed by both a NodeID and an event count allowing
@@ -36,11 +99,69 @@ pub struct RealmTime {
     event: i64,
 }
 
+impl RealmTime {
+    pub fn new(time: NodeTime, node: NodeID, event: i64) -> RealmTime {
+        RealmTime { time, node, event }
+    }
+
+    pub fn time(&self) -> NodeTime {
+        self.time
+    }
+
+    pub fn node(&self) -> NodeID {
+        self.node
+    }
+
+    pub fn event(&self) -> i64 {
+        self.event
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 pub enum SpecificMsg {
     Ping,
-    Put(Expr, Vec<Path>),
+    // A reply to a Ping.
+    Pong,
+    // A transaction's thunk, serialized by the txn layer.
+    Put(Vec<u8>),
     Ack,
+    // One of the parts a thunk too large for a single Put is split into,
+    // and the number of them.
+    PutPart {
+        part: u32,
+        parts: u32,
+        bytes: Vec<u8>,
+    },
+    // An ack of one part.
+    AckPart(u32),
+    // The coordinator has called off the transaction the message's txn_time
+    // names, before it was released.
+    Abort,
+    // An ack of an Abort.
+    Aborted,
+    // A node's local watermark, which is also the message's txn_time.
+    Watermark(RealmTime),
+    // A message of a paxos instance, which the message's txn_time names,
+    // serialized by the txn layer.
+    Paxos(Vec<u8>),
+    // A fresh node, asking to join the configuration.
+    Ready,
+    // The state a joining node catches up from, serialized by the txn
+    // layer; the message's txn_time is the configuration's start.
+    CatchUp(Vec<u8>),
+    // A joining node has caught up, and its acks count from now on.
+    CaughtUp,
+    // The hash of a transaction's result, which the message's txn_time
+    // names, as the sending node computed it.
+    ResultHash(u64),
+    // A diverged node, asking for the versions from the message's txn_time
+    // on.
+    Resync,
+    // Those versions, serialized by the txn layer.
+    Segment(Vec<u8>),
+    // A gossip digest (see gossip.rs), and the digest sent back in reply.
+    Gossip(Vec<u8>),
+    GossipReply(Vec<u8>),
 }
 
 // All inter-node communication takes the form of Messages. A message has
@@ -57,14 +178,94 @@ pub struct Msg {
     specific: SpecificMsg,
 }
 
+impl Msg {
+    pub fn new(
+        src: NodeID,
+        dst: NodeID,
+        txn_time: RealmTime,
+        msg_time: RealmTime,
+        sequence: i64,
+        specific: SpecificMsg,
+    ) -> Msg {
+        Msg {
+            src,
+            dst,
+            txn_time,
+            msg_time,
+            sequence,
+            response: false,
+            specific,
+        }
+    }
+
+    // The response to this message, from its destination back to its
+    // source, for the same transaction and sequence number.
+    pub fn response(&self, msg_time: RealmTime, specific: SpecificMsg) -> Msg {
+        Msg {
+            src: self.dst,
+            dst: self.src,
+            txn_time: self.txn_time,
+            msg_time,
+            sequence: self.sequence,
+            response: true,
+            specific,
+        }
+    }
+
+    pub fn src(&self) -> NodeID {
+        self.src
+    }
+
+    pub fn dst(&self) -> NodeID {
+        self.dst
+    }
+
+    pub fn txn_time(&self) -> RealmTime {
+        self.txn_time
+    }
+
+    pub fn msg_time(&self) -> RealmTime {
+        self.msg_time
+    }
+
+    pub fn sequence(&self) -> i64 {
+        self.sequence
+    }
+
+    pub fn is_response(&self) -> bool {
+        self.response
+    }
+
+    pub fn specific(&self) -> &SpecificMsg {
+        &self.specific
+    }
+
+    // Decode a buffer a Node queued to send, decompressing it if need be.
+    pub fn decode(buf: &[u8]) -> Result<Msg, Error> {
+        let (_, _, body) = compress::unpack(buf)?;
+        Ok(rmp_serde::from_slice(&body)?)
+    }
+}
+
 // Each message sent or received turns into a single [u8] buffer added to
-// the incoming or outgoing deque of the associated IOQueues. Transports
-// then turn these into bytes-on-the-wire with whatever framing the transport
-// finds necessary.
+// the incoming or outgoing deque of the associated IOQueues, behind a
+// header saying how it's compressed (see compress.rs) and which protocol
+// version it's encoded at (see version.rs). Transports (see
+// transport.rs) then turn these into bytes-on-the-wire with whatever
+// framing the transport finds necessary. What's queued to each peer may be
+// bounded (see queue.rs).
 #[derive(Clone, Debug, Eq, PartialEq, Hash, Default)]
 pub struct IOQueues {
-    outgoing: VecDeque<(NodeID, Box<[u8]>)>,
+    outgoing: VecDeque<Queued>,
     incoming: VecDeque<(NodeID, Box<[u8]>)>,
+    // The bounds on every peer's queue, and on those of peers with their
+    // own.
+    limit: Option<QueueLimit>,
+    limits: BTreeMap<NodeID, QueueLimit>,
+    stats: BTreeMap<NodeID, QueueStats>,
+    // The peers whose queues have filled under a Block policy, and haven't
+    // drained yet.
+    blocked: BTreeSet<NodeID>,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
@@ -82,14 +283,26 @@ pub struct Node {
     /// these and complete requests.
     incoming: VecDeque<Box<Msg>>,
     /// The set of request messages that have been sent but either not yet
-    /// responded-to, or not yet consumed by [`Node::recv_msg`].
-    requests: BTreeMap<i64, Request>,
+    /// responded-to, or not yet consumed by [`Node::recv_msg`] (see
+    /// request.rs).
+    requests: BTreeMap<RequestId, Request>,
     /// The set of decoded incoming request/response pairs awaiting consumption.
-    complete: VecDeque<i64>,
+    complete: VecDeque<RequestId>,
+    /// The sequence number of the next request sent.
+    sequence: i64,
     /// The set of incoming and outgoing serialized byte buffers associated with
     /// each peer node. [`Node::recv_bytes`] and [`Node::send_bytes`] operate on
     /// these.
     ioqueues: IOQueues,
+    /// Which codecs this node takes, and which buffers it compresses, if it
+    /// compresses any.
+    compression: Option<Compression>,
+    /// The set of codecs each peer has said it takes.
+    codecs: BTreeMap<NodeID, u8>,
+    /// The protocol versions this node speaks.
+    compatibility: Compatibility,
+    /// The protocol version each peer has said it speaks.
+    versions: BTreeMap<NodeID, u16>,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Default, Hash)]
@@ -108,19 +321,75 @@ impl Node {
         Self::default()
     }
 
+    // Bound what's queued to each peer.
+    pub fn with_queue_limit(mut self, limit: QueueLimit) -> Self {
+        self.ioqueues.limit = Some(limit);
+        self
+    }
+
+    pub fn with_peer_queue_limit(mut self, peer: NodeID, limit: QueueLimit) -> Self {
+        self.ioqueues.limits.insert(peer, limit);
+        self
+    }
+
+    // Whether sends to a peer are being refused until its queue drains.
+    pub fn blocked(&self, peer: NodeID) -> bool {
+        self.ioqueues.blocked.contains(&peer)
+    }
+
+    // What's queued to each peer sent to, and how much has been refused or
+    // shed.
+    pub fn queues(&self) -> &BTreeMap<NodeID, QueueStats> {
+        &self.ioqueues.stats
+    }
+
+    // Compress large buffers to peers that take compressed ones.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    // Speak only the protocol versions in a window.
+    pub fn with_compatibility(mut self, compatibility: Compatibility) -> Self {
+        self.compatibility = compatibility;
+        self
+    }
+
+    // The protocol versions the node speaks, which a stream transport says
+    // in its hellos.
+    pub fn compatibility(&self) -> Compatibility {
+        self.compatibility
+    }
+
+    // The protocol version each peer heard from speaks.
+    pub fn versions(&self) -> &BTreeMap<NodeID, u16> {
+        &self.versions
+    }
+
+    // The protocol version this node speaks to a peer.
+    pub fn version(&self, peer: NodeID) -> u16 {
+        let version = self.versions.get(&peer).copied();
+        self.compatibility.with(version)
+    }
+
     pub fn send_msg(&mut self, msg: Msg) -> Result<(), Error> {
+        let _span = telemetry::msg_span("send", &msg.src, &msg.dst, msg.sequence).entered();
         let dst = msg.dst;
-        let buf = rmp_serde::to_vec(&msg)?;
-        self.ioqueues
-            .outgoing
-            .push_back((dst, buf.into_boxed_slice()));
-        Ok(())
+        let version = self.version(dst);
+        msg.specific.check(dst, version)?;
+        let header = compress::header(self.compression.as_ref(), None, version);
+        let mut buf = header.to_vec();
+        rmp_serde::encode::write(&mut buf, &msg)?;
+        let peer = self.codecs.get(&dst).copied().unwrap_or(0);
+        let buf = compress::pack(self.compression.as_ref(), peer, buf);
+        let urgency = msg.specific.urgency();
+        self.ioqueues.push(dst, urgency, buf.into_boxed_slice())
     }
 
     pub fn maybe_pop_incoming_msg(&mut self) -> Option<Box<Msg>> {
         // When incoming and complete both have content, alternate
         // messages from one or the other.
-        if self.incoming.len() + self.complete.len() & 1 == 0 {
+        if self.complete.is_empty() || (self.incoming.len() + self.complete.len()) & 1 == 0 {
             self.incoming.pop_front()
         } else {
             None
@@ -128,28 +397,31 @@ impl Node {
     }
 
     pub fn recv_msg(&mut self) -> Result<RecvMsg, Error> {
-        if self.incoming.is_empty() && self.complete.is_empty() {
-            if let Some((src, buf)) = self.ioqueues.incoming.pop_front() {
-                self.decode_msg(src, buf)?;
-            }
+        // Decoding a buffer may drop its message, so keep going until
+        // there's one to return or nothing left.
+        while self.incoming.is_empty() && self.complete.is_empty() {
+            let Some((src, buf)) = self.ioqueues.incoming.pop_front() else {
+                break;
+            };
+            self.decode_msg(src, buf)?;
         }
 
         if let Some(msg) = self.maybe_pop_incoming_msg() {
             Ok(RecvMsg::Single(msg))
         } else if let Some(id) = self.complete.pop_front() {
             if let Some(req) = self.requests.remove(&id) {
-                if req.req.sequence != id {
+                if req.req.sequence != id.sequence() || req.req.dst != id.dst() {
                     return Err(err("Unexpected sequence"));
                 }
                 if req.req.response {
                     return Err(err("Request is a response"));
                 }
                 if let Some(res) = req.res {
-                    if res.sequence != id {
-                        return Err(err("Mismatched sequence"));
+                    if res.answers() != Some(id) {
+                        return Err(Error::protocol("Mismatched sequence"));
                     }
                     if !res.response {
-                        return Err(err("Response is not a response"));
+                        return Err(Error::protocol("Response is not a response"));
                     }
                     Ok(RecvMsg::Paired { req: req.req, res })
                 } else {
@@ -168,28 +440,62 @@ impl Node {
         Ok(())
     }
 
-    pub fn send_byes(&mut self) -> Result<Option<(NodeID, Box<[u8]>)>, Error> {
-        if let Some((dst, buf)) = self.ioqueues.outgoing.pop_front() {
-            Ok(Some((dst, buf)))
-        } else {
-            Ok(None)
-        }
+    // The next buffer queued to a node that passes a test.
+    pub fn send_bytes_where(&mut self, to: impl Fn(NodeID) -> bool) -> Option<(NodeID, Box<[u8]>)> {
+        let next = self.ioqueues.pop(|dst, _| to(dst));
+        next.map(|(dst, _, buf)| (dst, buf))
+    }
+
+    pub fn send_byes(&mut self) -> Result<Option<Outgoing>, Error> {
+        let next = self.ioqueues.pop(|_, _| true);
+        Ok(next.map(|(dst, _, buf)| (dst, buf)))
     }
 
     fn decode_msg(&mut self, src: NodeID, buf: Box<[u8]>) -> Result<(), Error> {
-        let msg: Box<Msg> = Box::new(rmp_serde::from_slice(buf.as_ref())?);
+        let (takes, version, body) = compress::unpack(&buf)?;
+        self.versions.insert(src, version);
+        if !self.compatibility.accepts(version) {
+            warn!(
+                target: telemetry::TARGET,
+                node = ?src,
+                version,
+                "dropped a message at a protocol version too old to accept"
+            );
+            return Ok(());
+        }
+        let msg: Box<Msg> = match rmp_serde::from_slice(&body) {
+            Ok(msg) => Box::new(msg),
+            Err(e) => match rmp_serde::from_slice::<version::Envelope>(&body) {
+                Ok(envelope) if envelope.src == src => {
+                    debug!(
+                        target: telemetry::TARGET,
+                        node = ?src,
+                        version,
+                        "dropped a message of a kind this node doesn't know"
+                    );
+                    return Ok(());
+                }
+                _ => return Err(e.into()),
+            },
+        };
+        let _span = telemetry::msg_span("recv", &msg.src, &msg.dst, msg.sequence).entered();
         if msg.src != src {
-            return Err(err("Mismatched source"));
+            return Err(Error::protocol("Mismatched source"));
         }
-        if let Some(req) = self.requests.get_mut(&msg.sequence) {
-            if req.res.is_none() {
-                self.complete.push_back(msg.sequence);
-                req.res = Some(msg);
-            } else {
-                return Err(err("Duplicate response"));
+        self.codecs.insert(src, takes);
+        let answers = msg
+            .answers()
+            .and_then(|id| Some((id, self.requests.get_mut(&id)?)));
+        match answers {
+            Some((id, req)) if req.req.txn_time == msg.txn_time => {
+                if req.res.is_none() {
+                    self.complete.push_back(id);
+                    req.res = Some(msg);
+                } else {
+                    debug!(target: telemetry::TARGET, "dropped a duplicate response");
+                }
             }
-        } else {
-            self.incoming.push_back(msg);
+            _ => self.incoming.push_back(msg),
         }
         Ok(())
     }
@@ -0,0 +1,280 @@
+// Pools of connections to peers, for stream transports. A node keeps up to
+// a pool's worth of connections to each peer it sends to, opened as they're
+// needed, and multiplexes the streams of messages of each urgency (see
+// queue.rs) over them: control and routine messages go on the first, so
+// they never wait behind bulk data, and bulk messages go on the others,
+// each on whichever is least backlogged, another being opened only once
+// every one open has a backlog. In a pool of one, everything goes on the
+// one connection. Buffers on one connection stay in order, but bulk ones
+// spread over several may arrive out of it, as the simulated network's may
+// (see sim.rs).
+//
+// Every connection opens with a hello (see transport.rs). When one breaks,
+// the buffers it hadn't finished writing, one cut off partway included, go
+// back to be sent again, on another connection if there is one and a new
+// one if not; the receiving end drops the partial frame with the broken
+// connection, so nothing arrives twice. Buffers written in full before the
+// break may have been lost with it, as a network loses messages.
+//
+// While a peer can't be connected to, what's sent to it is held, up to a
+// bound past which the oldest is dropped, and connecting is tried again
+// each time the pool is flushed.
+
+use crate::transport::{frame, hello, MAX_FRAME};
+use crate::{Compatibility, NodeID, Urgency};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{ErrorKind, Write};
+use submerge_base::{telemetry, Error, Result};
+use tracing::{debug, warn};
+
+// A buffer framed for the wire, and its message's urgency; a hello has none.
+type Framed = (Option<Urgency>, Box<[u8]>);
+
+#[derive(Debug)]
+struct Link<S> {
+    stream: S,
+    unsent: VecDeque<Framed>,
+    // How much of the first unsent buffer has been written.
+    written: usize,
+}
+
+impl<S: Write> Link<S> {
+    fn new(me: NodeID, compatibility: &Compatibility, stream: S) -> Result<Link<S>> {
+        let mut buf = Vec::new();
+        hello(me, compatibility, &mut buf)?;
+        Ok(Link {
+            stream,
+            unsent: VecDeque::from([(None, buf.into())]),
+            written: 0,
+        })
+    }
+
+    // The bytes waiting for the stream to take them.
+    fn bytes(&self) -> usize {
+        let unsent = self.unsent.iter().map(|(_, buf)| buf.len());
+        unsent.sum::<usize>() - self.written
+    }
+
+    // Write as much as the stream takes.
+    fn flush(&mut self) -> Result<()> {
+        while let Some((_, buf)) = self.unsent.front() {
+            let len = buf.len();
+            match self.stream.write(&buf[self.written..]) {
+                Ok(0) => return Err(Error::io("connection closed")),
+                Ok(n) => self.written += n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+            if self.written == len {
+                self.unsent.pop_front();
+                self.written = 0;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Peer<S> {
+    // The first connection is for control and routine messages, the rest
+    // for bulk ones.
+    links: Vec<Option<Link<S>>>,
+    // Buffers not yet handed to a connection.
+    waiting: VecDeque<Framed>,
+    // Whether the last try to connect failed.
+    down: bool,
+}
+
+impl<S: Write> Peer<S> {
+    // The connection for a message of some urgency.
+    fn lane(&self, urgency: Urgency) -> usize {
+        if urgency != Urgency::Bulk || self.links.len() == 1 {
+            return 0;
+        }
+        let rank = |i: &usize| match self.links[*i].as_ref().map(Link::bytes) {
+            Some(0) => (0, 0),
+            None => (1, 0),
+            Some(bytes) => (2, bytes),
+        };
+        (1..self.links.len()).min_by_key(rank).unwrap_or(0)
+    }
+
+    // Write what each connection has to, and take back what the broken ones
+    // hadn't written.
+    fn write(&mut self, dst: NodeID) {
+        let mut again = Vec::new();
+        for slot in self.links.iter_mut() {
+            let Some(Err(e)) = slot.as_mut().map(Link::flush) else {
+                continue;
+            };
+            warn!(
+                target: telemetry::TARGET,
+                node = ?dst,
+                error = %e,
+                "lost a connection; sending what it hadn't written again"
+            );
+            if let Some(link) = slot.take() {
+                let unsent = link.unsent.into_iter();
+                again.extend(unsent.filter(|(urgency, _)| urgency.is_some()));
+            }
+        }
+        for framed in again.into_iter().rev() {
+            self.waiting.push_front(framed);
+        }
+    }
+
+    // Hand what's waiting to connections, connecting as need be.
+    fn dispatch(
+        &mut self,
+        me: NodeID,
+        compatibility: &Compatibility,
+        dst: NodeID,
+        connect: &mut impl FnMut(NodeID) -> Result<S>,
+    ) {
+        while let Some((urgency, _)) = self.waiting.front() {
+            let i = self.lane(urgency.unwrap_or(Urgency::Routine));
+            if self.links[i].is_none() {
+                match connect(dst).and_then(|stream| Link::new(me, compatibility, stream)) {
+                    Ok(link) => {
+                        if self.down {
+                            debug!(target: telemetry::TARGET, node = ?dst, "reconnected");
+                        }
+                        self.down = false;
+                        self.links[i] = Some(link);
+                    }
+                    Err(e) => {
+                        if !self.down {
+                            warn!(
+                                target: telemetry::TARGET,
+                                node = ?dst,
+                                error = %e,
+                                "can't connect; holding what's sent until it can"
+                            );
+                        }
+                        self.down = true;
+                        return;
+                    }
+                }
+            }
+            if let (Some(link), Some(framed)) = (self.links[i].as_mut(), self.waiting.pop_front()) {
+                link.unsent.push_back(framed);
+            }
+        }
+    }
+
+    // Drop the oldest of what's waiting until it's within a bound.
+    fn trim(&mut self, dst: NodeID, hold: usize) {
+        let mut held = self.waiting.iter().map(|(_, buf)| buf.len()).sum::<usize>();
+        let mut dropped = 0;
+        while held > hold {
+            let Some((_, buf)) = self.waiting.pop_front() else {
+                break;
+            };
+            held -= buf.len();
+            dropped += 1;
+        }
+        if dropped > 0 {
+            warn!(
+                target: telemetry::TARGET,
+                node = ?dst,
+                dropped,
+                "dropped buffers held for a peer that can't be connected to"
+            );
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Pool<S> {
+    me: NodeID,
+    compatibility: Compatibility,
+    size: usize,
+    hold: usize,
+    peers: BTreeMap<NodeID, Peer<S>>,
+}
+
+impl<S: Write> Pool<S> {
+    // One connection to each peer, holding up to MAX_FRAME bytes for a peer
+    // that can't be connected to.
+    pub(crate) fn new(me: NodeID) -> Pool<S> {
+        Pool {
+            me,
+            compatibility: Compatibility::default(),
+            size: 1,
+            hold: MAX_FRAME,
+            peers: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn with_size(mut self, size: usize) -> Pool<S> {
+        self.size = size.max(1);
+        self
+    }
+
+    pub(crate) fn with_hold(mut self, hold: usize) -> Pool<S> {
+        self.hold = hold;
+        self
+    }
+
+    // Say in each connection's hello that the node speaks a window.
+    pub(crate) fn with_compatibility(mut self, compatibility: Compatibility) -> Pool<S> {
+        self.compatibility = compatibility;
+        self
+    }
+
+    pub(crate) fn send(
+        &mut self,
+        dst: NodeID,
+        urgency: Urgency,
+        buf: &[u8],
+        connect: &mut impl FnMut(NodeID) -> Result<S>,
+    ) -> Result<()> {
+        let mut framed = Vec::new();
+        frame(buf, &mut framed)?;
+        let size = self.size;
+        let peer = self.peers.entry(dst).or_insert_with(|| Peer {
+            links: (0..size).map(|_| None).collect(),
+            waiting: VecDeque::new(),
+            down: false,
+        });
+        peer.waiting.push_back((Some(urgency), framed.into()));
+        self.flush_peer(dst, connect);
+        Ok(())
+    }
+
+    // Write what's waiting to go to every peer, reconnecting to those whose
+    // connections have broken.
+    pub(crate) fn flush(&mut self, connect: &mut impl FnMut(NodeID) -> Result<S>) {
+        let peers = self.peers.keys().copied().collect::<Vec<_>>();
+        for dst in peers {
+            self.flush_peer(dst, connect);
+        }
+    }
+
+    fn flush_peer(&mut self, dst: NodeID, connect: &mut impl FnMut(NodeID) -> Result<S>) {
+        let Some(peer) = self.peers.get_mut(&dst) else {
+            return;
+        };
+        peer.write(dst);
+        peer.dispatch(self.me, &self.compatibility, dst, connect);
+        peer.write(dst);
+        peer.trim(dst, self.hold);
+    }
+
+    // The bytes sent to a peer that haven't been written yet.
+    pub(crate) fn backlog(&self, dst: NodeID) -> usize {
+        let Some(peer) = self.peers.get(&dst) else {
+            return 0;
+        };
+        let waiting = peer.waiting.iter().map(|(_, buf)| buf.len()).sum::<usize>();
+        let links = peer.links.iter().flatten();
+        waiting + links.map(Link::bytes).sum::<usize>()
+    }
+
+    // The connections open to a peer.
+    pub(crate) fn conns(&self, dst: NodeID) -> usize {
+        let peer = self.peers.get(&dst);
+        peer.map_or(0, |p| p.links.iter().flatten().count())
+    }
+}
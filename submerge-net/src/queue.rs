@@ -0,0 +1,207 @@
+// Bounds on the buffers a Node queues to send. Without them, a peer that's
+// slow to take what it's sent, or a transport that can't keep up, leaves
+// the queue to it growing until the node runs out of memory.
+//
+// Each peer's queue may be bounded by a number of buffers and of bytes,
+// the same for every peer unless one has a limit of its own. A message that
+// doesn't fit is refused, with an overloaded error, or makes room, as the
+// limit's backpressure policy says:
+//
+//   - Block holds the sender off: once a peer's queue is full, every
+//     message to it is refused until the queue has drained to half its
+//     bounds, so the sender waits for the peer to catch up rather than
+//     keeping its queue pinned at the limit. blocked() says when to wait.
+//   - Drop refuses just the messages that don't fit.
+//   - Shed drops queued buffers of less urgent messages to the peer to
+//     make room for a more urgent one, the oldest first; if that can't
+//     make room, it refuses the new one and drops nothing.
+//
+// A message's urgency comes from its kind: routine messages, which are
+// sent again periodically anyway, go first, then bulk data, and small
+// control messages, which keep the protocols moving, last.
+//
+// Refused and shed messages are lost, as if the network had lost them;
+// the protocols above recover by retrying. Each peer's queue keeps count
+// of both, for status reports.
+//
+// When pump() moves buffers to a transport, it leaves those to a peer whose
+// transport has a limit's worth of bytes it hasn't written yet, so that a
+// slow peer's backlog stays in the queue, where the bounds apply.
+
+use crate::{IOQueues, NodeID, SpecificMsg};
+use serde::{Deserialize, Serialize};
+use submerge_base::{Error, Result};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum Backpressure {
+    Block,
+    Drop,
+    Shed,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum Urgency {
+    Routine,
+    Bulk,
+    Control,
+}
+
+impl SpecificMsg {
+    pub fn urgency(&self) -> Urgency {
+        match self {
+            SpecificMsg::Ping
+            | SpecificMsg::Pong
+            | SpecificMsg::Watermark(_)
+            | SpecificMsg::ResultHash(_)
+            | SpecificMsg::Gossip(_)
+            | SpecificMsg::GossipReply(_) => Urgency::Routine,
+            SpecificMsg::Put(_)
+            | SpecificMsg::PutPart { .. }
+            | SpecificMsg::CatchUp(_)
+            | SpecificMsg::Resync
+            | SpecificMsg::Segment(_) => Urgency::Bulk,
+            SpecificMsg::Ack
+            | SpecificMsg::AckPart(_)
+            | SpecificMsg::Abort
+            | SpecificMsg::Aborted
+            | SpecificMsg::Paxos(_)
+            | SpecificMsg::Ready
+            | SpecificMsg::CaughtUp => Urgency::Control,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct QueueLimit {
+    pub msgs: usize,
+    pub bytes: usize,
+    pub backpressure: Backpressure,
+}
+
+impl QueueLimit {
+    pub fn new(msgs: usize, bytes: usize, backpressure: Backpressure) -> QueueLimit {
+        QueueLimit {
+            msgs,
+            bytes,
+            backpressure,
+        }
+    }
+}
+
+// What's queued to a peer, and how much has been refused or shed so far.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct QueueStats {
+    pub msgs: usize,
+    pub bytes: usize,
+    pub refused: u64,
+    pub shed: u64,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub(crate) struct Queued {
+    dst: NodeID,
+    urgency: Urgency,
+    buf: Box<[u8]>,
+}
+
+impl IOQueues {
+    pub(crate) fn limit(&self, dst: NodeID) -> Option<QueueLimit> {
+        self.limits.get(&dst).copied().or(self.limit)
+    }
+
+    pub(crate) fn stats(&mut self, dst: NodeID) -> &mut QueueStats {
+        self.stats.entry(dst).or_default()
+    }
+
+    fn fits(&self, dst: NodeID, msgs: usize, bytes: usize) -> bool {
+        let Some(limit) = self.limit(dst) else {
+            return true;
+        };
+        let stats = self.stats.get(&dst).copied().unwrap_or_default();
+        stats.msgs + msgs <= limit.msgs && stats.bytes + bytes <= limit.bytes
+    }
+
+    fn refuse(&mut self, dst: NodeID) -> Error {
+        self.stats(dst).refused += 1;
+        Error::overloaded(format!("the queue to {:?} is full", dst))
+    }
+
+    // Queue a buffer to a peer, if there's room.
+    pub(crate) fn push(&mut self, dst: NodeID, urgency: Urgency, buf: Box<[u8]>) -> Result<()> {
+        if let Some(limit) = self.limit(dst) {
+            if self.blocked.contains(&dst) {
+                return Err(self.refuse(dst));
+            }
+            if !self.fits(dst, 1, buf.len()) {
+                match limit.backpressure {
+                    Backpressure::Block => {
+                        self.blocked.insert(dst);
+                        return Err(self.refuse(dst));
+                    }
+                    Backpressure::Drop => return Err(self.refuse(dst)),
+                    Backpressure::Shed => {
+                        if !self.shed(dst, urgency, buf.len()) {
+                            return Err(self.refuse(dst));
+                        }
+                    }
+                }
+            }
+        }
+        let stats = self.stats(dst);
+        stats.msgs += 1;
+        stats.bytes += buf.len();
+        self.outgoing.push_back(Queued { dst, urgency, buf });
+        Ok(())
+    }
+
+    // Drop the oldest buffers to a peer less urgent than a new one until
+    // the new one fits, if dropping all of them would make it fit.
+    fn shed(&mut self, dst: NodeID, urgency: Urgency, len: usize) -> bool {
+        let Some(limit) = self.limit(dst) else {
+            return true;
+        };
+        let stats = self.stats.get(&dst).copied().unwrap_or_default();
+        let lesser = |q: &Queued| q.dst == dst && q.urgency < urgency;
+        let (mut msgs, mut bytes, mut victims) = (stats.msgs + 1, stats.bytes + len, 0);
+        let mut lessers = self.outgoing.iter().filter(|q| lesser(q));
+        while msgs > limit.msgs || bytes > limit.bytes {
+            let Some(q) = lessers.next() else {
+                return false;
+            };
+            msgs -= 1;
+            bytes -= q.buf.len();
+            victims += 1;
+        }
+        let mut left = victims;
+        self.outgoing.retain(|q| {
+            let keep = left == 0 || !lesser(q);
+            left -= usize::from(!keep);
+            keep
+        });
+        let stats = self.stats(dst);
+        stats.msgs = msgs - 1;
+        stats.bytes = bytes - len;
+        stats.shed += victims as u64;
+        true
+    }
+
+    // The next buffer to a peer that's ready for it, taken off its queue,
+    // and the urgency of its message.
+    pub(crate) fn pop(
+        &mut self,
+        ready: impl Fn(NodeID, Option<QueueLimit>) -> bool,
+    ) -> Option<(NodeID, Urgency, Box<[u8]>)> {
+        let mut outgoing = self.outgoing.iter();
+        let i = outgoing.position(|q| ready(q.dst, self.limit(q.dst)))?;
+        let Queued { dst, urgency, buf } = self.outgoing.remove(i)?;
+        let limit = self.limit(dst);
+        let stats = self.stats(dst);
+        stats.msgs -= 1;
+        stats.bytes -= buf.len();
+        let drained = limit.is_none_or(|l| stats.msgs <= l.msgs / 2 && stats.bytes <= l.bytes / 2);
+        if drained {
+            self.blocked.remove(&dst);
+        }
+        Some((dst, urgency, buf))
+    }
+}
@@ -0,0 +1,111 @@
+// Requests and their responses. A node sends a request with
+// send_request(), which gives it the node's next sequence number and
+// records it as pending, and the peer answers it with reply(), which builds
+// the response: from the peer back to the requester, with the request's
+// transaction time and sequence number, flagged as a response. When the
+// response arrives, recv_msg() hands the two back together, as
+// RecvMsg::Paired.
+//
+// A response only answers a pending request if it comes from the node the
+// request went to, with the request's sequence number and transaction
+// time; any other message, responses included, is received on its own. A
+// request is answered once, so a second response to it, say one the
+// network duplicated, is received on its own too. Only a request can be
+// replied to, and only by the node it was sent to.
+//
+// Requests that go unanswered, because the request or the response was
+// lost, wait until they're cancelled or expire; a response that arrives
+// after that is received on its own as well.
+
+use crate::{Msg, Node, NodeID, NodeTime, RealmTime, Request, SpecificMsg};
+use serde::{Deserialize, Serialize};
+use submerge_base::{Error, Result};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct RequestId {
+    dst: NodeID,
+    sequence: i64,
+}
+
+impl RequestId {
+    pub fn dst(&self) -> NodeID {
+        self.dst
+    }
+
+    pub fn sequence(&self) -> i64 {
+        self.sequence
+    }
+}
+
+impl Msg {
+    // The request this message would answer, if it's a response.
+    pub(crate) fn answers(&self) -> Option<RequestId> {
+        self.response.then_some(RequestId {
+            dst: self.src,
+            sequence: self.sequence,
+        })
+    }
+}
+
+impl Node {
+    // Send a request, from the node and at the time a RealmTime names, which
+    // is also its transaction time.
+    pub fn send_request(
+        &mut self,
+        dst: NodeID,
+        time: RealmTime,
+        specific: SpecificMsg,
+    ) -> Result<RequestId> {
+        let sequence = self.sequence;
+        let req = Msg::new(time.node(), dst, time, time, sequence, specific);
+        self.send_msg(req.clone())?;
+        self.sequence += 1;
+        let id = RequestId { dst, sequence };
+        let req = Box::new(req);
+        self.requests.insert(id, Request { req, res: None });
+        Ok(id)
+    }
+
+    // Answer a request received, from the node it was sent to.
+    pub fn reply(&mut self, req: &Msg, time: RealmTime, specific: SpecificMsg) -> Result<()> {
+        if req.is_response() {
+            return Err(Error::protocol("replying to a response"));
+        }
+        if time.node() != req.dst() {
+            return Err(Error::protocol(format!(
+                "{:?} replying to a request to {:?}",
+                time.node(),
+                req.dst()
+            )));
+        }
+        self.send_msg(req.response(time, specific))
+    }
+
+    // Stop waiting for a request's response. Returns whether it was pending.
+    pub fn cancel(&mut self, id: RequestId) -> bool {
+        self.complete.retain(|c| *c != id);
+        self.requests.remove(&id).is_some()
+    }
+
+    // Give up on the requests sent before a time that haven't been
+    // answered, and return them.
+    pub fn expire(&mut self, before: NodeTime) -> Vec<RequestId> {
+        let expired = self
+            .requests
+            .iter()
+            .filter(|(_, r)| r.res.is_none() && r.req.msg_time().time() < before)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in &expired {
+            self.requests.remove(id);
+        }
+        expired
+    }
+
+    // The requests sent that haven't been answered yet.
+    pub fn pending(&self) -> Vec<RequestId> {
+        let requests = self.requests.iter();
+        let pending = requests.filter(|(_, r)| r.res.is_none());
+        pending.map(|(id, _)| *id).collect()
+    }
+}
@@ -0,0 +1,308 @@
+// A simulated network, for testing protocols deterministically. A SimNet
+// connects any number of nodes in memory, each through a SimTransport, on
+// a virtual clock that the test moves on with advance(): a buffer sent is
+// in flight until its delivery time, and then waits in its destination's
+// inbox for recv().
+//
+// The network injects faults, as a seeded generator picks or as a test
+// scripts them:
+//
+//   - Each buffer's latency is drawn from a distribution, so buffers sent
+//     close together arrive out of order when it varies; with reordering
+//     on, some are also held back for a while, to be overtaken.
+//   - A buffer may be dropped, or duplicated, some times in 100.
+//   - A node that's down, or partitioned from the sender, loses what's
+//     sent to it, and what it sends; this is decided on delivery, so a
+//     partition cuts off what's in flight too.
+//   - A fault scripted for a link happens to the next buffer sent on it,
+//     instead of whatever the generator would have picked.
+//
+// Nothing depends on the real clock or hash iteration order, so a run is a
+// function of its seed and the calls made on it. Every delivery is kept in
+// the trace, for comparing runs.
+//
+// Unlike the other transports, a SimNet doesn't keep buffers from a node
+// to another in order: that's the point of it.
+
+use crate::transport::Transport;
+use crate::{Duration, NodeID, NodeTime};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::rc::Rc;
+use submerge_base::Result;
+
+// A buffer delivered, at a virtual time, from a node to another.
+pub type Delivery = (NodeTime, NodeID, NodeID);
+
+// How long buffers take to arrive. Every latency is at least a
+// microsecond, so nothing sent arrives at the time it was sent.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Latency {
+    Fixed(Duration),
+    // Between a least and a most, evenly.
+    Uniform(Duration, Duration),
+    // A least, plus an exponentially distributed wait with a mean: mostly
+    // quick, with a long tail.
+    Exponential { min: Duration, mean: Duration },
+}
+
+// A fault to inject into the next buffer sent on a link.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum LinkFault {
+    Drop,
+    Duplicate,
+    // Deliver it this much later than its latency says.
+    Delay(Duration),
+}
+
+// A buffer in flight: its source, destination and bytes.
+type Flight = (NodeID, NodeID, Box<[u8]>);
+
+// The buffers delivered to a node, and their sources.
+type Inbox = VecDeque<(NodeID, Box<[u8]>)>;
+
+#[derive(Debug)]
+struct Wire {
+    now: NodeTime,
+    rng: u64,
+    latency: Latency,
+    drop_pct: u64,
+    dup_pct: u64,
+    // The chance in 100 of holding a buffer back, and for how long.
+    reorder: (u64, Duration),
+    down: BTreeSet<NodeID>,
+    // Each partition's nodes can't hear from any node outside it, or be
+    // heard by one.
+    partitions: Vec<BTreeSet<NodeID>>,
+    script: BTreeMap<(NodeID, NodeID), VecDeque<LinkFault>>,
+    // Buffers in flight, by delivery time and then by the order they were
+    // sent in.
+    flights: BTreeMap<(NodeTime, u64), Flight>,
+    sent: u64,
+    inboxes: BTreeMap<NodeID, Inbox>,
+    trace: Vec<Delivery>,
+}
+
+impl Wire {
+    fn random(&mut self, n: u64) -> u64 {
+        self.rng = self
+            .rng
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.rng >> 33) % n
+    }
+
+    // A chance of pct in 100, drawing from the generator only if there's
+    // any chance at all.
+    fn chance(&mut self, pct: u64) -> bool {
+        pct > 0 && self.random(100) < pct
+    }
+
+    fn latency(&mut self) -> Duration {
+        let us = match self.latency {
+            Latency::Fixed(d) => d.micros(),
+            Latency::Uniform(min, max) => {
+                let (min, max) = (min.micros(), max.micros().max(min.micros()));
+                min + self.random((max - min + 1) as u64) as i64
+            }
+            Latency::Exponential { min, mean } => {
+                // An inverse transform of a draw in (0, 1].
+                let u = (self.random(1 << 20) + 1) as f64 / (1 << 20) as f64;
+                min.micros() + (-u.ln() * mean.micros() as f64) as i64
+            }
+        };
+        Duration::from_micros(us.max(1))
+    }
+
+    fn cut(&self, a: NodeID, b: NodeID) -> bool {
+        let down = self.down.contains(&a) || self.down.contains(&b);
+        down || self
+            .partitions
+            .iter()
+            .any(|p| p.contains(&a) != p.contains(&b))
+    }
+
+    fn fly(&mut self, src: NodeID, dst: NodeID, buf: Box<[u8]>, delay: Duration) {
+        let at = self.now + self.latency() + delay;
+        self.flights.insert((at, self.sent), (src, dst, buf));
+        self.sent += 1;
+    }
+
+    fn send(&mut self, src: NodeID, dst: NodeID, buf: Box<[u8]>) {
+        let scripted = self.script.get_mut(&(src, dst)).and_then(|s| s.pop_front());
+        match scripted {
+            Some(LinkFault::Drop) => {}
+            Some(LinkFault::Duplicate) => {
+                self.fly(src, dst, buf.clone(), Duration::from_micros(0));
+                self.fly(src, dst, buf, Duration::from_micros(0));
+            }
+            Some(LinkFault::Delay(d)) => self.fly(src, dst, buf, d),
+            None => {
+                if self.random(100) < self.drop_pct {
+                    return;
+                }
+                if self.chance(self.dup_pct) {
+                    self.fly(src, dst, buf.clone(), Duration::from_micros(0));
+                }
+                let (pct, by) = self.reorder;
+                let delay = match self.chance(pct) {
+                    true => by,
+                    false => Duration::from_micros(0),
+                };
+                self.fly(src, dst, buf, delay);
+            }
+        }
+    }
+
+    // Deliver what's due by now to its destinations' inboxes.
+    fn deliver(&mut self) {
+        while let Some(entry) = self.flights.first_entry() {
+            if entry.key().0 > self.now {
+                break;
+            }
+            let (src, dst, buf) = entry.remove();
+            if self.cut(src, dst) {
+                continue;
+            }
+            self.inboxes.entry(dst).or_default().push_back((src, buf));
+            self.trace.push((self.now, src, dst));
+        }
+    }
+}
+
+// A handle on a simulated network, which its transports share.
+#[derive(Clone, Debug)]
+pub struct SimNet {
+    wire: Rc<RefCell<Wire>>,
+}
+
+impl SimNet {
+    // A network with a fixed latency of a microsecond, and no faults.
+    pub fn new(seed: u64) -> SimNet {
+        let wire = Wire {
+            now: NodeTime::from_micros(0),
+            rng: seed,
+            latency: Latency::Fixed(Duration::from_micros(1)),
+            drop_pct: 0,
+            dup_pct: 0,
+            reorder: (0, Duration::from_micros(0)),
+            down: BTreeSet::new(),
+            partitions: Vec::new(),
+            script: BTreeMap::new(),
+            flights: BTreeMap::new(),
+            sent: 0,
+            inboxes: BTreeMap::new(),
+            trace: Vec::new(),
+        };
+        SimNet {
+            wire: Rc::new(RefCell::new(wire)),
+        }
+    }
+
+    pub fn with_latency(self, latency: Latency) -> SimNet {
+        self.wire.borrow_mut().latency = latency;
+        self
+    }
+
+    // Drop a buffer pct times in 100.
+    pub fn with_drops(self, pct: u64) -> SimNet {
+        self.wire.borrow_mut().drop_pct = pct.min(100);
+        self
+    }
+
+    // Deliver a buffer twice pct times in 100.
+    pub fn with_dups(self, pct: u64) -> SimNet {
+        self.wire.borrow_mut().dup_pct = pct.min(100);
+        self
+    }
+
+    // Hold a buffer back by a while pct times in 100, for those sent after
+    // it to overtake.
+    pub fn with_reorder(self, pct: u64, by: Duration) -> SimNet {
+        self.wire.borrow_mut().reorder = (pct.min(100), by);
+        self
+    }
+
+    // A node's transport onto the network.
+    pub fn transport(&self, me: NodeID) -> SimTransport {
+        SimTransport {
+            me,
+            net: self.clone(),
+        }
+    }
+
+    pub fn now(&self) -> NodeTime {
+        self.wire.borrow().now
+    }
+
+    // Move the clock on to a time, delivering what's due by then.
+    pub fn advance_to(&self, now: NodeTime) {
+        let mut wire = self.wire.borrow_mut();
+        wire.now = wire.now.max(now);
+        wire.deliver();
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.advance_to(self.now() + by);
+    }
+
+    // Lose everything sent to or from a node, until it's up again.
+    pub fn down(&self, node: NodeID) {
+        self.wire.borrow_mut().down.insert(node);
+    }
+
+    pub fn up(&self, node: NodeID) {
+        self.wire.borrow_mut().down.remove(&node);
+    }
+
+    // Cut some nodes off from the rest, until the network heals.
+    pub fn partition(&self, nodes: impl IntoIterator<Item = NodeID>) {
+        let partition = nodes.into_iter().collect();
+        self.wire.borrow_mut().partitions.push(partition);
+    }
+
+    pub fn heal(&self) {
+        self.wire.borrow_mut().partitions.clear();
+    }
+
+    // Inject a fault into the next buffer sent from a node to another that
+    // doesn't already have one scripted.
+    pub fn script(&self, src: NodeID, dst: NodeID, fault: LinkFault) {
+        let mut wire = self.wire.borrow_mut();
+        wire.script.entry((src, dst)).or_default().push_back(fault);
+    }
+
+    // The number of buffers sent and not yet delivered or lost.
+    pub fn in_flight(&self) -> usize {
+        self.wire.borrow().flights.len()
+    }
+
+    pub fn trace(&self) -> Vec<Delivery> {
+        self.wire.borrow().trace.clone()
+    }
+}
+
+// A node's end of a simulated network.
+#[derive(Clone, Debug)]
+pub struct SimTransport {
+    me: NodeID,
+    net: SimNet,
+}
+
+impl SimTransport {
+    pub fn me(&self) -> NodeID {
+        self.me
+    }
+}
+
+impl Transport for SimTransport {
+    fn send(&mut self, dst: NodeID, buf: Box<[u8]>) -> Result<()> {
+        self.net.wire.borrow_mut().send(self.me, dst, buf);
+        Ok(())
+    }
+
+    fn recv(&mut self) -> Result<Option<(NodeID, Box<[u8]>)>> {
+        let mut wire = self.net.wire.borrow_mut();
+        Ok(wire.inboxes.get_mut(&self.me).and_then(|i| i.pop_front()))
+    }
+}
@@ -0,0 +1,860 @@
+use crate::pool::Pool;
+use crate::{
+    frame, hello, parse_hello, Backpressure, Codec, Compatibility, Compression, Detector, Duration,
+    Frames, Gossip, GossipPayload, Latency, LinkFault, Msg, Node, NodeID, NodeTime, QueueLimit,
+    RealmTime, RecvMsg, SimNet, SimTransport, SpecificMsg, Suspicion, Transport, UnixTransport,
+    Urgency, OLDEST_PROTOCOL, PROTOCOL,
+};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::rc::Rc;
+use submerge_base::Result;
+use test_log::test;
+
+fn ping(src: i64, dst: i64, specific: SpecificMsg) -> Msg {
+    let t = RealmTime::new(NodeTime::from_micros(1), NodeID(src), 0);
+    Msg::new(NodeID(src), NodeID(dst), t, t, 0, specific)
+}
+
+// Pump two nodes until the second has a message.
+fn deliver(a: (&mut Node, &mut UnixTransport), b: (&mut Node, &mut UnixTransport)) -> Box<Msg> {
+    for _ in 0..10_000 {
+        a.0.pump(a.1).unwrap();
+        b.0.pump(b.1).unwrap();
+        if let RecvMsg::Single(msg) = b.0.recv_msg().unwrap() {
+            return msg;
+        }
+    }
+    panic!("nothing arrived");
+}
+
+#[test]
+fn test_frames() {
+    let mut wire = Vec::new();
+    frame(b"hello", &mut wire).unwrap();
+    frame(&[7; 300], &mut wire).unwrap();
+    frame(b"", &mut wire).unwrap();
+
+    // Buffers come back whole however the bytes are split.
+    let mut frames = Frames::new();
+    let mut got = Vec::new();
+    for byte in &wire {
+        frames.push(&[*byte]);
+        while let Some(buf) = frames.pop().unwrap() {
+            got.push(buf);
+        }
+    }
+    assert_eq!(got.len(), 3);
+    assert_eq!(&*got[0], b"hello");
+    assert_eq!(&*got[1], &[7; 300]);
+    assert!(got[2].is_empty());
+    assert_eq!(frames.pending(), 0);
+
+    // A length no frame could have is corruption.
+    let mut frames = Frames::new();
+    frames.push(&[0xff, 0xff, 0xff, 0xff, 0x7f]);
+    assert!(frames.pop().unwrap_err().is_corruption());
+}
+
+#[test]
+fn test_unix_transport() {
+    let dir = std::env::temp_dir().join(format!("submerge-unix-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (mut n1, mut n2) = (Node::new(), Node::new());
+    let mut t1 = UnixTransport::bind(&dir, NodeID(1))
+        .unwrap()
+        .with_compatibility(n1.compatibility());
+    let mut t2 = UnixTransport::bind(&dir, NodeID(2)).unwrap();
+
+    // Messages go both ways, each from the node that sent it.
+    n1.send_msg(ping(1, 2, SpecificMsg::Ping)).unwrap();
+    let msg = deliver((&mut n1, &mut t1), (&mut n2, &mut t2));
+    assert_eq!(*msg, ping(1, 2, SpecificMsg::Ping));
+    n2.send_msg(msg.response(msg.msg_time(), SpecificMsg::Pong))
+        .unwrap();
+    let msg = deliver((&mut n2, &mut t2), (&mut n1, &mut t1));
+    assert_eq!((msg.src(), msg.specific()), (NodeID(2), &SpecificMsg::Pong));
+
+    // A buffer larger than the socket takes at once arrives whole, and in
+    // order with those after it.
+    let big = SpecificMsg::Put(vec![5; 4 << 20]);
+    n1.send_msg(ping(1, 2, big.clone())).unwrap();
+    n1.send_msg(ping(1, 2, SpecificMsg::Ack)).unwrap();
+    let msg = deliver((&mut n1, &mut t1), (&mut n2, &mut t2));
+    assert_eq!(msg.specific(), &big);
+    let msg = deliver((&mut n1, &mut t1), (&mut n2, &mut t2));
+    assert_eq!(msg.specific(), &SpecificMsg::Ack);
+
+    // What's sent to a peer that isn't listening is held, and one that
+    // comes back gets it, before what's sent after.
+    drop(t2);
+    t1.send(NodeID(2), b"held".as_slice().into()).unwrap();
+    t1.send(NodeID(3), b"held".as_slice().into()).unwrap();
+    assert_eq!(t1.backlog(NodeID(3)), 5);
+    let mut t2 = UnixTransport::bind(&dir, NodeID(2)).unwrap();
+    t1.send(NodeID(2), b"after".as_slice().into()).unwrap();
+    let mut got = Vec::new();
+    for _ in 0..10_000 {
+        assert!(t1.recv().unwrap().is_none());
+        got.extend(t2.recv().unwrap());
+        if got.len() == 2 {
+            break;
+        }
+    }
+    let held = (NodeID(1), b"held".as_slice().into());
+    assert_eq!(got, vec![held, (NodeID(1), b"after".as_slice().into())]);
+    assert_eq!(t1.backlog(NodeID(2)), 0);
+
+    // With a pool of connections, bulk messages are spread over several
+    // and control messages get one of their own.
+    let (mut n3, mut n4) = (Node::new(), Node::new());
+    let mut t3 = UnixTransport::bind(&dir, NodeID(3)).unwrap().with_conns(3);
+    let mut t4 = UnixTransport::bind(&dir, NodeID(4)).unwrap();
+    n3.send_msg(ping(3, 4, big.clone())).unwrap();
+    n3.send_msg(ping(3, 4, big.clone())).unwrap();
+    n3.send_msg(ping(3, 4, SpecificMsg::Ack)).unwrap();
+    let mut got = Vec::new();
+    for _ in 0..3 {
+        got.push(deliver((&mut n3, &mut t3), (&mut n4, &mut t4)));
+    }
+    assert_eq!(t3.conns(NodeID(4)), 3);
+    let count = |specific: &SpecificMsg| got.iter().filter(|m| m.specific() == specific).count();
+    assert_eq!((count(&SpecificMsg::Ack), count(&big)), (1, 2));
+}
+
+// Everything a transport has received, as (source, bytes) pairs.
+fn drain(t: &mut SimTransport) -> Vec<(i64, Vec<u8>)> {
+    let mut got = Vec::new();
+    while let Some((src, buf)) = t.recv().unwrap() {
+        got.push((src.0, buf.to_vec()));
+    }
+    got
+}
+
+fn buf(bytes: &[u8]) -> Box<[u8]> {
+    bytes.into()
+}
+
+#[test]
+fn test_sim_net() {
+    let us = Duration::from_micros;
+    let net = SimNet::new(1);
+    let (mut t1, mut t2, mut t3) = (
+        net.transport(NodeID(1)),
+        net.transport(NodeID(2)),
+        net.transport(NodeID(3)),
+    );
+
+    // Nothing arrives before its latency is up.
+    t1.send(NodeID(2), buf(b"a")).unwrap();
+    assert!(drain(&mut t2).is_empty());
+    net.advance(us(1));
+    assert_eq!(drain(&mut t2), vec![(1, b"a".to_vec())]);
+
+    // Scripted faults happen to the next buffers on their link, in order.
+    net.script(NodeID(1), NodeID(2), LinkFault::Drop);
+    net.script(NodeID(1), NodeID(2), LinkFault::Duplicate);
+    net.script(NodeID(1), NodeID(2), LinkFault::Delay(us(5)));
+    for b in [b"x", b"y", b"z"] {
+        t1.send(NodeID(2), buf(b)).unwrap();
+    }
+    t3.send(NodeID(2), buf(b"w")).unwrap();
+    net.advance(us(1));
+    let got = drain(&mut t2);
+    assert_eq!(
+        got,
+        vec![(1, b"y".to_vec()), (1, b"y".to_vec()), (3, b"w".to_vec())]
+    );
+    net.advance(us(5));
+    assert_eq!(drain(&mut t2), vec![(1, b"z".to_vec())]);
+
+    // A partition loses what crosses it, including what's already in
+    // flight, until it heals.
+    t1.send(NodeID(3), buf(b"in flight")).unwrap();
+    net.partition([NodeID(3)]);
+    t3.send(NodeID(1), buf(b"out")).unwrap();
+    t1.send(NodeID(2), buf(b"inside")).unwrap();
+    net.advance(us(1));
+    assert!(drain(&mut t3).is_empty());
+    assert!(drain(&mut t1).is_empty());
+    assert_eq!(drain(&mut t2), vec![(1, b"inside".to_vec())]);
+    net.heal();
+    t1.send(NodeID(3), buf(b"healed")).unwrap();
+    net.advance(us(1));
+    assert_eq!(drain(&mut t3), vec![(1, b"healed".to_vec())]);
+
+    // So does a node that's down.
+    net.down(NodeID(2));
+    t1.send(NodeID(2), buf(b"down")).unwrap();
+    net.advance(us(1));
+    net.up(NodeID(2));
+    assert!(drain(&mut t2).is_empty());
+    assert_eq!(net.in_flight(), 0);
+
+    // Nodes pump messages through their transports as through any other.
+    let (mut n1, mut n2) = (Node::new(), Node::new());
+    let t = RealmTime::new(NodeTime::from_micros(1), NodeID(1), 0);
+    let ping = Msg::new(NodeID(1), NodeID(2), t, t, 0, SpecificMsg::Ping);
+    n1.send_msg(ping.clone()).unwrap();
+    n1.pump(&mut t1).unwrap();
+    net.advance(us(1));
+    n2.pump(&mut t2).unwrap();
+    assert_eq!(n2.recv_msg().unwrap(), RecvMsg::Single(Box::new(ping)));
+}
+
+// Send a run of buffers from node 1 to node 2 over a faulty network, and
+// give back what arrived, in the order it did.
+fn faulty(seed: u64, latency: Latency) -> (SimNet, Vec<u8>) {
+    let net = SimNet::new(seed)
+        .with_latency(latency)
+        .with_drops(20)
+        .with_dups(20)
+        .with_reorder(20, Duration::from_micros(30));
+    let (mut t1, mut t2) = (net.transport(NodeID(1)), net.transport(NodeID(2)));
+    for i in 0..50_u8 {
+        t1.send(NodeID(2), buf(&[i])).unwrap();
+        net.advance(Duration::from_micros(1));
+    }
+    net.advance(Duration::from_micros(1000));
+    let got = drain(&mut t2).into_iter().map(|(_, b)| b[0]).collect();
+    (net, got)
+}
+
+#[test]
+fn test_sim_net_faults() {
+    let us = Duration::from_micros;
+    let (net, got) = faulty(3, Latency::Uniform(us(1), us(10)));
+
+    // Some buffers are lost, some come twice, and some overtake others.
+    let mut distinct = got.clone();
+    distinct.sort();
+    distinct.dedup();
+    assert!(distinct.len() < 50);
+    assert!(got.len() > distinct.len());
+    assert!(got.windows(2).any(|w| w[0] > w[1]));
+
+    // A run is a function of its seed.
+    let (again, same) = faulty(3, Latency::Uniform(us(1), us(10)));
+    assert_eq!((net.trace(), got), (again.trace(), same));
+    assert_ne!(
+        net.trace(),
+        faulty(4, Latency::Uniform(us(1), us(10))).0.trace()
+    );
+
+    // Exponential latencies are never under their least.
+    let (net, _) = faulty(
+        3,
+        Latency::Exponential {
+            min: us(5),
+            mean: us(20),
+        },
+    );
+    let first = net.trace().first().map(|(at, ..)| at.micros());
+    assert!(first >= Some(5));
+}
+
+// Each node's number, spread by gossip.
+#[derive(Default)]
+struct Numbers(BTreeMap<NodeID, i64>);
+
+impl GossipPayload for Numbers {
+    fn kind(&self) -> &'static str {
+        "numbers"
+    }
+
+    fn provide(&self) -> Result<Vec<u8>> {
+        Ok(rmp_serde::to_vec(&self.0)?)
+    }
+
+    fn merge(&mut self, _: NodeID, bytes: &[u8]) -> Result<()> {
+        let theirs: BTreeMap<NodeID, i64> = rmp_serde::from_slice(bytes)?;
+        for (node, n) in theirs {
+            let mine = self.0.entry(node).or_insert(n);
+            *mine = (*mine).max(n);
+        }
+        Ok(())
+    }
+}
+
+struct Gossiper {
+    node: Node,
+    transport: SimTransport,
+    gossip: Gossip,
+    numbers: Numbers,
+}
+
+impl Gossiper {
+    fn step(&mut self, now: NodeTime) {
+        self.node.pump(&mut self.transport).unwrap();
+        let mut payloads: [&mut dyn GossipPayload; 1] = [&mut self.numbers];
+        while let RecvMsg::Single(msg) = self.node.recv_msg().unwrap() {
+            self.gossip
+                .recv(&mut self.node, &msg, now, &mut payloads)
+                .unwrap();
+        }
+        self.gossip
+            .poll(&mut self.node, now, &mut payloads)
+            .unwrap();
+        self.node.pump(&mut self.transport).unwrap();
+    }
+}
+
+#[test]
+fn test_gossip() {
+    // Five nodes that only know of node 1 at first, gossiping to two peers
+    // a round over a lossy network.
+    let us = Duration::from_micros;
+    let net = SimNet::new(9)
+        .with_latency(Latency::Uniform(us(1), us(5)))
+        .with_drops(10);
+    let mut gossipers = (1..=5)
+        .map(|id| {
+            let me = NodeID(id);
+            let mut gossip = Gossip::new(me, us(10), id as u64).with_fanout(2);
+            gossip.meet([NodeID(1)], net.now());
+            let numbers = Numbers(BTreeMap::from([(me, id * 100)]));
+            let transport = net.transport(me);
+            (
+                me,
+                Gossiper {
+                    node: Node::new(),
+                    transport,
+                    gossip,
+                    numbers,
+                },
+            )
+        })
+        .collect::<BTreeMap<_, _>>();
+    gossipers.get_mut(&NodeID(3)).unwrap().gossip.set_epoch(2);
+    let run = |gossipers: &mut BTreeMap<NodeID, Gossiper>, steps: usize| {
+        for _ in 0..steps {
+            net.advance(us(1));
+            for g in gossipers.values_mut() {
+                g.step(net.now());
+            }
+        }
+    };
+    run(&mut gossipers, 200);
+
+    // Word of every node, its epoch and its payload reaches every other.
+    let everyone = (1..=5)
+        .map(NodeID)
+        .collect::<std::collections::BTreeSet<_>>();
+    for g in gossipers.values() {
+        assert_eq!(g.gossip.alive(net.now()), everyone);
+        assert_eq!(g.gossip.epochs()[&NodeID(3)], 2);
+        assert_eq!(g.gossip.epochs()[&NodeID(4)], 0);
+        assert_eq!(g.numbers.0.len(), 5);
+        assert_eq!(g.numbers.0[&NodeID(5)], 500);
+    }
+
+    // A node that stops gossiping is suspected once its heartbeat's been
+    // still for a while.
+    let gone = gossipers.remove(&NodeID(5)).unwrap();
+    let last = gone.gossip.heartbeat(NodeID(5)).unwrap();
+    run(&mut gossipers, 200);
+    for g in gossipers.values() {
+        assert!(g.gossip.heartbeat(NodeID(5)) <= Some(last));
+        assert_eq!(g.gossip.suspected(net.now()), [NodeID(5)].into());
+        assert!(g.gossip.heartbeat(NodeID(1)) > Some(last));
+    }
+}
+
+#[test]
+fn test_detector() {
+    let (n2, n3) = (NodeID(2), NodeID(3));
+    let at = NodeTime::from_micros;
+    let mut d = Detector::new()
+        .with_min_std(Duration::from_micros(1))
+        .with_ping(Duration::from_micros(50));
+
+    // Nothing to go on until a peer's been heard from twice.
+    assert_eq!(d.suspicion(n2, at(0)), Suspicion::Unknown);
+    d.heard(n2, at(0));
+    assert_eq!(d.phi(n2, at(100)), None);
+
+    // A peer heard every 8 or 12us is alive 10us after the last, slow
+    // after 18 and dead after 22.
+    let mut t = 0;
+    for i in 0..20 {
+        t += if i % 2 == 0 { 8 } else { 12 };
+        d.heard(n2, at(t));
+    }
+    assert_eq!(d.suspicion(n2, at(t + 10)), Suspicion::Alive);
+    assert_eq!(d.suspicion(n2, at(t + 18)), Suspicion::Slow);
+    assert_eq!(d.suspicion(n2, at(t + 22)), Suspicion::Dead);
+    assert!(d.phi(n2, at(t + 22)) > d.phi(n2, at(t + 18)));
+
+    // Hearing from it again clears it.
+    d.heard(n2, at(t + 30));
+    assert_eq!(d.suspicion(n2, at(t + 31)), Suspicion::Alive);
+
+    // Peers quiet for a ping interval are due a ping, once an interval.
+    let now = at(t + 60);
+    assert_eq!(d.quiet([n2, n3].into_iter(), now), vec![n3]);
+    assert!(d.quiet([n2, n3].into_iter(), at(t + 70)).is_empty());
+    assert_eq!(d.quiet([n2, n3].into_iter(), at(t + 110)), vec![n2, n3]);
+    assert_eq!(d.all(now).len(), 2);
+}
+
+// Move a node's queued buffers to another.
+fn hand(a: &mut Node, b: &mut Node) -> Vec<usize> {
+    let mut sizes = Vec::new();
+    while let Some((_, buf)) = a.send_byes().unwrap() {
+        sizes.push(buf.len());
+        let src = Msg::decode(&buf).unwrap().src();
+        b.recv_bytes(src, buf).unwrap();
+    }
+    sizes
+}
+
+#[test]
+fn test_compression() {
+    let big = || ping(1, 2, SpecificMsg::Put(b"thunk".repeat(2000)));
+    let small = || ping(1, 2, SpecificMsg::Put(b"thunk".to_vec()));
+    let recv = |n: &mut Node| match n.recv_msg().unwrap() {
+        RecvMsg::Single(msg) => msg,
+        other => panic!("{:?}", other),
+    };
+    let compression = Compression::new().with_threshold(1024);
+    let mut n1 = Node::new().with_compression(compression.clone());
+    let mut n2 = Node::new().with_compression(compression.clone());
+
+    // Nothing's compressed before the peer's said what it takes.
+    n1.send_msg(big()).unwrap();
+    let raw = hand(&mut n1, &mut n2)[0];
+    assert!(raw > 10_000);
+    assert_eq!(*recv(&mut n2), big());
+
+    // Once it has, large buffers are, and small ones still aren't.
+    n2.send_msg(ping(2, 1, SpecificMsg::Ack)).unwrap();
+    hand(&mut n2, &mut n1);
+    recv(&mut n1);
+    n1.send_msg(big()).unwrap();
+    n1.send_msg(small()).unwrap();
+    let sizes = hand(&mut n1, &mut n2);
+    assert!(sizes[0] < raw / 10);
+    assert!(sizes[1] < 100);
+    assert_eq!(*recv(&mut n2), big());
+    assert_eq!(*recv(&mut n2), small());
+
+    // A node that hasn't opted in is never sent a compressed buffer, and
+    // one that only takes zstd is sent zstd.
+    for (peer, shrinks) in [
+        (Node::new(), false),
+        (
+            Node::new().with_compression(compression.with_codecs([Codec::Zstd])),
+            true,
+        ),
+    ] {
+        let (mut n1, mut n3) = (n1.clone(), peer);
+        n3.send_msg(ping(3, 1, SpecificMsg::Ack)).unwrap();
+        hand(&mut n3, &mut n1);
+        recv(&mut n1);
+        n1.send_msg(ping(1, 3, SpecificMsg::Put(b"thunk".repeat(2000))))
+            .unwrap();
+        let sizes = hand(&mut n1, &mut n3);
+        assert_eq!(sizes[0] < raw / 10, shrinks);
+        assert!(matches!(n3.recv_msg().unwrap(), RecvMsg::Single(_)));
+    }
+
+    // A buffer naming a codec there isn't is a protocol error.
+    n1.send_msg(small()).unwrap();
+    let (_, mut buf) = n1.send_byes().unwrap().unwrap();
+    buf[0] |= 0xf;
+    assert!(Msg::decode(&buf).unwrap_err().kind() == submerge_base::ErrorKind::Protocol);
+}
+
+// A transport that takes what it's sent, with a backlog to some nodes.
+struct Backlogged {
+    sent: Vec<NodeID>,
+    backlog: BTreeMap<NodeID, usize>,
+}
+
+impl Transport for Backlogged {
+    fn send(&mut self, dst: NodeID, _: Box<[u8]>) -> Result<()> {
+        self.sent.push(dst);
+        Ok(())
+    }
+
+    fn recv(&mut self) -> Result<Option<(NodeID, Box<[u8]>)>> {
+        Ok(None)
+    }
+
+    fn backlog(&self, dst: NodeID) -> usize {
+        self.backlog.get(&dst).copied().unwrap_or(0)
+    }
+}
+
+#[test]
+fn test_queue_limits() {
+    let overloaded = |r: Result<()>| r.unwrap_err().kind() == submerge_base::ErrorKind::Overloaded;
+    let n2 = NodeID(2);
+
+    // Drop refuses just what doesn't fit.
+    let mut net = Node::new().with_queue_limit(QueueLimit::new(2, 1 << 20, Backpressure::Drop));
+    net.send_msg(ping(1, 2, SpecificMsg::Ping)).unwrap();
+    net.send_msg(ping(1, 2, SpecificMsg::Ping)).unwrap();
+    assert!(overloaded(net.send_msg(ping(1, 2, SpecificMsg::Ping))));
+    net.send_msg(ping(1, 3, SpecificMsg::Ping)).unwrap();
+    net.send_byes().unwrap().unwrap();
+    net.send_msg(ping(1, 2, SpecificMsg::Ping)).unwrap();
+    assert_eq!((net.queues()[&n2].msgs, net.queues()[&n2].refused), (2, 1));
+    assert!(!net.blocked(n2));
+
+    // Bytes are bounded as well as messages.
+    let limit = QueueLimit::new(10, 100, Backpressure::Drop);
+    let mut net = Node::new().with_peer_queue_limit(n2, limit);
+    assert!(overloaded(net.send_msg(ping(
+        1,
+        2,
+        SpecificMsg::Put(vec![0; 100])
+    ))));
+    net.send_msg(ping(1, 2, SpecificMsg::Ping)).unwrap();
+    assert!(net.queues()[&n2].bytes <= 100);
+
+    // Block refuses everything once full, until the queue's half drained.
+    let mut net = Node::new().with_queue_limit(QueueLimit::new(4, 1 << 20, Backpressure::Block));
+    for _ in 0..4 {
+        net.send_msg(ping(1, 2, SpecificMsg::Ping)).unwrap();
+    }
+    assert!(overloaded(net.send_msg(ping(1, 2, SpecificMsg::Ping))));
+    assert!(net.blocked(n2));
+    net.send_byes().unwrap().unwrap();
+    assert!(overloaded(net.send_msg(ping(1, 2, SpecificMsg::Ping))));
+    net.send_byes().unwrap().unwrap();
+    assert!(!net.blocked(n2));
+    net.send_msg(ping(1, 2, SpecificMsg::Ping)).unwrap();
+    assert_eq!(net.queues()[&n2].refused, 2);
+
+    // Shed makes room for urgent messages by dropping less urgent ones.
+    let put = || ping(1, 2, SpecificMsg::Put(vec![0; 100]));
+    let limit = QueueLimit::new(2, 1 << 20, Backpressure::Shed);
+    let mut net = Node::new().with_peer_queue_limit(n2, limit);
+    net.send_msg(ping(1, 2, SpecificMsg::Ping)).unwrap();
+    net.send_msg(put()).unwrap();
+    net.send_msg(ping(1, 2, SpecificMsg::Ack)).unwrap();
+    assert!(overloaded(net.send_msg(put())));
+    assert!(overloaded(net.send_msg(ping(1, 2, SpecificMsg::Pong))));
+    let queued = std::iter::from_fn(|| net.send_byes().unwrap());
+    let queued = queued.map(|(_, buf)| Msg::decode(&buf).unwrap().specific().clone());
+    let queued = queued.collect::<Vec<_>>();
+    assert_eq!(
+        queued,
+        vec![SpecificMsg::Put(vec![0; 100]), SpecificMsg::Ack]
+    );
+    assert_eq!((net.queues()[&n2].shed, net.queues()[&n2].refused), (1, 2));
+
+    // Other peers are unbounded.
+    for _ in 0..10 {
+        net.send_msg(ping(1, 3, SpecificMsg::Put(vec![0; 100])))
+            .unwrap();
+    }
+
+    // pump() leaves what's queued to a peer the transport's backlogged
+    // for.
+    let mut transport = Backlogged {
+        sent: Vec::new(),
+        backlog: BTreeMap::from([(n2, 1 << 20)]),
+    };
+    net.send_msg(ping(1, 2, SpecificMsg::Ack)).unwrap();
+    net.pump(&mut transport).unwrap();
+    assert_eq!(transport.sent, vec![NodeID(3); 10]);
+    assert_eq!(net.queues()[&n2].msgs, 1);
+    transport.backlog.clear();
+    net.pump(&mut transport).unwrap();
+    assert_eq!(transport.sent.last(), Some(&n2));
+}
+
+#[test]
+fn test_requests() {
+    let (n1, n2, n3) = (NodeID(1), NodeID(2), NodeID(3));
+    let t = |us, node| RealmTime::new(NodeTime::from_micros(us), node, 0);
+    let mut a = Node::new();
+    let mut b = Node::new();
+
+    // Requests get successive sequence numbers, and are pending until
+    // they're answered.
+    let first = a.send_request(n2, t(1, n1), SpecificMsg::Ping).unwrap();
+    let second = a.send_request(n2, t(2, n1), SpecificMsg::Ping).unwrap();
+    assert_eq!((first.sequence(), second.sequence()), (0, 1));
+    assert_eq!(a.pending(), vec![first, second]);
+    hand(&mut a, &mut b);
+
+    // Only the node a request went to can reply to it, and only to a
+    // request.
+    let RecvMsg::Single(req) = b.recv_msg().unwrap() else {
+        panic!("no request");
+    };
+    assert!(b
+        .reply(&req, t(3, n3), SpecificMsg::Pong)
+        .unwrap_err()
+        .is_protocol());
+    b.reply(&req, t(3, n2), SpecificMsg::Pong).unwrap();
+    let res = req.response(t(3, n2), SpecificMsg::Pong);
+    assert!(b
+        .reply(&res, t(3, n2), SpecificMsg::Pong)
+        .unwrap_err()
+        .is_protocol());
+
+    // The response comes back paired with its request, once: a duplicate
+    // comes on its own.
+    let (_, buf) = b.send_byes().unwrap().unwrap();
+    a.recv_bytes(n2, buf.clone()).unwrap();
+    a.recv_bytes(n2, buf).unwrap();
+    match a.recv_msg().unwrap() {
+        RecvMsg::Paired { req, res } => {
+            assert_eq!(req.sequence(), first.sequence());
+            assert_eq!(*res.specific(), SpecificMsg::Pong);
+            assert!(res.is_response());
+        }
+        other => panic!("{:?}", other),
+    }
+    assert!(matches!(a.recv_msg().unwrap(), RecvMsg::Single(_)));
+    assert_eq!(a.pending(), vec![second]);
+
+    // A response that doesn't match a pending request's peer, sequence and
+    // time is received on its own.
+    let stray = ping(2, 1, SpecificMsg::Ping).response(t(4, n2), SpecificMsg::Ack);
+    let forged = Msg::new(n2, n1, t(9, n1), t(9, n1), 1, SpecificMsg::Ping);
+    let forged = forged.response(t(4, n2), SpecificMsg::Pong);
+    for msg in [stray, forged] {
+        b.send_msg(msg.clone()).unwrap();
+        hand(&mut b, &mut a);
+        assert_eq!(a.recv_msg().unwrap(), RecvMsg::Single(Box::new(msg)));
+    }
+
+    // Unanswered requests expire, or are cancelled, and their responses
+    // are then received on their own.
+    let third = a.send_request(n3, t(5, n1), SpecificMsg::Ping).unwrap();
+    assert_eq!(a.expire(NodeTime::from_micros(5)), vec![second]);
+    assert!(a.cancel(third));
+    assert!(!a.cancel(third));
+    assert!(a.pending().is_empty());
+}
+
+// A kind of message from a build newer than this one.
+#[derive(serde::Serialize)]
+enum Newer {
+    Teleport(u8),
+}
+
+#[derive(serde::Serialize)]
+struct NewerMsg {
+    src: NodeID,
+    dst: NodeID,
+    txn_time: RealmTime,
+    msg_time: RealmTime,
+    sequence: i64,
+    response: bool,
+    specific: Newer,
+}
+
+#[test]
+fn test_versions() {
+    let recv = |n: &mut Node| match n.recv_msg().unwrap() {
+        RecvMsg::Single(msg) => msg,
+        other => panic!("{:?}", other),
+    };
+
+    // A hello says who's connecting, and the newest version of their
+    // window.
+    let mut wire = Vec::new();
+    hello(NodeID(7), &Compatibility::default(), &mut wire).unwrap();
+    let old = Compatibility::new(OLDEST_PROTOCOL, OLDEST_PROTOCOL);
+    hello(NodeID(8), &old, &mut wire).unwrap();
+    let mut frames = Frames::new();
+    frames.push(&wire);
+    let buf = frames.pop().unwrap().unwrap();
+    assert_eq!(parse_hello(&buf).unwrap(), (NodeID(7), PROTOCOL));
+    assert!(parse_hello(&buf[..8]).unwrap_err().is_protocol());
+    let buf = frames.pop().unwrap().unwrap();
+    assert_eq!(parse_hello(&buf).unwrap(), (NodeID(8), OLDEST_PROTOCOL));
+
+    // A window is clamped to what this build speaks, and a node speaks
+    // down to an older peer.
+    let window = Compatibility::new(0, u16::MAX);
+    assert_eq!(window, Compatibility::default());
+    assert_eq!(
+        (window.oldest(), window.newest()),
+        (OLDEST_PROTOCOL, PROTOCOL)
+    );
+    assert_eq!(window.with(None), PROTOCOL);
+    assert_eq!(window.with(Some(u16::MAX)), PROTOCOL);
+    assert_eq!(window.with(Some(0)), 0);
+    assert!(!window.accepts(OLDEST_PROTOCOL - 1));
+
+    // Every buffer carries the version it's encoded at, and the receiver
+    // learns its peer's from it.
+    let (mut n1, mut n2) = (Node::new(), Node::new());
+    assert_eq!(n1.version(NodeID(2)), PROTOCOL);
+    n1.send_msg(ping(1, 2, SpecificMsg::Ping)).unwrap();
+    let (_, buf) = n1.send_byes().unwrap().unwrap();
+    assert_eq!(buf[1..3], PROTOCOL.to_le_bytes());
+    n2.recv_bytes(NodeID(1), buf).unwrap();
+    assert_eq!(*recv(&mut n2), ping(1, 2, SpecificMsg::Ping));
+    assert_eq!(n2.versions().get(&NodeID(1)), Some(&PROTOCOL));
+
+    // A message at a version too old to accept is dropped, as is one of a
+    // kind the node doesn't know, and the messages after them still
+    // arrive.
+    n1.send_msg(ping(1, 2, SpecificMsg::Ack)).unwrap();
+    let (_, mut old) = n1.send_byes().unwrap().unwrap();
+    old[1..3].copy_from_slice(&(OLDEST_PROTOCOL - 1).to_le_bytes());
+    n2.recv_bytes(NodeID(1), old).unwrap();
+    let t = RealmTime::new(NodeTime::from_micros(1), NodeID(1), 0);
+    let newer = NewerMsg {
+        src: NodeID(1),
+        dst: NodeID(2),
+        txn_time: t,
+        msg_time: t,
+        sequence: 0,
+        response: false,
+        specific: Newer::Teleport(3),
+    };
+    let mut buf = vec![0];
+    buf.extend_from_slice(&(PROTOCOL + 1).to_le_bytes());
+    buf.extend_from_slice(&rmp_serde::to_vec(&newer).unwrap());
+    n2.recv_bytes(NodeID(1), buf.clone().into()).unwrap();
+    n1.send_msg(ping(1, 2, SpecificMsg::Pong)).unwrap();
+    hand(&mut n1, &mut n2);
+    assert_eq!(*recv(&mut n2), ping(1, 2, SpecificMsg::Pong));
+    assert!(matches!(n2.recv_msg().unwrap(), RecvMsg::NoMsgs));
+    assert_eq!(n2.versions().get(&NodeID(1)), Some(&PROTOCOL));
+
+    // But one that isn't a message at all is still an error.
+    buf.truncate(buf.len() - 4);
+    n2.recv_bytes(NodeID(1), buf.into()).unwrap();
+    assert!(n2.recv_msg().is_err());
+}
+
+// The other end of a connection, which takes only so many bytes, or none
+// once it's broken.
+#[derive(Default)]
+struct Wire {
+    bytes: Vec<u8>,
+    room: usize,
+    broken: bool,
+}
+
+#[derive(Clone, Default)]
+struct Pipe(Rc<RefCell<Wire>>);
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut wire = self.0.borrow_mut();
+        if wire.broken {
+            return Err(std::io::ErrorKind::BrokenPipe.into());
+        }
+        let n = buf.len().min(wire.room);
+        if n == 0 {
+            return Err(std::io::ErrorKind::WouldBlock.into());
+        }
+        wire.room -= n;
+        wire.bytes.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Pipe {
+    fn open(&self, room: usize) {
+        self.0.borrow_mut().room = room;
+    }
+
+    fn break_off(&self) {
+        self.0.borrow_mut().broken = true;
+    }
+
+    // The buffers written whole, bar the hello.
+    fn bufs(&self) -> Vec<Vec<u8>> {
+        let mut frames = Frames::new();
+        frames.push(&self.0.borrow().bytes);
+        let mut bufs = Vec::new();
+        while let Some(buf) = frames.pop().unwrap() {
+            bufs.push(buf.to_vec());
+        }
+        if let Some(first) = bufs.first() {
+            assert_eq!(parse_hello(first).unwrap(), (NodeID(1), PROTOCOL));
+            bufs.remove(0);
+        }
+        bufs
+    }
+}
+
+// Connect a pipe that takes some bytes, or fail to.
+fn dial(pipes: &mut Vec<Pipe>, room: Option<usize>) -> Result<Pipe> {
+    let room = room.ok_or_else(|| submerge_base::Error::io("refused"))?;
+    let pipe = Pipe::default();
+    pipe.open(room);
+    pipes.push(pipe.clone());
+    Ok(pipe)
+}
+
+#[test]
+fn test_pool() {
+    let n2 = NodeID(2);
+    let mut pipes = Vec::new();
+    let mut pool = Pool::new(NodeID(1)).with_size(3).with_hold(20);
+    let send = |pool: &mut Pool<Pipe>, pipes: &mut Vec<Pipe>, urgency, buf: &[u8]| {
+        pool.send(n2, urgency, buf, &mut |_| dial(pipes, Some(0)))
+            .unwrap()
+    };
+
+    // Connections open as they're needed: one for control messages, and
+    // another for bulk ones whenever those open have a backlog.
+    send(&mut pool, &mut pipes, Urgency::Control, b"c1");
+    assert_eq!(pool.conns(n2), 1);
+    send(&mut pool, &mut pipes, Urgency::Bulk, b"b1");
+    send(&mut pool, &mut pipes, Urgency::Bulk, b"b2-longer");
+    assert_eq!(pool.conns(n2), 3);
+    send(&mut pool, &mut pipes, Urgency::Routine, b"r1");
+    send(&mut pool, &mut pipes, Urgency::Bulk, b"b3");
+    assert_eq!((pool.conns(n2), pipes.len()), (3, 3));
+    assert_eq!(pool.backlog(n2), 3 * 11 + 3 + 3 + 10 + 3 + 3);
+
+    // When one breaks, what it hadn't written goes on another.
+    for pipe in &pipes {
+        pipe.open(1000);
+    }
+    pipes[2].break_off();
+    pool.flush(&mut |_| dial(&mut pipes, Some(1000)));
+    assert_eq!(pool.conns(n2), 2);
+    assert_eq!(pool.backlog(n2), 0);
+    assert_eq!(pipes[0].bufs(), vec![b"c1".to_vec(), b"r1".to_vec()]);
+    let bulk = [b"b1".to_vec(), b"b3".to_vec(), b"b2-longer".to_vec()];
+    assert_eq!(pipes[1].bufs(), bulk);
+    assert!(pipes[2].bufs().is_empty());
+
+    // A buffer cut off partway goes again whole, on a new connection once
+    // the peer can be connected to.
+    let (n3, mut pipes) = (NodeID(3), Vec::new());
+    let mut pool = Pool::new(NodeID(1)).with_hold(15);
+    pool.send(n3, Urgency::Bulk, b"cut-off", &mut |_| {
+        dial(&mut pipes, Some(14))
+    })
+    .unwrap();
+    assert_eq!(pool.backlog(n3), 5);
+    pipes[0].break_off();
+    pool.send(n3, Urgency::Bulk, b"first", &mut |_| dial(&mut pipes, None))
+        .unwrap();
+    assert_eq!((pool.conns(n3), pool.backlog(n3)), (0, 14));
+    pool.flush(&mut |_| dial(&mut pipes, Some(1000)));
+    assert_eq!(
+        pipes[1].bufs(),
+        vec![b"cut-off".to_vec(), b"first".to_vec()]
+    );
+    assert_eq!((pool.conns(n3), pool.backlog(n3)), (1, 0));
+
+    // What's held for a peer that can't be connected to is bounded.
+    let n4 = NodeID(4);
+    for buf in [b"one-one", b"two-two", b"six-six"] {
+        pool.send(n4, Urgency::Routine, buf, &mut |_| dial(&mut pipes, None))
+            .unwrap();
+    }
+    assert_eq!((pool.conns(n4), pool.backlog(n4)), (0, 8));
+}
@@ -0,0 +1,139 @@
+// Transports carry the byte buffers a Node's IOQueues hold between nodes.
+// A Node does no IO itself: whatever drives it moves each outgoing buffer
+// to a transport with send(), and each buffer recv() gives back into the
+// node, which pump() does in one go. A transport never blocks, so it can be
+// polled from the same loop that drives the node. One that holds on to what
+// it can't write yet says how much, so that pump() can leave the rest of a
+// slow peer's backlog in the node's queue, where it's bounded (see
+// queue.rs).
+//
+// A transport may lose a buffer, say if its peer is down, as the network
+// would; the protocols above retry. It delivers the buffers it does carry
+// from one node to another intact and, bar the simulated network (see
+// sim.rs) and bulk messages spread over a pool of connections (see
+// pool.rs), in the order they were sent. pump() says how urgent each
+// buffer's message is, for transports that send them differently.
+//
+// Stream transports frame each buffer with its length, as a varint, and
+// open each connection with a frame naming the node it's from and the
+// newest protocol version its compatibility window speaks, so the receiving
+// end knows who it's talking to without asking the network, and can turn
+// away a node too old to understand (see version.rs).
+
+use crate::{Compatibility, Node, NodeID, QueueLimit, Urgency};
+use submerge_base::varint::{decode_uvarint, encode_uvarint, MAX_VARINT_LEN};
+use submerge_base::{Error, Result};
+
+// The largest buffer a frame may carry; a longer length is corruption, not
+// a message.
+pub const MAX_FRAME: usize = 1 << 26;
+
+pub trait Transport {
+    // Send a buffer to a node.
+    fn send(&mut self, dst: NodeID, buf: Box<[u8]>) -> Result<()>;
+    // Send a buffer of a message of some urgency to a node.
+    fn send_as(&mut self, dst: NodeID, _urgency: Urgency, buf: Box<[u8]>) -> Result<()> {
+        self.send(dst, buf)
+    }
+    // The next buffer another node has sent this one, if one has arrived.
+    fn recv(&mut self) -> Result<Option<(NodeID, Box<[u8]>)>>;
+    // The bytes sent to a node that haven't been written yet.
+    fn backlog(&self, _dst: NodeID) -> usize {
+        0
+    }
+}
+
+impl Node {
+    // Send everything the node has queued to go out, bar what's queued to
+    // peers the transport has a queue limit's worth of bytes backlogged
+    // for, and take in everything that's arrived.
+    pub fn pump(&mut self, transport: &mut dyn Transport) -> Result<()> {
+        loop {
+            let ready = |dst, limit: Option<QueueLimit>| {
+                limit.is_none_or(|l| transport.backlog(dst) < l.bytes)
+            };
+            let next = self.ioqueues.pop(ready);
+            let Some((dst, urgency, buf)) = next else {
+                break;
+            };
+            transport.send_as(dst, urgency, buf)?;
+        }
+        while let Some((src, buf)) = transport.recv()? {
+            self.recv_bytes(src, buf)?;
+        }
+        Ok(())
+    }
+}
+
+// Add a buffer, framed, to the bytes to write.
+pub fn frame(buf: &[u8], out: &mut Vec<u8>) -> Result<()> {
+    if buf.len() > MAX_FRAME {
+        return Err(Error::protocol(format!(
+            "a {} byte buffer is too large to frame",
+            buf.len()
+        )));
+    }
+    let mut len = [0_u8; MAX_VARINT_LEN];
+    let n = encode_uvarint(buf.len() as u64, &mut len);
+    out.extend_from_slice(&len[..n]);
+    out.extend_from_slice(buf);
+    Ok(())
+}
+
+// The first frame on a connection, naming the node it's from and the
+// newest protocol version it speaks.
+pub fn hello(me: NodeID, compatibility: &Compatibility, out: &mut Vec<u8>) -> Result<()> {
+    let mut buf = me.0.to_le_bytes().to_vec();
+    buf.extend_from_slice(&compatibility.newest().to_le_bytes());
+    frame(&buf, out)
+}
+
+pub fn parse_hello(buf: &[u8]) -> Result<(NodeID, u16)> {
+    let Some((id, version)) = buf.split_first_chunk::<8>() else {
+        return Err(Error::protocol("malformed hello frame"));
+    };
+    let version = version
+        .try_into()
+        .map_err(|_| Error::protocol("malformed hello frame"))?;
+    Ok((NodeID(i64::from_le_bytes(*id)), u16::from_le_bytes(version)))
+}
+
+// The bytes read from a stream, cut back into the buffers framed in them.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Frames {
+    buf: Vec<u8>,
+}
+
+impl Frames {
+    pub fn new() -> Frames {
+        Frames::default()
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    // The next whole buffer, if it's all arrived.
+    pub fn pop(&mut self) -> Result<Option<Box<[u8]>>> {
+        let (len, n) = match decode_uvarint(&self.buf) {
+            Ok(v) => v,
+            Err(_) if self.buf.len() < MAX_VARINT_LEN => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if len > MAX_FRAME as u64 {
+            return Err(Error::corruption(format!("{} byte frame", len)));
+        }
+        let end = n + len as usize;
+        if end > self.buf.len() {
+            return Ok(None);
+        }
+        let buf = self.buf[n..end].into();
+        self.buf.drain(..end);
+        Ok(Some(buf))
+    }
+
+    // The bytes read that aren't yet a whole buffer.
+    pub fn pending(&self) -> usize {
+        self.buf.len()
+    }
+}
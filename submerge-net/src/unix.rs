@@ -0,0 +1,235 @@
+// A transport over Unix domain sockets, for clusters on one machine: local
+// multi-node testing and co-located deployments. Real bytes go between
+// processes, framed as on any stream (see transport.rs), without touching
+// the network stack.
+//
+// Each node listens on a socket named for its NodeID in a directory the
+// cluster shares, and connects to a peer's socket as it sends it things,
+// up to a pool's worth of connections (see pool.rs). A connection carries
+// buffers one way, from the node that opened it, so each pair of nodes
+// that talk both ways has at least two. Every socket is non-blocking: what
+// a write can't take yet waits in the pool until the next send() or
+// recv(), and a read takes whatever has arrived.
+//
+// What's sent to a peer that isn't listening, or whose connection breaks,
+// is held in the pool and sent once it can be connected to again. Each
+// connection's hello gives the newest version of the node's compatibility
+// window, and a connection from a node whose hello says it speaks a version
+// older than the window accepts is dropped.
+
+use crate::pool::Pool;
+use crate::transport::{parse_hello, Frames, Transport};
+use crate::{Compatibility, NodeID, Urgency};
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use submerge_base::{telemetry, Error, Result};
+use tracing::warn;
+
+#[derive(Debug)]
+struct Conn {
+    stream: UnixStream,
+    // Who's at the other end, once their hello has arrived.
+    peer: Option<NodeID>,
+    frames: Frames,
+}
+
+impl Conn {
+    fn new(stream: UnixStream) -> Result<Conn> {
+        stream.set_nonblocking(true)?;
+        Ok(Conn {
+            stream,
+            peer: None,
+            frames: Frames::new(),
+        })
+    }
+
+    // Read whatever's arrived, and take the whole buffers from it. Returns
+    // whether the peer's still connected.
+    fn take(
+        &mut self,
+        compatibility: &Compatibility,
+        received: &mut VecDeque<(NodeID, Box<[u8]>)>,
+    ) -> Result<bool> {
+        let open = self.fill()?;
+        while let Some(buf) = self.frames.pop()? {
+            match self.peer {
+                Some(src) => received.push_back((src, buf)),
+                None => {
+                    let (peer, version) = parse_hello(&buf)?;
+                    if !compatibility.accepts(version) {
+                        return Err(Error::protocol(format!(
+                            "{:?} speaks protocol {}, older than {}",
+                            peer,
+                            version,
+                            compatibility.oldest()
+                        )));
+                    }
+                    self.peer = Some(peer);
+                }
+            }
+        }
+        Ok(open)
+    }
+
+    fn fill(&mut self) -> Result<bool> {
+        let mut buf = [0_u8; 64 * 1024];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Ok(false),
+                Ok(n) => self.frames.push(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(true),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+// The socket a node listens on, in a cluster's directory.
+pub fn socket_path(dir: &Path, node: NodeID) -> PathBuf {
+    dir.join(format!("{}.sock", node.0))
+}
+
+fn connect(dir: &Path, dst: NodeID) -> Result<UnixStream> {
+    let stream = UnixStream::connect(socket_path(dir, dst))?;
+    stream.set_nonblocking(true)?;
+    Ok(stream)
+}
+
+#[derive(Debug)]
+pub struct UnixTransport {
+    me: NodeID,
+    compatibility: Compatibility,
+    dir: PathBuf,
+    listener: UnixListener,
+    // Connections this node opened, to send on, and those it accepted, to
+    // receive on.
+    outgoing: Pool<UnixStream>,
+    incoming: Vec<Conn>,
+    received: VecDeque<(NodeID, Box<[u8]>)>,
+}
+
+impl UnixTransport {
+    // Listen for a node in a cluster's directory, replacing the socket a
+    // previous run left behind.
+    pub fn bind(dir: impl AsRef<Path>, me: NodeID) -> Result<UnixTransport> {
+        let dir = dir.as_ref().to_path_buf();
+        let path = socket_path(&dir, me);
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        let listener = UnixListener::bind(&path)?;
+        listener.set_nonblocking(true)?;
+        Ok(UnixTransport {
+            me,
+            compatibility: Compatibility::default(),
+            dir,
+            listener,
+            outgoing: Pool::new(me),
+            incoming: Vec::new(),
+            received: VecDeque::new(),
+        })
+    }
+
+    // Open up to a number of connections to each peer, one for control and
+    // routine messages and the rest for bulk ones.
+    pub fn with_conns(mut self, conns: usize) -> UnixTransport {
+        let outgoing = std::mem::replace(&mut self.outgoing, Pool::new(self.me));
+        self.outgoing = outgoing.with_size(conns);
+        self
+    }
+
+    // Hold up to a number of bytes for a peer that can't be connected to.
+    pub fn with_hold(mut self, hold: usize) -> UnixTransport {
+        let outgoing = std::mem::replace(&mut self.outgoing, Pool::new(self.me));
+        self.outgoing = outgoing.with_hold(hold);
+        self
+    }
+
+    // Speak the protocol versions in a window, as the node does (see
+    // Node::with_compatibility): say its newest in each hello, and drop
+    // connections from nodes older than it accepts.
+    pub fn with_compatibility(mut self, compatibility: Compatibility) -> UnixTransport {
+        let outgoing = std::mem::replace(&mut self.outgoing, Pool::new(self.me));
+        self.outgoing = outgoing.with_compatibility(compatibility);
+        self.compatibility = compatibility;
+        self
+    }
+
+    pub fn me(&self) -> NodeID {
+        self.me
+    }
+
+    // The connections open to a peer.
+    pub fn conns(&self, dst: NodeID) -> usize {
+        self.outgoing.conns(dst)
+    }
+
+    fn accept(&mut self) -> Result<()> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => self.incoming.push(Conn::new(stream)?),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    // Write what's waiting to go out, reconnecting where need be.
+    fn flush(&mut self) {
+        let dir = &self.dir;
+        self.outgoing.flush(&mut |dst| connect(dir, dst));
+    }
+}
+
+fn lost(node: Option<NodeID>, e: &Error) {
+    warn!(
+        target: telemetry::TARGET,
+        node = ?node,
+        error = %e,
+        "lost a unix socket connection"
+    );
+}
+
+impl Transport for UnixTransport {
+    fn send(&mut self, dst: NodeID, buf: Box<[u8]>) -> Result<()> {
+        self.send_as(dst, Urgency::Routine, buf)
+    }
+
+    fn send_as(&mut self, dst: NodeID, urgency: Urgency, buf: Box<[u8]>) -> Result<()> {
+        let dir = &self.dir;
+        let connect = &mut |dst| connect(dir, dst);
+        self.outgoing.send(dst, urgency, &buf, connect)?;
+        self.flush();
+        Ok(())
+    }
+
+    fn backlog(&self, dst: NodeID) -> usize {
+        self.outgoing.backlog(dst)
+    }
+
+    fn recv(&mut self) -> Result<Option<(NodeID, Box<[u8]>)>> {
+        self.flush();
+        self.accept()?;
+        let (compatibility, received) = (&self.compatibility, &mut self.received);
+        self.incoming
+            .retain_mut(|conn| match conn.take(compatibility, received) {
+                Ok(open) => open,
+                Err(e) => {
+                    lost(conn.peer, &e);
+                    false
+                }
+            });
+        Ok(self.received.pop_front())
+    }
+}
+
+impl Drop for UnixTransport {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(socket_path(&self.dir, self.me));
+    }
+}
@@ -0,0 +1,129 @@
+// Protocol versions, so that a realm can be upgraded a node at a time
+// rather than all at once: for a while, nodes of different builds have to
+// talk to each other.
+//
+// Every buffer a Node queues says, in its header (see compress.rs), which
+// version of the protocol its message is encoded at, and every connection
+// a stream transport opens starts with a hello saying which version the
+// node at the other end speaks. A node's compatibility window is the range
+// of versions it speaks: the newest it can encode, and the oldest it still
+// accepts. Once a node has heard from a peer, it speaks to it at the older
+// of the peer's version and its own newest, so a newer node talks down to
+// an older one and an older one is never sent what it couldn't know; until
+// then it speaks its newest. A message at a version older than the window
+// is dropped, with a warning, so a node too old to keep up with the realm
+// is cut off rather than misunderstood.
+//
+// Each kind of message has the version it was added in, and a node doesn't
+// send a peer a kind newer than the version it speaks to it; the protocols
+// above have to get by without it. A message of a kind a node doesn't know,
+// say from a newer peer that hasn't heard from it yet, is dropped rather
+// than failing the receive, as if the network had lost it. So a new kind of
+// message goes at the end of SpecificMsg, with a since() of the version that
+// adds it, and PROTOCOL goes up; the fields of an existing kind never
+// change, since a node can't skip what it doesn't understand within one.
+//
+// Protocol versions go up by one at a time, and OLDEST_PROTOCOL only once no
+// node of a realm speaks anything older.
+
+use crate::{NodeID, SpecificMsg};
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use submerge_base::{Error, Result};
+
+// The newest version of the protocol this build speaks.
+pub const PROTOCOL: u16 = 1;
+// The oldest version it accepts.
+pub const OLDEST_PROTOCOL: u16 = 1;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct Compatibility {
+    oldest: u16,
+    newest: u16,
+}
+
+impl Default for Compatibility {
+    fn default() -> Compatibility {
+        Compatibility::new(OLDEST_PROTOCOL, PROTOCOL)
+    }
+}
+
+impl Compatibility {
+    // A window of versions, clamped to those this build speaks.
+    pub fn new(oldest: u16, newest: u16) -> Compatibility {
+        let newest = newest.clamp(OLDEST_PROTOCOL, PROTOCOL);
+        let oldest = oldest.clamp(OLDEST_PROTOCOL, newest);
+        Compatibility { oldest, newest }
+    }
+
+    pub fn oldest(&self) -> u16 {
+        self.oldest
+    }
+
+    pub fn newest(&self) -> u16 {
+        self.newest
+    }
+
+    pub fn accepts(&self, version: u16) -> bool {
+        self.oldest <= version
+    }
+
+    // The version to speak to a peer that speaks some version, if it's
+    // been heard from.
+    pub fn with(&self, peer: Option<u16>) -> u16 {
+        peer.map_or(self.newest, |v| v.min(self.newest))
+    }
+}
+
+impl SpecificMsg {
+    // The protocol version the kind of message was added in.
+    pub fn since(&self) -> u16 {
+        match self {
+            SpecificMsg::Ping
+            | SpecificMsg::Pong
+            | SpecificMsg::Put(_)
+            | SpecificMsg::Ack
+            | SpecificMsg::PutPart { .. }
+            | SpecificMsg::AckPart(_)
+            | SpecificMsg::Abort
+            | SpecificMsg::Aborted
+            | SpecificMsg::Watermark(_)
+            | SpecificMsg::Paxos(_)
+            | SpecificMsg::Ready
+            | SpecificMsg::CatchUp(_)
+            | SpecificMsg::CaughtUp
+            | SpecificMsg::ResultHash(_)
+            | SpecificMsg::Resync
+            | SpecificMsg::Segment(_)
+            | SpecificMsg::Gossip(_)
+            | SpecificMsg::GossipReply(_) => 1,
+        }
+    }
+
+    // Whether the kind of message can be sent at a version.
+    pub(crate) fn check(&self, dst: NodeID, version: u16) -> Result<()> {
+        if self.since() > version {
+            return Err(Error::protocol(format!(
+                "{:?} speaks protocol {}, and the message needs {}",
+                dst,
+                version,
+                self.since()
+            )));
+        }
+        Ok(())
+    }
+}
+
+// A Msg laid out as it's encoded, with its specifics skipped, to read
+// around a kind of message this build doesn't know.
+#[derive(Deserialize)]
+#[allow(dead_code)]
+pub(crate) struct Envelope {
+    pub(crate) src: NodeID,
+    dst: IgnoredAny,
+    txn_time: IgnoredAny,
+    msg_time: IgnoredAny,
+    sequence: IgnoredAny,
+    response: IgnoredAny,
+    specific: IgnoredAny,
+}
@@ -0,0 +1,92 @@
+// Aborting. A coordinator can call off a transaction it's still
+// replicating. Until the transaction is replicated, the coordinator's local
+// watermark is held behind it (see watermark.rs), so the global watermark
+// can't release it anywhere; once it's been marked replicated, which in
+// quorum mode is before it leaves Put, it may be released and run at any
+// time, and it's too late.
+//
+// cancel() moves the transaction from Put to Abort, after which poll()
+// sends every node of the configuration an Abort instead of Puts, retried
+// in the same way, and ack() counts Aborted replies instead of acks, so
+// acks to Puts that arrive late change nothing. A node that gets an Abort
+// takes the transaction out of its execution queue, removes the versions
+// it installed for it (see store.rs) and replies Aborted. It also
+// remembers the transaction as aborted until the global watermark passes
+// it, so a Put or part that was overtaken by the Abort isn't installed
+// after it.
+//
+// Once every node that counts has replied, the transaction is Aborted and
+// the coordinator lets its watermark pass it; a node that doesn't reply in
+// time leaves it in Err, for reconfiguration to deal with, like a Put
+// would.
+
+use crate::put::settled;
+use crate::{send, Config, PutTry, State, Transaction};
+use submerge_base::Result;
+use submerge_net::{Msg, Node, NodeID, NodeTime, RealmTime, SpecificMsg};
+
+impl Transaction {
+    // Start aborting, if the transaction is still replicating. Returns
+    // whether it is.
+    pub fn cancel(&mut self, config: &Config) -> bool {
+        if self.quorate() {
+            return false;
+        }
+        let State::Put { joining, .. } = &self.state else {
+            return false;
+        };
+        let joining = joining.clone();
+        let nodes = config.nodes.iter().map(|n| (*n, PutTry::Nothing));
+        self.set_state(State::Abort {
+            nodes: nodes.collect(),
+            joining,
+        });
+        true
+    }
+
+    pub(crate) fn poll_abort(
+        &mut self,
+        net: &mut Node,
+        me: NodeID,
+        config: &Config,
+        now: NodeTime,
+    ) -> Result<()> {
+        let _span = self.span("abort", me).entered();
+        let State::Abort { nodes, .. } = &mut self.state else {
+            return Ok(());
+        };
+        for (node, tried) in nodes.iter_mut() {
+            if let Some(count) = tried.due(config, now) {
+                let msg_time = RealmTime::new(now, me, count);
+                let abort = SpecificMsg::Abort;
+                send(net, Msg::new(me, *node, self.time, msg_time, count, abort))?;
+            }
+        }
+        self.settle_abort();
+        Ok(())
+    }
+
+    pub(crate) fn aborted(&mut self, msg: &Msg) {
+        let State::Abort { nodes, .. } = &mut self.state else {
+            return;
+        };
+        if *msg.specific() != SpecificMsg::Aborted {
+            return;
+        }
+        if let Some(tried) = nodes.get_mut(&msg.src()) {
+            *tried = PutTry::Success;
+        }
+        self.settle_abort();
+    }
+
+    fn settle_abort(&mut self) {
+        let State::Abort { nodes, joining } = &self.state else {
+            return;
+        };
+        match settled(nodes, joining) {
+            None => {}
+            Some(timed_out) if timed_out.is_empty() => self.set_state(State::Aborted),
+            Some(timed_out) => self.set_state(State::Err { nodes: timed_out }),
+        }
+    }
+}
@@ -0,0 +1,180 @@
+// Admission control. Every transaction a node coordinates is submitted in
+// a priority class, and each class has bounds on how many of its
+// transactions may be replicating at once and how many may be replicated
+// but waiting to run. A class at either bound is saturated: what's
+// submitted to it then is delayed, held until the class has room again,
+// or shed, refused with an overloaded error, as the class's limit says.
+// By default the low class sheds and the others delay, so a node that's
+// swamped with bulk work still takes the work that matters.
+//
+// Delayed transactions are admitted in priority order, and in the order
+// they were submitted within a class; nothing is admitted to a class
+// ahead of what it's already delayed. Every delay and shed is counted, for
+// the status report.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use submerge_base::{Error, Result};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum Priority {
+    Low,
+    Normal,
+    High,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum Overload {
+    Delay,
+    Shed,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct Limit {
+    pub replicating: usize,
+    pub queued: usize,
+    pub overload: Overload,
+}
+
+impl Limit {
+    pub fn new(replicating: usize, queued: usize, overload: Overload) -> Limit {
+        Limit {
+            replicating,
+            queued,
+            overload,
+        }
+    }
+}
+
+// A class's transactions in each stage, and its delays and sheds so far.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ClassStats {
+    pub replicating: usize,
+    pub queued: usize,
+    pub waiting: usize,
+    pub delayed: u64,
+    pub shed: u64,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Admission {
+    limits: BTreeMap<Priority, Limit>,
+    stats: BTreeMap<Priority, ClassStats>,
+}
+
+impl Default for Admission {
+    fn default() -> Admission {
+        let limits = [
+            (Priority::High, Limit::new(256, 1024, Overload::Delay)),
+            (Priority::Normal, Limit::new(128, 512, Overload::Delay)),
+            (Priority::Low, Limit::new(32, 128, Overload::Shed)),
+        ];
+        Admission {
+            limits: limits.into_iter().collect(),
+            stats: BTreeMap::new(),
+        }
+    }
+}
+
+impl Admission {
+    pub fn new() -> Admission {
+        Admission::default()
+    }
+
+    pub fn with_limit(mut self, priority: Priority, limit: Limit) -> Admission {
+        self.limits.insert(priority, limit);
+        self
+    }
+
+    pub fn limit(&self, priority: Priority) -> Limit {
+        self.limits[&priority]
+    }
+
+    pub fn stats(&self) -> &BTreeMap<Priority, ClassStats> {
+        &self.stats
+    }
+
+    fn class(&mut self, priority: Priority) -> &mut ClassStats {
+        self.stats.entry(priority).or_default()
+    }
+
+    fn saturated(&self, priority: Priority) -> bool {
+        let limit = self.limit(priority);
+        let stats = self.stats.get(&priority).copied().unwrap_or_default();
+        stats.replicating >= limit.replicating || stats.queued >= limit.queued
+    }
+
+    // Decide on a submission: Ok(true) to begin it now, Ok(false) to hold
+    // it until admit() says there's room, or an overloaded error to shed
+    // it.
+    pub fn submit(&mut self, priority: Priority) -> Result<bool> {
+        let waiting = self.class(priority).waiting > 0;
+        if !waiting && !self.saturated(priority) {
+            self.class(priority).replicating += 1;
+            return Ok(true);
+        }
+        match self.limit(priority).overload {
+            Overload::Delay => {
+                let class = self.class(priority);
+                class.waiting += 1;
+                class.delayed += 1;
+                Ok(false)
+            }
+            Overload::Shed => {
+                self.class(priority).shed += 1;
+                Err(Error::overloaded(format!(
+                    "{:?} priority transactions are saturated",
+                    priority
+                )))
+            }
+        }
+    }
+
+    // The class to begin a held transaction of next, if any has room.
+    pub fn admit(&mut self) -> Option<Priority> {
+        let ready = Priority::ALL
+            .into_iter()
+            .find(|p| self.class(*p).waiting > 0 && !self.saturated(*p))?;
+        let class = self.class(ready);
+        class.waiting -= 1;
+        class.replicating += 1;
+        Some(ready)
+    }
+
+    // A held transaction of the class was aborted before it began.
+    pub fn withdraw(&mut self, priority: Priority) {
+        let class = self.class(priority);
+        class.waiting = class.waiting.saturating_sub(1);
+    }
+
+    // A transaction of the class has replicated, and is queued to run.
+    pub fn replicated(&mut self, priority: Priority) {
+        let class = self.class(priority);
+        class.replicating = class.replicating.saturating_sub(1);
+        class.queued += 1;
+    }
+
+    // A replicated transaction of the class was killed by a
+    // reconfiguration, and is replicating again.
+    pub fn resubmitted(&mut self, priority: Priority) {
+        let class = self.class(priority);
+        class.queued = class.queued.saturating_sub(1);
+        class.replicating += 1;
+    }
+
+    // A transaction of the class failed to replicate.
+    pub fn failed(&mut self, priority: Priority) {
+        let class = self.class(priority);
+        class.replicating = class.replicating.saturating_sub(1);
+    }
+
+    // A transaction of the class has run.
+    pub fn finished(&mut self, priority: Priority) {
+        let class = self.class(priority);
+        class.queued = class.queued.saturating_sub(1);
+    }
+}
@@ -0,0 +1,95 @@
+// Chunked replication. A thunk that carries a large Tab, as a bulk load
+// does, can serialize to more than fits in a message, and even when it
+// fits, one giant frame holds up everything queued behind it. So a thunk
+// larger than the configuration's part size is sent as several PutParts
+// instead of a Put, each acked on its own; a retry resends only the parts
+// a node hasn't acked, and the node counts as replicated once it's acked
+// them all (see put.rs).
+//
+// The receiving node keeps the parts of each transaction until it has them
+// all, then installs the whole thunk as if it had come in a single Put. It
+// acks a part once it has it, and the last only once the thunk is
+// installed, so the coordinator never counts a node that hasn't got the
+// whole of it. Parts of transactions the global watermark has passed are
+// forgotten, complete or not, since their Puts are ignored by then anyway.
+
+use std::collections::BTreeMap;
+use submerge_base::{Error, Result};
+use submerge_net::RealmTime;
+
+// The default most thunk bytes a single Put carries.
+pub const PART_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct Partial {
+    parts: u32,
+    got: BTreeMap<u32, Vec<u8>>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Reassembly {
+    partial: BTreeMap<RealmTime, Partial>,
+}
+
+impl Reassembly {
+    pub fn new() -> Reassembly {
+        Reassembly::default()
+    }
+
+    // Take a part in, and return the whole thunk's bytes once every part
+    // has arrived.
+    pub fn recv(
+        &mut self,
+        time: RealmTime,
+        part: u32,
+        parts: u32,
+        bytes: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        if part >= parts {
+            return Err(Error::protocol(format!(
+                "{:?} sent part {} of {}",
+                time, part, parts
+            )));
+        }
+        let partial = self.partial.entry(time).or_insert(Partial {
+            parts,
+            got: BTreeMap::new(),
+        });
+        if partial.parts != parts {
+            return Err(Error::protocol(format!(
+                "{:?} was sent in {} parts and in {}",
+                time, partial.parts, parts
+            )));
+        }
+        partial.got.insert(part, bytes.to_vec());
+        if partial.got.len() < parts as usize {
+            return Ok(None);
+        }
+        let got = self
+            .partial
+            .remove(&time)
+            .map(|p| p.got)
+            .unwrap_or_default();
+        Ok(Some(got.into_values().flatten().collect()))
+    }
+
+    // Drop the parts of an aborted transaction.
+    pub fn discard(&mut self, time: RealmTime) {
+        self.partial.remove(&time);
+    }
+
+    // Forget the transactions at or before a time, which have been released.
+    pub fn forget(&mut self, upto: RealmTime) {
+        self.partial = self.partial.split_off(&upto);
+        self.partial.remove(&upto);
+    }
+
+    // The number of transactions with parts still missing.
+    pub fn len(&self) -> usize {
+        self.partial.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.partial.is_empty()
+    }
+}
@@ -0,0 +1,979 @@
+// The transaction client: one node's whole part in the protocol, behind a
+// submit-and-await API, so that embedders and the server needn't drive the
+// transaction state machine themselves.
+//
+// submit() gives a thunk the next RealmTime on this node, begins it in the
+// local watermark, and starts replicating it; the TxnHandle it returns
+// waits for the result. tick() does everything else that's due: it takes
+// Puts from other coordinators into the store and the execution queue and
+// acks them, counts acks for its own Puts, gossips watermarks, and runs
+// whatever the global watermark releases, writing the results into the
+// store and handing them to the handles of the transactions it
+// coordinates.
+//
+// The client doesn't do any IO: the embedder moves bytes between its
+// send_bytes() and recv_bytes() and the network, and calls tick() as often
+// as it likes; messages a node sends itself never leave it. A handle can be
+// awaited from another thread while one drives the client. With compression,
+// the buffers large thunks make go out compressed to the peers that take
+// them (see submerge-net's compress.rs). With a queue limit, what waits for
+// the embedder to carry it to each peer is bounded, and a message that a
+// full queue refuses is treated as lost, and sent again as any lost message
+// is (see submerge-net's queue.rs). With a compatibility window, the
+// client's node speaks only the protocol versions in it, so a realm can be
+// upgraded a node at a time (see submerge-net's version.rs).
+//
+// A Put that arrives again, because the ack to it was lost, is acked again
+// but installed only once (see dedup.rs); one that conflicts with the Put
+// installed at its time is logged and dropped. A thunk sent in parts is
+// installed once they've all arrived (see chunk.rs).
+//
+// Once the global watermark has released and run everything up to it, the
+// client collects the versions behind it (see gc.rs), keeping a retention
+// period of history and whatever open snapshots pin.
+//
+// Each submission is in a priority class, Normal unless it says otherwise;
+// admission control (see admit.rs) may hold it back until its class has
+// room, in which case it gets its time only when it begins, or shed it.
+//
+// A transaction can be aborted while it's replicating (see abort.rs), and
+// its handle then gets an aborted error.
+//
+// With a write quorum in the configuration (see quorum.rs), a transaction
+// counts as replicated once a quorum has acked it, and the client releases
+// only up to the first transaction from any coordinator it hasn't got.
+//
+// The client estimates its peers' clock skew from the messages they send,
+// and may slew the clock it takes RealmTimes from to keep within a bound of
+// theirs (see skew.rs).
+//
+// A read in a session (see session.rs) waits until the node has released
+// every write the session made, and then reads as of everything released.
+//
+// An interactive transaction (see interactive.rs) reads at a snapshot the
+// client pins while it's open, and commits as a thunk that fails with a
+// conflict error if what it read has changed by the time it runs.
+//
+// A node that crashed can be restarted on the store it kept, and recover
+// what it had in flight from it (see recover.rs). With a configuration log
+// (see epochs.rs), each configuration the client moves to is logged before
+// it acts on it, a restarted client picks up in the latest logged one, and
+// messages from before that epoch began are dropped as stale.
+//
+// A node whose Puts go unacked through every retry may be given more, backing
+// off, and pinged to tell whether it's there at all (see escalate.rs). With
+// a failure detector (see submerge-net's detect.rs), which every message
+// received feeds and which has quiet peers pinged, the client tells a slow
+// node from a dead one: it goes on escalating to a node that's only slow,
+// but gives up on one the detector takes for dead at once.
+//
+// diagnose() reports what each of the node's waiting transactions is
+// blocked on (see diag.rs), and with a stall threshold the client logs
+// each that waits longer than it.
+//
+// A transaction whose Puts time out fails with a timeout error: it needs a
+// reconfiguration (see reconfig.rs) before it can be resubmitted. Once one
+// is decided, reconfigure() moves the client to it, killing the
+// transactions past its seal, and resubmits those the node coordinates at
+// new times in the new epoch, under the same handles. A transaction killed
+// by more reconfigurations than the client's limit fails with an aborted
+// error instead.
+
+use crate::{
+    lossy, seal, send, Admission, Arrivals, Blocked, Config, ConfigLog, Dedup, Gc, Gossip,
+    Latencies, NodeSet, OpenTxn, Priority, Probation, PutTry, Queue, ReadHandle, Reassembly,
+    Record, Recovery, Session, Skews, State, StateCounts, Store, Thunk, Transaction, TxnStatus,
+    Watermark,
+};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::{Arc, Condvar, Mutex};
+use submerge_base::{err, telemetry, Error, Result};
+use submerge_eval::{Outcome, StepResult};
+use submerge_lang::Path;
+use submerge_net::{
+    Compatibility, Compression, Detector, Duration, Msg, Node, NodeID, NodeTime, QueueLimit,
+    RealmTime, RecvMsg, SpecificMsg, Suspicion,
+};
+use tracing::{debug, error, warn};
+
+// How many times a transaction is resubmitted after reconfigurations kill
+// it, by default.
+const RESUBMITS: usize = 3;
+
+// What a handle shares with the client: the transaction's time, once it's
+// begun, and its result, once it's finished. The time changes if a
+// reconfiguration kills the transaction and it's resubmitted.
+#[derive(Debug, Default)]
+struct Shared {
+    time: Option<RealmTime>,
+    result: Option<Result<Outcome>>,
+    resubmits: usize,
+}
+
+type Slot = Arc<(Mutex<Shared>, Condvar)>;
+
+// A submitted transaction's result, to come.
+#[derive(Debug)]
+pub struct TxnHandle {
+    slot: Slot,
+}
+
+impl TxnHandle {
+    // The transaction's time, unless admission control is holding it.
+    pub fn time(&self) -> Option<RealmTime> {
+        let (shared, _) = &*self.slot;
+        shared.lock().ok()?.time
+    }
+
+    // The result, if the transaction has finished.
+    pub fn try_result(&self) -> Option<Result<Outcome>> {
+        let (shared, _) = &*self.slot;
+        shared.lock().ok()?.result.take()
+    }
+
+    // Wait for the transaction to finish, while another thread drives the
+    // client.
+    pub fn await_result(self) -> Result<Outcome> {
+        let (shared, done) = &*self.slot;
+        let mut shared = shared.lock().map_err(|_| err("txn handle poisoned"))?;
+        loop {
+            match shared.result.take() {
+                Some(r) => return r,
+                None => shared = done.wait(shared).map_err(|_| err("txn handle poisoned"))?,
+            }
+        }
+    }
+}
+
+fn refuse(put: &Msg, e: &Error) {
+    error!(
+        target: telemetry::TARGET,
+        txn_time = ?put.txn_time(),
+        node = ?put.src(),
+        error = %e,
+        "refused a put"
+    );
+}
+
+fn fill(slot: &Slot, r: Result<Outcome>) {
+    let (shared, done) = &**slot;
+    if let Ok(mut shared) = shared.lock() {
+        shared.result = Some(r);
+        done.notify_all();
+    }
+}
+
+pub struct TxnClient<S: Store> {
+    me: NodeID,
+    config: Config,
+    // Where the configurations the client moves to are kept, if anywhere.
+    configs: Option<ConfigLog>,
+    store: S,
+    net: Node,
+    watermark: Watermark,
+    gossip: Gossip,
+    queue: Queue,
+    dedup: Dedup,
+    reassembly: Reassembly,
+    arrivals: Arrivals,
+    gc: Gc,
+    admission: Admission,
+    // The priorities of the transactions this node coordinates, until
+    // they've run, and those admission control is holding.
+    priorities: BTreeMap<RealmTime, Priority>,
+    held: BTreeMap<Priority, VecDeque<(Thunk, Slot)>>,
+    // The transactions this node coordinates that are still replicating.
+    putting: BTreeMap<RealmTime, Transaction>,
+    // Those of them a quorum has acked, which are marked replicated.
+    quorate: BTreeSet<RealmTime>,
+    // The handles waiting for the transactions this node coordinates.
+    handles: BTreeMap<RealmTime, Slot>,
+    // Reads waiting for the node to release their sessions' writes.
+    reads: Vec<(Session, Path, ReadHandle)>,
+    // How long each peer takes to ack this node's Puts.
+    latencies: Latencies,
+    probation: Probation,
+    // How suspicious each peer's silence is, if the client's watching.
+    detector: Option<Detector>,
+    skews: Skews,
+    // The transactions that have ended, been aborted or failed since the
+    // client started.
+    ended: usize,
+    aborted: usize,
+    failed: usize,
+    // The next event number for submit's RealmTimes.
+    event: i64,
+    // How long a transaction waits before it's logged as stalled, and those
+    // that have been.
+    stall: Option<Duration>,
+    stalled: BTreeSet<RealmTime>,
+    // How many times a transaction killed by reconfigurations is
+    // resubmitted before it fails.
+    resubmits: usize,
+}
+
+impl<S: Store> TxnClient<S> {
+    pub fn new(me: NodeID, config: Config, store: S, gossip: Duration) -> TxnClient<S> {
+        TxnClient {
+            me,
+            config,
+            configs: None,
+            store,
+            net: Node::new(),
+            watermark: Watermark::new(),
+            gossip: Gossip::new(gossip),
+            queue: Queue::new(),
+            dedup: Dedup::new(),
+            reassembly: Reassembly::new(),
+            arrivals: Arrivals::new(),
+            gc: Gc::new(Duration::from_micros(0)),
+            admission: Admission::new(),
+            priorities: BTreeMap::new(),
+            held: BTreeMap::new(),
+            putting: BTreeMap::new(),
+            quorate: BTreeSet::new(),
+            handles: BTreeMap::new(),
+            reads: Vec::new(),
+            latencies: Latencies::new(),
+            probation: Probation::new(),
+            detector: None,
+            skews: Skews::new(),
+            ended: 0,
+            aborted: 0,
+            failed: 0,
+            event: 0,
+            stall: None,
+            stalled: BTreeSet::new(),
+            resubmits: RESUBMITS,
+        }
+    }
+
+    // Keep a period of history behind the global watermark, for time-travel
+    // reads.
+    pub fn with_retention(mut self, retention: Duration) -> TxnClient<S> {
+        self.gc = Gc::new(retention);
+        self
+    }
+
+    pub fn with_admission(mut self, admission: Admission) -> TxnClient<S> {
+        self.admission = admission;
+        self
+    }
+
+    // Log peers whose clocks are skewed too far, and perhaps slew.
+    pub fn with_skews(mut self, skews: Skews) -> TxnClient<S> {
+        self.skews = skews;
+        self
+    }
+
+    // Bound what's queued to each peer, for a driver that can't keep up.
+    pub fn with_queue_limit(mut self, limit: QueueLimit) -> TxnClient<S> {
+        // What the node sends itself never leaves it, so needn't be bounded.
+        let unbounded = QueueLimit::new(usize::MAX, usize::MAX, limit.backpressure);
+        let net = std::mem::take(&mut self.net).with_queue_limit(limit);
+        self.net = net.with_peer_queue_limit(self.me, unbounded);
+        self
+    }
+
+    // Compress large Puts to peers that take compressed buffers.
+    pub fn with_compression(mut self, compression: Compression) -> TxnClient<S> {
+        self.net = std::mem::take(&mut self.net).with_compression(compression);
+        self
+    }
+
+    // Speak only the protocol versions in a window, say to cut off nodes
+    // older than a rolling upgrade has left behind.
+    pub fn with_compatibility(mut self, compatibility: Compatibility) -> TxnClient<S> {
+        self.net = std::mem::take(&mut self.net).with_compatibility(compatibility);
+        self
+    }
+
+    // Log each configuration before moving to it, to pick up in on restart.
+    pub fn with_config_log(mut self, configs: ConfigLog) -> TxnClient<S> {
+        self.configs = Some(configs);
+        self
+    }
+
+    // Tell slow peers from dead ones by how long they've been quiet.
+    pub fn with_detector(mut self, detector: Detector) -> TxnClient<S> {
+        self.detector = Some(detector);
+        self
+    }
+
+    // Log transactions that wait longer than a threshold.
+    pub fn with_stall(mut self, stall: Duration) -> TxnClient<S> {
+        self.stall = Some(stall);
+        self
+    }
+
+    pub fn with_resubmits(mut self, resubmits: usize) -> TxnClient<S> {
+        self.resubmits = resubmits;
+        self
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn into_store(self) -> S {
+        self.store
+    }
+
+    // Rebuild what the node had in flight before it crashed from the store
+    // it kept, and pick up where it left off.
+    pub fn recover(mut self, now: NodeTime) -> Result<TxnClient<S>> {
+        if let Some(current) = self.configs.as_ref().and_then(|c| c.current()) {
+            self.config = current.clone();
+        }
+        let recovery = Recovery::scan(&self.store)?;
+        if let Some(released) = recovery.released {
+            self.queue.release(released);
+        }
+        self.arrivals = Arrivals::resumed();
+        self.event = recovery.events.get(&self.me).map_or(0, |e| e + 1);
+        for (time, thunk) in recovery.unresolved {
+            self.dedup.admit(time, &thunk.encode()?)?;
+            self.arrivals.record(time);
+            let txn = Transaction {
+                time,
+                thunk: thunk.clone(),
+                state: State::Seq,
+            };
+            self.queue.push(txn)?;
+            if time.node() == self.me {
+                self.watermark.begin(time)?;
+                let mut txn = Transaction::new(time, thunk, &self.config);
+                txn.poll(&mut self.net, self.me, &self.config, now)?;
+                self.putting.insert(time, txn);
+            } else {
+                let msg_time = RealmTime::new(now, self.me, 0);
+                let ack = SpecificMsg::Ack;
+                let msg = Msg::new(self.me, time.node(), time, msg_time, 0, ack);
+                send(&mut self.net, msg)?;
+            }
+        }
+        self.loopback()?;
+        Ok(self)
+    }
+
+    // A snapshot of where the node's transactions stand, for metrics.
+    pub fn status(&self) -> TxnStatus {
+        let mut states = StateCounts {
+            seq: self.queue.len(),
+            end: self.ended,
+            aborted: self.aborted,
+            err: self.failed,
+            ..StateCounts::default()
+        };
+        for txn in self.putting.values() {
+            states.count(&txn.state);
+        }
+        TxnStatus {
+            node: self.me,
+            epoch: self.config.epoch,
+            local: self.watermark.mark(),
+            heard: self.gossip.all_heard().clone(),
+            global: self.gossip.global(&self.config),
+            states,
+            latency: self.latencies.all(),
+            skew: self.skews.all(),
+            slew: self.skews.offset(),
+            admission: self.admission.stats().clone(),
+            queues: self.net.queues().clone(),
+        }
+    }
+
+    // How suspicious a peer's silence is, if there's a detector to say.
+    pub fn suspicion(&self, node: NodeID, now: NodeTime) -> Suspicion {
+        self.detector
+            .as_ref()
+            .map_or(Suspicion::Unknown, |d| d.suspicion(node, now))
+    }
+
+    // What each transaction still replicating or waiting to run is blocked
+    // on.
+    pub fn diagnose(&self, now: NodeTime) -> Vec<Blocked> {
+        let replicating = self.putting.values();
+        let mut blocked = replicating
+            .filter_map(|t| Blocked::replicating(t, now))
+            .collect::<Vec<_>>();
+        let (heard, global) = (self.gossip.all_heard(), self.gossip.global(&self.config));
+        // A transaction still replicating is reported on as such.
+        let queued = self.queue.times().filter(|t| !self.putting.contains_key(t));
+        for time in queued {
+            let voters = self.config.voters();
+            blocked.push(Blocked::queued(time, voters, heard, global, now));
+        }
+        blocked
+    }
+
+    // Log the transactions that have newly waited past the threshold.
+    fn stalls(&mut self, now: NodeTime) {
+        let Some(stall) = self.stall else {
+            return;
+        };
+        let blocked = self.diagnose(now);
+        self.stalled
+            .retain(|t| blocked.iter().any(|b| b.time == *t));
+        for b in blocked.iter().filter(|b| b.age > stall) {
+            if self.stalled.insert(b.time) {
+                warn!(
+                    target: telemetry::TARGET,
+                    txn_time = ?b.time,
+                    blocked = %b,
+                    "txn stalled"
+                );
+            }
+        }
+    }
+
+    // Keep the versions a read at a time sees, until it's unpinned.
+    pub fn pin(&mut self, time: RealmTime) {
+        self.gc.pin(time)
+    }
+
+    pub fn unpin(&mut self, time: RealmTime) {
+        self.gc.unpin(time)
+    }
+
+    // The next message for the network to carry.
+    pub fn send_bytes(&mut self) -> Option<(NodeID, Box<[u8]>)> {
+        let me = self.me;
+        self.net.send_bytes_where(|dst| dst != me)
+    }
+
+    // A message the network brought, to handle on the next tick.
+    pub fn recv_bytes(&mut self, src: NodeID, buf: Box<[u8]>) -> Result<()> {
+        self.net.recv_bytes(src, buf)
+    }
+
+    pub fn submit(&mut self, thunk: Thunk, now: NodeTime) -> Result<TxnHandle> {
+        self.submit_with(thunk, Priority::Normal, now)
+    }
+
+    // Submit in a priority class, which admission control may hold the
+    // transaction back in or shed it from.
+    pub fn submit_with(
+        &mut self,
+        thunk: Thunk,
+        priority: Priority,
+        now: NodeTime,
+    ) -> Result<TxnHandle> {
+        let slot = Slot::default();
+        match self.admission.submit(priority)? {
+            true => self.begin(thunk, priority, slot.clone(), now)?,
+            false => {
+                let held = self.held.entry(priority).or_default();
+                held.push_back((thunk, slot.clone()));
+            }
+        }
+        Ok(TxnHandle { slot })
+    }
+
+    // Read a path as of everything the node has released, once that takes
+    // in every write the session made.
+    pub fn read(&mut self, session: &Session, path: Path) -> ReadHandle {
+        let handle = ReadHandle::new();
+        self.reads.push((*session, path, handle.share()));
+        self.answer();
+        handle
+    }
+
+    // Answer the reads whose sessions' writes have been released.
+    fn answer(&mut self) {
+        let Some(released) = self.queue.released() else {
+            return;
+        };
+        let (ready, waiting) = std::mem::take(&mut self.reads)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(session, ..)| session.covered(Some(released)));
+        self.reads = waiting;
+        for (_, path, handle) in ready {
+            handle.fill(self.store.get(path, released));
+        }
+    }
+
+    // Start an interactive transaction, reading as of everything released.
+    pub fn open(&mut self) -> Result<OpenTxn> {
+        let snapshot = self
+            .queue
+            .released()
+            .ok_or_else(|| err("nothing to read yet"))?;
+        self.gc.pin(snapshot);
+        Ok(OpenTxn::new(snapshot))
+    }
+
+    // Submit an interactive transaction's writes.
+    pub fn commit(&mut self, txn: OpenTxn, now: NodeTime) -> Result<TxnHandle> {
+        self.gc.unpin(txn.snapshot());
+        self.submit(txn.into_thunk(), now)
+    }
+
+    pub fn rollback(&mut self, txn: OpenTxn) {
+        self.gc.unpin(txn.snapshot());
+    }
+
+    // Call off a transaction that hasn't replicated yet. Returns whether
+    // it's being aborted: once it's replicated it's too late, and it runs.
+    pub fn abort(&mut self, handle: &TxnHandle, now: NodeTime) -> Result<bool> {
+        let Some(time) = handle.time() else {
+            for (priority, held) in self.held.iter_mut() {
+                let Some(i) = held.iter().position(|(_, s)| Arc::ptr_eq(s, &handle.slot)) else {
+                    continue;
+                };
+                held.remove(i);
+                self.admission.withdraw(*priority);
+                fill(&handle.slot, Err(Error::aborted("aborted before it began")));
+                return Ok(true);
+            }
+            return Ok(false);
+        };
+        let Some(txn) = self.putting.get_mut(&time) else {
+            return Ok(false);
+        };
+        if !txn.cancel(&self.config) {
+            return Ok(matches!(txn.state, State::Abort { .. }));
+        }
+        txn.poll(&mut self.net, self.me, &self.config, now)?;
+        self.loopback()?;
+        Ok(true)
+    }
+
+    // The next time to begin a transaction at, in the current epoch.
+    fn stamp(&mut self, now: NodeTime) -> RealmTime {
+        let mut time = RealmTime::new(self.skews.clock(now), self.me, self.event);
+        if time < self.config.start {
+            let start = self.config.start.time() + Duration::from_micros(1);
+            time = RealmTime::new(start, self.me, self.event);
+        }
+        self.event += 1;
+        time
+    }
+
+    fn begin(&mut self, thunk: Thunk, priority: Priority, slot: Slot, now: NodeTime) -> Result<()> {
+        let time = self.stamp(now);
+        self.watermark.begin(time)?;
+        let mut txn = Transaction::new(time, thunk, &self.config);
+        txn.poll(&mut self.net, self.me, &self.config, now)?;
+        self.putting.insert(time, txn);
+        if let Ok(mut shared) = slot.0.lock() {
+            shared.time = Some(time);
+        }
+        self.handles.insert(time, slot);
+        self.priorities.insert(time, priority);
+        self.loopback()
+    }
+
+    // Move to the configuration a reconfiguration decided: kill the
+    // transactions past its seal, here and in the queue, and resubmit this
+    // node's in the new epoch.
+    pub fn reconfigure(&mut self, next: Config, now: NodeTime) -> Result<()> {
+        if next == self.config {
+            return Ok(());
+        }
+        let sealed = next.sealed.filter(|_| next.epoch == self.config.epoch + 1);
+        let Some(sealed) = sealed else {
+            return Err(Error::protocol(format!(
+                "epoch {} can't follow epoch {}",
+                next.epoch, self.config.epoch
+            )));
+        };
+        // The log starts with the configuration the client started in.
+        if let Some(configs) = &mut self.configs {
+            if configs.current().is_none() {
+                configs.append(&self.config)?;
+            }
+            configs.append(&next)?;
+        }
+        // Each killed transaction of this node's, and whether it had been
+        // marked replicated.
+        let mut killed = BTreeMap::new();
+        for txn in seal(&mut self.putting, &next) {
+            let replicated = self.quorate.remove(&txn.time);
+            killed.insert(txn.time, (txn, replicated));
+        }
+        let queued = self.queue.times().filter(|t| *t > sealed);
+        for time in queued.collect::<Vec<_>>() {
+            let Some(txn) = self.queue.remove(time) else {
+                continue;
+            };
+            txn.abort(&self.store)?;
+            self.dedup.abort(time);
+            if time.node() == self.me {
+                killed.entry(time).or_insert((txn, true));
+            }
+        }
+        self.watermark.seal(sealed);
+        self.config = next;
+        for (txn, replicated) in killed.into_values() {
+            self.resubmit(txn, replicated, now)?;
+        }
+        self.loopback()
+    }
+
+    // Begin a killed transaction again at a new time, under the same
+    // handle, unless it was being aborted or has been killed too often.
+    fn resubmit(&mut self, txn: Transaction, replicated: bool, now: NodeTime) -> Result<()> {
+        let old = txn.time;
+        let priority = self.priorities.remove(&old);
+        let slot = self.handles.remove(&old);
+        let resubmits = slot.as_ref().map_or(0, |s| {
+            s.0.lock().map_or(self.resubmits, |shared| shared.resubmits)
+        });
+        let failure = match txn.state {
+            State::Abort { .. } => {
+                self.aborted += 1;
+                Some(Error::aborted(format!("{:?} was aborted", old)))
+            }
+            _ if resubmits >= self.resubmits => {
+                self.failed += 1;
+                warn!(
+                    target: telemetry::TARGET,
+                    txn_time = ?old,
+                    resubmits,
+                    "txn killed by too many reconfigurations"
+                );
+                Some(Error::aborted(format!(
+                    "{:?} was killed by {} reconfigurations",
+                    old,
+                    resubmits + 1
+                )))
+            }
+            _ => None,
+        };
+        if let Some(e) = failure {
+            if let Some(priority) = priority {
+                match replicated {
+                    true => self.admission.finished(priority),
+                    false => self.admission.failed(priority),
+                }
+            }
+            if let Some(slot) = slot {
+                fill(&slot, Err(e));
+            }
+            return Ok(());
+        }
+        let time = self.stamp(now);
+        let mut txn = txn.resubmit(time, &self.config)?;
+        self.watermark.begin(time)?;
+        txn.poll(&mut self.net, self.me, &self.config, now)?;
+        self.putting.insert(time, txn);
+        if let Some(slot) = slot {
+            if let Ok(mut shared) = slot.0.lock() {
+                shared.time = Some(time);
+                shared.resubmits += 1;
+            }
+            self.handles.insert(time, slot);
+        }
+        if let Some(priority) = priority {
+            if replicated {
+                self.admission.resubmitted(priority);
+            }
+            self.priorities.insert(time, priority);
+        }
+        Ok(())
+    }
+
+    // Do whatever's due at now.
+    pub fn tick(&mut self, now: NodeTime) -> Result<()> {
+        self.skews.slew(now);
+        loop {
+            match self.net.recv_msg()? {
+                RecvMsg::NoMsgs => break,
+                RecvMsg::Single(msg) => self.recv(&msg, now)?,
+                RecvMsg::Paired { res, .. } => self.recv(&res, now)?,
+            }
+        }
+        // A response later than this is taken on its own, as any message.
+        let expired = NodeTime::from_micros(now.micros() - self.config.timeout.micros());
+        self.net.expire(expired);
+        self.ping(now)?;
+        self.replicate(now)?;
+        // Everything this node will begin from now on is after its last.
+        let last = RealmTime::new(self.skews.clock(now), self.me, self.event - 1);
+        self.watermark.advance(last);
+        let (net, me, config) = (&mut self.net, self.me, &self.config);
+        self.gossip.poll(net, me, config, &self.watermark, now)?;
+        if let Some(bound) = self.bound() {
+            for txn in self.queue.release(bound) {
+                self.run(txn)?;
+            }
+            self.dedup.forget(bound);
+            self.reassembly.forget(bound);
+            self.gc.collect(&self.store, bound)?;
+        }
+        self.answer();
+        self.stalls(now);
+        while let Some(priority) = self.admission.admit() {
+            let held = self.held.get_mut(&priority).and_then(|h| h.pop_front());
+            let Some((thunk, slot)) = held else {
+                return Err(Error::internal("admitted a transaction that wasn't held"));
+            };
+            self.begin(thunk, priority, slot, now)?;
+        }
+        self.loopback()
+    }
+
+    // How far to release: the global watermark, or in quorum mode no
+    // further than this node has every transaction.
+    fn bound(&self) -> Option<RealmTime> {
+        let global = self.gossip.global(&self.config)?;
+        if self.config.write_quorum().is_none() {
+            return Some(global);
+        }
+        self.arrivals.bound(self.gossip.all_heard(), global)
+    }
+
+    fn recv(&mut self, msg: &Msg, now: NodeTime) -> Result<()> {
+        let time = msg.txn_time();
+        // Acks to this node's own Puts from before the seal still count.
+        let stale = self.configs.as_ref().is_some_and(|c| c.stale(msg));
+        if stale && !self.putting.contains_key(&time) {
+            debug!(
+                target: telemetry::TARGET,
+                txn_time = ?time,
+                node = ?msg.src(),
+                "stale message dropped"
+            );
+            return Ok(());
+        }
+        if msg.src() != self.me {
+            self.skews.record(msg.src(), msg.msg_time(), now);
+            if let Some(detector) = &mut self.detector {
+                detector.heard(msg.src(), now);
+            }
+        }
+        match msg.specific() {
+            SpecificMsg::Put(bytes) => {
+                if Some(time) > self.queue.released() && !self.admit(msg, bytes)? {
+                    return Ok(());
+                }
+                self.reply(msg, now, SpecificMsg::Ack)
+            }
+            SpecificMsg::PutPart { part, parts, bytes } => {
+                if Some(time) > self.queue.released() && !self.dedup.contains(time) {
+                    match self.reassembly.recv(time, *part, *parts, bytes) {
+                        Ok(None) => {}
+                        Ok(Some(whole)) => {
+                            if !self.admit(msg, &whole)? {
+                                return Ok(());
+                            }
+                        }
+                        Err(e) => {
+                            refuse(msg, &e);
+                            return Ok(());
+                        }
+                    }
+                }
+                self.reply(msg, now, SpecificMsg::AckPart(*part))
+            }
+            SpecificMsg::Abort => {
+                if Some(time) <= self.queue.released() {
+                    error!(
+                        target: telemetry::TARGET,
+                        txn_time = ?time,
+                        node = ?msg.src(),
+                        "refused an abort of a released txn"
+                    );
+                    return Ok(());
+                }
+                if let Some(txn) = self.queue.remove(time) {
+                    txn.abort(&self.store)?;
+                }
+                self.dedup.abort(time);
+                self.reassembly.discard(time);
+                self.arrivals.record(time);
+                self.reply(msg, now, SpecificMsg::Aborted)
+            }
+            SpecificMsg::Ack | SpecificMsg::AckPart(_) | SpecificMsg::Aborted => {
+                if let Some(txn) = self.putting.get_mut(&time) {
+                    let acked = |txn: &Transaction| match &txn.state {
+                        State::Put { nodes, .. } => nodes.get(&msg.src()) == Some(&PutTry::Success),
+                        _ => true,
+                    };
+                    let before = acked(txn);
+                    txn.ack(msg);
+                    if !before && acked(txn) {
+                        let begun = txn.time.time().micros();
+                        let clock = self.skews.clock(now).micros();
+                        let latency = Duration::from_micros(clock - begun);
+                        self.latencies.record(msg.src(), latency);
+                    }
+                }
+                Ok(())
+            }
+            SpecificMsg::Ping => self.reply(msg, now, SpecificMsg::Pong),
+            SpecificMsg::Pong => {
+                self.probation.pong(msg.src());
+                Ok(())
+            }
+            SpecificMsg::Watermark(_) => {
+                self.gossip.hear(msg);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Install a replicated thunk and queue it to run, unless it's a
+    // duplicate. Returns whether to ack it.
+    fn admit(&mut self, msg: &Msg, bytes: &[u8]) -> Result<bool> {
+        let time = msg.txn_time();
+        match self.dedup.admit(time, bytes) {
+            Ok(true) => {}
+            Ok(false) => return Ok(true),
+            Err(e) => {
+                refuse(msg, &e);
+                return Ok(false);
+            }
+        }
+        self.arrivals.record(time);
+        let txn = Transaction {
+            time,
+            thunk: Thunk::decode(bytes)?,
+            state: State::Seq,
+        };
+        txn.install(&self.store)?;
+        self.queue.push(txn)?;
+        Ok(true)
+    }
+
+    fn reply(&mut self, put: &Msg, now: NodeTime, ack: SpecificMsg) -> Result<()> {
+        let msg_time = RealmTime::new(now, self.me, 0);
+        lossy(self.net.reply(put, msg_time, ack))
+    }
+
+    // Ping the peers the detector hasn't heard from lately.
+    fn ping(&mut self, now: NodeTime) -> Result<()> {
+        let Some(detector) = &mut self.detector else {
+            return Ok(());
+        };
+        let peers = self.config.nodes.iter().copied().filter(|n| *n != self.me);
+        for peer in detector.quiet(peers, now) {
+            let msg_time = RealmTime::new(now, self.me, 0);
+            let ping = self.net.send_request(peer, msg_time, SpecificMsg::Ping);
+            lossy(ping.map(drop))?;
+        }
+        Ok(())
+    }
+
+    // Send the Puts that are due, and finish with those that have stopped
+    // replicating.
+    fn replicate(&mut self, now: NodeTime) -> Result<()> {
+        let escalated = self.putting.values().map(|t| t.escalated(&self.config));
+        let escalated = escalated.flatten().collect::<NodeSet>();
+        // A node the detector takes for dead won't ack however long it's
+        // given.
+        let mut down = escalated
+            .iter()
+            .filter(|n| self.suspicion(**n, now) == Suspicion::Dead)
+            .copied()
+            .collect::<NodeSet>();
+        if self.config.escalation().probation {
+            let (net, me, timeout) = (&mut self.net, self.me, self.config.timeout);
+            down.extend(self.probation.poll(net, me, &escalated, timeout, now)?);
+        }
+        for node in down {
+            for txn in self.putting.values_mut() {
+                txn.give_up(node);
+            }
+        }
+        for txn in self.putting.values_mut() {
+            txn.poll(&mut self.net, self.me, &self.config, now)?;
+            // In quorum mode, the rest of its Puts go on in the background.
+            if txn.quorate() && self.quorate.insert(txn.time) {
+                self.watermark.replicated(txn.time)?;
+                if let Some(priority) = self.priorities.get(&txn.time) {
+                    self.admission.replicated(*priority);
+                }
+            }
+        }
+        let done = self
+            .putting
+            .values()
+            .filter(|t| !matches!(t.state, State::Put { .. } | State::Abort { .. }));
+        let done = done.map(|t| t.time).collect::<Vec<_>>();
+        for time in done {
+            let Some(txn) = self.putting.remove(&time) else {
+                continue;
+            };
+            match txn.state {
+                // Unless it was marked replicated when a quorum acked it.
+                State::Seq if !self.quorate.remove(&time) => {
+                    self.watermark.replicated(time)?;
+                    if let Some(priority) = self.priorities.get(&time) {
+                        self.admission.replicated(*priority);
+                    }
+                }
+                // Nothing's left of it anywhere, so the watermark can pass it.
+                State::Aborted => {
+                    self.watermark.replicated(time)?;
+                    self.aborted += 1;
+                    if let Some(priority) = self.priorities.remove(&time) {
+                        self.admission.failed(priority);
+                    }
+                    if let Some(slot) = self.handles.remove(&time) {
+                        let msg = format!("{:?} was aborted", time);
+                        fill(&slot, Err(Error::aborted(msg)));
+                    }
+                }
+                State::Err { nodes } => {
+                    self.failed += 1;
+                    if let Some(priority) = self.priorities.remove(&time) {
+                        self.admission.failed(priority);
+                    }
+                    if let Some(slot) = self.handles.remove(&time) {
+                        let msg = format!("{:?} timed out replicating to {:?}", time, nodes);
+                        fill(&slot, Err(Error::timeout(msg)));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, mut txn: Transaction) -> Result<()> {
+        let State::Run { eval } = &mut txn.state else {
+            return Err(Error::internal(format!(
+                "{:?} was released but isn't running",
+                txn.time
+            )));
+        };
+        let validated = txn.thunk.validate(&self.store, txn.time);
+        let result = match validated.map(|_| eval.run(usize::MAX)) {
+            Err(e) => {
+                txn.abort(&self.store)?;
+                Err(e)
+            }
+            Ok(StepResult::Done(outcome)) => {
+                let record = Record::from(outcome.clone());
+                for path in &txn.thunk.foot.writes {
+                    let written = txn.thunk.written(path, &record);
+                    self.store.put(path.clone(), txn.time, written)?;
+                }
+                Ok(outcome)
+            }
+            Ok(StepResult::Failed(e)) => Err(e),
+            Ok(StepResult::Pending) => Err(Error::internal("unbounded run returned early")),
+        };
+        txn.set_state(State::End);
+        self.ended += 1;
+        if let Some(priority) = self.priorities.remove(&txn.time) {
+            self.admission.finished(priority);
+        }
+        if let Some(slot) = self.handles.remove(&txn.time) {
+            fill(&slot, result);
+        }
+        Ok(())
+    }
+
+    // Take the messages this node sent itself straight back in.
+    fn loopback(&mut self) -> Result<()> {
+        let me = self.me;
+        while let Some((_, buf)) = self.net.send_bytes_where(|dst| dst == me) {
+            self.net.recv_bytes(me, buf)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+// Deduplication of replicated thunks. A coordinator retries a Put that
+// hasn't been acked within the timeout (see put.rs), and the network may
+// deliver a message more than once, so a node can receive the same Put
+// several times. Only the first is installed; the rest are acked again
+// without touching the store or the execution queue, since the ack that
+// was lost is the reason they were sent.
+//
+// Puts are keyed by their transaction's RealmTime, and carry a hash of the
+// thunk's bytes: a second Put at the same time with a different thunk isn't
+// a retry but a coordinator that's lost track of its RealmTimes, and is
+// refused as a protocol error rather than installed or acked.
+//
+// A transaction its coordinator has aborted (see abort.rs) is kept as a
+// key with no hash, so that a Put the Abort overtook isn't installed after
+// it.
+//
+// The keys are forgotten once the global watermark has released their
+// transactions, after which the execution queue ignores their Puts anyway.
+
+use std::collections::BTreeMap;
+use submerge_base::{Error, Result};
+use submerge_net::RealmTime;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Dedup {
+    // The hash of each installed thunk, or None for an aborted one.
+    installed: BTreeMap<RealmTime, Option<u64>>,
+}
+
+impl Dedup {
+    pub fn new() -> Dedup {
+        Dedup::default()
+    }
+
+    // Whether a Put is the first for its transaction, and so should be
+    // installed.
+    pub fn admit(&mut self, time: RealmTime, bytes: &[u8]) -> Result<bool> {
+        let hash = rapidhash::rapidhash(bytes);
+        match self.installed.get(&time) {
+            None => {
+                self.installed.insert(time, Some(hash));
+                Ok(true)
+            }
+            Some(None) => Ok(false),
+            Some(Some(h)) if *h == hash => Ok(false),
+            Some(_) => Err(Error::protocol(format!(
+                "{:?} was replicated with two different thunks",
+                time
+            ))),
+        }
+    }
+
+    // Refuse the transaction's Puts from now on.
+    pub fn abort(&mut self, time: RealmTime) {
+        self.installed.insert(time, None);
+    }
+
+    pub fn contains(&self, time: RealmTime) -> bool {
+        self.installed.contains_key(&time)
+    }
+
+    // Forget the transactions at or before a time, which have been released.
+    pub fn forget(&mut self, upto: RealmTime) {
+        self.installed = self.installed.split_off(&upto);
+        self.installed.remove(&upto);
+    }
+
+    pub fn len(&self) -> usize {
+        self.installed.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.installed.is_empty()
+    }
+}
@@ -0,0 +1,30 @@
+// The execution dependency graph. Transactions run in timestamp order only
+// where their footprints conflict (see Footprint::conflicts): each depends
+// on every earlier one that writes something it reads or writes, or reads
+// something it writes, and the rest may run in parallel. The coarser the
+// footprint, the more it depends on and is depended on by; one that covers
+// the whole database is a barrier.
+
+use crate::Transaction;
+use std::collections::{BTreeMap, BTreeSet};
+use submerge_net::RealmTime;
+
+impl Transaction {
+    // Whether this must run after another.
+    pub fn depends_on(&self, other: &Transaction) -> bool {
+        other.time < self.time && self.thunk.foot.conflicts(&other.thunk.foot)
+    }
+}
+
+// The earlier transactions each one depends on.
+pub fn dependencies(
+    txns: &BTreeMap<RealmTime, Transaction>,
+) -> BTreeMap<RealmTime, BTreeSet<RealmTime>> {
+    let mut deps = BTreeMap::new();
+    for (time, txn) in txns {
+        let earlier = txns.range(..time).map(|(_, t)| t);
+        let on = earlier.filter(|t| txn.depends_on(t)).map(|t| t.time);
+        deps.insert(*time, on.collect());
+    }
+    deps
+}
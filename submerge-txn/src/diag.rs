@@ -0,0 +1,142 @@
+// Blocked-transaction diagnostics. A transaction that isn't getting
+// anywhere is held up by one of three things, depending on where it is:
+// replicas that haven't acked its Puts (or, if it's being aborted, its
+// Aborts), the global watermark, which some peers' watermarks are holding
+// behind it, or an earlier transaction's unresolved version that it's
+// waiting to read (see wait.rs). Blocked says which, and names the nodes
+// or the version, so that an operator looking at a stall can tell a slow
+// peer from a lagging clock from a long-running writer.
+//
+// The client reports on the transactions it has replicating or queued
+// (see TxnClient::diagnose), and can log each one that's been waiting for
+// longer than a threshold, once; readers are reported on by whatever runs
+// them with Waits. Each report displays as one line, for a monitor to list.
+
+use crate::{NodeSet, PutTry, State, Transaction, Waits};
+use std::collections::BTreeMap;
+use std::fmt;
+use submerge_lang::Path;
+use submerge_net::{Duration, NodeID, NodeTime, RealmTime};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Blocker {
+    // The nodes that haven't acked, and the attempts made at each.
+    Unacked(BTreeMap<NodeID, i64>),
+    // The same, for an abort.
+    Unaborted(BTreeMap<NodeID, i64>),
+    // Replicated, but the global watermark isn't past it: the voters whose
+    // watermarks are behind it, or haven't been heard.
+    Watermark {
+        global: Option<RealmTime>,
+        behind: NodeSet,
+    },
+    // Waiting to read the unresolved versions a running transaction wrote.
+    Reading(Vec<(Path, RealmTime)>),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Blocked {
+    pub time: RealmTime,
+    // How long since the transaction's time.
+    pub age: Duration,
+    pub blocker: Blocker,
+}
+
+fn age(time: RealmTime, now: NodeTime) -> Duration {
+    Duration::from_micros(now.micros() - time.time().micros())
+}
+
+fn attempts(nodes: &BTreeMap<NodeID, PutTry>) -> BTreeMap<NodeID, i64> {
+    let unacked = nodes.iter().filter_map(|(n, tried)| match tried {
+        PutTry::Nothing => Some((*n, 0)),
+        PutTry::Attempt { count, .. } => Some((*n, *count)),
+        _ => None,
+    });
+    unacked.collect()
+}
+
+impl Blocked {
+    // What a replicating or aborting transaction is waiting for.
+    pub(crate) fn replicating(txn: &Transaction, now: NodeTime) -> Option<Blocked> {
+        let blocker = match &txn.state {
+            State::Put { nodes, .. } => Blocker::Unacked(attempts(nodes)),
+            State::Abort { nodes, .. } => Blocker::Unaborted(attempts(nodes)),
+            _ => return None,
+        };
+        Some(Blocked {
+            time: txn.time,
+            age: age(txn.time, now),
+            blocker,
+        })
+    }
+
+    // What a queued transaction is waiting for, given the voters'
+    // watermarks.
+    pub(crate) fn queued(
+        time: RealmTime,
+        voters: impl Iterator<Item = NodeID>,
+        heard: &BTreeMap<NodeID, RealmTime>,
+        global: Option<RealmTime>,
+        now: NodeTime,
+    ) -> Blocked {
+        let behind = voters.filter(|n| heard.get(n).is_none_or(|m| *m < time));
+        Blocked {
+            time,
+            age: age(time, now),
+            blocker: Blocker::Watermark {
+                global,
+                behind: behind.collect(),
+            },
+        }
+    }
+
+    // What each reader waiting in Waits is waiting for.
+    pub fn reading(waits: &Waits, now: NodeTime) -> Vec<Blocked> {
+        let readers = waits.readers().map(|reader| Blocked {
+            time: reader,
+            age: age(reader, now),
+            blocker: Blocker::Reading(waits.waiting(reader).cloned().collect()),
+        });
+        readers.collect()
+    }
+}
+
+impl fmt::Display for Blocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} waiting {}us: ", self.time, self.age.micros())?;
+        let nodes = |f: &mut fmt::Formatter<'_>, nodes: &BTreeMap<NodeID, i64>| {
+            let mut sep = "";
+            for (node, count) in nodes {
+                write!(f, "{}node {} ({} attempts)", sep, node.0, count)?;
+                sep = ", ";
+            }
+            Ok(())
+        };
+        match &self.blocker {
+            Blocker::Unacked(unacked) => {
+                write!(f, "unacked by ")?;
+                nodes(f, unacked)
+            }
+            Blocker::Unaborted(unacked) => {
+                write!(f, "abort unacked by ")?;
+                nodes(f, unacked)
+            }
+            Blocker::Watermark { global, behind } => {
+                write!(f, "global watermark at {:?}, ", global)?;
+                if behind.is_empty() {
+                    return write!(f, "waiting for a missing transaction");
+                }
+                let behind = behind.iter().map(|n| n.0.to_string()).collect::<Vec<_>>();
+                let s = if behind.len() == 1 { "" } else { "s" };
+                write!(f, "held back by node{} {}", s, behind.join(", "))
+            }
+            Blocker::Reading(versions) => {
+                write!(f, "reading")?;
+                for (path, writer) in versions {
+                    write!(f, " {} at {:?}", path, writer)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,129 @@
+// Divergence detection. Every node runs every transaction, and must come to
+// the same result; once it has, it hashes the result's canonical encoding
+// and sends the hash to the other nodes of the configuration. A node that
+// hears a hash for a transaction that differs from one it already has,
+// its own or another's, has found a divergence: some node's execution
+// isn't deterministic, or its state is damaged. That's a serious event, so
+// it's logged as an error, and kept for the divergence report until the
+// nodes are resynchronized.
+//
+// Transactions every voter has reported the same hash for are settled, and
+// forgotten.
+
+use crate::{send, Config, Record};
+use std::collections::BTreeMap;
+use submerge_base::{err, telemetry, Result};
+use submerge_net::{Msg, Node, NodeID, NodeTime, RealmTime, SpecificMsg};
+use tracing::error;
+
+impl Record {
+    // A hash of the result, the same on every node that has the same one.
+    // Unresolved records have no result to hash.
+    pub fn content_hash(&self) -> Option<u64> {
+        match self {
+            Record::Resolved(vals) => Some(vals.content_hash()),
+            Record::Faulted(fault) => Some(fault.content_hash()),
+            Record::Unresolved(_) => None,
+        }
+    }
+}
+
+// The hashes the nodes reported for a transaction's result, when they
+// don't all agree.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Divergence {
+    txn: RealmTime,
+    hashes: BTreeMap<NodeID, u64>,
+}
+
+impl Divergence {
+    pub fn txn(&self) -> RealmTime {
+        self.txn
+    }
+
+    pub fn hashes(&self) -> &BTreeMap<NodeID, u64> {
+        &self.hashes
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Hashes {
+    // Each unsettled transaction's result hash, by the node that sent it.
+    heard: BTreeMap<RealmTime, BTreeMap<NodeID, u64>>,
+}
+
+impl Hashes {
+    pub fn new() -> Hashes {
+        Hashes::default()
+    }
+
+    // Hash a transaction's result, once it's run here, and send it to the
+    // other nodes.
+    pub fn finish(
+        &mut self,
+        net: &mut Node,
+        me: NodeID,
+        config: &Config,
+        txn: RealmTime,
+        result: &Record,
+        now: NodeTime,
+    ) -> Result<()> {
+        let Some(hash) = result.content_hash() else {
+            return Err(err(format!("{:?} hasn't run yet", txn)));
+        };
+        self.note(me, txn, hash);
+        let msg_time = RealmTime::new(now, me, 0);
+        for peer in config.nodes.iter().filter(|n| **n != me) {
+            let result = SpecificMsg::ResultHash(hash);
+            send(net, Msg::new(me, *peer, txn, msg_time, 0, result))?;
+        }
+        Ok(())
+    }
+
+    pub fn hear(&mut self, msg: &Msg) {
+        if let SpecificMsg::ResultHash(hash) = msg.specific() {
+            self.note(msg.src(), msg.txn_time(), *hash);
+        }
+    }
+
+    fn note(&mut self, node: NodeID, txn: RealmTime, hash: u64) {
+        let hashes = self.heard.entry(txn).or_default();
+        let agreed = hashes.values().all(|h| *h == hash);
+        let was = hashes.values().min() == hashes.values().max();
+        hashes.insert(node, hash);
+        if was && !agreed {
+            error!(
+                target: telemetry::TARGET,
+                txn_time = ?txn,
+                hashes = ?hashes,
+                "txn results diverged"
+            );
+        }
+    }
+
+    pub fn divergence(&self, txn: RealmTime) -> Option<Divergence> {
+        let hashes = self.heard.get(&txn)?;
+        if hashes.values().min() == hashes.values().max() {
+            return None;
+        }
+        Some(Divergence {
+            txn,
+            hashes: hashes.clone(),
+        })
+    }
+
+    // Every divergence found and not yet settled, in time order.
+    pub fn divergences(&self) -> Vec<Divergence> {
+        let txns = self.heard.keys().filter_map(|t| self.divergence(*t));
+        txns.collect()
+    }
+
+    // Forget the transactions every voter agrees on.
+    pub fn settle(&mut self, config: &Config) {
+        self.heard.retain(|_, hashes| {
+            let mut voters = config.voters().map(|n| hashes.get(&n));
+            let first = voters.next().flatten();
+            first.is_none() || !voters.all(|h| h == first)
+        });
+    }
+}
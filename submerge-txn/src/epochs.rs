@@ -0,0 +1,171 @@
+// The configuration history, on disk. Each configuration a node learns of,
+// starting with the first, is appended to a small log and synced before the
+// node acts on it, and the log is read back on startup, so a restarted node
+// knows which epoch it's in and where the earlier ones were sealed.
+//
+// Each entry is a varint length, a rapidhash of the entry's bytes, and the
+// configuration encoded with rmp. A crash can leave a partial entry at the
+// end, which is dropped on reading. No entry is longer than MAX_ENTRY_LEN,
+// so a partial entry is one whose length is within that but runs past the
+// end of the file. A longer length, an entry whose hash doesn't match, and
+// entries out of epoch order are corruption, and stop the log opening
+// rather than cutting off the entries after them.
+//
+// Messages about transactions or reconfigurations from before the current
+// epoch's start are stale: they're retransmissions from nodes that haven't
+// learned of the current epoch yet, and are for transactions that either
+// finished replicating before the seal or were killed by it.
+
+use crate::Config;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+use submerge_base::varint::{decode_uvarint, encode_uvarint, MAX_VARINT_LEN};
+use submerge_base::{err, Error, Result};
+use submerge_net::{Msg, RealmTime, SpecificMsg};
+
+const HASH_LEN: usize = 8;
+
+// Far more than a configuration of any realistic number of nodes encodes to.
+const MAX_ENTRY_LEN: usize = 1 << 16;
+
+#[derive(Debug)]
+pub struct ConfigLog {
+    file: File,
+    configs: Vec<Config>,
+}
+
+impl ConfigLog {
+    // Open the log at a path, creating it if there isn't one.
+    pub fn open(path: impl AsRef<Path>) -> Result<ConfigLog> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        let mut configs = Vec::new();
+        let mut pos = 0;
+        while pos < buf.len() {
+            let Some((entry, next)) = entry(&buf, pos)? else {
+                file.set_len(pos as u64)?;
+                break;
+            };
+            let config: Config = rmp_serde::from_slice(entry)?;
+            if let Some(last) = configs.last() {
+                if !follows(last, &config) {
+                    return Err(Error::corruption(format!(
+                        "config log has epoch {} after epoch {}",
+                        config.epoch, last.epoch
+                    )));
+                }
+            }
+            configs.push(config);
+            pos = next;
+        }
+        Ok(ConfigLog { file, configs })
+    }
+
+    // Add the configuration after the current one, durably. Appending the
+    // current one again does nothing.
+    pub fn append(&mut self, config: &Config) -> Result<()> {
+        if let Some(last) = self.configs.last() {
+            if last == config {
+                return Ok(());
+            }
+            if !follows(last, config) {
+                return Err(Error::protocol(format!(
+                    "epoch {} can't follow epoch {}",
+                    config.epoch, last.epoch
+                )));
+            }
+        }
+        let bytes = rmp_serde::to_vec(config)?;
+        if bytes.len() > MAX_ENTRY_LEN {
+            return Err(err(format!("{} byte configuration", bytes.len())));
+        }
+        let mut len = [0_u8; MAX_VARINT_LEN];
+        let n = encode_uvarint(bytes.len() as u64, &mut len);
+        let mut buf = len[..n].to_vec();
+        buf.extend_from_slice(&rapidhash::rapidhash(&bytes).to_le_bytes());
+        buf.extend_from_slice(&bytes);
+        self.file.write_all(&buf)?;
+        self.file.sync_data()?;
+        self.configs.push(config.clone());
+        Ok(())
+    }
+
+    pub fn current(&self) -> Option<&Config> {
+        self.configs.last()
+    }
+
+    // Every configuration, oldest first.
+    pub fn history(&self) -> &[Config] {
+        &self.configs
+    }
+
+    // The configuration a timestamp belongs to, if it's not in the gap
+    // between one's seal and the next one's start.
+    pub fn epoch_of(&self, time: RealmTime) -> Option<&Config> {
+        let i = self.configs.iter().rposition(|c| c.start <= time)?;
+        match self.configs.get(i + 1).and_then(|next| next.sealed) {
+            Some(seal) if time > seal => None,
+            _ => Some(&self.configs[i]),
+        }
+    }
+
+    pub fn stale(&self, msg: &Msg) -> bool {
+        let Some(current) = self.current() else {
+            return false;
+        };
+        let epochal = matches!(
+            msg.specific(),
+            SpecificMsg::Put(_)
+                | SpecificMsg::Ack
+                | SpecificMsg::PutPart { .. }
+                | SpecificMsg::AckPart(_)
+                | SpecificMsg::Abort
+                | SpecificMsg::Aborted
+                | SpecificMsg::Paxos(_)
+                | SpecificMsg::CatchUp(_)
+                | SpecificMsg::CaughtUp
+        );
+        epochal && msg.txn_time() < current.start
+    }
+}
+
+// Whether a configuration is the one after another: the next epoch, starting
+// later.
+fn follows(last: &Config, next: &Config) -> bool {
+    last.epoch.checked_add(1) == Some(next.epoch) && next.start > last.start
+}
+
+// The entry at a position in the log and the position after it, or None if
+// it's cut off by the end.
+fn entry(buf: &[u8], pos: usize) -> Result<Option<(&[u8], usize)>> {
+    let rest = &buf[pos..];
+    let (len, n) = match decode_uvarint(rest) {
+        Ok(v) => v,
+        // A varint cut off by the end is all continuation bytes.
+        Err(_) if rest.len() < MAX_VARINT_LEN && rest.iter().all(|b| b & 0x80 != 0) => {
+            return Ok(None)
+        }
+        Err(e) => return Err(e),
+    };
+    let len = match usize::try_from(len) {
+        Ok(len) if len <= MAX_ENTRY_LEN => len,
+        _ => return Err(Error::corruption("config log entry too long")),
+    };
+    let start = n + HASH_LEN;
+    let end = start + len;
+    if end > rest.len() {
+        return Ok(None);
+    }
+    let hash = u64::from_le_bytes(rest[n..start].try_into()?);
+    let bytes = &rest[start..end];
+    if rapidhash::rapidhash(bytes) != hash {
+        return Err(Error::corruption("config log entry doesn't match its hash"));
+    }
+    Ok(Some((bytes, pos + end)))
+}
@@ -0,0 +1,157 @@
+// Timeout escalation. A Put that's gone unacked through every retry leaves
+// its transaction in Err, which calls for a reconfiguration to drop the
+// node that didn't ack (see reconfig.rs). A node that's only slow for a
+// moment, through a pause or a burst of load, shouldn't cost a
+// reconfiguration, so the configuration can say how to escalate first.
+//
+// An escalation adds rounds of retries after the regular ones, each waiting
+// backoff times longer than the last, up to a ceiling, so a slow node gets
+// longer and longer to catch up. With probation on as well, the
+// coordinator pings a node whose Puts have escalated, once per timeout:
+// one that answers is slow but there, and keeps its escalated rounds, but
+// one that doesn't answer within the timeout is taken for down, and its
+// Puts time out at once rather than backing off for nothing.
+//
+// The default escalation has no rounds, and so no backoff or probation:
+// the retries run out as they always have.
+
+use crate::{lossy, Config, NodeSet, PutTry, State, Transaction};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use submerge_base::Result;
+use submerge_net::{Duration, Node, NodeID, NodeTime, RealmTime, SpecificMsg};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct Escalation {
+    pub rounds: i64,
+    pub backoff: i64,
+    pub ceiling: Duration,
+    pub probation: bool,
+}
+
+impl Default for Escalation {
+    fn default() -> Escalation {
+        Escalation::new(0, 1, Duration::from_micros(0))
+    }
+}
+
+impl Escalation {
+    pub fn new(rounds: i64, backoff: i64, ceiling: Duration) -> Escalation {
+        Escalation {
+            rounds: rounds.max(0),
+            backoff: backoff.max(1),
+            ceiling,
+            probation: false,
+        }
+    }
+
+    pub fn with_probation(mut self) -> Escalation {
+        self.probation = true;
+        self
+    }
+
+    // The escalated round an attempt is in, or 0 if it's a regular one.
+    pub fn round(&self, retries: i64, count: i64) -> i64 {
+        (count - retries - 1).max(0)
+    }
+
+    // How long to wait for an ack to an attempt before the next.
+    pub fn wait(&self, timeout: Duration, retries: i64, count: i64) -> Duration {
+        let round = self.round(retries, count);
+        if round == 0 {
+            return timeout;
+        }
+        let factor = self
+            .backoff
+            .saturating_pow(round.min(u32::MAX as i64) as u32);
+        let wait = timeout.micros().saturating_mul(factor);
+        Duration::from_micros(wait.min(self.ceiling.micros().max(timeout.micros())))
+    }
+}
+
+impl Transaction {
+    // The nodes whose Puts have gone into escalated rounds.
+    pub(crate) fn escalated(&self, config: &Config) -> NodeSet {
+        let State::Put { nodes, .. } = &self.state else {
+            return NodeSet::new();
+        };
+        let escalation = config.escalation();
+        let escalated = nodes.iter().filter(|(_, tried)| match tried {
+            PutTry::Attempt { count, .. } => escalation.round(config.retries, *count) > 0,
+            _ => false,
+        });
+        escalated.map(|(n, _)| *n).collect()
+    }
+
+    // Stop waiting for a node's ack, which won't come.
+    pub(crate) fn give_up(&mut self, node: NodeID) {
+        let State::Put { nodes, .. } = &mut self.state else {
+            return;
+        };
+        if let Some(tried @ PutTry::Attempt { .. }) = nodes.get_mut(&node) {
+            *tried = PutTry::TimedOut;
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Ping {
+    sent: NodeTime,
+    answered: bool,
+}
+
+// The pings to nodes on probation.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Probation {
+    pings: BTreeMap<NodeID, Ping>,
+}
+
+impl Probation {
+    pub fn new() -> Probation {
+        Probation::default()
+    }
+
+    // Ping the escalated nodes that are due one, and return those that
+    // didn't answer the last within the timeout.
+    pub fn poll(
+        &mut self,
+        net: &mut Node,
+        me: NodeID,
+        escalated: &NodeSet,
+        timeout: Duration,
+        now: NodeTime,
+    ) -> Result<NodeSet> {
+        self.pings.retain(|n, _| escalated.contains(n));
+        let mut down = NodeSet::new();
+        for node in escalated {
+            match self.pings.get(node) {
+                Some(p) if now < p.sent + timeout => continue,
+                Some(p) if !p.answered => {
+                    down.insert(*node);
+                    continue;
+                }
+                _ => {}
+            }
+            let msg_time = RealmTime::new(now, me, 0);
+            lossy(
+                net.send_request(*node, msg_time, SpecificMsg::Ping)
+                    .map(drop),
+            )?;
+            let ping = Ping {
+                sent: now,
+                answered: false,
+            };
+            self.pings.insert(*node, ping);
+        }
+        for node in &down {
+            self.pings.remove(node);
+        }
+        Ok(down)
+    }
+
+    pub fn pong(&mut self, node: NodeID) {
+        if let Some(ping) = self.pings.get_mut(&node) {
+            ping.answered = true;
+        }
+    }
+}
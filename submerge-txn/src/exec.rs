@@ -0,0 +1,94 @@
+// Release to execution, step 6 of the protocol. A node queues each
+// transaction that's finished replicating (State::Seq) by its time, and as
+// the global watermark advances it releases every queued transaction at or
+// before it to run, in time order.
+//
+// The global watermark is a promise that nothing at or before it is still
+// replicating anywhere, so once the queue has released through some time,
+// a transaction at or before it turning up later is a bug in the protocol,
+// not something to order around: the transactions after it have already
+// started running without it. push() refuses one with an internal error.
+
+use crate::{State, Transaction};
+use std::collections::BTreeMap;
+use submerge_base::{Error, Result};
+use submerge_net::RealmTime;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Queue {
+    // Replicated transactions waiting for the global watermark.
+    waiting: BTreeMap<RealmTime, Transaction>,
+    // The global watermark the queue has released through.
+    released: Option<RealmTime>,
+}
+
+impl Queue {
+    pub fn new() -> Queue {
+        Queue::default()
+    }
+
+    pub fn push(&mut self, txn: Transaction) -> Result<()> {
+        if txn.state != State::Seq {
+            return Err(Error::internal(format!(
+                "{:?} is in {}, not seq",
+                txn.time,
+                txn.state.name()
+            )));
+        }
+        if Some(txn.time) <= self.released {
+            return Err(Error::internal(format!(
+                "{:?} arrived after the global watermark passed it, at {:?}",
+                txn.time, self.released
+            )));
+        }
+        if self.waiting.contains_key(&txn.time) {
+            return Err(Error::internal(format!("{:?} is already queued", txn.time)));
+        }
+        self.waiting.insert(txn.time, txn);
+        Ok(())
+    }
+
+    // Start running every transaction at or before the global watermark,
+    // and give them back in time order. A watermark behind one already
+    // released through releases nothing.
+    pub fn release(&mut self, global: RealmTime) -> Vec<Transaction> {
+        if Some(global) <= self.released {
+            return Vec::new();
+        }
+        self.released = Some(global);
+        let later = self.waiting.split_off(&global);
+        let mut due = std::mem::replace(&mut self.waiting, later);
+        due.extend(self.waiting.remove(&global).map(|txn| (global, txn)));
+        let mut released = due.into_values().collect::<Vec<_>>();
+        for txn in released.iter_mut() {
+            txn.release(global);
+        }
+        released
+    }
+
+    // Take out a transaction that's been aborted before its release.
+    pub fn remove(&mut self, time: RealmTime) -> Option<Transaction> {
+        self.waiting.remove(&time)
+    }
+
+    pub fn contains(&self, time: RealmTime) -> bool {
+        self.waiting.contains_key(&time)
+    }
+
+    // The times of the transactions waiting, in order.
+    pub fn times(&self) -> impl Iterator<Item = RealmTime> + '_ {
+        self.waiting.keys().copied()
+    }
+
+    pub fn released(&self) -> Option<RealmTime> {
+        self.released
+    }
+
+    pub fn len(&self) -> usize {
+        self.waiting.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waiting.is_empty()
+    }
+}
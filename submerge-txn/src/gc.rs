@@ -0,0 +1,81 @@
+// Garbage collection of old versions. Once a transaction has run, its
+// thunk is replaced by its result, but every result stays in the store
+// after later ones supersede it. Nothing reads behind the global watermark
+// except the snapshots still open at older times, so a version is garbage
+// once a later one is at or before the horizon: the older of the global
+// watermark and the oldest open snapshot. The horizon is held back further
+// by a retention period, so time-travel reads can go that far into the
+// past without pinning a snapshot first.
+//
+// An unresolved version is never collected, since its transaction hasn't
+// run yet; nor is the latest version at or before the horizon, since reads
+// at the horizon still see it.
+
+use crate::Store;
+use std::collections::BTreeMap;
+use submerge_base::Result;
+use submerge_net::{Duration, NodeTime, RealmTime};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Gc {
+    retention: Duration,
+    // The open snapshots, counted by time.
+    pinned: BTreeMap<RealmTime, usize>,
+    // The horizon of the last collection.
+    collected: Option<RealmTime>,
+}
+
+impl Gc {
+    pub fn new(retention: Duration) -> Gc {
+        Gc {
+            retention,
+            pinned: BTreeMap::new(),
+            collected: None,
+        }
+    }
+
+    pub fn retention(&self) -> Duration {
+        self.retention
+    }
+
+    // Keep the versions a read at a time sees, until it's unpinned.
+    pub fn pin(&mut self, time: RealmTime) {
+        *self.pinned.entry(time).or_insert(0) += 1;
+    }
+
+    pub fn unpin(&mut self, time: RealmTime) {
+        if let Some(count) = self.pinned.get_mut(&time) {
+            *count -= 1;
+            if *count == 0 {
+                self.pinned.remove(&time);
+            }
+        }
+    }
+
+    // The oldest open snapshot.
+    pub fn oldest(&self) -> Option<RealmTime> {
+        self.pinned.keys().next().copied()
+    }
+
+    // The time no read goes behind, given the global watermark.
+    pub fn horizon(&self, global: RealmTime) -> RealmTime {
+        let oldest = self.oldest().map_or(global, |t| t.min(global));
+        let time = oldest
+            .time()
+            .micros()
+            .saturating_sub(self.retention.micros());
+        RealmTime::new(NodeTime::from_micros(time), oldest.node(), 0)
+    }
+
+    // Prune the versions behind the horizon, if it's moved since the last
+    // collection. Returns how many went.
+    pub fn collect(&mut self, store: &dyn Store, global: RealmTime) -> Result<usize> {
+        let horizon = self.horizon(global);
+        if self.collected.is_some_and(|t| t >= horizon) {
+            return Ok(0);
+        }
+        let pruned = store.prune(horizon)?;
+        self.collected = Some(horizon);
+        Ok(pruned)
+    }
+}
@@ -0,0 +1,129 @@
+// Watermark gossip, steps 5 and 6 of the protocol. Every node sends its
+// local watermark to every other node of the configuration once a period,
+// and keeps the latest it's heard from each. The global watermark is the
+// least of them, its own included: every transaction at or before it has
+// been replicated to every node, and no node will begin another there, so
+// the transactions waiting in Seq up to it can be released to run.
+//
+// Until a node has heard from every other there's no global watermark.
+// Nodes still joining aren't waited for, as their acks don't count yet.
+// Watermarks only move forward, so one that arrives after a later one from
+// the same node, having been reordered in the network, is ignored.
+//
+// The watermarks heard can also be spread by anti-entropy gossip (see
+// submerge-net's gossip.rs), as a payload: a node then learns another's
+// watermark through any node that's heard it, not only from the node
+// itself.
+
+use crate::{send, Config, State, Transaction, Watermark, STAGES};
+use std::collections::BTreeMap;
+use submerge_base::Result;
+use submerge_eval::Evaluator;
+use submerge_lang::FloatMode;
+use submerge_net::{Duration, GossipPayload, Msg, Node, NodeID, NodeTime, RealmTime, SpecificMsg};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Gossip {
+    period: Duration,
+    last: Option<NodeTime>,
+    // The latest watermark heard from each node.
+    heard: BTreeMap<NodeID, RealmTime>,
+}
+
+impl Gossip {
+    pub fn new(period: Duration) -> Gossip {
+        Gossip {
+            period,
+            last: None,
+            heard: BTreeMap::new(),
+        }
+    }
+
+    // Send the local watermark to the other nodes, if a period has passed
+    // since it was last sent.
+    pub fn poll(
+        &mut self,
+        net: &mut Node,
+        me: NodeID,
+        config: &Config,
+        local: &Watermark,
+        now: NodeTime,
+    ) -> Result<()> {
+        let Some(mark) = local.mark() else {
+            return Ok(());
+        };
+        self.note(me, mark);
+        if self.last.is_some_and(|t| now < t + self.period) {
+            return Ok(());
+        }
+        self.last = Some(now);
+        let msg_time = RealmTime::new(now, me, 0);
+        for peer in config.nodes.iter().filter(|n| **n != me) {
+            let gossip = SpecificMsg::Watermark(mark);
+            send(net, Msg::new(me, *peer, mark, msg_time, 0, gossip))?;
+        }
+        Ok(())
+    }
+
+    pub fn hear(&mut self, msg: &Msg) {
+        if let SpecificMsg::Watermark(mark) = msg.specific() {
+            self.note(msg.src(), *mark);
+        }
+    }
+
+    pub(crate) fn note(&mut self, node: NodeID, mark: RealmTime) {
+        let heard = self.heard.entry(node).or_insert(mark);
+        *heard = (*heard).max(mark);
+    }
+
+    pub fn heard(&self, node: NodeID) -> Option<RealmTime> {
+        self.heard.get(&node).copied()
+    }
+
+    pub(crate) fn all_heard(&self) -> &BTreeMap<NodeID, RealmTime> {
+        &self.heard
+    }
+
+    // The least watermark of the configuration's voters, once all of them
+    // have been heard from.
+    pub fn global(&self, config: &Config) -> Option<RealmTime> {
+        let marks = config.voters().map(|n| self.heard(n));
+        marks.collect::<Option<Vec<_>>>()?.into_iter().min()
+    }
+}
+
+impl GossipPayload for Gossip {
+    fn kind(&self) -> &'static str {
+        "watermarks"
+    }
+
+    fn provide(&self) -> Result<Vec<u8>> {
+        Ok(rmp_serde::to_vec(&self.heard)?)
+    }
+
+    fn merge(&mut self, _: NodeID, bytes: &[u8]) -> Result<()> {
+        let heard: BTreeMap<NodeID, RealmTime> = rmp_serde::from_slice(bytes)?;
+        for (node, mark) in heard {
+            self.note(node, mark);
+        }
+        Ok(())
+    }
+}
+
+impl Transaction {
+    // Start running, if the transaction is waiting in Seq and the global
+    // watermark has passed it.
+    pub fn release(&mut self, global: RealmTime) -> bool {
+        if self.state != State::Seq || self.time > global {
+            return false;
+        }
+        let thunk = &self.thunk;
+        let ctx = vec![thunk.vals.clone()];
+        let eval = Evaluator::staged(&thunk.expr, ctx, thunk.foot.clone(), STAGES)
+            .with_float_mode(FloatMode::Deterministic);
+        self.set_state(State::Run {
+            eval: Box::new(eval),
+        });
+        true
+    }
+}
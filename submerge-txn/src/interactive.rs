@@ -0,0 +1,100 @@
+// Interactive transactions. A one-shot thunk has to say what it reads and
+// writes before it's replicated, which a client that decides what to write
+// from what it reads can't. An OpenTxn instead lets the client read, as of
+// a snapshot of everything its node has released, and stage writes, over
+// as many requests as it likes, and then commits them as a thunk whose
+// footprint is the paths it read and wrote.
+//
+// Nothing is replicated until the commit, so the writes land at the
+// commit's time, not the snapshot's, and another transaction may have
+// written what it read in between. So the thunk carries each version it
+// read, and when it runs, on every node, it checks each is still the
+// latest before its time. If one isn't, the writes would rest on a stale
+// read: the thunk removes its versions, writing nothing, and its handle
+// gets a conflict error for the client to retry on a new snapshot. The
+// store is the same on every node at that point, so every node makes the
+// same call.
+//
+// A read of a path the transaction has already written sees the staged
+// value. The snapshot is pinned against collection while it's open (see
+// gc.rs).
+
+use crate::{Record, Store, Thunk};
+use std::collections::BTreeMap;
+use submerge_base::{Error, Result};
+use submerge_eval::Footprint;
+use submerge_lang::{Expr, Path, Tab, Vals};
+use submerge_net::RealmTime;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OpenTxn {
+    snapshot: RealmTime,
+    // The version of each path read, as of the snapshot.
+    seen: BTreeMap<Path, Record>,
+    // The values staged for each path written.
+    puts: BTreeMap<Path, Vals>,
+}
+
+impl OpenTxn {
+    pub fn new(snapshot: RealmTime) -> OpenTxn {
+        OpenTxn {
+            snapshot,
+            seen: BTreeMap::new(),
+            puts: BTreeMap::new(),
+        }
+    }
+
+    pub fn snapshot(&self) -> RealmTime {
+        self.snapshot
+    }
+
+    pub fn read(&mut self, store: &dyn Store, path: Path) -> Result<Record> {
+        if let Some(vals) = self.puts.get(&path) {
+            return Ok(Record::Resolved(vals.clone()));
+        }
+        let record = store.get(path.clone(), self.snapshot)?;
+        self.seen.entry(path).or_insert(record.clone());
+        Ok(record)
+    }
+
+    pub fn write(&mut self, path: Path, vals: Vals) {
+        self.puts.insert(path, vals);
+    }
+
+    // The thunk that checks the reads and makes the writes.
+    pub fn into_thunk(self) -> Thunk {
+        let foot = Footprint {
+            reads: self.seen.keys().cloned().collect(),
+            writes: self.puts.keys().cloned().collect(),
+        };
+        let mut thunk = Thunk::new(Tab::default(), Expr::Pass, foot);
+        thunk.seen = self.seen;
+        thunk.puts = self.puts;
+        thunk
+    }
+}
+
+impl Thunk {
+    // A conflict error if something the thunk read has been written since,
+    // as of its time.
+    pub(crate) fn validate(&self, store: &dyn Store, time: RealmTime) -> Result<()> {
+        for (path, seen) in &self.seen {
+            let latest = store.before(path.clone(), time)?;
+            if latest.as_ref().map(|(_, r)| r) != Some(seen) {
+                return Err(Error::conflict(format!(
+                    "{} changed between its read and {:?}",
+                    path, time
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // What the thunk writes at a path, given its result.
+    pub(crate) fn written(&self, path: &Path, result: &Record) -> Record {
+        match self.puts.get(path) {
+            Some(vals) => Record::Resolved(vals.clone()),
+            None => result.clone(),
+        }
+    }
+}
@@ -0,0 +1,226 @@
+// Joining, the last part of step 8 of the protocol. A fresh node sends
+// Ready to the nodes it knows of, once a period, until it's caught up. The
+// configuration's proposer, its highest-numbered voter, proposes a
+// successor with the ready nodes added, by the same reconfiguration as
+// removing failed ones. In the successor they're joining: they're sent
+// every Put from its start on, but their acks don't count.
+//
+// Once the successor is decided its proposer sends each joining node a
+// CatchUp: the configuration, every record in its store as of the seal,
+// and the watermarks it's heard. The records must all be resolved, so the
+// proposer waits until it has run everything up to the seal. The joining
+// node installs them at the seal, takes its own watermark up to it, and
+// tells every node it has CaughtUp, after which they count its acks.
+
+use crate::{send, Config, Gossip, NodeSet, Record, Store, Watermark};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use submerge_base::{err, Result};
+use submerge_lang::Path;
+use submerge_net::{Duration, Msg, Node, NodeID, NodeTime, RealmTime, SpecificMsg};
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CatchUp {
+    config: Config,
+    records: Vec<(Path, Record)>,
+    heard: BTreeMap<NodeID, RealmTime>,
+}
+
+impl CatchUp {
+    // The state of a node that's run everything up to the seal.
+    pub fn new(config: &Config, store: &dyn Store, gossip: &Gossip) -> Result<CatchUp> {
+        let seal = config
+            .sealed
+            .ok_or_else(|| err("the first epoch has no seal"))?;
+        let mut records = Vec::new();
+        for path in store.paths(seal)? {
+            let record = store.get(path.clone(), seal)?;
+            if let Record::Unresolved(_) = record {
+                return Err(err(format!("{:?} is not resolved yet", path)));
+            }
+            records.push((path, record));
+        }
+        Ok(CatchUp {
+            config: config.clone(),
+            records,
+            heard: gossip.all_heard().clone(),
+        })
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        Ok(rmp_serde::to_vec(self)?)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<CatchUp> {
+        Ok(rmp_serde::from_slice(bytes)?)
+    }
+
+    // Copy the state into a fresh node's.
+    pub fn install(
+        &self,
+        store: &dyn Store,
+        gossip: &mut Gossip,
+        local: &mut Watermark,
+    ) -> Result<()> {
+        let seal = self
+            .config
+            .sealed
+            .ok_or_else(|| err("the first epoch has no seal"))?;
+        for (path, record) in &self.records {
+            store.put(path.clone(), seal, record.clone())?;
+        }
+        for (node, mark) in &self.heard {
+            gossip.note(*node, *mark);
+        }
+        local.advance(seal);
+        Ok(())
+    }
+}
+
+// A configuration member's side of joining.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Join {
+    // The nodes heard to be ready.
+    ready: NodeSet,
+}
+
+impl Join {
+    pub fn new() -> Join {
+        Join::default()
+    }
+
+    pub fn hear(&mut self, msg: &Msg) {
+        if *msg.specific() == SpecificMsg::Ready {
+            self.ready.insert(msg.src());
+        }
+    }
+
+    // The nodes to propose as the configuration's successor, if this node
+    // is its proposer and there are ready nodes outside it.
+    pub fn expansion(&self, me: NodeID, config: &Config) -> Option<NodeSet> {
+        if config.proposer() != Some(me) || self.ready.is_subset(&config.nodes) {
+            return None;
+        }
+        Some(config.nodes.union(&self.ready).copied().collect())
+    }
+
+    // Send the joining nodes the state to catch up from, if this node is
+    // the configuration's proposer.
+    pub fn catch_up(
+        &self,
+        net: &mut Node,
+        me: NodeID,
+        config: &Config,
+        store: &dyn Store,
+        gossip: &Gossip,
+        now: NodeTime,
+    ) -> Result<()> {
+        if config.proposer() != Some(me) || config.joining.is_empty() {
+            return Ok(());
+        }
+        let bytes = CatchUp::new(config, store, gossip)?.encode()?;
+        let msg_time = RealmTime::new(now, me, 0);
+        for node in config.joining.iter() {
+            let catch_up = SpecificMsg::CatchUp(bytes.clone());
+            send(
+                net,
+                Msg::new(me, *node, config.start, msg_time, 0, catch_up),
+            )?;
+        }
+        Ok(())
+    }
+
+    // Count a joining node's acks, if it's said it's caught up.
+    pub fn caught_up(&mut self, msg: &Msg, config: &mut Config) -> bool {
+        if *msg.specific() != SpecificMsg::CaughtUp || msg.txn_time() != config.start {
+            return false;
+        }
+        self.ready.remove(&msg.src());
+        config.caught_up(msg.src())
+    }
+}
+
+// A fresh node's side of joining.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Joining {
+    // The nodes it knows of, to ask to join.
+    peers: NodeSet,
+    period: Duration,
+    last: Option<NodeTime>,
+    config: Option<Config>,
+}
+
+impl Joining {
+    pub fn new(peers: NodeSet, period: Duration) -> Joining {
+        Joining {
+            peers,
+            period,
+            last: None,
+            config: None,
+        }
+    }
+
+    // Say it's ready, if a period has passed since it last did and it
+    // hasn't caught up yet.
+    pub fn poll(&mut self, net: &mut Node, me: NodeID, now: NodeTime) -> Result<()> {
+        if self.config.is_some() || self.last.is_some_and(|t| now < t + self.period) {
+            return Ok(());
+        }
+        self.last = Some(now);
+        let msg_time = RealmTime::new(now, me, 0);
+        for peer in self.peers.iter().filter(|n| **n != me) {
+            let ready = SpecificMsg::Ready;
+            send(net, Msg::new(me, *peer, msg_time, msg_time, 0, ready))?;
+        }
+        Ok(())
+    }
+
+    // Install the state a CatchUp carries, once, and tell every node of its
+    // configuration.
+    pub fn recv(
+        &mut self,
+        net: &mut Node,
+        now: NodeTime,
+        msg: &Msg,
+        store: &dyn Store,
+        gossip: &mut Gossip,
+        local: &mut Watermark,
+    ) -> Result<()> {
+        let SpecificMsg::CatchUp(bytes) = msg.specific() else {
+            return Ok(());
+        };
+        if self.config.is_some() {
+            return Ok(());
+        }
+        let me = msg.dst();
+        let catch_up = CatchUp::decode(bytes)?;
+        let mut config = catch_up.config.clone();
+        if !config.joining.contains(&me) {
+            return Err(err(format!(
+                "{:?} isn't joining epoch {}",
+                me, config.epoch
+            )));
+        }
+        catch_up.install(store, gossip, local)?;
+        let msg_time = RealmTime::new(now, me, 0);
+        for node in config.nodes.iter().filter(|n| **n != me) {
+            let caught_up = SpecificMsg::CaughtUp;
+            send(
+                net,
+                Msg::new(me, *node, config.start, msg_time, 0, caught_up),
+            )?;
+        }
+        config.caught_up(me);
+        self.config = Some(config);
+        Ok(())
+    }
+
+    // The configuration it's caught up in.
+    pub fn config(&self) -> Option<&Config> {
+        self.config.as_ref()
+    }
+}
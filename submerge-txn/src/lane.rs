@@ -0,0 +1,111 @@
+// Execution lanes, step 7 of the protocol. Released transactions are split
+// into a fixed number of lanes, each run in order by its own worker, such
+// that transactions in different lanes never conflict (see
+// Footprint::conflicts): those that do conflict serialize in timestamp
+// order in one lane, and the rest run in parallel.
+//
+// A transaction goes into the lane of whatever earlier ones it conflicts
+// with, or by the lane policy if it conflicts with none. One that
+// conflicts with several lanes is held until all but one of them have
+// finished the transactions it conflicts with, as is any later one that
+// conflicts with a held one, so that it can't overtake it.
+
+use crate::Transaction;
+use std::collections::VecDeque;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum LanePolicy {
+    // The lane with the fewest transactions in it.
+    Shortest,
+    // Each lane in turn.
+    RoundRobin,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Lanes {
+    lanes: Vec<VecDeque<Transaction>>,
+    policy: LanePolicy,
+    // Transactions waiting for lanes to drain, in time order.
+    held: Vec<Transaction>,
+    // The lane the round-robin policy picks next.
+    turn: usize,
+}
+
+impl Lanes {
+    // There's always at least one lane.
+    pub fn new(count: usize, policy: LanePolicy) -> Lanes {
+        Lanes {
+            lanes: vec![VecDeque::new(); count.max(1)],
+            policy,
+            held: Vec::new(),
+            turn: 0,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.lanes.len()
+    }
+
+    // Assign a released transaction to a lane, or hold it. Transactions
+    // must be assigned in time order.
+    pub fn assign(&mut self, txn: Transaction) {
+        match self.lane_for(&txn) {
+            Some(lane) => self.lanes[lane].push_back(txn),
+            None => self.held.push(txn),
+        }
+    }
+
+    // The lane a transaction can go in now, if any.
+    fn lane_for(&mut self, txn: &Transaction) -> Option<usize> {
+        if self.held.iter().any(|h| txn.depends_on(h)) {
+            return None;
+        }
+        let conflicts = |lane: &VecDeque<Transaction>| lane.iter().any(|t| txn.depends_on(t));
+        let lanes = (0..self.lanes.len()).filter(|i| conflicts(&self.lanes[*i]));
+        match lanes.collect::<Vec<_>>()[..] {
+            [] => Some(self.pick()),
+            [lane] => Some(lane),
+            _ => None,
+        }
+    }
+
+    fn pick(&mut self) -> usize {
+        match self.policy {
+            LanePolicy::Shortest => (0..self.lanes.len())
+                .min_by_key(|i| self.lanes[*i].len())
+                .unwrap_or(0),
+            LanePolicy::RoundRobin => {
+                let lane = self.turn;
+                self.turn = (self.turn + 1) % self.lanes.len();
+                lane
+            }
+        }
+    }
+
+    // The transaction a lane's worker runs now.
+    pub fn front(&mut self, lane: usize) -> Option<&mut Transaction> {
+        self.lanes.get_mut(lane)?.front_mut()
+    }
+
+    // Take the transaction at the front of a lane, once it's finished, and
+    // place any held transactions that can go now.
+    pub fn finish(&mut self, lane: usize) -> Option<Transaction> {
+        let txn = self.lanes.get_mut(lane)?.pop_front()?;
+        for held in std::mem::take(&mut self.held) {
+            self.assign(held);
+        }
+        Some(txn)
+    }
+
+    pub fn len(&self, lane: usize) -> usize {
+        self.lanes.get(lane).map_or(0, VecDeque::len)
+    }
+
+    pub fn held(&self) -> usize {
+        self.held.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.held.is_empty() && self.lanes.iter().all(VecDeque::is_empty)
+    }
+}
@@ -56,10 +56,9 @@
 
 // Note this usage is a _very_ minimal use of paxos and so doesn't
 // need anything fancy (logs, persistent leaders, view change phases,
-// etc.) it's just single-decree paxos as implemented for example in
+// etc.) it's just single-decree paxos, in paxos.rs, adapted from
 // stateright's example folder:
 // https://github.com/stateright/stateright/blob/master/examples/paxos.rs
-// (this is probably the best one to use!)
 //
 // Note that while reconfiguration only needs a quorum (of the old
 // participants) to succeed, if it reconfigures to a new state with a
@@ -118,40 +117,211 @@
 use std::collections::{BTreeMap, BTreeSet};
 
 use serde::{Deserialize, Serialize};
-use submerge_eval::Evaluator;
-use submerge_lang::{Expr, Path, Tab, Vals};
-use submerge_net::{Duration, NodeID, NodeTime, RealmTime};
+use submerge_eval::{Evaluator, Footprint, Outcome, RowStore};
+use submerge_lang::{Col, Expr, Fault, Path, Tab, Vals, Word};
+use submerge_net::{Duration, Msg, Node, NodeID, NodeTime, RealmTime};
 
-use submerge_base::Error;
+use submerge_base::{err, telemetry, Error};
+use tracing::{debug, Span};
 
 pub type NodeSet = BTreeSet<NodeID>;
 
+mod abort;
+mod admit;
+mod chunk;
+mod client;
+mod dedup;
+mod deps;
+mod diag;
+mod diverge;
+mod epochs;
+mod escalate;
+mod exec;
+mod gc;
+mod gossip;
+mod interactive;
+mod join;
+mod lane;
 mod paxos;
+mod put;
+mod quorum;
+mod reconfig;
+mod recover;
+mod resync;
+mod session;
+mod sim;
+mod skew;
+mod status;
+mod store;
+mod wait;
+mod watermark;
+
+pub use admit::{Admission, ClassStats, Limit, Overload, Priority};
+pub use chunk::{Reassembly, PART_SIZE};
+pub use client::{TxnClient, TxnHandle};
+pub use dedup::Dedup;
+pub use deps::dependencies;
+pub use diag::{Blocked, Blocker};
+pub use diverge::{Divergence, Hashes};
+pub use epochs::ConfigLog;
+pub use escalate::{Escalation, Probation};
+pub use exec::Queue;
+pub use gc::Gc;
+pub use gossip::Gossip;
+pub use interactive::OpenTxn;
+pub use join::{CatchUp, Join, Joining};
+pub use lane::{LanePolicy, Lanes};
+pub use paxos::{Ballot, Paxos, PaxosMsg};
+pub use quorum::Arrivals;
+pub use reconfig::{seal, Reconfig};
+pub use recover::Recovery;
+pub use resync::{Resync, Resynced, Segment, Status};
+pub use session::{ReadHandle, Session};
+pub use sim::Sim;
+pub use skew::Skews;
+pub use status::{Latencies, Percentiles, StateCounts, TxnStatus};
+pub use store::MemStore;
+pub use wait::Waits;
+pub use watermark::Watermark;
+
+// The most stages a transaction's thunk may run in.
+const STAGES: usize = 16;
+
+// Take a send refused by a full queue (see submerge-net's queue.rs) for the
+// network losing the message: whatever's lost is sent again.
+pub(crate) fn lossy(sent: Result<(), Error>) -> Result<(), Error> {
+    match sent {
+        Err(e) if e.is_overloaded() => {
+            debug!(target: telemetry::TARGET, error = %e, "message dropped");
+            Ok(())
+        }
+        sent => sent,
+    }
+}
+
+pub(crate) fn send(net: &mut Node, msg: Msg) -> Result<(), Error> {
+    lossy(net.send_msg(msg))
+}
 
 #[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 pub struct Config {
     // The set of nodes to replicate transactions to
     nodes: NodeSet,
+    // The nodes that joined in this configuration and are still catching
+    // up, whose acks don't count yet
+    joining: NodeSet,
     // The number of times a replication-write should be retried
     retries: i64,
-    // The number of milliseconds each attempt waits for an ack
-    // before assuming it failed and retrying or giving up
+    // The time each attempt waits for an ack before assuming it failed
+    // and retrying or giving up
     timeout: Duration,
+    // How to go on retrying once the retries have run out, before giving
+    // up on a node
+    escalation: Escalation,
+    // The most thunk bytes a single Put carries; larger thunks are split
+    // into parts of this size
+    part_size: usize,
+    // The number of voters' acks that replicate a transaction, if not all
+    // of them
+    write_quorum: Option<usize>,
+    // The number of reconfigurations before this one
+    epoch: i64,
+    // The last timestamp of the previous configuration, if there was one
+    sealed: Option<RealmTime>,
+    // The first timestamp of this configuration
+    start: RealmTime,
 }
 
-// A footprint indicates the set of keys that a given txn will read and write.
-// The writes will all get thunks written to them pointing to this txn. The
-// reads will all be considered dependencies of the txn, which it cannot execute
-// before the resolution-of. Both reads and writes can indicate "an entire
-// column", "an entire table", or "an entire database" (and may choose to do so
-// if there is no way to statically bound the read or write set), though doing
-// so will create an increasingly significant synchronization barrier, inhibiting
-// parallel execution through it.
+impl Config {
+    // The first configuration, in epoch 0, starting at time 0.
+    pub fn new(nodes: NodeSet, retries: i64, timeout: Duration) -> Config {
+        Config {
+            nodes,
+            joining: NodeSet::new(),
+            retries,
+            timeout,
+            escalation: Escalation::default(),
+            part_size: PART_SIZE,
+            write_quorum: None,
+            epoch: 0,
+            sealed: None,
+            start: RealmTime::new(NodeTime::from_micros(0), NodeID(0), 0),
+        }
+    }
 
-#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
-struct Footprint {
-    reads: Vec<Path>,
-    writes: Vec<Path>,
+    // Escalate a node's unacked Puts before timing them out (see
+    // escalate.rs).
+    pub fn with_escalation(mut self, escalation: Escalation) -> Config {
+        self.escalation = escalation;
+        self
+    }
+
+    pub fn escalation(&self) -> Escalation {
+        self.escalation
+    }
+
+    // How long to wait for an ack to a node's count'th attempt.
+    pub fn wait(&self, count: i64) -> Duration {
+        self.escalation.wait(self.timeout, self.retries, count)
+    }
+
+    pub fn with_part_size(mut self, bytes: usize) -> Config {
+        self.part_size = bytes.max(1);
+        self
+    }
+
+    // Count a transaction as replicated once w voters have acked it,
+    // rather than all of them (see quorum.rs).
+    pub fn with_write_quorum(mut self, w: usize) -> Config {
+        self.write_quorum = Some(w.max(1));
+        self
+    }
+
+    pub fn write_quorum(&self) -> Option<usize> {
+        self.write_quorum
+    }
+
+    // The number of voters' acks that replicate a transaction.
+    pub fn quorum(&self) -> usize {
+        let voters = self.voters().count();
+        self.write_quorum.map_or(voters, |w| w.min(voters))
+    }
+
+    pub fn nodes(&self) -> &NodeSet {
+        &self.nodes
+    }
+
+    pub fn joining(&self) -> &NodeSet {
+        &self.joining
+    }
+
+    // The nodes whose acks count: those that aren't still joining.
+    pub fn voters(&self) -> impl Iterator<Item = NodeID> + '_ {
+        self.nodes.difference(&self.joining).copied()
+    }
+
+    // The node that proposes expanding the configuration, and brings new
+    // nodes up to date: the highest-numbered voter.
+    pub fn proposer(&self) -> Option<NodeID> {
+        self.voters().last()
+    }
+
+    // Count a joining node's acks, now that it's caught up.
+    pub fn caught_up(&mut self, node: NodeID) -> bool {
+        self.joining.remove(&node)
+    }
+
+    pub fn epoch(&self) -> i64 {
+        self.epoch
+    }
+
+    pub fn sealed(&self) -> Option<RealmTime> {
+        self.sealed
+    }
+
+    pub fn start(&self) -> RealmTime {
+        self.start
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
@@ -159,6 +329,23 @@ pub struct Thunk {
     vals: Tab,
     expr: Expr,
     foot: Footprint,
+    // For an interactive transaction (see interactive.rs), the versions it
+    // read, which must still be the latest when it runs, and the values it
+    // writes instead of its result.
+    seen: BTreeMap<Path, Record>,
+    puts: BTreeMap<Path, Vals>,
+}
+
+impl Thunk {
+    pub fn new(vals: Tab, expr: Expr, foot: Footprint) -> Thunk {
+        Thunk {
+            vals,
+            expr,
+            foot,
+            seen: BTreeMap::new(),
+            puts: BTreeMap::new(),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
@@ -172,33 +359,145 @@ pub struct Transaction {
 pub enum Record {
     Resolved(Vals),
     Unresolved(Thunk),
+    // A thunk whose evaluation faulted. Faults are deterministic, so every
+    // replica records the same one, and readers see it as the result.
+    Faulted(Fault),
+}
+
+impl From<Outcome> for Record {
+    fn from(outcome: Outcome) -> Record {
+        match outcome {
+            Ok(vals) => Record::Resolved(vals),
+            Err(fault) => Record::Faulted(fault),
+        }
+    }
 }
 
+// A multiversion store: every path has a version of its record at the time
+// of each transaction that writes it.
 pub trait Store {
-    fn get(&self, path: Path) -> Result<Record, Error>;
-    fn put(&self, path: Path, record: Record) -> Result<(), Error>;
-    fn abort(&self, path: Path) -> Result<(), Error>;
+    // The latest version at or before a time.
+    fn get(&self, path: Path, time: RealmTime) -> Result<Record, Error>;
+    // The latest version strictly before a time, and its time: what a
+    // transaction at that time reads, past its own writes.
+    fn before(&self, path: Path, time: RealmTime) -> Result<Option<(RealmTime, Record)>, Error>;
+    fn put(&self, path: Path, time: RealmTime, record: Record) -> Result<(), Error>;
+    // Remove the version at a time, whose transaction was killed.
+    fn abort(&self, path: Path, time: RealmTime) -> Result<(), Error>;
+    // Every path with a version at or before a time, for copying to a new
+    // node.
+    fn paths(&self, time: RealmTime) -> Result<Vec<Path>, Error>;
+    // Every version at or after a time, for resynchronizing a node.
+    fn since(&self, time: RealmTime) -> Result<Vec<(Path, RealmTime, Record)>, Error>;
+    // Remove the versions no read at or after a time can see: each path's
+    // resolved versions before its latest at or before the time. Returns
+    // how many went.
+    fn prune(&self, time: RealmTime) -> Result<usize, Error>;
+}
+
+// A Store as a hybrid scan's row store, as of some time: a table's
+// unflushed rows are the record at its path, a record of columns. A thunk
+// that hasn't run yet has no rows to give, so reading it is an error, as
+// is reading a fault.
+pub struct StoreRows<'a, S: ?Sized>(pub &'a S, pub RealmTime);
+
+impl<S: Store + ?Sized> RowStore for StoreRows<'_, S> {
+    fn rows(&self, table: &Word) -> Result<Tab, Error> {
+        match self.0.get(Path(vec![table.clone()]), self.1)? {
+            Record::Resolved(vals) => {
+                let col = |(w, v): (Word, Vals)| match v {
+                    Vals::Rich(c) => Col::new(w, c.vals().clone())
+                        .with_form(c.form())
+                        .with_unit(c.unit()),
+                    v => Col::new(w, v),
+                };
+                Tab::new(vals.unzip()?.into_iter().map(col).collect())
+            }
+            Record::Unresolved(_) => Err(err(format!("{} is not resolved yet", table))),
+            Record::Faulted(fault) => Err(err(format!("{} faulted: {}", table, fault.msg))),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 enum PutTry {
     Nothing,
+    // The number of Puts sent so far, and when the last one was.
     Attempt { count: i64, time: NodeTime },
     Success,
+    // Every attempt went unacked.
+    TimedOut,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
 enum State {
-    // Replicating thunks into nodes
-    Put { nodes: BTreeMap<NodeID, PutTry> },
+    // Replicating thunks into nodes, which needn't include the joining ones
+    Put {
+        nodes: BTreeMap<NodeID, PutTry>,
+        joining: NodeSet,
+        // The number of parts the thunk is sent in, once it's been sent,
+        // and the parts each node has acked, if there's more than one
+        parts: u32,
+        acked: BTreeMap<NodeID, BTreeSet<u32>>,
+        // The voters' acks that replicate it
+        quorum: usize,
+    },
+    // Calling the transaction off, by removing its thunk from the nodes
+    // it was replicated to
+    Abort {
+        nodes: BTreeMap<NodeID, PutTry>,
+        joining: NodeSet,
+    },
+    // Called off before it was released
+    Aborted,
     // Replication failed with some set of timed-out nodes
-    Err { nodes: NodeSet },
+    Err {
+        nodes: NodeSet,
+    },
     // Waiting for the watermark to advance past us
     Seq,
     // Running the transaction thunk
-    Run { eval: Evaluator },
+    Run {
+        eval: Box<Evaluator>,
+    },
     // Complete
     End,
 }
 
-impl Transaction {}
+impl State {
+    fn name(&self) -> &'static str {
+        match self {
+            State::Put { .. } => "put",
+            State::Abort { .. } => "abort",
+            State::Aborted => "aborted",
+            State::Err { .. } => "err",
+            State::Seq => "seq",
+            State::Run { .. } => "run",
+            State::End => "end",
+        }
+    }
+}
+
+impl Transaction {
+    pub fn time(&self) -> RealmTime {
+        self.time
+    }
+
+    fn span(&self, op: &'static str, node: NodeID) -> Span {
+        telemetry::txn_span(op, &node, &self.time)
+    }
+
+    // All state changes go through here so they're traced uniformly.
+    fn set_state(&mut self, state: State) {
+        debug!(
+            target: telemetry::TARGET,
+            from = self.state.name(),
+            to = state.name(),
+            "txn state transition"
+        );
+        self.state = state;
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -1,11 +1,18 @@
-// This file is adapted from stateright's examples directory. It's the simplest
-// implementation I could find of single-decree paxos.
+// This file was adapted from stateright's examples directory, which had the
+// simplest implementation I could find of single-decree paxos:
 //
 //  https://github.com/stateright/stateright/blob/master/examples/paxos.rs
 //
+// It now stands alone: a Paxos is a plain state machine over the messages it
+// sends and receives through submerge-net, on whatever clock its caller
+// gives it, so a test can drive a cluster of them through a simulated
+// network, reordering and dropping messages as it likes.
+//
 // Submerge uses paxos strictly to manage the cluster reconfiguration protocol,
 // which happens only when a transaction fails to fully replicate (indicating
-// a node is down).
+// a node is down). Each round of reconfiguration is its own instance, named
+// by a RealmTime that its messages carry as their txn_time, and decides one
+// proposal of any type the caller likes.
 
 //! This is an implementation of Single Decree Paxos, an algorithm that ensures a cluster of
 //! servers never disagrees on a value.
@@ -55,36 +62,28 @@
 //! are proposed in parallel, but the following implementation follows this approach to match how
 //! the algorithm is typically described.
 
-use serde::{Deserialize, Serialize};
-use stateright::actor::register::{RegisterActor, RegisterMsg, RegisterMsg::*};
-use stateright::actor::{majority, model_peers, Actor, ActorModel, Id, Network, Out};
-use stateright::report::WriteReporter;
-use stateright::semantics::register::Register;
-use stateright::semantics::LinearizabilityTester;
-use stateright::util::{HashableHashMap, HashableHashSet};
-use stateright::{Checker, Expectation, Model, UniformChooser};
-use std::borrow::Cow;
-use std::time::Duration;
+use crate::{send, NodeSet};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use submerge_base::Result;
+use submerge_net::{Data, Msg, Node, NodeID, NodeTime, RealmTime, SpecificMsg};
 
-type Round = u32;
-type Ballot = (Round, Id);
-type Proposal = (RequestId, Id, Value);
-type RequestId = u64;
-type Value = char;
+// A leadership term: a round number, and the leader, to break ties.
+pub type Ballot = (i64, NodeID);
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
-enum PaxosMsg {
+pub enum PaxosMsg<P> {
     Prepare {
         ballot: Ballot,
     },
     Prepared {
         ballot: Ballot,
-        last_accepted: Option<(Ballot, Proposal)>,
+        last_accepted: Option<(Ballot, P)>,
     },
 
     Accept {
         ballot: Ballot,
-        proposal: Proposal,
+        proposal: P,
     },
     Accepted {
         ballot: Ballot,
@@ -92,427 +91,205 @@ enum PaxosMsg {
 
     Decided {
         ballot: Ballot,
-        proposal: Proposal,
+        proposal: P,
     },
 }
 use PaxosMsg::*;
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
-struct PaxosState {
+pub struct Paxos<P> {
+    me: NodeID,
+    // Every participant, this one included.
+    nodes: NodeSet,
+    instance: RealmTime,
+    // The number of messages sent, for their sequence numbers.
+    sent: i64,
+
     // shared state
     ballot: Ballot,
 
     // leader state
-    proposal: Option<Proposal>,
-    prepares: HashableHashMap<Id, Option<(Ballot, Proposal)>>,
-    accepts: HashableHashSet<Id>,
+    proposal: Option<P>,
+    prepares: BTreeMap<NodeID, Option<(Ballot, P)>>,
+    accepts: BTreeSet<NodeID>,
 
     // acceptor state
-    accepted: Option<(Ballot, Proposal)>,
-    is_decided: bool,
+    accepted: Option<(Ballot, P)>,
+    decided: bool,
 }
 
-#[derive(Clone)]
-struct PaxosActor {
-    peer_ids: Vec<Id>,
-}
+impl<P: Data + Serialize + DeserializeOwned> Paxos<P> {
+    pub fn new(me: NodeID, nodes: NodeSet, instance: RealmTime) -> Paxos<P> {
+        Paxos {
+            me,
+            nodes,
+            instance,
+            sent: 0,
+            ballot: (0, NodeID(0)),
+            proposal: None,
+            prepares: BTreeMap::new(),
+            accepts: BTreeSet::new(),
+            accepted: None,
+            decided: false,
+        }
+    }
 
-impl Actor for PaxosActor {
-    type Msg = RegisterMsg<RequestId, Value, PaxosMsg>;
-    type State = PaxosState;
-    type Timer = ();
+    // The proposal the participants decided on, once this one knows it.
+    pub fn decided(&self) -> Option<&P> {
+        match &self.accepted {
+            Some((_, p)) if self.decided => Some(p),
+            _ => None,
+        }
+    }
 
-    fn name(&self) -> String {
-        "Paxos Server".to_owned()
+    fn majority(&self) -> usize {
+        self.nodes.len() / 2 + 1
     }
 
-    fn on_start(&self, _id: Id, _o: &mut Out<Self>) -> Self::State {
-        PaxosState {
-            // shared state
-            ballot: (0, Id::from(0)),
+    fn broadcast(&mut self, net: &mut Node, now: NodeTime, msg: &PaxosMsg<P>) -> Result<()> {
+        let peers = self.nodes.iter().filter(|n| **n != self.me);
+        for peer in peers.copied().collect::<Vec<_>>() {
+            self.send(net, now, peer, msg)?;
+        }
+        Ok(())
+    }
 
-            // leader state
-            proposal: None,
-            prepares: Default::default(),
-            accepts: Default::default(),
+    fn send(
+        &mut self,
+        net: &mut Node,
+        now: NodeTime,
+        dst: NodeID,
+        msg: &PaxosMsg<P>,
+    ) -> Result<()> {
+        let msg_time = RealmTime::new(now, self.me, self.sent);
+        let paxos = SpecificMsg::Paxos(rmp_serde::to_vec(msg)?);
+        let msg = Msg::new(self.me, dst, self.instance, msg_time, self.sent, paxos);
+        self.sent += 1;
+        send(net, msg)
+    }
 
-            // acceptor state
-            accepted: None,
-            is_decided: false,
+    // Start a new term, led by this participant, to propose a value. If
+    // the term gets nowhere, say because its messages were lost or a later
+    // term overtook it, proposing again starts another, later one. Once a
+    // value is decided this does nothing.
+    pub fn propose(&mut self, net: &mut Node, now: NodeTime, value: P) -> Result<()> {
+        if self.decided {
+            return Ok(());
         }
+        self.proposal = Some(value);
+        self.prepares = BTreeMap::new();
+        self.accepts = BTreeSet::new();
+
+        // Simulate `Prepare` self-send.
+        self.ballot = (self.ballot.0 + 1, self.me);
+        // Simulate `Prepared` self-send.
+        self.prepares.insert(self.me, self.accepted.clone());
+
+        let ballot = self.ballot;
+        self.broadcast(net, now, &Prepare { ballot })?;
+        self.check_prepared(net, now)
     }
 
-    fn on_msg(
-        &self,
-        id: Id,
-        state: &mut Cow<Self::State>,
-        src: Id,
-        msg: Self::Msg,
-        o: &mut Out<Self>,
-    ) {
-        if state.is_decided {
-            if let Get(request_id) = msg {
-                // While it's tempting to `o.send(src, GetOk(request_id, None))` for undecided,
-                // we don't know if a value was decided elsewhere and the delivery is pending. Our
-                // solution is to not reply in this case, but a more useful choice might be
-                // to broadcast to the other actors and let them reply to the originator, or query
-                // the other actors and reply based on that.
-                let (_b, (_req_id, _src, value)) =
-                    state.accepted.expect("decided but lacks accepted state");
-                o.send(src, GetOk(request_id, value));
-            };
-            return;
+    // Handle a message from another participant of this instance; any other
+    // message is ignored.
+    pub fn recv(&mut self, net: &mut Node, now: NodeTime, msg: &Msg) -> Result<()> {
+        let SpecificMsg::Paxos(bytes) = msg.specific() else {
+            return Ok(());
+        };
+        let src = msg.src();
+        if msg.txn_time() != self.instance || !self.nodes.contains(&src) {
+            return Ok(());
         }
-
-        match msg {
-            Put(request_id, value) if state.proposal.is_none() => {
-                let state = state.to_mut();
-                state.proposal = Some((request_id, src, value));
-                state.prepares = Default::default();
-                state.accepts = Default::default();
-
-                // Simulate `Prepare` self-send.
-                state.ballot = (state.ballot.0 + 1, id);
-                // Simulate `Prepared` self-send.
-                state.prepares.insert(id, state.accepted);
-
-                o.broadcast(
-                    &self.peer_ids,
-                    &Internal(Prepare {
-                        ballot: state.ballot,
-                    }),
-                );
+        let msg = rmp_serde::from_slice(bytes)?;
+        if let Some((ballot, proposal)) = self.accepted.clone().filter(|_| self.decided) {
+            // A term that's still trying after the decision, perhaps because
+            // it never heard of it, is told the decision.
+            if matches!(msg, Prepare { .. } | Accept { .. }) {
+                self.send(net, now, src, &Decided { ballot, proposal })?;
             }
-            Internal(Prepare { ballot }) if state.ballot < ballot => {
-                state.to_mut().ballot = ballot;
-                o.send(
-                    src,
-                    Internal(Prepared {
-                        ballot,
-                        last_accepted: state.accepted,
-                    }),
-                );
+            return Ok(());
+        }
+        match msg {
+            Prepare { ballot } if self.ballot < ballot => {
+                self.ballot = ballot;
+                let last_accepted = self.accepted.clone();
+                let reply = Prepared {
+                    ballot,
+                    last_accepted,
+                };
+                self.send(net, now, src, &reply)?;
             }
-            Internal(Prepared {
+            Prepared {
                 ballot,
                 last_accepted,
-            }) if ballot == state.ballot => {
-                let state = state.to_mut();
-                state.prepares.insert(src, last_accepted);
-                if state.prepares.len() == majority(self.peer_ids.len() + 1) {
-                    // This stage is best understood as "leadership handoff," in which this term's
-                    // leader needs to ensure it does not contradict a decision (a quorum of
-                    // accepts) from a previous term. Here's how:
-                    //
-                    // 1. To start this term, the leader first "locked" the older terms from
-                    //    additional accepts via the `Prepare` messages.
-                    // 2. If the servers reached a decision in a previous term, then the observed
-                    //    prepare quorum is guaranteed to contain that accepted proposal, and we
-                    //    have to favor that one.
-                    // 3. We only have to drive the proposal accepted by the most recent term
-                    //    because the leaders of the previous terms would have done the same before
-                    //    asking their peers to accept proposals (so any proposals accepted by
-                    //    earlier terms either match the most recently accepted proposal or are
-                    //    guaranteed to have never reached quorum and so are safe to ignore).
-                    // 4. If no proposals were previously accepted, the leader is safe to proceed
-                    //    with the one from the client.
-                    let proposal = state
-                        .prepares
-                        .values()
-                        .max()
-                        .unwrap()
-                        .map(|(_b, p)| p)
-                        .unwrap_or_else(|| state.proposal.expect("proposal expected")); // See `Put` case above.
-                    state.proposal = Some(proposal);
-
-                    // Simulate `Accept` self-send.
-                    state.accepted = Some((ballot, proposal));
-                    // Simulate `Accepted` self-send.
-                    state.accepts.insert(id);
-
-                    o.broadcast(&self.peer_ids, &Internal(Accept { ballot, proposal }));
-                }
+            } if ballot == self.ballot && self.proposal.is_some() => {
+                self.prepares.insert(src, last_accepted);
+                self.check_prepared(net, now)?;
             }
-            Internal(Accept { ballot, proposal }) if state.ballot <= ballot => {
-                let state = state.to_mut();
-                state.ballot = ballot;
-                state.accepted = Some((ballot, proposal));
-                o.send(src, Internal(Accepted { ballot }));
+            Accept { ballot, proposal } if self.ballot <= ballot => {
+                self.ballot = ballot;
+                self.accepted = Some((ballot, proposal));
+                self.send(net, now, src, &Accepted { ballot })?;
             }
-            Internal(Accepted { ballot }) if ballot == state.ballot => {
-                let state = state.to_mut();
-                state.accepts.insert(src);
-                if state.accepts.len() == majority(self.peer_ids.len() + 1) {
-                    state.is_decided = true;
-                    let proposal = state.proposal.expect("proposal expected"); // See `Put` case above.
-                    o.broadcast(&self.peer_ids, &Internal(Decided { ballot, proposal }));
-                    let (request_id, requester_id, _) = proposal;
-                    o.send(requester_id, PutOk(request_id));
-                }
+            Accepted { ballot } if ballot == self.ballot && self.proposal.is_some() => {
+                self.accepts.insert(src);
+                self.check_accepted(net, now)?;
             }
-            Internal(Decided { ballot, proposal }) => {
-                let state = state.to_mut();
-                state.ballot = ballot;
-                state.accepted = Some((ballot, proposal));
-                state.is_decided = true;
+            Decided { ballot, proposal } => {
+                self.ballot = ballot;
+                self.accepted = Some((ballot, proposal));
+                self.decided = true;
             }
             _ => {}
         }
+        Ok(())
     }
-}
-
-#[derive(Clone)]
-struct PaxosModelCfg {
-    client_count: usize,
-    server_count: usize,
-    network: Network<<PaxosActor as Actor>::Msg>,
-}
-
-impl PaxosModelCfg {
-    fn into_model(
-        self,
-    ) -> ActorModel<RegisterActor<PaxosActor>, Self, LinearizabilityTester<Id, Register<Value>>>
-    {
-        ActorModel::new(
-            self.clone(),
-            LinearizabilityTester::new(Register(Value::default())),
-        )
-        .actors((0..self.server_count).map(|i| {
-            RegisterActor::Server(PaxosActor {
-                peer_ids: model_peers(i, self.server_count),
-            })
-        }))
-        .actors((0..self.client_count).map(|_| RegisterActor::Client {
-            put_count: 1,
-            server_count: self.server_count,
-        }))
-        .init_network(self.network)
-        .property(Expectation::Always, "linearizable", |_, state| {
-            state.history.serialized_history().is_some()
-        })
-        .property(Expectation::Sometimes, "value chosen", |_, state| {
-            for env in state.network.iter_deliverable() {
-                if let RegisterMsg::GetOk(_req_id, value) = env.msg {
-                    if *value != Value::default() {
-                        return true;
-                    }
-                }
-            }
-            false
-        })
-        .record_msg_in(RegisterMsg::record_returns)
-        .record_msg_out(RegisterMsg::record_invocations)
-    }
-}
-
-#[cfg(test)]
-#[test]
-fn can_model_paxos() {
-    use stateright::actor::ActorModelAction::Deliver;
-
-    // BFS
-    let checker = PaxosModelCfg {
-        client_count: 2,
-        server_count: 3,
-        network: Network::new_unordered_nonduplicating([]),
-    }
-    .into_model()
-    .checker()
-    .spawn_bfs()
-    .join();
-    checker.assert_properties();
-    #[rustfmt::skip]
-    checker.assert_discovery("value chosen", vec![
-        Deliver { src: 4.into(), dst: 1.into(), msg: Put(4, 'B') },
-        Deliver { src: 1.into(), dst: 0.into(), msg: Internal(Prepare { ballot: (1, 1.into()) }) },
-        Deliver { src: 0.into(), dst: 1.into(), msg: Internal(Prepared { ballot: (1, 1.into()), last_accepted: None }) },
-        Deliver { src: 1.into(), dst: 2.into(), msg: Internal(Accept { ballot: (1, 1.into()), proposal: (4, 4.into(), 'B') }) },
-        Deliver { src: 2.into(), dst: 1.into(), msg: Internal(Accepted { ballot: (1, 1.into()) }) },
-        Deliver { src: 1.into(), dst: 4.into(), msg: PutOk(4) },
-        Deliver { src: 1.into(), dst: 2.into(), msg: Internal(Decided { ballot: (1, 1.into()), proposal: (4, 4.into(), 'B') }) },
-        Deliver { src: 4.into(), dst: 2.into(), msg: Get(8) }
-    ]);
-    assert_eq!(checker.unique_state_count(), 16_668);
-
-    // DFS
-    let checker = PaxosModelCfg {
-        client_count: 2,
-        server_count: 3,
-        network: Network::new_unordered_nonduplicating([]),
-    }
-    .into_model()
-    .checker()
-    .spawn_dfs()
-    .join();
-    checker.assert_properties();
-    #[rustfmt::skip]
-    checker.assert_discovery("value chosen", vec![
-        Deliver { src: 4.into(), dst: 1.into(), msg: Put(4, 'B') },
-        Deliver { src: 1.into(), dst: 0.into(), msg: Internal(Prepare { ballot: (1, 1.into()) }) },
-        Deliver { src: 0.into(), dst: 1.into(), msg: Internal(Prepared { ballot: (1, 1.into()), last_accepted: None }) },
-        Deliver { src: 1.into(), dst: 2.into(), msg: Internal(Accept { ballot: (1, 1.into()), proposal: (4, 4.into(), 'B') }) },
-        Deliver { src: 2.into(), dst: 1.into(), msg: Internal(Accepted { ballot: (1, 1.into()) }) },
-        Deliver { src: 1.into(), dst: 4.into(), msg: PutOk(4) },
-        Deliver { src: 1.into(), dst: 2.into(), msg: Internal(Decided { ballot: (1, 1.into()), proposal: (4, 4.into(), 'B') }) },
-        Deliver { src: 4.into(), dst: 2.into(), msg: Get(8) }
-    ]);
-    assert_eq!(checker.unique_state_count(), 16_668);
-}
-
-fn main() -> Result<(), pico_args::Error> {
-    use stateright::actor::spawn;
-    use std::net::{Ipv4Addr, SocketAddrV4};
 
-    env_logger::init_from_env(env_logger::Env::default().default_filter_or("info")); // `RUST_LOG=${LEVEL}` env variable to override
-
-    let mut args = pico_args::Arguments::from_env();
-    match args.subcommand()?.as_deref() {
-        Some("check-bfs") | Some("check") => {
-            let client_count = args.opt_free_from_str()?.unwrap_or(2);
-            let network = args
-                .opt_free_from_str()?
-                .unwrap_or(Network::new_unordered_nonduplicating([]));
-            println!(
-                "Model checking Single Decree Paxos with {} clients.",
-                client_count
-            );
-            PaxosModelCfg {
-                client_count,
-                server_count: 3,
-                network,
-            }
-            .into_model()
-            .checker()
-            .threads(num_cpus::get())
-            .spawn_bfs()
-            .report(&mut WriteReporter::new(&mut std::io::stdout()));
-        }
-        Some("check-dfs") => {
-            let client_count = args.opt_free_from_str()?.unwrap_or(2);
-            let network = args
-                .opt_free_from_str()?
-                .unwrap_or(Network::new_unordered_nonduplicating([]));
-            println!(
-                "Model checking Single Decree Paxos with {} clients.",
-                client_count
-            );
-            PaxosModelCfg {
-                client_count,
-                server_count: 3,
-                network,
-            }
-            .into_model()
-            .checker()
-            .threads(num_cpus::get())
-            .spawn_dfs()
-            .report(&mut WriteReporter::new(&mut std::io::stdout()));
-        }
-        Some("check-simulation") => {
-            let client_count = args.opt_free_from_str()?.unwrap_or(2);
-            let network = args
-                .opt_free_from_str()?
-                .unwrap_or(Network::new_unordered_nonduplicating([]));
-            println!(
-                "Model checking Single Decree Paxos with {} clients.",
-                client_count
-            );
-            PaxosModelCfg {
-                client_count,
-                server_count: 3,
-                network,
-            }
-            .into_model()
-            .checker()
-            .threads(num_cpus::get())
-            .timeout(Duration::from_secs(10))
-            .spawn_simulation(0, UniformChooser)
-            .report(&mut WriteReporter::new(&mut std::io::stdout()));
+    fn check_prepared(&mut self, net: &mut Node, now: NodeTime) -> Result<()> {
+        if self.prepares.len() != self.majority() {
+            return Ok(());
         }
-        Some("explore") => {
-            let client_count = args.opt_free_from_str()?.unwrap_or(2);
-            let address = args
-                .opt_free_from_str()?
-                .unwrap_or("localhost:3000".to_string());
-            let network = args
-                .opt_free_from_str()?
-                .unwrap_or(Network::new_unordered_nonduplicating([]));
-            println!(
-                "Exploring state space for Single Decree Paxos with {} clients on {}.",
-                client_count, address
-            );
-            PaxosModelCfg {
-                client_count,
-                server_count: 3,
-                network,
-            }
-            .into_model()
-            .checker()
-            .threads(num_cpus::get())
-            .serve(address);
-        }
-        Some("spawn") => {
-            let port = 3000;
-
-            println!("  A set of servers that implement Single Decree Paxos.");
-            println!("  You can monitor and interact using tcpdump and netcat.");
-            println!("  Use `tcpdump -D` if you see error `lo0: No such device exists`.");
-            println!("Examples:");
-            println!("$ sudo tcpdump -i lo0 -s 0 -nnX");
-            println!("$ nc -u localhost {}", port);
-            println!(
-                "{}",
-                serde_json::to_string(&RegisterMsg::Put::<RequestId, Value, ()>(1, 'X')).unwrap()
-            );
-            println!(
-                "{}",
-                serde_json::to_string(&RegisterMsg::Get::<RequestId, Value, ()>(2)).unwrap()
-            );
-            println!();
+        // This stage is best understood as "leadership handoff," in which this term's
+        // leader needs to ensure it does not contradict a decision (a quorum of
+        // accepts) from a previous term. Here's how:
+        //
+        // 1. To start this term, the leader first "locked" the older terms from
+        //    additional accepts via the `Prepare` messages.
+        // 2. If the servers reached a decision in a previous term, then the observed
+        //    prepare quorum is guaranteed to contain that accepted proposal, and we
+        //    have to favor that one.
+        // 3. We only have to drive the proposal accepted by the most recent term
+        //    because the leaders of the previous terms would have done the same before
+        //    asking their peers to accept proposals (so any proposals accepted by
+        //    earlier terms either match the most recently accepted proposal or are
+        //    guaranteed to have never reached quorum and so are safe to ignore).
+        // 4. If no proposals were previously accepted, the leader is safe to proceed
+        //    with the one from the caller.
+        let last = self.prepares.values().flatten().max_by_key(|(b, _)| *b);
+        let proposal = match last {
+            Some((_, p)) => p.clone(),
+            None => self.proposal.clone().expect("proposal expected"), // See `propose`.
+        };
+        self.proposal = Some(proposal.clone());
+
+        // Simulate `Accept` self-send.
+        let ballot = self.ballot;
+        self.accepted = Some((ballot, proposal.clone()));
+        // Simulate `Accepted` self-send.
+        self.accepts.insert(self.me);
+
+        self.broadcast(net, now, &Accept { ballot, proposal })?;
+        self.check_accepted(net, now)
+    }
 
-            // WARNING: Omits `ordered_reliable_link` to keep the message
-            //          protocol simple for `nc`.
-            let id0 = Id::from(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port));
-            let id1 = Id::from(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port + 1));
-            let id2 = Id::from(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port + 2));
-            spawn(
-                serde_json::to_vec,
-                |bytes| serde_json::from_slice(bytes),
-                vec![
-                    (
-                        id0,
-                        PaxosActor {
-                            peer_ids: vec![id1, id2],
-                        },
-                    ),
-                    (
-                        id1,
-                        PaxosActor {
-                            peer_ids: vec![id0, id2],
-                        },
-                    ),
-                    (
-                        id2,
-                        PaxosActor {
-                            peer_ids: vec![id0, id1],
-                        },
-                    ),
-                ],
-            )
-            .unwrap();
-        }
-        _ => {
-            println!("USAGE:");
-            println!("  ./paxos check-dfs [CLIENT_COUNT] [NETWORK]");
-            println!("  ./paxos check-bfs [CLIENT_COUNT] [NETWORK]");
-            println!("  ./paxos check-simulation [CLIENT_COUNT] [NETWORK]");
-            println!("  ./paxos explore [CLIENT_COUNT] [ADDRESS] [NETWORK]");
-            println!("  ./paxos spawn");
-            println!(
-                "NETWORK: {}",
-                Network::<<PaxosActor as Actor>::Msg>::names().join(" | ")
-            );
+    fn check_accepted(&mut self, net: &mut Node, now: NodeTime) -> Result<()> {
+        if self.accepts.len() != self.majority() {
+            return Ok(());
         }
+        self.decided = true;
+        let ballot = self.ballot;
+        let proposal = self.proposal.clone().expect("proposal expected"); // See `propose`.
+        self.accepted = Some((ballot, proposal.clone()));
+        self.broadcast(net, now, &Decided { ballot, proposal })
     }
-
-    Ok(())
 }
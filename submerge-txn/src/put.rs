@@ -0,0 +1,224 @@
+// Replication, step 3 of the protocol. A transaction starts in State::Put
+// with a PutTry per node of the configuration, and its coordinator sends
+// every node a Put carrying the serialized thunk. A node that acks is done.
+// One that hasn't acked within the timeout is sent the Put again, up to the
+// configuration's retries, and after that it has timed out. Nodes still
+// joining get the Puts too, but aren't waited for.
+//
+// Once every node has acked, the transaction waits for the watermark
+// (State::Seq). If a node times out, the transaction still waits for the
+// others to ack or time out too, and then moves to State::Err with every
+// node that timed out, for reconfiguration to exclude. A late ack from a
+// node that timed out still counts, if it comes before then.
+//
+// A thunk larger than the configuration's part size is sent in PutParts
+// instead, each acked separately (see chunk.rs): a retry resends only the
+// parts a node hasn't acked yet, and once it's acked all of them it's done.
+//
+// In quorum mode the transaction counts as replicated once the quorum has
+// acked, while it goes on sending to the rest (see quorum.rs); it only
+// fails if too few ack for that.
+//
+// A transaction called off while it's in Put is aborted, and poll() and
+// ack() do that from then on (see abort.rs).
+//
+// poll() runs on the coordinator's clock and ack() on the acks that come
+// back; neither blocks. An ack for another transaction, from a node outside
+// the configuration, or after the transaction has left Put is ignored, as
+// is a node's second ack.
+
+use crate::{send, Config, NodeSet, PutTry, State, Thunk, Transaction};
+use std::collections::BTreeMap;
+use submerge_base::{telemetry, Result};
+use submerge_net::{Msg, Node, NodeID, NodeTime, RealmTime, SpecificMsg};
+use tracing::error;
+
+impl Thunk {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        Ok(rmp_serde::to_vec(self)?)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Thunk> {
+        Ok(rmp_serde::from_slice(bytes)?)
+    }
+}
+
+impl Transaction {
+    pub fn new(time: RealmTime, thunk: Thunk, config: &Config) -> Transaction {
+        let nodes = config.nodes.iter().map(|n| (*n, PutTry::Nothing));
+        Transaction {
+            time,
+            thunk,
+            state: State::Put {
+                nodes: nodes.collect(),
+                joining: config.joining.clone(),
+                parts: 0,
+                acked: BTreeMap::new(),
+                quorum: config.quorum(),
+            },
+        }
+    }
+
+    // Send the Puts that are due at now: the first attempts, and retries of
+    // attempts that have timed out. The coordinator calls this at least once
+    // per timeout while the transaction is in Put.
+    pub fn poll(
+        &mut self,
+        net: &mut Node,
+        me: NodeID,
+        config: &Config,
+        now: NodeTime,
+    ) -> Result<()> {
+        if let State::Abort { .. } = self.state {
+            return self.poll_abort(net, me, config, now);
+        }
+        let _span = self.span("put", me).entered();
+        let State::Put {
+            nodes,
+            parts,
+            acked,
+            ..
+        } = &mut self.state
+        else {
+            return Ok(());
+        };
+        let mut bytes = None;
+        for (node, tried) in nodes.iter_mut() {
+            let Some(count) = tried.due(config, now) else {
+                continue;
+            };
+            let bytes = match &bytes {
+                Some(b) => b,
+                None => bytes.insert(self.thunk.encode()?),
+            };
+            let msg_time = RealmTime::new(now, me, count);
+            let chunks = bytes.chunks(config.part_size);
+            *parts = chunks.len() as u32;
+            let put = match *parts {
+                1 => vec![SpecificMsg::Put(bytes.clone())],
+                _ => chunks
+                    .enumerate()
+                    .map(|(i, c)| (i as u32, c))
+                    .filter(|(i, _)| !acked.get(node).is_some_and(|a| a.contains(i)))
+                    .map(|(part, c)| SpecificMsg::PutPart {
+                        part,
+                        parts: *parts,
+                        bytes: c.to_vec(),
+                    })
+                    .collect(),
+            };
+            for put in put {
+                send(net, Msg::new(me, *node, self.time, msg_time, count, put))?;
+            }
+        }
+        self.settle();
+        Ok(())
+    }
+
+    pub fn ack(&mut self, msg: &Msg) {
+        if msg.txn_time() != self.time {
+            return;
+        }
+        if let State::Abort { .. } = self.state {
+            return self.aborted(msg);
+        }
+        let State::Put {
+            nodes,
+            parts,
+            acked,
+            ..
+        } = &mut self.state
+        else {
+            return;
+        };
+        let Some(tried) = nodes.get_mut(&msg.src()) else {
+            return;
+        };
+        match msg.specific() {
+            SpecificMsg::Ack => *tried = PutTry::Success,
+            SpecificMsg::AckPart(part) if *part < *parts && *tried != PutTry::Success => {
+                let got = acked.entry(msg.src()).or_default();
+                got.insert(*part);
+                if got.len() == *parts as usize {
+                    acked.remove(&msg.src());
+                    *tried = PutTry::Success;
+                }
+            }
+            _ => return,
+        }
+        self.settle();
+    }
+
+    // Whether enough nodes have acked it for it to count as replicated,
+    // while it's still in Put: only before every node has, in quorum mode.
+    pub fn quorate(&self) -> bool {
+        let State::Put {
+            nodes,
+            joining,
+            quorum,
+            ..
+        } = &self.state
+        else {
+            return false;
+        };
+        let counted = nodes.iter().filter(|(n, _)| !joining.contains(n));
+        counted.filter(|(_, t)| **t == PutTry::Success).count() >= *quorum
+    }
+
+    // Leave Put once no node that counts has an attempt outstanding.
+    fn settle(&mut self) {
+        let State::Put { nodes, joining, .. } = &self.state else {
+            return;
+        };
+        let Some(timed_out) = settled(nodes, joining) else {
+            return;
+        };
+        if timed_out.is_empty() {
+            self.set_state(State::Seq);
+        } else if self.quorate() {
+            error!(
+                target: telemetry::TARGET,
+                txn_time = ?self.time,
+                nodes = ?timed_out,
+                "txn replicated to a quorum, but these nodes will stall without it"
+            );
+            self.set_state(State::Seq);
+        } else {
+            self.set_state(State::Err { nodes: timed_out });
+        }
+    }
+}
+
+impl PutTry {
+    // The number of attempts so far, if another is due at now, which it's
+    // recorded as; None if not, or if the attempts have run out, which
+    // it's recorded as too.
+    pub(crate) fn due(&mut self, config: &Config, now: NodeTime) -> Option<i64> {
+        let count = match *self {
+            PutTry::Nothing => 0,
+            PutTry::Attempt { count, time } if now >= time + config.wait(count) => count,
+            _ => return None,
+        };
+        if count > config.retries + config.escalation.rounds {
+            *self = PutTry::TimedOut;
+            return None;
+        }
+        *self = PutTry::Attempt {
+            count: count + 1,
+            time: now,
+        };
+        Some(count)
+    }
+}
+
+// The nodes that timed out, once none that counts has an attempt
+// outstanding.
+pub(crate) fn settled(nodes: &BTreeMap<NodeID, PutTry>, joining: &NodeSet) -> Option<NodeSet> {
+    let counted = nodes.iter().filter(|(n, _)| !joining.contains(n));
+    let waiting = |t: &PutTry| matches!(t, PutTry::Nothing | PutTry::Attempt { .. });
+    if counted.clone().any(|(_, t)| waiting(t)) {
+        return None;
+    }
+    let timed_out = counted.filter(|(_, t)| **t == PutTry::TimedOut);
+    Some(timed_out.map(|(n, _)| *n).collect())
+}
@@ -0,0 +1,98 @@
+// Quorum writes, an optional mode for larger clusters where waiting on the
+// slowest replica for every transaction costs too much. With a write quorum
+// of w in the configuration, a transaction counts as replicated once w
+// voters have acked it: its coordinator marks it replicated in its local
+// watermark then, so the global watermark can pass it, and keeps sending
+// it to the other nodes in the background (see put.rs). The default,
+// without a quorum, is full replication, where every voter must ack.
+//
+// That lets the global watermark pass a transaction some node doesn't have
+// yet, so a node mustn't release up to the global watermark blindly: it
+// would run the transactions after the missing one without it, and
+// diverge. Each coordinator gives its transactions consecutive event
+// numbers, and its watermark's event is the last one it's begun at or
+// before that time, so a node knows which of a coordinator's transactions
+// the watermark covers and which of them it has, and releases only up to
+// the last one before its first gap. A lagging node so stalls until the
+// background Puts reach it, rather than diverging; one that never gets
+// them has to be resynchronized.
+//
+// Every voter must still gossip its watermark for the global watermark to
+// move, so a quorum tolerates slow and lossy replicas, but a node that's
+// down still needs a reconfiguration to remove it.
+
+use std::collections::BTreeMap;
+use submerge_net::{NodeID, RealmTime};
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct Arrived {
+    // The first event that hasn't arrived, and the time of the one before.
+    next: i64,
+    last: Option<RealmTime>,
+    // The events that arrived after it.
+    ahead: BTreeMap<i64, RealmTime>,
+}
+
+// The transactions a node has received from each coordinator.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Arrivals {
+    coordinators: BTreeMap<NodeID, Arrived>,
+    // Whether each coordinator's gaps count from the first transaction
+    // recorded, rather than from its first event.
+    resumed: bool,
+}
+
+impl Arrivals {
+    pub fn new() -> Arrivals {
+        Arrivals::default()
+    }
+
+    // For a node recovering from a crash (see recover.rs), which can't tell
+    // what it missed before.
+    pub fn resumed() -> Arrivals {
+        Arrivals {
+            coordinators: BTreeMap::new(),
+            resumed: true,
+        }
+    }
+
+    // Note a transaction that's been installed, or aborted.
+    pub fn record(&mut self, time: RealmTime) {
+        let first = Arrived {
+            next: if self.resumed { time.event() } else { 0 },
+            ..Arrived::default()
+        };
+        let arrived = self.coordinators.entry(time.node()).or_insert(first);
+        if time.event() < arrived.next {
+            return;
+        }
+        arrived.ahead.insert(time.event(), time);
+        while let Some(t) = arrived.ahead.remove(&arrived.next) {
+            arrived.last = Some(t);
+            arrived.next += 1;
+        }
+    }
+
+    // The latest time the node may release through: the global watermark,
+    // unless some coordinator's watermark covers a transaction it doesn't
+    // have, in which case the last one from that coordinator before it.
+    // None if it mayn't release anything.
+    pub fn bound(
+        &self,
+        heard: &BTreeMap<NodeID, RealmTime>,
+        global: RealmTime,
+    ) -> Option<RealmTime> {
+        let mut bound = Some(global);
+        for (node, mark) in heard.iter().filter(|(n, m)| m.node() == **n) {
+            let arrived = self.coordinators.get(node);
+            if arrived.is_none() && self.resumed {
+                continue;
+            }
+            let (next, last) = arrived.map_or((0, None), |a| (a.next, a.last));
+            if next <= mark.event() {
+                bound = bound.min(last);
+            }
+        }
+        bound
+    }
+}
@@ -0,0 +1,128 @@
+// Reconfiguration, step 8 of the protocol. When a transaction's Put runs
+// out of retries on some nodes (State::Err), its coordinator proposes, by
+// paxos among the current configuration's nodes, a configuration without
+// them: the next epoch, sealed at the last timestamp of this one and
+// starting at a later one. The seal is the global watermark the proposer
+// knows of, so nothing any node has released is past it. Each epoch's
+// reconfiguration is its own paxos instance, named by the epoch's start,
+// and a quorum of the old nodes decides it; the failed nodes needn't take
+// part. New nodes join the same way, in a successor with more nodes (see
+// join.rs).
+//
+// Once a node learns the decision, seal() kills its transactions past the
+// seal that haven't started running, as they'll never finish replicating
+// in the old epoch, and gives them back. Their coordinators resubmit them
+// with new timestamps in the new epoch, from its start on.
+
+use crate::{Config, NodeSet, Paxos, State, Transaction};
+use std::collections::BTreeMap;
+use submerge_base::{err, Result};
+use submerge_net::{Msg, Node, NodeID, NodeTime, RealmTime};
+
+impl Config {
+    // The configuration after this one, of some nodes. Those that aren't
+    // in this one are joining, as are any still joining that stay.
+    pub fn successor(&self, nodes: NodeSet, seal: RealmTime, start: RealmTime) -> Config {
+        let new = nodes
+            .iter()
+            .filter(|n| !self.nodes.contains(n) || self.joining.contains(n));
+        Config {
+            joining: new.copied().collect(),
+            nodes,
+            epoch: self.epoch + 1,
+            sealed: Some(seal),
+            start,
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Reconfig {
+    paxos: Paxos<Config>,
+    proposal: Option<Config>,
+}
+
+impl Reconfig {
+    // Take part in deciding a configuration's successor, without proposing
+    // one.
+    pub fn new(me: NodeID, config: &Config) -> Reconfig {
+        Reconfig {
+            paxos: Paxos::new(me, config.nodes.clone(), config.start),
+            proposal: None,
+        }
+    }
+
+    // Propose a successor of some nodes, sealed at the global watermark:
+    // say, without those a Put timed out on.
+    pub fn propose(
+        &mut self,
+        net: &mut Node,
+        me: NodeID,
+        config: &Config,
+        nodes: NodeSet,
+        seal: RealmTime,
+        now: NodeTime,
+    ) -> Result<()> {
+        let start = RealmTime::new(now, me, 0);
+        if seal < config.start || start <= seal {
+            return Err(err(format!(
+                "can't seal epoch {} at {:?} and start the next at {:?}",
+                config.epoch, seal, start
+            )));
+        }
+        let next = config.successor(nodes, seal, start);
+        self.proposal = Some(next.clone());
+        self.paxos.propose(net, now, next)
+    }
+
+    // Propose again, in a later term, if the last one got nowhere.
+    pub fn retry(&mut self, net: &mut Node, now: NodeTime) -> Result<()> {
+        match self.proposal.clone() {
+            Some(next) => self.paxos.propose(net, now, next),
+            None => Ok(()),
+        }
+    }
+
+    pub fn recv(&mut self, net: &mut Node, now: NodeTime, msg: &Msg) -> Result<()> {
+        self.paxos.recv(net, now, msg)
+    }
+
+    // The configuration the old one's nodes decided on, which may not be
+    // the one this node proposed.
+    pub fn decided(&self) -> Option<&Config> {
+        self.paxos.decided()
+    }
+}
+
+// Move a node's transactions to a decided configuration: remove and give
+// back those past its seal that haven't started running.
+pub fn seal(txns: &mut BTreeMap<RealmTime, Transaction>, next: &Config) -> Vec<Transaction> {
+    let Some(seal) = next.sealed else {
+        return Vec::new();
+    };
+    let dead = |t: &Transaction| {
+        let killable = matches!(
+            t.state,
+            State::Put { .. } | State::Abort { .. } | State::Err { .. } | State::Seq
+        );
+        t.time > seal && killable
+    };
+    let times = txns.values().filter(|t| dead(t)).map(|t| t.time);
+    let times = times.collect::<Vec<_>>();
+    times.iter().filter_map(|t| txns.remove(t)).collect()
+}
+
+impl Transaction {
+    // The same thunk, as a new transaction at a new time in a new
+    // configuration.
+    pub fn resubmit(&self, time: RealmTime, config: &Config) -> Result<Transaction> {
+        if time < config.start {
+            return Err(err(format!(
+                "{:?} is before epoch {} starts",
+                time, config.epoch
+            )));
+        }
+        Ok(Transaction::new(time, self.thunk.clone(), config))
+    }
+}
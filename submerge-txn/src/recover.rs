@@ -0,0 +1,68 @@
+// Crash recovery. A node that restarts on the store it had before it
+// crashed rebuilds the rest of its state from it, rather than copying the
+// whole store from a peer (see resync.rs). What it needs is there: every
+// transaction it had installed and not yet run is an unresolved version at
+// its time, in each path the transaction writes, and every one it ran is a
+// result.
+//
+// Transactions run in time order, and in full replication none is
+// released before the node has it, so the latest result is as far as the
+// node has released: its execution queue starts from there, and a Put for
+// anything at or before it that a coordinator retries is ignored, as it
+// would have been. Each unresolved thunk goes back in the queue and the
+// dedup table, so its Puts are acked rather than installed again, and the
+// node acks it to its coordinator at once, since the ack may never have
+// gone out. Those it coordinated itself are begun again in its local
+// watermark and replicated again from the start, since it can't tell which
+// nodes have them; nodes that do ack the repeats without installing them.
+// Its next event number is past every one of its own in the store.
+//
+// The rest is lost: the handles waiting on the node's transactions, parts
+// of thunks it was still reassembling, which their coordinators don't
+// resend once acked, so they time out, and a thunk that writes nothing,
+// which isn't in the store at all. The node's clock must be past every
+// time it used before the crash, as it is for any restart. In quorum mode
+// the node can't tell which transactions it missed before the crash, so it
+// tracks each coordinator's gaps from the first of its transactions it has
+// after (see quorum.rs); one that might have missed some should be
+// resynchronized instead.
+
+use crate::{Record, Store, Thunk};
+use std::collections::BTreeMap;
+use submerge_base::Result;
+use submerge_net::{NodeID, NodeTime, RealmTime};
+
+// What a store says about the node that kept it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Recovery {
+    // The latest transaction that ran before the earliest that didn't.
+    pub released: Option<RealmTime>,
+    // The transactions installed but not run.
+    pub unresolved: BTreeMap<RealmTime, Thunk>,
+    // The latest event number of each coordinator's in the store.
+    pub events: BTreeMap<NodeID, i64>,
+}
+
+impl Recovery {
+    pub fn scan<S: Store + ?Sized>(store: &S) -> Result<Recovery> {
+        let start = RealmTime::new(NodeTime::from_micros(i64::MIN), NodeID(i64::MIN), i64::MIN);
+        let mut recovery = Recovery::default();
+        let mut resolved = Vec::new();
+        for (_, time, record) in store.since(start)? {
+            let event = recovery.events.entry(time.node()).or_insert(time.event());
+            *event = (*event).max(time.event());
+            match record {
+                Record::Unresolved(thunk) => {
+                    recovery.unresolved.insert(time, thunk);
+                }
+                Record::Resolved(_) | Record::Faulted(_) => resolved.push(time),
+            }
+        }
+        let first = recovery.unresolved.keys().next().copied();
+        let ran = resolved
+            .into_iter()
+            .filter(|t| first.is_none_or(|f| *t < f));
+        recovery.released = ran.max();
+        Ok(recovery)
+    }
+}
@@ -0,0 +1,215 @@
+// Resynchronization after a divergence (see diverge.rs). Once every voter
+// has reported its hash of the diverged transaction's result, the result
+// a majority of them got is the right one; if there's no majority, the
+// highest-numbered node's is, and the lower-numbered nodes copy from it. A
+// node that got a different result halts: it stops applying transactions
+// from the diverged one on, discards every version it has from there on,
+// and asks the highest-numbered node that got the right result for its
+// versions instead. It installs those and resumes, running whatever they
+// leave unresolved again.
+//
+// Halting and resuming are logged as errors, since they're the serious
+// events divergence is, and each resync is kept in the status history so
+// operators can see that it happened and where from.
+
+use crate::{lossy, send, Config, Divergence, Record, Store};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use submerge_base::{telemetry, Result};
+use submerge_lang::Path;
+use submerge_net::{Msg, Node, NodeID, NodeTime, RealmTime, SpecificMsg};
+use tracing::error;
+
+// The versions a node has from some time on.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Segment {
+    from: RealmTime,
+    versions: Vec<(Path, RealmTime, Record)>,
+}
+
+impl Segment {
+    pub fn new(store: &dyn Store, from: RealmTime) -> Result<Segment> {
+        let versions = store.since(from)?;
+        Ok(Segment { from, versions })
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        Ok(rmp_serde::to_vec(self)?)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Segment> {
+        Ok(rmp_serde::from_slice(bytes)?)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Status {
+    Running,
+    // Not applying transactions from a time on, until a segment arrives
+    // from the source.
+    Halted {
+        from: RealmTime,
+        source: NodeID,
+        since: NodeTime,
+    },
+}
+
+// A resync that's finished.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Resynced {
+    pub from: RealmTime,
+    pub source: NodeID,
+    pub halted: NodeTime,
+    pub resumed: NodeTime,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Resync {
+    status: Status,
+    history: Vec<Resynced>,
+}
+
+impl Default for Resync {
+    fn default() -> Resync {
+        Resync {
+            status: Status::Running,
+            history: Vec::new(),
+        }
+    }
+}
+
+// The right result of a diverged transaction, and the highest-numbered
+// voter that got it, once every voter has reported.
+fn authority(div: &Divergence, config: &Config) -> Option<(u64, NodeID)> {
+    let voters = config.voters().collect::<Vec<_>>();
+    let hashes = voters.iter().map(|n| div.hashes().get(n).copied());
+    let hashes = hashes.collect::<Option<Vec<_>>>()?;
+    let mut counts = BTreeMap::new();
+    for h in &hashes {
+        *counts.entry(*h).or_insert(0) += 1;
+    }
+    let majority = counts.iter().find(|(_, c)| **c * 2 > voters.len());
+    let hash = match majority {
+        Some((h, _)) => *h,
+        None => *hashes.last()?,
+    };
+    let source = voters.iter().zip(&hashes).rev().find(|(_, h)| **h == hash);
+    source.map(|(n, _)| (hash, *n))
+}
+
+impl Resync {
+    pub fn new() -> Resync {
+        Resync::default()
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    // Whether the node mustn't apply a transaction at a time.
+    pub fn halts(&self, txn: RealmTime) -> bool {
+        matches!(self.status, Status::Halted { from, .. } if txn >= from)
+    }
+
+    // The resyncs the node has finished, oldest first.
+    pub fn history(&self) -> &[Resynced] {
+        &self.history
+    }
+
+    // Halt, discard and ask for the segment from the diverged transaction
+    // on, if this node is in the minority. Returns whether it halted.
+    pub fn check(
+        &mut self,
+        net: &mut Node,
+        me: NodeID,
+        config: &Config,
+        div: &Divergence,
+        store: &dyn Store,
+        now: NodeTime,
+    ) -> Result<bool> {
+        if self.status != Status::Running {
+            return Ok(false);
+        }
+        let Some((hash, source)) = authority(div, config) else {
+            return Ok(false);
+        };
+        if div.hashes().get(&me) == Some(&hash) {
+            return Ok(false);
+        }
+        let from = div.txn();
+        error!(
+            target: telemetry::TARGET,
+            txn_time = ?from,
+            node = ?me,
+            source = ?source,
+            "txn results diverged here, halting to resync"
+        );
+        discard(store, from)?;
+        self.status = Status::Halted {
+            from,
+            source,
+            since: now,
+        };
+        let msg_time = RealmTime::new(now, me, 0);
+        send(
+            net,
+            Msg::new(me, source, from, msg_time, 0, SpecificMsg::Resync),
+        )?;
+        Ok(true)
+    }
+
+    // Answer another node's request for a segment.
+    pub fn serve(&self, net: &mut Node, store: &dyn Store, msg: &Msg, now: NodeTime) -> Result<()> {
+        if *msg.specific() != SpecificMsg::Resync {
+            return Ok(());
+        }
+        let bytes = Segment::new(store, msg.txn_time())?.encode()?;
+        let msg_time = RealmTime::new(now, msg.dst(), 0);
+        lossy(net.reply(msg, msg_time, SpecificMsg::Segment(bytes)))
+    }
+
+    // Install the segment the node halted for, and resume.
+    pub fn recv(&mut self, store: &dyn Store, msg: &Msg, now: NodeTime) -> Result<bool> {
+        let SpecificMsg::Segment(bytes) = msg.specific() else {
+            return Ok(false);
+        };
+        let Status::Halted {
+            from,
+            source,
+            since,
+        } = self.status
+        else {
+            return Ok(false);
+        };
+        let segment = Segment::decode(bytes)?;
+        if msg.src() != source || segment.from != from {
+            return Ok(false);
+        }
+        discard(store, from)?;
+        for (path, time, record) in segment.versions {
+            store.put(path, time, record)?;
+        }
+        error!(
+            target: telemetry::TARGET,
+            txn_time = ?from,
+            node = ?msg.dst(),
+            source = ?source,
+            "resynced, resuming"
+        );
+        self.status = Status::Running;
+        self.history.push(Resynced {
+            from,
+            source,
+            halted: since,
+            resumed: now,
+        });
+        Ok(true)
+    }
+}
+
+fn discard(store: &dyn Store, from: RealmTime) -> Result<()> {
+    for (path, time, _) in store.since(from)? {
+        store.abort(path, time)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,91 @@
+// Read-your-writes sessions. A client's writes are replicated and run on
+// every node, but a read on some node sees only what that node has
+// released, which can be behind a write the client has just seen commit
+// elsewhere, or even on the same node, for a moment after.
+//
+// A Session is a token the client carries from request to request: the
+// latest time of any transaction it's written. A read in the session
+// waits until the node has released through that time, then reads as of
+// everything released, so it sees every write the session made and any
+// later. The token is serializable, so a server can hand it to a remote
+// client with each answer and take it back with the next request.
+
+use crate::Record;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Condvar, Mutex};
+use submerge_base::{err, Result};
+use submerge_net::RealmTime;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    written: Option<RealmTime>,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session::default()
+    }
+
+    // Note a write the session made, at its transaction's time.
+    pub fn wrote(&mut self, time: RealmTime) {
+        self.written = self.written.max(Some(time));
+    }
+
+    pub fn written(&self) -> Option<RealmTime> {
+        self.written
+    }
+
+    // Whether a node that's released through a time has every write the
+    // session made.
+    pub fn covered(&self, released: Option<RealmTime>) -> bool {
+        released.is_some() && self.written <= released
+    }
+}
+
+type ReadSlot = Arc<(Mutex<Option<Result<Record>>>, Condvar)>;
+
+// A read's result, to come.
+#[derive(Debug, Default)]
+pub struct ReadHandle {
+    slot: ReadSlot,
+}
+
+impl ReadHandle {
+    pub(crate) fn new() -> ReadHandle {
+        ReadHandle::default()
+    }
+
+    // The same read, for the client to fill.
+    pub(crate) fn share(&self) -> ReadHandle {
+        ReadHandle {
+            slot: self.slot.clone(),
+        }
+    }
+
+    pub(crate) fn fill(&self, r: Result<Record>) {
+        let (result, done) = &*self.slot;
+        if let Ok(mut result) = result.lock() {
+            *result = Some(r);
+            done.notify_all();
+        }
+    }
+
+    // The result, if the read has been answered.
+    pub fn try_result(&self) -> Option<Result<Record>> {
+        let (result, _) = &*self.slot;
+        result.lock().ok()?.take()
+    }
+
+    // Wait for the read to be answered, while another thread drives the
+    // client.
+    pub fn await_result(self) -> Result<Record> {
+        let (result, done) = &*self.slot;
+        let mut result = result.lock().map_err(|_| err("read handle poisoned"))?;
+        loop {
+            match result.take() {
+                Some(r) => return r,
+                None => result = done.wait(result).map_err(|_| err("read handle poisoned"))?,
+            }
+        }
+    }
+}
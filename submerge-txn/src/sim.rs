@@ -0,0 +1,261 @@
+// Deterministic simulation. A Sim runs a TxnClient per node of a
+// configuration over a simulated network (see submerge-net's sim.rs), on a
+// virtual clock: each step moves the clock on a microsecond, delivers the
+// messages due by then, and ticks every node that's up. Each message sent
+// is delayed by a number of microseconds in a range, or dropped, as a
+// seeded generator picks, and messages to or from a node that's down are
+// lost; the network can be given other faults to inject, and partitioned,
+// through net(). A node's clock can be
+// set to run a fixed skew from the virtual one, and a node can be restarted
+// on its store, losing everything else (see recover.rs). A reconfiguration
+// decided outside the simulation is learned by every node that's up.
+//
+// Nothing depends on the real clock, thread scheduling or hash iteration
+// order, so a run is a function of its seed and the calls made on it: a
+// protocol bug a run finds happens again on every run with the same seed.
+// Every delivery is kept in the trace, for comparing runs.
+
+use crate::{Config, MemStore, NodeSet, ReadHandle, Session, Skews, Thunk, TxnClient, TxnHandle};
+use std::collections::BTreeMap;
+use submerge_base::{err, Result};
+use submerge_lang::Path;
+use submerge_net::{
+    Delivery, Detector, Duration, Latency, NodeID, NodeTime, SimNet, SimTransport, Transport,
+};
+
+pub struct Sim {
+    config: Config,
+    gossip: Duration,
+    skews: Skews,
+    detector: Option<Detector>,
+    clients: BTreeMap<NodeID, TxnClient<MemStore>>,
+    net: SimNet,
+    transports: BTreeMap<NodeID, SimTransport>,
+    down: NodeSet,
+    skew: BTreeMap<NodeID, Duration>,
+}
+
+impl Sim {
+    pub fn new(config: &Config, gossip: Duration, seed: u64) -> Sim {
+        let client = |id: &NodeID| {
+            let client = TxnClient::new(*id, config.clone(), MemStore::new(), gossip);
+            (*id, client)
+        };
+        let one = Duration::from_micros(1);
+        let net = SimNet::new(seed).with_latency(Latency::Uniform(one, one));
+        let transport = |id: &NodeID| (*id, net.transport(*id));
+        Sim {
+            config: config.clone(),
+            gossip,
+            skews: Skews::new(),
+            detector: None,
+            clients: config.nodes.iter().map(client).collect(),
+            transports: config.nodes.iter().map(transport).collect(),
+            net,
+            down: NodeSet::new(),
+            skew: BTreeMap::new(),
+        }
+    }
+
+    // Delay each message by between min and max microseconds.
+    pub fn with_delay(mut self, min: Duration, max: Duration) -> Sim {
+        self.net = self.net.with_latency(Latency::Uniform(min, max));
+        self
+    }
+
+    // Drop a message pct times in 100.
+    pub fn with_drops(mut self, pct: u64) -> Sim {
+        self.net = self.net.with_drops(pct);
+        self
+    }
+
+    // Deliver a message twice pct times in 100.
+    pub fn with_dups(mut self, pct: u64) -> Sim {
+        self.net = self.net.with_dups(pct);
+        self
+    }
+
+    // Hold a message back by a while pct times in 100.
+    pub fn with_reorder(mut self, pct: u64, by: Duration) -> Sim {
+        self.net = self.net.with_reorder(pct, by);
+        self
+    }
+
+    // Run a node's clock ahead of the virtual clock by skew, or behind if
+    // it's negative.
+    pub fn with_skew(mut self, node: NodeID, skew: Duration) -> Sim {
+        self.skew.insert(node, skew);
+        self
+    }
+
+    // Estimate and bound skew on every node.
+    pub fn with_skews(mut self, skews: Skews) -> Sim {
+        let clients = std::mem::take(&mut self.clients).into_iter();
+        self.clients = clients
+            .map(|(n, c)| (n, c.with_skews(skews.clone())))
+            .collect();
+        self.skews = skews;
+        self
+    }
+
+    // Tell slow peers from dead ones on every node.
+    pub fn with_detector(mut self, detector: Detector) -> Sim {
+        let clients = std::mem::take(&mut self.clients).into_iter();
+        self.clients = clients
+            .map(|(n, c)| (n, c.with_detector(detector.clone())))
+            .collect();
+        self.detector = Some(detector);
+        self
+    }
+
+    pub fn now(&self) -> NodeTime {
+        self.net.now()
+    }
+
+    pub fn trace(&self) -> Vec<Delivery> {
+        self.net.trace()
+    }
+
+    // The simulated network, to script faults on or partition.
+    pub fn net(&self) -> &SimNet {
+        &self.net
+    }
+
+    pub fn client(&self, node: NodeID) -> Option<&TxnClient<MemStore>> {
+        self.clients.get(&node)
+    }
+
+    // Stop a node: it's not ticked, and its messages are lost, until it's
+    // restored.
+    pub fn crash(&mut self, node: NodeID) {
+        self.down.insert(node);
+        self.net.down(node);
+    }
+
+    pub fn restore(&mut self, node: NodeID) {
+        self.down.remove(&node);
+        self.net.up(node);
+    }
+
+    // A node's clock.
+    fn clock(&self, node: NodeID) -> NodeTime {
+        self.net.now()
+            + self
+                .skew
+                .get(&node)
+                .copied()
+                .unwrap_or(Duration::from_micros(0))
+    }
+
+    // Restart a node on its store, as if it had crashed and come back.
+    pub fn restart(&mut self, node: NodeID) -> Result<()> {
+        let Some(client) = self.clients.remove(&node) else {
+            return Err(err(format!("{:?} isn't simulated", node)));
+        };
+        let (config, store) = (self.config.clone(), client.into_store());
+        let mut client =
+            TxnClient::new(node, config, store, self.gossip).with_skews(self.skews.clone());
+        if let Some(detector) = &self.detector {
+            client = client.with_detector(detector.clone());
+        }
+        let client = client.recover(self.clock(node))?;
+        self.clients.insert(node, client);
+        self.collect(node)?;
+        Ok(())
+    }
+
+    // Move every node that's up to a decided configuration.
+    pub fn reconfigure(&mut self, next: &Config) -> Result<()> {
+        self.config = next.clone();
+        let up = self.clients.keys().filter(|n| !self.down.contains(n));
+        for node in up.copied().collect::<Vec<_>>() {
+            let now = self.clock(node);
+            if let Some(client) = self.clients.get_mut(&node) {
+                client.reconfigure(next.clone(), now)?;
+            }
+            self.collect(node)?;
+        }
+        Ok(())
+    }
+
+    pub fn submit(&mut self, node: NodeID, thunk: Thunk) -> Result<TxnHandle> {
+        let now = self.clock(node);
+        let client = self
+            .clients
+            .get_mut(&node)
+            .ok_or_else(|| err(format!("{:?} isn't simulated", node)))?;
+        let handle = client.submit(thunk, now)?;
+        self.collect(node)?;
+        Ok(handle)
+    }
+
+    pub fn abort(&mut self, node: NodeID, handle: &TxnHandle) -> Result<bool> {
+        let now = self.clock(node);
+        let client = self
+            .clients
+            .get_mut(&node)
+            .ok_or_else(|| err(format!("{:?} isn't simulated", node)))?;
+        let aborting = client.abort(handle, now)?;
+        self.collect(node)?;
+        Ok(aborting)
+    }
+
+    pub fn read(&mut self, node: NodeID, session: &Session, path: Path) -> Result<ReadHandle> {
+        let client = self
+            .clients
+            .get_mut(&node)
+            .ok_or_else(|| err(format!("{:?} isn't simulated", node)))?;
+        Ok(client.read(session, path))
+    }
+
+    // Put a node's outgoing messages on the network.
+    fn collect(&mut self, src: NodeID) -> Result<()> {
+        let (Some(client), Some(transport)) =
+            (self.clients.get_mut(&src), self.transports.get_mut(&src))
+        else {
+            return Ok(());
+        };
+        while let Some((dst, buf)) = client.send_bytes() {
+            transport.send(dst, buf)?;
+        }
+        Ok(())
+    }
+
+    // Move the clock on a microsecond, deliver what's due, and tick every
+    // node that's up.
+    pub fn step(&mut self) -> Result<()> {
+        self.net.advance(Duration::from_micros(1));
+        let up = self.clients.keys().filter(|n| !self.down.contains(n));
+        let up = up.copied().collect::<Vec<_>>();
+        for node in &up {
+            let (Some(client), Some(transport)) =
+                (self.clients.get_mut(node), self.transports.get_mut(node))
+            else {
+                continue;
+            };
+            while let Some((src, buf)) = transport.recv()? {
+                client.recv_bytes(src, buf)?;
+            }
+        }
+        for node in up {
+            let now = self.clock(node);
+            if let Some(client) = self.clients.get_mut(&node) {
+                client.tick(now)?;
+            }
+            self.collect(node)?;
+        }
+        Ok(())
+    }
+
+    // Step until a condition holds, or a number of steps have passed.
+    // Returns whether it held.
+    pub fn run_until(&mut self, steps: usize, mut done: impl FnMut(&Sim) -> bool) -> Result<bool> {
+        for _ in 0..steps {
+            if done(self) {
+                return Ok(true);
+            }
+            self.step()?;
+        }
+        Ok(done(self))
+    }
+}
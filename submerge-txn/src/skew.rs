@@ -0,0 +1,131 @@
+// Clock skew. RealmTimes are taken from each node's own clock, and the
+// protocol is correct however far apart the clocks are, but the global
+// watermark is the least of the nodes' watermarks, so a node whose clock
+// lags holds up every release by as much as it lags.
+//
+// Every message carries the time its sender sent it at, and a node
+// compares that to its own clock when it takes the message in: the
+// difference is the sender's skew less the message's delay. The least
+// delayed of a peer's recent messages gives the best estimate, so the
+// estimate is the greatest difference in the window; positive means the
+// peer's clock is ahead.
+//
+// With a bound, a peer whose estimate goes beyond it is logged, once, until
+// it's back within. With slewing on as well, a node that finds peers ahead
+// of it by more than the bound moves the clock it takes RealmTimes from
+// forward to close the gap, running at most a tenth fast so that its
+// timestamps don't jump. It never moves the clock back, which would break
+// its watermark's promise; a node that's ahead is left for the others to
+// catch up to.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use submerge_base::telemetry;
+use submerge_net::{Duration, NodeID, NodeTime, RealmTime};
+use tracing::warn;
+
+// The messages kept for each peer.
+const WINDOW: usize = 64;
+
+// The slewed clock gains at most one microsecond for every SLEW.
+const SLEW: i64 = 10;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Skews {
+    samples: BTreeMap<NodeID, VecDeque<Duration>>,
+    bound: Option<Duration>,
+    slew: bool,
+    // The peers beyond the bound, which have been logged.
+    beyond: BTreeSet<NodeID>,
+    // How far the clock has been slewed, in microseconds, and the clock
+    // when it last was.
+    offset: i64,
+    slewed: Option<NodeTime>,
+}
+
+impl Skews {
+    pub fn new() -> Skews {
+        Skews::default()
+    }
+
+    pub fn with_bound(mut self, bound: Duration) -> Skews {
+        self.bound = Some(bound);
+        self
+    }
+
+    // Slew the clock forward to stay within the bound of the peers ahead.
+    pub fn with_slew(mut self) -> Skews {
+        self.slew = true;
+        self
+    }
+
+    pub fn bound(&self) -> Option<Duration> {
+        self.bound
+    }
+
+    // Take in a message a peer sent at sent, received at now.
+    pub fn record(&mut self, peer: NodeID, sent: RealmTime, now: NodeTime) {
+        let samples = self.samples.entry(peer).or_default();
+        if samples.len() == WINDOW {
+            samples.pop_front();
+        }
+        samples.push_back(Duration::from_micros(sent.time().micros() - now.micros()));
+        let (Some(bound), Some(skew)) = (self.bound, self.estimate(peer)) else {
+            return;
+        };
+        let beyond = skew.micros().abs() > bound.micros();
+        if beyond && self.beyond.insert(peer) {
+            warn!(
+                target: telemetry::TARGET,
+                node = ?peer,
+                skew = ?skew,
+                bound = ?bound,
+                "clock skew is beyond the bound"
+            );
+        } else if !beyond {
+            self.beyond.remove(&peer);
+        }
+    }
+
+    // How far ahead of this node's clock a peer's is.
+    pub fn estimate(&self, peer: NodeID) -> Option<Duration> {
+        self.samples.get(&peer)?.iter().max().copied()
+    }
+
+    pub fn all(&self) -> BTreeMap<NodeID, Duration> {
+        let peers = self.samples.keys();
+        peers
+            .filter_map(|p| Some((*p, self.estimate(*p)?)))
+            .collect()
+    }
+
+    // How far forward the clock has been slewed.
+    pub fn offset(&self) -> Duration {
+        Duration::from_micros(self.offset)
+    }
+
+    // Slew the clock towards the peers ahead, for the time since the last.
+    pub fn slew(&mut self, now: NodeTime) {
+        let last = self.slewed.unwrap_or(now);
+        self.slewed = Some(now);
+        let Some(bound) = self.bound.filter(|_| self.slew) else {
+            return;
+        };
+        let ahead = self.samples.keys().filter_map(|p| self.estimate(*p)).max();
+        let target = ahead.map_or(0, |a| a.micros() - bound.micros());
+        let gap = target - self.offset;
+        if gap <= 0 {
+            return;
+        }
+        let step = gap.min((now.micros() - last.micros()) / SLEW);
+        self.offset += step;
+        // Carry the time that didn't make a whole microsecond over.
+        if step < gap {
+            self.slewed = Some(last + Duration::from_micros(step * SLEW));
+        }
+    }
+
+    // The clock to take RealmTimes from.
+    pub fn clock(&self, now: NodeTime) -> NodeTime {
+        now + self.offset()
+    }
+}
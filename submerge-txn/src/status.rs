@@ -0,0 +1,113 @@
+// A node's transaction status, for the server's metrics endpoint and the
+// dashboard: its local watermark, the watermarks it's heard from its peers,
+// the global watermark, how many transactions are in each state, how long
+// its peers take to ack its Puts, how far their clocks are from its own
+// and how far it's slewed its own (see skew.rs), what admission control
+// has delayed and shed in each priority class, and what's queued to each
+// peer and what full queues have refused and shed.
+//
+// Replication latency is the time from a transaction's begin to each
+// peer's first ack of it, retries included. Only the most recent samples
+// are kept for each peer, so the percentiles follow the current network
+// rather than averaging over the node's whole life.
+
+use crate::{ClassStats, Priority, State};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use submerge_net::{Duration, NodeID, QueueStats, RealmTime};
+
+// The samples kept for each peer.
+const WINDOW: usize = 1024;
+
+// How many transactions are in each state. Ended, aborted and failed
+// transactions aren't kept, so End, Aborted and Err count every one since
+// the node started.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct StateCounts {
+    pub put: usize,
+    pub abort: usize,
+    pub aborted: usize,
+    pub err: usize,
+    pub seq: usize,
+    pub run: usize,
+    pub end: usize,
+}
+
+impl StateCounts {
+    pub(crate) fn count(&mut self, state: &State) {
+        match state {
+            State::Put { .. } => self.put += 1,
+            State::Abort { .. } => self.abort += 1,
+            State::Aborted => self.aborted += 1,
+            State::Err { .. } => self.err += 1,
+            State::Seq => self.seq += 1,
+            State::Run { .. } => self.run += 1,
+            State::End => self.end += 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Percentiles {
+    pub samples: usize,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Latencies {
+    samples: BTreeMap<NodeID, VecDeque<Duration>>,
+}
+
+impl Latencies {
+    pub fn new() -> Latencies {
+        Latencies::default()
+    }
+
+    pub fn record(&mut self, peer: NodeID, latency: Duration) {
+        let samples = self.samples.entry(peer).or_default();
+        if samples.len() == WINDOW {
+            samples.pop_front();
+        }
+        samples.push_back(latency);
+    }
+
+    // The nearest-rank percentiles of a peer's recent samples.
+    pub fn percentiles(&self, peer: NodeID) -> Option<Percentiles> {
+        let mut sorted = self.samples.get(&peer)?.iter().copied().collect::<Vec<_>>();
+        sorted.sort();
+        let max = *sorted.last()?;
+        let rank = |p: usize| sorted[(sorted.len() * p).div_ceil(100).max(1) - 1];
+        Some(Percentiles {
+            samples: sorted.len(),
+            p50: rank(50),
+            p90: rank(90),
+            p99: rank(99),
+            max,
+        })
+    }
+
+    pub fn all(&self) -> BTreeMap<NodeID, Percentiles> {
+        let peers = self.samples.keys();
+        peers
+            .filter_map(|p| Some((*p, self.percentiles(*p)?)))
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TxnStatus {
+    pub node: NodeID,
+    pub epoch: i64,
+    pub local: Option<RealmTime>,
+    pub heard: BTreeMap<NodeID, RealmTime>,
+    pub global: Option<RealmTime>,
+    pub states: StateCounts,
+    pub latency: BTreeMap<NodeID, Percentiles>,
+    pub skew: BTreeMap<NodeID, Duration>,
+    pub slew: Duration,
+    pub admission: BTreeMap<Priority, ClassStats>,
+    pub queues: BTreeMap<NodeID, QueueStats>,
+}
@@ -0,0 +1,129 @@
+// An in-memory multiversion Store. Each path keeps its versions ordered by
+// time, so a read at some time sees the latest one at or before it and
+// never anything written after. A transaction replicated in (step 3 of the
+// protocol) installs its thunk, unresolved, at its time in every path of
+// its write footprint; running it replaces those versions with results,
+// and killing it by reconfiguration aborts them, so reads see through to
+// the versions before.
+
+use crate::{Record, Store, Transaction};
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+use submerge_base::{err, Result};
+use submerge_lang::Path;
+use submerge_net::RealmTime;
+
+type Versions = BTreeMap<Path, BTreeMap<RealmTime, Record>>;
+
+#[derive(Debug, Default)]
+pub struct MemStore {
+    versions: Mutex<Versions>,
+}
+
+impl MemStore {
+    pub fn new() -> MemStore {
+        MemStore::default()
+    }
+
+    fn versions(&self) -> Result<MutexGuard<'_, Versions>> {
+        self.versions.lock().map_err(|_| err("store lock poisoned"))
+    }
+}
+
+impl Store for MemStore {
+    fn get(&self, path: Path, time: RealmTime) -> Result<Record> {
+        let versions = self.versions()?;
+        let latest = versions
+            .get(&path)
+            .and_then(|v| v.range(..=time).next_back());
+        match latest {
+            Some((_, record)) => Ok(record.clone()),
+            None => Err(err(format!("{} has no version at {:?}", path, time))),
+        }
+    }
+
+    fn before(&self, path: Path, time: RealmTime) -> Result<Option<(RealmTime, Record)>> {
+        let versions = self.versions()?;
+        let latest = versions
+            .get(&path)
+            .and_then(|v| v.range(..time).next_back());
+        Ok(latest.map(|(t, record)| (*t, record.clone())))
+    }
+
+    fn put(&self, path: Path, time: RealmTime, record: Record) -> Result<()> {
+        let mut versions = self.versions()?;
+        versions.entry(path).or_default().insert(time, record);
+        Ok(())
+    }
+
+    // Aborting a version that isn't there does nothing, so a killed
+    // transaction can be aborted again.
+    fn abort(&self, path: Path, time: RealmTime) -> Result<()> {
+        let mut versions = self.versions()?;
+        if let Some(v) = versions.get_mut(&path) {
+            v.remove(&time);
+            if v.is_empty() {
+                versions.remove(&path);
+            }
+        }
+        Ok(())
+    }
+
+    fn paths(&self, time: RealmTime) -> Result<Vec<Path>> {
+        let versions = self.versions()?;
+        let visible = versions
+            .iter()
+            .filter(|(_, v)| v.range(..=time).next().is_some());
+        Ok(visible.map(|(p, _)| p.clone()).collect())
+    }
+
+    fn since(&self, time: RealmTime) -> Result<Vec<(Path, RealmTime, Record)>> {
+        let versions = self.versions()?;
+        let mut out = Vec::new();
+        for (path, v) in versions.iter() {
+            for (t, record) in v.range(time..) {
+                out.push((path.clone(), *t, record.clone()));
+            }
+        }
+        Ok(out)
+    }
+
+    fn prune(&self, time: RealmTime) -> Result<usize> {
+        let mut versions = self.versions()?;
+        let mut pruned = 0;
+        for v in versions.values_mut() {
+            let Some(latest) = v.range(..=time).next_back().map(|(t, _)| *t) else {
+                continue;
+            };
+            let before = v.len();
+            let mut old = v.split_off(&latest);
+            std::mem::swap(v, &mut old);
+            v.extend(
+                old.into_iter()
+                    .filter(|(_, r)| matches!(r, Record::Unresolved(_))),
+            );
+            pruned += before - v.len();
+        }
+        Ok(pruned)
+    }
+}
+
+impl Transaction {
+    // Allocate the transaction's versions: its thunk, at its time, in every
+    // path it writes.
+    pub fn install(&self, store: &dyn Store) -> Result<()> {
+        for path in &self.thunk.foot.writes {
+            let thunk = Record::Unresolved(self.thunk.clone());
+            store.put(path.clone(), self.time, thunk)?;
+        }
+        Ok(())
+    }
+
+    // Remove the transaction's versions, once it's been killed.
+    pub fn abort(&self, store: &dyn Store) -> Result<()> {
+        for path in &self.thunk.foot.writes {
+            store.abort(path.clone(), self.time)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,2331 @@
+use crate::{
+    dependencies, seal, Admission, Arrivals, Blocked, Blocker, CatchUp, ClassStats, Config,
+    ConfigLog, Dedup, Escalation, Gc, Gossip, Hashes, Join, Joining, LanePolicy, Lanes, Latencies,
+    Limit, MemStore, NodeSet, Overload, Paxos, Priority, PutTry, Queue, Reassembly, Reconfig,
+    Record, Recovery, Resync, Session, Sim, Skews, State, StateCounts, Status, Store, Thunk,
+    Transaction, TxnClient, Waits, Watermark,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use submerge_eval::{Footprint, StepResult};
+use submerge_lang::{parse, Fault, FaultKind, Path, Tab, Vals, Word};
+use submerge_net::{
+    Backpressure, Detector, Duration, GossipPayload, LinkFault, Msg, Node, NodeID, NodeTime,
+    QueueLimit, RealmTime, SpecificMsg, Suspicion,
+};
+use test_log::test;
+
+fn at(us: i64) -> NodeTime {
+    NodeTime::from_micros(us)
+}
+
+fn nodes(ids: &[i64]) -> NodeSet {
+    ids.iter().map(|i| NodeID(*i)).collect()
+}
+
+fn thunk() -> Thunk {
+    let foot = Footprint {
+        reads: vec![],
+        writes: vec![],
+    };
+    Thunk::new(Tab::default(), parse("1 + 2").unwrap().expr, foot)
+}
+
+// A dotted path; the empty string is the whole database.
+fn path(p: &str) -> Path {
+    let words = p.split('.').filter(|w| !w.is_empty());
+    Path(words.map(|w| Word::new(w).unwrap()).collect())
+}
+
+// A transaction at a time, with a footprint of dotted paths.
+fn touching(us: i64, reads: &[&str], writes: &[&str]) -> Transaction {
+    let foot = Footprint {
+        reads: reads.iter().map(|p| path(p)).collect(),
+        writes: writes.iter().map(|p| path(p)).collect(),
+    };
+    let thunk = Thunk::new(Tab::default(), parse("1 + 2").unwrap().expr, foot);
+    let config = Config::new(nodes(&[1]), 0, Duration::from_micros(10));
+    Transaction::new(RealmTime::new(at(us), NodeID(1), 0), thunk, &config)
+}
+
+// The messages a node has queued to send.
+fn sent(net: &mut Node) -> Vec<Msg> {
+    let mut out = Vec::new();
+    while let Some((dst, buf)) = net.send_byes().unwrap() {
+        let msg = Msg::decode(&buf).unwrap();
+        assert_eq!(msg.dst(), dst);
+        out.push(msg);
+    }
+    out
+}
+
+#[test]
+fn test_put() {
+    let me = NodeID(1);
+    let config = Config::new(nodes(&[1, 2, 3]), 1, Duration::from_micros(10));
+    let time = RealmTime::new(at(0), me, 0);
+    let mut txn = Transaction::new(time, thunk(), &config);
+    let mut net = Node::new();
+
+    // Every node gets the thunk.
+    txn.poll(&mut net, me, &config, at(0)).unwrap();
+    let puts = sent(&mut net);
+    let dsts = puts.iter().map(Msg::dst).collect::<NodeSet>();
+    assert_eq!(dsts, config.nodes);
+    for put in &puts {
+        let SpecificMsg::Put(bytes) = put.specific() else {
+            panic!("not a put: {:?}", put)
+        };
+        assert_eq!(Thunk::decode(bytes).unwrap(), thunk());
+        assert_eq!((put.txn_time(), put.sequence()), (time, 0));
+    }
+    let ack = |put: &Msg| put.response(RealmTime::new(at(1), put.dst(), 0), SpecificMsg::Ack);
+
+    // Acks for other transactions, or that aren't acks, change nothing.
+    let other = Msg::new(
+        NodeID(2),
+        me,
+        RealmTime::new(at(0), NodeID(2), 0),
+        time,
+        0,
+        SpecificMsg::Ack,
+    );
+    txn.ack(&other);
+    txn.ack(&puts[1].response(time, SpecificMsg::Ping));
+    txn.ack(&ack(&puts[0]));
+    let State::Put { nodes: tries, .. } = &txn.state else {
+        panic!("left put")
+    };
+    assert_eq!(tries[&NodeID(1)], PutTry::Success);
+    assert_eq!(
+        tries[&NodeID(2)],
+        PutTry::Attempt {
+            count: 1,
+            time: at(0)
+        }
+    );
+
+    // Before the timeout nothing is resent; after it, the unacked nodes
+    // are tried again.
+    txn.poll(&mut net, me, &config, at(9)).unwrap();
+    assert!(sent(&mut net).is_empty());
+    txn.poll(&mut net, me, &config, at(10)).unwrap();
+    let retries = sent(&mut net);
+    let dsts = retries.iter().map(|m| (m.dst(), m.sequence()));
+    assert_eq!(
+        dsts.collect::<Vec<_>>(),
+        vec![(NodeID(2), 1), (NodeID(3), 1)]
+    );
+
+    // Node 2 acks a retry; node 3 runs out of them.
+    txn.ack(&ack(&retries[0]));
+    txn.poll(&mut net, me, &config, at(19)).unwrap();
+    assert!(matches!(txn.state, State::Put { .. }));
+    txn.poll(&mut net, me, &config, at(20)).unwrap();
+    assert!(sent(&mut net).is_empty());
+    assert_eq!(txn.state, State::Err { nodes: nodes(&[3]) });
+
+    // Once every node acks, the transaction waits for the watermark, and
+    // ignores any more acks.
+    let mut txn = Transaction::new(time, thunk(), &config);
+    txn.poll(&mut net, me, &config, at(0)).unwrap();
+    let puts = sent(&mut net);
+    for put in &puts {
+        assert!(matches!(txn.state, State::Put { .. }));
+        txn.ack(&ack(put));
+    }
+    assert_eq!(txn.state, State::Seq);
+    txn.ack(&ack(&puts[0]));
+    txn.poll(&mut net, me, &config, at(100)).unwrap();
+    assert_eq!(txn.state, State::Seq);
+    assert!(sent(&mut net).is_empty());
+}
+
+#[test]
+fn test_watermark() {
+    let me = NodeID(1);
+    let t = |us: i64| RealmTime::new(at(us), me, 0);
+    let mut w = Watermark::new();
+    assert_eq!(w.mark(), None);
+    for us in [10, 20, 30, 40] {
+        w.begin(t(us)).unwrap();
+    }
+
+    // Acks out of order: 30 and 20 wait behind 10, then all three pass at
+    // once; 40 is still replicating.
+    w.replicated(t(30)).unwrap();
+    w.replicated(t(20)).unwrap();
+    assert_eq!(w.mark(), None);
+    w.replicated(t(10)).unwrap();
+    assert_eq!(w.mark(), Some(t(30)));
+    assert!(w.replicated(t(10)).is_err());
+    assert!(w.replicated(t(35)).is_err());
+
+    // The clock can't pass 40 while it replicates, and the node can't begin
+    // anything behind what it's promised.
+    w.advance(t(50));
+    assert_eq!(w.mark(), Some(t(30)));
+    assert!(w.begin(t(25)).is_err());
+    assert!(w.begin(t(45)).is_err());
+    assert!(w.begin(t(40)).is_err());
+    w.replicated(t(40)).unwrap();
+    assert_eq!(w.mark(), Some(t(50)));
+
+    // An idle node's mark follows its clock.
+    w.advance(t(60));
+    assert_eq!(w.mark(), Some(t(60)));
+    w.begin(t(70)).unwrap();
+    w.advance(t(80));
+    assert_eq!(w.mark(), Some(t(60)));
+
+    // A transaction joins once its Put is done.
+    let config = Config::new(nodes(&[2]), 0, Duration::from_micros(10));
+    let mut txn = Transaction::new(t(90), thunk(), &config);
+    let mut w = Watermark::new();
+    w.begin(txn.time()).unwrap();
+    let mut net = Node::new();
+    txn.poll(&mut net, me, &config, at(90)).unwrap();
+    let put = sent(&mut net).remove(0);
+    txn.ack(&put.response(t(91), SpecificMsg::Ack));
+    assert_eq!(txn.state, State::Seq);
+    w.replicated(txn.time()).unwrap();
+    assert_eq!(w.mark(), Some(t(90)));
+}
+
+#[test]
+fn test_gossip() {
+    let config = Config::new(nodes(&[1, 2, 3]), 0, Duration::from_micros(10));
+    let t = |us: i64, node: i64| RealmTime::new(at(us), NodeID(node), 0);
+    let (me, mut net) = (NodeID(1), Node::new());
+    let mut gossip = Gossip::new(Duration::from_micros(10));
+    let mut local = Watermark::new();
+
+    // Nothing to say without a watermark; with one, every peer hears it,
+    // once a period.
+    gossip.poll(&mut net, me, &config, &local, at(0)).unwrap();
+    assert!(sent(&mut net).is_empty());
+    local.advance(t(5, 1));
+    gossip.poll(&mut net, me, &config, &local, at(5)).unwrap();
+    let msgs = sent(&mut net);
+    let dsts = msgs.iter().map(Msg::dst).collect::<NodeSet>();
+    assert_eq!(dsts, nodes(&[2, 3]));
+    assert!(msgs
+        .iter()
+        .all(|m| *m.specific() == SpecificMsg::Watermark(t(5, 1))));
+    local.advance(t(8, 1));
+    gossip.poll(&mut net, me, &config, &local, at(8)).unwrap();
+    assert!(sent(&mut net).is_empty());
+    assert_eq!(gossip.heard(me), Some(t(8, 1)));
+    gossip.poll(&mut net, me, &config, &local, at(15)).unwrap();
+    assert_eq!(sent(&mut net).len(), 2);
+
+    // The global watermark waits for every node, then is the least; a
+    // watermark reordered behind a later one is ignored.
+    let hear = |gossip: &mut Gossip, node: i64, mark: RealmTime| {
+        let msg = Msg::new(
+            NodeID(node),
+            me,
+            mark,
+            mark,
+            0,
+            SpecificMsg::Watermark(mark),
+        );
+        gossip.hear(&msg);
+    };
+    hear(&mut gossip, 2, t(30, 2));
+    assert_eq!(gossip.global(&config), None);
+    hear(&mut gossip, 3, t(6, 3));
+    assert_eq!(gossip.global(&config), Some(t(6, 3)));
+    hear(&mut gossip, 3, t(20, 3));
+    hear(&mut gossip, 3, t(12, 3));
+    assert_eq!(gossip.heard(NodeID(3)), Some(t(20, 3)));
+    assert_eq!(gossip.global(&config), Some(t(8, 1)));
+
+    // Another node learns them all as an anti-entropy payload, without
+    // going back on one it's heard later.
+    let mut other = Gossip::new(Duration::from_micros(10));
+    other.note(NodeID(2), t(40, 2));
+    other.merge(me, &gossip.provide().unwrap()).unwrap();
+    assert_eq!(other.heard(NodeID(2)), Some(t(40, 2)));
+    assert_eq!(other.global(&config), gossip.global(&config));
+
+    // A transaction in Seq runs once the global watermark passes it.
+    let mut txn = Transaction::new(t(10, 2), thunk(), &config);
+    assert!(!txn.release(t(20, 3)));
+    txn.state = State::Seq;
+    assert!(!txn.release(gossip.global(&config).unwrap()));
+    local.advance(t(25, 1));
+    gossip.poll(&mut net, me, &config, &local, at(25)).unwrap();
+    let global = gossip.global(&config).unwrap();
+    assert_eq!(global, t(20, 3));
+    assert!(txn.release(global));
+    let State::Run { eval } = &mut txn.state else {
+        panic!("not running")
+    };
+    let StepResult::Done(outcome) = eval.run(100) else {
+        panic!("not done")
+    };
+    assert_eq!(outcome, Ok(Vals::I64s(vec![3])));
+    assert!(!txn.release(global));
+}
+
+// Paxos participants on a simulated network, which delivers their messages
+// in an order a seed picks, dropping some.
+struct Cluster {
+    nodes: BTreeMap<NodeID, (Paxos<i64>, Node)>,
+    wire: Vec<Msg>,
+    rng: u64,
+    drop_pct: u64,
+}
+
+impl Cluster {
+    fn new(n: i64, seed: u64, drop_pct: u64) -> Cluster {
+        let ids = nodes(&(1..=n).collect::<Vec<_>>());
+        let instance = RealmTime::new(at(0), NodeID(1), 0);
+        let node = |id: NodeID| (id, (Paxos::new(id, ids.clone(), instance), Node::new()));
+        Cluster {
+            nodes: ids.iter().copied().map(node).collect(),
+            wire: Vec::new(),
+            rng: seed,
+            drop_pct,
+        }
+    }
+
+    fn random(&mut self, n: usize) -> usize {
+        self.rng = self
+            .rng
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.rng >> 33) as usize % n
+    }
+
+    fn propose(&mut self, id: i64, value: i64) {
+        let (paxos, net) = self.nodes.get_mut(&NodeID(id)).unwrap();
+        paxos.propose(net, at(0), value).unwrap();
+        self.collect();
+    }
+
+    fn collect(&mut self) {
+        for (_, net) in self.nodes.values_mut() {
+            self.wire.extend(sent(net));
+        }
+    }
+
+    // Deliver a message off the wire, unless it's dropped; false once the
+    // wire is empty.
+    fn deliver(&mut self) -> bool {
+        if self.wire.is_empty() {
+            return false;
+        }
+        let i = self.random(self.wire.len());
+        let msg = self.wire.swap_remove(i);
+        if (self.random(100) as u64) >= self.drop_pct {
+            let (paxos, net) = self.nodes.get_mut(&msg.dst()).unwrap();
+            paxos.recv(net, at(0), &msg).unwrap();
+            self.collect();
+        }
+        true
+    }
+
+    fn decided(&self, id: i64) -> Option<i64> {
+        self.nodes[&NodeID(id)].0.decided().copied()
+    }
+}
+
+#[test]
+fn test_paxos() {
+    // With no losses, one proposal is decided everywhere.
+    let mut c = Cluster::new(3, 0, 0);
+    c.propose(2, 7);
+    while c.deliver() {}
+    assert!((1..=3).all(|i| c.decided(i) == Some(7)));
+
+    // Messages of another instance, or that aren't paxos, are ignored.
+    let mut c = Cluster::new(3, 0, 0);
+    c.propose(1, 5);
+    let other = RealmTime::new(at(1), NodeID(1), 0);
+    for msg in std::mem::take(&mut c.wire) {
+        let (src, dst, seq) = (msg.src(), msg.dst(), msg.sequence());
+        let SpecificMsg::Paxos(bytes) = msg.specific().clone() else {
+            panic!("not paxos")
+        };
+        let moved = Msg::new(src, dst, other, other, seq, SpecificMsg::Paxos(bytes));
+        let ping = Msg::new(src, dst, msg.txn_time(), other, seq, SpecificMsg::Ping);
+        c.wire.extend([moved, ping]);
+    }
+    while c.deliver() {}
+    assert!((1..=3).all(|i| c.decided(i).is_none()));
+
+    // Competing proposers, with messages reordered and dropped, never
+    // decide different values; proposing again until they've decided,
+    // both learn the one value.
+    for seed in 0..200 {
+        let mut c = Cluster::new(5, seed, 20);
+        c.propose(1, 100);
+        c.propose(2, 200);
+        let mut tries = 0;
+        while c.decided(1).is_none() || c.decided(2).is_none() {
+            if !c.deliver() {
+                tries += 1;
+                assert!(tries < 100, "seed {} never decided", seed);
+                for (id, v) in [(1, 100), (2, 200)] {
+                    if c.decided(id).is_none() {
+                        c.propose(id, v);
+                    }
+                }
+            }
+        }
+        while c.deliver() {}
+        let decided = (1..=5)
+            .filter_map(|i| c.decided(i))
+            .collect::<BTreeSet<_>>();
+        assert_eq!(decided.len(), 1, "seed {}: {:?}", seed, decided);
+        assert!(decided.contains(&100) || decided.contains(&200));
+    }
+}
+
+#[test]
+fn test_reconfig() {
+    let config = Config::new(nodes(&[1, 2, 3]), 0, Duration::from_micros(10));
+    let t = |us: i64| RealmTime::new(at(us), NodeID(1), 0);
+    let me = NodeID(1);
+
+    // Node 1 coordinates three transactions; node 3 is down, so the last
+    // one's Put times out there, and the others are in Seq.
+    let mut txns = BTreeMap::new();
+    let mut net = Node::new();
+    for us in [4, 8, 10] {
+        let mut txn = Transaction::new(t(us), thunk(), &config);
+        txn.poll(&mut net, me, &config, at(us)).unwrap();
+        for put in sent(&mut net).iter().filter(|m| m.dst() != NodeID(3)) {
+            txn.ack(&put.response(t(us), SpecificMsg::Ack));
+        }
+        txns.insert(t(us), txn);
+    }
+    let txn = txns.get_mut(&t(10)).unwrap();
+    txn.poll(&mut net, me, &config, at(20)).unwrap();
+    assert_eq!(txn.state, State::Err { nodes: nodes(&[3]) });
+    txns.get_mut(&t(4)).unwrap().state = State::Seq;
+    txns.get_mut(&t(8)).unwrap().state = State::Seq;
+
+    // It proposes a configuration without node 3, sealed at the global
+    // watermark; nodes 1 and 2 are a quorum, and decide it.
+    let mut parts = BTreeMap::new();
+    for id in [1, 2, 3] {
+        parts.insert(
+            NodeID(id),
+            (Reconfig::new(NodeID(id), &config), Node::new()),
+        );
+    }
+    let (r, rnet) = parts.get_mut(&me).unwrap();
+    assert!(r
+        .propose(rnet, me, &config, nodes(&[1, 2]), t(5), at(5))
+        .is_err());
+    r.propose(rnet, me, &config, nodes(&[1, 2]), t(5), at(30))
+        .unwrap();
+    loop {
+        let mut wire = Vec::new();
+        for (_, net) in parts.values_mut() {
+            wire.extend(sent(net));
+        }
+        if wire.is_empty() {
+            break;
+        }
+        for msg in wire.iter().filter(|m| m.dst() != NodeID(3)) {
+            let (r, net) = parts.get_mut(&msg.dst()).unwrap();
+            r.recv(net, at(31), msg).unwrap();
+        }
+    }
+    let next = parts[&me].0.decided().unwrap().clone();
+    assert_eq!(parts[&NodeID(2)].0.decided(), Some(&next));
+    assert_eq!(parts[&NodeID(3)].0.decided(), None);
+    assert_eq!(next.nodes(), &nodes(&[1, 2]));
+    assert_eq!((next.epoch(), next.sealed()), (1, Some(t(5))));
+    assert_eq!(next.start(), RealmTime::new(at(30), me, 0));
+
+    // The transactions past the seal die, and the one that timed out is
+    // resubmitted in the new epoch, replicating only to nodes 1 and 2.
+    let killed = seal(&mut txns, &next);
+    let times = killed.iter().map(Transaction::time).collect::<Vec<_>>();
+    assert_eq!(times, vec![t(8), t(10)]);
+    assert_eq!(txns.keys().copied().collect::<Vec<_>>(), vec![t(4)]);
+    let old = &killed[1];
+    assert!(old.resubmit(t(20), &next).is_err());
+    let mut again = old.resubmit(t(40), &next).unwrap();
+    again.poll(&mut net, me, &next, at(40)).unwrap();
+    let dsts = sent(&mut net).iter().map(Msg::dst).collect::<NodeSet>();
+    assert_eq!(dsts, nodes(&[1, 2]));
+    assert_eq!(again.thunk, old.thunk);
+
+    // The local watermark forgets the killed transactions, so it isn't
+    // held up by them.
+    let mut w = Watermark::new();
+    for us in [4, 8, 10] {
+        w.begin(t(us)).unwrap();
+    }
+    w.replicated(t(4)).unwrap();
+    w.advance(t(30));
+    assert_eq!(w.mark(), Some(t(4)));
+    w.seal(t(5));
+    assert_eq!(w.mark(), Some(t(30)));
+    assert!(w.begin(t(40)).is_ok());
+}
+
+#[test]
+fn test_join() {
+    let config = Config::new(nodes(&[1, 2, 3]), 0, Duration::from_micros(10));
+    let t = |us: i64, n: i64| RealmTime::new(at(us), NodeID(n), 0);
+    let fresh = NodeID(4);
+    let proposer = NodeID(3);
+    assert_eq!(config.proposer(), Some(proposer));
+
+    // The fresh node says it's ready, once a period.
+    let mut joining = Joining::new(nodes(&[1, 2, 3]), Duration::from_micros(10));
+    let mut fnet = Node::new();
+    joining.poll(&mut fnet, fresh, at(0)).unwrap();
+    joining.poll(&mut fnet, fresh, at(5)).unwrap();
+    let ready = sent(&mut fnet);
+    assert_eq!(
+        ready.iter().map(Msg::dst).collect::<NodeSet>(),
+        config.nodes
+    );
+    assert!(ready.iter().all(|m| *m.specific() == SpecificMsg::Ready));
+
+    // Only the highest-numbered member proposes taking it in.
+    let mut joins = BTreeMap::new();
+    for msg in &ready {
+        joins.entry(msg.dst()).or_insert_with(Join::new).hear(msg);
+    }
+    assert_eq!(joins[&NodeID(1)].expansion(NodeID(1), &config), None);
+    let grown = joins[&proposer].expansion(proposer, &config).unwrap();
+    assert_eq!(grown, nodes(&[1, 2, 3, 4]));
+
+    let mut parts = BTreeMap::new();
+    for id in [1, 2, 3] {
+        let r = Reconfig::new(NodeID(id), &config);
+        parts.insert(NodeID(id), (r, Node::new()));
+    }
+    let (r, rnet) = parts.get_mut(&proposer).unwrap();
+    r.propose(rnet, proposer, &config, grown, t(5, 3), at(20))
+        .unwrap();
+    loop {
+        let mut wire = Vec::new();
+        for (_, net) in parts.values_mut() {
+            wire.extend(sent(net));
+        }
+        if wire.is_empty() {
+            break;
+        }
+        for msg in &wire {
+            let (r, net) = parts.get_mut(&msg.dst()).unwrap();
+            r.recv(net, at(21), msg).unwrap();
+        }
+    }
+    let mut next = parts[&proposer].0.decided().unwrap().clone();
+    assert_eq!(next.joining(), &nodes(&[4]));
+    assert_eq!(next.proposer(), Some(proposer));
+    assert_eq!(joins[&proposer].expansion(proposer, &next), None);
+
+    // While it's joining it gets Puts, but they settle without its ack.
+    let mut txn = Transaction::new(t(30, 1), thunk(), &next);
+    let mut net = Node::new();
+    txn.poll(&mut net, NodeID(1), &next, at(30)).unwrap();
+    let puts = sent(&mut net);
+    assert_eq!(puts.iter().map(Msg::dst).collect::<NodeSet>(), next.nodes);
+    for put in puts.iter().filter(|m| m.dst() != fresh) {
+        txn.ack(&put.response(t(31, 1), SpecificMsg::Ack));
+    }
+    assert_eq!(txn.state, State::Seq);
+
+    // The proposer only sends its state once everything up to the seal
+    // has run; the new epoch's transactions needn't have.
+    let store = MemStore::new();
+    let table = Path(vec![Word::new("t").unwrap()]);
+    for time in [t(2, 1), t(30, 1)] {
+        let thunk = Record::Unresolved(thunk());
+        store.put(table.clone(), time, thunk).unwrap();
+    }
+    let mut gossip = Gossip::new(Duration::from_micros(10));
+    gossip.note(NodeID(1), t(5, 1));
+    gossip.note(NodeID(2), t(5, 3));
+    let join = &joins[&proposer];
+    let mut pnet = Node::new();
+    assert!(join
+        .catch_up(&mut pnet, proposer, &next, &store, &gossip, at(40))
+        .is_err());
+    let resolved = Record::Resolved(Vals::I64s(vec![3]));
+    store.put(table.clone(), t(2, 1), resolved.clone()).unwrap();
+    join.catch_up(&mut pnet, proposer, &next, &store, &gossip, at(40))
+        .unwrap();
+    join.catch_up(&mut pnet, NodeID(1), &next, &store, &gossip, at(40))
+        .unwrap();
+    let catch_ups = sent(&mut pnet);
+    assert_eq!(catch_ups.len(), 1);
+    let SpecificMsg::CatchUp(bytes) = catch_ups[0].specific() else {
+        panic!("not a catch-up: {:?}", catch_ups[0])
+    };
+    assert_eq!(CatchUp::decode(bytes).unwrap().config(), &next);
+
+    // The fresh node installs it once, and tells everyone.
+    let fstore = MemStore::new();
+    let mut fgossip = Gossip::new(Duration::from_micros(10));
+    let mut local = Watermark::new();
+    for _ in 0..2 {
+        let msg = &catch_ups[0];
+        joining
+            .recv(&mut fnet, at(41), msg, &fstore, &mut fgossip, &mut local)
+            .unwrap();
+    }
+    assert_eq!(fstore.get(table, t(5, 3)).unwrap(), resolved);
+    assert_eq!(fgossip.heard(NodeID(2)), Some(t(5, 3)));
+    assert_eq!(local.mark(), Some(t(5, 3)));
+    assert!(joining.config().unwrap().joining().is_empty());
+    joining.poll(&mut fnet, fresh, at(50)).unwrap();
+    let caught_up = sent(&mut fnet);
+    assert_eq!(caught_up.len(), 3);
+    assert!(caught_up
+        .iter()
+        .all(|m| *m.specific() == SpecificMsg::CaughtUp));
+
+    // After which its acks count.
+    let join = joins.get_mut(&NodeID(1)).unwrap();
+    assert!(!join.caught_up(&ready[0], &mut next));
+    assert!(join.caught_up(&caught_up[0], &mut next));
+    assert!(next.joining().is_empty());
+    let mut txn = Transaction::new(t(60, 1), thunk(), &next);
+    txn.poll(&mut net, NodeID(1), &next, at(60)).unwrap();
+    for put in sent(&mut net).iter().filter(|m| m.dst() != fresh) {
+        txn.ack(&put.response(t(61, 1), SpecificMsg::Ack));
+    }
+    assert!(matches!(txn.state, State::Put { .. }));
+}
+
+#[test]
+fn test_store() {
+    let store = MemStore::new();
+    let t = |us: i64| RealmTime::new(at(us), NodeID(1), 0);
+    let foot = Footprint {
+        reads: vec![],
+        writes: vec![path("a"), path("b")],
+    };
+    let thunk = Thunk::new(Tab::default(), parse("1 + 2").unwrap().expr, foot);
+    let config = Config::new(nodes(&[1]), 0, Duration::from_micros(10));
+
+    // A transaction's thunk goes in every path it writes, at its time.
+    let first = Transaction::new(t(10), thunk.clone(), &config);
+    first.install(&store).unwrap();
+    let unresolved = Record::Unresolved(thunk.clone());
+    assert_eq!(store.get(path("a"), t(10)).unwrap(), unresolved);
+    assert_eq!(store.get(path("b"), t(15)).unwrap(), unresolved);
+    assert!(store.get(path("a"), t(9)).is_err());
+    assert!(store.get(path("c"), t(10)).is_err());
+
+    // Reads see the latest version at or before their time.
+    let three = Record::Resolved(Vals::I64s(vec![3]));
+    store.put(path("a"), t(10), three.clone()).unwrap();
+    let second = Transaction::new(t(20), thunk.clone(), &config);
+    second.install(&store).unwrap();
+    assert_eq!(store.get(path("a"), t(19)).unwrap(), three);
+    assert_eq!(store.get(path("a"), t(20)).unwrap(), unresolved);
+    assert_eq!(store.paths(t(9)).unwrap(), vec![]);
+    assert_eq!(store.paths(t(10)).unwrap(), vec![path("a"), path("b")]);
+
+    // Aborting removes only the transaction's own versions, twice over.
+    second.abort(&store).unwrap();
+    second.abort(&store).unwrap();
+    assert_eq!(store.get(path("a"), t(20)).unwrap(), three);
+    first.abort(&store).unwrap();
+    assert!(store.get(path("a"), t(20)).is_err());
+    assert_eq!(store.paths(t(20)).unwrap(), vec![]);
+}
+
+#[test]
+fn test_gc() {
+    let store = MemStore::new();
+    let t = |us: i64| RealmTime::new(at(us), NodeID(1), 0);
+    let int = |i: i64| Record::Resolved(Vals::I64s(vec![i]));
+    for us in [10, 20, 30] {
+        store.put(path("a"), t(us), int(us)).unwrap();
+    }
+    store.put(path("b"), t(10), int(1)).unwrap();
+    let thunk = Record::Unresolved(thunk());
+    store.put(path("b"), t(15), thunk.clone()).unwrap();
+    store.put(path("b"), t(20), int(2)).unwrap();
+
+    // The horizon is the older of the global watermark and the oldest
+    // open snapshot, less the retention.
+    let mut gc = Gc::new(Duration::from_micros(5));
+    assert_eq!(gc.horizon(t(30)), t(25));
+    gc.pin(t(22));
+    gc.pin(t(22));
+    gc.pin(t(26));
+    assert_eq!(gc.oldest(), Some(t(22)));
+    assert_eq!(gc.horizon(t(30)), t(17));
+    gc.unpin(t(22));
+    assert_eq!(gc.horizon(t(30)), t(17));
+
+    // Versions before the latest at the horizon go, except unresolved
+    // ones; reads at the horizon and after see what they did before.
+    assert_eq!(gc.collect(&store, t(30)).unwrap(), 1);
+    assert_eq!(store.get(path("a"), t(10)).unwrap(), int(10));
+    assert_eq!(store.get(path("b"), t(17)).unwrap(), thunk);
+    assert!(store.get(path("b"), t(10)).is_err());
+
+    // Collecting again at the same horizon does nothing; unpinning
+    // moves it on.
+    assert_eq!(gc.collect(&store, t(30)).unwrap(), 0);
+    gc.unpin(t(22));
+    gc.unpin(t(26));
+    assert_eq!(gc.oldest(), None);
+    assert_eq!(gc.collect(&store, t(30)).unwrap(), 1);
+    assert!(store.get(path("a"), t(15)).is_err());
+    assert_eq!(store.get(path("a"), t(25)).unwrap(), int(20));
+    assert_eq!(store.get(path("a"), t(30)).unwrap(), int(30));
+    assert_eq!(store.get(path("b"), t(15)).unwrap(), thunk);
+    assert_eq!(store.get(path("b"), t(25)).unwrap(), int(2));
+}
+
+#[test]
+fn test_dependencies() {
+    let mut txns = BTreeMap::new();
+    let mut add = |us: i64, reads: &[&str], writes: &[&str]| {
+        let txn = touching(us, reads, writes);
+        let time = txn.time;
+        txns.insert(time, txn);
+        time
+    };
+    let a = add(1, &[], &["t.a"]);
+    let b = add(2, &["t.b"], &["t.b"]);
+    let c = add(3, &["t.a"], &[]);
+    let d = add(4, &[], &["t"]);
+    let e = add(5, &["u"], &[]);
+    let f = add(6, &[""], &[""]);
+    let deps = dependencies(&txns);
+    let on = |t| deps[&t].iter().copied().collect::<Vec<_>>();
+    assert_eq!(on(a), vec![]);
+    assert_eq!(on(b), vec![]);
+    assert_eq!(on(c), vec![a]);
+    assert_eq!(on(d), vec![a, b, c]);
+    assert_eq!(on(e), vec![]);
+    assert_eq!(on(f), vec![a, b, c, d, e]);
+}
+
+#[test]
+fn test_queue() {
+    let config = Config::new(nodes(&[1, 2]), 0, Duration::from_micros(10));
+    let t = |us: i64| RealmTime::new(at(us), NodeID(1), 0);
+    let seq = |us: i64| {
+        let mut txn = Transaction::new(t(us), thunk(), &config);
+        txn.state = State::Seq;
+        txn
+    };
+    let mut queue = Queue::new();
+
+    // Only replicated transactions queue, once each.
+    assert!(queue
+        .push(Transaction::new(t(5), thunk(), &config))
+        .is_err());
+    for us in [30, 10, 20] {
+        queue.push(seq(us)).unwrap();
+    }
+    assert!(queue.push(seq(10)).is_err());
+    assert_eq!(queue.len(), 3);
+
+    // Everything at or before the global watermark runs, in time order.
+    let run = queue.release(t(20));
+    assert_eq!(
+        run.iter().map(Transaction::time).collect::<Vec<_>>(),
+        vec![t(10), t(20)]
+    );
+    assert!(run.iter().all(|txn| matches!(txn.state, State::Run { .. })));
+    assert_eq!(queue.released(), Some(t(20)));
+    assert!(queue.release(t(15)).is_empty());
+    assert_eq!(queue.len(), 1);
+
+    // Nothing can turn up behind it afterwards.
+    let late = queue.push(seq(15)).unwrap_err();
+    assert_eq!(late.kind(), submerge_base::ErrorKind::Internal);
+    assert!(queue.push(seq(20)).is_err());
+    queue.push(seq(25)).unwrap();
+    let run = queue.release(t(40));
+    assert_eq!(
+        run.iter().map(Transaction::time).collect::<Vec<_>>(),
+        vec![t(25), t(30)]
+    );
+    assert!(queue.is_empty());
+}
+
+#[test]
+fn test_waits() {
+    let store = MemStore::new();
+    let t = |us: i64| RealmTime::new(at(us), NodeID(1), 0);
+    let one = Record::Resolved(Vals::I64s(vec![1]));
+    let two = Record::Resolved(Vals::I64s(vec![2]));
+    for p in ["a", "b", "c"] {
+        store.put(path(p), t(5), one.clone()).unwrap();
+    }
+    for p in ["a", "b"] {
+        store
+            .put(path(p), t(10), Record::Unresolved(thunk()))
+            .unwrap();
+    }
+    let mut waits = Waits::new();
+
+    // Resolved versions read straight through, as of before the reader, so
+    // the writer itself reads past its own thunk.
+    assert_eq!(
+        waits.read(&store, t(20), path("c")).unwrap(),
+        Some(one.clone())
+    );
+    assert_eq!(
+        waits.read(&store, t(10), path("a")).unwrap(),
+        Some(one.clone())
+    );
+    assert!(waits.read(&store, t(5), path("a")).is_err());
+
+    // Readers of the thunk wait until everything they read is resolved.
+    for reader in [t(30), t(20)] {
+        assert_eq!(waits.read(&store, reader, path("a")).unwrap(), None);
+    }
+    assert_eq!(waits.read(&store, t(20), path("b")).unwrap(), None);
+    assert_eq!(waits.waiting(t(20)).count(), 2);
+    let woken = waits
+        .resolve(&store, path("a"), t(10), two.clone())
+        .unwrap();
+    assert_eq!(woken, vec![t(30)]);
+    let woken = waits
+        .resolve(&store, path("b"), t(10), two.clone())
+        .unwrap();
+    assert_eq!(woken, vec![t(20)]);
+    assert_eq!(waits.waiting(t(20)).count(), 0);
+    assert_eq!(waits.read(&store, t(20), path("a")).unwrap(), Some(two));
+
+    // Waits that would close a cycle are internal errors.
+    waits.wait(t(40), (path("a"), t(50))).unwrap();
+    waits.wait(t(50), (path("b"), t(60))).unwrap();
+    let cycle = waits.wait(t(60), (path("c"), t(40))).unwrap_err();
+    assert_eq!(cycle.kind(), submerge_base::ErrorKind::Internal);
+    assert_eq!(waits.waiting(t(60)).count(), 0);
+}
+
+#[test]
+fn test_lanes() {
+    let times = |lanes: &Lanes, lane: usize| {
+        let mut lanes = lanes.clone();
+        let finished = std::iter::from_fn(|| lanes.finish(lane));
+        finished
+            .map(|t| t.time().time().micros())
+            .collect::<Vec<_>>()
+    };
+
+    // Conflicting transactions share a lane, in order; the rest spread out.
+    let mut lanes = Lanes::new(2, LanePolicy::Shortest);
+    lanes.assign(touching(1, &[], &["t.a"]));
+    lanes.assign(touching(2, &["t.b"], &["t.b"]));
+    lanes.assign(touching(3, &["t.a"], &[]));
+    assert_eq!((times(&lanes, 0), times(&lanes, 1)), (vec![1, 3], vec![2]));
+
+    // One that conflicts with both lanes waits, as does a later one that
+    // conflicts with it; one that conflicts with nothing doesn't.
+    lanes.assign(touching(4, &[], &["t"]));
+    lanes.assign(touching(5, &["u"], &["u"]));
+    lanes.assign(touching(6, &["t.b"], &[]));
+    assert_eq!(lanes.held(), 2);
+    assert_eq!(
+        (times(&lanes, 0), times(&lanes, 1)),
+        (vec![1, 3], vec![2, 5])
+    );
+
+    // They go once all but one of the lanes they conflict with drain.
+    assert_eq!(lanes.front(0).unwrap().time().time().micros(), 1);
+    lanes.finish(0).unwrap();
+    assert_eq!(lanes.held(), 2);
+    lanes.finish(0).unwrap();
+    assert_eq!(lanes.held(), 0);
+    assert_eq!(times(&lanes, 1), vec![2, 5, 4, 6]);
+    assert!(lanes.finish(0).is_none());
+    while lanes.finish(1).is_some() {}
+    assert!(lanes.is_empty());
+
+    // Round-robin takes turns regardless of length.
+    let mut lanes = Lanes::new(3, LanePolicy::RoundRobin);
+    for (us, table) in [(1, "a"), (2, "b"), (3, "a"), (4, "c"), (5, "d")] {
+        lanes.assign(touching(us, &[], &[table]));
+    }
+    let all = (0..3).map(|i| times(&lanes, i)).collect::<Vec<_>>();
+    assert_eq!(all, vec![vec![1, 3, 5], vec![2], vec![4]]);
+    assert_eq!(Lanes::new(0, LanePolicy::Shortest).count(), 1);
+}
+
+#[test]
+fn test_divergence() {
+    let config = Config::new(nodes(&[1, 2, 3]), 0, Duration::from_micros(10));
+    let t = |us: i64| RealmTime::new(at(us), NodeID(1), 0);
+    let three = Record::Resolved(Vals::I64s(vec![3]));
+    let four = Record::Resolved(Vals::I64s(vec![4]));
+    let fault = Record::Faulted(Fault {
+        kind: FaultKind::Overflow,
+        pc: 0,
+        msg: "3".into(),
+    });
+    assert_eq!(three.content_hash(), three.clone().content_hash());
+    assert_ne!(three.content_hash(), four.content_hash());
+    assert_ne!(three.content_hash(), fault.content_hash());
+    assert_eq!(Record::Unresolved(thunk()).content_hash(), None);
+
+    // Each node sends the others its hash of each result.
+    let mut nets = BTreeMap::new();
+    let mut hashes = BTreeMap::new();
+    let results = [(1, &three), (2, &three), (3, &four)];
+    for (id, result) in results {
+        let (me, mut net, mut h) = (NodeID(id), Node::new(), Hashes::new());
+        for txn in [t(10), t(20)] {
+            let result = if txn == t(10) { &three } else { result };
+            h.finish(&mut net, me, &config, txn, result, at(30))
+                .unwrap();
+        }
+        nets.insert(me, net);
+        hashes.insert(me, h);
+    }
+    let mut h = Hashes::new();
+    let unrun = Record::Unresolved(thunk());
+    assert!(h
+        .finish(&mut Node::new(), NodeID(1), &config, t(5), &unrun, at(30))
+        .is_err());
+    for net in nets.values_mut() {
+        for msg in sent(net) {
+            assert!(matches!(msg.specific(), SpecificMsg::ResultHash(_)));
+            hashes.get_mut(&msg.dst()).unwrap().hear(&msg);
+        }
+    }
+
+    // Every node sees node 3 differ on the second transaction.
+    for h in hashes.values_mut() {
+        assert_eq!(h.divergence(t(10)), None);
+        let report = h.divergences();
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].txn(), t(20));
+        let by_node = report[0].hashes();
+        assert_eq!(by_node.keys().copied().collect::<NodeSet>(), config.nodes);
+        assert_eq!(by_node[&NodeID(1)], three.content_hash().unwrap());
+        assert_eq!(by_node[&NodeID(3)], four.content_hash().unwrap());
+
+        // Agreed results settle; divergent ones stay to be resolved.
+        h.settle(&config);
+        assert_eq!(h.divergence(t(20)), Some(report[0].clone()));
+    }
+}
+
+#[test]
+fn test_resync() {
+    let config = Config::new(nodes(&[1, 2, 3]), 0, Duration::from_micros(10));
+    let t = |us: i64| RealmTime::new(at(us), NodeID(1), 0);
+    let int = |i: i64| Record::Resolved(Vals::I64s(vec![i]));
+    let report = |hashes: &[(i64, i64)]| {
+        let mut h = Hashes::new();
+        for (node, i) in hashes {
+            let hash = SpecificMsg::ResultHash(int(*i).content_hash().unwrap());
+            h.hear(&Msg::new(NodeID(*node), NodeID(1), t(10), t(30), 0, hash));
+        }
+        h.divergence(t(10)).unwrap()
+    };
+    let stores = (1..=3)
+        .map(|id| {
+            let store = MemStore::new();
+            store.put(path("a"), t(5), int(1)).unwrap();
+            store
+                .put(path("a"), t(10), int(if id == 3 { 9 } else { 2 }))
+                .unwrap();
+            store
+                .put(path("b"), t(20), Record::Unresolved(thunk()))
+                .unwrap();
+            (NodeID(id), store)
+        })
+        .collect::<BTreeMap<_, _>>();
+    let mut net = Node::new();
+
+    // Nobody acts until every voter has reported.
+    let mut resync = Resync::new();
+    let partial = report(&[(1, 2), (3, 9)]);
+    let store = &stores[&NodeID(3)];
+    assert!(!resync
+        .check(&mut net, NodeID(3), &config, &partial, store, at(40))
+        .unwrap());
+
+    // The majority carries on; the minority halts from the divergence on,
+    // discards what it had there, and asks the highest majority node.
+    let div = report(&[(1, 2), (2, 2), (3, 9)]);
+    let mut majority = Resync::new();
+    assert!(!majority
+        .check(
+            &mut net,
+            NodeID(1),
+            &config,
+            &div,
+            &stores[&NodeID(1)],
+            at(40)
+        )
+        .unwrap());
+    assert!(resync
+        .check(&mut net, NodeID(3), &config, &div, store, at(40))
+        .unwrap());
+    let halted = Status::Halted {
+        from: t(10),
+        source: NodeID(2),
+        since: at(40),
+    };
+    assert_eq!(resync.status(), halted);
+    assert!(resync.halts(t(20)) && !resync.halts(t(5)));
+    assert_eq!(store.get(path("a"), t(20)).unwrap(), int(1));
+    assert!(store.get(path("b"), t(20)).is_err());
+    let asks = sent(&mut net);
+    assert_eq!(asks.len(), 1);
+    assert_eq!((asks[0].dst(), asks[0].txn_time()), (NodeID(2), t(10)));
+
+    // The source sends its versions from there on, and the node resumes.
+    majority
+        .serve(&mut net, &stores[&NodeID(2)], &asks[0], at(41))
+        .unwrap();
+    let segment = sent(&mut net).pop().unwrap();
+    assert!(matches!(segment.specific(), SpecificMsg::Segment(_)));
+    assert!(resync.recv(store, &segment, at(42)).unwrap());
+    assert_eq!(resync.status(), Status::Running);
+    assert_eq!(store.get(path("a"), t(20)).unwrap(), int(2));
+    assert_eq!(
+        store.get(path("b"), t(20)).unwrap(),
+        Record::Unresolved(thunk())
+    );
+    let done = resync.history();
+    assert_eq!(
+        (done.len(), done[0].source, done[0].resumed),
+        (1, NodeID(2), at(42))
+    );
+    assert!(!resync.recv(store, &segment, at(43)).unwrap());
+
+    // Without a majority, lower-numbered nodes copy from higher.
+    let config = Config::new(nodes(&[1, 2]), 0, Duration::from_micros(10));
+    let div = report(&[(1, 2), (2, 9)]);
+    let mut low = Resync::new();
+    let mut high = Resync::new();
+    assert!(high
+        .check(&mut net, NodeID(2), &config, &div, store, at(50))
+        .is_ok_and(|h| !h));
+    assert!(low
+        .check(
+            &mut net,
+            NodeID(1),
+            &config,
+            &div,
+            &stores[&NodeID(1)],
+            at(50)
+        )
+        .unwrap());
+    assert_eq!(sent(&mut net)[0].dst(), NodeID(2));
+}
+
+#[test]
+fn test_config_log() {
+    let file = std::env::temp_dir().join(format!("submerge-config-log-{}", std::process::id()));
+    let _ = std::fs::remove_file(&file);
+    let t = |us: i64| RealmTime::new(at(us), NodeID(1), 0);
+    let first = Config::new(nodes(&[1, 2, 3]), 1, Duration::from_micros(10));
+    let second = first.successor(nodes(&[1, 2]), t(10), t(20));
+    let third = second.successor(nodes(&[1, 2, 4]), t(30), t(40));
+
+    // Configurations go in in order, and each once.
+    let mut log = ConfigLog::open(&file).unwrap();
+    assert_eq!(log.current(), None);
+    log.append(&first).unwrap();
+    log.append(&second).unwrap();
+    log.append(&second).unwrap();
+    assert!(log.append(&first).is_err());
+    assert!(log
+        .append(&third.successor(nodes(&[1]), t(50), t(60)))
+        .is_err());
+    drop(log);
+
+    // They're all there after a restart.
+    let mut log = ConfigLog::open(&file).unwrap();
+    assert_eq!(log.history(), &[first.clone(), second.clone()]);
+    assert_eq!(log.epoch_of(t(5)), Some(&first));
+    assert_eq!(log.epoch_of(t(15)), None);
+    assert_eq!(log.epoch_of(t(25)), Some(&second));
+
+    // Traffic from before the current epoch is stale.
+    let put = |us: i64| {
+        Msg::new(
+            NodeID(3),
+            NodeID(1),
+            t(us),
+            t(us),
+            0,
+            SpecificMsg::Put(vec![]),
+        )
+    };
+    assert!(log.stale(&put(5)));
+    assert!(!log.stale(&put(25)));
+    let mark = SpecificMsg::Watermark(t(5));
+    assert!(!log.stale(&Msg::new(NodeID(2), NodeID(1), t(5), t(5), 0, mark)));
+
+    // A torn entry at the end is dropped, and the log goes on after it.
+    log.append(&third).unwrap();
+    drop(log);
+    let len = std::fs::metadata(&file).unwrap().len();
+    let file_len = |n| {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_len(n)
+    };
+    file_len(len - 3).unwrap();
+    let mut log = ConfigLog::open(&file).unwrap();
+    assert_eq!(log.current(), Some(&second));
+    log.append(&third).unwrap();
+    drop(log);
+    assert_eq!(ConfigLog::open(&file).unwrap().current(), Some(&third));
+
+    // A damaged one isn't.
+    let mut bytes = std::fs::read(&file).unwrap();
+    bytes[12] ^= 1;
+    std::fs::write(&file, bytes).unwrap();
+    let damaged = ConfigLog::open(&file).unwrap_err();
+    assert_eq!(damaged.kind(), submerge_base::ErrorKind::Corruption);
+
+    // Nor is a length longer than any entry, which a torn write can't
+    // leave, nor entries out of order.
+    std::fs::remove_file(&file).unwrap();
+    let mut log = ConfigLog::open(&file).unwrap();
+    log.append(&first).unwrap();
+    let first_len = std::fs::metadata(&file).unwrap().len() as usize;
+    log.append(&second).unwrap();
+    drop(log);
+    let bytes = std::fs::read(&file).unwrap();
+    let mut long = bytes.clone();
+    long[..3].copy_from_slice(&[0x80, 0x80, 0x40]);
+    std::fs::write(&file, long).unwrap();
+    let damaged = ConfigLog::open(&file).unwrap_err();
+    assert_eq!(damaged.kind(), submerge_base::ErrorKind::Corruption);
+    let swapped = [&bytes[first_len..], &bytes[..first_len]].concat();
+    std::fs::write(&file, swapped).unwrap();
+    let damaged = ConfigLog::open(&file).unwrap_err();
+    assert_eq!(damaged.kind(), submerge_base::ErrorKind::Corruption);
+    assert_eq!(
+        std::fs::metadata(&file).unwrap().len() as usize,
+        bytes.len()
+    );
+    std::fs::remove_file(&file).unwrap();
+
+    // A client logs the configurations it moves to, and restarted on the
+    // log, picks up in the latest and ignores Puts from before it began.
+    let (n1, n2) = (NodeID(1), NodeID(2));
+    let config = Config::new(nodes(&[1, 2]), 10, Duration::from_micros(20));
+    let client = |store| {
+        let gossip = Duration::from_micros(5);
+        let log = ConfigLog::open(&file).unwrap();
+        TxnClient::new(n1, config.clone(), store, gossip).with_config_log(log)
+    };
+    let mut restarted = client(MemStore::new());
+    let next = config.successor(nodes(&[1, 2]), t(5), t(20));
+    restarted.reconfigure(next.clone(), at(20)).unwrap();
+    let store = restarted.into_store();
+    let mut restarted = client(store).recover(at(30)).unwrap();
+    assert_eq!(restarted.status().epoch, 1);
+    assert_eq!(
+        ConfigLog::open(&file).unwrap().history(),
+        &[config.clone(), next]
+    );
+    let writes = touching(0, &[], &["x"]).thunk.encode().unwrap();
+    let (old, new) = (RealmTime::new(at(10), n2, 0), RealmTime::new(at(25), n2, 0));
+    let mut net = Node::new();
+    for time in [old, new] {
+        let put = SpecificMsg::Put(writes.clone());
+        net.send_msg(Msg::new(n2, n1, time, time, 0, put)).unwrap();
+    }
+    while let Some((_, buf)) = net.send_byes().unwrap() {
+        restarted.recv_bytes(n2, buf).unwrap();
+    }
+    restarted.tick(at(30)).unwrap();
+    let since = RealmTime::new(at(0), NodeID(0), 0);
+    let versions = restarted.store().since(since).unwrap();
+    let installed = versions.iter().map(|(_, t, _)| *t).collect::<Vec<_>>();
+    assert_eq!(installed, vec![new]);
+    let mut acked = Vec::new();
+    while let Some((_, buf)) = restarted.send_bytes() {
+        let msg = Msg::decode(&buf).unwrap();
+        if matches!(msg.specific(), SpecificMsg::Ack) {
+            acked.push(msg.txn_time());
+        }
+    }
+    assert_eq!(acked, vec![new]);
+    std::fs::remove_file(&file).unwrap();
+}
+
+#[test]
+fn test_client() {
+    let config = Config::new(nodes(&[1, 2, 3]), 1, Duration::from_micros(10));
+    let mut clients = (1..=3)
+        .map(|id| {
+            let client = TxnClient::new(
+                NodeID(id),
+                config.clone(),
+                MemStore::new(),
+                Duration::from_micros(5),
+            );
+            (NodeID(id), client)
+        })
+        .collect::<BTreeMap<_, _>>();
+    // Tick every client, and carry their messages, except to the down nodes.
+    let step = |clients: &mut BTreeMap<NodeID, TxnClient<MemStore>>, us: i64, down: &NodeSet| {
+        let mut wire = Vec::new();
+        for (id, client) in clients.iter_mut() {
+            client.tick(at(us)).unwrap();
+            while let Some((dst, buf)) = client.send_bytes() {
+                wire.push((*id, dst, buf));
+            }
+        }
+        for (src, dst, buf) in wire.into_iter().filter(|(_, dst, _)| !down.contains(dst)) {
+            clients.get_mut(&dst).unwrap().recv_bytes(src, buf).unwrap();
+        }
+    };
+    let writes = touching(0, &[], &["x"]).thunk;
+
+    // A transaction submitted on one node runs on all of them, and its
+    // result comes back to whoever's waiting for it.
+    let handle = clients
+        .get_mut(&NodeID(1))
+        .unwrap()
+        .submit(writes.clone(), at(1))
+        .unwrap();
+    let time = handle.time().unwrap();
+    assert_eq!(time, RealmTime::new(at(1), NodeID(1), 0));
+    assert!(handle.try_result().is_none());
+    let outcome = std::thread::scope(|s| {
+        let waiter = s.spawn(|| handle.await_result());
+        for us in 2..100 {
+            step(&mut clients, us, &NodeSet::new());
+        }
+        waiter.join().unwrap()
+    });
+    assert_eq!(outcome.unwrap(), Ok(Vals::I64s(vec![3])));
+    for client in clients.values() {
+        let x = client.store().get(path("x"), time).unwrap();
+        assert_eq!(x, Record::Resolved(Vals::I64s(vec![3])));
+    }
+
+    // One whose Puts time out fails.
+    let handle = clients
+        .get_mut(&NodeID(2))
+        .unwrap()
+        .submit(writes, at(1001))
+        .unwrap();
+    for us in 1002..1100 {
+        step(&mut clients, us, &nodes(&[3]));
+    }
+    let failed = handle.try_result().unwrap().unwrap_err();
+    assert!(failed.is_timeout());
+}
+
+#[test]
+fn test_dedup() {
+    let t = |us: i64| RealmTime::new(at(us), NodeID(1), 0);
+    let bytes = thunk().encode().unwrap();
+    let other = touching(0, &[], &["x"]).thunk.encode().unwrap();
+
+    // The first Put at a time is installed, a replay of it isn't, and a
+    // different thunk at the same time is refused.
+    let mut dedup = Dedup::new();
+    assert!(dedup.admit(t(10), &bytes).unwrap());
+    assert!(!dedup.admit(t(10), &bytes).unwrap());
+    assert!(dedup.admit(t(20), &other).unwrap());
+    let e = dedup.admit(t(10), &other).unwrap_err();
+    assert_eq!(e.kind(), submerge_base::ErrorKind::Protocol);
+    dedup.forget(t(10));
+    assert_eq!(dedup.len(), 1);
+    assert!(!dedup.admit(t(20), &other).unwrap());
+
+    // On a network that delivers every message twice, each transaction is
+    // still installed and run once on every node.
+    let config = Config::new(nodes(&[1, 2, 3]), 0, Duration::from_micros(10));
+    let mut clients = (1..=3)
+        .map(|id| {
+            let gossip = Duration::from_micros(5);
+            let client = TxnClient::new(NodeID(id), config.clone(), MemStore::new(), gossip);
+            (NodeID(id), client)
+        })
+        .collect::<BTreeMap<_, _>>();
+    let step = |clients: &mut BTreeMap<NodeID, TxnClient<MemStore>>, us: i64| {
+        let mut wire = Vec::new();
+        for (id, client) in clients.iter_mut() {
+            client.tick(at(us)).unwrap();
+            while let Some((dst, buf)) = client.send_bytes() {
+                wire.push((*id, dst, buf.clone()));
+                wire.push((*id, dst, buf));
+            }
+        }
+        for (src, dst, buf) in wire {
+            clients.get_mut(&dst).unwrap().recv_bytes(src, buf).unwrap();
+        }
+    };
+    let writes = touching(0, &[], &["x"]).thunk;
+    let first = clients
+        .get_mut(&NodeID(1))
+        .unwrap()
+        .submit(writes.clone(), at(1))
+        .unwrap();
+    let second = clients
+        .get_mut(&NodeID(1))
+        .unwrap()
+        .submit(writes, at(1))
+        .unwrap();
+
+    // A coordinator that reuses a RealmTime for another thunk is refused:
+    // the replica keeps the thunk it installed first.
+    let five = Footprint {
+        reads: vec![],
+        writes: vec![path("x")],
+    };
+    let five = Thunk::new(Tab::default(), parse("2 + 3").unwrap().expr, five);
+    let put = SpecificMsg::Put(five.encode().unwrap());
+    let msg_time = RealmTime::new(at(1), NodeID(1), 9);
+    step(&mut clients, 2);
+    let mut net = Node::new();
+    let conflict = Msg::new(
+        NodeID(1),
+        NodeID(2),
+        first.time().unwrap(),
+        msg_time,
+        0,
+        put,
+    );
+    net.send_msg(conflict).unwrap();
+    let (_, buf) = net.send_byes().unwrap().unwrap();
+    clients
+        .get_mut(&NodeID(2))
+        .unwrap()
+        .recv_bytes(NodeID(1), buf)
+        .unwrap();
+
+    for us in 3..100 {
+        step(&mut clients, us);
+    }
+    let three = Record::Resolved(Vals::I64s(vec![3]));
+    for handle in [first, second] {
+        assert_eq!(
+            handle.try_result().unwrap().unwrap(),
+            Ok(Vals::I64s(vec![3]))
+        );
+    }
+    for client in clients.values() {
+        let versions = client.store().since(RealmTime::new(at(0), NodeID(0), 0));
+        let versions = versions.unwrap().into_iter().map(|(p, _, r)| (p, r));
+        assert_eq!(
+            versions.collect::<Vec<_>>(),
+            vec![(path("x"), three.clone())]
+        );
+    }
+}
+
+#[test]
+fn test_status() {
+    // Percentiles are nearest-rank over each peer's recent samples.
+    let mut latencies = Latencies::new();
+    assert_eq!(latencies.percentiles(NodeID(2)), None);
+    for us in (1..=100).rev() {
+        latencies.record(NodeID(2), Duration::from_micros(us));
+    }
+    latencies.record(NodeID(3), Duration::from_micros(7));
+    let p = latencies.percentiles(NodeID(2)).unwrap();
+    assert_eq!(p.samples, 100);
+    assert_eq!(p.p50, Duration::from_micros(50));
+    assert_eq!(p.p90, Duration::from_micros(90));
+    assert_eq!(p.p99, Duration::from_micros(99));
+    assert_eq!(p.max, Duration::from_micros(100));
+    let p = latencies.percentiles(NodeID(3)).unwrap();
+    assert_eq!((p.p50, p.p99), (Duration::from_micros(7), p.max));
+    assert_eq!(latencies.all().len(), 2);
+    for _ in 0..2000 {
+        latencies.record(NodeID(3), Duration::from_micros(1));
+    }
+    let p = latencies.percentiles(NodeID(3)).unwrap();
+    assert_eq!((p.samples, p.max), (1024, Duration::from_micros(1)));
+
+    // A client reports its watermarks, its transactions' states, and how
+    // long each peer took to ack.
+    let config = Config::new(nodes(&[1, 2]), 0, Duration::from_micros(10));
+    let mut clients = (1..=2)
+        .map(|id| {
+            let gossip = Duration::from_micros(5);
+            let client = TxnClient::new(NodeID(id), config.clone(), MemStore::new(), gossip);
+            (NodeID(id), client)
+        })
+        .collect::<BTreeMap<_, _>>();
+    let step = |clients: &mut BTreeMap<NodeID, TxnClient<MemStore>>, us: i64| {
+        let mut wire = Vec::new();
+        for (id, client) in clients.iter_mut() {
+            client.tick(at(us)).unwrap();
+            while let Some((dst, buf)) = client.send_bytes() {
+                wire.push((*id, dst, buf));
+            }
+        }
+        for (src, dst, buf) in wire {
+            clients.get_mut(&dst).unwrap().recv_bytes(src, buf).unwrap();
+        }
+    };
+    let status = clients[&NodeID(1)].status();
+    assert_eq!(status.node, NodeID(1));
+    assert_eq!((status.local, status.global), (None, None));
+    assert_eq!(status.states, StateCounts::default());
+
+    let writes = touching(0, &[], &["x"]).thunk;
+    let one = clients.get_mut(&NodeID(1)).unwrap();
+    let handle = one.submit(writes, at(1)).unwrap();
+    assert_eq!(one.status().states.put, 1);
+    for us in 2..50 {
+        step(&mut clients, us);
+    }
+    assert!(handle.try_result().unwrap().is_ok());
+    let status = clients[&NodeID(1)].status();
+    assert!(status.local > handle.time());
+    assert!(status.global > handle.time());
+    assert_eq!(
+        status.heard.keys().collect::<Vec<_>>(),
+        [&NodeID(1), &NodeID(2)]
+    );
+    let states = StateCounts {
+        end: 1,
+        ..StateCounts::default()
+    };
+    assert_eq!(status.states, states);
+    let acked = |n: i64| status.latency[&NodeID(n)].max;
+    // Node 1's own Put and ack each wait a tick in its loopback; node 2's
+    // cross the wire, which takes one more.
+    assert_eq!(acked(1), Duration::from_micros(2));
+    assert_eq!(acked(2), Duration::from_micros(3));
+    assert_eq!(clients[&NodeID(2)].status().states.end, 1);
+}
+
+// Transactions submitted on a lossy, slow network, as a seed has it.
+fn simulate(seed: u64) -> Sim {
+    let config = Config::new(nodes(&[1, 2, 3]), 10, Duration::from_micros(20));
+    let mut sim = Sim::new(&config, Duration::from_micros(5), seed)
+        .with_delay(Duration::from_micros(1), Duration::from_micros(8))
+        .with_drops(10);
+    let writes = touching(0, &[], &["x"]).thunk;
+    let mut handles = Vec::new();
+    for (node, steps) in [(1, 0), (3, 3), (1, 10), (2, 10)] {
+        for _ in 0..steps {
+            sim.step().unwrap();
+        }
+        handles.push(sim.submit(NodeID(node), writes.clone()).unwrap());
+    }
+    let ended =
+        |sim: &Sim| (1..=3).all(|n| sim.client(NodeID(n)).unwrap().status().states.end == 4);
+    assert!(sim.run_until(5000, ended).unwrap());
+    for handle in handles {
+        assert_eq!(
+            handle.try_result().unwrap().unwrap(),
+            Ok(Vals::I64s(vec![3]))
+        );
+    }
+    sim
+}
+
+#[test]
+fn test_sim() {
+    // Every node runs every transaction, despite delays and drops, and
+    // ends up with the same store.
+    let sim = simulate(7);
+    let store = |n: i64| {
+        let client = sim.client(NodeID(n)).unwrap();
+        client
+            .store()
+            .since(RealmTime::new(at(0), NodeID(0), 0))
+            .unwrap()
+    };
+    assert!(!store(1).is_empty());
+    assert_eq!(store(1), store(2));
+    assert_eq!(store(1), store(3));
+
+    // The same seed runs the same way; another runs differently.
+    let again = simulate(7);
+    assert_eq!(sim.trace(), again.trace());
+    assert_eq!(sim.now(), again.now());
+    assert_ne!(sim.trace(), simulate(8).trace());
+
+    // A transaction can't replicate to a node that's down.
+    let config = Config::new(nodes(&[1, 2, 3]), 1, Duration::from_micros(20));
+    let mut sim = Sim::new(&config, Duration::from_micros(5), 7);
+    sim.crash(NodeID(3));
+    let handle = sim
+        .submit(NodeID(1), touching(0, &[], &["x"]).thunk)
+        .unwrap();
+    let mut result = None;
+    let finished = sim.run_until(500, |_| {
+        result = result.take().or_else(|| handle.try_result());
+        result.is_some()
+    });
+    assert!(finished.unwrap());
+    let e = result.unwrap().unwrap_err();
+    assert_eq!(e.kind(), submerge_base::ErrorKind::Timeout);
+}
+
+#[test]
+fn test_chunked() {
+    let me = NodeID(1);
+    let config = Config::new(nodes(&[1, 2]), 1, Duration::from_micros(10)).with_part_size(16);
+    let time = RealmTime::new(at(0), me, 0);
+    let bytes = thunk().encode().unwrap();
+    let parts = bytes.len().div_ceil(16) as u32;
+    assert!(parts > 2);
+    let mut txn = Transaction::new(time, thunk(), &config);
+    let mut net = Node::new();
+
+    // A thunk larger than the part size goes to every node in parts, which
+    // put back together make the thunk.
+    txn.poll(&mut net, me, &config, at(0)).unwrap();
+    let puts = sent(&mut net);
+    assert_eq!(puts.len(), 2 * parts as usize);
+    let mut whole = Vec::new();
+    for put in puts.iter().filter(|m| m.dst() == NodeID(2)) {
+        let SpecificMsg::PutPart {
+            part,
+            parts: n,
+            bytes,
+        } = put.specific()
+        else {
+            panic!("not a put part: {:?}", put)
+        };
+        assert_eq!((*part as usize, *n), (whole.len() / 16, parts));
+        whole.extend_from_slice(bytes);
+    }
+    assert_eq!(whole, bytes);
+    let ack = |put: &Msg| {
+        let SpecificMsg::PutPart { part, .. } = put.specific() else {
+            panic!("not a put part: {:?}", put)
+        };
+        put.response(
+            RealmTime::new(at(1), put.dst(), 0),
+            SpecificMsg::AckPart(*part),
+        )
+    };
+
+    // A retry sends only the parts a node hasn't acked; a node that's acked
+    // every part is done.
+    for put in &puts {
+        if put.dst() == me || put.specific() != puts[1].specific() {
+            txn.ack(&ack(put));
+        }
+    }
+    txn.ack(&ack(&puts[0]));
+    let State::Put { nodes: tries, .. } = &txn.state else {
+        panic!("left put")
+    };
+    assert_eq!(tries[&me], PutTry::Success);
+    assert!(matches!(tries[&NodeID(2)], PutTry::Attempt { .. }));
+    txn.poll(&mut net, me, &config, at(10)).unwrap();
+    let retries = sent(&mut net);
+    assert_eq!(retries.len(), 1);
+    assert_eq!(retries[0].specific(), puts[1].specific());
+    assert_eq!(retries[0].dst(), NodeID(2));
+    txn.ack(&ack(&retries[0]));
+    assert_eq!(txn.state, State::Seq);
+
+    // Parts go back together in any order, once.
+    let mut reassembly = Reassembly::new();
+    let chunks = bytes.chunks(16).enumerate().rev();
+    let mut done = None;
+    for (i, chunk) in chunks {
+        assert_eq!(done, None);
+        done = reassembly.recv(time, i as u32, parts, chunk).unwrap();
+    }
+    assert_eq!(done, Some(bytes.clone()));
+    assert!(reassembly.is_empty());
+
+    // Parts that don't agree are refused, and unfinished ones forgotten
+    // once they're released.
+    let later = RealmTime::new(at(5), me, 0);
+    assert_eq!(reassembly.recv(later, 0, 2, &bytes[..16]).unwrap(), None);
+    let e = reassembly.recv(later, 1, 3, &bytes[16..]).unwrap_err();
+    assert_eq!(e.kind(), submerge_base::ErrorKind::Protocol);
+    assert!(reassembly.recv(later, 2, 2, &bytes[16..]).is_err());
+    reassembly.forget(time);
+    assert_eq!(reassembly.len(), 1);
+    reassembly.forget(later);
+    assert!(reassembly.is_empty());
+
+    // On a lossy network every node gets the whole thunk, and runs it.
+    let config = Config::new(nodes(&[1, 2, 3]), 10, Duration::from_micros(20)).with_part_size(16);
+    let mut sim = Sim::new(&config, Duration::from_micros(5), 3)
+        .with_delay(Duration::from_micros(1), Duration::from_micros(4))
+        .with_drops(10);
+    let handle = sim
+        .submit(NodeID(2), touching(0, &[], &["x"]).thunk)
+        .unwrap();
+    let ended =
+        |sim: &Sim| (1..=3).all(|n| sim.client(NodeID(n)).unwrap().status().states.end == 1);
+    assert!(sim.run_until(2000, ended).unwrap());
+    assert_eq!(
+        handle.try_result().unwrap().unwrap(),
+        Ok(Vals::I64s(vec![3]))
+    );
+    for n in 1..=3 {
+        let store = sim.client(NodeID(n)).unwrap().store();
+        let x = store.get(path("x"), RealmTime::new(at(2000), NodeID(0), 0));
+        assert_eq!(x.unwrap(), Record::Resolved(Vals::I64s(vec![3])));
+    }
+}
+
+#[test]
+fn test_admission() {
+    let mut admission = Admission::new()
+        .with_limit(Priority::Low, Limit::new(1, 1, Overload::Shed))
+        .with_limit(Priority::Normal, Limit::new(1, 1, Overload::Delay));
+
+    // A saturated class sheds or delays, as its limit says; other classes
+    // carry on.
+    assert!(admission.submit(Priority::Low).unwrap());
+    let shed = admission.submit(Priority::Low).unwrap_err();
+    assert_eq!(shed.kind(), submerge_base::ErrorKind::Overloaded);
+    assert!(shed.kind().is_retryable());
+    assert!(admission.submit(Priority::Normal).unwrap());
+    assert!(!admission.submit(Priority::Normal).unwrap());
+    assert!(admission.submit(Priority::High).unwrap());
+    assert_eq!(admission.admit(), None);
+
+    // Held transactions begin once their class has room in both stages.
+    admission.replicated(Priority::Normal);
+    assert_eq!(admission.admit(), None);
+    admission.finished(Priority::Normal);
+    assert_eq!(admission.admit(), Some(Priority::Normal));
+    assert_eq!(admission.admit(), None);
+    let stats = admission.stats();
+    let normal = ClassStats {
+        replicating: 1,
+        delayed: 1,
+        ..ClassStats::default()
+    };
+    assert_eq!(stats[&Priority::Normal], normal);
+    assert_eq!(
+        (
+            stats[&Priority::Low].shed,
+            stats[&Priority::Low].replicating
+        ),
+        (1, 1)
+    );
+    admission.failed(Priority::Low);
+    assert!(admission.submit(Priority::Low).unwrap());
+
+    // A client holds what it can't begin yet, and begins it in order as
+    // the earlier ones run.
+    let config = Config::new(nodes(&[1]), 0, Duration::from_micros(10));
+    let admission =
+        Admission::new().with_limit(Priority::Normal, Limit::new(1, 1, Overload::Delay));
+    let mut client = TxnClient::new(NodeID(1), config, MemStore::new(), Duration::from_micros(5))
+        .with_admission(admission);
+    let writes = touching(0, &[], &["x"]).thunk;
+    let handles = (0..3)
+        .map(|_| client.submit(writes.clone(), at(1)).unwrap())
+        .collect::<Vec<_>>();
+    assert!(handles[0].time().is_some());
+    assert!(handles[1].time().is_none());
+    assert_eq!(client.status().admission[&Priority::Normal].waiting, 2);
+    let urgent = client.submit_with(writes, Priority::High, at(1)).unwrap();
+    assert!(urgent.time().is_some());
+    for us in 2..50 {
+        client.tick(at(us)).unwrap();
+    }
+    let times = handles
+        .iter()
+        .map(|h| h.time().unwrap())
+        .collect::<Vec<_>>();
+    assert!(times.windows(2).all(|w| w[0] < w[1]));
+    for handle in handles.iter().chain([&urgent]) {
+        assert_eq!(
+            handle.try_result().unwrap().unwrap(),
+            Ok(Vals::I64s(vec![3]))
+        );
+    }
+    let normal = client.status().admission[&Priority::Normal];
+    assert_eq!((normal.delayed, normal.waiting, normal.queued), (2, 0, 0));
+}
+
+#[test]
+fn test_abort() {
+    let me = NodeID(1);
+    let config = Config::new(nodes(&[1, 2, 3]), 1, Duration::from_micros(10));
+    let time = RealmTime::new(at(0), me, 0);
+    let mut txn = Transaction::new(time, thunk(), &config);
+    let mut net = Node::new();
+    txn.poll(&mut net, me, &config, at(0)).unwrap();
+    let puts = sent(&mut net);
+    let ack = |m: &Msg, reply| m.response(RealmTime::new(at(1), m.dst(), 0), reply);
+    txn.ack(&ack(&puts[1], SpecificMsg::Ack));
+
+    // A replicating transaction is aborted on every node, acked or not;
+    // acks to its Puts that come late change nothing.
+    assert!(txn.cancel(&config));
+    assert!(!txn.cancel(&config));
+    txn.poll(&mut net, me, &config, at(1)).unwrap();
+    let aborts = sent(&mut net);
+    assert_eq!(aborts.len(), 3);
+    assert!(aborts.iter().all(|m| *m.specific() == SpecificMsg::Abort));
+    txn.ack(&ack(&puts[2], SpecificMsg::Ack));
+    txn.ack(&ack(&aborts[0], SpecificMsg::Aborted));
+    txn.ack(&ack(&aborts[1], SpecificMsg::Aborted));
+    assert!(matches!(txn.state, State::Abort { .. }));
+
+    // Unreplied aborts are retried like Puts; once every node has replied
+    // the transaction is over.
+    txn.poll(&mut net, me, &config, at(11)).unwrap();
+    let retries = sent(&mut net);
+    assert_eq!(
+        retries.iter().map(Msg::dst).collect::<Vec<_>>(),
+        vec![NodeID(3)]
+    );
+    txn.ack(&ack(&retries[0], SpecificMsg::Aborted));
+    assert_eq!(txn.state, State::Aborted);
+    assert!(!txn.cancel(&config));
+
+    // On the network, the replicas drop the thunk they've installed, the
+    // handle hears it was aborted, and the watermark passes it.
+    let mut sim = Sim::new(&config, Duration::from_micros(5), 1);
+    let writes = touching(0, &[], &["x"]).thunk;
+    let handle = sim.submit(me, writes.clone()).unwrap();
+    sim.step().unwrap();
+    let installed = |sim: &Sim, n: i64| {
+        let store = sim.client(NodeID(n)).unwrap().store();
+        store.get(path("x"), handle.time().unwrap()).is_ok()
+    };
+    assert!(installed(&sim, 2));
+    assert!(sim.abort(me, &handle).unwrap());
+    let later = sim.submit(NodeID(2), writes).unwrap();
+    let ended =
+        |sim: &Sim| (1..=3).all(|n| sim.client(NodeID(n)).unwrap().status().states.end == 1);
+    assert!(sim.run_until(200, ended).unwrap());
+    let e = handle.try_result().unwrap().unwrap_err();
+    assert_eq!(e.kind(), submerge_base::ErrorKind::Aborted);
+    assert_eq!(sim.client(me).unwrap().status().states.aborted, 1);
+    assert_eq!(
+        later.try_result().unwrap().unwrap(),
+        Ok(Vals::I64s(vec![3]))
+    );
+    assert!((1..=3).all(|n| !installed(&sim, n)));
+
+    // Once it's replicated, it's too late.
+    assert!(!sim.abort(NodeID(2), &later).unwrap());
+}
+
+#[test]
+fn test_quorum() {
+    // A node releases up to the first transaction it's missing from any
+    // coordinator whose watermark covers it.
+    let (n1, n2) = (NodeID(1), NodeID(2));
+    let t = |us, node, event| RealmTime::new(at(us), node, event);
+    let mut arrivals = Arrivals::new();
+    arrivals.record(t(0, n1, 0));
+    arrivals.record(t(2, n1, 2));
+    let global = t(5, n1, 2);
+    let mut heard = BTreeMap::from([(n1, global)]);
+    assert_eq!(arrivals.bound(&heard, global), Some(t(0, n1, 0)));
+    arrivals.record(t(1, n1, 1));
+    assert_eq!(arrivals.bound(&heard, global), Some(global));
+    heard.insert(n2, t(6, n2, -1));
+    assert_eq!(arrivals.bound(&heard, global), Some(global));
+    heard.insert(n2, t(6, n2, 0));
+    assert_eq!(arrivals.bound(&heard, global), None);
+
+    // Replication counts a quorum of acks, and goes on to the rest.
+    let config = Config::new(nodes(&[1, 2, 3]), 1, Duration::from_micros(10)).with_write_quorum(2);
+    let mut txn = Transaction::new(t(0, n1, 0), thunk(), &config);
+    let mut net = Node::new();
+    txn.poll(&mut net, n1, &config, at(0)).unwrap();
+    let puts = sent(&mut net);
+    let ack = |m: &Msg| m.response(RealmTime::new(at(1), m.dst(), 0), SpecificMsg::Ack);
+    txn.ack(&ack(&puts[0]));
+    assert!(!txn.quorate());
+    txn.ack(&ack(&puts[1]));
+    assert!(txn.quorate());
+    assert!(!txn.cancel(&config));
+    assert!(matches!(txn.state, State::Put { .. }));
+    txn.poll(&mut net, n1, &config, at(11)).unwrap();
+    assert_eq!(sent(&mut net).len(), 1);
+    txn.poll(&mut net, n1, &config, at(22)).unwrap();
+    assert_eq!(txn.state, State::Seq);
+
+    // On the network, a coordinator's watermark passes a transaction a
+    // down node hasn't got, and the node catches up once it's back.
+    let config =
+        Config::new(nodes(&[1, 2, 3]), 100, Duration::from_micros(20)).with_write_quorum(2);
+    let mut sim = Sim::new(&config, Duration::from_micros(5), 3)
+        .with_delay(Duration::from_micros(1), Duration::from_micros(4));
+    sim.crash(NodeID(3));
+    let writes = touching(0, &[], &["x"]).thunk;
+    let first = sim.submit(n1, writes.clone()).unwrap();
+    let time = first.time().unwrap();
+    let passed = |sim: &Sim| sim.client(n1).unwrap().status().local > Some(time);
+    assert!(sim.run_until(100, passed).unwrap());
+    assert!(first.try_result().is_none());
+    sim.restore(NodeID(3));
+    let second = sim.submit(n2, writes).unwrap();
+    let ended =
+        |sim: &Sim| (1..=3).all(|n| sim.client(NodeID(n)).unwrap().status().states.end == 2);
+    assert!(sim.run_until(2000, ended).unwrap());
+    assert_eq!(
+        first.try_result().unwrap().unwrap(),
+        Ok(Vals::I64s(vec![3]))
+    );
+    assert_eq!(
+        second.try_result().unwrap().unwrap(),
+        Ok(Vals::I64s(vec![3]))
+    );
+    let store = |n: i64| {
+        let client = sim.client(NodeID(n)).unwrap();
+        client.store().since(t(0, NodeID(0), 0)).unwrap()
+    };
+    assert_eq!(store(1), store(3));
+    assert_eq!(store(2), store(3));
+}
+
+#[test]
+fn test_skew() {
+    // The estimate is the least delayed message's difference from the
+    // clock, and a peer beyond the bound is noted until it's back within.
+    let (n2, n3) = (NodeID(2), NodeID(3));
+    let sent = |us, node| RealmTime::new(at(us), node, 0);
+    let mut skews = Skews::new()
+        .with_bound(Duration::from_micros(5))
+        .with_slew();
+    skews.record(n2, sent(100, n2), at(103));
+    skews.record(n2, sent(110, n2), at(111));
+    assert_eq!(skews.estimate(n2), Some(Duration::from_micros(-1)));
+    skews.record(n3, sent(200, n3), at(150));
+    assert_eq!(skews.estimate(n3), Some(Duration::from_micros(50)));
+    assert_eq!(skews.all().len(), 2);
+
+    // The clock slews towards the peer ahead at most a tenth fast, and
+    // stops at the bound.
+    skews.slew(at(0));
+    skews.slew(at(105));
+    assert_eq!(skews.offset(), Duration::from_micros(10));
+    assert_eq!(skews.clock(at(105)), at(115));
+    skews.slew(at(1000));
+    assert_eq!(skews.offset(), Duration::from_micros(45));
+    assert_eq!(Skews::new().clock(at(7)), at(7));
+
+    // A node lagging its peers holds up the global watermark, unless it
+    // slews to catch up.
+    let config = Config::new(nodes(&[1, 2, 3]), 10, Duration::from_micros(20));
+    let global = |skews: Skews| {
+        let mut sim = Sim::new(&config, Duration::from_micros(5), 1)
+            .with_delay(Duration::from_micros(1), Duration::from_micros(4))
+            .with_skew(n3, Duration::from_micros(-300))
+            .with_skews(skews);
+        for _ in 0..4000 {
+            sim.step().unwrap();
+        }
+        let status = sim.client(NodeID(1)).unwrap().status();
+        let skew = status.skew[&n3].micros();
+        assert!((-310..-300).contains(&skew));
+        let lag = sim.now().micros() - status.global.unwrap().time().micros();
+        (lag, sim.client(n3).unwrap().status().slew)
+    };
+    let (lag, slew) = global(Skews::new());
+    assert!(lag >= 300);
+    assert_eq!(slew, Duration::from_micros(0));
+    let (lag, slew) = global(
+        Skews::new()
+            .with_bound(Duration::from_micros(20))
+            .with_slew(),
+    );
+    assert!(lag < 60);
+    assert!(slew > Duration::from_micros(250));
+}
+
+#[test]
+fn test_recover() {
+    // A store tells what ran, what didn't, and the latest event of each
+    // coordinator.
+    let (n1, n2) = (NodeID(1), NodeID(2));
+    let store = MemStore::new();
+    let t = |us, node, event| RealmTime::new(at(us), node, event);
+    let done = Record::Resolved(Vals::I64s(vec![3]));
+    store.put(path("x"), t(1, n1, 0), done.clone()).unwrap();
+    store
+        .put(path("x"), t(2, n2, 4), Record::Unresolved(thunk()))
+        .unwrap();
+    store
+        .put(path("y"), t(2, n2, 4), Record::Unresolved(thunk()))
+        .unwrap();
+    store.put(path("y"), t(3, n1, 1), done).unwrap();
+    let recovery = Recovery::scan(&store).unwrap();
+    assert_eq!(recovery.released, Some(t(1, n1, 0)));
+    assert_eq!(
+        recovery.unresolved.keys().copied().collect::<Vec<_>>(),
+        vec![t(2, n2, 4)]
+    );
+    assert_eq!(recovery.events, BTreeMap::from([(n1, 1), (n2, 4)]));
+
+    // A node restarted after installing transactions it hadn't run runs
+    // them with the others, and goes on from its own last event.
+    let config = Config::new(nodes(&[1, 2, 3]), 10, Duration::from_micros(20));
+    let mut sim = Sim::new(&config, Duration::from_micros(5), 5)
+        .with_delay(Duration::from_micros(1), Duration::from_micros(4));
+    let writes = touching(0, &[], &["x"]).thunk;
+    let first = sim.submit(n1, writes.clone()).unwrap();
+    sim.submit(n2, writes.clone()).unwrap();
+    let unresolved = |sim: &Sim, n: i64| {
+        let store = sim.client(NodeID(n)).unwrap().store();
+        let versions = store.since(t(0, NodeID(0), 0)).unwrap();
+        let thunks = versions
+            .iter()
+            .filter(|(_, _, r)| matches!(r, Record::Unresolved(_)));
+        thunks.count()
+    };
+    assert!(sim.run_until(100, |sim| unresolved(sim, 2) == 2).unwrap());
+    sim.restart(n2).unwrap();
+    assert_eq!(sim.client(n2).unwrap().status().states.put, 1);
+    let third = sim.submit(n2, writes).unwrap();
+    assert_eq!(third.time().unwrap().event(), 1);
+    let settled = |sim: &Sim| (1..=3).all(|n| unresolved(sim, n) == 0);
+    assert!(sim.run_until(2000, settled).unwrap());
+    assert_eq!(
+        first.try_result().unwrap().unwrap(),
+        Ok(Vals::I64s(vec![3]))
+    );
+    assert_eq!(
+        third.try_result().unwrap().unwrap(),
+        Ok(Vals::I64s(vec![3]))
+    );
+    let store = |n: i64| {
+        let client = sim.client(NodeID(n)).unwrap();
+        client.store().since(t(0, NodeID(0), 0)).unwrap()
+    };
+    assert!(!store(1).is_empty());
+    assert_eq!(store(1), store(2));
+    assert_eq!(store(1), store(3));
+}
+
+#[test]
+fn test_session() {
+    // A session covers a node once it's released through its latest write.
+    let (n1, n2) = (NodeID(1), NodeID(2));
+    let mut session = Session::new();
+    assert!(!session.covered(None));
+    assert!(session.covered(Some(RealmTime::new(at(0), n1, 0))));
+    session.wrote(RealmTime::new(at(5), n1, 0));
+    session.wrote(RealmTime::new(at(3), n2, 0));
+    assert_eq!(session.written(), Some(RealmTime::new(at(5), n1, 0)));
+    assert!(!session.covered(Some(RealmTime::new(at(4), n1, 0))));
+    assert!(session.covered(Some(RealmTime::new(at(5), n1, 0))));
+
+    // A read on another node waits for the session's write to run there,
+    // and then sees it.
+    let config = Config::new(nodes(&[1, 2, 3]), 10, Duration::from_micros(20));
+    let mut sim = Sim::new(&config, Duration::from_micros(5), 2)
+        .with_delay(Duration::from_micros(1), Duration::from_micros(4));
+    let write = sim.submit(n1, touching(0, &[], &["x"]).thunk).unwrap();
+    let finished = |_: &Sim| write.try_result().is_some();
+    assert!(sim.run_until(500, finished).unwrap());
+    let mut session = Session::new();
+    session.wrote(write.time().unwrap());
+    let read = sim.read(n2, &session, path("x")).unwrap();
+    assert!(read.try_result().is_none());
+    let mut result = None;
+    let answered = sim.run_until(500, |_| {
+        result = result.take().or_else(|| read.try_result());
+        result.is_some()
+    });
+    assert!(answered.unwrap());
+    let resolved = Record::Resolved(Vals::I64s(vec![3]));
+    assert_eq!(result.unwrap().unwrap(), resolved);
+
+    // Where it's already run, the read is answered at once.
+    let read = sim.read(n1, &session, path("x")).unwrap();
+    assert_eq!(read.try_result().unwrap().unwrap(), resolved);
+}
+
+#[test]
+fn test_interactive() {
+    let config = Config::new(nodes(&[1]), 1, Duration::from_micros(10));
+    let mut client = TxnClient::new(NodeID(1), config, MemStore::new(), Duration::from_micros(5));
+    assert!(client.open().is_err());
+    let run = |client: &mut TxnClient<MemStore>, thunk: Thunk, us: i64| {
+        let handle = client.submit(thunk, at(us)).unwrap();
+        for tick in us..us + 3 {
+            client.tick(at(tick)).unwrap();
+        }
+        handle.try_result().unwrap()
+    };
+    assert!(run(&mut client, touching(0, &[], &["x"]).thunk, 0).is_ok());
+
+    // Reads are at the snapshot, or of what the transaction has written,
+    // and each path gets its own staged value.
+    let mut txn = client.open().unwrap();
+    let three = Record::Resolved(Vals::I64s(vec![3]));
+    assert_eq!(txn.read(client.store(), path("x")).unwrap(), three);
+    txn.write(path("x"), Vals::I64s(vec![4]));
+    txn.write(path("y"), Vals::I64s(vec![7]));
+    let four = Record::Resolved(Vals::I64s(vec![4]));
+    assert_eq!(txn.read(client.store(), path("x")).unwrap(), four);
+    let thunk = txn.clone().into_thunk();
+    assert_eq!(thunk.foot.reads, vec![path("x")]);
+    assert_eq!(thunk.foot.writes, vec![path("x"), path("y")]);
+    let handle = client.commit(txn, at(10)).unwrap();
+    for tick in 10..13 {
+        client.tick(at(tick)).unwrap();
+    }
+    assert!(handle.try_result().unwrap().is_ok());
+    let latest = |client: &TxnClient<MemStore>, p| {
+        let time = RealmTime::new(at(100), NodeID(1), 0);
+        client.store().get(path(p), time).unwrap()
+    };
+    assert_eq!(latest(&client, "x"), four);
+    assert_eq!(latest(&client, "y"), Record::Resolved(Vals::I64s(vec![7])));
+
+    // A transaction whose read was overwritten before it ran writes
+    // nothing and fails with a conflict.
+    let mut txn = client.open().unwrap();
+    txn.read(client.store(), path("x")).unwrap();
+    txn.write(path("z"), Vals::I64s(vec![1]));
+    assert!(run(&mut client, touching(0, &[], &["x"]).thunk, 20).is_ok());
+    let handle = client.commit(txn, at(30)).unwrap();
+    for tick in 30..33 {
+        client.tick(at(tick)).unwrap();
+    }
+    let e = handle.try_result().unwrap().unwrap_err();
+    assert_eq!(e.kind(), submerge_base::ErrorKind::Conflict);
+    let time = RealmTime::new(at(100), NodeID(1), 0);
+    assert!(client.store().get(path("z"), time).is_err());
+
+    // A rolled back transaction writes nothing.
+    let txn = client.open().unwrap();
+    client.rollback(txn);
+}
+
+#[test]
+fn test_escalation() {
+    // Escalated rounds back off from the timeout up to the ceiling.
+    let us = Duration::from_micros;
+    let escalation = Escalation::new(2, 2, us(30));
+    let waits = (1..=5).map(|c| escalation.wait(us(10), 1, c).micros());
+    assert_eq!(waits.collect::<Vec<_>>(), vec![10, 10, 20, 30, 30]);
+
+    // A node that doesn't ack gets the escalated rounds before it times
+    // out.
+    let me = NodeID(1);
+    let config = Config::new(nodes(&[1, 2]), 1, us(10)).with_escalation(escalation);
+    let mut txn = Transaction::new(RealmTime::new(at(0), me, 0), thunk(), &config);
+    let mut net = Node::new();
+    let mut sends = Vec::new();
+    for t in 0..100 {
+        txn.poll(&mut net, me, &config, at(t)).unwrap();
+        for msg in sent(&mut net) {
+            if msg.dst() == me {
+                txn.ack(&msg.response(RealmTime::new(at(t), me, 0), SpecificMsg::Ack));
+            } else {
+                sends.push(t);
+            }
+        }
+        if !matches!(txn.state, State::Put { .. }) {
+            assert_eq!(t, 70);
+            break;
+        }
+    }
+    assert_eq!(sends, vec![0, 10, 20, 40]);
+    assert!(matches!(txn.state, State::Err { .. }));
+
+    // That rides out a node's brief outage.
+    let escalation = Escalation::new(6, 2, us(200));
+    let config = Config::new(nodes(&[1, 2, 3]), 1, us(10)).with_escalation(escalation);
+    let run = |config: &Config, down: usize| {
+        let mut sim = Sim::new(config, us(5), 4);
+        sim.crash(NodeID(3));
+        let handle = sim.submit(me, touching(0, &[], &["x"]).thunk).unwrap();
+        for _ in 0..down {
+            sim.step().unwrap();
+        }
+        sim.restore(NodeID(3));
+        let mut result = None;
+        let finished = sim.run_until(2000, |_| {
+            result = result.take().or_else(|| handle.try_result());
+            result.is_some()
+        });
+        assert!(finished.unwrap());
+        (result.unwrap(), sim.now())
+    };
+    let (result, _) = run(&config, 60);
+    assert_eq!(result.unwrap(), Ok(Vals::I64s(vec![3])));
+
+    // On probation, a node that doesn't answer pings is given up on well
+    // before its rounds run out.
+    let (result, _) = run(&config, 2000);
+    assert_eq!(
+        result.unwrap_err().kind(),
+        submerge_base::ErrorKind::Timeout
+    );
+    let config = config.with_escalation(escalation.with_probation());
+    let (result, now) = run(&config, 60);
+    assert_eq!(
+        result.unwrap_err().kind(),
+        submerge_base::ErrorKind::Timeout
+    );
+    assert!(now.micros() < 100);
+}
+
+#[test]
+fn test_diagnose() {
+    // A transaction a down node hasn't acked is blocked on it, and where
+    // it's replicated it's blocked on the down node's watermark.
+    let (n1, n2, n3) = (NodeID(1), NodeID(2), NodeID(3));
+    let config = Config::new(nodes(&[1, 2, 3]), 10, Duration::from_micros(20));
+    let mut sim = Sim::new(&config, Duration::from_micros(5), 6);
+    sim.crash(n3);
+    let handle = sim.submit(n1, touching(0, &[], &["x"]).thunk).unwrap();
+    let time = handle.time().unwrap();
+    for _ in 0..30 {
+        sim.step().unwrap();
+    }
+    let now = sim.now();
+    let blocked = sim.client(n1).unwrap().diagnose(now);
+    assert_eq!(blocked.len(), 1);
+    assert_eq!(blocked[0].time, time);
+    assert_eq!(blocked[0].age, Duration::from_micros(30));
+    let Blocker::Unacked(unacked) = &blocked[0].blocker else {
+        panic!("not blocked on acks")
+    };
+    assert_eq!(unacked.keys().copied().collect::<Vec<_>>(), vec![n3]);
+    assert!(unacked[&n3] > 1);
+    assert!(blocked[0].to_string().contains("unacked by node 3"));
+
+    let blocked = sim.client(n2).unwrap().diagnose(now);
+    assert_eq!(blocked.len(), 1);
+    let Blocker::Watermark { behind, .. } = &blocked[0].blocker else {
+        panic!("not blocked on the watermark")
+    };
+    // Node 1's own watermark waits on the Put node 3 hasn't acked.
+    assert_eq!(behind, &NodeSet::from([n1, n3]));
+    assert!(blocked[0].to_string().ends_with("held back by nodes 1, 3"));
+
+    // Readers are blocked on the versions they're waiting for.
+    let store = MemStore::new();
+    let (writer, reader) = (RealmTime::new(at(1), n1, 0), RealmTime::new(at(2), n1, 1));
+    store
+        .put(path("a"), writer, Record::Unresolved(thunk()))
+        .unwrap();
+    let mut waits = Waits::new();
+    assert_eq!(waits.read(&store, reader, path("a")).unwrap(), None);
+    let blocked = Blocked::reading(&waits, at(12));
+    assert_eq!(blocked.len(), 1);
+    assert_eq!(
+        blocked[0].blocker,
+        Blocker::Reading(vec![(path("a"), writer)])
+    );
+    assert!(blocked[0]
+        .to_string()
+        .contains("waiting 10us: reading a at"));
+}
+
+#[test]
+fn test_resubmit() {
+    // A transaction still replicating to a down node when the node is
+    // reconfigured away is killed, resubmitted in the new epoch under the
+    // same handle, and runs there.
+    let (n1, n2, n3) = (NodeID(1), NodeID(2), NodeID(3));
+    let config = Config::new(nodes(&[1, 2, 3]), 10, Duration::from_micros(20));
+    let mut sim = Sim::new(&config, Duration::from_micros(5), 7);
+    sim.crash(n3);
+    let handle = sim.submit(n1, touching(0, &[], &["x"]).thunk).unwrap();
+    let old = handle.time().unwrap();
+    for _ in 0..10 {
+        sim.step().unwrap();
+    }
+    let installed = |sim: &Sim, time| {
+        let store = sim.client(n2).unwrap().store();
+        store.get(path("x"), time).is_ok()
+    };
+    assert!(installed(&sim, old));
+    let start = RealmTime::new(sim.now(), n1, 0);
+    let next = config.successor(nodes(&[1, 2]), config.start(), start);
+    sim.reconfigure(&next).unwrap();
+    let new = handle.time().unwrap();
+    assert!(new > start);
+    assert!(!installed(&sim, old));
+    assert!(sim.run_until(100, |sim| installed(sim, new)).unwrap());
+    assert!(sim
+        .run_until(100, |_| handle.try_result().is_some())
+        .unwrap());
+    let status = sim.client(n1).unwrap().status();
+    assert_eq!((status.epoch, status.states.end), (1, 1));
+
+    // One killed more times than the client resubmits fails instead.
+    let t = |us, event| RealmTime::new(at(us), n1, event);
+    let config = Config::new(nodes(&[1, 2]), 10, Duration::from_micros(20));
+    let mut client = TxnClient::new(
+        n1,
+        config.clone(),
+        MemStore::new(),
+        Duration::from_micros(5),
+    )
+    .with_resubmits(1);
+    let handle = client.submit(thunk(), at(10)).unwrap();
+    assert_eq!(handle.time(), Some(t(10, 0)));
+    let first = config.successor(nodes(&[1, 2]), t(5, 0), t(20, 0));
+    client.reconfigure(first.clone(), at(20)).unwrap();
+    assert_eq!(handle.time(), Some(t(20, 1)));
+    assert!(handle.try_result().is_none());
+    let e = client.reconfigure(config.clone(), at(20)).unwrap_err();
+    assert_eq!(e.kind(), submerge_base::ErrorKind::Protocol);
+    let second = first.successor(nodes(&[1, 2]), t(20, 0), t(30, 0));
+    client.reconfigure(second, at(30)).unwrap();
+    let e = handle.try_result().unwrap().unwrap_err();
+    assert_eq!(e.kind(), submerge_base::ErrorKind::Aborted);
+    assert_eq!(client.status().states.err, 1);
+}
+
+#[test]
+fn test_partition() {
+    // A transaction replicating across a partition waits for it to heal,
+    // through duplicated and reordered messages, and runs once everywhere.
+    let (n1, n3) = (NodeID(1), NodeID(3));
+    let config = Config::new(nodes(&[1, 2, 3]), 10, Duration::from_micros(20));
+    let mut sim = Sim::new(&config, Duration::from_micros(5), 11)
+        .with_delay(Duration::from_micros(1), Duration::from_micros(4))
+        .with_dups(30)
+        .with_reorder(20, Duration::from_micros(10));
+    sim.net().partition([n3]);
+    let handle = sim.submit(n1, touching(0, &[], &["x"]).thunk).unwrap();
+    let time = handle.time().unwrap();
+    for _ in 0..60 {
+        sim.step().unwrap();
+    }
+    assert!(handle.try_result().is_none());
+    let blocked = sim.client(n1).unwrap().diagnose(sim.now());
+    assert!(matches!(&blocked[0].blocker, Blocker::Unacked(u) if u.contains_key(&n3)));
+    sim.net().heal();
+    assert!(sim
+        .run_until(200, |_| handle.try_result().is_some())
+        .unwrap());
+    let ran = |sim: &Sim, n: i64| {
+        let store = sim.client(NodeID(n)).unwrap().store();
+        store.get(path("x"), time).ok()
+    };
+    assert!(sim
+        .run_until(200, |sim| {
+            (1..=3).all(|n| ran(sim, n) == Some(Record::Resolved(Vals::I64s(vec![3]))))
+        })
+        .unwrap());
+    assert!((1..=3).all(|n| sim.client(NodeID(n)).unwrap().status().states.end == 1));
+
+    // A scripted drop of node 3's ack costs a retry, not the transaction.
+    let handle = sim.submit(n1, touching(0, &[], &["y"]).thunk).unwrap();
+    sim.net().script(n3, n1, LinkFault::Drop);
+    assert!(sim
+        .run_until(200, |_| handle.try_result().is_some())
+        .unwrap());
+}
+
+#[test]
+fn test_detector() {
+    // With a failure detector, the coordinator gives up at once on a node
+    // that's gone quiet, rather than escalating to it.
+    let us = Duration::from_micros;
+    let (n1, n3) = (NodeID(1), NodeID(3));
+    let escalation = Escalation::new(5, 2, us(200));
+    let config = Config::new(nodes(&[1, 2, 3]), 2, us(10)).with_escalation(escalation);
+    let detector = Detector::new().with_min_std(us(1)).with_ping(us(20));
+    let sim = |seed| Sim::new(&config, us(5), seed).with_detector(detector.clone());
+    let mut dead = sim(12);
+    for _ in 0..50 {
+        dead.step().unwrap();
+    }
+    assert_eq!(
+        dead.client(n1).unwrap().suspicion(n3, dead.now()),
+        Suspicion::Alive
+    );
+    dead.crash(n3);
+    let start = dead.now().micros();
+    let handle = dead.submit(n1, touching(0, &[], &["x"]).thunk).unwrap();
+    let mut result = None;
+    let done = dead.run_until(500, |_| {
+        result = result.take().or_else(|| handle.try_result());
+        result.is_some()
+    });
+    assert!(done.unwrap());
+    let e = result.unwrap().unwrap_err();
+    assert_eq!(e.kind(), submerge_base::ErrorKind::Timeout);
+    assert!(dead.now().micros() - start < 60);
+    assert_eq!(
+        dead.client(n1).unwrap().suspicion(n3, dead.now()),
+        Suspicion::Dead
+    );
+
+    // But a node that's only missed some Puts, and is heard from
+    // otherwise, is escalated to until it acks.
+    let mut slow = sim(12);
+    for _ in 0..50 {
+        slow.step().unwrap();
+    }
+    for _ in 0..4 {
+        slow.net().script(n1, n3, LinkFault::Drop);
+    }
+    let handle = slow.submit(n1, touching(0, &[], &["x"]).thunk).unwrap();
+    let mut result = None;
+    let done = slow.run_until(1000, |_| {
+        result = result.take().or_else(|| handle.try_result());
+        result.is_some()
+    });
+    assert!(done.unwrap());
+    assert!(result.unwrap().is_ok());
+    assert_eq!(
+        slow.client(n1).unwrap().suspicion(n3, slow.now()),
+        Suspicion::Alive
+    );
+}
+
+#[test]
+fn test_queue_limit() {
+    // A node whose queues hold only a message at a time refuses the Put of
+    // the second of two submissions at once, and sends it again.
+    let config = Config::new(nodes(&[1, 2, 3]), 1, Duration::from_micros(10));
+    let limit = QueueLimit::new(1, 1 << 20, Backpressure::Drop);
+    let mut clients = (1..=3)
+        .map(|id| {
+            let client = TxnClient::new(
+                NodeID(id),
+                config.clone(),
+                MemStore::new(),
+                Duration::from_micros(5),
+            );
+            (NodeID(id), client.with_queue_limit(limit))
+        })
+        .collect::<BTreeMap<_, _>>();
+    let n1 = clients.get_mut(&NodeID(1)).unwrap();
+    let handles = (0..2)
+        .map(|_| n1.submit(touching(0, &[], &["x"]).thunk, at(1)).unwrap())
+        .collect::<Vec<_>>();
+    let mut results = handles.iter().map(|_| None).collect::<Vec<_>>();
+    for us in 1..500 {
+        let mut wire = Vec::new();
+        for (id, client) in clients.iter_mut() {
+            client.tick(at(us)).unwrap();
+            while let Some((dst, buf)) = client.send_bytes() {
+                wire.push((*id, dst, buf));
+            }
+        }
+        for (src, dst, buf) in wire {
+            clients.get_mut(&dst).unwrap().recv_bytes(src, buf).unwrap();
+        }
+        for (result, handle) in results.iter_mut().zip(&handles) {
+            *result = result.take().or_else(|| handle.try_result());
+        }
+    }
+    for result in results {
+        assert_eq!(result.unwrap().unwrap(), Ok(Vals::I64s(vec![3])));
+    }
+    let queues = clients
+        .values()
+        .flat_map(|c| c.status().queues.into_values());
+    let queues = queues.collect::<Vec<_>>();
+    assert!(queues.iter().all(|q| q.msgs <= 1));
+    assert!(queues.iter().map(|q| q.refused).sum::<u64>() > 0);
+}
@@ -0,0 +1,115 @@
+// Blocking reads, step 7 of the protocol. A running transaction reads the
+// latest version before its own time of each path; if that's a thunk that
+// hasn't run yet, the reader waits for it. Waits keeps track of who waits
+// for which version, and when a version is resolved gives back the readers
+// with nothing left to wait for, to run again.
+//
+// A reader only ever waits for an earlier writer, so waits can't form a
+// cycle. If one does anyway, it's a bug that would deadlock every reader
+// in it, so the wait that closes it is refused with an internal error.
+
+use crate::{Record, Store};
+use std::collections::{BTreeMap, BTreeSet};
+use submerge_base::{err, Error, Result};
+use submerge_lang::Path;
+use submerge_net::RealmTime;
+
+// A version of a record, by its path and the time of its writer.
+type Version = (Path, RealmTime);
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Waits {
+    // The readers waiting for each unresolved version.
+    readers: BTreeMap<Version, BTreeSet<RealmTime>>,
+    // The versions each waiting reader waits for.
+    blocked: BTreeMap<RealmTime, BTreeSet<Version>>,
+}
+
+impl Waits {
+    pub fn new() -> Waits {
+        Waits::default()
+    }
+
+    // What a reader at a time reads at a path, or None if it must wait for
+    // the version there to be resolved.
+    pub fn read(
+        &mut self,
+        store: &dyn Store,
+        reader: RealmTime,
+        path: Path,
+    ) -> Result<Option<Record>> {
+        match store.before(path.clone(), reader)? {
+            None => Err(err(format!("{} has no version before {:?}", path, reader))),
+            Some((writer, Record::Unresolved(_))) => {
+                self.wait(reader, (path, writer))?;
+                Ok(None)
+            }
+            Some((_, record)) => Ok(Some(record)),
+        }
+    }
+
+    pub(crate) fn wait(&mut self, reader: RealmTime, version: Version) -> Result<()> {
+        if self.reaches(version.1, reader) {
+            return Err(Error::internal(format!(
+                "{:?} waiting for {} at {:?} would deadlock",
+                reader, version.0, version.1
+            )));
+        }
+        let readers = self.readers.entry(version.clone()).or_default();
+        readers.insert(reader);
+        self.blocked.entry(reader).or_default().insert(version);
+        Ok(())
+    }
+
+    // Whether a transaction waits, directly or through others, for a
+    // version another one writes.
+    fn reaches(&self, from: RealmTime, to: RealmTime) -> bool {
+        let mut seen = BTreeSet::new();
+        let mut next = vec![from];
+        while let Some(t) = next.pop() {
+            if t == to {
+                return true;
+            }
+            if seen.insert(t) {
+                let versions = self.blocked.get(&t).into_iter().flatten();
+                next.extend(versions.map(|(_, writer)| *writer));
+            }
+        }
+        false
+    }
+
+    // Store a version's result, and give back the readers that were waiting
+    // for it and for nothing else, in time order.
+    pub fn resolve(
+        &mut self,
+        store: &dyn Store,
+        path: Path,
+        writer: RealmTime,
+        record: Record,
+    ) -> Result<Vec<RealmTime>> {
+        store.put(path.clone(), writer, record)?;
+        let version = (path, writer);
+        let mut woken = Vec::new();
+        for reader in self.readers.remove(&version).unwrap_or_default() {
+            let Some(versions) = self.blocked.get_mut(&reader) else {
+                continue;
+            };
+            versions.remove(&version);
+            if versions.is_empty() {
+                self.blocked.remove(&reader);
+                woken.push(reader);
+            }
+        }
+        Ok(woken)
+    }
+
+    // The readers waiting for anything.
+    pub fn readers(&self) -> impl Iterator<Item = RealmTime> + '_ {
+        self.blocked.keys().copied()
+    }
+
+    // The versions a reader is waiting for.
+    pub fn waiting(&self, reader: RealmTime) -> impl Iterator<Item = &Version> {
+        self.blocked.get(&reader).into_iter().flatten()
+    }
+}
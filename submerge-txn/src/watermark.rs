@@ -0,0 +1,88 @@
+// A node's local watermark, steps 4 and 5 of the protocol. Every
+// transaction the node coordinates is begun here when it's given its
+// timestamp, and marked replicated once every node has acked it. The
+// watermark is the latest time at or before which every transaction the
+// node has begun is replicated, and which it promises never to begin a
+// transaction at; it's what the node gossips to the others, and the global
+// watermark is the least of them.
+//
+// Acks come back in any order, so a transaction replicated ahead of an
+// earlier one waits behind it, and the watermark only passes the prefix of
+// transactions with no gap. A node with nothing in flight would hold up
+// every other's releases if its watermark stood still, so advance() moves
+// it up to the node's clock, past which the node's timestamps all lie: up
+// to the clock, but never to or past a transaction still replicating.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use submerge_base::{err, Result};
+use submerge_net::RealmTime;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct Watermark {
+    mark: Option<RealmTime>,
+    // Begun and not yet replicated.
+    pending: BTreeSet<RealmTime>,
+    // Replicated, but after one still pending.
+    replicated: BTreeSet<RealmTime>,
+    // The latest time the node has said it won't begin anything at.
+    clock: Option<RealmTime>,
+}
+
+impl Watermark {
+    pub fn new() -> Watermark {
+        Watermark::default()
+    }
+
+    // Every transaction the node began at or before this is replicated.
+    pub fn mark(&self) -> Option<RealmTime> {
+        self.mark
+    }
+
+    pub fn begin(&mut self, time: RealmTime) -> Result<()> {
+        if Some(time) <= self.mark.max(self.clock) {
+            return Err(err(format!("{:?} is behind the watermark", time)));
+        }
+        if self.pending.contains(&time) || self.replicated.contains(&time) {
+            return Err(err(format!("{:?} has already begun", time)));
+        }
+        self.pending.insert(time);
+        Ok(())
+    }
+
+    pub fn replicated(&mut self, time: RealmTime) -> Result<()> {
+        if !self.pending.remove(&time) {
+            return Err(err(format!("{:?} isn't replicating", time)));
+        }
+        self.replicated.insert(time);
+        self.settle();
+        Ok(())
+    }
+
+    // Forget the transactions past a reconfiguration's seal, which it
+    // killed; their resubmissions begin again in the new epoch.
+    pub fn seal(&mut self, seal: RealmTime) {
+        self.pending.retain(|t| *t <= seal);
+        self.replicated.retain(|t| *t <= seal);
+        self.settle();
+    }
+
+    // The node won't begin anything at or before now.
+    pub fn advance(&mut self, now: RealmTime) {
+        self.clock = self.clock.max(Some(now));
+        self.settle();
+    }
+
+    // Move the mark up to the first transaction still pending.
+    fn settle(&mut self) {
+        let first = self.pending.first().copied();
+        let before = |t: &RealmTime| first.is_none_or(|p| *t < p);
+        while let Some(t) = self.replicated.first().copied().filter(before) {
+            self.replicated.pop_first();
+            self.mark = self.mark.max(Some(t));
+        }
+        if let Some(c) = self.clock.filter(before) {
+            self.mark = self.mark.max(Some(c));
+        }
+    }
+}
@@ -17,6 +17,8 @@ pub enum ServerState {
 
 pub trait ServerTrait {}
 
+// Not built by anything yet; it's where the server's resources will go.
+#[allow(dead_code)]
 struct ServerImpl {}
 
 impl ServerTrait for ServerImpl {}